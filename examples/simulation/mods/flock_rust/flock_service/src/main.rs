@@ -123,6 +123,8 @@ pub fn main() -> Result<()> {
                                                         (entity_id, comp_name, var_name),
                                                         var,
                                                     ),
+                                                    idempotency_key: None,
+                                                    units: Default::default(),
                                                 },
                                                 None,
                                             );
@@ -8,8 +8,8 @@ use outcome_core::{
     VarName,
 };
 use outcome_net::msg::{
-    DataPullRequest, DataTransferRequest, DataTransferResponse, Message, MessageType,
-    PullRequestData, TransferResponseData, TurnAdvanceRequest, TurnAdvanceResponse,
+    DataPackBuilder, DataPullRequest, DataTransferRequest, DataTransferResponse, Message,
+    MessageType, PullRequestData, TransferResponseData, TurnAdvanceRequest, TurnAdvanceResponse,
     TypedSimDataPack, VarSimDataPack, VarSimDataPackOrdered,
 };
 use outcome_net::{Client, ClientConfig, CompressionPolicy, SocketEvent, SocketEventType};
@@ -56,24 +56,20 @@ pub fn main() -> Result<()> {
         // println!("loop");
         // println!("advanced_turn: {}", advanced_turn);
         if advanced_turn {
-            let mut data = VarSimDataPack {
-                vars: Default::default(),
-            };
-            data.vars.insert(
-                // "2:hello_greetable:str:hello".to_string(),
-                (
-                    EntityName::from("2").unwrap(),
-                    CompName::from("greeting").unwrap(),
-                    VarName::from("hello").unwrap(),
-                ),
-                outcome_core::Var::String(format!(
-                    "hello since {}",
-                    Instant::now().duration_since(start).as_millis()
-                )),
-            );
+            let data = DataPackBuilder::new()
+                .set(
+                    "2:greeting:str:hello",
+                    outcome_core::Var::String(format!(
+                        "hello since {}",
+                        Instant::now().duration_since(start).as_millis()
+                    )),
+                )
+                .build()?;
             client.connection.send_payload(
                 DataPullRequest {
                     data: PullRequestData::NativeAddressedVars(data),
+                    idempotency_key: None,
+                    units: Default::default(),
                 },
                 None,
             )?;
@@ -173,6 +169,9 @@ fn data_transfer_request() -> DataTransferRequest {
         transfer_type: "SelectVarOrdered".to_string(),
         // selection: vec!["*:velocity:float:x".to_string()],
         selection: vec![],
+        precision: None,
+        since_step: None,
+        order_id: None,
     }
 }
 
@@ -0,0 +1,173 @@
+//! Low-level debugging interface attaching directly to an organizer or
+//! a worker.
+//!
+//! Unlike the interactive client mode, this doesn't go through a server.
+//! Requests are sent straight to the greeter socket of the target using
+//! the internal cluster protocol.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use linefeed::{Interface, ReadResult};
+
+use outcome_net::msg::coord_worker::{InspectReport, InspectTarget};
+use outcome_net::Inspector;
+
+/// Kind of the attach target, used for the prompt only, as both organizers
+/// and workers understand the same requests.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Coord,
+    Worker,
+}
+
+static COMMANDS: &[(&str, &str)] = &[
+    ("status", "Print general information about the target"),
+    ("entities", "List entities known to the target"),
+    (
+        "entity",
+        "Print all vars stored on an entity, takes an entity id",
+    ),
+    (
+        "routing",
+        "Print which worker stores each entity (organizer only)",
+    ),
+    ("pending", "List unfinished tasks and blocking workers"),
+    ("help", "Show available commands"),
+    ("quit", "Detach and quit"),
+];
+
+/// Runs a single diagnostics command on a worker and prints the output.
+pub fn run_diagnostics(addr: &str, command: &str) -> Result<()> {
+    let mut inspector = Inspector::connect(addr)?;
+    let output = inspector.diagnostics(command);
+    inspector.disconnect()?;
+    print!("{}", output?);
+    Ok(())
+}
+
+/// Starts the diagnostics prompt loop.
+pub fn start_diagnostics(addr: &str) -> Result<()> {
+    let mut inspector = Inspector::connect(addr)?;
+    let interface = Arc::new(Interface::new("diag")?);
+    interface.set_prompt(&format!("[diag {}] ", addr))?;
+
+    println!("Connected to worker at {}.", addr);
+    println!("See possible commands with \"help\". Exit using \"quit\" or ctrl-d.");
+
+    while let ReadResult::Input(line) = interface.read_line()? {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        interface.add_history_unique(line.to_string());
+        if line == "quit" {
+            break;
+        }
+        match inspector.diagnostics(line) {
+            Ok(output) => print!("{}", output),
+            Err(e) => println!("error: {}", e),
+        }
+    }
+
+    inspector.disconnect()?;
+    Ok(())
+}
+
+/// Starts the attach prompt loop.
+pub fn start(addr: &str, target: Target) -> Result<()> {
+    let mut inspector = Inspector::connect(addr)?;
+    let interface = Arc::new(Interface::new("attach")?);
+    let prompt = match target {
+        Target::Coord => format!("[coord {}] ", addr),
+        Target::Worker => format!("[worker {}] ", addr),
+    };
+    interface.set_prompt(&prompt)?;
+
+    println!("Attached to {}.", addr);
+    println!("See possible commands with \"help\". Exit using \"quit\" or ctrl-d.");
+
+    while let ReadResult::Input(line) = interface.read_line()? {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        interface.add_history_unique(line.to_string());
+        let (cmd, args) = match line.find(char::is_whitespace) {
+            Some(pos) => (&line[..pos], line[pos..].trim()),
+            None => (line, ""),
+        };
+        let inspect_target = match cmd {
+            "status" => InspectTarget::Status,
+            "entities" => InspectTarget::Entities,
+            "entity" => match args.parse() {
+                Ok(id) => InspectTarget::Entity(id),
+                Err(_) => {
+                    println!("entity takes an integer entity id");
+                    continue;
+                }
+            },
+            "routing" => InspectTarget::Routing,
+            "pending" => InspectTarget::Pending,
+            "help" => {
+                for (name, help) in COMMANDS {
+                    println!("{:>10}  {}", name, help);
+                }
+                continue;
+            }
+            "quit" => break,
+            _ => {
+                println!("unknown command: {}", cmd);
+                continue;
+            }
+        };
+        match inspector.inspect(inspect_target) {
+            Ok(report) => print_report(report),
+            Err(e) => println!("error: {}", e),
+        }
+    }
+
+    inspector.disconnect()?;
+    Ok(())
+}
+
+fn print_report(report: InspectReport) {
+    match report {
+        InspectReport::Status(values) | InspectReport::Entity(values) => {
+            for (name, value) in values {
+                println!("{}: {}", name, value);
+            }
+        }
+        InspectReport::Entities(entities) => {
+            for e in entities {
+                println!(
+                    "{}{}{}{}",
+                    e.id,
+                    e.name.map(|n| format!(" ({})", n)).unwrap_or_default(),
+                    e.worker
+                        .map(|w| format!(" on worker {}", w))
+                        .unwrap_or_default(),
+                    if e.components.is_empty() {
+                        String::new()
+                    } else {
+                        format!(": {}", e.components.join(", "))
+                    }
+                );
+            }
+        }
+        InspectReport::Routing(routing) => {
+            for (entity, worker) in routing {
+                println!("{} -> worker {}", entity, worker);
+            }
+        }
+        InspectReport::Pending(pending) => {
+            if pending.is_empty() {
+                println!("nothing pending");
+            }
+            for item in pending {
+                println!("{}", item);
+            }
+        }
+        InspectReport::Empty => (),
+    }
+}
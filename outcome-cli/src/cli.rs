@@ -10,19 +10,19 @@ use std::{env, thread};
 
 use anyhow::{Error, Result};
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use outcome::sim::replay::Replay;
+use outcome::sim::stats::RunSummary;
 use outcome::util::{find_project_root, get_scenario_paths, get_snapshot_paths};
 use outcome::Sim;
-use outcome_net::{
-    CompressionPolicy, Organizer, Server, ServerConfig, SimConnection, SocketEvent,
-    SocketEventType, Worker,
-};
+use outcome_net::msg::{RecorderAction, RecorderRequest};
+use outcome_net::{CompressionPolicy, Organizer, Server, ServerConfig, SimConnection, Worker};
 
 #[cfg(feature = "watcher")]
 use notify::{RecommendedWatcher, Watcher};
 
 use crate::interactive::{OnSignal, OnSignalAction};
 use crate::util::format_elements_list;
-use crate::{interactive, test};
+use crate::{interactive, query, test};
 use std::str::FromStr;
 
 pub const VERSION: &'static str = env!("CARGO_PKG_VERSION");
@@ -72,6 +72,53 @@ pub fn app_matches() -> ArgMatches<'static> {
                 .short("p"))
         )
 
+        // dump
+        .subcommand(SubCommand::with_name("dump")
+            .about("Print a canonical, sorted textual dump of simulation state")
+            .display_order(14)
+            .long_about("Print a canonical, sorted textual dump of simulation state.\n\
+                Dump is line-oriented, with one line per component and var of each\n\
+                entity, which makes it suitable for diffing state between runs.")
+            .arg(Arg::with_name("path")
+                .value_name("path")
+                .required(true)
+                .help("Path to the scenario manifest or snapshot file"))
+            .arg(Arg::with_name("snapshot")
+                .long("snapshot")
+                .short("n")
+                .help("Load simulation from a snapshot file"))
+            .arg(Arg::with_name("steps")
+                .long("steps")
+                .short("s")
+                .help("Number of steps to process before creating the dump")
+                .takes_value(true)
+                .value_name("steps")
+                .default_value("0"))
+            .arg(Arg::with_name("component")
+                .long("component")
+                .short("c")
+                .help("Only include selected component, can be used multiple times")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("component"))
+            .arg(Arg::with_name("prefix")
+                .long("prefix")
+                .short("p")
+                .help("Only include lines with addresses starting with the prefix, \
+                can be used multiple times")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("prefix"))
+            .arg(Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .help("Write the dump to a file instead of printing it")
+                .takes_value(true)
+                .value_name("path"))
+        )
+
         // run
         .subcommand(SubCommand::with_name("run")
             .about("Run a simulation locally")
@@ -110,6 +157,11 @@ pub fn app_matches() -> ArgMatches<'static> {
                 .value_name("on-change")
                 .default_value("restart")
                 .possible_values(&["restart", "update"]))
+            .arg(Arg::with_name("stats-json")
+                .long("stats-json")
+                .help("Write run summary to a json file once the run ends")
+                .takes_value(true)
+                .value_name("path"))
 
         )
 
@@ -123,6 +175,8 @@ pub fn app_matches() -> ArgMatches<'static> {
             server. Simulation can be started with either a path to scenario or a snapshot.\n\n\
             `outcome server -s ./scenarios/hello_world` \n    \
             (starts a server backed by local simulation process, based on a selected scenario)\n\n\
+            With neither provided the server starts idle, waiting for a client to start \n\
+            the simulation remotely.\n\n\
             NOTE: data sent between client and server is not encrypted, connection is not \n\
             secure! Basic authentication methods are provided, but they are more of \n\
             a convenience than a serious security measure.")
@@ -161,6 +215,11 @@ pub fn app_matches() -> ArgMatches<'static> {
                 .display_order(5)
                 .takes_value(true)
                 .value_name("seconds"))
+            .arg(Arg::with_name("anonymous-admin")
+                .long("anonymous-admin")
+                .help("Grant admin access to all clients if authorization is disabled, \
+                 otherwise they're only granted read-write access")
+                .display_order(6))
             .arg(Arg::with_name("compress")
                 .long("compress")
                 .short("c")
@@ -169,6 +228,12 @@ pub fn app_matches() -> ArgMatches<'static> {
                 .takes_value(true)
                 .value_name("compression-policy")
                 .possible_values(&["all", "bigger_than_[n_bytes]"]))
+            .arg(Arg::with_name("stats-json")
+                .long("stats-json")
+                .help("Write run summary to a json file on shutdown")
+                .display_order(7)
+                .takes_value(true)
+                .value_name("path"))
             .arg(Arg::with_name("organizer")
                 .long("organizer")
                 .short("o")
@@ -191,6 +256,109 @@ pub fn app_matches() -> ArgMatches<'static> {
                 .display_order(102)
                 .takes_value(true)
                 .value_name("worker-addresses"))
+            .arg(Arg::with_name("step-trigger")
+                .long("step-trigger")
+                .help("Policy for triggering steps, only applicable if `--organizer` \
+                option is also present [possible values: clients, continuous, \
+                event:<event_name>, interval:<millis>]")
+                .display_order(103)
+                .takes_value(true)
+                .value_name("trigger"))
+            .arg(Arg::with_name("audit-log")
+                .long("audit-log")
+                .help("Record all client-originated writes to the file at the given path")
+                .display_order(104)
+                .takes_value(true)
+                .value_name("path"))
+            .arg(Arg::with_name("publish")
+                .long("publish")
+                .help("Broadcast step notices to subscribers from a socket bound to the given address")
+                .display_order(105)
+                .takes_value(true)
+                .value_name("address"))
+            .arg(Arg::with_name("crashdump-dir")
+                .long("crashdump-dir")
+                .help("Write a crash dump to the given directory whenever a step fails")
+                .display_order(106)
+                .takes_value(true)
+                .value_name("path"))
+            .arg(Arg::with_name("memory-soft-limit")
+                .long("memory-soft-limit")
+                .help("Release server caches and buffers once process memory exceeds the given number of megabytes")
+                .display_order(107)
+                .takes_value(true)
+                .value_name("megabytes"))
+            .arg(Arg::with_name("memory-hard-limit")
+                .long("memory-hard-limit")
+                .help("Refuse spawning new entities once process memory exceeds the given number of megabytes")
+                .display_order(108)
+                .takes_value(true)
+                .value_name("megabytes"))
+            .arg(Arg::with_name("replay-log")
+                .long("replay-log")
+                .help("Log client-originated mutations and steps to the file at the given path, \
+                for replaying the run later")
+                .display_order(109)
+                .takes_value(true)
+                .value_name("path"))
+            .arg(Arg::with_name("worker-timeout")
+                .long("worker-timeout")
+                .help("Consider workers dead after not hearing from them for the given number \
+                of seconds, only applicable if `--organizer` option is also present")
+                .display_order(110)
+                .takes_value(true)
+                .value_name("seconds"))
+            .arg(Arg::with_name("dead-worker-policy")
+                .long("dead-worker-policy")
+                .help("Policy for handling entities of dead workers, only applicable if \
+                `--organizer` option is also present")
+                .display_order(111)
+                .takes_value(true)
+                .possible_values(&["abort", "respawn"])
+                .value_name("policy"))
+            .arg(Arg::with_name("recovery-snapshot-interval")
+                .long("recovery-snapshot-interval")
+                .help("Number of steps between snapshots used for respawning entities of dead \
+                workers, only applicable if `--organizer` option is also present")
+                .display_order(112)
+                .takes_value(true)
+                .value_name("steps"))
+            .arg(Arg::with_name("checkpoint-store")
+                .long("checkpoint-store")
+                .help("Write periodic checkpoints of the union to the given directory, \
+                only applicable if `--organizer` option is also present")
+                .display_order(113)
+                .takes_value(true)
+                .value_name("location"))
+            .arg(Arg::with_name("checkpoint-interval")
+                .long("checkpoint-interval")
+                .help("Number of steps between periodic checkpoints")
+                .display_order(114)
+                .takes_value(true)
+                .default_value("100")
+                .value_name("steps"))
+            .arg(Arg::with_name("restore-checkpoint")
+                .long("restore-checkpoint")
+                .help("Restore the union from the most recent checkpoint in the given \
+                directory, only applicable if `--organizer` option is also present")
+                .display_order(115)
+                .takes_value(true)
+                .value_name("location"))
+            .arg(Arg::with_name("scenarios-dir")
+                .long("scenarios-dir")
+                .help("Directory containing the scenarios clients are allowed to start \
+                on an idle server, defaults to the working directory")
+                .display_order(116)
+                .takes_value(true)
+                .value_name("path"))
+            .arg(Arg::with_name("placement")
+                .long("placement")
+                .help("Strategy for placing newly spawned entities on workers, only \
+                applicable if `--organizer` option is also present")
+                .display_order(117)
+                .takes_value(true)
+                .possible_values(&["random", "round-robin", "least-loaded"])
+                .value_name("placement"))
             .arg(Arg::with_name("encodings")
                 .long("encodings")
                 .short("e")
@@ -250,6 +418,12 @@ pub fn app_matches() -> ArgMatches<'static> {
                 .help("Sets the client as blocking, requiring it to explicitly \
                 agree to step simulation forward")
             )
+            .arg(Arg::with_name("observer")
+                .long("observer")
+                .help("Registers the client as a read-only observer, guaranteed \
+                not to stall or otherwise affect the simulation")
+                .conflicts_with("blocking")
+            )
             .arg(Arg::with_name("compress")
                 .long("compress")
                 .short("c")
@@ -258,6 +432,18 @@ pub fn app_matches() -> ArgMatches<'static> {
                 .takes_value(true)
                 .value_name("policy")
                 .default_value("none"))
+            .arg(Arg::with_name("precision")
+                .long("precision")
+                .help("Precision of numbers in data transfers received from the server \
+                [possible values: native, f32, quantized:<step>]")
+                .takes_value(true)
+                .value_name("precision")
+                .default_value("native"))
+            .arg(Arg::with_name("reconnect")
+                .long("reconnect")
+                .help("Reconnect to the server if the connection is lost, resuming the \
+                previous session")
+            )
             .arg(Arg::with_name("heartbeat")
                 .long("heartbeat")
                 .help("Set the heartbeat frequency in heartbeat per n seconds")
@@ -276,6 +462,168 @@ pub fn app_matches() -> ArgMatches<'static> {
                 .default_value("tcp"))
         )
 
+        // attach
+        .subcommand(SubCommand::with_name("attach")
+            .about("Attach directly to an organizer or a worker for debugging")
+            .long_about("Attach directly to an organizer or a worker for debugging.\n\n\
+            Talks to the target using the internal cluster protocol, without\n\
+            going through a server. Allows inspecting entities stored on\n\
+            workers, entity routing and pending tasks.")
+            .display_order(28)
+            .arg(Arg::with_name("coord")
+                .long("coord")
+                .help("Address of the organizer to attach to")
+                .takes_value(true)
+                .value_name("address")
+                .conflicts_with("worker")
+                .required_unless("worker"))
+            .arg(Arg::with_name("worker")
+                .long("worker")
+                .help("Address of the worker to attach to")
+                .takes_value(true)
+                .value_name("address"))
+        )
+
+        // diag
+        .subcommand(SubCommand::with_name("diag")
+            .about("Run diagnostics commands on a worker")
+            .long_about("Run diagnostics commands on a worker.\n\n\
+            Only a restricted, read-only set of commands is available:\n\
+            listing entities, printing vars, showing queue depths and\n\
+            printing the most recent errors. If no command is provided,\n\
+            an interactive prompt is started.")
+            .display_order(29)
+            .arg(Arg::with_name("address")
+                .help("Address of the worker")
+                .required(true)
+                .value_name("address"))
+            .arg(Arg::with_name("command")
+                .help("Command to run, e.g. `queues` or `var 1:position:float:x`")
+                .multiple(true)
+                .value_name("command"))
+        )
+
+        // query
+        .subcommand(SubCommand::with_name("query")
+            .about("Run a single query on a server and print the results")
+            .display_order(27)
+            .arg(Arg::with_name("server-addr")
+                .help("Address of the server")
+                .required(true)
+                .value_name("address"))
+            .arg(Arg::with_name("filter")
+                .long("filter")
+                .short("f")
+                .help("Filter selecting entities, can be used multiple times \
+                [possible values: comp=<comp,..>, name=<name,..>, id=<id,..>]")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("filter"))
+            .arg(Arg::with_name("map")
+                .long("map")
+                .short("m")
+                .help("Mapping selecting data of the selected entities, can be \
+                used multiple times [possible values: all, <type>:<var>, \
+                var=<var>, comp=<comp,..>]")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("map"))
+            .arg(Arg::with_name("format")
+                .long("format")
+                .help("Output format")
+                .takes_value(true)
+                .possible_values(&["table", "csv", "json"])
+                .default_value("table")
+                .value_name("format"))
+            .arg(Arg::with_name("auth")
+                .long("auth")
+                .short("a")
+                .help("Authentication pair used when connecting to server \
+                [example value: user,password]")
+                .takes_value(true))
+        )
+
+        // record
+        .subcommand(SubCommand::with_name("record")
+            .about("Control recording of selected vars on a server")
+            .long_about("Control recording of selected vars on a server.\n\n\
+            Recordings are written to the `recordings` directory of the\n\
+            project the simulation was loaded from. Requires admin scope.")
+            .display_order(31)
+            .arg(Arg::with_name("server-addr")
+                .help("Address of the server")
+                .required(true)
+                .value_name("address"))
+            .arg(Arg::with_name("action")
+                .help("Recorder action")
+                .required(true)
+                .possible_values(&["start", "stop", "flush"])
+                .value_name("action"))
+            .arg(Arg::with_name("addr")
+                .long("addr")
+                .help("Address of the recorded var, can be used multiple times")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required_if("action", "start")
+                .value_name("address"))
+            .arg(Arg::with_name("interval")
+                .long("interval")
+                .short("i")
+                .help("Number of steps between samples")
+                .takes_value(true)
+                .default_value("1")
+                .value_name("steps"))
+            .arg(Arg::with_name("format")
+                .long("format")
+                .help("Output format")
+                .takes_value(true)
+                .possible_values(&["csv", "jsonl", "columnar"])
+                .default_value("csv")
+                .value_name("format"))
+            .arg(Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .help("Output file path, relative to the recordings directory")
+                .takes_value(true)
+                .default_value("recording.csv")
+                .value_name("path"))
+            .arg(Arg::with_name("auth")
+                .long("auth")
+                .short("a")
+                .help("Authentication pair used when connecting to server \
+                [example value: user,password]")
+                .takes_value(true))
+        )
+
+        // replay
+        .subcommand(SubCommand::with_name("replay")
+            .about("Replay a run from a replay log")
+            .long_about("Replay a run from a replay log.\n\n\
+            Restores the simulation from the snapshot stored at the start of\n\
+            the log and re-applies all the logged mutations and steps in order.\n\
+            Replay is stopped with an error as soon as it diverges from the\n\
+            original run.")
+            .display_order(32)
+            .arg(Arg::with_name("path")
+                .help("Path to the replay log")
+                .required(true)
+                .value_name("path"))
+            .arg(Arg::with_name("until")
+                .long("until")
+                .short("u")
+                .help("Stop replaying once the given clock is reached")
+                .takes_value(true)
+                .value_name("clock"))
+            .arg(Arg::with_name("snapshot")
+                .long("snapshot")
+                .help("Save the replayed simulation as a snapshot with the given name")
+                .takes_value(true)
+                .value_name("name"))
+        )
+
         .subcommand(SubCommand::with_name("worker")
             .about("Start a worker")
             .long_about("Start a worker. Worker is the smallest independent part\n\
@@ -303,6 +651,12 @@ pub fn app_matches() -> ArgMatches<'static> {
                 .takes_value(true)
                 .min_values(0)
                 .value_name("address"))
+            .arg(Arg::with_name("config")
+                .long("config")
+                .short("c")
+                .help("Path to worker config file, declaring plugins to load")
+                .takes_value(true)
+                .value_name("path"))
         )
 
         .subcommand(SubCommand::with_name("workplace")
@@ -366,6 +720,20 @@ pub fn app_matches() -> ArgMatches<'static> {
                 .value_name("address"))
         )
 
+        .subcommand(SubCommand::with_name("bridge")
+            .about("Start an MQTT bridge")
+            .long_about("Start an MQTT bridge.\n\n\
+            Bridge connects to a server and an MQTT broker, publishing\n\
+            selected vars to topics after each step and translating\n\
+            messages on subscribed topics into var writes or event\n\
+            invocations. Requires the `mqtt` feature.")
+            .display_order(30)
+            .arg(Arg::with_name("config")
+                .help("Path to the bridge config file")
+                .required(true)
+                .value_name("path"))
+        )
+
         .subcommand(SubCommand::with_name("organizer")
             .about("Start a union organizer")
             .display_order(26)
@@ -397,10 +765,17 @@ pub fn start(matches: ArgMatches) -> Result<()> {
     match matches.subcommand() {
         ("new", Some(m)) => start_new(m),
         ("test", Some(m)) => start_test(m),
+        ("dump", Some(m)) => start_dump(m),
         ("run", Some(m)) => start_run(m),
         ("server", Some(m)) => start_server(m),
         ("client", Some(m)) => start_client(m),
+        ("query", Some(m)) => start_query(m),
+        ("record", Some(m)) => start_record(m),
+        ("replay", Some(m)) => start_replay(m),
         ("worker", Some(m)) => start_worker(m),
+        ("attach", Some(m)) => start_attach(m),
+        ("diag", Some(m)) => start_diag(m),
+        ("bridge", Some(m)) => start_bridge(m),
         _ => Ok(()),
     }
 }
@@ -426,6 +801,37 @@ fn start_test(matches: &ArgMatches) -> Result<()> {
     Ok(())
 }
 
+/// Loads a simulation, processes the requested number of steps and dumps
+/// the resulting state.
+fn start_dump(matches: &ArgMatches) -> Result<()> {
+    let path = matches.value_of("path").unwrap();
+    let mut sim = if matches.is_present("snapshot") {
+        Sim::from_snapshot_at(path)?
+    } else {
+        Sim::from_scenario_at(path)?
+    };
+    let steps = matches.value_of("steps").unwrap().parse::<usize>()?;
+    for _ in 0..steps {
+        sim.step()?;
+    }
+    let options = outcome::sim::dump::DumpOptions {
+        components: matches
+            .values_of("component")
+            .map(|v| v.map(|s| s.to_string()).collect())
+            .unwrap_or_default(),
+        prefixes: matches
+            .values_of("prefix")
+            .map(|v| v.map(|s| s.to_string()).collect())
+            .unwrap_or_default(),
+    };
+    let dump = sim.dump_text(&options);
+    match matches.value_of("output") {
+        Some(output) => std::fs::write(output, dump)?,
+        None => print!("{}", dump),
+    }
+    Ok(())
+}
+
 /// Starts a new simulation run, using a scenario or a snapshot file.
 ///
 /// # Resolving ambiguity
@@ -619,7 +1025,7 @@ fn start_run_scenario(path: PathBuf, matches: &ArgMatches) -> Result<()> {
         })
         .expect("error setting ctrlc handler");
 
-        interactive::start(
+        let summary = interactive::start(
             interactive::InterfaceType::Scenario(path.to_string_lossy().to_string()),
             &config_path,
             on_change,
@@ -628,6 +1034,9 @@ fn start_run_scenario(path: PathBuf, matches: &ArgMatches) -> Result<()> {
                 action: OnSignalAction::Custom,
             }),
         )?;
+        if let Some(summary) = summary {
+            report_run_summary(&summary, matches)?;
+        }
     }
     Ok(())
 }
@@ -635,14 +1044,28 @@ fn start_run_scenario(path: PathBuf, matches: &ArgMatches) -> Result<()> {
 fn start_run_snapshot(path: PathBuf, matches: &ArgMatches) -> Result<()> {
     info!("Running interactive session using snapshot at: {:?}", path);
     if matches.is_present("interactive") {
-        interactive::start(
+        let summary = interactive::start(
             interactive::InterfaceType::Snapshot(
                 path.file_name().unwrap().to_string_lossy().to_string(),
             ),
             matches.value_of("icfg").unwrap_or(interactive::CONFIG_FILE),
             None,
             None,
-        );
+        )?;
+        if let Some(summary) = summary {
+            report_run_summary(&summary, matches)?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints the run summary, optionally also writing it to a json file
+/// if the `stats-json` argument is present.
+fn report_run_summary(summary: &RunSummary, matches: &ArgMatches) -> Result<()> {
+    println!("{}", summary);
+    if let Some(path) = matches.value_of("stats-json") {
+        std::fs::write(path, serde_json::to_string_pretty(summary)?)?;
+        println!("Run summary written to {}", path);
     }
     Ok(())
 }
@@ -693,6 +1116,7 @@ fn start_server(matches: &ArgMatches) -> Result<()> {
         },
 
         use_auth: false,
+        anonymous_admin: matches.is_present("anonymous-admin"),
         use_compression: matches.is_present("use-compression"),
         auth_pairs: vec![],
         transports: match matches.value_of("transports") {
@@ -722,6 +1146,23 @@ fn start_server(matches: &ArgMatches) -> Result<()> {
             }
             None => default.encodings,
         },
+        audit_log: matches.value_of("audit-log").map(|p| PathBuf::from(p)),
+        publish_address: matches.value_of("publish").map(|a| a.to_string()),
+        crashdump_dir: matches.value_of("crashdump-dir").map(|p| PathBuf::from(p)),
+        replay_log: matches.value_of("replay-log").map(|p| PathBuf::from(p)),
+        scenarios_dir: matches
+            .value_of("scenarios-dir")
+            .map(|p| PathBuf::from(p))
+            .unwrap_or(default.scenarios_dir),
+        memory_soft_limit: match matches.value_of("memory-soft-limit") {
+            Some(mb) => Some(mb.parse::<u64>()? * 1024 * 1024),
+            None => None,
+        },
+        memory_hard_limit: match matches.value_of("memory-hard-limit") {
+            Some(mb) => Some(mb.parse::<u64>()? * 1024 * 1024),
+            None => None,
+        },
+        ..default
     };
 
     let worker_addrs = match matches.value_of("workers") {
@@ -732,9 +1173,15 @@ fn start_server(matches: &ArgMatches) -> Result<()> {
         None => Vec::new(),
     };
 
-    let sim_instance = match matches.value_of("organizer") {
+    let mut sim_instance = match matches.value_of("organizer") {
         Some(addr) => {
-            if let Some(scenario_path) = matches.value_of("scenario") {
+            if let Some(location) = matches.value_of("restore-checkpoint") {
+                SimConnection::UnionOrganizer(Organizer::from_checkpoint(
+                    location,
+                    addr,
+                    worker_addrs,
+                )?)
+            } else if let Some(scenario_path) = matches.value_of("scenario") {
                 SimConnection::UnionOrganizer(Organizer::new_with_path(
                     &scenario_path,
                     addr,
@@ -772,11 +1219,33 @@ fn start_server(matches: &ArgMatches) -> Result<()> {
             } else if let Some(snapshot_path) = matches.value_of("snapshot") {
                 SimConnection::Local(Sim::from_snapshot_at(&snapshot_path)?)
             } else {
-                panic!("")
+                SimConnection::Idle
             }
         }
     };
 
+    if let SimConnection::UnionOrganizer(organ) = &mut sim_instance {
+        if let Some(trigger) = matches.value_of("step-trigger") {
+            organ.trigger = trigger.parse()?;
+        }
+        if let Some(secs) = matches.value_of("worker-timeout") {
+            organ.net.heartbeat_timeout = Some(Duration::from_secs(secs.parse()?));
+        }
+        if let Some(policy) = matches.value_of("dead-worker-policy") {
+            organ.dead_worker_policy = policy.parse()?;
+        }
+        if let Some(placement) = matches.value_of("placement") {
+            organ.central.set_placement_by_name(placement)?;
+        }
+        if let Some(steps) = matches.value_of("recovery-snapshot-interval") {
+            organ.recovery_snapshot_interval = Some(steps.parse()?);
+        }
+        if let Some(location) = matches.value_of("checkpoint-store") {
+            organ.checkpoint_store = Some(location.to_string());
+            organ.checkpoint_interval = matches.value_of("checkpoint-interval").unwrap().parse()?;
+        }
+    }
+
     let mut server = Server::new_with_config(server_address, config, sim_instance)?;
     server.initialize_services()?;
 
@@ -790,19 +1259,14 @@ fn start_server(matches: &ArgMatches) -> Result<()> {
 
     server.start_polling(running)?;
     println!("Initiating graceful shutdown...");
-    for (client_id, client) in &mut server.clients {
-        client.connection.disconnect(None);
-    }
-    match &server.sim {
-        SimConnection::UnionOrganizer(coord) => {
-            for (_, worker) in &coord.net.workers {
-                worker
-                    .connection
-                    .send_event(SocketEvent::new(SocketEventType::Disconnect), None)?;
-            }
+    match server.sim_connection() {
+        SimConnection::Local(sim) => report_run_summary(&sim.run_stats.summary(), matches)?,
+        SimConnection::UnionOrganizer(organ) => {
+            report_run_summary(&organ.run_stats.summary(), matches)?
         }
         _ => (),
     }
+    server.disconnect_all()?;
 
     // server.manual_poll()?;
     server.cleanup()?;
@@ -812,6 +1276,97 @@ fn start_server(matches: &ArgMatches) -> Result<()> {
     Ok(())
 }
 
+/// Runs a single query on a remote server and prints the results.
+fn start_query(matches: &ArgMatches) -> Result<()> {
+    let query = query::build_query(
+        matches
+            .values_of("filter")
+            .map(|v| v.collect())
+            .unwrap_or_default(),
+        matches
+            .values_of("map")
+            .map(|v| v.collect())
+            .unwrap_or_default(),
+    )?;
+    let format = matches.value_of("format").unwrap_or("table").parse()?;
+
+    let mut client = outcome_net::Client::new_with_config(outcome_net::ClientConfig {
+        name: "cli-query".to_string(),
+        ..Default::default()
+    })?;
+    client.connect(
+        matches.value_of("server-addr").unwrap(),
+        matches.value_of("auth").map(|s| s.to_string()),
+    )?;
+    let resp = client.native_query(query)?;
+    client.disconnect()?;
+    if let Some(error) = resp.error {
+        return Err(Error::msg(error));
+    }
+    query::print_product(resp.query_product, format)
+}
+
+fn start_record(matches: &ArgMatches) -> Result<()> {
+    let action = match matches.value_of("action").unwrap() {
+        "start" => RecorderAction::Start,
+        "stop" => RecorderAction::Stop,
+        _ => RecorderAction::Flush,
+    };
+    let req = RecorderRequest {
+        action,
+        addrs: matches
+            .values_of("addr")
+            .map(|v| v.map(|s| s.to_string()).collect())
+            .unwrap_or_default(),
+        interval: matches.value_of("interval").unwrap_or("1").parse()?,
+        format: matches.value_of("format").unwrap_or("csv").to_string(),
+        path: matches
+            .value_of("output")
+            .unwrap_or("recording.csv")
+            .to_string(),
+    };
+
+    let mut client = outcome_net::Client::new_with_config(outcome_net::ClientConfig {
+        name: "cli-record".to_string(),
+        ..Default::default()
+    })?;
+    client.connect(
+        matches.value_of("server-addr").unwrap(),
+        matches.value_of("auth").map(|s| s.to_string()),
+    )?;
+    let resp = client.control_recorder(req);
+    client.disconnect()?;
+    match resp?.recording {
+        true => println!("recording in progress"),
+        false => println!("not recording"),
+    }
+    Ok(())
+}
+
+fn start_replay(matches: &ArgMatches) -> Result<()> {
+    let path = matches.value_of("path").unwrap();
+    let mut replay = Replay::open(path)?;
+    let start_clock = replay.sim.get_clock();
+    let sim = match matches.value_of("until") {
+        Some(clock) => {
+            replay.run_until(clock.parse()?)?;
+            replay.sim
+        }
+        None => replay.run()?,
+    };
+    println!(
+        "replayed {} steps, clock: {}, entities: {}",
+        sim.get_clock() - start_clock,
+        sim.get_clock(),
+        sim.entity_count()
+    );
+    if let Some(name) = matches.value_of("snapshot") {
+        sim.save_snapshot(name, false)?;
+        println!("saved snapshot: {}", name);
+    }
+    Ok(())
+}
+
 fn start_client(matches: &ArgMatches) -> Result<()> {
     let mut client = outcome_net::Client::new_with_config(
         // matches.value_of("public-addr").map(|s| s.to_string()),
@@ -822,6 +1377,7 @@ fn start_client(matches: &ArgMatches) -> Result<()> {
                 None => None,
             },
             is_blocking: matches.is_present("blocking"),
+            is_observer: matches.is_present("observer"),
             compress: CompressionPolicy::from_str(matches.value_of("compress").unwrap())?,
             //matches.is_present("compress"),
             encodings: match matches.value_of("encodings") {
@@ -850,6 +1406,11 @@ fn start_client(matches: &ArgMatches) -> Result<()> {
                 }
                 None => Vec::new(),
             },
+            float_precision: matches.value_of("precision").unwrap().parse()?,
+            reconnect: match matches.is_present("reconnect") {
+                true => Some(outcome_net::RetryPolicy::default()),
+                false => None,
+            },
         },
     )?;
 
@@ -881,6 +1442,65 @@ fn start_client(matches: &ArgMatches) -> Result<()> {
     Ok(())
 }
 
+fn start_attach(matches: &ArgMatches) -> Result<()> {
+    match (matches.value_of("coord"), matches.value_of("worker")) {
+        (Some(addr), _) => crate::attach::start(addr, crate::attach::Target::Coord),
+        (_, Some(addr)) => crate::attach::start(addr, crate::attach::Target::Worker),
+        _ => Err(Error::msg("provide either a coord or a worker address")),
+    }
+}
+
+fn start_diag(matches: &ArgMatches) -> Result<()> {
+    let addr = matches.value_of("address").unwrap();
+    match matches.values_of("command") {
+        Some(command) => {
+            let command = command.collect::<Vec<_>>().join(" ");
+            crate::attach::run_diagnostics(addr, &command)
+        }
+        None => crate::attach::start_diagnostics(addr),
+    }
+}
+
+#[cfg(feature = "worker_plugins")]
+fn load_worker_config(worker: &mut Worker, path: &str) -> Result<()> {
+    let config: outcome_net::plugin::WorkerConfig =
+        toml::from_str(&std::fs::read_to_string(path)?)?;
+    worker.load_plugins(&config)?;
+    println!("Loaded {} worker plugin(s)", config.plugins.len());
+    Ok(())
+}
+
+#[cfg(not(feature = "worker_plugins"))]
+fn load_worker_config(_worker: &mut Worker, _path: &str) -> Result<()> {
+    Err(Error::msg(
+        "worker config requires the `worker_plugins` feature",
+    ))
+}
+
+#[cfg(feature = "mqtt")]
+fn start_bridge(matches: &ArgMatches) -> Result<()> {
+    let path = matches.value_of("config").unwrap();
+    let config: outcome_net::mqtt::BridgeConfig = toml::from_str(&std::fs::read_to_string(path)?)?;
+    let mut bridge = outcome_net::mqtt::MqttBridge::new(config)?;
+    println!("Bridge running");
+
+    // allow graceful shutdown on signal
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })
+    .expect("error setting ctrlc handler");
+
+    bridge.start_polling(running)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "mqtt"))]
+fn start_bridge(_matches: &ArgMatches) -> Result<()> {
+    Err(Error::msg("bridge requires the `mqtt` feature"))
+}
+
 fn start_worker(matches: &ArgMatches) -> Result<()> {
     let mut use_auth = matches.is_present("use_auth");
     let passwd_list = match matches.value_of("passwd") {
@@ -896,6 +1516,9 @@ fn start_worker(matches: &ArgMatches) -> Result<()> {
     }
 
     let mut worker = Worker::new(matches.value_of("address"))?;
+    if let Some(config_path) = matches.value_of("config") {
+        load_worker_config(&mut worker, config_path)?;
+    }
     println!("Now listening on {}", worker.greeter.listener_addr()?);
 
     if let Some(coord_addr) = matches.value_of("organizer") {
@@ -931,9 +1554,7 @@ fn start_worker(matches: &ArgMatches) -> Result<()> {
         server.start_polling(running);
 
         println!("Initiating graceful shutdown...");
-        for (client_id, client) in &mut server.clients {
-            client.connection.disconnect(None);
-        }
+        server.disconnect_all()?;
         // server.manual_poll()?;
         server.cleanup()?;
 
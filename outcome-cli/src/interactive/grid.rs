@@ -0,0 +1,321 @@
+//! Grid viewer for the terminal.
+//!
+//! Grid values are mapped to colors using one of the available color maps,
+//! and printed using the `img_print` facilities. Large grids can be
+//! inspected by zooming in on a region and panning around. Diff view shows
+//! the change of each cell since the previous render.
+//!
+//! In remote mode only the visible region of the grid is requested from
+//! the server.
+
+extern crate image;
+
+use std::str::FromStr;
+
+use anyhow::{Error, Result};
+use outcome::{Address, Var};
+
+use self::image::{DynamicImage, GenericImage, Rgba};
+use super::{Config, SimDriver};
+
+/// Width of the rendered view in terminal columns.
+const VIEW_WIDTH: u32 = 80;
+/// Height of the rendered view in pixels, two pixels are printed on each
+/// terminal line.
+const VIEW_HEIGHT: u32 = 48;
+
+/// Mapping of normalized values onto colors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorMap {
+    Gray,
+    Heat,
+    Viridis,
+    /// Blue for negative, white for zero and red for positive values
+    Diverging,
+}
+
+impl FromStr for ColorMap {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "" | "gray" => Ok(ColorMap::Gray),
+            "heat" => Ok(ColorMap::Heat),
+            "viridis" => Ok(ColorMap::Viridis),
+            "diverging" => Ok(ColorMap::Diverging),
+            _ => Err(Error::msg(format!("unknown color map: {}", s))),
+        }
+    }
+}
+
+impl ColorMap {
+    fn stops(&self) -> &'static [[u8; 3]] {
+        match self {
+            ColorMap::Gray => &[[0, 0, 0], [255, 255, 255]],
+            ColorMap::Heat => &[[0, 0, 0], [200, 0, 0], [255, 200, 0], [255, 255, 255]],
+            ColorMap::Viridis => &[
+                [68, 1, 84],
+                [59, 82, 139],
+                [33, 145, 140],
+                [94, 201, 98],
+                [253, 231, 37],
+            ],
+            ColorMap::Diverging => &[[40, 60, 200], [255, 255, 255], [200, 30, 30]],
+        }
+    }
+
+    /// Returns the color for a value normalized to the `0..=1` range.
+    pub fn color(&self, value: f64) -> [u8; 3] {
+        let stops = self.stops();
+        let pos = value.max(0.).min(1.) * (stops.len() - 1) as f64;
+        let n = (pos.floor() as usize).min(stops.len() - 2);
+        let t = pos - n as f64;
+        let mut out = [0; 3];
+        for c in 0..3 {
+            out[c] = (stops[n][c] as f64 * (1. - t) + stops[n + 1][c] as f64 * t) as u8;
+        }
+        out
+    }
+}
+
+/// State of the grid viewer.
+pub struct GridViewer {
+    /// Address of the viewed grid var
+    pub addr: Option<String>,
+    /// Number of pixels for each grid cell, 0 fits the whole grid in view
+    pub zoom: u32,
+    /// Position of the top left visible cell
+    pub offset: (u32, u32),
+    /// Whether to show the change since previous render instead of values
+    pub diff: bool,
+    /// Values from the previous render along with the viewport they were
+    /// taken from
+    previous: Option<((u32, u32, u32, u32), Vec<Vec<f64>>)>,
+}
+
+impl Default for GridViewer {
+    fn default() -> Self {
+        GridViewer {
+            addr: None,
+            zoom: 0,
+            offset: (0, 0),
+            diff: false,
+            previous: None,
+        }
+    }
+}
+
+impl GridViewer {
+    /// Sets the viewed grid, resetting the view if it's a different one.
+    pub fn set_addr(&mut self, addr: &str) -> Result<()> {
+        Address::from_str(addr)?;
+        if self.addr.as_deref() != Some(addr) {
+            *self = GridViewer {
+                addr: Some(addr.to_string()),
+                diff: self.diff,
+                ..GridViewer::default()
+            };
+        }
+        Ok(())
+    }
+
+    /// Parses zoom argument, either a number of pixels per cell, `in`,
+    /// `out` or `fit`.
+    pub fn set_zoom(&mut self, arg: &str) -> Result<()> {
+        self.zoom = match arg {
+            "in" => (self.zoom * 2).max(1),
+            "out" => self.zoom / 2,
+            "fit" => 0,
+            _ => arg.parse()?,
+        };
+        Ok(())
+    }
+
+    /// Moves the view by the given number of cells.
+    pub fn pan(&mut self, args: &str) -> Result<()> {
+        let split = args.split_whitespace().collect::<Vec<&str>>();
+        if split.len() != 2 {
+            return Err(Error::msg("expected two arguments: <dx> <dy>"));
+        }
+        let dx = split[0].parse::<i64>()?;
+        let dy = split[1].parse::<i64>()?;
+        self.offset = (
+            (self.offset.0 as i64 + dx).max(0) as u32,
+            (self.offset.1 as i64 + dy).max(0) as u32,
+        );
+        Ok(())
+    }
+
+    /// Fetches the visible region of the grid and prints it.
+    pub fn render(&mut self, driver: &mut SimDriver, config: &Config) -> Result<()> {
+        let addr = self
+            .addr
+            .clone()
+            .ok_or(Error::msg("no grid selected, use `show-grid <addr>`"))?;
+
+        // with zoom enabled only the visible part of the grid is fetched
+        let (x, y, width, height) = match self.zoom {
+            0 => (0, 0, u32::MAX, u32::MAX),
+            z => (
+                self.offset.0,
+                self.offset.1,
+                VIEW_WIDTH / z,
+                VIEW_HEIGHT / z,
+            ),
+        };
+        let (grid_size, region) = fetch_region(driver, &addr, x, y, width, height)?;
+        if self.zoom > 0 {
+            // don't let the view wander off the grid
+            self.offset = (
+                self.offset.0.min(grid_size.0.saturating_sub(1)),
+                self.offset.1.min(grid_size.1.saturating_sub(1)),
+            );
+        }
+        let values = region
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|v| v.to_float() as f64)
+                    .collect::<Vec<f64>>()
+            })
+            .collect::<Vec<_>>();
+        let region_height = values.len() as u32;
+        let region_width = values.first().map(|r| r.len()).unwrap_or(0) as u32;
+        if region_width == 0 || region_height == 0 {
+            println!("{}: empty region (grid size: {:?})", addr, grid_size);
+            return Ok(());
+        }
+
+        let viewport = (x, y, width, height);
+        let shown = match (&self.previous, self.diff) {
+            (Some((prev_viewport, prev)), true) if *prev_viewport == viewport => values
+                .iter()
+                .zip(prev)
+                .map(|(row, prev_row)| row.iter().zip(prev_row).map(|(v, p)| v - p).collect())
+                .collect(),
+            (_, true) => values.iter().map(|row| vec![0.; row.len()]).collect(),
+            _ => values.clone(),
+        };
+        self.previous = Some((viewport, values));
+
+        let (color_map, (min, max)) = if self.diff {
+            let extent = shown
+                .iter()
+                .flatten()
+                .fold(0f64, |acc: f64, v: &f64| acc.max(v.abs()));
+            (ColorMap::Diverging, (-extent, extent))
+        } else {
+            let range = match config.grid_value_range.as_slice() {
+                [min, max] => (*min, *max),
+                _ => shown
+                    .iter()
+                    .flatten()
+                    .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| {
+                        (min.min(*v), max.max(*v))
+                    }),
+            };
+            (ColorMap::from_str(&config.grid_color_map)?, range)
+        };
+
+        let (img_width, img_height) = match self.zoom {
+            0 => (VIEW_WIDTH, VIEW_HEIGHT),
+            z => (region_width * z, region_height * z),
+        };
+        let mut img = DynamicImage::new_rgba8(img_width, img_height);
+        for py in 0..img_height {
+            for px in 0..img_width {
+                let (cx, cy) = match self.zoom {
+                    0 => (
+                        px * region_width / img_width,
+                        py * region_height / img_height,
+                    ),
+                    z => (px / z, py / z),
+                };
+                let value = shown[cy as usize][cx as usize];
+                let norm = if max > min {
+                    (value - min) / (max - min)
+                } else {
+                    0.5
+                };
+                let [r, g, b] = color_map.color(norm);
+                img.put_pixel(px, py, Rgba([r, g, b, 255]));
+            }
+        }
+        super::img_print::print_image(img, true, img_width, img_height);
+        println!(
+            "{} [{}x{}] view: ({},{}) {}x{}, zoom: {}, range: {}..{}{}",
+            addr,
+            grid_size.0,
+            grid_size.1,
+            x,
+            y,
+            region_width,
+            region_height,
+            if self.zoom == 0 {
+                "fit".to_string()
+            } else {
+                self.zoom.to_string()
+            },
+            min,
+            max,
+            if self.diff { " (diff)" } else { "" }
+        );
+        Ok(())
+    }
+}
+
+/// Gets a region of the grid var along with the full grid dimensions.
+fn fetch_region(
+    driver: &mut SimDriver,
+    addr: &str,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) -> Result<((u32, u32), Vec<Vec<Var>>)> {
+    match driver {
+        SimDriver::Local(sim) => {
+            let var = sim.get_var(&Address::from_str(addr)?)?;
+            let (grid_width, grid_height) = var.grid_size()?;
+            let region =
+                var.grid_region(x as usize, y as usize, width as usize, height as usize)?;
+            Ok(((grid_width as u32, grid_height as u32), region))
+        }
+        SimDriver::Remote(client) => {
+            let resp = client.get_grid_region(addr, x, y, width, height)?;
+            if !resp.error.is_empty() {
+                return Err(Error::msg(resp.error));
+            }
+            Ok((resp.grid_size, resp.region))
+        }
+    }
+}
+
+#[test]
+fn color_maps_interpolate_between_stops() {
+    assert_eq!(ColorMap::Gray.color(0.), [0, 0, 0]);
+    assert_eq!(ColorMap::Gray.color(1.), [255, 255, 255]);
+    assert_eq!(ColorMap::Gray.color(0.5), [127, 127, 127]);
+    // values outside the range are clamped
+    assert_eq!(ColorMap::Diverging.color(-1.), [40, 60, 200]);
+    assert_eq!(ColorMap::Diverging.color(0.5), [255, 255, 255]);
+    assert_eq!(ColorMap::Viridis.color(2.), [253, 231, 37]);
+    assert!(ColorMap::from_str("rainbow").is_err());
+}
+
+#[test]
+fn view_is_zoomed_and_panned() {
+    let mut viewer = GridViewer::default();
+    viewer.set_addr("map:terrain:grid_float:height").unwrap();
+    viewer.set_zoom("in").unwrap();
+    viewer.set_zoom("in").unwrap();
+    assert_eq!(viewer.zoom, 2);
+    viewer.pan("3 -1").unwrap();
+    assert_eq!(viewer.offset, (3, 0));
+    assert!(viewer.pan("3").is_err());
+
+    // same grid keeps the view, another one resets it
+    viewer.set_addr("map:terrain:grid_float:height").unwrap();
+    assert_eq!(viewer.offset, (3, 0));
+    viewer.set_addr("map:terrain:grid_float:water").unwrap();
+    assert_eq!((viewer.zoom, viewer.offset), (0, (0, 0)));
+}
@@ -6,19 +6,26 @@
 //! modes. Local mode will operate directly on a `Sim` struct, while
 //! remote mode will use a `Client` connected to an `outcome` server.
 //! `Client` interface from the `outcome-net` crate is used.
+//!
+//! Commands that don't need anything specific to either mode are written
+//! once against the `SimInterface` trait, see `SimDriver::interface`.
 
 extern crate toml;
 
 mod compl;
 mod local;
+mod plot;
 mod remote;
+mod runf;
 
+#[cfg(feature = "img_print")]
+mod grid;
 #[cfg(feature = "img_print")]
 mod img_print;
 
 use std::io::Write;
 use std::ops::{Deref, DerefMut};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::{fs, io, thread};
@@ -27,10 +34,14 @@ use anyhow::Result;
 use linefeed::inputrc::parse_text;
 use linefeed::{Interface, ReadResult};
 
-use outcome::Sim;
+use outcome::machine::{WatchdogAction, WatchdogConfig};
+use outcome::sim::stats::RunSummary;
+use outcome::{Address, Sim, SimInterface};
+use outcome_net::msg::SnapshotLoadMode;
 use outcome_net::{Client, SocketEvent, SocketEventType};
 
 use self::compl::MainCompleter;
+use self::plot::PlotHistory;
 use outcome_net::msg::{SpawnEntitiesRequest, TransferResponseData};
 use std::time::Instant;
 
@@ -42,7 +53,15 @@ pub const CONFIG_FILE: &str = "interactive.yaml";
 // 2. add to Config struct
 // 3. add to Config impl fn get and set
 // 4. add to cfg-list command
-static CFG_VARS: &[&str] = &["turn_ticks", "show_on", "show_list"];
+static CFG_VARS: &[&str] = &[
+    "turn_ticks",
+    "show_on",
+    "show_list",
+    "prompt_format",
+    "prompt_vars",
+    "grid_color_map",
+    "grid_value_range",
+];
 
 /// Serializable configuration for the interactive interface.
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
@@ -57,6 +76,10 @@ pub struct Config {
     pub prompt_format: String,
     #[serde(default)]
     pub prompt_vars: Vec<String>,
+    #[serde(default)]
+    pub grid_color_map: String,
+    #[serde(default)]
+    pub grid_value_range: Vec<f64>,
 }
 
 impl Config {
@@ -67,6 +90,8 @@ impl Config {
             show_list: Vec::new(),
             prompt_format: "".to_string(),
             prompt_vars: Vec::new(),
+            grid_color_map: "gray".to_string(),
+            grid_value_range: Vec::new(),
         }
     }
 
@@ -93,6 +118,8 @@ impl Config {
             "show_list" => Ok(format!("{:?}", self.show_list)),
             "prompt_format" => Ok(self.prompt_format.clone()),
             "prompt_vars" => Ok(format!("{:?}", self.prompt_vars)),
+            "grid_color_map" => Ok(self.grid_color_map.clone()),
+            "grid_value_range" => Ok(format!("{:?}", self.grid_value_range)),
             _ => Err(io::Error::new(
                 io::ErrorKind::NotFound,
                 "Cfg variable doesn't exist",
@@ -126,6 +153,32 @@ impl Config {
                     }
                 }
             }
+            "prompt_format" => self.prompt_format = value.to_string(),
+            "prompt_vars" => {
+                self.prompt_vars = value
+                    .split(',')
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.to_string())
+                    .collect()
+            }
+            "grid_color_map" => self.grid_color_map = value.to_string(),
+            // either `min,max` or empty for automatic range
+            "grid_value_range" => {
+                self.grid_value_range = match value
+                    .split(',')
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.trim().parse::<f64>())
+                    .collect::<Result<Vec<f64>, _>>()
+                {
+                    Ok(r) if r.len() == 2 || r.is_empty() => r,
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::NotFound,
+                            "Failed parsing value",
+                        ))
+                    }
+                }
+            }
             _ => {
                 return Err(io::Error::new(
                     io::ErrorKind::NotFound,
@@ -159,6 +212,16 @@ pub enum SimDriver {
     Remote(Client),
 }
 
+impl SimDriver {
+    /// Returns the backend-independent simulation interface.
+    pub fn interface(&mut self) -> &mut dyn SimInterface {
+        match self {
+            SimDriver::Local(sim) => sim,
+            SimDriver::Remote(client) => client,
+        }
+    }
+}
+
 pub struct OnChange {
     pub trigger: Arc<Mutex<bool>>,
     pub action: OnChangeAction,
@@ -186,7 +249,7 @@ pub enum InterfaceType {
 }
 
 /// Variant without the external change trigger.
-pub fn start_simple(_type: InterfaceType, config_path: &str) -> Result<()> {
+pub fn start_simple(_type: InterfaceType, config_path: &str) -> Result<Option<RunSummary>> {
     start(_type, config_path, None, None)
 }
 
@@ -200,12 +263,17 @@ pub fn start_simple(_type: InterfaceType, config_path: &str) -> Result<()> {
 /// supporting a "watch" mode where changes to project files result in
 /// triggering actions such as restarting the simulation using newly
 /// introduced changes.
+///
+/// # Run summary
+///
+/// When running a local simulation, summary of the run is returned once
+/// the interactive session ends.
 pub fn start(
     _type: InterfaceType,
     config_path: &str,
     on_change: Option<OnChange>,
     on_signal: Option<OnSignal>,
-) -> Result<()> {
+) -> Result<Option<RunSummary>> {
     let path = match &_type {
         InterfaceType::Scenario(path) => Some(path.clone()),
         InterfaceType::Snapshot(path) => Some(path.clone()),
@@ -258,6 +326,11 @@ pub fn start(
             }
         };
 
+        // history of vars sampled for plotting
+        let mut plots = PlotHistory::default();
+        #[cfg(feature = "img_print")]
+        let mut grid_viewer = grid::GridViewer::default();
+
         println!("\nYou're now in interactive mode.");
         println!("See possible commands with \"help\". Exit using \"quit\" or ctrl-d.");
 
@@ -342,6 +415,7 @@ pub fn start(
                             interface.set_prompt(create_prompt(&mut driver, &config)?.as_str())?;
                         }
                     }
+                    plots.sample(&mut driver)?;
 
                     if let Some(on_sig) = &on_signal {
                         if on_sig.trigger.load(Ordering::SeqCst) {
@@ -399,23 +473,20 @@ pub fn start(
                             "run-until" => {
                                 unimplemented!();
                             }
-                            "runf" => {
-                                interface.lock_reader();
-                                let mut loop_count = args.parse::<u32>().unwrap();
-                                match driver.deref_mut() {
-                                    SimDriver::Local(ref mut sim) => {
-                                        while loop_count > 0 {
-                                            sim.step();
-                                            loop_count -= 1;
-                                        }
-                                    }
-                                    SimDriver::Remote(client) => {
-                                        client.server_step_request(loop_count)?;
-                                    }
+                            "runf" => match args.parse::<u32>() {
+                                Ok(steps) => {
+                                    interface.lock_reader();
+                                    runf::run_fast(
+                                        driver.deref_mut(),
+                                        steps,
+                                        on_signal.as_ref().map(|s| s.trigger.as_ref()),
+                                    )?;
+                                    interface.set_prompt(
+                                        create_prompt(&mut driver, &config)?.as_str(),
+                                    )?;
                                 }
-                                interface
-                                    .set_prompt(create_prompt(&mut driver, &config)?.as_str())?;
-                            }
+                                Err(_) => println!("runf takes an integer number of steps"),
+                            },
                             //TODO
                             "runf-until" => {
                                 unimplemented!();
@@ -508,22 +579,36 @@ pub fn start(
                             // spawn entity
                             "spawn" => {
                                 let split = args.split(" ").collect::<Vec<&str>>();
-                                match driver.deref_mut() {
-                                    SimDriver::Remote(client) => {
-                                        client.connection.send_payload(
-                                            SpawnEntitiesRequest {
-                                                entity_prefabs: vec![split[0].to_string()],
-                                                entity_names: vec![split[1].to_string()],
-                                            },
-                                            None,
-                                        )?;
-                                        client.connection.recv_msg()?;
+                                match driver.interface().spawn_entity(
+                                    Some(outcome::string::new_truncate(split[0])),
+                                    split.get(1).map(|n| outcome::string::new_truncate(n)),
+                                ) {
+                                    Ok(id) => println!("spawned entity {}", id),
+                                    Err(e) => println!("failed spawning entity: {}", e),
+                                }
+                            }
+                            // set a single var
+                            "set" => {
+                                let (addr, value) = split_first_word(args);
+                                let addr = match Address::from_str(addr) {
+                                    Ok(a) => a,
+                                    Err(e) => {
+                                        println!("invalid address: {}", e);
+                                        continue;
                                     }
-                                    SimDriver::Local(sim) => {
-                                        sim.spawn_entity(
-                                            Some(&outcome::string::new_truncate(split[0])),
-                                            Some(outcome::string::new_truncate(split[1])),
-                                        )?;
+                                };
+                                if let Err(e) = driver.interface().set_from_string(&addr, value) {
+                                    println!("failed setting var: {}", e);
+                                }
+                            }
+                            // add events to the global event queue
+                            "event" => {
+                                for event in args.split_whitespace() {
+                                    if let Err(e) = driver
+                                        .interface()
+                                        .add_event(outcome::string::new_truncate(event))
+                                    {
+                                        println!("failed adding event {}: {}", event, e);
                                     }
                                 }
                             }
@@ -551,10 +636,11 @@ pub fn start(
                                             SpawnEntitiesRequest {
                                                 entity_prefabs: vec![split[0].to_string()],
                                                 entity_names: vec![split[1].to_string()],
+                                                idempotency_key: None,
                                             },
                                             None,
                                         )?;
-                                        client.connection.recv_msg()?;
+                                        client.recv_msg()?;
                                     }
                                 }
                             }
@@ -606,6 +692,28 @@ pub fn start(
                                     _ => unimplemented!(),
                                 };
                             }
+                            // Replace current sim state with one loaded from a snapshot file.
+                            "load" => {
+                                if args.contains(" ") {
+                                    println!("Snapshot file path cannot contain spaces.");
+                                    continue;
+                                }
+                                let result: Result<()> = match driver.deref_mut() {
+                                    SimDriver::Local(sim) => Sim::from_snapshot_at(args)
+                                        .map(|s| *sim = s)
+                                        .map_err(|e| e.into()),
+                                    SimDriver::Remote(client) => match fs::read(args) {
+                                        Ok(bytes) => client
+                                            .load_snapshot(&bytes, SnapshotLoadMode::Replace)
+                                            .map_err(|e| e.into()),
+                                        Err(e) => Err(e.into()),
+                                    },
+                                };
+                                if let Err(e) = result {
+                                    println!("{}", e);
+                                    continue;
+                                }
+                            }
 
                             "help" => {
                                 println!("available commands:");
@@ -621,10 +729,18 @@ pub fn start(
 turn_ticks              {turn_ticks}
 show_on                 {show_on}
 show_list               {show_list}
+prompt_format           {prompt_format}
+prompt_vars             {prompt_vars}
+grid_color_map          {grid_color_map}
+grid_value_range        {grid_value_range}
 ",
                                     turn_ticks = config.turn_ticks,
                                     show_on = config.show_on,
                                     show_list = format!("{:?}", config.show_list),
+                                    prompt_format = config.prompt_format,
+                                    prompt_vars = format!("{:?}", config.prompt_vars),
+                                    grid_color_map = config.grid_color_map,
+                                    grid_value_range = format!("{:?}", config.grid_value_range),
                                 );
                             }
                             "cfg-get" => match config.get(args) {
@@ -666,13 +782,40 @@ show_list               {show_list}
                                 };
                             }
 
-                            #[cfg(feature = "outcome_core/grids")]
-                            "show-grid" => match driver.deref() {
-                                SimDriver::Local(sim) => {
-                                    local::print_show_grid(&sim, &config, args)
+                            #[cfg(feature = "img_print")]
+                            "show-grid" => {
+                                let result = match args {
+                                    "" => Ok(()),
+                                    _ => grid_viewer.set_addr(args),
                                 }
-                                _ => unimplemented!(),
-                            },
+                                .and_then(|_| grid_viewer.render(&mut driver, &config));
+                                if let Err(e) = result {
+                                    println!("show-grid: {}", e);
+                                }
+                            }
+                            #[cfg(feature = "img_print")]
+                            "grid-zoom" => {
+                                if let Err(e) = grid_viewer
+                                    .set_zoom(args)
+                                    .and_then(|_| grid_viewer.render(&mut driver, &config))
+                                {
+                                    println!("grid-zoom: {}", e);
+                                }
+                            }
+                            #[cfg(feature = "img_print")]
+                            "grid-pan" => {
+                                if let Err(e) = grid_viewer
+                                    .pan(args)
+                                    .and_then(|_| grid_viewer.render(&mut driver, &config))
+                                {
+                                    println!("grid-pan: {}", e);
+                                }
+                            }
+                            #[cfg(feature = "img_print")]
+                            "grid-diff" => {
+                                grid_viewer.diff = !grid_viewer.diff;
+                                println!("grid diff view: {}", grid_viewer.diff);
+                            }
 
                             "show" => match driver.deref() {
                                 SimDriver::Local(sim) => local::print_show(&sim, &config),
@@ -701,6 +844,34 @@ show_list               {show_list}
                                 config.show_list.clear();
                             }
 
+                            "plot" => {
+                                if args.is_empty() {
+                                    for addr in plots.addresses() {
+                                        println!("{}", addr);
+                                    }
+                                } else {
+                                    match plot::parse_args(args) {
+                                        Ok((addr, window)) => {
+                                            if plots.track(&addr) {
+                                                plots.sample(&mut driver)?;
+                                                println!(
+                                                    "started sampling {}, step the simulation to collect more samples",
+                                                    addr
+                                                );
+                                            }
+                                            if let Some(out) = plots.render(&addr, window) {
+                                                println!("{}", out);
+                                            }
+                                        }
+                                        Err(e) => {
+                                            println!("usage: plot <addr> [--window N] ({})", e)
+                                        }
+                                    }
+                                }
+                            }
+
+                            "plot-clear" => plots.clear(),
+
                             "history" => {
                                 let w = interface.lock_writer_erase()?;
 
@@ -746,6 +917,75 @@ show_list               {show_list}
                                     _ => (),
                                 }
                             }
+                            "compact" => match driver.deref_mut() {
+                                SimDriver::Local(sim) => {
+                                    let fragmentation = sim.fragmentation();
+                                    let remap = sim.compact_entities()?;
+                                    println!(
+                                        "fragmentation: {:.2} -> {:.2}, reassigned {} entity ids",
+                                        fragmentation,
+                                        sim.fragmentation(),
+                                        remap.len()
+                                    );
+                                }
+                                _ => println!("compaction can only be triggered on a local sim"),
+                            },
+                            "export-model" => match driver.deref_mut() {
+                                SimDriver::Local(sim) => {
+                                    let dir = if args.is_empty() { "." } else { args };
+                                    sim.model.export_yaml(Path::new(dir))?;
+                                    println!("exported model to: {}", dir);
+                                }
+                                _ => println!("model can only be exported from a local sim"),
+                            },
+                            "strict" => match driver.deref_mut() {
+                                SimDriver::Local(sim) => {
+                                    sim.strict = match args {
+                                        "on" => true,
+                                        "off" => false,
+                                        _ => !sim.strict,
+                                    };
+                                    println!("strict mode: {}", sim.strict);
+                                }
+                                _ => println!(
+                                    "strict mode is not supported on distributed sims, \
+                                     it can only be set on a local sim"
+                                ),
+                            },
+                            "watchdog" => match driver.deref_mut() {
+                                SimDriver::Local(sim) => {
+                                    let mut split = args.split_whitespace();
+                                    match split.next() {
+                                        None | Some("off") => sim.watchdog = None,
+                                        Some(millis) => match millis.parse::<u64>() {
+                                            Ok(step_millis) => {
+                                                let action = match split.next() {
+                                                    Some("abort_step") => WatchdogAction::AbortStep,
+                                                    Some("abort_component") => {
+                                                        WatchdogAction::AbortComponent
+                                                    }
+                                                    _ => WatchdogAction::Report,
+                                                };
+                                                sim.watchdog = Some(WatchdogConfig {
+                                                    step_millis,
+                                                    action,
+                                                });
+                                            }
+                                            Err(e) => {
+                                                println!("failed parsing time limit: {}", e)
+                                            }
+                                        },
+                                    }
+                                    match &sim.watchdog {
+                                        Some(w) => println!(
+                                            "watchdog: {}ms, {:?}",
+                                            w.step_millis, w.action
+                                        ),
+                                        None => println!("watchdog: off"),
+                                    }
+                                }
+                                _ => println!("watchdog can only be set on a local sim"),
+                            },
                             "model" => match driver.deref_mut() {
                                 SimDriver::Local(sim) => {
                                     println!("{:#?}", sim.model);
@@ -755,6 +995,7 @@ show_list               {show_list}
 
                             _ => println!("couldn't recognize input: {:?}", line),
                         }
+                        plots.sample(&mut driver)?;
                         if do_run_freq.is_none() && !do_run_loop {
                             interface.set_prompt(create_prompt(&mut driver, &config)?.as_str())?;
                         }
@@ -796,7 +1037,9 @@ show_list               {show_list}
                                 let new_model =
                                     Sim::from_scenario_at(&path.clone().unwrap())?.model;
 
-                                sim.model = new_model;
+                                if let Err(e) = sim.update_model(new_model) {
+                                    error!("failed updating model: {}", e);
+                                }
                             }
                             *oc = false;
 
@@ -813,7 +1056,11 @@ show_list               {show_list}
         }
     }
     println!("Leaving interactive mode.");
-    Ok(())
+    let summary = match driver_arc.lock().unwrap().deref() {
+        SimDriver::Local(sim) => Some(sim.run_stats.summary()),
+        SimDriver::Remote(_) => None,
+    };
+    Ok(summary)
 }
 
 pub fn create_prompt(driver: &mut SimDriver, cfg: &Config) -> Result<String> {
@@ -823,20 +1070,42 @@ pub fn create_prompt(driver: &mut SimDriver, cfg: &Config) -> Result<String> {
     }
 }
 
+/// Renders the prompt based on the configured template.
+///
+/// `{clock}` is replaced with the current clock value, while each `{}` is
+/// replaced with the value of the corresponding var from the `prompt_vars`
+/// list, in order. Vars that couldn't be retrieved, for example because
+/// the entity holding them no longer exists, are displayed as `?`.
+pub(crate) fn render_prompt(format: &str, clock: usize, vars: Vec<Option<String>>) -> String {
+    let clock = clock.to_string();
+    let mut vars = vars.into_iter();
+    let mut out = String::new();
+    for (n, part) in format.split("{}").enumerate() {
+        if n > 0 {
+            out.push_str(&vars.next().flatten().unwrap_or("?".to_string()));
+        }
+        out.push_str(&part.replace("{clock}", &clock));
+    }
+    format!("[{}] ", out)
+}
+
 static APP_COMMANDS: &[(&str, &str)] = &[
     ("run", "Run a number of simulation ticks (hours), takes in an integer number"),
-    ("runf", "Similar to `run` but doesn't listen to interupt signals, `f` stands for \"fast\" \
-        (it's faster, but you will have to wait until it's finished processing)"),
+    ("runf", "Similar to `run` but without processing input between steps, `f` stands for \"fast\". \
+        Reports progress with an estimated time left, can be cancelled with ctrl-c"),
     ("run-freq", "Run simulation at a constant pace, using the provided frequency"),
     ("test", "Run quick mem+proc test. Takes in a number of secs to run the average processing speed test (default=2)"),
     ("ls", "List simple variables (no lists or grids). Takes in a string argument, returns only vars that contain that string in their address"),
     ("snap", "Export current sim state to snapshot file. Takes a path to target file, relative to where endgame is running."),
     ("snapc", "Same as snap but applies compression"),
+    ("load", "Replace current sim state with one loaded from a snapshot file. With a remote sim the file is uploaded to the server"),
     ("cfg", "Set config variable"),
     ("cfg-get", "Print the value of one config variable"),
     ("cfg-list", "Get a list of all config variables"),
     ("cfg-save", "Save current configuration to file"),
     ("cfg-reload", "Reload current configuration from file"),
+    ("set", "Set the var at the address, takes an address and a value"),
+    ("event", "Add events to the event queue, to be processed during the next step. Takes one or more event names"),
     ("show", "Print selected simulation data"),
     ("show-add", "Add to the list of simulation data to be shown"),
     (
@@ -848,6 +1117,16 @@ static APP_COMMANDS: &[(&str, &str)] = &[
         "Clear the list of simulation data to be shown",
     ),
     ("show-toggle", "Toggle automatic printing after each turn"),
+    ("show-grid", "Print a grid var using the configured color map. Takes an address, or re-renders the current grid if none is given"),
+    ("grid-zoom", "Zoom the grid view, takes a number of pixels per cell, `in`, `out` or `fit`"),
+    ("grid-pan", "Move the zoomed in grid view, takes horizontal and vertical offsets in cells"),
+    ("grid-diff", "Toggle showing the change in grid values since the previous render"),
+    ("compact", "Reassign entity ids to fill gaps left by despawned entities and shrink internal storage"),
+    ("export-model", "Write the current model, including changes made at runtime, out as module files. Takes a path to target directory"),
+    ("strict", "Toggle aborting the step on first logic error, optionally takes `on` or `off`"),
+    ("watchdog", "Set the step time limit in milliseconds, optionally followed by an action: `report` (default), `abort_step` or `abort_component`. Takes `off` to disable"),
+    ("plot", "Plot recent history of a numeric var as a sparkline. Takes an address and an optional `--window N` (default=60). Sampling starts on first use"),
+    ("plot-clear", "Stop sampling all plotted vars"),
     ("history", "Print input history"),
     ("help", "Show available commands"),
     (
@@ -864,3 +1143,18 @@ fn split_first_word(s: &str) -> (&str, &str) {
         None => (s, ""),
     }
 }
+
+#[test]
+fn prompt_template_is_rendered() {
+    let vars = vec![Some("12".to_string()), None];
+    assert_eq!(
+        render_prompt("{clock} | {}:{}", 5, vars),
+        "[5 | 12:?] ".to_string()
+    );
+    // vars missing from the list are displayed the same as unavailable ones
+    assert_eq!(render_prompt("{}", 0, vec![]), "[?] ".to_string());
+
+    let mut config = Config::new();
+    config.set("prompt_vars", "a:b:float:c,,d:e:int:f").unwrap();
+    assert_eq!(config.prompt_vars.len(), 2);
+}
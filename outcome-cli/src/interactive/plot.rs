@@ -0,0 +1,188 @@
+//! Terminal plotting of var time series.
+//!
+//! Values of plotted vars are sampled on the client side each time the
+//! simulation clock advances, and kept in a bounded history buffer for
+//! each address. History is rendered as a unicode sparkline.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::str::FromStr;
+
+use anyhow::{Error, Result};
+use outcome::Address;
+
+use super::SimDriver;
+
+/// Number of most recent samples plotted if no window is specified.
+pub const DEFAULT_WINDOW: usize = 60;
+
+/// Maximum number of samples retained for each address.
+const HISTORY_CAPACITY: usize = 1000;
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Sampled history of plotted vars.
+#[derive(Default)]
+pub struct PlotHistory {
+    series: BTreeMap<String, VecDeque<(usize, f64)>>,
+}
+
+impl PlotHistory {
+    /// Starts tracking the var at the given address. Returns false if the
+    /// address was already tracked.
+    pub fn track(&mut self, addr: &str) -> bool {
+        if self.series.contains_key(addr) {
+            return false;
+        }
+        self.series.insert(addr.to_string(), VecDeque::new());
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.series.clear();
+    }
+
+    pub fn addresses(&self) -> Vec<&String> {
+        self.series.keys().collect()
+    }
+
+    /// Records current values of all tracked vars. Each address gets at
+    /// most one sample per clock tick. Vars that don't exist or can't be
+    /// represented as a number are skipped.
+    pub fn sample(&mut self, driver: &mut SimDriver) -> Result<()> {
+        if self.series.is_empty() {
+            return Ok(());
+        }
+        let addrs = self.series.keys().cloned().collect::<Vec<String>>();
+        let (clock, values) = match driver {
+            SimDriver::Local(sim) => (
+                sim.get_clock(),
+                addrs
+                    .iter()
+                    .map(|a| {
+                        Address::from_str(a)
+                            .ok()
+                            .and_then(|addr| sim.get_var(&addr).ok())
+                            .map(|var| var.to_string())
+                    })
+                    .collect::<Vec<Option<String>>>(),
+            ),
+            SimDriver::Remote(client) => (
+                client.server_status()?.current_tick,
+                client.get_vars_as_strings(&addrs)?,
+            ),
+        };
+        for (addr, value) in addrs.iter().zip(values) {
+            let value = match value.and_then(|v| v.parse::<f64>().ok()) {
+                Some(v) => v,
+                None => continue,
+            };
+            let series = self.series.get_mut(addr).unwrap();
+            if let Some((last_clock, _)) = series.back() {
+                if *last_clock == clock {
+                    continue;
+                }
+            }
+            if series.len() >= HISTORY_CAPACITY {
+                series.pop_front();
+            }
+            series.push_back((clock, value));
+        }
+        Ok(())
+    }
+
+    /// Renders the last `window` samples of the var at the given address.
+    pub fn render(&self, addr: &str, window: usize) -> Option<String> {
+        let series = self.series.get(addr)?;
+        if series.is_empty() {
+            return Some(format!("{}: no samples yet", addr));
+        }
+        let samples = series
+            .iter()
+            .skip(series.len().saturating_sub(window))
+            .collect::<Vec<_>>();
+        let min = samples
+            .iter()
+            .map(|(_, v)| *v)
+            .fold(f64::INFINITY, f64::min);
+        let max = samples
+            .iter()
+            .map(|(_, v)| *v)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let sparkline = samples
+            .iter()
+            .map(|(_, v)| {
+                if max > min {
+                    let n = ((v - min) / (max - min) * (BARS.len() - 1) as f64).round();
+                    BARS[n as usize]
+                } else {
+                    BARS[BARS.len() / 2]
+                }
+            })
+            .collect::<String>();
+        Some(format!(
+            "{} (clock {}..{}, {} samples)\n{}\nmin: {}, max: {}, last: {}",
+            addr,
+            samples.first().unwrap().0,
+            samples.last().unwrap().0,
+            samples.len(),
+            sparkline,
+            min,
+            max,
+            samples.last().unwrap().1,
+        ))
+    }
+}
+
+/// Parses `plot` command arguments in the form of `<addr> [--window N]`.
+pub fn parse_args(args: &str) -> Result<(String, usize)> {
+    let mut addr = None;
+    let mut window = DEFAULT_WINDOW;
+    let mut split = args.split_whitespace();
+    while let Some(arg) = split.next() {
+        match arg {
+            "--window" | "-w" => {
+                window = split
+                    .next()
+                    .ok_or(Error::msg("missing value for --window"))?
+                    .parse()?;
+            }
+            _ => addr = Some(arg.to_string()),
+        }
+    }
+    let addr = addr.ok_or(Error::msg("missing address"))?;
+    Address::from_str(&addr)?;
+    Ok((addr, window.max(1)))
+}
+
+#[test]
+fn sparkline_covers_the_window() {
+    let addr = "dot:pos:float:x";
+    let mut history = PlotHistory::default();
+    assert!(history.track(addr));
+    assert!(!history.track(addr));
+    assert_eq!(
+        history.render(addr, 10).unwrap(),
+        format!("{}: no samples yet", addr)
+    );
+
+    history
+        .series
+        .get_mut(addr)
+        .unwrap()
+        .extend(vec![(0, 5.), (1, 1.), (2, 2.)]);
+    let rendered = history.render(addr, 2).unwrap();
+    assert!(rendered.contains("clock 1..2, 2 samples"));
+    assert!(rendered.contains("▁█"));
+    assert!(history.render("dot:pos:float:y", 2).is_none());
+}
+
+#[test]
+fn plot_args_are_parsed() {
+    assert_eq!(
+        parse_args("dot:pos:float:x --window 5").unwrap(),
+        ("dot:pos:float:x".to_string(), 5)
+    );
+    assert_eq!(parse_args("dot:pos:float:x -w 0").unwrap().1, 1);
+    assert!(parse_args("--window 5").is_err());
+    assert!(parse_args("dot:pos:float:x --window").is_err());
+}
@@ -4,40 +4,42 @@ use outcome_net::Client;
 use std::str::FromStr;
 
 pub fn process_step(client: &mut Client, config: &Config) -> Result<(), String> {
+    let clock = client
+        .server_status()
+        .map_err(|e| e.to_string())?
+        .current_tick;
     client
         .server_step_request(config.turn_ticks as u32)
         .unwrap();
 
+    // report errors from all the steps processed, not only the last one
+    let resp = client
+        .get_runtime_errors(Some(clock))
+        .map_err(|e| e.to_string())?;
+    for error in resp.errors {
+        println!(
+            "[{}] error: entity: {}, component: {}: {}",
+            error.clock, error.entity, error.component, error.message
+        );
+    }
+
     Ok(())
 }
 
 /// Create the prompt string. It defaults to current clock tick integer number.
 /// It can display a custom prompt based on the passed configuration.
+///
+/// See [`render_prompt`] for details on the template format.
+///
+/// [`render_prompt`]: super::render_prompt
 pub fn create_prompt(client: &mut Client, cfg: &Config) -> anyhow::Result<String> {
-    if &cfg.prompt_format == "" {
+    if cfg.prompt_format.is_empty() {
         return create_prompt_default(client);
     }
-
-    let mut var_addrs = Vec::new();
-    for v in &cfg.prompt_vars {
-        let addr = match Address::from_str(v) {
-            Ok(a) => a,
-            Err(e) => {
-                return create_prompt_default(client);
-            }
-        };
-        var_addrs.push(addr.to_string());
-    }
-    let vars = client.get_vars_as_strings(&var_addrs).unwrap();
-    let matches: Vec<&str> = cfg.prompt_format.matches("{}").collect();
-    if matches.len() != vars.len() {
-        return create_prompt_default(client);
-    }
-    let mut out_string = format!("[{}] ", cfg.prompt_format.clone());
-    for var_res in vars {
-        out_string = out_string.replacen("{}", &var_res, 1);
-    }
-    Ok(out_string)
+    let clock = client.server_status()?.current_tick;
+    // vars that no longer exist on the server are returned as `None`
+    let vars = client.get_vars_as_strings(&cfg.prompt_vars)?;
+    Ok(super::render_prompt(&cfg.prompt_format, clock, vars))
 }
 
 pub fn create_prompt_default(client: &mut Client) -> anyhow::Result<String> {
@@ -0,0 +1,123 @@
+//! Fast-forwarding the simulation by a number of steps.
+//!
+//! Steps are processed in batches. Batch size is adjusted based on the
+//! measured step duration so that each batch takes roughly
+//! `TARGET_BATCH_TIME`. Between batches progress is reported, the cancel
+//! trigger is checked, and in remote mode the connection is polled so that
+//! it's kept alive during long fast-forwards.
+
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use super::SimDriver;
+
+/// Preferred duration of a single batch of steps.
+const TARGET_BATCH_TIME: Duration = Duration::from_millis(200);
+
+/// Upper limit on the number of steps in a single batch.
+const MAX_BATCH_SIZE: u32 = 10_000;
+
+/// Minimum time between progress reports.
+const REPORT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Weight of the newest batch within the rolling step time average.
+const ROLLING_WEIGHT: f64 = 0.3;
+
+/// Processes the given number of steps, returning the number of steps
+/// actually processed, which is lower than requested if the run was
+/// cancelled or a step failed.
+pub fn run_fast(driver: &mut SimDriver, steps: u32, cancel: Option<&AtomicBool>) -> Result<u32> {
+    let start = Instant::now();
+    let mut last_report = start;
+    let mut done = 0;
+    let mut batch_size = 1;
+    // rolling average of a single step duration, in seconds
+    let mut step_time: Option<f64> = None;
+
+    while done < steps {
+        if let Some(cancel) = cancel {
+            if cancel.load(Ordering::SeqCst) {
+                cancel.store(false, Ordering::SeqCst);
+                println!("\ncancelled after {} of {} steps", done, steps);
+                return Ok(done);
+            }
+        }
+
+        let batch = batch_size.min(steps - done);
+        let batch_start = Instant::now();
+        match driver {
+            SimDriver::Local(sim) => {
+                for n in 0..batch {
+                    if let Err(e) = sim.step() {
+                        println!("\nstep failed after {} of {} steps: {}", done + n, steps, e);
+                        return Ok(done + n);
+                    }
+                }
+            }
+            SimDriver::Remote(client) => {
+                client.server_step_request(batch)?;
+                // let the connection process pending events, keeping it alive
+                client.connection.manual_poll()?;
+            }
+        }
+        done += batch;
+
+        let batch_step_time = batch_start.elapsed().as_secs_f64() / batch as f64;
+        let avg = match step_time {
+            Some(t) => t * (1. - ROLLING_WEIGHT) + batch_step_time * ROLLING_WEIGHT,
+            None => batch_step_time,
+        };
+        step_time = Some(avg);
+        batch_size = if avg > 0. {
+            ((TARGET_BATCH_TIME.as_secs_f64() / avg) as u32)
+                .max(1)
+                .min(MAX_BATCH_SIZE)
+        } else {
+            MAX_BATCH_SIZE
+        };
+
+        if last_report.elapsed() >= REPORT_INTERVAL && done < steps {
+            last_report = Instant::now();
+            let eta = Duration::from_secs_f64(avg * (steps - done) as f64);
+            print!(
+                "\rstep {}/{} ({}%), {:.3}ms/step, eta {:.1}s   ",
+                done,
+                steps,
+                done as u64 * 100 / steps as u64,
+                avg * 1000.,
+                eta.as_secs_f64()
+            );
+            std::io::stdout().flush()?;
+        }
+    }
+
+    if start.elapsed() >= REPORT_INTERVAL {
+        println!(
+            "\rprocessed {} steps in {:.1}s                              ",
+            done,
+            start.elapsed().as_secs_f64()
+        );
+    }
+    Ok(done)
+}
+
+#[test]
+fn runs_requested_steps_until_cancelled() {
+    let sim = outcome::SimModelBuilder::new().build_sim().unwrap();
+    let mut driver = SimDriver::Local(sim);
+    assert_eq!(run_fast(&mut driver, 50, None).unwrap(), 50);
+
+    let cancel = AtomicBool::new(true);
+    assert_eq!(run_fast(&mut driver, 50, Some(&cancel)).unwrap(), 0);
+    // the trigger is reset so that the next run isn't cancelled right away
+    assert!(!cancel.load(Ordering::SeqCst));
+    assert_eq!(run_fast(&mut driver, 5, Some(&cancel)).unwrap(), 5);
+
+    match driver {
+        SimDriver::Local(sim) => assert_eq!(sim.get_clock(), 55),
+        SimDriver::Remote(_) => unreachable!(),
+    }
+}
@@ -14,9 +14,11 @@ extern crate linefeed;
 
 extern crate outcome_core as outcome;
 
+pub mod attach;
 pub mod cli;
 pub mod init;
 pub mod interactive;
+pub mod query;
 pub mod test;
 mod util;
 
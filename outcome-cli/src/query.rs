@@ -0,0 +1,287 @@
+//! One-shot remote query runner.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+use anyhow::{Error, Result};
+use outcome::query::{Description, Filter, Layout, Map, QueryProduct, Trigger};
+use outcome::{EntityId, Query, Var};
+use outcome_net::msg::VarJson;
+
+/// Output format for query results.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Table,
+    Csv,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "table" => Ok(Format::Table),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            _ => Err(Error::msg(format!("unknown output format: {}", s))),
+        }
+    }
+}
+
+/// Builds a query from filter and map arguments provided on the command
+/// line.
+///
+/// Supported filters: `comp=<comp>[,<comp>..]`, `name=<name>[,<name>..]`,
+/// `id=<id>[,<id>..]`, `neighbors=<graph_address>,<id>`,
+/// `degree=<graph_address>,<min>,<max>`,
+/// `geo=<comp>,<var>,<lat>,<lon>,<max_distance_meters>`,
+/// `eq=<comp>,<var_type>:<var>,<value>`, `gt=<comp>,<var>,<number>`,
+/// `lt=<comp>,<var>,<number>`, `range=<comp>,<var>,<min>,<max>`,
+/// `contains=<comp>,<var>,<string>`, `custom=<name>[,<arg>..]`.
+///
+/// Supported maps: `all`, `<var_type>:<var_name>`, `var=<var_name>`,
+/// `comp=<comp>[,<comp>..]`, `custom=<name>[,<arg>..]`. If no maps are
+/// provided all data of the selected entities is returned.
+///
+/// Custom filters and maps need to be registered on the server side.
+pub fn build_query(filters: Vec<&str>, maps: Vec<&str>) -> Result<Query> {
+    let mut query = Query {
+        trigger: Trigger::Immediate,
+        description: Description::NativeDescribed,
+        layout: Layout::Var,
+        filters: vec![],
+        mappings: vec![],
+    };
+
+    for filter in filters {
+        let (key, args) = split_arg(filter)?;
+        query.filters.push(match key {
+            "comp" => Filter::AllComponents(
+                args.iter()
+                    .map(|s| outcome::string::new_truncate(s))
+                    .collect(),
+            ),
+            "name" => Filter::Name(
+                args.iter()
+                    .map(|s| outcome::string::new_truncate(s))
+                    .collect(),
+            ),
+            "id" => Filter::Id(
+                args.iter()
+                    .map(|s| s.parse())
+                    .collect::<std::result::Result<Vec<EntityId>, _>>()?,
+            ),
+            "neighbors" if args.len() == 2 => {
+                Filter::Neighbors(outcome::Address::from_str(args[0])?, args[1].parse()?)
+            }
+            "degree" if args.len() == 3 => Filter::Degree(
+                outcome::Address::from_str(args[0])?,
+                args[1].parse()?,
+                args[2].parse()?,
+            ),
+            "geo" if args.len() == 5 => Filter::GeoDistance(
+                outcome::string::new_truncate(args[0]),
+                outcome::string::new_truncate(args[1]),
+                outcome::geo::GeoPosition::new(args[2].parse()?, args[3].parse()?)?,
+                args[4].parse()?,
+            ),
+            "eq" if args.len() == 3 => {
+                let split = args[1].splitn(2, ':').collect::<Vec<&str>>();
+                if split.len() != 2 {
+                    return Err(Error::msg(format!("invalid filter: {}", filter)));
+                }
+                let var_type = outcome::VarType::from_str(split[0])?;
+                Filter::VarEquals(
+                    outcome::string::new_truncate(args[0]),
+                    outcome::string::new_truncate(split[1]),
+                    Var::from_str(args[2], Some(var_type))?,
+                )
+            }
+            "gt" if args.len() == 3 => Filter::VarGreaterThan(
+                outcome::string::new_truncate(args[0]),
+                outcome::string::new_truncate(args[1]),
+                args[2].parse()?,
+            ),
+            "lt" if args.len() == 3 => Filter::VarLessThan(
+                outcome::string::new_truncate(args[0]),
+                outcome::string::new_truncate(args[1]),
+                args[2].parse()?,
+            ),
+            "range" if args.len() == 4 => Filter::VarInRange(
+                outcome::string::new_truncate(args[0]),
+                outcome::string::new_truncate(args[1]),
+                args[2].parse()?,
+                args[3].parse()?,
+            ),
+            "contains" if args.len() == 3 => Filter::Contains(
+                outcome::string::new_truncate(args[0]),
+                outcome::string::new_truncate(args[1]),
+                args[2].to_string(),
+            ),
+            "custom" => Filter::Custom(
+                args[0].to_string(),
+                args[1..].iter().map(|s| s.to_string()).collect(),
+            ),
+            _ => return Err(Error::msg(format!("unknown filter: {}", filter))),
+        });
+    }
+
+    for map in maps {
+        if map == "all" {
+            query.mappings.push(Map::All);
+            continue;
+        }
+        if !map.contains('=') {
+            let split = map.splitn(2, ':').collect::<Vec<&str>>();
+            if split.len() != 2 {
+                return Err(Error::msg(format!("invalid map: {}", map)));
+            }
+            query.mappings.push(Map::Var(
+                outcome::VarType::from_str(split[0])?,
+                outcome::string::new_truncate(split[1]),
+            ));
+            continue;
+        }
+        let (key, args) = split_arg(map)?;
+        query.mappings.push(match key {
+            "var" => Map::VarName(outcome::string::new_truncate(args[0])),
+            "comp" => Map::Components(
+                args.iter()
+                    .map(|s| outcome::string::new_truncate(s))
+                    .collect(),
+            ),
+            "custom" => Map::Custom(
+                args[0].to_string(),
+                args[1..].iter().map(|s| s.to_string()).collect(),
+            ),
+            _ => return Err(Error::msg(format!("unknown map: {}", map))),
+        });
+    }
+    if query.mappings.is_empty() {
+        query.mappings.push(Map::All);
+    }
+
+    Ok(query)
+}
+
+/// Splits `key=value1,value2` argument into it's key and list of values.
+fn split_arg(arg: &str) -> Result<(&str, Vec<&str>)> {
+    let split = arg.splitn(2, '=').collect::<Vec<&str>>();
+    if split.len() != 2 || split[1].is_empty() {
+        return Err(Error::msg(format!("invalid argument: {}", arg)));
+    }
+    Ok((split[0], split[1].split(',').collect()))
+}
+
+/// Prints the query product using the selected format.
+pub fn print_product(product: QueryProduct, format: Format) -> Result<()> {
+    let data = match product {
+        QueryProduct::NativeAddressedVar(data) => data,
+        QueryProduct::Empty => Default::default(),
+        _ => return Err(Error::msg("unexpected query product")),
+    };
+
+    // pivot the data so that each entity makes up a single row
+    let mut columns = BTreeSet::new();
+    let mut rows: BTreeMap<EntityId, BTreeMap<String, Var>> = BTreeMap::new();
+    for ((entity, comp, var_name), var) in data {
+        let column = format!("{}.{}", comp, var_name);
+        columns.insert(column.clone());
+        rows.entry(entity).or_default().insert(column, var);
+    }
+
+    match format {
+        Format::Table => {
+            let mut header = vec!["entity".to_string()];
+            header.extend(columns.iter().cloned());
+            let mut lines = vec![header];
+            for (entity, vars) in &rows {
+                let mut line = vec![entity.to_string()];
+                for column in &columns {
+                    line.push(vars.get(column).map(|v| v.to_string()).unwrap_or_default());
+                }
+                lines.push(line);
+            }
+            let mut widths = vec![0; columns.len() + 1];
+            for line in &lines {
+                for (n, cell) in line.iter().enumerate() {
+                    widths[n] = widths[n].max(cell.len());
+                }
+            }
+            for line in &lines {
+                let cells = line
+                    .iter()
+                    .enumerate()
+                    .map(|(n, cell)| format!("{:width$}", cell, width = widths[n]))
+                    .collect::<Vec<String>>();
+                println!("{}", cells.join("  ").trim_end());
+            }
+            println!("({} entities)", rows.len());
+        }
+        Format::Csv => {
+            let mut header = vec!["entity".to_string()];
+            header.extend(columns.iter().map(|c| csv_escape(c)));
+            println!("{}", header.join(","));
+            for (entity, vars) in &rows {
+                let mut line = vec![entity.to_string()];
+                for column in &columns {
+                    line.push(
+                        vars.get(column)
+                            .map(|v| csv_escape(&v.to_string()))
+                            .unwrap_or_default(),
+                    );
+                }
+                println!("{}", line.join(","));
+            }
+        }
+        Format::Json => {
+            let json: BTreeMap<String, BTreeMap<String, VarJson>> = rows
+                .into_iter()
+                .map(|(entity, vars)| {
+                    (
+                        entity.to_string(),
+                        vars.into_iter()
+                            .map(|(k, v)| (k, VarJson::from(v)))
+                            .collect(),
+                    )
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&json)?);
+        }
+    }
+    Ok(())
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains(',') || s.contains('"') || s.contains('\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[test]
+fn query_is_built_from_arguments() {
+    let query = build_query(vec!["comp=pos,vel", "gt=pos,x,1.5"], vec!["float:x"]).unwrap();
+    assert!(matches!(&query.filters[0], Filter::AllComponents(comps) if comps.len() == 2));
+    assert!(matches!(&query.filters[1], Filter::VarGreaterThan(_, _, v) if *v == 1.5));
+    assert!(matches!(
+        &query.mappings[0],
+        Map::Var(outcome::VarType::Float, name) if name.as_str() == "x"
+    ));
+
+    // all data is selected if there are no maps
+    let query = build_query(vec![], vec![]).unwrap();
+    assert!(matches!(query.mappings.as_slice(), [Map::All]));
+
+    assert!(build_query(vec!["gt=pos,x"], vec![]).is_err());
+    assert!(build_query(vec!["comp="], vec![]).is_err());
+    assert!(build_query(vec![], vec!["x"]).is_err());
+}
+
+#[test]
+fn csv_values_are_escaped() {
+    assert_eq!(csv_escape("plain"), "plain");
+    assert_eq!(csv_escape("a,b"), "\"a,b\"");
+    assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+}
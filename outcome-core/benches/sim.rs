@@ -17,11 +17,14 @@ fn spawn_entities(c: &mut Criterion) {
         name: string::new_truncate("id"),
         type_: VarType::Int,
         default: Some(Var::Int(42)),
+        min: None,
+        max: None,
     });
     sim.model.components.push(comp_model);
     sim.model.entities.push(EntityPrefab {
         name: string::new_truncate("bench_ent"),
         components: vec![string::new_truncate("bench_comp")],
+        ..EntityPrefab::default()
     });
 
     println!("once");
@@ -25,15 +25,10 @@ fn main() {
         }
     };
 
-    let (ent_uid, ent) = sim.entities.iter().nth(1).unwrap();
-    let comp_uid = ent.components.iter().next().unwrap().clone();
-    let ent_uid = *ent_uid;
-    let ent_index = sim
-        .entity_idx
-        .iter()
-        .find(|(_, euid)| euid == &&ent_uid)
-        .map(|(str_idx, _)| str_idx.clone())
-        .unwrap();
+    let ent = sim.iter_entities().nth(1).unwrap();
+    let comp_uid = ent.components().iter().next().unwrap().clone();
+    let ent_uid = ent.id();
+    let ent_index = ent.name().cloned().unwrap();
 
     let mut input_amalg = String::new();
     'outer: loop {
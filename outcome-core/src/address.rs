@@ -2,13 +2,18 @@
 
 use std::str::FromStr;
 
-use crate::entity::{Storage, StorageIndex};
+use crate::distr::SimNode;
+use crate::entity::{Entity, Storage, StorageIndex};
 use crate::error::{Error, Result};
-use crate::{string, CompName, EntityName, StringId, VarName};
+use fnv::FnvHashMap;
+
+use crate::{string, CompName, EntityId, EntityName, StringId, VarName};
 use crate::{Sim, VarType};
 use std::fmt::{Display, Formatter};
 
 pub const SEPARATOR_SYMBOL: &'static str = ":";
+/// Address segment matching any entity, component or var name.
+pub const WILDCARD_SYMBOL: &'static str = "*";
 
 /// Entity-scope address that can also handle component-scope locality.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -192,6 +197,97 @@ impl Address {
     pub fn storage_index(&self) -> StorageIndex {
         (self.component.clone(), self.var_name.clone())
     }
+
+    /// Checks whether any of the entity, component or var name segments is
+    /// a wildcard.
+    pub fn is_wildcard(&self) -> bool {
+        self.entity.as_str() == WILDCARD_SYMBOL
+            || self.component.as_str() == WILDCARD_SYMBOL
+            || self.var_name.as_str() == WILDCARD_SYMBOL
+    }
+
+    /// Expands wildcard segments into the list of matching addresses of
+    /// vars existing in the sim, e.g. `*:position:float:*` expands into
+    /// addresses of all the float vars of the `position` component across
+    /// all the entities. Var type is never a wildcard, only vars of the
+    /// given type are matched.
+    ///
+    /// Entities are addressed using their names if they have one, using
+    /// their ids otherwise. Entities named with the wildcard symbol are
+    /// always addressed using their ids, so that expanded addresses never
+    /// contain wildcards. Expanded addresses are ordered by entity id,
+    /// component and var name.
+    ///
+    /// Address without wildcards is returned as is, regardless of whether
+    /// it points to an existing var.
+    pub fn expand(&self, sim: &Sim) -> Vec<Address> {
+        self.expand_in(&sim.entities, &sim.entity_idx)
+    }
+
+    /// Expands wildcard segments against the entities stored on the node,
+    /// same as [`Address::expand`] does for the whole sim.
+    pub fn expand_node(&self, node: &SimNode) -> Vec<Address> {
+        self.expand_in(&node.entities, &node.entities_idx)
+    }
+
+    fn expand_in(
+        &self,
+        entities: &FnvHashMap<EntityId, Entity>,
+        entity_idx: &FnvHashMap<EntityName, EntityId>,
+    ) -> Vec<Address> {
+        if !self.is_wildcard() {
+            return vec![self.clone()];
+        }
+        let names = entity_idx
+            .iter()
+            .filter(|(name, _)| name.as_str() != WILDCARD_SYMBOL)
+            .map(|(name, id)| (*id, name))
+            .collect::<FnvHashMap<_, _>>();
+        let entities = if self.entity.as_str() == WILDCARD_SYMBOL {
+            entities.iter().collect::<Vec<_>>()
+        } else {
+            let id = match entity_idx.get(&self.entity) {
+                Some(id) => Some(*id),
+                None => self.entity.parse::<EntityId>().ok(),
+            };
+            id.and_then(|id| entities.get_key_value(&id))
+                .into_iter()
+                .collect()
+        };
+
+        let mut expanded = Vec::new();
+        for (entity_id, entity) in entities {
+            for ((comp, var_name), var) in &entity.storage.map {
+                if (self.component.as_str() != WILDCARD_SYMBOL && comp != &self.component)
+                    || (self.var_name.as_str() != WILDCARD_SYMBOL && var_name != &self.var_name)
+                    || var.get_type() != self.var_type
+                {
+                    continue;
+                }
+                let entity = match names.get(entity_id) {
+                    Some(name) => (*name).clone(),
+                    None => string::new_truncate(&entity_id.to_string()),
+                };
+                expanded.push((
+                    *entity_id,
+                    Address {
+                        entity,
+                        component: comp.clone(),
+                        var_type: self.var_type,
+                        var_name: var_name.clone(),
+                    },
+                ));
+            }
+        }
+        expanded.sort_by(|(a_id, a), (b_id, b)| {
+            (a_id, a.component.as_str(), a.var_name.as_str()).cmp(&(
+                b_id,
+                b.component.as_str(),
+                b.var_name.as_str(),
+            ))
+        });
+        expanded.into_iter().map(|(_, addr)| addr).collect()
+    }
 }
 
 /// Partial reference to simulation data point.
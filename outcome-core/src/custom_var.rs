@@ -0,0 +1,322 @@
+//! Custom var types provided by embedders.
+//!
+//! Domain-specific data that doesn't map well onto any of the built-in var
+//! types can be stored using custom vars. Embedders implement the
+//! [`CustomVar`] trait for their data type and register it under a unique
+//! kind name before loading a simulation:
+//!
+//! ```ignore
+//! #[derive(Debug, Clone, PartialEq)]
+//! struct Polygons(Vec<Vec<(f64, f64)>>);
+//!
+//! impl CustomVar for Polygons {
+//!     fn kind(&self) -> &str { "polygons" }
+//!     fn to_bytes(&self) -> Vec<u8> { bincode::serialize(&self.0).unwrap() }
+//!     ...
+//! }
+//!
+//! impl CustomVarKind for Polygons {
+//!     const KIND: &'static str = "polygons";
+//!     fn from_bytes(bytes: &[u8]) -> Result<Self> { ... }
+//!     fn parse(s: &str) -> Result<Self> { ... }
+//! }
+//!
+//! outcome::custom_var::register::<Polygons>();
+//! ```
+//!
+//! Custom vars are declared with the `custom` type, with values written
+//! as `<kind>:<value>`, e.g. `custom:area = "polygons:0,0 1,0 1,1"`. Within
+//! storage and snapshots they're kept as the kind name and the serialized
+//! bytes, which means all the kinds used by a snapshot need to be
+//! registered before loading it.
+
+use std::any::Any;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::RwLock;
+
+use once_cell::sync::Lazy;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+use crate::error::{Error, Result};
+
+/// Separates the kind name from the value within string representation.
+pub const KIND_SEPARATOR: char = ':';
+
+/// Kind name of the value used as default for custom vars.
+pub const EMPTY_KIND: &str = "empty";
+
+/// Value of a custom var.
+pub trait CustomVar: Any + fmt::Debug + Send + Sync {
+    /// Name under which the kind was registered.
+    fn kind(&self) -> &str;
+    /// Serializes the value, used for storing the value within snapshots
+    /// and sending it over the network.
+    fn to_bytes(&self) -> Vec<u8>;
+    /// Returns readable representation of the value, parseable using
+    /// [`CustomVarKind::parse`].
+    fn to_string(&self) -> String;
+    fn clone_box(&self) -> Box<dyn CustomVar>;
+    /// Describes how the other value differs from this one, returning
+    /// `None` if the values are equal.
+    fn diff(&self, other: &dyn CustomVar) -> Option<String>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/// Custom var kind that can be registered.
+pub trait CustomVarKind: CustomVar + Sized {
+    /// Unique kind name, must not contain the `KIND_SEPARATOR`.
+    const KIND: &'static str;
+    fn from_bytes(bytes: &[u8]) -> Result<Self>;
+    fn parse(s: &str) -> Result<Self>;
+}
+
+struct Constructors {
+    from_bytes: fn(&[u8]) -> Result<Box<dyn CustomVar>>,
+    parse: fn(&str) -> Result<Box<dyn CustomVar>>,
+}
+
+static REGISTRY: Lazy<RwLock<HashMap<String, Constructors>>> = Lazy::new(|| {
+    let mut kinds = HashMap::new();
+    kinds.insert(EMPTY_KIND.to_string(), constructors::<Empty>());
+    RwLock::new(kinds)
+});
+
+fn constructors<T: CustomVarKind>() -> Constructors {
+    Constructors {
+        from_bytes: |bytes| T::from_bytes(bytes).map(|v| Box::new(v) as Box<dyn CustomVar>),
+        parse: |s| T::parse(s).map(|v| Box::new(v) as Box<dyn CustomVar>),
+    }
+}
+
+/// Registers a custom var kind, replacing any kind previously registered
+/// under the same name.
+pub fn register<T: CustomVarKind>() {
+    REGISTRY
+        .write()
+        .unwrap()
+        .insert(T::KIND.to_string(), constructors::<T>());
+}
+
+/// Checks whether a kind with the given name was registered.
+pub fn is_registered(kind: &str) -> bool {
+    REGISTRY.read().unwrap().contains_key(kind)
+}
+
+/// Creates a value of the given kind from serialized bytes.
+pub fn from_bytes(kind: &str, bytes: &[u8]) -> Result<Box<dyn CustomVar>> {
+    match REGISTRY.read().unwrap().get(kind) {
+        Some(c) => (c.from_bytes)(bytes),
+        None => Err(Error::UnknownCustomVarKind(kind.to_string())),
+    }
+}
+
+/// Parses a value written as `<kind>:<value>`.
+pub fn parse(s: &str) -> Result<Box<dyn CustomVar>> {
+    let (kind, value) = match s.find(KIND_SEPARATOR) {
+        Some(n) => (&s[..n], &s[n + 1..]),
+        None => (s, ""),
+    };
+    match REGISTRY.read().unwrap().get(kind) {
+        Some(c) => (c.parse)(value),
+        None => Err(Error::UnknownCustomVarKind(kind.to_string())),
+    }
+}
+
+/// Boxed custom var value, stored within `Var::Custom`.
+pub struct CustomValue(pub Box<dyn CustomVar>);
+
+impl CustomValue {
+    pub fn new<T: CustomVar>(value: T) -> Self {
+        CustomValue(Box::new(value))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.kind() == EMPTY_KIND
+    }
+
+    /// Returns a reference to the value if it's of the given kind.
+    pub fn downcast_ref<T: CustomVar>(&self) -> Option<&T> {
+        self.0.as_any().downcast_ref()
+    }
+
+    /// Returns a mutable reference to the value if it's of the given kind.
+    pub fn downcast_mut<T: CustomVar>(&mut self) -> Option<&mut T> {
+        self.0.as_any_mut().downcast_mut()
+    }
+}
+
+impl Default for CustomValue {
+    fn default() -> Self {
+        CustomValue::new(Empty)
+    }
+}
+
+impl Clone for CustomValue {
+    fn clone(&self) -> Self {
+        CustomValue(self.0.clone_box())
+    }
+}
+
+impl fmt::Debug for CustomValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for CustomValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            self.0.kind(),
+            KIND_SEPARATOR,
+            self.0.to_string()
+        )
+    }
+}
+
+impl PartialEq for CustomValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.kind() == other.0.kind() && self.0.diff(other.0.as_ref()).is_none()
+    }
+}
+
+impl PartialOrd for CustomValue {
+    /// Values are ordered by kind name first, then by serialized bytes.
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self == other {
+            return Some(Ordering::Equal);
+        }
+        match self.0.kind().cmp(other.0.kind()) {
+            Ordering::Equal => Some(self.0.to_bytes().cmp(&other.0.to_bytes())),
+            ord => Some(ord),
+        }
+    }
+}
+
+impl Serialize for CustomValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        (self.0.kind(), self.0.to_bytes()).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CustomValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let (kind, bytes) = <(String, Vec<u8>)>::deserialize(deserializer)?;
+        from_bytes(&kind, &bytes)
+            .map(CustomValue)
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Value of custom vars that weren't given any value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Empty;
+
+impl CustomVar for Empty {
+    fn kind(&self) -> &str {
+        EMPTY_KIND
+    }
+    fn to_bytes(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    fn to_string(&self) -> String {
+        String::new()
+    }
+    fn clone_box(&self) -> Box<dyn CustomVar> {
+        Box::new(*self)
+    }
+    fn diff(&self, other: &dyn CustomVar) -> Option<String> {
+        match other.kind() {
+            EMPTY_KIND => None,
+            kind => Some(format!("expected empty, got {}", kind)),
+        }
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl CustomVarKind for Empty {
+    const KIND: &'static str = EMPTY_KIND;
+    fn from_bytes(_: &[u8]) -> Result<Self> {
+        Ok(Empty)
+    }
+    fn parse(_: &str) -> Result<Self> {
+        Ok(Empty)
+    }
+}
+
+#[test]
+fn registered_kinds_roundtrip_through_serialization() {
+    use crate::Var;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Tally(u32);
+
+    impl CustomVar for Tally {
+        fn kind(&self) -> &str {
+            Self::KIND
+        }
+        fn to_bytes(&self) -> Vec<u8> {
+            self.0.to_le_bytes().to_vec()
+        }
+        fn to_string(&self) -> String {
+            self.0.to_string()
+        }
+        fn clone_box(&self) -> Box<dyn CustomVar> {
+            Box::new(*self)
+        }
+        fn diff(&self, other: &dyn CustomVar) -> Option<String> {
+            match other.as_any().downcast_ref::<Tally>() {
+                Some(other) if other == self => None,
+                _ => Some("tally differs".to_string()),
+            }
+        }
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    impl CustomVarKind for Tally {
+        const KIND: &'static str = "tally";
+        fn from_bytes(bytes: &[u8]) -> Result<Self> {
+            let mut buf = [0; 4];
+            buf.copy_from_slice(bytes);
+            Ok(Tally(u32::from_le_bytes(buf)))
+        }
+        fn parse(s: &str) -> Result<Self> {
+            s.parse()
+                .map(Tally)
+                .map_err(|e| Error::Other(format!("invalid tally: {}", e)))
+        }
+    }
+
+    assert!(parse("tally:3").is_err());
+    register::<Tally>();
+    assert!(is_registered("tally"));
+
+    let value = CustomValue(parse("tally:3").unwrap());
+    assert_eq!(value.to_string(), "tally:3");
+    assert_eq!(value.downcast_ref::<Tally>(), Some(&Tally(3)));
+    assert!(!value.is_empty());
+    assert!(CustomValue::default().is_empty());
+
+    let mut var = Var::Custom(value);
+    var.as_custom_mut::<Tally>().unwrap().0 += 1;
+    let bytes = bincode::serialize(&var).unwrap();
+    let restored: Var = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(restored, var);
+    assert_eq!(restored.as_custom::<Tally>().unwrap(), &Tally(4));
+    assert!(restored.as_custom::<Empty>().is_err());
+}
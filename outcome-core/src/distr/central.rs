@@ -5,26 +5,25 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
-#[cfg(feature = "machine")]
-use rayon::prelude::*;
-
 use fnv::FnvHashMap;
 use id_pool::IdPool;
-use rand::prelude::SliceRandom;
 
 #[cfg(feature = "machine")]
 use crate::machine::{cmd::CentralRemoteCommand, cmd::Command, cmd::ExtCommand, ExecutionContext};
 
+use crate::distr::placement::{self, PlacementContext};
 use crate::distr::{
-    CentralCommunication, DistributionPolicy, NodeCommunication, NodeId, Signal, TaskId,
+    CentralCommunication, DistributionPolicy, EntityPlacement, NodeCommunication, NodeId, Signal,
+    TaskId,
 };
 use crate::entity::Entity;
 use crate::error::{Error, Result};
-use crate::model::Scenario;
+use crate::model::{DistributionHints, PinTarget, Scenario};
+use crate::scheduler::EventScheduler;
 use crate::snapshot::Snapshot;
 use crate::{
-    string, Address, EntityId, EntityName, EventName, PrefabName, ShortString, Sim, SimModel,
-    SimStarter, StringId, Var, SCENARIOS_DIR_NAME, SNAPSHOTS_DIR_NAME,
+    string, Address, CompName, EntityId, EntityName, EventName, PrefabName, ShortString, Sim,
+    SimModel, SimStarter, StringId, Var, SCENARIOS_DIR_NAME, SNAPSHOTS_DIR_NAME,
 };
 
 /// Distributed simulation central authority. Does the necessary coordination
@@ -44,18 +43,37 @@ pub struct SimCentral {
     pub model: SimModel,
     pub clock: usize,
     pub event_queue: Vec<EventName>,
+    /// Events scheduled for future steps
+    #[serde(default)]
+    pub scheduler: EventScheduler,
 
     /// Default distribution policy for entities. Note that entities can be
     /// assigned custom individual policies that override it.
     pub distribution_policy: DistributionPolicy,
+    /// Custom placement consulted when spawning entities, takes precedence
+    /// over all policies other than binding to a specific node
+    #[serde(skip)]
+    pub placement: Option<Box<dyn EntityPlacement>>,
 
     pub node_entities: FnvHashMap<NodeId, Vec<EntityId>>,
+    /// Node running alongside the central authority, targeted by entities
+    /// pinned to the coordinator. Node with the lowest id is used if not set
+    #[serde(default)]
+    pub coordinator_node: Option<NodeId>,
+    /// Prefabs entities were spawned from, used for resolving affinity
+    #[serde(default)]
+    entity_prefabs: FnvHashMap<EntityId, PrefabName>,
     // pub entity_node_routes: FnvHashMap<>
     pub entities_idx: FnvHashMap<EntityName, EntityId>,
     pub entity_idpool: IdPool,
 
     ent_spawn_queue: FnvHashMap<NodeId, Vec<(EntityId, Option<PrefabName>, Option<EntityName>)>>,
     pub model_changes_queue: SimModel,
+
+    /// Logic errors reported by the nodes during the last step
+    #[cfg(feature = "machine")]
+    #[serde(skip)]
+    pub error_journal: Vec<(ExecutionContext, crate::machine::Error)>,
 }
 
 impl SimCentral {
@@ -71,6 +89,10 @@ impl SimCentral {
             for (k, v) in &self.ent_spawn_queue {
                 warn!("node: {:?}, spawn: {:?}", k, v);
                 comms.send_sig_to_node(*k, 0, Signal::SpawnEntities(v.clone()))?;
+                self.node_entities
+                    .entry(*k)
+                    .or_default()
+                    .extend(v.iter().map(|(id, _, _)| *id));
             }
             self.ent_spawn_queue.clear();
         }
@@ -104,12 +126,18 @@ impl SimCentral {
                     model: sim.model,
                     clock: sim.clock,
                     event_queue: sim.event_queue,
+                    scheduler: sim.scheduler,
                     distribution_policy: DistributionPolicy::Random,
+                    placement: None,
                     node_entities: Default::default(),
+                    coordinator_node: None,
+                    entity_prefabs: Default::default(),
                     entities_idx: sim.entity_idx,
                     entity_idpool: sim.entity_pool,
                     ent_spawn_queue: Default::default(),
                     model_changes_queue: Default::default(),
+                    #[cfg(feature = "machine")]
+                    error_journal: Vec::new(),
                 })
             }
             SimStarter::Experiment(_) => unimplemented!(),
@@ -124,12 +152,18 @@ impl SimCentral {
             model: model.clone(),
             clock: 0,
             event_queue,
+            scheduler: Default::default(),
             distribution_policy: DistributionPolicy::Random,
+            placement: None,
             node_entities: Default::default(),
+            coordinator_node: None,
+            entity_prefabs: Default::default(),
             entities_idx: Default::default(),
             entity_idpool: IdPool::new(),
             ent_spawn_queue: Default::default(),
             model_changes_queue: SimModel::default(),
+            #[cfg(feature = "machine")]
+            error_journal: Vec::new(),
         };
         // module script init
         // #[cfg(feature = "machine_script")]
@@ -172,11 +206,9 @@ impl SimCentral {
         prefab: Option<StringId>,
         name: Option<StringId>,
         policy: DistributionPolicy,
-    ) -> Result<()> {
+    ) -> Result<EntityId> {
         trace!("spawning entity from central");
 
-        let new_id = self.entity_idpool.request_id().unwrap();
-
         if let Some(n) = &name {
             if self.entities_idx.contains_key(n) {
                 return Err(Error::Other(format!(
@@ -184,46 +216,28 @@ impl SimCentral {
                     n,
                 )));
             }
-            self.entities_idx.insert(n.clone(), new_id);
         }
 
-        match policy {
-            DistributionPolicy::BindToNode(node_id) => {
-                if !self.ent_spawn_queue.contains_key(&node_id) {
-                    self.ent_spawn_queue.insert(node_id, Vec::new());
-                }
-                self.ent_spawn_queue.get_mut(&node_id).unwrap().push((
-                    new_id,
-                    prefab,
-                    name.clone(),
-                ));
-            }
-            // TODO
-            DistributionPolicy::Random => {
-                if self.node_entities.is_empty() {
-                    return Err(Error::Other("no nodes available".to_string()));
-                }
-
-                // shuffle the existing node ids and draw one
-                let mut nums: Vec<&u32> = self.node_entities.keys().collect::<Vec<&u32>>();
-                warn!("nodes: {:?}", nums);
-                nums.shuffle(&mut rand::thread_rng());
-                let node_id = *nums.first().unwrap();
-
-                // create place in the queue for that node
-                if !self.ent_spawn_queue.contains_key(node_id) {
-                    self.ent_spawn_queue.insert(*node_id, Vec::new());
-                }
-
-                // push to the queue
-                self.ent_spawn_queue.get_mut(&node_id).unwrap().push((
-                    new_id,
-                    prefab,
-                    name.clone(),
-                ));
+        let new_id = self.entity_idpool.request_id().unwrap();
+        let node_id = match self.place_entity(new_id, prefab.as_ref(), policy) {
+            Ok(node_id) => node_id,
+            Err(e) => {
+                let _ = self.entity_idpool.return_id(new_id);
+                return Err(e);
             }
-            _ => unimplemented!(),
+        };
+        if let Some(n) = &name {
+            self.entities_idx.insert(n.clone(), new_id);
         }
+        if let Some(p) = &prefab {
+            self.entity_prefabs.insert(new_id, p.clone());
+        }
+
+        // push to the queue of the selected node
+        self.ent_spawn_queue
+            .entry(node_id)
+            .or_default()
+            .push((new_id, prefab, name.clone()));
 
         // self.ent_spawn_queue.push((new_uid, prefab, name));
         // while self.ent_spawn_queue
@@ -231,6 +245,150 @@ impl SimCentral {
         //     net.send_sig_to_node(*n, Signal::SpawnEntities(v.clone()));
         // }
 
+        Ok(new_id)
+    }
+
+    /// Picks the node for a newly spawned entity.
+    ///
+    /// Binding to a specific node takes precedence over distribution hints
+    /// declared on the prefab's components, which in turn take precedence
+    /// over custom placement and the remaining policies.
+    fn place_entity(
+        &mut self,
+        id: EntityId,
+        prefab: Option<&PrefabName>,
+        policy: DistributionPolicy,
+    ) -> Result<NodeId> {
+        let loads = self.node_loads();
+        let ctx = PlacementContext { loads: &loads };
+        if let DistributionPolicy::BindToNode(node_id) = policy {
+            return Ok(node_id);
+        }
+
+        let hints = self.distribution_hints(prefab);
+        if let Some(pin) = hints.pin_to {
+            return self.pinned_node(pin, &ctx);
+        }
+        if let Some(node) = hints.affinity.and_then(|comp| self.affine_node(&comp)) {
+            return Ok(node);
+        }
+
+        match policy {
+            _ if self.placement.is_some() => {
+                self.placement.as_mut().unwrap().place(id, prefab, &ctx)
+            }
+            DistributionPolicy::Random => placement::Random.place(id, prefab, &ctx),
+            policy => Err(Error::Other(format!(
+                "distribution policy not supported: {:?}",
+                policy
+            ))),
+        }
+    }
+
+    /// Collects distribution hints from all the components of the prefab.
+    fn distribution_hints(&self, prefab: Option<&PrefabName>) -> DistributionHints {
+        let mut hints = DistributionHints::default();
+        if let Some(prefab) = prefab.and_then(|p| self.model.get_entity(p)) {
+            for comp_name in &prefab.components {
+                if let Ok(component) = self.model.get_component(comp_name) {
+                    hints.merge(&component.distribution);
+                }
+            }
+        }
+        hints
+    }
+
+    /// Resolves the pin target to a node id.
+    fn pinned_node(&self, pin: PinTarget, ctx: &PlacementContext) -> Result<NodeId> {
+        match pin {
+            PinTarget::Coordinator => self
+                .coordinator_node
+                .or_else(|| ctx.nodes().first().cloned())
+                .ok_or_else(|| Error::Other("no nodes available".to_string())),
+            PinTarget::Node(node) if ctx.loads.contains_key(&node) => Ok(node),
+            PinTarget::Node(node) => Err(Error::Other(format!(
+                "entity pinned to unavailable node: {}",
+                node
+            ))),
+        }
+    }
+
+    /// Finds the node holding the most entities with the given component,
+    /// including the ones queued for spawning. Ties are broken by the lowest
+    /// node id.
+    fn affine_node(&self, comp_name: &CompName) -> Option<NodeId> {
+        let has_comp = |prefab: Option<&PrefabName>| {
+            prefab
+                .and_then(|p| self.model.get_entity(p))
+                .map(|p| p.components.contains(comp_name))
+                .unwrap_or(false)
+        };
+        let mut counts: FnvHashMap<NodeId, usize> = FnvHashMap::default();
+        for (node, entities) in &self.node_entities {
+            let count = entities
+                .iter()
+                .filter(|id| has_comp(self.entity_prefabs.get(id)))
+                .count();
+            *counts.entry(*node).or_default() += count;
+        }
+        for (node, queued) in &self.ent_spawn_queue {
+            let count = queued
+                .iter()
+                .filter(|(_, prefab, _)| has_comp(prefab.as_ref()))
+                .count();
+            *counts.entry(*node).or_default() += count;
+        }
+        counts
+            .into_iter()
+            .filter(|(_, count)| *count > 0)
+            .max_by_key(|(node, count)| (*count, std::cmp::Reverse(*node)))
+            .map(|(node, _)| node)
+    }
+
+    /// Sets the custom placement consulted when spawning entities.
+    pub fn set_placement<P: EntityPlacement + 'static>(&mut self, placement: P) {
+        self.placement = Some(Box::new(placement));
+    }
+
+    /// Sets one of the built-in placements by name, see
+    /// [`placement::from_name`].
+    pub fn set_placement_by_name(&mut self, name: &str) -> Result<()> {
+        self.placement = Some(placement::from_name(name)?);
+        Ok(())
+    }
+
+    /// Counts entities on each node, including the ones queued for
+    /// spawning.
+    pub fn node_loads(&self) -> FnvHashMap<NodeId, usize> {
+        let mut loads = self
+            .node_entities
+            .iter()
+            .map(|(node, entities)| (*node, entities.len()))
+            .collect::<FnvHashMap<_, _>>();
+        for (node, queued) in &self.ent_spawn_queue {
+            *loads.entry(*node).or_default() += queued.len();
+        }
+        loads
+    }
+
+    /// Records the entity as moved from one node to another.
+    pub fn move_entity(&mut self, id: EntityId, from: NodeId, to: NodeId) -> Result<()> {
+        let entities = self
+            .node_entities
+            .get_mut(&from)
+            .ok_or_else(|| Error::Other(format!("no node with id: {}", from)))?;
+        match entities.iter().position(|e| *e == id) {
+            Some(idx) => {
+                entities.swap_remove(idx);
+            }
+            None => {
+                return Err(Error::Other(format!(
+                    "entity {} is not assigned to node {}",
+                    id, from
+                )))
+            }
+        }
+        self.node_entities.entry(to).or_default().push(id);
         Ok(())
     }
 
@@ -287,16 +445,34 @@ impl SimCentral {
     /// # Protocol overview
     ///
     /// 1. All nodes are signalled to start processing next step.
-    /// 2. Nodes send back central remote commands that came up during their
-    /// local processing, if any.
+    /// 2. Nodes send back logic errors and central remote commands that came
+    /// up during their local processing, if any. Errors are collected in
+    /// `error_journal`.
     /// 3. Incoming central remote commands are executed and results are sent
     /// back. Any model changes are also sent to the nodes.
     /// 4. Nodes signal their readiness to move on to the next step.
+    ///
+    /// While waiting for the nodes, any node reported dead by the network
+    /// causes the step to be aborted with [`Error::NodeUnresponsive`].
     pub fn step_network<N: CentralCommunication>(
         &mut self,
         network: &mut N,
-        event_queue: Vec<StringId>,
+        mut event_queue: Vec<StringId>,
     ) -> Result<()> {
+        // modulated hazards are skipped as central doesn't hold var values
+        let manifest = &self.model.scenario.manifest;
+        for event in
+            crate::hazard::sample_hazards(&manifest.hazards, manifest.seed, self.clock, |_| None)
+        {
+            if !event_queue.contains(&event) {
+                event_queue.push(event);
+            }
+        }
+        for event in self.scheduler.take_due(self.clock) {
+            if !event_queue.contains(&event) {
+                event_queue.push(event);
+            }
+        }
         debug!("starting processing step, event queue: {:?}", event_queue);
 
         // tell nodes to start processing next step
@@ -308,6 +484,9 @@ impl SimCentral {
         let mut cext_cmds: Arc<Mutex<Vec<(ExecutionContext, CentralRemoteCommand)>>> =
             Arc::new(Mutex::new(Vec::new()));
 
+        #[cfg(feature = "machine")]
+        self.error_journal.clear();
+
         let mut do_nodes = network.get_node_ids()?;
         let mut node_counter = 0;
         while !do_nodes.is_empty() {
@@ -318,14 +497,25 @@ impl SimCentral {
                     Signal::ExecuteCentralExtCmd(cmd) => cext_cmds.lock().unwrap().push(cmd),
                     #[cfg(feature = "machine")]
                     Signal::ExecuteCentralExtCmds(cmds) => cext_cmds.lock().unwrap().extend(cmds),
+                    #[cfg(feature = "machine")]
+                    Signal::StepErrors(errors) => self.error_journal.extend(errors),
                     Signal::EndOfMessages | Signal::ProcessStepFinished => {
                         do_nodes.remove(node_counter);
                     }
                     _ => debug!("unimplemented: received signal: {:?}", signal),
                 },
                 Err(e) => match e {
-                    Error::WouldBlock => continue,
-                    _ => error!("{:?}", e),
+                    Error::WouldBlock => {
+                        if let Some(dead) = network
+                            .dead_nodes()
+                            .into_iter()
+                            .find(|n| do_nodes.contains(n))
+                        {
+                            return Err(self.abort_step(network, Error::NodeUnresponsive(dead)));
+                        }
+                        continue;
+                    }
+                    _ => return Err(self.abort_step(network, e)),
                 },
             };
             node_counter += 1;
@@ -349,11 +539,15 @@ impl SimCentral {
         // network.sig_broadcast(Signal::EndOfMessages)?;
         loop {
             std::thread::sleep(std::time::Duration::from_millis(8));
-            if let Ok((_, _, s)) = network.try_recv_sig() {
-                match s {
-                    Signal::ProcessStepFinished => break,
-                    _ => (),
+            match network.try_recv_sig() {
+                Ok((_, _, Signal::ProcessStepFinished)) => break,
+                Ok(_) => (),
+                Err(Error::WouldBlock) => {
+                    if let Some(dead) = network.dead_nodes().first() {
+                        return Err(self.abort_step(network, Error::NodeUnresponsive(*dead)));
+                    }
                 }
+                Err(e) => return Err(self.abort_step(network, e)),
             }
         }
         debug!("finished executing cext commands");
@@ -362,6 +556,19 @@ impl SimCentral {
         Ok(())
     }
 
+    /// Tells nodes to abandon the current step after an unrecoverable
+    /// error, returning the error to be passed on to the caller.
+    ///
+    /// Notifying the nodes is best-effort, as the error itself is likely
+    /// to be caused by the network.
+    fn abort_step<N: CentralCommunication>(&mut self, network: &mut N, e: Error) -> Error {
+        error!("aborting step {}: {}", self.clock, e);
+        if let Err(be) = network.broadcast_sig(0, Signal::StepAborted) {
+            warn!("failed notifying nodes about aborted step: {}", be);
+        }
+        e
+    }
+
     pub fn init_snapshot_download<N: CentralCommunication>(
         &mut self,
         network: &mut N,
@@ -376,3 +583,177 @@ impl SimCentral {
         Ok(task_id)
     }
 }
+
+#[test]
+fn pinned_entities_are_placed_on_a_single_node() {
+    let mut model = crate::SimModelBuilder::new()
+        .component("heavy", |c| c.var("int:x", Var::Int(0)))
+        .prefab("thing", &["heavy"])
+        .build()
+        .unwrap();
+    model
+        .get_component_mut(&string::new_truncate("heavy"))
+        .unwrap()
+        .distribution
+        .pin_to = Some(PinTarget::Coordinator);
+    let mut central = SimCentral::from_model(model, None).unwrap();
+    central.node_entities.insert(2, Vec::new());
+    central.node_entities.insert(1, Vec::new());
+
+    let id = central
+        .spawn_entity(
+            Some(string::new_truncate("thing")),
+            None,
+            DistributionPolicy::Random,
+        )
+        .unwrap();
+    assert_eq!(central.ent_spawn_queue.len(), 1);
+    assert_eq!(central.ent_spawn_queue[&1][0].0, id);
+}
+
+#[test]
+fn unsupported_policy_is_error() {
+    let model = crate::SimModelBuilder::new()
+        .component("light", |c| c.var("int:x", Var::Int(0)))
+        .prefab("thing", &["light"])
+        .build()
+        .unwrap();
+    let mut central = SimCentral::from_model(model, None).unwrap();
+    central.node_entities.insert(1, Vec::new());
+    assert!(central
+        .spawn_entity(
+            Some(string::new_truncate("thing")),
+            None,
+            DistributionPolicy::Spatial,
+        )
+        .is_err());
+}
+
+/// Network stand-in feeding central with signals queued up for each node.
+#[cfg(test)]
+#[derive(Default)]
+struct QueuedNetwork {
+    incoming: std::collections::BTreeMap<NodeId, std::collections::VecDeque<Signal>>,
+    /// Nodes reported as dead
+    dead: Vec<NodeId>,
+    /// Signals broadcast to all the nodes
+    broadcast: Vec<Signal>,
+}
+
+#[cfg(test)]
+impl CentralCommunication for QueuedNetwork {
+    fn request_task_id(&mut self) -> Result<TaskId> {
+        Ok(0)
+    }
+    fn return_task_id(&mut self, _task_id: TaskId) -> Result<()> {
+        Ok(())
+    }
+    fn get_node_ids(&self) -> Result<Vec<NodeId>> {
+        Ok(self.incoming.keys().copied().collect())
+    }
+    fn try_recv_sig(&mut self) -> Result<(NodeId, TaskId, Signal)> {
+        for (node, queue) in &mut self.incoming {
+            if let Some(sig) = queue.pop_front() {
+                return Ok((*node, 0, sig));
+            }
+        }
+        Err(Error::WouldBlock)
+    }
+    fn try_recv_sig_from(&mut self, node_id: NodeId) -> Result<(TaskId, Signal)> {
+        self.incoming
+            .get_mut(&node_id)
+            .and_then(|queue| queue.pop_front())
+            .map(|sig| (0, sig))
+            .ok_or(Error::WouldBlock)
+    }
+    fn send_sig_to_node(&mut self, _node_id: NodeId, _task_id: TaskId, _sig: Signal) -> Result<()> {
+        Ok(())
+    }
+    fn send_sig_to_entity(&mut self, _uid: EntityId, _task_id: TaskId, _sig: Signal) -> Result<()> {
+        Ok(())
+    }
+    fn broadcast_sig(&mut self, _task_id: TaskId, sig: Signal) -> Result<()> {
+        self.broadcast.push(sig);
+        Ok(())
+    }
+    fn dead_nodes(&self) -> Vec<NodeId> {
+        self.dead.clone()
+    }
+}
+
+#[cfg(feature = "machine")]
+#[test]
+fn errors_reported_by_nodes_are_collected() {
+    use crate::machine::{ErrorKind, LocationInfo};
+
+    let error = |ent| {
+        (
+            ExecutionContext {
+                ent,
+                comp: string::new_truncate("comp"),
+                location: LocationInfo::default(),
+            },
+            crate::machine::Error::new(
+                LocationInfo::default(),
+                ErrorKind::CoreError(format!("failed on {}", ent)),
+            ),
+        )
+    };
+    let model = crate::SimModelBuilder::new().build().unwrap();
+    let mut central = SimCentral::from_model(model, None).unwrap();
+    let mut network = QueuedNetwork::default();
+    for node in 1..=2 {
+        network.incoming.insert(
+            node,
+            vec![
+                Signal::StepErrors(vec![error(node)]),
+                Signal::EndOfMessages,
+                Signal::ProcessStepFinished,
+            ]
+            .into(),
+        );
+    }
+
+    central.step_network(&mut network, Vec::new()).unwrap();
+    let mut entities = central
+        .error_journal
+        .iter()
+        .map(|(ctx, _)| ctx.ent)
+        .collect::<Vec<_>>();
+    entities.sort_unstable();
+    assert_eq!(entities, vec![1, 2]);
+
+    // journal only covers the last step
+    for node in 1..=2 {
+        network.incoming.insert(
+            node,
+            vec![Signal::EndOfMessages, Signal::ProcessStepFinished].into(),
+        );
+    }
+    central.step_network(&mut network, Vec::new()).unwrap();
+    assert!(central.error_journal.is_empty());
+}
+
+#[test]
+fn step_waiting_on_dead_node_is_aborted() {
+    let model = crate::SimModelBuilder::new().build().unwrap();
+    let mut central = SimCentral::from_model(model, None).unwrap();
+    let mut network = QueuedNetwork::default();
+    network
+        .incoming
+        .insert(1, vec![Signal::EndOfMessages].into());
+    // second node never responds
+    network.incoming.insert(2, Default::default());
+    network.dead.push(2);
+
+    match central.step_network(&mut network, Vec::new()) {
+        Err(Error::NodeUnresponsive(node)) => assert_eq!(node, 2),
+        _ => panic!("expected step to be aborted"),
+    }
+    // surviving nodes are told to abandon the step
+    assert!(matches!(
+        network.broadcast.last(),
+        Some(Signal::StepAborted)
+    ));
+    assert_eq!(central.clock, 0);
+}
@@ -5,9 +5,11 @@
 
 pub mod central;
 pub mod node;
+pub mod placement;
 
 pub use central::SimCentral;
 pub use node::SimNode;
+pub use placement::EntityPlacement;
 
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
@@ -16,8 +18,6 @@ use fnv::FnvHashMap;
 
 #[cfg(feature = "machine_dynlib")]
 use libloading::Library;
-#[cfg(feature = "machine")]
-use rayon::prelude::*;
 #[cfg(feature = "machine_lua")]
 use rlua::Lua;
 
@@ -26,9 +26,10 @@ use crate::entity::{Entity, Storage};
 use crate::error::{Error, Result};
 use crate::model::{DataEntry, DataImageEntry, Scenario};
 use crate::sim::step;
+use crate::snapshot::SnapshotPart;
 use crate::{
-    model, CompName, EntityId, EntityName, PrefabName, Query, QueryProduct, SimModel, StringId,
-    Var, VarType,
+    model, CompName, EntityId, EntityName, EventName, PrefabName, Query, QueryProduct, SimModel,
+    StringId, Var, VarType,
 };
 
 #[cfg(feature = "machine")]
@@ -50,11 +51,17 @@ pub enum Signal {
     /// Request node to start processing step, includes event_queue vec
     StartProcessStep(Vec<StringId>),
 
+    /// Request node to send back its part of the simulation state
     SnapshotRequest,
+    /// Node's part of the simulation state, sent in response to a snapshot
+    /// request
+    SnapshotResponse(SnapshotPart),
 
     WorkerConnected,
 
     WorkerStepAdvanceRequest(u32),
+    /// Request central to add the event to the global event queue
+    AddEvent(EventName),
     WorkerReady,
     WorkerNotReady,
 
@@ -86,6 +93,18 @@ pub enum Signal {
     /// Request pulling the provided data
     DataPullRequest(Vec<(Address, Var)>),
 
+    /// Request node to hand over the entity so that it can be moved to the
+    /// given node
+    MigrateEntity(EntityId, NodeId),
+    /// Entity handed over by the node, on it's way to the target node
+    MigratingEntity(EntityId, Option<EntityName>, Entity),
+    /// Request node to take in an entity moved from another node
+    IngestEntity(EntityId, Option<EntityName>, Entity),
+    /// Node took in the moved entity
+    EntityIngested(EntityId),
+    /// Node failed to either hand over or take in the entity
+    MigrationFailed(EntityId, String),
+
     /// External command to be executed on a node
     #[cfg(feature = "machine")]
     ExecuteExtCmd((ExecutionContext, ExtCommand)),
@@ -94,6 +113,90 @@ pub enum Signal {
     ExecuteCentralExtCmd((ExecutionContext, CentralRemoteCommand)),
     #[cfg(feature = "machine")]
     ExecuteCentralExtCmds(Vec<(ExecutionContext, CentralRemoteCommand)>),
+    /// Logic errors recorded by the node while processing the current step
+    #[cfg(feature = "machine")]
+    StepErrors(Vec<(ExecutionContext, crate::machine::Error)>),
+
+    /// Acknowledges receiving all signals up to and including the one with
+    /// the given sequence number
+    Ack(u32),
+    /// Reports a missing signal with the given sequence number
+    Nack(u32),
+    /// Step was aborted because of an unrecoverable error, nodes should
+    /// abandon the current step without advancing the clock
+    StepAborted,
+    /// Signal with the same task id was rejected as it's deadline passed,
+    /// includes the clock of the rejecting node
+    DeadlineExceeded(usize),
+    /// Node is still alive, sent periodically to central
+    Heartbeat,
+
+    /// Request node to write it's state to the checkpoint store at the
+    /// given location, under the given key. Includes the clock the
+    /// checkpoint is taken at, node at a different clock must refuse
+    Checkpoint(String, String, usize),
+    /// Node's state was written under the given key
+    CheckpointSaved(String),
+    /// Request node to restore state from the checkpoint store at the given
+    /// location, stored under the given key
+    RestoreCheckpoint(String, String),
+    /// Node's state was restored from the given key
+    CheckpointRestored(String),
+    /// Node failed to either write or restore it's state
+    CheckpointFailed(String),
+}
+
+impl Signal {
+    /// Returns the name of the signal variant, without any of the data.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Signal::InitializeNode(_) => "InitializeNode",
+            Signal::SpawnEntities(_) => "SpawnEntities",
+            Signal::StartProcessStep(_) => "StartProcessStep",
+            Signal::SnapshotRequest => "SnapshotRequest",
+            Signal::SnapshotResponse(_) => "SnapshotResponse",
+            Signal::WorkerConnected => "WorkerConnected",
+            Signal::WorkerStepAdvanceRequest(_) => "WorkerStepAdvanceRequest",
+            Signal::AddEvent(_) => "AddEvent",
+            Signal::WorkerReady => "WorkerReady",
+            Signal::WorkerNotReady => "WorkerNotReady",
+            Signal::ShuttingDown => "ShuttingDown",
+            Signal::ProcessStepFinished => "ProcessStepFinished",
+            Signal::EndOfRequests => "EndOfRequests",
+            Signal::EndOfResponses => "EndOfResponses",
+            Signal::EndOfMessages => "EndOfMessages",
+            Signal::UpdateModel(_) => "UpdateModel",
+            Signal::QueryRequest(_) => "QueryRequest",
+            Signal::QueryResponse(_) => "QueryResponse",
+            Signal::DataRequestAll => "DataRequestAll",
+            Signal::DataRequestSelect(_) => "DataRequestSelect",
+            Signal::DataResponse(_) => "DataResponse",
+            Signal::DataPullRequest(_) => "DataPullRequest",
+            Signal::MigrateEntity(..) => "MigrateEntity",
+            Signal::MigratingEntity(..) => "MigratingEntity",
+            Signal::IngestEntity(..) => "IngestEntity",
+            Signal::EntityIngested(_) => "EntityIngested",
+            Signal::MigrationFailed(..) => "MigrationFailed",
+            #[cfg(feature = "machine")]
+            Signal::ExecuteExtCmd(_) => "ExecuteExtCmd",
+            #[cfg(feature = "machine")]
+            Signal::ExecuteCentralExtCmd(_) => "ExecuteCentralExtCmd",
+            #[cfg(feature = "machine")]
+            Signal::ExecuteCentralExtCmds(_) => "ExecuteCentralExtCmds",
+            #[cfg(feature = "machine")]
+            Signal::StepErrors(_) => "StepErrors",
+            Signal::Ack(_) => "Ack",
+            Signal::Nack(_) => "Nack",
+            Signal::StepAborted => "StepAborted",
+            Signal::DeadlineExceeded(_) => "DeadlineExceeded",
+            Signal::Heartbeat => "Heartbeat",
+            Signal::Checkpoint(..) => "Checkpoint",
+            Signal::CheckpointSaved(_) => "CheckpointSaved",
+            Signal::RestoreCheckpoint(..) => "RestoreCheckpoint",
+            Signal::CheckpointRestored(_) => "CheckpointRestored",
+            Signal::CheckpointFailed(_) => "CheckpointFailed",
+        }
+    }
 }
 
 /// Trait representing central coordinator's ability to send and receive
@@ -124,6 +227,13 @@ pub trait CentralCommunication {
 
     /// Sends a signal to all nodes.
     fn broadcast_sig(&mut self, task_id: TaskId, signal: Signal) -> Result<()>;
+
+    /// Gets ids of the nodes that haven't been heard from for too long and
+    /// are considered dead. Implementations that don't track node liveness
+    /// never report any.
+    fn dead_nodes(&self) -> Vec<NodeId> {
+        Vec::new()
+    }
 }
 
 /// Trait representing node's ability to send and receive data over the
@@ -167,7 +277,7 @@ pub trait NodeCommunication {
 /// Some policies define a more rigid distribution, while others work by
 /// actively monitoring the situation across different nodes and transferring
 /// entities around as needed.
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum DistributionPolicy {
     /// Set binding to a specific node
     BindToNode(u32),
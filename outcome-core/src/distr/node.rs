@@ -6,15 +6,19 @@ use std::sync::{Arc, Mutex};
 use fnv::FnvHashMap;
 
 use crate::distr::{NodeCommunication, Signal};
-use crate::entity::Entity;
+use crate::entity::{Entity, EntityRef};
 use crate::sim::step;
 use crate::{Address, CompName, Result, Var};
 use crate::{EntityId, EntityName, SimModel, StringId};
 
 use crate::error::Error;
-#[cfg(feature = "machine")]
+#[cfg(all(feature = "machine", feature = "parallel"))]
 use rayon::prelude::*;
 
+#[cfg(feature = "machine")]
+use crate::machine::budget::StepBudget;
+#[cfg(feature = "machine")]
+use crate::machine::ExecutionContext;
 #[cfg(feature = "machine_dynlib")]
 use crate::machine::Libraries;
 
@@ -34,9 +38,33 @@ pub struct SimNode {
     pub event_queue: Vec<StringId>,
     pub entities: FnvHashMap<EntityId, Entity>,
     pub entities_idx: FnvHashMap<EntityName, EntityId>,
+    /// Custom query filters and maps registered by the embedder
+    #[serde(skip)]
+    pub query_plugins: crate::query::QueryPlugins,
+    /// Logic errors that occurred during the last step, also sent to
+    /// central with [`Signal::StepErrors`]
+    #[cfg(feature = "machine")]
+    #[serde(skip)]
+    pub error_journal: Vec<(ExecutionContext, crate::machine::Error)>,
+    /// Execution order of the model's systems, built on the first step
+    /// after the model is replaced
+    #[cfg(feature = "machine")]
+    #[serde(skip)]
+    pub(crate) system_schedule: Option<crate::machine::system::SystemSchedule>,
 }
 
 impl SimNode {
+    /// Iterates over all locally stored entities, in no particular order.
+    pub fn iter_entities(&self) -> impl Iterator<Item = EntityRef> {
+        crate::entity::handle::iter(&self.entities, &self.entities_idx)
+    }
+
+    /// Iterates over all locally stored entities in parallel.
+    #[cfg(feature = "rayon")]
+    pub fn par_iter_entities(&self) -> impl rayon::iter::ParallelIterator<Item = EntityRef> {
+        crate::entity::handle::par_iter(&self.entities, &self.entities_idx)
+    }
+
     /// Creates a new node using the sim model and a list of entities.
     pub fn from_model(model: &SimModel) -> Result<SimNode> {
         let mut sim_node = SimNode {
@@ -45,6 +73,11 @@ impl SimNode {
             entities: FnvHashMap::default(),
             entities_idx: FnvHashMap::default(),
             event_queue: vec![crate::string::new_truncate("_scr_init")],
+            query_plugins: Default::default(),
+            #[cfg(feature = "machine")]
+            error_journal: Vec::new(),
+            #[cfg(feature = "machine")]
+            system_schedule: None,
         };
 
         // sim_node.apply_model_entities(entities);
@@ -91,6 +124,12 @@ impl SimNode {
         Err(Error::FailedGettingVarFromSim(addr.clone()))
     }
 
+    /// Checks whether the provided var can be written at the given address,
+    /// see [`SimModel::validate_var`].
+    pub fn validate_var(&self, addr: &Address, var: &Var) -> Result<()> {
+        self.model.validate_var(addr, self.get_var(addr)?, var)
+    }
+
     /// Get a variable from the sim using an absolute address.
     pub fn get_var_mut(&mut self, addr: &Address) -> Result<&mut Var> {
         if let Some(ent_uid) = self.entities_idx.get(&addr.entity) {
@@ -132,6 +171,44 @@ impl SimNode {
         Ok(())
     }
 
+    /// Removes the entity so that it can be moved to another node. Returns
+    /// the entity along with it's name, if it has one.
+    pub fn take_entity(&mut self, uid: &EntityId) -> Result<(Option<EntityName>, Entity)> {
+        let entity = self
+            .entities
+            .remove(uid)
+            .ok_or(Error::FailedGettingEntityById(*uid))?;
+        let name = self
+            .entities_idx
+            .iter()
+            .find(|(_, id)| *id == uid)
+            .map(|(name, _)| name.clone());
+        if let Some(name) = &name {
+            self.entities_idx.remove(name);
+        }
+        Ok((name, entity))
+    }
+
+    /// Inserts an entity moved from another node.
+    pub fn insert_entity(
+        &mut self,
+        uid: EntityId,
+        name: Option<EntityName>,
+        entity: Entity,
+    ) -> Result<()> {
+        if self.entities.contains_key(&uid) {
+            return Err(Error::Other(format!(
+                "failed inserting entity: entity with id {} already exists",
+                uid
+            )));
+        }
+        self.entities.insert(uid, entity);
+        if let Some(name) = name {
+            self.entities_idx.insert(name, uid);
+        }
+        Ok(())
+    }
+
     /// Apply registered model entities by instantiating them.
     /// None of the existing entities are removed. Only entities
     /// registered with the `spawn` flag are instantiated.
@@ -175,14 +252,17 @@ impl SimNode {
     /// value containing the id of the target node.
     ///
     /// `addr_book` is a map of nodes and their connections
+    ///
+    /// Logic errors are recorded into the error journal and never abort the
+    /// step, strict mode is not supported on nodes.
     #[cfg(feature = "machine")]
     pub fn step<N: NodeCommunication>(
         &mut self,
         mut network: &mut N,
         event_queue: &Vec<StringId>,
     ) -> Result<()> {
+        use crate::machine::cmd;
         use crate::machine::cmd::{CentralRemoteCommand, ExtCommand};
-        use crate::machine::{cmd, ExecutionContext};
         trace!(
             "sim_node start processing step, event queue: {:?}",
             event_queue
@@ -201,29 +281,77 @@ impl SimNode {
         // let event_queue = &self.event_queue;
 
         // declare sync vecs for external and central-external
-        let ext_cmds: Arc<Mutex<Vec<(ExecutionContext, ExtCommand)>>> =
-            Arc::new(Mutex::new(Vec::new()));
-        let central_ext_cmds: Arc<Mutex<Vec<(ExecutionContext, CentralRemoteCommand)>>> =
+        let ext_cmds: step::EventCommands<ExtCommand> = Arc::new(Mutex::new(Vec::new()));
+        let central_ext_cmds: step::EventCommands<CentralRemoteCommand> =
             Arc::new(Mutex::new(Vec::new()));
 
-        // loc phase
+        // errors are journaled and sent to central at the end of the step
+        let errors: Arc<Mutex<Vec<(ExecutionContext, crate::machine::Error)>>> =
+            Arc::new(Mutex::new(Vec::new()));
+        self.error_journal.clear();
+        let budget = StepBudget::new(&model.scenario.manifest.budget);
+
+        // loc phase, order-sensitive components are left for the serial
+        // pass
+        let has_serial = model.components.iter().any(|c| c.logic.serial);
+        let step_entity = |(ent_uid, entity): (&EntityId, &mut Entity), serial: Option<bool>| {
+            trace!("processing entity: {:?}", entity);
+            step::step_entity_local(
+                model,
+                &event_queue,
+                ent_uid,
+                entity,
+                &ext_cmds,
+                &central_ext_cmds,
+                &errors,
+                // TODO report execution times to central
+                &mut Default::default(),
+                &budget,
+                // strict mode is not supported on nodes, see `Sim::strict`
+                false,
+                serial,
+                // TODO make nodes store their libraries
+                #[cfg(feature = "machine_dynlib")]
+                &Libraries::default(),
+            );
+        };
+        let parallel_pass = match has_serial {
+            true => Some(false),
+            false => None,
+        };
+        #[cfg(feature = "parallel")]
         self.entities
             .par_iter_mut()
-            .for_each(|(ent_uid, mut entity): (&EntityId, &mut Entity)| {
-                trace!("processing entity: {:?}", entity);
-                step::step_entity_local(
-                    model,
-                    &event_queue,
-                    ent_uid,
-                    entity,
-                    &ext_cmds,
-                    &central_ext_cmds,
-                    // TODO make nodes store their libraries
-                    #[cfg(feature = "machine_dynlib")]
-                    &Libraries::default(),
-                );
-            });
+            .for_each(|entry| step_entity(entry, parallel_pass));
+        #[cfg(not(feature = "parallel"))]
+        {
+            let mut entities = self.entities.iter_mut().collect::<Vec<_>>();
+            entities.sort_unstable_by_key(|(ent_uid, _)| **ent_uid);
+            for entry in entities {
+                step_entity(entry, parallel_pass);
+            }
+        }
+        if has_serial {
+            let mut entities = self.entities.iter_mut().collect::<Vec<_>>();
+            entities.sort_unstable_by_key(|(ent_uid, _)| **ent_uid);
+            for entry in entities {
+                step_entity(entry, Some(true));
+            }
+        }
         trace!("sim_node finished local phase");
+        self.error_journal.extend(errors.lock().unwrap().drain(..));
+
+        // systems phase, reductions only cover locally stored entities
+        if self.system_schedule.is_none() {
+            self.system_schedule = Some(crate::machine::system::SystemSchedule::new(
+                &model.systems,
+            )?);
+        }
+        let schedule = self.system_schedule.as_ref().unwrap();
+        for (context, error) in schedule.run(&model.systems, event_queue, &mut self.entities)? {
+            warn!("system error: {:?}: {}", context, error);
+            self.error_journal.push((context, error));
+        }
 
         // // send ext cmd requests
         // for (exec_context, ext_cmd) in ext_cmds.lock().unwrap().iter() {
@@ -268,7 +396,23 @@ impl SimNode {
         //     });
         // println!("sim_node finished read ext cmd responses");
 
-        let mut cexts = central_ext_cmds.lock().unwrap().clone();
+        // central collects errors from all the nodes so that they're
+        // available in one place
+        if !self.error_journal.is_empty() {
+            network.sig_send_central(0, Signal::StepErrors(self.error_journal.clone()));
+        }
+
+        // order only covers locally stored entities, central executes
+        // the commands in the order they're received from the nodes
+        let mut entities = self.entities.keys().copied().collect::<Vec<_>>();
+        entities.sort_unstable();
+        let mut cexts = step::order_commands(
+            std::mem::take(&mut *central_ext_cmds.lock().unwrap()),
+            model,
+            event_queue,
+            &entities,
+            self.clock,
+        );
         cexts.reverse();
         let mut counter = 0;
         let mut cexts_part = Vec::new();
@@ -310,12 +454,20 @@ impl SimNode {
                 Signal::UpdateModel(model) => {
                     debug!("signal: update model");
                     self.model = model;
+                    #[cfg(feature = "machine")]
+                    {
+                        self.system_schedule = None;
+                    }
                     trace!("update model finished");
                 }
                 Signal::EndOfMessages => {
                     debug!("signal: end of messages, breaking loop");
                     break;
                 }
+                Signal::StepAborted => {
+                    warn!("signal: step aborted, clock stays at {}", self.clock);
+                    return Err(Error::StepAborted(self.clock));
+                }
                 _ => (),
             }
         }
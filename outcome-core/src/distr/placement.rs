@@ -0,0 +1,183 @@
+//! Pluggable entity placement.
+//!
+//! Placement decides which node a newly spawned entity ends up on. The
+//! built-in [`DistributionPolicy`] variants cover the basic cases, custom
+//! strategies can be plugged into [`SimCentral`] by implementing the
+//! [`EntityPlacement`] trait, e.g. to keep entities spawned from the same
+//! prefab together, or to weigh nodes by available memory.
+//!
+//! ```ignore
+//! use outcome_core::distr::placement::LeastLoaded;
+//!
+//! central.set_placement(LeastLoaded);
+//! ```
+//!
+//! Built-in strategies can also be selected by name, e.g. when read from
+//! a config file or command line, see [`from_name`].
+//!
+//! Distribution hints declared on components, such as pinning entities to
+//! the coordinator or co-locating them with entities holding another
+//! component, are resolved by central before consulting the placement, see
+//! [`DistributionHints`].
+//!
+//! [`DistributionPolicy`]: super::DistributionPolicy
+//! [`SimCentral`]: super::SimCentral
+//! [`DistributionHints`]: crate::model::DistributionHints
+
+use fnv::FnvHashMap;
+use rand::prelude::SliceRandom;
+
+use crate::distr::NodeId;
+use crate::error::{Error, Result};
+use crate::{EntityId, PrefabName};
+
+/// Current distribution of entities among nodes, as seen by central.
+pub struct PlacementContext<'a> {
+    /// Number of entities on each node, including ones queued for spawning
+    pub loads: &'a FnvHashMap<NodeId, usize>,
+}
+
+impl<'a> PlacementContext<'a> {
+    /// Ids of all the available nodes, in ascending order.
+    pub fn nodes(&self) -> Vec<NodeId> {
+        let mut nodes = self.loads.keys().cloned().collect::<Vec<_>>();
+        nodes.sort_unstable();
+        nodes
+    }
+}
+
+/// Strategy for assigning newly spawned entities to nodes.
+pub trait EntityPlacement: Send {
+    /// Picks the node the entity will be spawned on.
+    fn place(
+        &mut self,
+        entity: EntityId,
+        prefab: Option<&PrefabName>,
+        ctx: &PlacementContext,
+    ) -> Result<NodeId>;
+}
+
+/// Creates one of the built-in placement strategies based on it's name,
+/// one of: `random`, `round-robin`, `least-loaded`.
+pub fn from_name(name: &str) -> Result<Box<dyn EntityPlacement>> {
+    match name {
+        "random" => Ok(Box::new(Random)),
+        "round-robin" => Ok(Box::new(RoundRobin::default())),
+        "least-loaded" => Ok(Box::new(LeastLoaded)),
+        _ => Err(Error::Other(format!("invalid entity placement: {}", name))),
+    }
+}
+
+fn no_nodes() -> Error {
+    Error::Other("no nodes available".to_string())
+}
+
+/// Picks a random node for each entity.
+#[derive(Default)]
+pub struct Random;
+
+impl EntityPlacement for Random {
+    fn place(
+        &mut self,
+        _: EntityId,
+        _: Option<&PrefabName>,
+        ctx: &PlacementContext,
+    ) -> Result<NodeId> {
+        ctx.nodes()
+            .choose(&mut rand::thread_rng())
+            .cloned()
+            .ok_or_else(no_nodes)
+    }
+}
+
+/// Cycles through the nodes in order of their ids.
+#[derive(Default)]
+pub struct RoundRobin {
+    next: usize,
+}
+
+impl EntityPlacement for RoundRobin {
+    fn place(
+        &mut self,
+        _: EntityId,
+        _: Option<&PrefabName>,
+        ctx: &PlacementContext,
+    ) -> Result<NodeId> {
+        let nodes = ctx.nodes();
+        if nodes.is_empty() {
+            return Err(no_nodes());
+        }
+        let node = nodes[self.next % nodes.len()];
+        self.next = self.next.wrapping_add(1);
+        Ok(node)
+    }
+}
+
+/// Picks the node currently holding the fewest entities, ties are broken
+/// by the lowest node id.
+#[derive(Default)]
+pub struct LeastLoaded;
+
+impl EntityPlacement for LeastLoaded {
+    fn place(
+        &mut self,
+        _: EntityId,
+        _: Option<&PrefabName>,
+        ctx: &PlacementContext,
+    ) -> Result<NodeId> {
+        ctx.loads
+            .iter()
+            .min_by_key(|(node, load)| (**load, **node))
+            .map(|(node, _)| *node)
+            .ok_or_else(no_nodes)
+    }
+}
+
+#[cfg(test)]
+fn node_loads(loads: &[(NodeId, usize)]) -> FnvHashMap<NodeId, usize> {
+    loads.iter().cloned().collect()
+}
+
+#[test]
+fn round_robin_cycles_through_nodes_in_order() {
+    let loads = node_loads(&[(3, 0), (1, 5), (2, 0)]);
+    let ctx = PlacementContext { loads: &loads };
+    let mut placement = RoundRobin::default();
+    let picked = (0..5)
+        .map(|id| placement.place(id, None, &ctx).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(picked, vec![1, 2, 3, 1, 2]);
+
+    let empty = FnvHashMap::default();
+    let ctx = PlacementContext { loads: &empty };
+    assert!(placement.place(5, None, &ctx).is_err());
+}
+
+#[test]
+fn least_loaded_picks_emptiest_node() {
+    let loads = node_loads(&[(1, 4), (2, 1), (3, 2)]);
+    let ctx = PlacementContext { loads: &loads };
+    assert_eq!(LeastLoaded.place(0, None, &ctx).unwrap(), 2);
+
+    // ties go to the lowest node id
+    let loads = node_loads(&[(3, 1), (2, 1), (1, 4)]);
+    let ctx = PlacementContext { loads: &loads };
+    assert_eq!(LeastLoaded.place(0, None, &ctx).unwrap(), 2);
+
+    let empty = FnvHashMap::default();
+    let ctx = PlacementContext { loads: &empty };
+    assert!(LeastLoaded.place(0, None, &ctx).is_err());
+}
+
+#[test]
+fn placement_selected_by_name() {
+    let loads = node_loads(&[(1, 2), (2, 0)]);
+    let ctx = PlacementContext { loads: &loads };
+    let mut placement = from_name("least-loaded").unwrap();
+    assert_eq!(placement.place(0, None, &ctx).unwrap(), 2);
+    let mut placement = from_name("round-robin").unwrap();
+    assert_eq!(placement.place(0, None, &ctx).unwrap(), 1);
+    assert_eq!(placement.place(1, None, &ctx).unwrap(), 2);
+    assert!(from_name("random").is_ok());
+    assert!(from_name("by-weather").is_err());
+}
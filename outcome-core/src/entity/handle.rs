@@ -0,0 +1,250 @@
+//! Handles to entities and their components.
+
+use fnv::FnvHashMap;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::address::Address;
+use crate::entity::{Entity, Storage};
+use crate::error::{Error, Result};
+use crate::model::{ComponentModel, VarModel};
+use crate::{string, CompName, EntityId, EntityName, Float, Int, Var, VarName};
+
+/// Read-only handle to a single entity.
+#[derive(Clone)]
+pub struct EntityRef<'a> {
+    id: EntityId,
+    name: Option<EntityName>,
+    entity: &'a Entity,
+}
+
+impl<'a> EntityRef<'a> {
+    /// Integer id of the entity.
+    pub fn id(&self) -> EntityId {
+        self.id
+    }
+
+    /// String id of the entity, if it has one.
+    pub fn name(&self) -> Option<&EntityName> {
+        self.name.as_ref()
+    }
+
+    /// Names of the components attached to the entity.
+    pub fn components(&self) -> &'a [CompName] {
+        &self.entity.components
+    }
+
+    /// Checks whether the component is attached to the entity.
+    pub fn has_component(&self, comp: &CompName) -> bool {
+        self.entity.components.contains(comp)
+    }
+
+    /// Gets a view of the component's vars, if the component is attached.
+    pub fn component(&self, comp: &CompName) -> Option<ComponentView<'a>> {
+        if !self.has_component(comp) {
+            return None;
+        }
+        Some(ComponentView {
+            name: comp.clone(),
+            entity: self.entity,
+        })
+    }
+
+    /// Gets the value of a component var.
+    pub fn var(&self, comp: &CompName, var: &VarName) -> Result<&'a Var> {
+        self.entity.storage.get_var(&(comp.clone(), var.clone()))
+    }
+
+    /// Underlying entity.
+    pub fn entity(&self) -> &'a Entity {
+        self.entity
+    }
+}
+
+/// Read-only view of the vars of a single component attached to an entity.
+#[derive(Clone)]
+pub struct ComponentView<'a> {
+    name: CompName,
+    entity: &'a Entity,
+}
+
+impl<'a> ComponentView<'a> {
+    /// Name of the component.
+    pub fn name(&self) -> &CompName {
+        &self.name
+    }
+
+    /// Gets the value of a var.
+    pub fn var(&self, var: &VarName) -> Result<&'a Var> {
+        self.entity
+            .storage
+            .get_var(&(self.name.clone(), var.clone()))
+    }
+
+    /// Iterates over all the component's vars, in no particular order.
+    pub fn vars(&self) -> impl Iterator<Item = (&'a VarName, &'a Var)> + '_ {
+        self.entity
+            .storage
+            .map
+            .iter()
+            .filter(move |((comp, _), _)| comp == &self.name)
+            .map(|((_, var_name), var)| (var_name, var))
+    }
+}
+
+/// Reverses the entity name index so that names can be attached to handles.
+fn names_by_id(idx: &FnvHashMap<EntityName, EntityId>) -> FnvHashMap<EntityId, EntityName> {
+    idx.iter().map(|(name, id)| (*id, name.clone())).collect()
+}
+
+/// Iterates over entities, creating handles for each.
+pub(crate) fn iter<'a>(
+    entities: &'a FnvHashMap<EntityId, Entity>,
+    idx: &FnvHashMap<EntityName, EntityId>,
+) -> impl Iterator<Item = EntityRef<'a>> {
+    let names = names_by_id(idx);
+    entities.iter().map(move |(id, entity)| EntityRef {
+        id: *id,
+        name: names.get(id).cloned(),
+        entity,
+    })
+}
+
+/// Iterates over entities in parallel, creating handles for each.
+#[cfg(feature = "rayon")]
+pub(crate) fn par_iter<'a>(
+    entities: &'a FnvHashMap<EntityId, Entity>,
+    idx: &FnvHashMap<EntityName, EntityId>,
+) -> impl ParallelIterator<Item = EntityRef<'a>> {
+    let names = names_by_id(idx);
+    entities.par_iter().map(move |(id, entity)| EntityRef {
+        id: *id,
+        name: names.get(id).cloned(),
+        entity,
+    })
+}
+
+/// Mutable view of a single component attached to an entity, backed by the
+/// component model.
+///
+/// Vars are accessed by their names as declared in the model, instead of
+/// building storage indexes by hand. Accessing a var the model doesn't
+/// declare results in an error, as does writing a value of the wrong type
+/// or one falling outside the declared bounds. This makes the view a safer
+/// interface for code living outside the engine, like dynamic libraries,
+/// which could otherwise silently write to stale storage keys after a var
+/// was renamed.
+pub struct ComponentViewMut<'a> {
+    ent: EntityId,
+    model: &'a ComponentModel,
+    storage: &'a mut Storage,
+}
+
+impl<'a> ComponentViewMut<'a> {
+    /// Creates a new view of the component on the entity with the given
+    /// storage.
+    pub fn new(ent: EntityId, model: &'a ComponentModel, storage: &'a mut Storage) -> Self {
+        Self {
+            ent,
+            model,
+            storage,
+        }
+    }
+
+    /// Id of the entity the component is attached to.
+    pub fn entity_id(&self) -> EntityId {
+        self.ent
+    }
+
+    /// Name of the component.
+    pub fn name(&self) -> &CompName {
+        &self.model.name
+    }
+
+    /// Names of the vars declared by the component model, in declaration
+    /// order.
+    pub fn var_names(&self) -> impl Iterator<Item = &VarName> {
+        self.model.vars.iter().map(|v| &v.name)
+    }
+
+    /// Gets the value of a var.
+    pub fn get(&self, var: &str) -> Result<&Var> {
+        let var_model = self.var_model(var)?;
+        self.storage
+            .get_var(&(self.model.name.clone(), var_model.name.clone()))
+    }
+
+    /// Sets the value of a var. The value has to match the declared type
+    /// and fall within the declared bounds.
+    pub fn set(&mut self, var: &str, value: Var) -> Result<()> {
+        let var_model = self.var_model(var)?;
+        if value.get_type() != var_model.type_ {
+            return Err(Error::VarTypeMismatch(
+                self.address(var_model),
+                var_model.type_.to_str().to_string(),
+                value.get_type().to_str().to_string(),
+            ));
+        }
+        if !var_model.within_bounds(&value) {
+            return Err(Error::VarOutOfBounds(
+                self.address(var_model),
+                value.to_string(),
+            ));
+        }
+        let idx = (self.model.name.clone(), var_model.name.clone());
+        *self.storage.get_var_mut(&idx)? = value;
+        Ok(())
+    }
+
+    /// Gets the value of a float var.
+    pub fn get_float(&self, var: &str) -> Result<Float> {
+        Ok(*self.get(var)?.as_float()?)
+    }
+
+    /// Sets the value of a float var.
+    pub fn set_float(&mut self, var: &str, value: Float) -> Result<()> {
+        self.set(var, Var::Float(value))
+    }
+
+    /// Gets the value of an int var.
+    pub fn get_int(&self, var: &str) -> Result<Int> {
+        Ok(*self.get(var)?.as_int()?)
+    }
+
+    /// Sets the value of an int var.
+    pub fn set_int(&mut self, var: &str, value: Int) -> Result<()> {
+        self.set(var, Var::Int(value))
+    }
+
+    /// Gets the value of a bool var.
+    pub fn get_bool(&self, var: &str) -> Result<bool> {
+        Ok(*self.get(var)?.as_bool()?)
+    }
+
+    /// Sets the value of a bool var.
+    pub fn set_bool(&mut self, var: &str, value: bool) -> Result<()> {
+        self.set(var, Var::Bool(value))
+    }
+
+    fn var_model(&self, var: &str) -> Result<&'a VarModel> {
+        let model: &'a ComponentModel = self.model;
+        model
+            .vars
+            .iter()
+            .find(|v| v.name.as_str() == var)
+            .ok_or(Error::Other(format!(
+                "component {} has no var named {}",
+                model.name, var
+            )))
+    }
+
+    fn address(&self, var_model: &VarModel) -> Address {
+        Address {
+            entity: string::new_truncate(&self.ent.to_string()),
+            component: self.model.name.clone(),
+            var_type: var_model.type_,
+            var_name: var_model.name.clone(),
+        }
+    }
+}
@@ -1,7 +1,9 @@
 //! Entity structure related definitions.
 
+pub mod handle;
 mod storage;
 
+pub use self::handle::{ComponentView, ComponentViewMut, EntityRef};
 pub use self::storage::Storage;
 
 use std::collections::{BTreeMap, HashMap};
@@ -11,13 +13,12 @@ use fnv::FnvHashMap;
 
 use crate::error::{Error, Result};
 use crate::model::{ComponentModel, EntityPrefab};
+use crate::rng::EntityRng;
 use crate::{model, CompName, StringId};
 use crate::{string, EntityName, EventName, SimModel};
 
 #[cfg(feature = "machine_dynlib")]
 use libloading::Library;
-#[cfg(feature = "machine_lua")]
-use rlua::Lua;
 
 pub use storage::StorageIndex;
 
@@ -38,6 +39,17 @@ pub struct Entity {
     #[cfg(feature = "machine")]
     pub comp_queue: FnvHashMap<EventName, Vec<CompName>>,
 
+    /// Events invoked for this entity only, processed during the next step
+    /// along with the global events
+    #[cfg(feature = "machine")]
+    #[serde(default)]
+    pub event_queue: Vec<EventName>,
+
+    /// Random number stream of the entity, created the first time the
+    /// entity executes logic
+    #[serde(default)]
+    pub rng: Option<EntityRng>,
+
     /// Non-serializable aspects of an entity
     // TODO use cfg_if to include this only if related features are enabled
     // #[serde(skip)]
@@ -46,7 +58,12 @@ pub struct Entity {
 
 /// Contains all the non-serializable constructs stored on an entity instance.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
-pub struct EntityNonSer {}
+pub struct EntityNonSer {
+    /// Lua state, created on first `lua_call` made by the entity
+    #[cfg(feature = "machine_lua")]
+    #[serde(skip)]
+    pub lua_state: Option<crate::machine::cmd::lua::LuaState>,
+}
 
 impl Entity {
     /// Creates a new entity using the prefab model.
@@ -72,6 +89,9 @@ impl Entity {
         for comp in &prefab.components {
             ent.attach(comp.clone(), model)?;
         }
+        for (idx, value) in &prefab.defaults {
+            ent.storage.insert(idx.clone(), value.clone());
+        }
 
         // TODO setup dyn libs
 
@@ -96,6 +116,9 @@ impl Entity {
             comp_state: Default::default(),
             #[cfg(feature = "machine")]
             comp_queue: Default::default(),
+            #[cfg(feature = "machine")]
+            event_queue: Vec::new(),
+            rng: None,
             insta: EntityNonSer::default(),
         }
     }
@@ -156,7 +179,7 @@ impl Entity {
     }
 
     pub fn detach(&mut self, comp_name: &CompName, sim_model: &SimModel) -> Result<()> {
-        if let Ok(idx) = self.components.binary_search(comp_name) {
+        if let Some(idx) = self.components.iter().position(|c| c == comp_name) {
             self.components.remove(idx);
         }
         self.storage
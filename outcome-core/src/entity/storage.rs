@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
 
 use crate::address::{Address, LocalAddress};
 use crate::error::{Error, Result};
@@ -11,11 +11,22 @@ pub type StorageIndex = (CompName, VarName);
 // type TypedStorageIndex = (StorageIndex, VarType);
 
 /// Entity's main data storage structure.
+///
+/// Changes made through the storage methods are tracked per var. Vars are
+/// first marked as dirty, and once a step is processed the dirty vars are
+/// stamped with the clock value of that step. Writes made directly to the
+/// `map` are not tracked.
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Storage {
     pub map: FnvHashMap<StorageIndex, Var>,
     // TODO benchmark performance of the alternative storage layout
     // _map: FnvHashMap<CompId, FnvHashMap<VarId, Var>>,
+    /// Vars modified since changes were last stamped
+    #[serde(skip)]
+    dirty: FnvHashSet<StorageIndex>,
+    /// Clock value of the step during which each var was last modified
+    #[serde(skip)]
+    changed_at: FnvHashMap<StorageIndex, usize>,
 }
 
 impl Storage {
@@ -25,10 +36,15 @@ impl Storage {
             .ok_or(Error::FailedGettingVarFromEntityStorage(idx.clone()))
     }
 
+    /// Gets a mutable reference to the var, marking it as changed.
     pub fn get_var_mut(&mut self, idx: &StorageIndex) -> Result<&mut Var> {
-        self.map
-            .get_mut(&idx)
-            .ok_or(Error::FailedGettingVarFromEntityStorage(idx.clone()))
+        match self.map.get_mut(&idx) {
+            Some(var) => {
+                self.dirty.insert(idx.clone());
+                Ok(var)
+            }
+            None => Err(Error::FailedGettingVarFromEntityStorage(idx.clone())),
+        }
     }
 
     pub fn get_all_coerce_to_string(&self) -> HashMap<String, String> {
@@ -45,6 +61,7 @@ impl Storage {
     }
 
     pub fn insert(&mut self, idx: (CompName, VarName), var: Var) {
+        self.dirty.insert(idx.clone());
         self.map.insert(idx, var);
     }
 
@@ -61,10 +78,35 @@ impl Storage {
         *target = var.clone();
     }
 
+    /// Removes a single var, along with its change tracking state.
+    pub fn remove(&mut self, idx: &StorageIndex) -> Option<Var> {
+        self.dirty.remove(idx);
+        self.changed_at.remove(idx);
+        self.map.remove(idx)
+    }
+
     pub fn remove_comp_vars(&mut self, comp_name: &CompName, comp_model: &ComponentModel) {
         for var_model in &comp_model.vars {
-            self.map
-                .remove(&(comp_name.clone(), var_model.name.clone()));
+            self.remove(&(comp_name.clone(), var_model.name.clone()));
         }
     }
+
+    /// Stamps vars changed since the last call with the given clock value.
+    pub fn stamp_changes(&mut self, clock: usize) {
+        for idx in self.dirty.drain() {
+            self.changed_at.insert(idx, clock);
+        }
+    }
+
+    /// Iterates over vars changed during or after the step with the given
+    /// clock value, including changes not yet stamped.
+    pub fn changed_since(&self, step: usize) -> impl Iterator<Item = (&StorageIndex, &Var)> {
+        self.map.iter().filter(move |(idx, _)| {
+            self.dirty.contains(*idx)
+                || self
+                    .changed_at
+                    .get(*idx)
+                    .map_or(false, |clock| *clock >= step)
+        })
+    }
 }
@@ -20,17 +20,29 @@ impl From<io::Error> for Error {
     }
 }
 
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        Self::SerializationError(e.to_string())
+    }
+}
+
 /// Crate-wide error type.
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("would block")]
     WouldBlock,
-    #[error("would block")]
+    #[error("network error: {0}")]
     NetworkError(String),
+    #[error("step {0} aborted by central")]
+    StepAborted(usize),
+    #[error("node {0} stopped responding, considered dead")]
+    NodeUnresponsive(u32),
 
     // IoError(#[from] io::Error),
     #[error("io error: {0}")]
     IoError(String),
+    #[error("serialization error: {0}")]
+    SerializationError(String),
 
     #[cfg(feature = "yaml")]
     #[error("yaml deserialization error")]
@@ -58,6 +70,8 @@ pub enum Error {
 
     #[error("invalid var type: {0}")]
     InvalidVarType(String),
+    #[error("unknown custom var kind: {0}")]
+    UnknownCustomVarKind(String),
     #[error("invalid local address: {0}")]
     InvalidAddress(String),
     #[error("invalid local address: {0}")]
@@ -98,6 +112,20 @@ pub enum Error {
     FailedCreatingAddress(String),
     #[error("failed creating variable from string: {0}")]
     FailedCreatingVar(String),
+    #[error("var type mismatch for {0}: expected {1}, got {2}")]
+    VarTypeMismatch(Address, String, String),
+    #[error("var value out of declared bounds for {0}: {1}")]
+    VarOutOfBounds(Address, String),
+    #[error("decimal value out of range: {0}")]
+    DecimalOutOfRange(String),
+    #[error("fixed point value out of range: {0}")]
+    FixedOutOfRange(String),
+    #[error("unknown unit: {0}")]
+    UnknownUnit(String),
+    #[error("unit mismatch: {0} vs {1}")]
+    UnitMismatch(String, String),
+    #[error("failed loading data file {0}, line {1}: {2}")]
+    FailedLoadingDataFile(String, usize, String),
 
     #[error("project root not found for file: {0}")]
     ProjectRootNotFound(String),
@@ -108,7 +136,7 @@ pub enum Error {
     #[error("other error: {0}")]
     Other(String),
     #[cfg(feature = "machine")]
-    #[error("runtime machine panic")]
+    #[error("runtime machine panic: {0}")]
     MachinePanic(#[from] machine::Error),
 }
 
@@ -0,0 +1,123 @@
+//! Geographic coordinates.
+//!
+//! Provides the position type backing `geo` vars, along with distance and
+//! bearing calculations on a spherical Earth model. Positions are given in
+//! degrees, distances in meters.
+//!
+//! Projections for mapping positions onto flat grids are available with
+//! the `geo_projection` feature.
+
+#[cfg(feature = "geo_projection")]
+pub mod projection;
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+
+/// Mean Earth radius in meters.
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+const COORD_SEPARATOR: char = ',';
+
+/// Position on Earth's surface, in degrees.
+///
+/// Coordinates are always stored as 64 bit floats, regardless of the
+/// `big_nums` feature, as 32 bits are only precise to about a meter.
+#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct GeoPosition {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl GeoPosition {
+    /// Creates a new position, validating the coordinate ranges.
+    pub fn new(lat: f64, lon: f64) -> Result<Self> {
+        if !(-90. ..=90.).contains(&lat) || !(-180. ..=180.).contains(&lon) {
+            return Err(Error::Other(format!(
+                "geo coordinates out of range: {}, {}",
+                lat, lon
+            )));
+        }
+        Ok(GeoPosition { lat, lon })
+    }
+
+    /// Great-circle distance to the other position in meters, calculated
+    /// using the haversine formula.
+    pub fn distance(&self, other: &GeoPosition) -> f64 {
+        distance(self.lat, self.lon, other.lat, other.lon)
+    }
+
+    /// Initial bearing towards the other position in degrees, clockwise
+    /// from north, within `0..360`.
+    pub fn bearing(&self, other: &GeoPosition) -> f64 {
+        bearing(self.lat, self.lon, other.lat, other.lon)
+    }
+
+    /// Position reached by travelling the given distance in meters along
+    /// the great circle with the given initial bearing.
+    pub fn destination(&self, bearing: f64, distance: f64) -> GeoPosition {
+        let (lat, lon) = (self.lat.to_radians(), self.lon.to_radians());
+        let bearing = bearing.to_radians();
+        let angular = distance / EARTH_RADIUS;
+        let dest_lat =
+            (lat.sin() * angular.cos() + lat.cos() * angular.sin() * bearing.cos()).asin();
+        let dest_lon = lon
+            + (bearing.sin() * angular.sin() * lat.cos())
+                .atan2(angular.cos() - lat.sin() * dest_lat.sin());
+        GeoPosition {
+            lat: dest_lat.to_degrees(),
+            // normalize to -180..180
+            lon: (dest_lon.to_degrees() + 540.) % 360. - 180.,
+        }
+    }
+}
+
+/// Great-circle distance in meters between two positions given as
+/// coordinates in degrees.
+pub fn distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.).sin().powi(2);
+    2. * EARTH_RADIUS * a.sqrt().min(1.).asin()
+}
+
+/// Initial bearing in degrees from the first position towards the second,
+/// with positions given as coordinates in degrees.
+pub fn bearing(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lon = (lon2 - lon1).to_radians();
+    let y = d_lon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * d_lon.cos();
+    (y.atan2(x).to_degrees() + 360.) % 360.
+}
+
+impl fmt::Display for GeoPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.lat, COORD_SEPARATOR, self.lon)
+    }
+}
+
+impl FromStr for GeoPosition {
+    type Err = Error;
+
+    /// Parses a position written as `lat,lon`, e.g. `52.23,21.01`.
+    fn from_str(s: &str) -> Result<Self> {
+        let split = s.split(COORD_SEPARATOR).collect::<Vec<&str>>();
+        if split.len() != 2 {
+            return Err(Error::FailedCreatingVar(s.to_string()));
+        }
+        GeoPosition::new(split[0].trim().parse()?, split[1].trim().parse()?)
+    }
+}
+
+#[test]
+fn display_round_trips() {
+    let pos = GeoPosition::new(52.229676, -21.012229).unwrap();
+    assert_eq!(pos.to_string().parse::<GeoPosition>().unwrap(), pos);
+
+    let var = crate::Var::Geo(pos);
+    let parsed = crate::Var::from_str(&var.to_string(), Some(crate::VarType::Geo)).unwrap();
+    assert_eq!(parsed, var);
+}
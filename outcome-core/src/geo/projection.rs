@@ -0,0 +1,96 @@
+//! Map projections for placing geographic positions on flat grids.
+
+use super::{GeoPosition, EARTH_RADIUS};
+
+/// Max latitude representable with the web mercator projection.
+pub const MERCATOR_MAX_LAT: f64 = 85.051_128_78;
+
+/// Projection from geographic positions onto a plane, with plane
+/// coordinates in meters.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Projection {
+    /// Equirectangular projection centered on the given position. Simple
+    /// and accurate enough for regions spanning up to a few hundred
+    /// kilometers.
+    Equirectangular { origin: GeoPosition },
+    /// Spherical mercator as used by most web maps. Latitudes are clamped
+    /// to `MERCATOR_MAX_LAT`.
+    WebMercator,
+}
+
+impl Projection {
+    /// Projects the position onto the plane, returning `(x, y)` with `x`
+    /// growing eastward and `y` growing northward.
+    pub fn project(&self, pos: &GeoPosition) -> (f64, f64) {
+        match self {
+            Projection::Equirectangular { origin } => (
+                (pos.lon - origin.lon).to_radians() * origin.lat.to_radians().cos() * EARTH_RADIUS,
+                (pos.lat - origin.lat).to_radians() * EARTH_RADIUS,
+            ),
+            Projection::WebMercator => {
+                let lat = pos.lat.max(-MERCATOR_MAX_LAT).min(MERCATOR_MAX_LAT);
+                (
+                    pos.lon.to_radians() * EARTH_RADIUS,
+                    (std::f64::consts::FRAC_PI_4 + lat.to_radians() / 2.)
+                        .tan()
+                        .ln()
+                        * EARTH_RADIUS,
+                )
+            }
+        }
+    }
+
+    /// Reverses the projection.
+    pub fn unproject(&self, x: f64, y: f64) -> GeoPosition {
+        match self {
+            Projection::Equirectangular { origin } => GeoPosition {
+                lat: origin.lat + (y / EARTH_RADIUS).to_degrees(),
+                lon: origin.lon + (x / (EARTH_RADIUS * origin.lat.to_radians().cos())).to_degrees(),
+            },
+            Projection::WebMercator => GeoPosition {
+                lat: (2. * (y / EARTH_RADIUS).exp().atan() - std::f64::consts::FRAC_PI_2)
+                    .to_degrees(),
+                lon: (x / EARTH_RADIUS).to_degrees(),
+            },
+        }
+    }
+}
+
+/// Maps geographic positions onto cells of a grid var.
+///
+/// Grid rows go from north to south, matching the layout of grids loaded
+/// from images, so the first row is the one furthest to the north.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GridMapping {
+    pub projection: Projection,
+    /// Position of the north-west corner of the grid
+    pub north_west: GeoPosition,
+    /// Width and height of a single cell in meters
+    pub cell_size: f64,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl GridMapping {
+    /// Returns `(x, y)` coordinates of the cell containing the position,
+    /// or `None` if the position lies outside the grid.
+    pub fn to_cell(&self, pos: &GeoPosition) -> Option<(usize, usize)> {
+        let (origin_x, origin_y) = self.projection.project(&self.north_west);
+        let (x, y) = self.projection.project(pos);
+        let col = ((x - origin_x) / self.cell_size).floor();
+        let row = ((origin_y - y) / self.cell_size).floor();
+        if col < 0. || row < 0. || col >= self.width as f64 || row >= self.height as f64 {
+            return None;
+        }
+        Some((col as usize, row as usize))
+    }
+
+    /// Returns the position of the center of the cell.
+    pub fn cell_center(&self, x: usize, y: usize) -> GeoPosition {
+        let (origin_x, origin_y) = self.projection.project(&self.north_west);
+        self.projection.unproject(
+            origin_x + (x as f64 + 0.5) * self.cell_size,
+            origin_y - (y as f64 + 0.5) * self.cell_size,
+        )
+    }
+}
@@ -0,0 +1,297 @@
+//! Graph structure for relationship modeling.
+//!
+//! Graphs connect entities using weighted edges, making it possible to
+//! model things like social or transport networks. A graph is stored as
+//! a regular var, either on a component of the entities involved or on
+//! a dedicated entity serving as a shared resource.
+//!
+//! Nodes are entity ids. Edges without an explicitly provided weight get
+//! the default weight of `1`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::{EntityId, Float};
+
+/// Weight given to edges added without an explicit weight.
+pub const DEFAULT_EDGE_WEIGHT: Float = 1.;
+
+const UNDIRECTED_EDGE_SYMBOL: char = '-';
+const DIRECTED_EDGE_SYMBOL: char = '>';
+const EDGE_WEIGHT_SYMBOL: char = ':';
+
+/// Graph of entities, stored as adjacency lists.
+///
+/// Edges of undirected graphs are stored in the adjacency lists of both
+/// their nodes.
+#[derive(Debug, Clone, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct Graph {
+    pub directed: bool,
+    /// Outgoing edges of each node, with their weights
+    pub adjacency: BTreeMap<EntityId, BTreeMap<EntityId, Float>>,
+}
+
+impl Graph {
+    pub fn new(directed: bool) -> Self {
+        Graph {
+            directed,
+            adjacency: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_node(&mut self, node: EntityId) {
+        self.adjacency.entry(node).or_default();
+    }
+
+    /// Removes the node along with all the edges leading to it.
+    pub fn remove_node(&mut self, node: EntityId) -> bool {
+        if self.adjacency.remove(&node).is_none() {
+            return false;
+        }
+        for edges in self.adjacency.values_mut() {
+            edges.remove(&node);
+        }
+        true
+    }
+
+    /// Replaces node ids using the provided function. Nodes for which the
+    /// function returns `None` are removed along with their edges.
+    pub fn map_nodes<F: Fn(EntityId) -> Option<EntityId>>(&mut self, f: F) {
+        let adjacency = std::mem::take(&mut self.adjacency);
+        for (node, edges) in adjacency {
+            let node = match f(node) {
+                Some(n) => n,
+                None => continue,
+            };
+            let edges = edges
+                .into_iter()
+                .filter_map(|(to, weight)| f(to).map(|to| (to, weight)))
+                .collect();
+            self.adjacency.insert(node, edges);
+        }
+    }
+
+    /// Adds an edge, creating missing nodes. Adding an existing edge
+    /// updates its weight.
+    pub fn add_edge(&mut self, from: EntityId, to: EntityId, weight: Float) {
+        self.adjacency.entry(from).or_default().insert(to, weight);
+        let reverse = self.adjacency.entry(to).or_default();
+        if !self.directed {
+            reverse.insert(from, weight);
+        }
+    }
+
+    pub fn remove_edge(&mut self, from: EntityId, to: EntityId) -> bool {
+        let removed = self
+            .adjacency
+            .get_mut(&from)
+            .map_or(false, |edges| edges.remove(&to).is_some());
+        if removed && !self.directed {
+            if let Some(edges) = self.adjacency.get_mut(&to) {
+                edges.remove(&from);
+            }
+        }
+        removed
+    }
+
+    pub fn contains_node(&self, node: EntityId) -> bool {
+        self.adjacency.contains_key(&node)
+    }
+
+    /// Returns the weight of the edge, if it exists.
+    pub fn weight(&self, from: EntityId, to: EntityId) -> Option<Float> {
+        self.adjacency
+            .get(&from)
+            .and_then(|edges| edges.get(&to))
+            .copied()
+    }
+
+    /// Returns nodes reachable from the node through a single edge.
+    pub fn neighbors(&self, node: EntityId) -> Vec<EntityId> {
+        self.adjacency
+            .get(&node)
+            .map(|edges| edges.keys().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the number of edges going out of the node. For undirected
+    /// graphs that's the number of all the edges connected to the node.
+    pub fn degree(&self, node: EntityId) -> usize {
+        self.adjacency.get(&node).map_or(0, |edges| edges.len())
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.adjacency.len()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        if self.directed {
+            self.adjacency.values().map(|edges| edges.len()).sum()
+        } else {
+            self.edges().count()
+        }
+    }
+
+    /// Iterates over all the edges. Edges of undirected graphs are only
+    /// visited once, starting from the node with the lower id.
+    pub fn edges(&self) -> impl Iterator<Item = (EntityId, EntityId, Float)> + '_ {
+        let directed = self.directed;
+        self.adjacency
+            .iter()
+            .flat_map(|(from, edges)| edges.iter().map(move |(to, w)| (*from, *to, *w)))
+            .filter(move |(from, to, _)| directed || from <= to)
+    }
+}
+
+impl fmt::Display for Graph {
+    /// Writes the graph as a list of edges, e.g. `1-2 2-3:0.5`, with
+    /// isolated nodes listed on their own.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = if self.directed {
+            DIRECTED_EDGE_SYMBOL
+        } else {
+            UNDIRECTED_EDGE_SYMBOL
+        };
+        let mut items = Vec::new();
+        for (node, edges) in &self.adjacency {
+            let connected = edges.len() > 0
+                || self
+                    .adjacency
+                    .values()
+                    .any(|edges| edges.contains_key(node));
+            if !connected {
+                items.push(node.to_string());
+            }
+        }
+        for (from, to, weight) in self.edges() {
+            if weight == DEFAULT_EDGE_WEIGHT {
+                items.push(format!("{}{}{}", from, symbol, to));
+            } else {
+                items.push(format!(
+                    "{}{}{}{}{}",
+                    from, symbol, to, EDGE_WEIGHT_SYMBOL, weight
+                ));
+            }
+        }
+        write!(f, "{}", items.join(" "))
+    }
+}
+
+impl FromStr for Graph {
+    type Err = Error;
+
+    /// Parses a whitespace or comma separated list of edges, e.g.
+    /// `1-2 2-3:0.5`. Edges written as `1>2` make the graph directed, the
+    /// two kinds of edges can't be mixed.
+    fn from_str(s: &str) -> Result<Self> {
+        let err = || Error::FailedCreatingVar(s.to_string());
+        let items = s
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|i| !i.is_empty())
+            .collect::<Vec<&str>>();
+        // weights can be negative, only look at the edge part
+        let edge_parts = || {
+            items
+                .iter()
+                .map(|i| i.split(EDGE_WEIGHT_SYMBOL).next().unwrap_or(i))
+        };
+        let directed = edge_parts().any(|e| e.contains(DIRECTED_EDGE_SYMBOL));
+        if directed && edge_parts().any(|e| e.contains(UNDIRECTED_EDGE_SYMBOL)) {
+            return Err(err());
+        }
+        let symbol = if directed {
+            DIRECTED_EDGE_SYMBOL
+        } else {
+            UNDIRECTED_EDGE_SYMBOL
+        };
+
+        let mut graph = Graph::new(directed);
+        for item in items {
+            let (edge, weight) = match item.split_once(EDGE_WEIGHT_SYMBOL) {
+                Some((edge, weight)) => (edge, weight.parse().map_err(|_| err())?),
+                None => (item, DEFAULT_EDGE_WEIGHT),
+            };
+            match edge.split_once(symbol) {
+                Some((from, to)) => graph.add_edge(
+                    from.parse().map_err(|_| err())?,
+                    to.parse().map_err(|_| err())?,
+                    weight,
+                ),
+                None => graph.add_node(edge.parse().map_err(|_| err())?),
+            }
+        }
+        Ok(graph)
+    }
+}
+
+#[test]
+fn edges_are_added_and_removed() {
+    let mut graph = Graph::new(false);
+    graph.add_edge(1, 2, DEFAULT_EDGE_WEIGHT);
+    graph.add_edge(2, 3, 0.5);
+    assert_eq!(graph.node_count(), 3);
+    assert_eq!(graph.edge_count(), 2);
+    // undirected edges go both ways
+    assert_eq!(graph.weight(3, 2), Some(0.5));
+    assert_eq!(graph.neighbors(2), vec![1, 3]);
+
+    // adding an existing edge updates its weight
+    graph.add_edge(2, 1, 2.);
+    assert_eq!(graph.weight(1, 2), Some(2.));
+    assert_eq!(graph.edge_count(), 2);
+
+    assert!(graph.remove_edge(3, 2));
+    assert!(!graph.remove_edge(3, 2));
+    assert_eq!(graph.weight(2, 3), None);
+    assert_eq!(graph.degree(2), 1);
+    // nodes stay in the graph after losing all their edges
+    assert!(graph.contains_node(3));
+
+    assert!(graph.remove_node(1));
+    assert_eq!(graph.neighbors(2), Vec::<EntityId>::new());
+    assert_eq!(graph.edge_count(), 0);
+}
+
+#[test]
+fn directed_edges_go_one_way() {
+    let mut graph = Graph::new(true);
+    graph.add_edge(1, 2, DEFAULT_EDGE_WEIGHT);
+    assert!(graph.contains_node(2));
+    assert_eq!(graph.neighbors(1), vec![2]);
+    assert!(graph.neighbors(2).is_empty());
+    assert!(!graph.remove_edge(2, 1));
+    assert!(graph.remove_edge(1, 2));
+    assert_eq!(graph.edge_count(), 0);
+}
+
+#[test]
+fn missing_nodes_are_handled() {
+    let mut graph: Graph = "1-2".parse().unwrap();
+    assert!(!graph.contains_node(5));
+    assert!(graph.neighbors(5).is_empty());
+    assert_eq!(graph.degree(5), 0);
+    assert_eq!(graph.weight(5, 1), None);
+    assert_eq!(graph.weight(1, 5), None);
+    assert!(!graph.remove_edge(5, 1));
+    assert!(!graph.remove_node(5));
+    assert_eq!(graph.edge_count(), 1);
+}
+
+#[test]
+fn graph_string_round_trip() {
+    let graph: Graph = "1-2, 2-3:0.5 4".parse().unwrap();
+    assert!(!graph.directed);
+    assert_eq!(graph.to_string(), "4 1-2 2-3:0.5");
+    assert_eq!(graph.to_string().parse::<Graph>().unwrap(), graph);
+
+    let graph: Graph = "1>2:-1 2>1".parse().unwrap();
+    assert!(graph.directed);
+    assert_eq!(graph.weight(1, 2), Some(-1.));
+    assert_eq!(graph.to_string().parse::<Graph>().unwrap(), graph);
+
+    assert!("1-2 2>3".parse::<Graph>().is_err());
+    assert!("1-x".parse::<Graph>().is_err());
+    assert!("1-2:heavy".parse::<Graph>().is_err());
+}
@@ -0,0 +1,356 @@
+//! Typed grids.
+//!
+//! Grid vars are stored as `Var::Grid`, a vector of rows of untyped vars.
+//! Typed grids provide a more convenient way of working with grid data,
+//! with elements of a single type, stored contiguously in row-major order.
+//!
+//! ```ignore
+//! let mut grid: IntGrid = sim.get_var(&addr)?.to_grid()?;
+//! grid.set(2, 3, 10)?;
+//! let region = grid.region(0, 0, 4, 4);
+//! *sim.get_var_mut(&addr)? = grid.into();
+//! ```
+//!
+//! Coordinates are given as `(x, y)`, with `x` indexing columns and `y`
+//! indexing rows.
+
+use crate::error::{Error, Result};
+use crate::var::{Var, VarType};
+use crate::{Float, Int};
+
+pub type StringGrid = Grid<String>;
+pub type IntGrid = Grid<Int>;
+pub type FloatGrid = Grid<Float>;
+pub type BoolGrid = Grid<bool>;
+pub type ByteGrid = Grid<u8>;
+
+/// Type that can be used as an element of a typed grid.
+pub trait GridValue: Clone + Default {
+    /// Grid var type corresponding to grids of this element type.
+    const GRID_TYPE: VarType;
+
+    /// Extracts the value from the var, failing if the var is of a different
+    /// type.
+    fn from_var(var: &Var) -> Result<Self>;
+
+    /// Parses the value from a string.
+    fn from_str(s: &str) -> Result<Self>;
+
+    fn into_var(self) -> Var;
+}
+
+impl GridValue for String {
+    const GRID_TYPE: VarType = VarType::StringGrid;
+
+    fn from_var(var: &Var) -> Result<Self> {
+        var.as_string().cloned()
+    }
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(s.to_string())
+    }
+
+    fn into_var(self) -> Var {
+        Var::String(self)
+    }
+}
+
+impl GridValue for Int {
+    const GRID_TYPE: VarType = VarType::IntGrid;
+
+    fn from_var(var: &Var) -> Result<Self> {
+        var.as_int().copied()
+    }
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.parse::<Int>()
+            .map_err(|e| Error::ParsingError(e.to_string()))
+    }
+
+    fn into_var(self) -> Var {
+        Var::Int(self)
+    }
+}
+
+impl GridValue for Float {
+    const GRID_TYPE: VarType = VarType::FloatGrid;
+
+    fn from_var(var: &Var) -> Result<Self> {
+        var.as_float().copied()
+    }
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.parse::<Float>()
+            .map_err(|e| Error::ParsingError(e.to_string()))
+    }
+
+    fn into_var(self) -> Var {
+        Var::Float(self)
+    }
+}
+
+impl GridValue for bool {
+    const GRID_TYPE: VarType = VarType::BoolGrid;
+
+    fn from_var(var: &Var) -> Result<Self> {
+        var.as_bool().copied()
+    }
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.parse::<bool>()
+            .map_err(|e| Error::ParsingError(e.to_string()))
+    }
+
+    fn into_var(self) -> Var {
+        Var::Bool(self)
+    }
+}
+
+impl GridValue for u8 {
+    const GRID_TYPE: VarType = VarType::ByteGrid;
+
+    fn from_var(var: &Var) -> Result<Self> {
+        match var {
+            Var::Byte(v) => Ok(*v),
+            _ => Err(Error::InvalidVarType(format!(
+                "expected byte, got {}",
+                var.get_type().to_str()
+            ))),
+        }
+    }
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.parse::<u8>()
+            .map_err(|e| Error::ParsingError(e.to_string()))
+    }
+
+    fn into_var(self) -> Var {
+        Var::Byte(self)
+    }
+}
+
+/// Rectangular grid of values of a single type.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Grid<T> {
+    width: usize,
+    height: usize,
+    /// Values stored in row-major order
+    cells: Vec<T>,
+}
+
+impl<T: GridValue> Grid<T> {
+    /// Creates a new grid of the given size filled with default values.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![T::default(); width * height],
+        }
+    }
+
+    /// Creates a new grid from a list of rows. All rows have to be of the
+    /// same length.
+    pub fn from_rows(rows: Vec<Vec<T>>) -> Result<Self> {
+        let width = rows.first().map(|row| row.len()).unwrap_or(0);
+        let height = rows.len();
+        let mut cells = Vec::with_capacity(width * height);
+        for (y, row) in rows.into_iter().enumerate() {
+            if row.len() != width {
+                return Err(Error::Other(format!(
+                    "grid row {} has length {}, expected {}",
+                    y,
+                    row.len(),
+                    width
+                )));
+            }
+            cells.extend(row);
+        }
+        Ok(Self {
+            width,
+            height,
+            cells,
+        })
+    }
+
+    /// Creates a new grid by parsing a list of rows of strings.
+    pub fn from_str_rows(rows: &[Vec<String>]) -> Result<Self> {
+        let rows = rows
+            .iter()
+            .map(|row| row.iter().map(|s| T::from_str(s)).collect())
+            .collect::<Result<Vec<Vec<T>>>>()?;
+        Self::from_rows(rows)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        if x < self.width && y < self.height {
+            Some(y * self.width + x)
+        } else {
+            None
+        }
+    }
+
+    /// Gets the value at the given coordinates, if they're within bounds.
+    pub fn get(&self, x: usize, y: usize) -> Option<&T> {
+        self.index(x, y).map(|i| &self.cells[i])
+    }
+
+    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut T> {
+        match self.index(x, y) {
+            Some(i) => Some(&mut self.cells[i]),
+            None => None,
+        }
+    }
+
+    /// Sets the value at the given coordinates, failing if they're out of
+    /// bounds.
+    pub fn set(&mut self, x: usize, y: usize, value: T) -> Result<()> {
+        let (width, height) = (self.width, self.height);
+        let cell = self.get_mut(x, y).ok_or_else(|| {
+            Error::Other(format!(
+                "grid coordinates ({}, {}) out of bounds ({}x{})",
+                x, y, width, height
+            ))
+        })?;
+        *cell = value;
+        Ok(())
+    }
+
+    /// Gets the row at the given index.
+    pub fn row(&self, y: usize) -> Option<&[T]> {
+        if y < self.height {
+            Some(&self.cells[y * self.width..(y + 1) * self.width])
+        } else {
+            None
+        }
+    }
+
+    /// Iterates over the rows of the grid, from the top.
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> {
+        (0..self.height).map(move |y| &self.cells[y * self.width..(y + 1) * self.width])
+    }
+
+    /// Iterates over the values in the column at the given index, from the
+    /// top. Column index out of bounds results in an empty iterator.
+    pub fn column(&self, x: usize) -> impl Iterator<Item = &T> {
+        let height = if x < self.width { self.height } else { 0 };
+        (0..height).map(move |y| &self.cells[y * self.width + x])
+    }
+
+    /// Iterates over the columns of the grid, from the left.
+    pub fn columns(&self) -> impl Iterator<Item = impl Iterator<Item = &T>> {
+        (0..self.width).map(move |x| self.column(x))
+    }
+
+    /// Iterates over all the values along with their coordinates, in
+    /// row-major order.
+    pub fn iter(&self) -> impl Iterator<Item = ((usize, usize), &T)> {
+        let width = self.width;
+        self.cells
+            .iter()
+            .enumerate()
+            .map(move |(i, v)| ((i % width, i / width), v))
+    }
+
+    /// Returns a copy of a rectangular region of the grid. Region is clipped
+    /// to the grid bounds, a region starting out of bounds is empty.
+    pub fn region(&self, x: usize, y: usize, width: usize, height: usize) -> Self {
+        if x >= self.width || y >= self.height {
+            return Self {
+                width: 0,
+                height: 0,
+                cells: Vec::new(),
+            };
+        }
+        let x_end = x.saturating_add(width).min(self.width);
+        let y_end = y.saturating_add(height).min(self.height);
+        let width = x_end.saturating_sub(x);
+        let height = y_end.saturating_sub(y);
+        let mut cells = Vec::with_capacity(width * height);
+        for row_y in y..y_end {
+            let start = row_y * self.width;
+            cells.extend_from_slice(&self.cells[start + x..start + x_end]);
+        }
+        Self {
+            width,
+            height,
+            cells,
+        }
+    }
+
+    /// Copies the other grid into this one, with the top-left corner placed
+    /// at the given coordinates. Values falling outside the bounds are
+    /// ignored.
+    pub fn paste(&mut self, x: usize, y: usize, other: &Grid<T>) {
+        for (row_y, row) in other.rows().enumerate() {
+            for (row_x, value) in row.iter().enumerate() {
+                let (cell_x, cell_y) = match (x.checked_add(row_x), y.checked_add(row_y)) {
+                    (Some(cell_x), Some(cell_y)) => (cell_x, cell_y),
+                    _ => continue,
+                };
+                if let Some(cell) = self.get_mut(cell_x, cell_y) {
+                    *cell = value.clone();
+                }
+            }
+        }
+    }
+
+    /// Converts the grid into a list of rows.
+    pub fn into_rows(self) -> Vec<Vec<T>> {
+        if self.width == 0 {
+            return vec![Vec::new(); self.height];
+        }
+        self.cells
+            .chunks(self.width)
+            .map(|row| row.to_vec())
+            .collect()
+    }
+}
+
+impl<T: GridValue> From<Grid<T>> for Var {
+    fn from(grid: Grid<T>) -> Self {
+        Var::Grid(
+            grid.into_rows()
+                .into_iter()
+                .map(|row| row.into_iter().map(|v| v.into_var()).collect())
+                .collect(),
+        )
+    }
+}
+
+impl Var {
+    /// Converts the grid var into a typed grid.
+    pub fn to_grid<T: GridValue>(&self) -> Result<Grid<T>> {
+        match self {
+            Var::Grid(rows) => Grid::from_rows(
+                rows.iter()
+                    .map(|row| row.iter().map(T::from_var).collect())
+                    .collect::<Result<Vec<Vec<T>>>>()?,
+            ),
+            _ => Err(Error::InvalidVarType(format!(
+                "expected {}, got {}",
+                T::GRID_TYPE.to_str(),
+                self.get_type().to_str()
+            ))),
+        }
+    }
+}
+
+#[test]
+fn region_is_clipped_to_bounds() {
+    let grid = Grid::<Int>::from_rows(vec![vec![1, 2, 3], vec![4, 5, 6]]).unwrap();
+    let region = grid.region(1, 0, 5, usize::MAX);
+    assert_eq!(region.into_rows(), vec![vec![2, 3], vec![5, 6]]);
+
+    let outside = grid.region(3, 0, 2, 2);
+    assert_eq!((outside.width(), outside.height()), (0, 0));
+    let outside = grid.region(0, 7, 2, 2);
+    assert_eq!((outside.width(), outside.height()), (0, 0));
+}
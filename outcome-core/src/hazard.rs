@@ -0,0 +1,179 @@
+//! Declarative random event generators.
+//!
+//! Hazards are declared in the scenario manifest. Each step every hazard
+//! is sampled according to its probability distribution, and when it
+//! fires, its event is added to the global event queue.
+//!
+//! ```toml
+//! [scenario]
+//! seed = 1234
+//!
+//! [hazards.earthquake]
+//! type = "poisson"
+//! rate = 0.002
+//!
+//! [hazards.flood]
+//! event = "flood_warning"
+//! type = "normal"
+//! mean = 10.0
+//! std_dev = 4.0
+//! threshold = 20.0
+//! modulate = "climate:climate:float:rain_factor"
+//! start = 100
+//! ```
+//!
+//! Optional `modulate` address points to a var scaling the distribution,
+//! multiplying the probability, rate or mean respectively. Modulated
+//! hazards are only sampled with a local simulation, as the var value is
+//! not available to the central authority of a distributed simulation.
+//!
+//! # Reproducibility
+//!
+//! Samples are derived from the scenario seed, the current step and the
+//! hazard name only. Running the same scenario with the same seed always
+//! results in the same hazards firing at the same steps, regardless of
+//! snapshots being taken and loaded in between.
+
+use std::hash::Hasher;
+
+use fnv::FnvHasher;
+
+use crate::address::Address;
+use crate::model::HazardModel;
+use crate::EventName;
+
+/// Probability distribution deciding whether a hazard fires.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Distribution {
+    /// Fires with the given probability each step
+    Bernoulli { p: f64 },
+    /// Occurrences follow a Poisson process with the given mean number of
+    /// occurrences per step, fires if there's at least one occurrence
+    Poisson { rate: f64 },
+    /// Samples a normally distributed value each step, fires if the value
+    /// exceeds the threshold
+    Normal {
+        mean: f64,
+        std_dev: f64,
+        threshold: f64,
+    },
+}
+
+impl HazardModel {
+    /// Samples the hazard for the given step. Modulation is the value of
+    /// the modulating var, if the hazard declares one.
+    pub fn sample(&self, seed: u64, clock: usize, modulation: Option<f64>) -> bool {
+        if self.start.map_or(false, |s| clock < s) || self.end.map_or(false, |e| clock > e) {
+            return false;
+        }
+        let m = modulation.unwrap_or(1.);
+        let stream = name_hash(&self.name);
+        let u = uniform(seed, clock as u64, stream);
+        match self.distribution {
+            Distribution::Bernoulli { p } => u < p * m,
+            Distribution::Poisson { rate } => u < 1. - (-rate * m).exp(),
+            Distribution::Normal {
+                mean,
+                std_dev,
+                threshold,
+            } => {
+                // box-muller transform, using a second independent sample
+                let u2 = uniform(seed, clock as u64, stream.rotate_left(32));
+                let z = (-2. * (1. - u).ln()).sqrt() * (2. * std::f64::consts::PI * u2).cos();
+                mean * m + z * std_dev > threshold
+            }
+        }
+    }
+}
+
+/// Samples all the hazards for the given step, returning events of the
+/// hazards that fired. Modulated hazards for which `modulation` returns
+/// `None` are skipped.
+pub fn sample_hazards(
+    hazards: &[HazardModel],
+    seed: u64,
+    clock: usize,
+    modulation: impl Fn(&Address) -> Option<f64>,
+) -> Vec<EventName> {
+    let mut events = Vec::new();
+    for hazard in hazards {
+        let m = match &hazard.modulate {
+            Some(addr) => match modulation(addr) {
+                Some(m) => Some(m),
+                None => continue,
+            },
+            None => None,
+        };
+        if hazard.sample(seed, clock, m) && !events.contains(&hazard.event) {
+            events.push(hazard.event.clone());
+        }
+    }
+    events
+}
+
+/// Stable hash of the hazard name, used to give each hazard an independent
+/// stream of samples.
+fn name_hash(name: &str) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(name.as_bytes());
+    hasher.finish()
+}
+
+/// Returns a uniformly distributed sample within `0..1`, derived from the
+/// inputs using the splitmix64 mixing function.
+fn uniform(seed: u64, clock: u64, stream: u64) -> f64 {
+    let mut x = seed
+        ^ clock.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ stream.wrapping_mul(0xd1b5_4a32_d192_ed03);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^= x >> 31;
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[test]
+fn hazards_fire_reproducibly_within_window() {
+    use std::str::FromStr;
+
+    let hazard = |name: &str, distribution, modulate: Option<&str>| HazardModel {
+        name: name.to_string(),
+        event: crate::string::new_truncate(name),
+        distribution,
+        modulate: modulate.map(|a| Address::from_str(a).unwrap()),
+        start: Some(10),
+        end: Some(20),
+    };
+
+    let always = hazard("always", Distribution::Bernoulli { p: 1. }, None);
+    assert!(!always.sample(1, 9, None));
+    assert!(always.sample(1, 10, None));
+    assert!(always.sample(1, 20, None));
+    assert!(!always.sample(1, 21, None));
+    assert!(!always.sample(1, 15, Some(0.)));
+
+    let mut sometimes = hazard("sometimes", Distribution::Bernoulli { p: 0.3 }, None);
+    sometimes.end = None;
+    let fired = (10..10_010)
+        .filter(|clock| sometimes.sample(1, *clock, None))
+        .collect::<Vec<_>>();
+    assert!(fired.len() > 2_500 && fired.len() < 3_500);
+    let again = (10..10_010)
+        .filter(|clock| sometimes.sample(1, *clock, None))
+        .collect::<Vec<_>>();
+    assert_eq!(fired, again);
+
+    let hazards = vec![
+        always,
+        hazard(
+            "modulated",
+            Distribution::Bernoulli { p: 1. },
+            Some("climate:climate:float:rain"),
+        ),
+    ];
+    assert_eq!(
+        sample_hazards(&hazards, 1, 15, |_| None),
+        vec![crate::string::new_truncate("always")]
+    );
+    assert_eq!(sample_hazards(&hazards, 1, 15, |_| Some(1.)).len(), 2);
+}
@@ -0,0 +1,123 @@
+//! Common interface over different simulation backends.
+//!
+//! [`SimInterface`] allows writing code once and running it against either
+//! a local [`Sim`] or one of the distributed representations provided by
+//! `outcome-net`, such as an organizer backed by [`SimCentral`] or a worker
+//! backed by [`SimNode`].
+//!
+//! Networked implementations may need to communicate with other parts of
+//! the simulation to fulfill a request, which is why all the methods take
+//! a mutable reference and return owned values.
+//!
+//! [`Sim`]: crate::Sim
+//! [`SimCentral`]: crate::distr::SimCentral
+//! [`SimNode`]: crate::distr::SimNode
+
+use crate::address::Address;
+use crate::{CompName, EntityId, EntityName, EventName, PrefabName, Result, Sim, Var};
+
+/// Simulation operations available regardless of the backend.
+pub trait SimInterface {
+    /// Gets the current value of the simulation clock.
+    fn get_clock(&mut self) -> Result<usize>;
+
+    /// Gets a copy of the var at the address.
+    fn get_var(&mut self, addr: &Address) -> Result<Var>;
+
+    /// Overwrites the var at the address.
+    fn set_var(&mut self, addr: &Address, var: Var) -> Result<()>;
+
+    /// Sets the var at the address using a string value as input, parsed
+    /// based on the var type of the address.
+    fn set_from_string(&mut self, addr: &Address, val: &str) -> Result<()> {
+        let var = Var::from_str(val, Some(addr.var_type))?;
+        self.set_var(addr, var)
+    }
+
+    /// Gets ids of all the entities that have all of the listed components.
+    fn get_entities_of_type(&mut self, type_: &[CompName]) -> Result<Vec<EntityId>>;
+
+    /// Spawns a new entity, optionally using a prefab and a name.
+    fn spawn_entity(
+        &mut self,
+        prefab: Option<PrefabName>,
+        name: Option<EntityName>,
+    ) -> Result<EntityId>;
+
+    /// Adds an event to the global event queue, to be processed during the
+    /// next step.
+    fn add_event(&mut self, name: EventName) -> Result<()>;
+
+    /// Processes a single simulation step.
+    fn step(&mut self) -> Result<()>;
+}
+
+impl SimInterface for Sim {
+    fn get_clock(&mut self) -> Result<usize> {
+        Ok(self.clock)
+    }
+
+    fn get_var(&mut self, addr: &Address) -> Result<Var> {
+        Sim::get_var(self, addr).map(|var| var.clone())
+    }
+
+    fn set_var(&mut self, addr: &Address, var: Var) -> Result<()> {
+        self.validate_var(addr, &var)?;
+        *self.get_var_mut(addr)? = var;
+        Ok(())
+    }
+
+    fn get_entities_of_type(&mut self, type_: &[CompName]) -> Result<Vec<EntityId>> {
+        let mut ids = self
+            .entities
+            .iter()
+            .filter(|(_, e)| type_.iter().all(|c| e.components.contains(c)))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    fn spawn_entity(
+        &mut self,
+        prefab: Option<PrefabName>,
+        name: Option<EntityName>,
+    ) -> Result<EntityId> {
+        Sim::spawn_entity(self, prefab.as_ref(), name)
+    }
+
+    fn add_event(&mut self, name: EventName) -> Result<()> {
+        if self.model.get_event(&name).is_none() {
+            return Sim::add_event(self, name);
+        }
+        if !self.event_queue.contains(&name) {
+            self.event_queue.push(name);
+        }
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<()> {
+        Sim::step(self)
+    }
+}
+
+#[test]
+fn set_var_is_validated() {
+    use std::str::FromStr;
+    let mut sim = crate::SimModelBuilder::new()
+        .component("pos", |c| c.var("int:x", Var::Int(0)))
+        .prefab("thing", &["pos"])
+        .build_sim()
+        .unwrap();
+    let id = sim
+        .spawn_entity(Some(&crate::string::new_truncate("thing")), None)
+        .unwrap();
+    let addr = Address::from_str(&format!("{}:pos:int:x", id)).unwrap();
+    let interface: &mut dyn SimInterface = &mut sim;
+    assert!(interface.set_var(&addr, Var::Float(1.)).is_err());
+    assert!(interface.set_from_string(&addr, "2").is_ok());
+    assert_eq!(interface.get_var(&addr).unwrap(), Var::Int(2));
+
+    let grid = Address::from_str(&format!("{}:pos:grid_int:x", id)).unwrap();
+    assert!(interface.set_from_string(&grid, "1").is_err());
+}
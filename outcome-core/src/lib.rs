@@ -35,6 +35,17 @@
 //!
 //! See crate's `Cargo.toml` for a full listing of available features.
 //!
+//! ## API stability
+//!
+//! Items available through the [`prelude`] are considered the dependable
+//! surface of the library. Simulation state is best accessed using the
+//! provided methods, such as [`Sim::iter_entities`] and [`Sim::get_var`],
+//! rather than through the raw fields backing it.
+//!
+//! Raw internals backing the state are not covered by semver guarantees
+//! and may change without a major version bump. They're only made public
+//! with the `unstable` feature enabled.
+//!
 //! ## Example
 //!
 //! Here's a very simple example of how the library can be used inside your
@@ -78,19 +89,35 @@ extern crate fasteval;
 // reexports
 pub use address::Address;
 pub use error::Result;
-pub use model::SimModel;
+pub use interface::SimInterface;
+pub use model::{SimModel, SimModelBuilder};
 pub use query::{Query, QueryProduct};
 pub use sim::Sim;
 pub use var::{Var, VarType};
 
 pub mod address;
+pub mod custom_var;
 pub mod distr;
 pub mod entity;
 pub mod error;
+pub mod geo;
+pub mod graph;
+pub mod grid;
+pub mod hazard;
+pub mod interface;
 pub mod model;
+pub mod order;
+#[cfg(feature = "pathfinding")]
+pub mod path;
+pub mod prelude;
+pub mod rng;
+pub mod scheduler;
 pub mod sim;
 pub mod snapshot;
 pub mod string;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod unit;
 pub mod util;
 pub mod var;
 
@@ -169,6 +196,8 @@ const MODULE_MANIFEST_FILE: &str = "mod.toml";
 pub const SCENARIOS_DIR_NAME: &str = "scenarios";
 /// Name of the module directory within the scenario file tree.
 pub const SNAPSHOTS_DIR_NAME: &str = "snapshots";
+/// Name of the directory recordings are written to by the server.
+pub const RECORDINGS_DIR_NAME: &str = "recordings";
 
 /// Name of the module directory within the scenario file tree.
 pub const MODULES_DIR_NAME: &str = "mods";
@@ -184,6 +213,8 @@ const DEFAULT_INACTIVE_STATE: &str = "idle";
 const DEFAULT_STEP_EVENT: &str = "step";
 #[cfg(feature = "machine")]
 const DEFAULT_INIT_EVENT: &str = "init";
+#[cfg(feature = "machine")]
+const DEFAULT_BUDGET_EXCEEDED_EVENT: &str = "budget_exceeded";
 
 /// Floating point numer type used throughout the library.
 #[cfg(feature = "big_nums")]
@@ -0,0 +1,352 @@
+//! Behavior trees as an alternative to state-based component logic.
+//!
+//! A behavior tree is declared on the component in structured data files,
+//! and is ticked each time the component is triggered, in place of
+//! executing the current state. All nodes complete within a single tick.
+//!
+//! ```yaml
+//! components:
+//!   guard:
+//!     triggers: [step]
+//!     vars:
+//!       bool:enemy_visible: false
+//!     behavior:
+//!       selector:
+//!         - sequence:
+//!             - condition: bool:enemy_visible
+//!             - procedure: attack
+//!         - command: set int:patrol_steps 1
+//! ```
+//!
+//! # Nodes
+//!
+//! - `sequence` runs children in order until one of them fails
+//! - `selector` runs children in order until one of them succeeds
+//! - `invert` inverts the result of the child
+//! - `succeed` runs the child and always succeeds
+//! - `repeat` runs the child the given number of times, failing as soon
+//!   as the child fails
+//! - `condition` succeeds if the local var at the given address is truthy
+//! - `procedure` runs a procedure or state from the component's logic
+//! - `command` runs a single script command
+//!
+//! Procedures and commands fail if executing them produced an error.
+//!
+//! Command nodes are compiled into the component's logic when the model
+//! is loaded, which requires the `machine_script` feature.
+
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use crate::address::ShortLocalAddress;
+use crate::entity::{EntityNonSer, Storage};
+use crate::model::{LogicModel, SimModel};
+use crate::rng::EntityRng;
+use crate::{CompName, EntityId, StringId};
+
+use super::budget::EntityBudget;
+use super::cmd::{CentralRemoteCommand, ExtCommand};
+use super::error::{Error, ErrorKind, Result};
+use super::{ExecutionContext, LocationInfo};
+
+#[cfg(feature = "machine_dynlib")]
+use super::Libraries;
+
+/// Single node of a behavior tree.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BehaviorNode {
+    Sequence(Vec<BehaviorNode>),
+    Selector(Vec<BehaviorNode>),
+    Invert(Box<BehaviorNode>),
+    Succeed(Box<BehaviorNode>),
+    Repeat {
+        times: u32,
+        node: Box<BehaviorNode>,
+    },
+    /// Local var address, e.g. `bool:ready` or `other_comp:bool:ready`
+    Condition(String),
+    /// Name of a procedure or state defined within the component's logic
+    Procedure(String),
+    /// Script command, compiled when loading the model
+    Command(String),
+    /// Command compiled into the component's logic, as start and end line
+    Lines(usize, usize),
+}
+
+/// Outcome of ticking a node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BehaviorStatus {
+    Success,
+    Failure,
+}
+
+impl BehaviorNode {
+    /// Compiles all command nodes into commands appended to the logic,
+    /// replacing them with references to the appended lines.
+    #[cfg(feature = "machine_script")]
+    pub fn compile(&mut self, logic: &mut LogicModel, comp_name: &CompName) -> Result<()> {
+        use super::cmd::Command;
+        use super::script::{parser, InstructionType};
+        use super::CommandPrototype;
+
+        match self {
+            BehaviorNode::Sequence(nodes) | BehaviorNode::Selector(nodes) => {
+                for node in nodes {
+                    node.compile(logic, comp_name)?;
+                }
+            }
+            BehaviorNode::Invert(node)
+            | BehaviorNode::Succeed(node)
+            | BehaviorNode::Repeat { node, .. } => node.compile(logic, comp_name)?,
+            BehaviorNode::Command(line) => {
+                let mut location = LocationInfo::empty();
+                location.comp_name = Some(comp_name.clone());
+                let prototypes = parser::parse_lines(line, location.clone())?
+                    .into_iter()
+                    .filter_map(|instruction| match instruction.type_ {
+                        InstructionType::Command(c) => Some(c),
+                        _ => None,
+                    })
+                    .collect::<Vec<CommandPrototype>>();
+                let start = logic.commands.len();
+                for (n, prototype) in prototypes.iter().enumerate() {
+                    location.line = Some(n);
+                    let mut command = Command::from_prototype(prototype, &location, &prototypes)?;
+                    // line numbers within commands need to match the
+                    // combined collection
+                    command.apply_offset(start);
+                    location.line = Some(start + n);
+                    logic.commands.push(command);
+                    logic.cmd_location_map.push(location.clone());
+                }
+                *self = BehaviorNode::Lines(start, logic.commands.len());
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+/// Everything needed for executing component logic on the entity level.
+pub(crate) struct TickContext<'a> {
+    pub logic: &'a LogicModel,
+    pub storage: &'a mut Storage,
+    pub insta: &'a mut EntityNonSer,
+    pub rng: &'a mut EntityRng,
+    pub comp_state: &'a mut StringId,
+    pub ent_uid: &'a EntityId,
+    pub comp_uid: &'a CompName,
+    pub model: &'a SimModel,
+    pub ext_cmds: &'a Arc<Mutex<Vec<(ExecutionContext, ExtCommand)>>>,
+    pub central_ext_cmds: &'a Arc<Mutex<Vec<(ExecutionContext, CentralRemoteCommand)>>>,
+    pub errors: &'a Arc<Mutex<Vec<(ExecutionContext, Error)>>>,
+    pub budget: &'a mut EntityBudget,
+    pub strict: bool,
+    #[cfg(feature = "machine_dynlib")]
+    pub libs: &'a Libraries,
+}
+
+impl<'a> TickContext<'a> {
+    /// Ticks the node, returning its status.
+    pub fn tick(&mut self, node: &BehaviorNode) -> Result<BehaviorStatus> {
+        let status = match node {
+            BehaviorNode::Sequence(nodes) => {
+                for node in nodes {
+                    if self.tick(node)? == BehaviorStatus::Failure {
+                        return Ok(BehaviorStatus::Failure);
+                    }
+                }
+                BehaviorStatus::Success
+            }
+            BehaviorNode::Selector(nodes) => {
+                for node in nodes {
+                    if self.tick(node)? == BehaviorStatus::Success {
+                        return Ok(BehaviorStatus::Success);
+                    }
+                }
+                BehaviorStatus::Failure
+            }
+            BehaviorNode::Invert(node) => match self.tick(node)? {
+                BehaviorStatus::Success => BehaviorStatus::Failure,
+                BehaviorStatus::Failure => BehaviorStatus::Success,
+            },
+            BehaviorNode::Succeed(node) => {
+                self.tick(node)?;
+                BehaviorStatus::Success
+            }
+            BehaviorNode::Repeat { times, node } => {
+                for _ in 0..*times {
+                    if self.tick(node)? == BehaviorStatus::Failure {
+                        return Ok(BehaviorStatus::Failure);
+                    }
+                }
+                BehaviorStatus::Success
+            }
+            BehaviorNode::Condition(address) => {
+                let addr = ShortLocalAddress::from_str(address).map_err(|e| {
+                    Error::new(
+                        LocationInfo::empty(),
+                        ErrorKind::InvalidAddress(e.to_string()),
+                    )
+                })?;
+                let comp = addr.comp.as_ref().unwrap_or(self.comp_uid);
+                match self.storage.get_var(&(comp.clone(), addr.var_name.clone())) {
+                    Ok(var) if var.to_bool() => BehaviorStatus::Success,
+                    _ => BehaviorStatus::Failure,
+                }
+            }
+            BehaviorNode::Procedure(name) => {
+                let lines = self
+                    .logic
+                    .procedures
+                    .get(name.as_str())
+                    .or_else(|| self.logic.states.get(name.as_str()))
+                    .cloned();
+                match lines {
+                    Some((start, end)) => self.execute_lines(start, end)?,
+                    None => {
+                        warn!(
+                            "behavior of component \"{}\": procedure not found: {}",
+                            self.comp_uid, name
+                        );
+                        BehaviorStatus::Failure
+                    }
+                }
+            }
+            BehaviorNode::Lines(start, end) => self.execute_lines(*start, *end)?,
+            BehaviorNode::Command(line) => {
+                warn!(
+                    "behavior of component \"{}\": command was not compiled: {}",
+                    self.comp_uid, line
+                );
+                BehaviorStatus::Failure
+            }
+        };
+        Ok(status)
+    }
+
+    /// Executes a range of the logic's commands, failing if any errors
+    /// were reported during execution.
+    ///
+    /// Errors are first collected locally, as the shared collection also
+    /// receives errors from entities processed on other threads, and then
+    /// merged into the shared one.
+    pub(crate) fn execute_lines(&mut self, start: usize, end: usize) -> Result<BehaviorStatus> {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let result = super::exec::execute_loc(
+            &self.logic.commands,
+            &self.logic.cmd_location_map,
+            self.storage,
+            self.insta,
+            self.rng,
+            self.comp_state,
+            self.ent_uid,
+            self.comp_uid,
+            self.model,
+            self.ext_cmds,
+            self.central_ext_cmds,
+            &errors,
+            self.budget,
+            self.strict,
+            Some(start),
+            Some(end),
+            #[cfg(feature = "machine_dynlib")]
+            self.libs,
+        );
+        let mut errors = std::mem::take(&mut *errors.lock().unwrap());
+        let failed = !errors.is_empty();
+        if failed {
+            self.errors.lock().unwrap().append(&mut errors);
+        }
+        result?;
+        if failed {
+            Ok(BehaviorStatus::Failure)
+        } else {
+            Ok(BehaviorStatus::Success)
+        }
+    }
+}
+
+#[test]
+fn nodes_are_ticked_by_their_semantics() {
+    use super::budget::{ExecBudget, StepBudget};
+    use crate::string::new_truncate;
+    use crate::Var;
+    use BehaviorNode::*;
+
+    let model = crate::SimModelBuilder::new().build().unwrap();
+    let logic = LogicModel::default();
+    let mut storage = Storage::default();
+    storage.insert(
+        (new_truncate("guard"), new_truncate("ready")),
+        Var::Bool(true),
+    );
+    storage.insert(
+        (new_truncate("eyes"), new_truncate("open")),
+        Var::Bool(false),
+    );
+    let mut insta = EntityNonSer::default();
+    let mut rng = EntityRng::new(0, 1);
+    let mut comp_state = new_truncate("idle");
+    let ext_cmds = Arc::new(Mutex::new(Vec::new()));
+    let central_ext_cmds = Arc::new(Mutex::new(Vec::new()));
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let budget_config = ExecBudget::default();
+    let step_budget = StepBudget::new(&budget_config);
+    let mut budget = step_budget.entity(1);
+    #[cfg(feature = "machine_dynlib")]
+    let libs = super::Libraries::new();
+    let mut ctx = TickContext {
+        logic: &logic,
+        storage: &mut storage,
+        insta: &mut insta,
+        rng: &mut rng,
+        comp_state: &mut comp_state,
+        ent_uid: &1,
+        comp_uid: &new_truncate("guard"),
+        model: &model,
+        ext_cmds: &ext_cmds,
+        central_ext_cmds: &central_ext_cmds,
+        errors: &errors,
+        budget: &mut budget,
+        strict: false,
+        #[cfg(feature = "machine_dynlib")]
+        libs: &libs,
+    };
+
+    let ready = || Condition("bool:ready".to_string());
+    let open = || Condition("eyes:bool:open".to_string());
+    let missing = || Procedure("attack".to_string());
+
+    assert_eq!(
+        ctx.tick(&Sequence(vec![ready(), Invert(Box::new(open()))]))
+            .unwrap(),
+        BehaviorStatus::Success
+    );
+    assert_eq!(
+        ctx.tick(&Selector(vec![open(), missing()])).unwrap(),
+        BehaviorStatus::Failure
+    );
+    assert_eq!(
+        ctx.tick(&Succeed(Box::new(missing()))).unwrap(),
+        BehaviorStatus::Success
+    );
+    assert_eq!(
+        ctx.tick(&Repeat {
+            times: 0,
+            node: Box::new(open())
+        })
+        .unwrap(),
+        BehaviorStatus::Success
+    );
+    assert_eq!(
+        ctx.tick(&Repeat {
+            times: 2,
+            node: Box::new(open())
+        })
+        .unwrap(),
+        BehaviorStatus::Failure
+    );
+    assert!(ctx.tick(&Condition("not an address".to_string())).is_err());
+}
@@ -0,0 +1,226 @@
+//! Execution budgets guarding against runaway logic.
+//!
+//! Budgets are declared in the scenario manifest under the `[budget]` table
+//! and as such are part of the model, shared by all the nodes in
+//! a distributed setting.
+//!
+//! ```toml
+//! [budget]
+//! entity_cmds = 10000
+//! entity_micros = 500
+//! step_cmds = 1000000
+//! step_millis = 100
+//! ```
+//!
+//! Entity exceeding any of the limits has its execution suspended until the
+//! next step. A `budget_exceeded` event is invoked so that it can be acted
+//! upon from within the simulation.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::watchdog::{EntityProgress, Progress};
+use crate::{CompName, EntityId};
+
+/// Configurable limits on the amount of logic executed within a single step.
+///
+/// All limits are optional, missing values mean no limit is enforced.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExecBudget {
+    /// Max number of commands executed for a single entity
+    #[serde(default)]
+    pub entity_cmds: Option<usize>,
+    /// Max wall-time spent executing a single entity, in microseconds
+    #[serde(default)]
+    pub entity_micros: Option<u64>,
+    /// Max number of commands executed across all entities
+    #[serde(default)]
+    pub step_cmds: Option<usize>,
+    /// Max wall-time spent on the local execution phase, in milliseconds
+    #[serde(default)]
+    pub step_millis: Option<u64>,
+}
+
+impl ExecBudget {
+    /// Checks whether any of the limits are set.
+    pub fn is_limited(&self) -> bool {
+        self.entity_cmds.is_some()
+            || self.entity_micros.is_some()
+            || self.step_cmds.is_some()
+            || self.step_millis.is_some()
+    }
+}
+
+/// Budget tracker shared between all entities processed during a single step.
+pub(crate) struct StepBudget<'a> {
+    config: &'a ExecBudget,
+    started: Instant,
+    cmds: AtomicUsize,
+    exhausted: AtomicBool,
+    /// Progress tracked for the watchdog, if enabled
+    progress: Option<&'a Progress>,
+}
+
+impl<'a> StepBudget<'a> {
+    pub fn new(config: &'a ExecBudget) -> Self {
+        Self {
+            config,
+            started: Instant::now(),
+            cmds: AtomicUsize::new(0),
+            exhausted: AtomicBool::new(false),
+            progress: None,
+        }
+    }
+
+    /// Creates a new tracker reporting entity progress to the watchdog.
+    pub fn with_progress(config: &'a ExecBudget, progress: &'a Progress) -> Self {
+        Self {
+            progress: Some(progress),
+            ..Self::new(config)
+        }
+    }
+
+    /// Creates a new tracker for a single entity.
+    pub fn entity(&self, ent: EntityId) -> EntityBudget {
+        EntityBudget {
+            step: self,
+            ent,
+            progress: self.progress.map(|p| p.register(ent)),
+            started: Instant::now(),
+            cmds: 0,
+            exceeded: false,
+        }
+    }
+
+    /// Checks whether the step-wide budget was already used up, or the step
+    /// was aborted by the watchdog.
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted.load(Ordering::Relaxed)
+            || self.progress.map_or(false, |p| p.is_step_aborted())
+    }
+}
+
+/// Budget tracker for a single entity.
+pub(crate) struct EntityBudget<'a> {
+    step: &'a StepBudget<'a>,
+    ent: EntityId,
+    progress: Option<Arc<EntityProgress>>,
+    started: Instant,
+    cmds: usize,
+    exceeded: bool,
+}
+
+impl<'a> EntityBudget<'a> {
+    /// Marks the start of execution of a component.
+    pub fn enter(&self, comp: &CompName) {
+        if let Some(progress) = &self.progress {
+            progress.enter(comp);
+        }
+    }
+
+    /// Accounts for a single executed command, given its index. Returns the
+    /// reason if the budget was exceeded as a result.
+    pub fn consume(&mut self, cmd_n: usize) -> Option<String> {
+        if let Some(progress) = &self.progress {
+            progress.command(cmd_n);
+            // only the current component is aborted, the entity itself is
+            // not suspended
+            if progress.is_aborted() {
+                return Some("aborted by watchdog".to_string());
+            }
+        }
+        if self.step.is_exhausted() {
+            self.exceeded = true;
+            return Some("step budget exhausted".to_string());
+        }
+        let config = self.step.config;
+        if !config.is_limited() {
+            return None;
+        }
+        self.cmds += 1;
+        let step_cmds = self.step.cmds.fetch_add(1, Ordering::Relaxed) + 1;
+
+        let reason = if config.entity_cmds.map_or(false, |max| self.cmds > max) {
+            Some(format!("entity command limit ({}) exceeded", self.cmds - 1))
+        } else if config.entity_micros.map_or(false, |max| {
+            self.started.elapsed() > Duration::from_micros(max)
+        }) {
+            Some(format!(
+                "entity time limit ({}us) exceeded",
+                config.entity_micros.unwrap()
+            ))
+        } else if config.step_cmds.map_or(false, |max| step_cmds > max) {
+            self.step.exhausted.store(true, Ordering::Relaxed);
+            Some(format!("step command limit ({}) exceeded", step_cmds - 1))
+        } else if config.step_millis.map_or(false, |max| {
+            self.step.started.elapsed() > Duration::from_millis(max)
+        }) {
+            self.step.exhausted.store(true, Ordering::Relaxed);
+            Some(format!(
+                "step time limit ({}ms) exceeded",
+                config.step_millis.unwrap()
+            ))
+        } else {
+            None
+        };
+        if reason.is_some() {
+            self.exceeded = true;
+        }
+        reason
+    }
+
+    /// Checks whether the entity has already exceeded its budget.
+    pub fn is_exceeded(&self) -> bool {
+        self.exceeded || self.step.is_exhausted()
+    }
+
+    /// Returns the reason for skipping the entity if it was suspended
+    /// without ever exceeding its own budget, e.g. because other entities
+    /// used up the step-wide budget. Such skip is reported only once.
+    pub fn take_skip_reason(&mut self) -> Option<String> {
+        if self.exceeded || !self.step.is_exhausted() {
+            return None;
+        }
+        self.exceeded = true;
+        match self.step.progress.map_or(false, |p| p.is_step_aborted()) {
+            true => Some("aborted by watchdog".to_string()),
+            false => Some("step budget exhausted".to_string()),
+        }
+    }
+
+    /// Checks whether the watchdog requested aborting the component being
+    /// executed.
+    pub fn is_aborted(&self) -> bool {
+        self.progress.as_ref().map_or(false, |p| p.is_aborted())
+    }
+}
+
+impl<'a> Drop for EntityBudget<'a> {
+    fn drop(&mut self) {
+        if let Some(progress) = self.step.progress {
+            progress.unregister(&self.ent);
+        }
+    }
+}
+
+#[test]
+fn step_budget_skip_reported_once() {
+    let config = ExecBudget {
+        step_cmds: Some(1),
+        ..Default::default()
+    };
+    let step = StepBudget::new(&config);
+    let mut first = step.entity(0);
+    assert!(first.consume(0).is_none());
+    assert!(first.consume(1).is_some());
+    assert!(first.take_skip_reason().is_none());
+
+    let mut second = step.entity(1);
+    assert!(second.is_exceeded());
+    assert_eq!(
+        second.take_skip_reason().as_deref(),
+        Some("step budget exhausted")
+    );
+    assert!(second.take_skip_reason().is_none());
+}
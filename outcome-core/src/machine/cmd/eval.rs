@@ -17,7 +17,9 @@ use crate::address::{Address, LocalAddress, ShortLocalAddress};
 // use crate::component::Component;
 use crate::entity::{Entity, Storage};
 // use crate::error::Error;
+use crate::geo;
 use crate::model::{ComponentModel, SimModel};
+use crate::unit::Unit;
 use crate::{string, CompName, Sim, StringId, Var, VarType};
 
 use super::super::{CommandPrototype, Error, LocationInfo, Registry, RegistryTarget, Result};
@@ -26,6 +28,26 @@ use crate::machine::ErrorKind;
 use std::str::FromStr;
 
 /// Precompiles an evaluation and stores it
+///
+/// # Units
+///
+/// With the `--unit` option set, the expression is evaluated in the given
+/// unit. Args whose vars declare a unit of the same dimension are
+/// converted into it before evaluation, and the result is converted into
+/// the unit of the output var. In strict mode mismatched units are
+/// reported as errors instead. Args with units of other dimensions are
+/// left as they are.
+///
+/// # Geographic positions
+///
+/// Args pointing to `geo` vars are available in the expression as two
+/// separate values, with `_lat` and `_lon` suffixes. Distance in meters
+/// and initial bearing in degrees between two positions can be calculated
+/// with `geo_distance` and `geo_bearing` functions:
+///
+/// ```text
+/// eval "geo_distance(a_lat, a_lon, b_lat, b_lon)" a=geo:pos b=geo:target --out float:dist
+/// ```
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Eval {
     pub expr: fasteval::Instruction,
@@ -33,12 +55,15 @@ pub struct Eval {
     pub args: Vec<(StringId, ShortLocalAddress)>,
     // pub arg0: Option<(ShortString, RegistryTarget)>,
     pub out: Option<ShortLocalAddress>,
+    /// Unit the expression is evaluated in
+    pub unit: Option<Unit>,
 }
 
 impl Eval {
     pub fn new(args: Vec<String>) -> Result<Command> {
         let matches = getopts::Options::new()
             .optopt("o", "out", "", "")
+            .optopt("u", "unit", "", "")
             .parse(&args)?;
 
         let mut slab = fasteval::Slab::new();
@@ -66,11 +91,18 @@ impl Eval {
             }
         }
 
+        let unit = matches
+            .opt_str("unit")
+            .map(|s| Unit::from_str(&s))
+            .transpose()
+            .map_err(|e| Error::new(LocationInfo::empty(), ErrorKind::CoreError(e.to_string())))?;
+
         Ok(Command::Eval(Eval {
             expr: compiled,
             slab,
             args: eval_args,
             out,
+            unit,
         }))
     }
     pub fn execute_loc(
@@ -78,12 +110,19 @@ impl Eval {
         storage: &mut Storage,
         comp_name: &CompName,
         registry: &mut Registry,
+        sim_model: &SimModel,
+        strict: bool,
         location: &LocationInfo,
     ) -> CommandResult {
         let mut ns = fasteval::StringToF64Namespace::new();
         // let mut map = BTreeMap::new();
         for (arg_name, arg_addr) in &self.args {
             let val = match storage.get_var(&arg_addr.storage_index_using(comp_name.clone())) {
+                Ok(Var::Geo(pos)) => {
+                    ns.insert(format!("{}_lat", arg_name), pos.lat);
+                    ns.insert(format!("{}_lon", arg_name), pos.lon);
+                    continue;
+                }
                 Ok(v) => v.to_float(),
                 Err(e) => {
                     return CommandResult::Err(Error::new(
@@ -93,22 +132,66 @@ impl Eval {
                 }
             };
             // println!("position:x value: {}", xval);
-            ns.insert(arg_name.to_string(), val as f64);
+            let val = match self.from_var_unit(val as f64, arg_addr, comp_name, sim_model, strict) {
+                Ok(v) => v,
+                Err(e) => return CommandResult::Err(Error::new(location.clone(), e)),
+            };
+            ns.insert(arg_name.to_string(), val);
         }
 
+        // args are looked up by name, geo helpers are exposed as functions
+        let mut bad_call = None;
+        let mut cb = |name: &str, args: Vec<f64>| -> Option<f64> {
+            match (name, args.as_slice()) {
+                ("geo_distance", [lat1, lon1, lat2, lon2]) => {
+                    Some(geo::distance(*lat1, *lon1, *lat2, *lon2))
+                }
+                ("geo_bearing", [lat1, lon1, lat2, lon2]) => {
+                    Some(geo::bearing(*lat1, *lon1, *lat2, *lon2))
+                }
+                ("geo_distance", _) | ("geo_bearing", _) => {
+                    bad_call = Some(format!(
+                        "{} takes 4 args (lat1, lon1, lat2, lon2), got {}",
+                        name,
+                        args.len()
+                    ));
+                    None
+                }
+                (_, []) => ns.get(name).copied(),
+                _ => None,
+            }
+        };
+
         // let val = fasteval::ez_eval(&self.expr, &mut ns).unwrap();
-        let val = self.expr.eval(&self.slab, &mut ns).unwrap();
+        let val = match self.expr.eval(&self.slab, &mut cb) {
+            Ok(v) => v,
+            Err(e) => {
+                let msg =
+                    bad_call.unwrap_or_else(|| format!("failed evaluating expression: {:?}", e));
+                return CommandResult::Err(Error::new(location.clone(), ErrorKind::CoreError(msg)));
+            }
+        };
         // let val = fasteval::eval_compiled!(self.expr, &self.slab, &mut ns);
         // println!("evaled val: {}", val);
 
         if let Some(out) = &self.out {
+            let val = match self.to_var_unit(val, out, comp_name, sim_model, strict) {
+                Ok(v) => v,
+                Err(e) => return CommandResult::Err(Error::new(location.clone(), e)),
+            };
             let mut target = storage
                 .get_var_mut(&out.storage_index_using(comp_name.clone()))
                 .unwrap();
             // *target = crate::Var::fr
             // let v = crate::Var::Float(val as crate::Float);
             // println!("newly created var::float: {:?}", v);
-            *target = Var::Float(val as crate::Float);
+            *target = match target {
+                // eval itself is float based, results are only rounded to
+                // the fixed point or decimal target
+                Var::Fixed(f) => Var::Fixed(crate::var::Fixed::from_float(val, f.frac)),
+                Var::Decimal(d) => Var::Decimal(crate::var::Decimal::from_float(val, d.scale)),
+                _ => Var::Float(val as crate::Float),
+            };
         }
 
         // match self.out {
@@ -119,6 +202,65 @@ impl Eval {
         // println!("eval result: {}", val);
         CommandResult::Continue
     }
+
+    /// Converts the value of the var into the unit of evaluation.
+    fn from_var_unit(
+        &self,
+        val: f64,
+        addr: &ShortLocalAddress,
+        comp_name: &CompName,
+        sim_model: &SimModel,
+        strict: bool,
+    ) -> std::result::Result<f64, ErrorKind> {
+        match (&self.unit, var_unit(addr, comp_name, sim_model)) {
+            (Some(unit), Some(var_unit)) => convert(val, &var_unit, unit, strict),
+            _ => Ok(val),
+        }
+    }
+
+    /// Converts the value from the unit of evaluation into the unit of
+    /// the var.
+    fn to_var_unit(
+        &self,
+        val: f64,
+        addr: &ShortLocalAddress,
+        comp_name: &CompName,
+        sim_model: &SimModel,
+        strict: bool,
+    ) -> std::result::Result<f64, ErrorKind> {
+        match (&self.unit, var_unit(addr, comp_name, sim_model)) {
+            (Some(unit), Some(var_unit)) => convert(val, unit, &var_unit, strict),
+            _ => Ok(val),
+        }
+    }
+}
+
+/// Gets the unit declared for the var in the model.
+fn var_unit(addr: &ShortLocalAddress, comp_name: &CompName, sim_model: &SimModel) -> Option<Unit> {
+    let comp = addr.comp.as_ref().unwrap_or(comp_name);
+    sim_model
+        .get_component(comp)
+        .ok()?
+        .vars
+        .iter()
+        .find(|v| v.name == addr.var_name)?
+        .unit()
+}
+
+/// Converts between units of the same dimension, leaving values in units
+/// of other dimensions unchanged.
+fn convert(val: f64, from: &Unit, to: &Unit, strict: bool) -> std::result::Result<f64, ErrorKind> {
+    if !from.is_compatible(to) || from.is_same(to) {
+        return Ok(val);
+    }
+    if strict {
+        return Err(ErrorKind::CoreError(format!(
+            "unit mismatch: {} vs {}",
+            from, to
+        )));
+    }
+    from.convert(val, to)
+        .map_err(|e| ErrorKind::CoreError(e.to_string()))
 }
 // #[derive(Debug, Clone, Serialize, Deserialize)]
 // pub struct EvalReg {
@@ -305,3 +447,47 @@ impl Eval {
 //         unimplemented!()
 //     }
 // }
+
+#[test]
+fn geo_functions_with_wrong_arg_count_are_errors() {
+    use crate::geo::GeoPosition;
+
+    let comp = string::new_truncate("nav");
+    let var = |name: &str| (comp.clone(), string::new_truncate(name));
+    let mut storage = Storage::default();
+    storage.insert(var("a"), Var::Geo(GeoPosition { lat: 0., lon: 0. }));
+    storage.insert(var("b"), Var::Geo(GeoPosition { lat: 0., lon: 1. }));
+    storage.insert(var("dist"), Var::Float(-1.));
+    let eval = |expr: &str| {
+        let args = vec![expr, "a=geo:a", "b=geo:b", "--out", "float:dist"];
+        match Eval::new(args.into_iter().map(|s| s.to_string()).collect()).unwrap() {
+            Command::Eval(eval) => eval,
+            _ => unreachable!(),
+        }
+    };
+    let mut execute = |eval: Eval| {
+        eval.execute_loc(
+            &mut storage,
+            &comp,
+            &mut Registry::new(),
+            &SimModel::default(),
+            false,
+            &LocationInfo::empty(),
+        )
+    };
+
+    match execute(eval("geo_distance(a_lat, a_lon, b_lat)")) {
+        CommandResult::Err(e) => assert!(e.to_string().contains("geo_distance takes 4 args")),
+        result => panic!("unexpected result: {:?}", result),
+    }
+    match execute(eval("geo_bearing(a_lat, a_lon, b_lat, b_lon, 1)")) {
+        CommandResult::Err(e) => assert!(e.to_string().contains("geo_bearing takes 4 args")),
+        result => panic!("unexpected result: {:?}", result),
+    }
+    assert!(matches!(
+        execute(eval("geo_distance(a_lat, a_lon, b_lat, b_lon)")),
+        CommandResult::Continue
+    ));
+    let dist = storage.get_var(&var("dist")).unwrap().to_float();
+    assert!(dist > 100_000. && dist < 120_000.);
+}
@@ -5,9 +5,10 @@ use crate::model::{ComponentModel, SimModel};
 use crate::{string, CompName, ShortString, StringId};
 
 use crate::machine::cmd::{CommandPrototype, CommandResult, LocationInfo};
-use crate::machine::error::{Error, ErrorKind};
+use crate::machine::error::{CallFrame, Error, ErrorKind};
 use crate::machine::{
     CallInfo, CallStackVec, IfElseCallInfo, IfElseMetaData, ProcedureCallInfo, Registry,
+    MAX_CALL_DEPTH,
 };
 
 /// Call a procedure by name.
@@ -45,16 +46,34 @@ impl Call {
             None => {
                 return CommandResult::Err(Error::new(
                     location.clone(),
-                    ErrorKind::InvalidCommandBody(format!(
-                    "call failed: procedure with the name `{}` doesn't exist in the current scope",
-                    &self.proc_name
-                )),
+                    ErrorKind::ProcedureNotFound(self.proc_name.to_string()),
                 ))
             }
         };
 
+        // guard against cycles in the call chain, direct or indirect
+        if call_stack.iter().any(|ci| match ci {
+            CallInfo::Procedure(pci) => pci.proc_name == self.proc_name,
+            _ => false,
+        }) {
+            return CommandResult::Err(Error::new(
+                location.clone(),
+                ErrorKind::RecursiveCall(
+                    self.proc_name.to_string(),
+                    self.call_chain(call_stack, comp_model, location),
+                ),
+            ));
+        }
+        if call_stack.len() >= MAX_CALL_DEPTH {
+            return CommandResult::Err(Error::new(
+                location.clone(),
+                ErrorKind::StackOverflow(self.call_chain(call_stack, comp_model, location)),
+            ));
+        }
+
         // push the call to the call stack
         call_stack.push(CallInfo::Procedure(ProcedureCallInfo {
+            proc_name: self.proc_name,
             call_line: line,
             start_line: *start_line,
             end_line: *end_line,
@@ -63,4 +82,145 @@ impl Call {
         // continue execution at the beginning of the called procedure
         CommandResult::JumpToLine(start_line + 1)
     }
+
+    /// Collects the chain of procedure calls leading up to and including
+    /// this call.
+    fn call_chain(
+        &self,
+        call_stack: &CallStackVec,
+        comp_model: &ComponentModel,
+        location: &LocationInfo,
+    ) -> Vec<CallFrame> {
+        let mut chain = call_stack
+            .iter()
+            .filter_map(|ci| match ci {
+                CallInfo::Procedure(pci) => Some(CallFrame {
+                    proc_name: pci.proc_name.to_string(),
+                    source_line: comp_model
+                        .logic
+                        .cmd_location_map
+                        .get(pci.call_line)
+                        .and_then(|l| l.source_line),
+                }),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        chain.push(CallFrame {
+            proc_name: self.proc_name.to_string(),
+            source_line: location.source_line,
+        });
+        chain
+    }
+}
+
+#[cfg(test)]
+fn test_model(comp_name: &CompName, procs: &[&str]) -> SimModel {
+    let mut sim_model = SimModel::default();
+    let mut comp_model = ComponentModel {
+        name: comp_name.clone(),
+        ..Default::default()
+    };
+    for (n, proc_name) in procs.iter().enumerate() {
+        comp_model
+            .logic
+            .procedures
+            .insert(proc_name.parse().unwrap(), (n * 10, n * 10 + 5));
+    }
+    sim_model.components.push(comp_model);
+    sim_model
+}
+
+#[test]
+fn direct_recursion_is_detected() {
+    let comp_name = string::new_truncate("comp");
+    let sim_model = test_model(&comp_name, &["recurse"]);
+    let call = Call {
+        proc_name: "recurse".parse().unwrap(),
+    };
+    let mut call_stack = CallStackVec::new();
+    let location = LocationInfo::default();
+    assert!(matches!(
+        call.execute_loc(&mut call_stack, 1, &sim_model, &comp_name, &location),
+        CommandResult::JumpToLine(1)
+    ));
+    match call.execute_loc(&mut call_stack, 2, &sim_model, &comp_name, &location) {
+        CommandResult::Err(e) => match e.kind() {
+            ErrorKind::RecursiveCall(name, chain) => {
+                assert_eq!(name, "recurse");
+                assert_eq!(chain.len(), 2);
+            }
+            _ => panic!("unexpected error: {}", e),
+        },
+        _ => panic!("unexpected command result"),
+    }
+}
+
+#[test]
+fn indirect_recursion_is_detected() {
+    let comp_name = string::new_truncate("comp");
+    let sim_model = test_model(&comp_name, &["a", "b"]);
+    let call = |name: &str| Call {
+        proc_name: name.parse().unwrap(),
+    };
+    let mut call_stack = CallStackVec::new();
+    let location = LocationInfo::default();
+    // a calls b, which calls a again
+    assert!(matches!(
+        call("a").execute_loc(&mut call_stack, 20, &sim_model, &comp_name, &location),
+        CommandResult::JumpToLine(1)
+    ));
+    assert!(matches!(
+        call("b").execute_loc(&mut call_stack, 2, &sim_model, &comp_name, &location),
+        CommandResult::JumpToLine(11)
+    ));
+    match call("a").execute_loc(&mut call_stack, 12, &sim_model, &comp_name, &location) {
+        CommandResult::Err(e) => match e.kind() {
+            ErrorKind::RecursiveCall(name, chain) => {
+                assert_eq!(name, "a");
+                let names = chain
+                    .iter()
+                    .map(|f| f.proc_name.as_str())
+                    .collect::<Vec<_>>();
+                assert_eq!(names, vec!["a", "b", "a"]);
+            }
+            _ => panic!("unexpected error: {}", e),
+        },
+        _ => panic!("unexpected command result"),
+    }
+}
+
+#[test]
+fn call_depth_is_limited() {
+    let comp_name = string::new_truncate("comp");
+    let names = (0..=MAX_CALL_DEPTH)
+        .map(|n| format!("p{}", n))
+        .collect::<Vec<_>>();
+    let sim_model = test_model(
+        &comp_name,
+        &names.iter().map(|n| n.as_str()).collect::<Vec<_>>(),
+    );
+    let mut call_stack = CallStackVec::new();
+    let location = LocationInfo::default();
+    for (n, name) in names.iter().enumerate() {
+        let call = Call {
+            proc_name: name.parse().unwrap(),
+        };
+        match call.execute_loc(
+            &mut call_stack,
+            n * 10 + 1,
+            &sim_model,
+            &comp_name,
+            &location,
+        ) {
+            CommandResult::JumpToLine(_) => assert!(n < MAX_CALL_DEPTH),
+            CommandResult::Err(e) => match e.kind() {
+                ErrorKind::StackOverflow(chain) => {
+                    assert_eq!(n, MAX_CALL_DEPTH);
+                    assert_eq!(chain.len(), MAX_CALL_DEPTH + 1);
+                }
+                _ => panic!("unexpected error: {}", e),
+            },
+            _ => panic!("unexpected command result"),
+        }
+    }
 }
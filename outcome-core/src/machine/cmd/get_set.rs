@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use std::str::FromStr;
 
 use crate::address::Address;
-use crate::entity::{Entity, Storage};
+use crate::entity::{Entity, Storage, StorageIndex};
 use crate::model::SimModel;
 use crate::{model, CompName, EntityId, Var};
 use crate::{EntityName, Sim, StringId, VarType};
@@ -154,25 +154,55 @@ pub struct ExtSetVar {
     pub source: Var,
 }
 impl ExtSetVar {
-    pub fn execute_ext(&self, sim: &mut Sim, exec_ctx: &ExecutionContext) -> Result<()> {
-        unimplemented!();
-        // let ext_ent = match sim
-        //.entities
-        //.get_mut(&(self.target.get_ent_type(), self.target.get_ent_id()))
-        //{
-        // Some(e) => e,
-        // None => {
-        // debug!(
-        //"execute ext failed: entity not found: {}",
-        // self.target.to_string()
-        //);
-        // return Ok(());
-        //};
-        // ext_ent
-        //.storage
-        //.set_from_var(&self.target.as_loc(), self.source.
-        //.set_from_var(&self.target.as_loc(), clone());
-        // return Ok(());
+    pub fn execute_ext(
+        &self,
+        sim: &mut Sim,
+        ent_id: &EntityId,
+        comp_name: &CompName,
+        location: &LocationInfo,
+    ) -> Result<()> {
+        let var = self
+            .source
+            .coerce(self.target.var_type)
+            .map_err(|e| Error::new(location.clone(), ErrorKind::CoreError(e.to_string())))?;
+        match sim.get_var_mut(&self.target) {
+            Ok(target) => *target = var,
+            Err(e) => {
+                return Err(Error::new(
+                    location.clone(),
+                    ErrorKind::CoreError(e.to_string()),
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Copies a var from anywhere in the simulation into a local var of the
+/// entity executing the command. The target is addressed using the
+/// executing entity's id, never by name.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExtSetLocal {
+    pub target: StorageIndex,
+    pub source: Address,
+}
+impl ExtSetLocal {
+    pub fn execute_ext(
+        &self,
+        sim: &mut Sim,
+        ent_id: &EntityId,
+        location: &LocationInfo,
+    ) -> Result<()> {
+        let error = |e: crate::error::Error| {
+            Error::new(location.clone(), ErrorKind::CoreError(e.to_string()))
+        };
+        let var = sim.get_var(&self.source).map_err(error)?.clone();
+        let entity = sim
+            .entities
+            .get_mut(ent_id)
+            .ok_or_else(|| error(crate::error::Error::FailedGettingEntityById(*ent_id)))?;
+        *entity.storage.get_var_mut(&self.target).map_err(error)? = var;
+        Ok(())
     }
 }
 
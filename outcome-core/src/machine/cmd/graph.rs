@@ -0,0 +1,204 @@
+use std::str::FromStr;
+
+use crate::address::ShortLocalAddress;
+use crate::entity::Storage;
+use crate::graph::DEFAULT_EDGE_WEIGHT;
+use crate::{CompName, EntityId, Float, Int, Var};
+
+use super::super::{error::Error, error::ErrorKind, error::Result, LocationInfo};
+use super::{Command, CommandResult};
+
+/// Keyword referring to the entity executing the command.
+const SELF_NODE_KEYWORD: &str = "self";
+
+/// Operates on a graph var.
+///
+/// ```text
+/// graph add_edge graph:friends self int:other_id 0.5
+/// graph remove_edge graph:friends self 12
+/// graph remove_node graph:friends self
+/// graph neighbors graph:friends self --out list_int:friend_ids
+/// graph degree graph:friends self --out int:friend_count
+/// ```
+///
+/// Nodes can be given as entity ids, as local addresses of int vars
+/// holding entity ids, or as `self` for the executing entity.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphCommand {
+    pub graph: ShortLocalAddress,
+    pub op: GraphOp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum GraphOp {
+    AddEdge(Node, Node, Option<Float>),
+    RemoveEdge(Node, Node),
+    RemoveNode(Node),
+    Neighbors(Node, ShortLocalAddress),
+    Degree(Node, ShortLocalAddress),
+}
+
+/// Reference to a graph node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Node {
+    This,
+    Id(EntityId),
+    Var(ShortLocalAddress),
+}
+
+impl FromStr for Node {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == SELF_NODE_KEYWORD {
+            Ok(Node::This)
+        } else if let Ok(id) = s.parse() {
+            Ok(Node::Id(id))
+        } else {
+            Ok(Node::Var(ShortLocalAddress::from_str(s)?))
+        }
+    }
+}
+
+impl Node {
+    pub(crate) fn resolve(
+        &self,
+        storage: &Storage,
+        comp_name: &CompName,
+        ent_id: &EntityId,
+    ) -> Result<EntityId> {
+        match self {
+            Node::This => Ok(*ent_id),
+            Node::Id(id) => Ok(*id),
+            Node::Var(addr) => Ok(storage
+                .get_var(&addr.storage_index_using(comp_name.clone()))?
+                .to_int() as EntityId),
+        }
+    }
+}
+
+impl GraphCommand {
+    pub fn new(args: Vec<String>, location: &LocationInfo) -> Result<Command> {
+        let matches = getopts::Options::new()
+            .optopt("o", "out", "", "")
+            .parse(&args)?;
+        let free = &matches.free;
+        let invalid = |msg: &str| {
+            Error::new(
+                location.clone(),
+                ErrorKind::InvalidCommandBody(format!("graph: {}", msg)),
+            )
+        };
+        if free.len() < 3 {
+            return Err(invalid("expected operation, graph address and node"));
+        }
+        let out = || -> Result<ShortLocalAddress> {
+            match matches.opt_str("out") {
+                Some(out) => Ok(ShortLocalAddress::from_str(&out)?),
+                None => Err(invalid("missing output address (--out)")),
+            }
+        };
+        let node = |i: usize| -> Result<Node> {
+            match free.get(i) {
+                Some(n) => n.parse(),
+                None => Err(invalid("missing node")),
+            }
+        };
+
+        let op = match free[0].as_str() {
+            "add_edge" => GraphOp::AddEdge(
+                node(2)?,
+                node(3)?,
+                free.get(4)
+                    .map(|w| w.parse::<Float>())
+                    .transpose()
+                    .map_err(|e| invalid(&format!("invalid edge weight: {}", e)))?,
+            ),
+            "remove_edge" => GraphOp::RemoveEdge(node(2)?, node(3)?),
+            "remove_node" => GraphOp::RemoveNode(node(2)?),
+            "neighbors" => GraphOp::Neighbors(node(2)?, out()?),
+            "degree" => GraphOp::Degree(node(2)?, out()?),
+            op => return Err(invalid(&format!("unknown operation: {}", op))),
+        };
+
+        Ok(Command::Graph(GraphCommand {
+            graph: ShortLocalAddress::from_str(&free[1])?,
+            op,
+        }))
+    }
+
+    pub fn execute_loc(
+        &self,
+        storage: &mut Storage,
+        ent_id: &EntityId,
+        comp_name: &CompName,
+        location: &LocationInfo,
+    ) -> CommandResult {
+        match self.execute(storage, ent_id, comp_name) {
+            Ok(()) => CommandResult::Continue,
+            Err(e) => CommandResult::Err(Error::new(location.clone(), e.kind().clone())),
+        }
+    }
+
+    fn execute(
+        &self,
+        storage: &mut Storage,
+        ent_id: &EntityId,
+        comp_name: &CompName,
+    ) -> Result<()> {
+        let graph_idx = self.graph.storage_index_using(comp_name.clone());
+        match &self.op {
+            GraphOp::AddEdge(from, to, weight) => {
+                let (from, to) = (
+                    from.resolve(storage, comp_name, ent_id)?,
+                    to.resolve(storage, comp_name, ent_id)?,
+                );
+                storage.get_var_mut(&graph_idx)?.as_graph_mut()?.add_edge(
+                    from,
+                    to,
+                    weight.unwrap_or(DEFAULT_EDGE_WEIGHT),
+                );
+            }
+            GraphOp::RemoveEdge(from, to) => {
+                let (from, to) = (
+                    from.resolve(storage, comp_name, ent_id)?,
+                    to.resolve(storage, comp_name, ent_id)?,
+                );
+                storage
+                    .get_var_mut(&graph_idx)?
+                    .as_graph_mut()?
+                    .remove_edge(from, to);
+            }
+            GraphOp::RemoveNode(node) => {
+                let node = node.resolve(storage, comp_name, ent_id)?;
+                storage
+                    .get_var_mut(&graph_idx)?
+                    .as_graph_mut()?
+                    .remove_node(node);
+            }
+            GraphOp::Neighbors(node, out) => {
+                let node = node.resolve(storage, comp_name, ent_id)?;
+                let neighbors = storage
+                    .get_var(&graph_idx)?
+                    .as_graph()?
+                    .neighbors(node)
+                    .into_iter()
+                    .map(|n| Var::Int(n as Int))
+                    .collect();
+                storage.insert(
+                    out.storage_index_using(comp_name.clone()),
+                    Var::List(neighbors),
+                );
+            }
+            GraphOp::Degree(node, out) => {
+                let node = node.resolve(storage, comp_name, ent_id)?;
+                let degree = storage.get_var(&graph_idx)?.as_graph()?.degree(node);
+                storage.insert(
+                    out.storage_index_using(comp_name.clone()),
+                    Var::Int(degree as Int),
+                );
+            }
+        }
+        Ok(())
+    }
+}
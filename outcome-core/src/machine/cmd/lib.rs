@@ -8,12 +8,13 @@ use serde_yaml::Value;
 
 use crate::address::Address;
 // use crate::;
-use crate::entity::{Entity, Storage};
+use crate::entity::{ComponentViewMut, Entity, Storage};
 use crate::error::{Error, Result};
 use crate::machine::cmd::{Command, CommandResult};
-use crate::machine::Libraries;
+use crate::machine::{ErrorKind, Libraries, LocationInfo};
 use crate::model::SimModel;
-use crate::{model, util, EntityId, Int};
+use crate::rng::EntityRng;
+use crate::{model, util, CompName, EntityId, Int};
 use crate::{Sim, VarType};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -26,6 +27,11 @@ pub enum LibCallSign {
     RetArg(VarType, VarType),
     RetArgArg(VarType, VarType, VarType),
     Var(VarType),
+    /// Function taking a view of the calling component, declared as `comp`
+    Component,
+    /// Function taking the entity id, its storage and its random number
+    /// stream, declared as `rng`
+    Rng,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -61,6 +67,24 @@ impl LibCall {
             "fn" => {
                 ret = None;
             }
+            "comp" => {
+                return Ok(Command::LibCall(LibCall {
+                    lib: args[0].to_string(),
+                    func_name: args[2].to_string(),
+                    func_signature: LibCallSign::Component,
+                    args: Default::default(),
+                    pipe_out,
+                }));
+            }
+            "rng" => {
+                return Ok(Command::LibCall(LibCall {
+                    lib: args[0].to_string(),
+                    func_name: args[2].to_string(),
+                    func_signature: LibCallSign::Rng,
+                    args: Default::default(),
+                    pipe_out,
+                }));
+            }
             "var" => {}
             _ => {
                 if sign_split[0].starts_with("fn->") {
@@ -115,11 +139,27 @@ impl LibCall {
     }
 }
 impl LibCall {
+    /// Creates the error returned when the function can't be found in the
+    /// library.
+    fn symbol_error(&self, error: libloading::Error, location: &LocationInfo) -> CommandResult {
+        CommandResult::Err(crate::machine::Error::new(
+            location.clone(),
+            ErrorKind::Other(format!(
+                "failed getting function \"{}\" from library \"{}\": {}",
+                self.func_name, self.lib, error
+            )),
+        ))
+    }
+
     pub fn execute_loc(
         &self,
         libs: &Libraries,
         entity_id: &EntityId,
         mut storage: &mut Storage,
+        rng: &mut EntityRng,
+        comp_name: &CompName,
+        sim_model: &SimModel,
+        location: &LocationInfo,
     ) -> CommandResult {
         info!("executing lib_call: {:?}, libs: {:?}", self, libs);
         //        let lock = libs.try_lock().expect("failed to lock
@@ -145,6 +185,38 @@ impl LibCall {
                     // func(&entity_id, &mut storage, &mut result);
                     debug!("called VoidEntity function, result: {:?}", result);
                 }
+                LibCallSign::Rng => {
+                    let func: libloading::Symbol<
+                        unsafe extern "C" fn(
+                            &EntityId,
+                            &mut Storage,
+                            &mut EntityRng,
+                        ) -> CommandResult,
+                    > = match lib.get(self.func_name.as_bytes()) {
+                        Ok(f) => f,
+                        Err(e) => return self.symbol_error(e, location),
+                    };
+                    return func(entity_id, storage, rng);
+                }
+                LibCallSign::Component => {
+                    let comp_model = match sim_model.get_component(comp_name) {
+                        Ok(c) => c,
+                        Err(e) => {
+                            return CommandResult::Err(crate::machine::Error::new(
+                                location.clone(),
+                                ErrorKind::FailedGettingComponent(e.to_string()),
+                            ))
+                        }
+                    };
+                    let func: libloading::Symbol<
+                        unsafe extern "C" fn(&mut ComponentViewMut) -> CommandResult,
+                    > = match lib.get(self.func_name.as_bytes()) {
+                        Ok(f) => f,
+                        Err(e) => return self.symbol_error(e, location),
+                    };
+                    let mut view = ComponentViewMut::new(*entity_id, comp_model, storage);
+                    return func(&mut view);
+                }
                 LibCallSign::VoidArg(arg_vt) => match arg_vt {
                     VarType::IntGrid => {
                         unimplemented!();
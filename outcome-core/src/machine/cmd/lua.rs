@@ -1,483 +1,551 @@
-use std::collections::HashMap;
-use std::error::Error;
+//! Calling into Lua scripts.
+//!
+//! Lua scripts are declared in module manifests and stored on the model,
+//! each entity executing Lua gets it's own Lua state with all the model's
+//! scripts loaded. States are created lazily, the first time the entity
+//! makes a `lua_call`, and are not serialized.
+//!
+//! ```text
+//! lua_call grow float:size 0.5 --out float:size
+//! lua_call neighbors --out int:count,str:closest
+//! ```
+//!
+//! Arguments are given as local addresses, whose values are passed to the
+//! function, or as literal values. Values returned by the function are
+//! written to the `--out` addresses, in order.
+//!
+//! Called functions get a handle to the calling entity as their first
+//! argument, also available as the global `entity` for the duration of the
+//! call:
+//!
+//! ```lua
+//! function grow(e, size, rate)
+//!     if size > 10 then
+//!         e:invoke("split")
+//!         e:spawn("cell")
+//!     end
+//!     e:ext_get("world:clock:int:step", "int:last_seen")
+//!     return size * (1 + rate)
+//! end
+//! ```
+//!
+//! | method | description |
+//! |---|---|
+//! | `e:id()` | id of the entity |
+//! | `e:component()` | name of the component making the call |
+//! | `e:get(addr)` | value of a local var |
+//! | `e:set(addr, value)` | sets an existing local var, coercing the value to the var's type |
+//! | `e:ext_get(addr, local_addr)` | copies a var from another entity into a local var |
+//! | `e:ext_set(addr, value)` | sets a var on another entity |
+//! | `e:invoke(event, ...)` | invokes events |
+//! | `e:spawn(prefab, [name])` | spawns a new entity |
+//! | `e:break_state()` | stops executing the calling component for this step |
+//!
+//! Local addresses are given in the short form, e.g. `float:size` or
+//! `other_comp:float:size`, other entities' vars need full addresses.
+//! Operations on other entities and on the simulation are executed as ext
+//! commands after all the local logic was processed, which means their
+//! results won't be visible until then.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 
 use rlua::prelude::{LuaContext, LuaValue};
-use rlua::{AnyUserData, Chunk, Context, Function, Lua, UserData, UserDataMethods};
-use serde_yaml::Value;
+use rlua::{Lua, MultiValue, UserData, UserDataMethods, Variadic};
 
-use super::getopts::Options;
-use crate::address::Address;
-use crate::component::Component;
-use crate::entity::{CompCollection, Entity, Storage};
-use crate::machine::cmd::{Attach, ExtSet, ExtSetVar, Get, Spawn};
-use crate::machine::cmd::{CentralRemoteCommand, Command, CommandResult, ExtCommand};
+use crate::address::{Address, ShortLocalAddress};
+use crate::entity::{EntityNonSer, Storage};
+use crate::machine::cmd::get_set::{ExtSetLocal, ExtSetVar};
+use crate::machine::cmd::{
+    CentralRemoteCommand, Command, CommandResult, ExtCommand, Invoke, Spawn,
+};
+use crate::machine::{CommandResultVec, Error, ErrorKind, LocationInfo, Result};
 use crate::model::SimModel;
-use crate::{model, util};
-use crate::{Sim, VarType};
-use crate::{StringId, Var};
+use crate::var::{Var, VarType};
+use crate::{string, CompName, EntityId, Float, Int};
+
+/// Lua state of a single entity.
+#[derive(Clone)]
+pub struct LuaState(Arc<Mutex<Lua>>);
+
+impl fmt::Debug for LuaState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LuaState")
+    }
+}
+
+impl LuaState {
+    /// Creates a new state with all the model's Lua scripts loaded.
+    pub fn new(model: &SimModel) -> Result<Self> {
+        let lua = Lua::new();
+        lua.context(|ctx| {
+            for script in &model.lua_scripts {
+                ctx.load(&script.source)
+                    .set_name(&script.name)
+                    .and_then(|chunk| chunk.exec())
+                    .map_err(|e| {
+                        Error::new(
+                            LocationInfo::empty(),
+                            ErrorKind::LuaError(format!("{}: {}", script.name, e)),
+                        )
+                    })?;
+            }
+            Ok(())
+        })?;
+        Ok(LuaState(Arc::new(Mutex::new(lua))))
+    }
+}
+
+/// Lua script source, as declared in a module manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LuaScriptEntry {
+    /// Name of the script, used when reporting errors
+    pub name: String,
+    pub source: String,
+}
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct LuaScript {
-    inputs: HashMap<String, Address>,
-    outputs: HashMap<String, Address>,
-    src: String,
+/// Argument passed to the called function.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LuaArg {
+    Address(ShortLocalAddress),
+    Value(Var),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Calls a global Lua function, passing arguments and writing returned
+/// values to local vars.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LuaCall {
-    func: String,
-    args: HashMap<String, Address>,
-    pipe_out: Option<Address>,
-    err: CommandResult,
-    ok: CommandResult,
+    pub func: String,
+    pub args: Vec<LuaArg>,
+    pub out: Vec<ShortLocalAddress>,
 }
+
 impl LuaCall {
-    pub fn from_str(mut args_str: &str) -> Result<Self, String> {
-        // first separate the pipe_out ending, if there is a pipe
-        // present
-        let mut pipe_out = None;
-        if args_str.contains("|") {
-            let split: Vec<&str> = args_str.splitn(2, "|").collect::<Vec<&str>>();
-            //            println!("{:?}", split);
-            args_str = split[0].trim();
-            let pipe_addr = Address::from_str_with_context(split[1].trim(), None, None).unwrap();
-            pipe_out = Some(pipe_addr);
+    pub fn new(args: Vec<String>, location: &LocationInfo) -> Result<Command> {
+        let matches = getopts::Options::new()
+            .optopt("o", "out", "", "")
+            .parse(&args)?;
+        if matches.free.is_empty() {
+            return Err(Error::new(
+                location.clone(),
+                ErrorKind::InvalidCommandBody("lua_call: missing function name".to_string()),
+            ));
         }
-        let mut options = Options::new();
-        options.optopt(
-            "",
-            "err",
-            "Result for situation where the call fails",
-            "RESULT",
-        );
-        options.optopt(
-            "",
-            "ok",
-            "Result for situation where the call succeeds",
-            "RESULT",
-        );
-        options.optopt("a", "args", "List of arguments (addresses)", "RESULT");
-        let split = args_str.split(" ").collect::<Vec<&str>>();
-        let matches = match options.parse(&split) {
-            Ok(m) => m,
-            Err(e) => return Err(e.to_string()),
-        };
-        let args = match matches.opt_str("args") {
-            Some(opt_s) => {
-                let mut out_map = HashMap::new();
-                let opt_split: Vec<&str> = opt_s.split(",").collect::<Vec<&str>>();
-                for (n, mut s) in opt_split.iter().enumerate() {
-                    let mut key = format!("{}", n);
-                    let mut val = s.to_string();
-                    if s.contains("=") {
-                        let key_split = s.split("=").collect::<Vec<&str>>();
-                        key = key_split[0].to_owned();
-                        val = key_split[1].to_owned();
-                    }
-                    let addr = match Address::from_str_with_context(&val, None, None) {
-                        Some(a) => a,
-                        None => {
-                            debug!("invalid lua_call arg: {}", s);
-                            continue;
-                        }
-                    };
-                    out_map.insert(key, addr);
+        let args = matches.free[1..]
+            .iter()
+            .map(|arg| match ShortLocalAddress::from_str(arg) {
+                Ok(addr) => LuaArg::Address(addr),
+                Err(_) => {
+                    LuaArg::Value(Var::from_str(arg, None).unwrap_or(Var::String(arg.clone())))
                 }
-                out_map
-            }
-            None => HashMap::new(),
+            })
+            .collect();
+        let out = match matches.opt_str("out") {
+            Some(out) => out
+                .split(',')
+                .map(|s| ShortLocalAddress::from_str(s.trim()))
+                .collect::<crate::Result<Vec<_>>>()?,
+            None => Vec::new(),
         };
-        //        println!("lua_invoke from_str()");
-        Ok(LuaCall {
-            func: split[0].to_string(),
+        Ok(Command::LuaCall(LuaCall {
+            func: matches.free[0].clone(),
             args,
-            pipe_out,
-            err: CommandResult::Continue,
-            ok: CommandResult::Continue,
-        })
+            out,
+        }))
+    }
+
+    pub fn execute_loc(
+        &self,
+        storage: &mut Storage,
+        insta: &mut EntityNonSer,
+        ent_id: &EntityId,
+        comp_name: &CompName,
+        sim_model: &SimModel,
+        location: &LocationInfo,
+    ) -> CommandResultVec {
+        let mut out = CommandResultVec::new();
+        if let Err(e) = self.execute(storage, insta, ent_id, comp_name, sim_model, &mut out) {
+            out.push(CommandResult::Err(Error::new(
+                location.clone(),
+                e.kind().clone(),
+            )));
+        }
+        out
+    }
+
+    fn execute(
+        &self,
+        storage: &mut Storage,
+        insta: &mut EntityNonSer,
+        ent_id: &EntityId,
+        comp_name: &CompName,
+        sim_model: &SimModel,
+        results: &mut CommandResultVec,
+    ) -> Result<()> {
+        if insta.lua_state.is_none() {
+            insta.lua_state = Some(LuaState::new(sim_model)?);
+        }
+        let lua = insta.lua_state.as_ref().unwrap().0.lock().unwrap();
+
+        let args = self
+            .args
+            .iter()
+            .map(|arg| -> Result<Var> {
+                match arg {
+                    LuaArg::Address(addr) => Ok(storage
+                        .get_var(&addr.storage_index_using(comp_name.clone()))?
+                        .clone()),
+                    LuaArg::Value(var) => Ok(var.clone()),
+                }
+            })
+            .collect::<Result<Vec<Var>>>()?;
+
+        let returned = lua.context(|ctx| {
+            let func: rlua::Function = ctx.globals().get(self.func.as_str())?;
+            ctx.scope(|scope| {
+                let handle = scope.create_nonstatic_userdata(EntityHandle {
+                    ent_id: *ent_id,
+                    comp_name: comp_name.clone(),
+                    storage: &mut *storage,
+                    results: &mut *results,
+                })?;
+                ctx.globals().set("entity", handle.clone())?;
+                let mut call_args = vec![LuaValue::UserData(handle)];
+                for arg in &args {
+                    call_args.push(var_to_lua(ctx, arg)?);
+                }
+                let returned = func.call::<_, MultiValue>(MultiValue::from_vec(call_args));
+                ctx.globals().set("entity", LuaValue::Nil)?;
+                returned?
+                    .into_iter()
+                    .zip(self.out.iter())
+                    .map(|(value, addr)| lua_to_var(ctx, value, addr.var_type))
+                    .collect::<rlua::Result<Vec<Var>>>()
+            })
+        });
+        let returned = returned.map_err(|e| {
+            Error::new(
+                LocationInfo::empty(),
+                ErrorKind::LuaError(format!("{}: {}", self.func, e)),
+            )
+        })?;
+
+        for (var, addr) in returned.into_iter().zip(self.out.iter()) {
+            set_local(storage, addr, comp_name, var)?;
+        }
+        Ok(())
     }
-    fn from_map(map: &HashMap<String, Value>) -> Result<Self, String> {
-        unimplemented!()
+}
+
+/// Handle to the entity making the call, exposed to Lua.
+struct EntityHandle<'a> {
+    ent_id: EntityId,
+    comp_name: CompName,
+    storage: &'a mut Storage,
+    results: &'a mut CommandResultVec,
+}
+
+impl<'a> EntityHandle<'a> {
+    fn local_addr(&self, addr: &str) -> rlua::Result<ShortLocalAddress> {
+        ShortLocalAddress::from_str(addr).map_err(rlua::Error::external)
     }
-    fn from_cmd(
-        cmd: &Command,
-        ent: &Entity,
-        comp: &Component,
-        model: &SimModel,
-    ) -> Result<Self, String> {
-        unimplemented!()
+
+    fn push_ext(&mut self, cmd: ExtCommand) {
+        self.results.push(CommandResult::ExecExt(cmd));
     }
-    fn setup(self, ent: &Entity, comp: &Component, model: &SimModel) -> Self {
-        unimplemented!()
+
+    fn push_central_ext(&mut self, cmd: CentralRemoteCommand) {
+        self.results.push(CommandResult::ExecCentralExt(cmd));
     }
 }
-struct ProcHandle<'a>(
-    &'a SimModel,
-    &'a mut Storage,
-    &'a mut CompCollection,
-    &'a mut Vec<CommandResult>,
-);
-use std::str::FromStr;
-impl<'a> UserData for ProcHandle<'a> {
+
+impl<'a> UserData for EntityHandle<'a> {
     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
-        methods.add_method_mut("break_state", |ctx: rlua::Context, data, ()| {
-            let mut cmd_res: &mut Vec<CommandResult> = &mut data.3;
-            cmd_res.push(CommandResult::Break);
+        methods.add_method("id", |_, this, ()| Ok(this.ent_id as i64));
+        methods.add_method("component", |_, this, ()| Ok(this.comp_name.to_string()));
+        methods.add_method("get", |ctx, this, addr: String| {
+            let addr = this.local_addr(&addr)?;
+            let var = this
+                .storage
+                .get_var(&addr.storage_index_using(this.comp_name.clone()))
+                .map_err(rlua::Error::external)?;
+            var_to_lua(ctx, var)
+        });
+        methods.add_method_mut("set", |ctx, this, (addr, value): (String, LuaValue)| {
+            let addr = this.local_addr(&addr)?;
+            let var = lua_to_var(ctx, value, addr.var_type)?;
+            set_local(this.storage, &addr, &this.comp_name, var).map_err(rlua::Error::external)
+        });
+        methods.add_method_mut("ext_get", |_, this, (source, target): (String, String)| {
+            let source = Address::from_str(&source).map_err(rlua::Error::external)?;
+            let target = this
+                .local_addr(&target)?
+                .storage_index_using(this.comp_name.clone());
+            this.push_ext(ExtCommand::SetLocal(ExtSetLocal { target, source }));
             Ok(())
         });
-        methods.add_method_mut(
-            "spawn_clone",
-            |ctx: rlua::Context, data, (ent_type, ent_id, new_id): (String, String, String)| {
-                let mut cmd_res: &mut Vec<CommandResult> = &mut data.3;
-                cmd_res.push(CommandResult::ExecCentralExt(CentralRemoteCommand::Spawn(
-                    Spawn {
-                        model_type: StringId::from_str(&ent_type).unwrap(),
-                        model_id: StringId::from_str(&ent_id).unwrap(),
-                        spawn_id: StringId::from_str(&new_id).unwrap(),
-                    },
-                )));
-                Ok(())
-            },
-        );
-        methods.add_method_mut(
-            "attach",
-            |ctx: rlua::Context, data, (model_type, model_id, new_id): (String, String, String)| {
-                let model: &SimModel = &data.0;
-                let mut storage: &mut Storage = &mut data.1;
-                let mut comps: &mut CompCollection = &mut data.2;
-                unimplemented!();
-                // comps.attach(model, storage, &model_type, &model_id, &new_id);
-                // cmd_res.push(CommandResult::
-                // ExecCentralExt(CentralExtCommand::Attach(
-                // Attach {
-                // model_type: ShortString::from_str(&model_type).unwrap(),
-                // model_id: ShortString::from_str(&model_id).unwrap(),
-                // new_id: ShortString::from_str(&new_id).unwrap(),
-                //},
-                //)));
-                Ok(())
-            },
-        );
-
-        methods.add_method_mut(
-            "ext_get_addr",
-            |ctx: rlua::Context, data, (target, source): (String, String)| {
-                let target = Address::from_str(&target).unwrap();
-                let source = Address::from_str(&source).unwrap();
-                // println!("{:?}", address);
-                let mut ext_cmds: &mut Vec<CommandResult> = &mut data.3;
-                ext_cmds.push(CommandResult::ExecExt(ExtCommand::Get(Get {
-                    target,
-                    source,
-                })));
-                Ok(())
-            },
-        );
-        methods.add_method_mut(
-            "ext_set_addr",
-            |ctx: rlua::Context, data, (target, source): (String, String)| {
-                let target = Address::from_str(&target).unwrap();
-                let source = Address::from_str(&source).unwrap();
-                // println!("{:?}", address);
-                let mut ext_cmds: &mut Vec<CommandResult> = &mut data.3;
-                ext_cmds.push(CommandResult::ExecExt(ExtCommand::Set(ExtSet {
-                    target,
-                    source,
-                })));
-                Ok(())
-            },
-        );
         methods.add_method_mut(
             "ext_set",
-            |ctx: rlua::Context, data, (target, val): (String, LuaValue)| {
-                let target = Address::from_str(&target).unwrap();
-                let vt = target.get_var_type();
-                let var = match vt {
-                    VarType::Int => Var::Int(ctx.unpack(val).unwrap()),
-                    VarType::Bool => Var::Bool(ctx.unpack(val).unwrap()),
-                    _ => unimplemented!(),
-                };
-                let mut ext_cmds: &mut Vec<CommandResult> = &mut data.3;
-                ext_cmds.push(CommandResult::ExecExt(ExtCommand::SetVar(ExtSetVar {
-                    target,
-                    source: var,
-                })));
+            |ctx, this, (target, value): (String, LuaValue)| {
+                let target = Address::from_str(&target).map_err(rlua::Error::external)?;
+                let source = lua_to_var(ctx, value, target.var_type)?;
+                this.push_ext(ExtCommand::SetVar(ExtSetVar { target, source }));
                 Ok(())
             },
         );
+        methods.add_method_mut("invoke", |_, this, events: Variadic<String>| {
+            this.push_central_ext(CentralRemoteCommand::Invoke(Invoke {
+                events: events.iter().map(|e| string::new_truncate(e)).collect(),
+            }));
+            Ok(())
+        });
         methods.add_method_mut(
-            "set",
-            |ctx: rlua::Context, data, (mut uid, val): (String, LuaValue)| {
-                let uid_str = match uid.starts_with("~/") {
-                    true => &uid[2..],
-                    false => &uid,
-                };
-                let ent_comps: &mut CompCollection = &mut data.2;
-                let ent_storage: &mut Storage = &mut data.1;
-                let loc_addr = Address::from_str(uid_str).unwrap();
-                let var_euid = loc_addr.storage_index();
-                let s = uid_str.rsplitn(3, "/").collect::<Vec<&str>>()[1];
-                let vt = VarType::from_str(s).unwrap();
-                match vt {
-                    VarType::String => {
-                        *ent_storage.get_str_mut(&var_euid).unwrap() =
-                            String::from(ctx.coerce_string(val).unwrap().unwrap().to_str().unwrap())
-                    }
-                    VarType::Int => {
-                        *ent_storage.get_int_mut(&var_euid).unwrap() =
-                            ctx.coerce_integer(val).unwrap().unwrap() as i32
-                    }
-                    VarType::Float => {
-                        *ent_storage.get_float_mut(&var_euid).unwrap() =
-                            ctx.coerce_number(val).unwrap().unwrap() as f32
-                    }
-                    VarType::Bool => {
-                        *ent_storage.get_bool_mut(&var_euid).unwrap() = ctx.unpack(val).unwrap()
-                    }
-                    _ => unimplemented!(),
-                    // VarType::StrList => {
-                    //     *ent_storage.get_str_list_mut(&var_euid).unwrap() = ctx.unpack(val).unwrap()
-                    // }
-                    // VarType::IntList => {
-                    //     *ent_storage.int_list.get_mut(&var_euid).unwrap() = ctx.unpack(val).unwrap()
-                    // }
-                    // VarType::FloatList => {
-                    //     *ent_storage.float_list.get_mut(&var_euid).unwrap() =
-                    //         ctx.unpack(val).unwrap()
-                    // }
-                    // VarType::BoolList => {
-                    //     *ent_storage.bool_list.get_mut(&var_euid).unwrap() =
-                    //         ctx.unpack(val).unwrap()
-                    // }
-                    // VarType::StrGrid => {
-                    //     *ent_storage.str_grid.get_mut(&var_euid).unwrap() = ctx.unpack(val).unwrap()
-                    // }
-                    // VarType::IntGrid => {
-                    //     *ent_storage.int_grid.get_mut(&var_euid).unwrap() = ctx.unpack(val).unwrap()
-                    // }
-                    // VarType::FloatGrid => {
-                    //     *ent_storage.float_grid.get_mut(&var_euid).unwrap() =
-                    //         ctx.unpack(val).unwrap()
-                    // }
-                    // VarType::BoolGrid => {
-                    //     *ent_storage.bool_grid.get_mut(&var_euid).unwrap() =
-                    //         ctx.unpack(val).unwrap()
-                    // }
-                };
+            "spawn",
+            |_, this, (prefab, name): (String, Option<String>)| {
+                this.push_central_ext(CentralRemoteCommand::Spawn(Spawn {
+                    prefab: Some(string::new_truncate(&prefab)),
+                    spawn_id: name.map(|n| string::new_truncate(&n)),
+                    out: None,
+                }));
                 Ok(())
             },
         );
-        methods.add_method("get", |ctx: rlua::Context, data, mut uid: String| {
-            //            let mut uid_addr = String::new();
-            let uid_str = match uid.starts_with("~/") {
-                true => &uid[2..],
-                false => &uid,
-            };
-            let ent_storage: &Storage = &*data.1;
-            let loc_addr = Address::from_str(uid_str).unwrap();
-            let var_euid = loc_addr.storage_index();
-            let s = uid_str.rsplitn(3, "/").collect::<Vec<&str>>()[1];
-            let vt = VarType::from_str(s).unwrap();
-            match vt {
-                VarType::String => Ok(ctx
-                    .pack(ent_storage.get_str(&var_euid).unwrap().clone())
-                    .unwrap()),
-                VarType::Int => Ok(ctx.pack(*ent_storage.get_int(&var_euid).unwrap()).unwrap()),
-                VarType::Float => Ok(ctx
-                    .pack(*ent_storage.get_float(&var_euid).unwrap())
-                    .unwrap()),
-                VarType::Bool => Ok(ctx.pack(*ent_storage.get_bool(&var_euid).unwrap()).unwrap()),
-                _ => unimplemented!(),
-                // VarType::StrList => Ok(ctx
-                //     .pack(ent_storage.str_list.get(&var_euid).unwrap().clone())
-                //     .unwrap()),
-                // VarType::IntList => Ok(ctx
-                //     .pack(ent_storage.int_list.get(&var_euid).unwrap().clone())
-                //     .unwrap()),
-                // VarType::FloatList => Ok(ctx
-                //     .pack(ent_storage.float_list.get(&var_euid).unwrap().clone())
-                //     .unwrap()),
-                // VarType::BoolList => Ok(ctx
-                //     .pack(ent_storage.bool_list.get(&var_euid).unwrap().clone())
-                //     .unwrap()),
-                // VarType::StrGrid => Ok(ctx
-                //     .pack(ent_storage.str_grid.get(&var_euid).unwrap().clone())
-                //     .unwrap()),
-                // VarType::IntGrid => Ok(ctx
-                //     .pack(ent_storage.int_grid.get(&var_euid).unwrap().clone())
-                //     .unwrap()),
-                // VarType::FloatGrid => Ok(ctx
-                //     .pack(ent_storage.float_grid.get(&var_euid).unwrap().clone())
-                //     .unwrap()),
-                // VarType::BoolGrid => Ok(ctx
-                //     .pack(ent_storage.bool_grid.get(&var_euid).unwrap().clone())
-                //     .unwrap()),
-            }
-            //            Ok(())
+        methods.add_method_mut("break_state", |_, this, ()| {
+            this.results.push(CommandResult::Break);
+            Ok(())
         });
     }
 }
 
-impl LuaCall {
-    pub fn execute_loc_lua(
-        &self,
-        // lua_state: &mut Option<Lua>,
-        // storage: &mut RamStorage,
-        model: &SimModel,
-        entity: &mut Entity,
-    ) -> Vec<CommandResult> {
-        let lua_state = &mut entity.insta.lua_state;
-        let storage = &mut entity.storage;
-        let comps = &mut entity.components;
-        let mut out_cmds = Vec::new();
-        let mut ok = true;
-        let lua_state = match lua_state {
-            Some(ls) => ls,
-            None => {
-                debug!("lua_state is none");
-                return vec![self.err.clone()];
+/// Writes the var to local storage. Vars can't be created this way, the
+/// target var has to exist already.
+fn set_local(
+    storage: &mut Storage,
+    addr: &ShortLocalAddress,
+    comp_name: &CompName,
+    var: Var,
+) -> crate::Result<()> {
+    *storage.get_var_mut(&addr.storage_index_using(comp_name.clone()))? = var;
+    Ok(())
+}
+
+/// Converts a var into a Lua value. Collections are converted into tables,
+/// types without a Lua counterpart are converted into strings.
+fn var_to_lua<'lua>(ctx: LuaContext<'lua>, var: &Var) -> rlua::Result<LuaValue<'lua>> {
+    let value = match var {
+        Var::String(v) => LuaValue::String(ctx.create_string(v)?),
+        Var::Int(v) => LuaValue::Integer(*v as i64),
+        Var::Float(v) => LuaValue::Number(*v as f64),
+        Var::Bool(v) => LuaValue::Boolean(*v),
+        Var::Byte(v) => LuaValue::Integer(*v as i64),
+        Var::Vec2(x, y) => LuaValue::Table(ctx.create_sequence_from(vec![*x as f64, *y as f64])?),
+        Var::Vec3(x, y, z) => {
+            LuaValue::Table(ctx.create_sequence_from(vec![*x as f64, *y as f64, *z as f64])?)
+        }
+        Var::List(list) => {
+            let table = ctx.create_table()?;
+            for (n, v) in list.iter().enumerate() {
+                table.set(n + 1, var_to_lua(ctx, v)?)?;
             }
-        };
-        lua_state.lock().unwrap().context(|ctx| {
-            let mut res_val = rlua::Value::Nil;
-            let globals = ctx.globals();
-            ctx.scope(|scope| {
-                //                let userdata =
-                // scope.create_nonstatic_userdata(EntityUserdata(storage)).
-                // unwrap();
+            LuaValue::Table(table)
+        }
+        Var::Grid(rows) => {
+            let table = ctx.create_table()?;
+            for (n, row) in rows.iter().enumerate() {
+                table.set(n + 1, var_to_lua(ctx, &Var::List(row.clone()))?)?;
+            }
+            LuaValue::Table(table)
+        }
+        Var::Map(map) => {
+            let table = ctx.create_table()?;
+            for (k, v) in map {
+                table.set(var_to_lua(ctx, k)?, var_to_lua(ctx, v)?)?;
+            }
+            LuaValue::Table(table)
+        }
+        _ => LuaValue::String(ctx.create_string(&var.to_string())?),
+    };
+    Ok(value)
+}
 
-                // create the args table
-                let mut args_lua = ctx.create_table().unwrap();
-                for (arg_key, arg_addr) in &self.args {
-                    match arg_addr.var_type.unwrap() {
-                        VarType::String => args_lua
-                            .set(
-                                arg_key.to_owned(),
-                                ctx.pack(
-                                    storage
-                                        .get_str(&arg_addr.storage_index())
-                                        .unwrap()
-                                        .to_owned(),
-                                )
-                                .unwrap(),
-                            )
-                            .unwrap(),
-                        VarType::Int => args_lua
-                            .set(
-                                arg_key.to_owned(),
-                                ctx.pack(*storage.get_int(&arg_addr.storage_index()).unwrap())
-                                    .unwrap(),
-                            )
-                            .unwrap(),
-                        VarType::Float => args_lua
-                            .set(
-                                arg_key.to_owned(),
-                                ctx.pack(*storage.get_float(&arg_addr.storage_index()).unwrap())
-                                    .unwrap(),
-                            )
-                            .unwrap(),
-                        VarType::Bool => args_lua
-                            .set(
-                                arg_key.to_owned(),
-                                ctx.pack(*storage.get_bool(&arg_addr.storage_index()).unwrap())
-                                    .unwrap(),
-                            )
-                            .unwrap(),
-                        _ => unimplemented!(),
-                    }
+/// Converts a Lua value into a var of the target type.
+fn lua_to_var<'lua>(
+    ctx: LuaContext<'lua>,
+    value: LuaValue<'lua>,
+    target: VarType,
+) -> rlua::Result<Var> {
+    let from = value.type_name();
+    let mismatch = || rlua::Error::FromLuaConversionError {
+        from,
+        to: "var",
+        message: Some(format!("expected {}", target.to_str())),
+    };
+    let var = match target {
+        VarType::String => match ctx.coerce_string(value)? {
+            Some(s) => Var::String(s.to_str()?.to_string()),
+            None => return Err(mismatch()),
+        },
+        VarType::Int => match ctx.coerce_integer(value)? {
+            Some(i) => Var::Int(i as Int),
+            None => return Err(mismatch()),
+        },
+        VarType::Float => match ctx.coerce_number(value)? {
+            Some(n) => Var::Float(n as Float),
+            None => return Err(mismatch()),
+        },
+        VarType::Bool => match value {
+            LuaValue::Boolean(b) => Var::Bool(b),
+            LuaValue::Nil => Var::Bool(false),
+            _ => return Err(mismatch()),
+        },
+        VarType::Byte => match ctx.coerce_integer(value)? {
+            Some(i) => Var::Byte(i as u8),
+            None => return Err(mismatch()),
+        },
+        VarType::Vec2 | VarType::Vec3 => {
+            let coords = lua_to_list(ctx, value, Some(VarType::Float))?;
+            match coords.as_slice() {
+                [x, y] if target == VarType::Vec2 => Var::Vec2(x.to_float(), y.to_float()),
+                [x, y, z] if target == VarType::Vec3 => {
+                    Var::Vec3(x.to_float(), y.to_float(), z.to_float())
                 }
+                _ => return Err(mismatch()),
+            }
+        }
+        VarType::Map => match lua_to_var_untyped(ctx, value)? {
+            map @ Var::Map(_) => map,
+            _ => return Err(mismatch()),
+        },
+        VarType::Fixed | VarType::Decimal => lua_to_var_untyped(ctx, value)?
+            .coerce(target)
+            .map_err(rlua::Error::external)?,
+        _ => match collection_element(target) {
+            Some((element, false)) => Var::List(lua_to_list(ctx, value, element)?),
+            Some((element, true)) => {
+                let rows = match value {
+                    LuaValue::Table(table) => table
+                        .sequence_values::<LuaValue>()
+                        .map(|row| lua_to_list(ctx, row?, element))
+                        .collect::<rlua::Result<Vec<Vec<Var>>>>()?,
+                    _ => return Err(mismatch()),
+                };
+                Var::Grid(rows)
+            }
+            None => return Err(mismatch()),
+        },
+    };
+    Ok(var)
+}
 
-                // create the userdata object
-                let userdata = scope
-                    .create_nonstatic_userdata(ProcHandle(model, storage, comps, &mut out_cmds))
-                    .unwrap();
+/// Returns the element type of list and grid var types, along with whether
+/// the type is a grid. Element type of `VarList` and `VarGrid` is not fixed.
+fn collection_element(var_type: VarType) -> Option<(Option<VarType>, bool)> {
+    let element = match var_type {
+        VarType::StringList => (Some(VarType::String), false),
+        VarType::IntList => (Some(VarType::Int), false),
+        VarType::FloatList => (Some(VarType::Float), false),
+        VarType::BoolList => (Some(VarType::Bool), false),
+        VarType::ByteList => (Some(VarType::Byte), false),
+        VarType::Vec2List => (Some(VarType::Vec2), false),
+        VarType::Vec3List => (Some(VarType::Vec3), false),
+        VarType::VarList => (None, false),
+        VarType::StringGrid => (Some(VarType::String), true),
+        VarType::IntGrid => (Some(VarType::Int), true),
+        VarType::FloatGrid => (Some(VarType::Float), true),
+        VarType::BoolGrid => (Some(VarType::Bool), true),
+        VarType::ByteGrid => (Some(VarType::Byte), true),
+        VarType::Vec2Grid => (Some(VarType::Vec2), true),
+        VarType::Vec3Grid => (Some(VarType::Vec3), true),
+        VarType::VarGrid => (None, true),
+        _ => return None,
+    };
+    Some(element)
+}
 
-                // get the function
-                match globals.get(self.func.as_str()) {
-                    Ok(f) => {
-                        let func: Function = f;
-                        res_val = match func.call::<_, rlua::Value>((userdata, args_lua)) {
-                            Ok(v) => v,
-                            Err(e) => {
-                                error!("lua_call: {}", e);
-                                ok = false;
-                                rlua::Value::Nil
-                            }
-                        };
-                    }
-                    Err(e) => {
-                        debug!("{}: func name: {}", e, self.func);
-                        ok = false;
-                    }
-                };
+/// Converts the sequence part of a Lua table into a list of vars.
+fn lua_to_list<'lua>(
+    ctx: LuaContext<'lua>,
+    value: LuaValue<'lua>,
+    element: Option<VarType>,
+) -> rlua::Result<Vec<Var>> {
+    match value {
+        LuaValue::Table(table) => table
+            .sequence_values::<LuaValue>()
+            .map(|v| match element {
+                Some(var_type) => lua_to_var(ctx, v?, var_type),
+                None => lua_to_var_untyped(ctx, v?),
+            })
+            .collect(),
+        value => Err(rlua::Error::FromLuaConversionError {
+            from: value.type_name(),
+            to: "list",
+            message: None,
+        }),
+    }
+}
 
-                //                println!("{:?}", res_val);
-            });
-            match self.pipe_out {
-                Some(addr) => {
-                    match addr.var_type.unwrap() {
-                        VarType::String => match ctx.unpack(res_val) {
-                            Ok(v) => *storage.get_str_mut(&addr.storage_index()).unwrap() = v,
-                            Err(e) => error!("pipe failed: {}", e),
-                        },
-                        VarType::Int => match ctx.unpack(res_val) {
-                            Ok(v) => *storage.get_int_mut(&addr.storage_index()).unwrap() = v,
-                            Err(e) => error!("pipe failed: {}", e),
-                        },
-                        VarType::Float => match ctx.unpack(res_val) {
-                            Ok(v) => *storage.get_float_mut(&addr.storage_index()).unwrap() = v,
-                            Err(e) => error!("pipe failed: {}", e),
-                        },
-                        VarType::Bool => match ctx.unpack(res_val) {
-                            Ok(v) => *storage.get_bool_mut(&addr.storage_index()).unwrap() = v,
-                            Err(e) => error!("pipe failed: {}", e),
-                        },
-                        _ => unimplemented!(),
-                        // VarType::StrList => match ctx.unpack(res_val) {
-                        //     Ok(v) => *storage.str_list.get_mut(&addr.get_var_euid()).unwrap() = v,
-                        //     Err(e) => error!("pipe failed: {}", e),
-                        // },
-                        // VarType::IntList => match ctx.unpack(res_val) {
-                        //     Ok(v) => *storage.int_list.get_mut(&addr.get_var_euid()).unwrap() = v,
-                        //     Err(e) => error!("pipe failed: {}", e),
-                        // },
-                        // VarType::FloatList => match ctx.unpack(res_val) {
-                        //     Ok(v) => *storage.float_list.get_mut(&addr.get_var_euid()).unwrap() = v,
-                        //     Err(e) => error!("pipe failed: {}", e),
-                        // },
-                        // VarType::BoolList => match ctx.unpack(res_val) {
-                        //     Ok(v) => *storage.bool_list.get_mut(&addr.get_var_euid()).unwrap() = v,
-                        //     Err(e) => error!("pipe failed: {}", e),
-                        // },
-                        // VarType::StrGrid => match ctx.unpack(res_val) {
-                        //     Ok(v) => *storage.str_grid.get_mut(&addr.get_var_euid()).unwrap() = v,
-                        //     Err(e) => error!("pipe failed: {}", e),
-                        // },
-                        // VarType::IntGrid => match ctx.unpack(res_val) {
-                        //     Ok(v) => *storage.int_grid.get_mut(&addr.get_var_euid()).unwrap() = v,
-                        //     Err(e) => error!("pipe failed: {}", e),
-                        // },
-                        // VarType::FloatGrid => match ctx.unpack(res_val) {
-                        //     Ok(v) => *storage.float_grid.get_mut(&addr.get_var_euid()).unwrap() = v,
-                        //     Err(e) => error!("pipe failed: {}", e),
-                        // },
-                        // VarType::BoolGrid => match ctx.unpack(res_val) {
-                        //     Ok(v) => *storage.bool_grid.get_mut(&addr.get_var_euid()).unwrap() = v,
-                        //     Err(e) => error!("pipe failed: {}", e),
-                        // },
-                    };
-                }
-                None => (),
+/// Converts a Lua value into a var, picking the type based on the value.
+/// Tables with a sequence part are converted into lists, other tables into
+/// maps.
+fn lua_to_var_untyped<'lua>(ctx: LuaContext<'lua>, value: LuaValue<'lua>) -> rlua::Result<Var> {
+    let var = match value {
+        LuaValue::Boolean(b) => Var::Bool(b),
+        LuaValue::Integer(i) => Var::Int(i as Int),
+        LuaValue::Number(n) => Var::Float(n as Float),
+        LuaValue::String(s) => Var::String(s.to_str()?.to_string()),
+        LuaValue::Table(table) if table.raw_len() > 0 => {
+            Var::List(lua_to_list(ctx, LuaValue::Table(table), None)?)
+        }
+        LuaValue::Table(table) => {
+            let mut map = BTreeMap::new();
+            for pair in table.pairs::<LuaValue, LuaValue>() {
+                let (k, v) = pair?;
+                map.insert(lua_to_var_untyped(ctx, k)?, lua_to_var_untyped(ctx, v)?);
             }
-        });
-        // TODO remove
-        lua_state.lock().unwrap().gc_collect();
-        if !ok {
-            out_cmds.push(self.err.clone());
-        } else {
-            out_cmds.push(self.ok.clone());
+            Var::Map(map)
         }
-        // println!("{:?}", out_cmds);
-        out_cmds
-    }
+        value => {
+            return Err(rlua::Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: "var",
+                message: None,
+            })
+        }
+    };
+    Ok(var)
+}
+
+#[test]
+fn set_only_writes_existing_vars() {
+    let mut model = crate::SimModelBuilder::new().build().unwrap();
+    model.lua_scripts.push(LuaScriptEntry {
+        name: "test".to_string(),
+        source: "function set_known(e) e:set('int:count', 2) end \
+                 function set_missing(e) e:set('int:missing', 1) end"
+            .to_string(),
+    });
+    let comp = string::new_truncate("counter");
+    let count = (comp.clone(), string::new_truncate("count"));
+    let missing = (comp.clone(), string::new_truncate("missing"));
+    let mut storage = Storage::default();
+    storage.insert(count.clone(), Var::Int(0));
+    let mut insta = EntityNonSer::default();
+    let mut results = CommandResultVec::new();
+    let call = |func: &str| LuaCall {
+        func: func.to_string(),
+        args: Vec::new(),
+        out: Vec::new(),
+    };
+
+    call("set_known")
+        .execute(&mut storage, &mut insta, &0, &comp, &model, &mut results)
+        .unwrap();
+    assert_eq!(storage.get_var(&count).unwrap(), &Var::Int(2));
+
+    assert!(call("set_missing")
+        .execute(&mut storage, &mut insta, &0, &comp, &model, &mut results)
+        .is_err());
+    assert!(storage.get_var(&missing).is_err());
 }
@@ -38,6 +38,7 @@ use crate::address::{Address, ShortLocalAddress};
 use crate::entity::{Entity, EntityNonSer, Storage};
 // use crate::error::Error;
 use crate::model::SimModel;
+use crate::rng::EntityRng;
 // use crate::Result;
 use crate::Var;
 
@@ -46,6 +47,7 @@ pub mod register;
 pub mod eval;
 pub mod flow;
 pub mod get_set;
+pub mod graph;
 
 #[cfg(feature = "machine_dynlib")]
 pub mod lib;
@@ -55,7 +57,11 @@ use crate::machine::cmd::lib::LibCall;
 #[cfg(feature = "machine_lua")]
 pub mod lua;
 
+#[cfg(feature = "pathfinding")]
+pub mod path;
+
 pub mod print;
+pub mod random;
 pub mod range;
 pub mod set;
 pub mod sim;
@@ -129,8 +135,6 @@ pub enum Command {
     // Equal(Equal),
     // BiggerThan(BiggerThan),
     #[cfg(feature = "machine_lua")]
-    LuaScript(lua::LuaScript),
-    #[cfg(feature = "machine_lua")]
     LuaCall(lua::LuaCall),
     #[cfg(feature = "machine_dynlib")]
     LibCall(lib::LibCall),
@@ -170,6 +174,10 @@ pub enum Command {
     Procedure(flow::procedure::Procedure),
 
     Range(range::Range),
+    Random(random::Random),
+    Graph(graph::GraphCommand),
+    #[cfg(feature = "pathfinding")]
+    Path(path::Path),
 }
 
 impl Command {
@@ -244,6 +252,12 @@ impl Command {
             "break" => Ok(Command::Break(flow::_loop::Break {})),
 
             "range" => Ok(Command::Range(range::Range::new(args)?)),
+            "random" => Ok(random::Random::new(args, location)?),
+            "graph" => Ok(graph::GraphCommand::new(args, location)?),
+            #[cfg(feature = "pathfinding")]
+            "path" => Ok(path::Path::new(args, location)?),
+            #[cfg(feature = "machine_lua")]
+            "lua_call" => Ok(lua::LuaCall::new(args, location)?),
 
             "eval" => Ok(eval::Eval::new(args)?),
 
@@ -262,6 +276,7 @@ impl Command {
         &self,
         ent_storage: &mut Storage,
         ent_insta: &mut EntityNonSer,
+        ent_rng: &mut EntityRng,
         comp_state: &mut StringId,
         call_stack: &mut super::CallStackVec,
         registry: &mut super::Registry,
@@ -269,6 +284,7 @@ impl Command {
         ent_id: &EntityId,
         sim_model: &SimModel,
         location: &LocationInfo,
+        strict: bool,
         #[cfg(feature = "machine_dynlib")] libs: &super::Libraries,
     ) -> CommandResultVec {
         let line = location.line.unwrap();
@@ -288,19 +304,38 @@ impl Command {
                 out_res.push(cmd.execute_loc(ent_storage, comp_name, location))
             }
 
-            Command::Eval(cmd) => {
-                out_res.push(cmd.execute_loc(ent_storage, comp_name, registry, location))
-            }
+            Command::Eval(cmd) => out_res.push(cmd.execute_loc(
+                ent_storage,
+                comp_name,
+                registry,
+                sim_model,
+                strict,
+                location,
+            )),
             // Command::EvalReg(cmd) => out_res.push(cmd.execute_loc(registry)),
 
             //Command::Eval(cmd) => out_res.push(cmd.execute_loc(ent_storage)),
             //Command::Equal(cmd) => out_res.push(cmd.execute_loc(ent_storage)),
             //Command::BiggerThan(cmd) => out_res.push(cmd.execute_loc(ent_storage)),
-
-            //// Command::LuaScript(cmd) => out_res.push(cmd.execute_loc(ent_storage, comp_uid)),
-            //Command::LuaCall(cmd) => out_res.extend(cmd.execute_loc_lua(sim_model, ent)),
+            #[cfg(feature = "machine_lua")]
+            Command::LuaCall(cmd) => out_res.extend(cmd.execute_loc(
+                ent_storage,
+                ent_insta,
+                ent_id,
+                comp_name,
+                sim_model,
+                location,
+            )),
             #[cfg(feature = "machine_dynlib")]
-            Command::LibCall(cmd) => out_res.push(cmd.execute_loc(libs, ent_id, ent_storage)),
+            Command::LibCall(cmd) => out_res.push(cmd.execute_loc(
+                libs,
+                ent_id,
+                ent_storage,
+                ent_rng,
+                comp_name,
+                sim_model,
+                location,
+            )),
             //Command::Attach(cmd) => out_res.push(cmd.execute_loc(ent, sim_model)),
             //Command::Detach(cmd) => out_res.push(cmd.execute_loc(ent, sim_model)),
             Command::Goto(cmd) => out_res.push(cmd.execute_loc(comp_state)),
@@ -350,6 +385,14 @@ impl Command {
             Command::Extend(cmd) => out_res.push(cmd.execute_loc()),
             // Command::Register(cmd) => out_res.extend(cmd.execute_loc(call_stack)),
             Command::Range(cmd) => out_res.push(cmd.execute_loc(ent_storage, comp_name, location)),
+            Command::Random(cmd) => out_res.push(cmd.execute_loc(ent_storage, ent_rng, comp_name)),
+            Command::Graph(cmd) => {
+                out_res.push(cmd.execute_loc(ent_storage, ent_id, comp_name, location))
+            }
+            #[cfg(feature = "pathfinding")]
+            Command::Path(cmd) => {
+                out_res.push(cmd.execute_loc(ent_storage, ent_id, comp_name, location))
+            }
 
             _ => out_res.push(CommandResult::Continue),
         };
@@ -451,6 +494,7 @@ pub enum ExtCommand {
     Get(Get),
     Set(ExtSet),
     SetVar(ExtSetVar),
+    SetLocal(ExtSetLocal),
     // RemoteExec(Command),
     // CentralizedExec(CentralExtCommand),
 }
@@ -465,7 +509,8 @@ impl ExtCommand {
         match self {
             // ExtCommand::Get(cmd) => return cmd.execute_ext(sim, ent_uid, comp_uid, location),
             ExtCommand::Set(cmd) => return cmd.execute_ext(sim, ent_id, comp_name, location),
-            // ExtCommand::SetVar(cmd) => return cmd.execute_ext(sim, exec_ctx),
+            ExtCommand::SetVar(cmd) => return cmd.execute_ext(sim, ent_id, comp_name, location),
+            ExtCommand::SetLocal(cmd) => return cmd.execute_ext(sim, ent_id, location),
             _ => return Ok(()),
         }
     }
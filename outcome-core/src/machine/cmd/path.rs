@@ -0,0 +1,223 @@
+use std::str::FromStr;
+
+use crate::address::ShortLocalAddress;
+use crate::entity::Storage;
+use crate::path::{find_path, Algorithm};
+use crate::{CompName, EntityId, Float, Int, Var};
+
+use super::super::{error::Error, error::ErrorKind, error::Result, LocationInfo};
+use super::{Command, CommandResult};
+
+/// Finds the cheapest path on a grid or graph var.
+///
+/// ```text
+/// path grid_float:terrain vec2:pos vec2:target --out list_vec2:route
+/// path graph:roads self int:destination --out list_int:route --cost float:dist
+/// path grid_int:map 0,0 12,4 --out list_vec2:route --algo dijkstra
+/// ```
+///
+/// Grid ends are given as `x,y` coordinates or addresses of `vec2` vars.
+/// Graph ends are given the same way as with the `graph` command. If there
+/// is no path, the output list is left empty and the cost is set to `-1`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Path {
+    pub source: ShortLocalAddress,
+    pub from: End,
+    pub to: End,
+    pub algorithm: Algorithm,
+    pub out: ShortLocalAddress,
+    pub cost: Option<ShortLocalAddress>,
+}
+
+/// Path end, either a graph node or a grid cell.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum End {
+    Node(super::graph::Node),
+    Cell(Float, Float),
+}
+
+impl FromStr for End {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let split = s.split(',').collect::<Vec<&str>>();
+        if split.len() == 2 {
+            if let (Ok(x), Ok(y)) = (split[0].parse(), split[1].parse()) {
+                return Ok(End::Cell(x, y));
+            }
+        }
+        Ok(End::Node(s.parse()?))
+    }
+}
+
+impl End {
+    fn resolve(&self, storage: &Storage, comp_name: &CompName, ent_id: &EntityId) -> Result<Var> {
+        match self {
+            End::Cell(x, y) => Ok(Var::Vec2(*x, *y)),
+            End::Node(super::graph::Node::Var(addr)) => Ok(storage
+                .get_var(&addr.storage_index_using(comp_name.clone()))?
+                .clone()),
+            End::Node(node) => Ok(Var::Int(node.resolve(storage, comp_name, ent_id)? as Int)),
+        }
+    }
+}
+
+impl Path {
+    pub fn new(args: Vec<String>, location: &LocationInfo) -> Result<Command> {
+        let matches = getopts::Options::new()
+            .optopt("o", "out", "", "")
+            .optopt("c", "cost", "", "")
+            .optopt("a", "algo", "", "")
+            .parse(&args)?;
+        let invalid = |msg: String| {
+            Error::new(
+                location.clone(),
+                ErrorKind::InvalidCommandBody(format!("path: {}", msg)),
+            )
+        };
+        if matches.free.len() != 3 {
+            return Err(invalid(
+                "expected grid or graph address, start and end".to_string(),
+            ));
+        }
+        let out = match matches.opt_str("out") {
+            Some(out) => ShortLocalAddress::from_str(&out)?,
+            None => return Err(invalid("missing output address (--out)".to_string())),
+        };
+        let algorithm = match matches.opt_str("algo") {
+            Some(algo) => algo.parse().map_err(|e| invalid(format!("{}", e)))?,
+            None => Algorithm::default(),
+        };
+
+        Ok(Command::Path(Path {
+            source: ShortLocalAddress::from_str(&matches.free[0])?,
+            from: matches.free[1].parse()?,
+            to: matches.free[2].parse()?,
+            algorithm,
+            out,
+            cost: matches
+                .opt_str("cost")
+                .map(|s| ShortLocalAddress::from_str(&s))
+                .transpose()?,
+        }))
+    }
+
+    pub fn execute_loc(
+        &self,
+        storage: &mut Storage,
+        ent_id: &EntityId,
+        comp_name: &CompName,
+        location: &LocationInfo,
+    ) -> CommandResult {
+        match self.execute(storage, ent_id, comp_name) {
+            Ok(()) => CommandResult::Continue,
+            Err(e) => CommandResult::Err(Error::new(location.clone(), e.kind().clone())),
+        }
+    }
+
+    fn execute(
+        &self,
+        storage: &mut Storage,
+        ent_id: &EntityId,
+        comp_name: &CompName,
+    ) -> Result<()> {
+        let from = self.from.resolve(storage, comp_name, ent_id)?;
+        let to = self.to.resolve(storage, comp_name, ent_id)?;
+        let source = storage.get_var(&self.source.storage_index_using(comp_name.clone()))?;
+        let (path, cost) = find_path(source, &from, &to, self.algorithm)?
+            .unwrap_or_else(|| (Var::List(Vec::new()), -1.));
+        storage.insert(self.out.storage_index_using(comp_name.clone()), path);
+        if let Some(cost_addr) = &self.cost {
+            storage.insert(
+                cost_addr.storage_index_using(comp_name.clone()),
+                Var::Float(cost),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn path_cmd(args: &str) -> Result<Path> {
+    let args = args.split_whitespace().map(|s| s.to_string()).collect();
+    match Path::new(args, &LocationInfo::empty())? {
+        Command::Path(path) => Ok(path),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn path_finds_route_with_each_algorithm() {
+    let comp = crate::string::new_truncate("nav");
+    let var = |name: &str| (comp.clone(), crate::string::new_truncate(name));
+    let mut storage = Storage::default();
+    storage.insert(
+        var("map"),
+        Var::Grid(vec![
+            vec![Var::Int(1), Var::Int(1), Var::Int(1)],
+            vec![Var::Int(-1), Var::Int(-1), Var::Int(1)],
+            vec![Var::Int(1), Var::Int(1), Var::Int(1)],
+        ]),
+    );
+    let route = Var::List(
+        [(0, 0), (1, 0), (2, 0), (2, 1), (2, 2), (1, 2), (0, 2)]
+            .iter()
+            .map(|(x, y)| Var::Vec2(*x as Float, *y as Float))
+            .collect(),
+    );
+    for algo in &["", "--algo astar", "--algo a*", "--algo dijkstra"] {
+        let cmd = path_cmd(&format!(
+            "grid_int:map 0,0 0,2 --out list_vec2:route --cost float:dist {}",
+            algo
+        ))
+        .unwrap();
+        cmd.execute(&mut storage, &1, &comp).unwrap();
+        assert_eq!(storage.get_var(&var("route")).unwrap(), &route);
+        assert_eq!(storage.get_var(&var("dist")).unwrap(), &Var::Float(6.));
+    }
+}
+
+#[test]
+fn missing_path_has_negative_cost() {
+    let comp = crate::string::new_truncate("nav");
+    let var = |name: &str| (comp.clone(), crate::string::new_truncate(name));
+    let mut storage = Storage::default();
+    storage.insert(var("roads"), Var::Graph("1-2 3".parse().unwrap()));
+    storage.insert(
+        var("map"),
+        Var::Grid(vec![vec![Var::Int(1), Var::Int(-1), Var::Int(1)]]),
+    );
+
+    for args in &[
+        "graph:roads 1 3 --out list_int:route --cost float:dist",
+        "grid_int:map 0,0 2,0 --out list_vec2:route --cost float:dist",
+        "grid_int:map 0,0 2,0 --out list_vec2:route --cost float:dist --algo dijkstra",
+    ] {
+        storage.insert(var("route"), Var::List(vec![Var::Int(1)]));
+        path_cmd(args)
+            .unwrap()
+            .execute(&mut storage, &1, &comp)
+            .unwrap();
+        assert_eq!(storage.get_var(&var("route")).unwrap(), &Var::List(vec![]));
+        assert_eq!(storage.get_var(&var("dist")).unwrap(), &Var::Float(-1.));
+    }
+
+    // reachable graph node, for comparison
+    path_cmd("graph:roads self 2 --out list_int:route --cost float:dist")
+        .unwrap()
+        .execute(&mut storage, &1, &comp)
+        .unwrap();
+    assert_eq!(
+        storage.get_var(&var("route")).unwrap(),
+        &Var::List(vec![Var::Int(1), Var::Int(2)])
+    );
+    assert_eq!(storage.get_var(&var("dist")).unwrap(), &Var::Float(1.));
+}
+
+#[test]
+fn invalid_algorithm_is_rejected() {
+    assert!(path_cmd("grid_int:map 0,0 1,1 --out list_vec2:route --algo bfs").is_err());
+    assert!(path_cmd("grid_int:map 0,0 1,1 --out list_vec2:route --algo").is_err());
+    assert!("Dijkstra".parse::<Algorithm>().is_err());
+    assert_eq!("a*".parse::<Algorithm>().unwrap(), Algorithm::AStar);
+}
@@ -0,0 +1,80 @@
+use std::str::FromStr;
+
+use crate::address::ShortLocalAddress;
+use crate::entity::Storage;
+use crate::rng::EntityRng;
+use crate::{CompName, Float, Int, Var, VarType};
+
+use super::super::{error::Error, error::ErrorKind, error::Result, LocationInfo};
+use super::{Command, CommandResult};
+
+/// Draws a number from the entity's random number stream, see the
+/// [`rng`] module.
+///
+/// ```text
+/// random float:roll
+/// random int:dice 1 7
+/// random float:temperature -5.0 5.0
+/// ```
+///
+/// Output var type decides whether an int or a float is drawn. Range is
+/// inclusive of the lower bound and exclusive of the upper one, without
+/// bounds a float within `0..1` is drawn.
+///
+/// [`rng`]: crate::rng
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Random {
+    pub out: ShortLocalAddress,
+    pub range: Option<(Float, Float)>,
+}
+
+impl Random {
+    pub fn new(args: Vec<String>, location: &LocationInfo) -> Result<Command> {
+        let invalid = |msg: String| {
+            Error::new(
+                location.clone(),
+                ErrorKind::InvalidCommandBody(format!("random: {}", msg)),
+            )
+        };
+        let out = match args.get(0) {
+            Some(out) => ShortLocalAddress::from_str(out)?,
+            None => return Err(invalid("missing output address".to_string())),
+        };
+        match out.var_type {
+            VarType::Int | VarType::Float => (),
+            _ => {
+                return Err(invalid(format!(
+                    "unsupported output var type: {:?}",
+                    out.var_type
+                )))
+            }
+        }
+        let bound = |i: usize| -> Result<Float> {
+            args[i]
+                .parse::<Float>()
+                .map_err(|e| invalid(format!("invalid bound: {}", e)))
+        };
+        let range = match args.len() {
+            1 => None,
+            3 => Some((bound(1)?, bound(2)?)),
+            _ => return Err(invalid("expected either both bounds or none".to_string())),
+        };
+        Ok(Command::Random(Random { out, range }))
+    }
+
+    pub fn execute_loc(
+        &self,
+        storage: &mut Storage,
+        rng: &mut EntityRng,
+        comp_name: &CompName,
+    ) -> CommandResult {
+        let var = match (&self.out.var_type, self.range) {
+            (VarType::Int, Some((min, max))) => Var::Int(rng.range_int(min as Int, max as Int)),
+            (VarType::Int, None) => Var::Int(rng.next_u64() as Int),
+            (_, Some((min, max))) => Var::Float(rng.range_float(min, max)),
+            (_, None) => Var::Float(rng.next_float()),
+        };
+        storage.insert(self.out.storage_index_using(comp_name.clone()), var);
+        CommandResult::Continue
+    }
+}
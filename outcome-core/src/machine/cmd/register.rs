@@ -115,6 +115,9 @@ impl RegisterVar {
                 name: self.addr.var_name.clone(),
                 type_: self.addr.var_type,
                 default: self.val.clone(),
+                min: None,
+                max: None,
+                unit: None,
             });
         }
 
@@ -139,6 +142,9 @@ impl RegisterVar {
                 name: self.addr.var_name.clone(),
                 type_: self.addr.var_type,
                 default: self.val.clone(),
+                min: None,
+                max: None,
+                unit: None,
             });
         }
 
@@ -226,6 +232,7 @@ impl RegisterEvent {
     pub fn execute_ext_distr(&self, central: &mut SimCentral) -> Result<()> {
         central.model.events.push(EventModel {
             id: self.name.clone(),
+            order: Default::default(),
         });
         central.event_queue.push(self.name.clone());
         Ok(())
@@ -268,6 +275,7 @@ impl RegisterEntityPrefab {
         sim.model.entities.push(EntityPrefab {
             name: self.name.clone(),
             components: self.components.clone(),
+            ..EntityPrefab::default()
         });
         Ok(())
     }
@@ -276,6 +284,7 @@ impl RegisterEntityPrefab {
         central.model.entities.push(EntityPrefab {
             name: self.name.clone(),
             components: self.components.clone(),
+            ..EntityPrefab::default()
         });
         Ok(())
     }
@@ -1,7 +1,7 @@
 use super::{Command, CommandResult};
 use crate::address::{Address, LocalAddress, ShortLocalAddress};
 use crate::entity::{Entity, Storage};
-use crate::var::{Var, VarType};
+use crate::var::{ArithOp, Var, VarType};
 use crate::{address, string};
 use crate::{CompName, EntityId, EntityName, StringId};
 
@@ -32,10 +32,23 @@ impl SetIntIntAddr {
     }
 }
 
+/// Sets the target var to the source value, optionally applying an
+/// arithmetic operation to the current target value first.
+///
+/// ```text
+/// set float:x 1.5
+/// set float:x = other_comp:float:x
+/// set fixed:x += fixed:dx
+/// ```
+///
+/// Supported operators are `=`, `+=`, `-=`, `*=` and `/=`. Arithmetic
+/// keeps the type of the target, which allows for bit-exact arithmetic
+/// on `fixed` vars. Arithmetic is not available with global sources.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Set {
     target: Target,
     source: Source,
+    op: Option<ArithOp>,
     out: Option<ShortLocalAddress>,
 }
 
@@ -142,16 +155,42 @@ impl Set {
         let target = Target::from_str(&args[0], location)?;
 
         let mut source_str = "";
-        // is '=' present?
+        let mut op = None;
+        // is an assignment operator present?
         if args.len() > 1 {
-            if args[1] == "=" {
-                source_str = &args[2];
-            } else {
-                source_str = &args[1];
+            let assign_op = match args[1].as_str() {
+                "=" => Some(None),
+                "+=" => Some(Some(ArithOp::Add)),
+                "-=" => Some(Some(ArithOp::Sub)),
+                "*=" => Some(Some(ArithOp::Mul)),
+                "/=" => Some(Some(ArithOp::Div)),
+                _ => None,
+            };
+            match assign_op {
+                Some(_op) => {
+                    op = _op;
+                    source_str = args.get(2).map(|s| s.as_str()).ok_or_else(|| {
+                        Error::new(
+                            location.clone(),
+                            ErrorKind::InvalidCommandBody("missing source".to_string()),
+                        )
+                    })?;
+                }
+                None => source_str = &args[1],
             }
         }
 
         let source = Source::from_str(source_str, target.var_type(), location)?;
+        if op.is_some() {
+            if let Source::Address(_) = source {
+                return Err(Error::new(
+                    location.clone(),
+                    ErrorKind::InvalidCommandBody(
+                        "arithmetic is not supported with global source address".to_string(),
+                    ),
+                ));
+            }
+        }
 
         let mut out = None;
         if let Some((out_sign_pos, _)) = args.iter().enumerate().find(|(_, s)| s.as_str() == "=>") {
@@ -163,6 +202,7 @@ impl Set {
         Ok(Command::Set(Set {
             target,
             source,
+            op,
             out,
         }))
     }
@@ -185,6 +225,37 @@ impl Set {
             },
         };
 
+        if let Some(op) = self.op {
+            let source = match &self.source {
+                Source::LocalAddress(loc_addr) => {
+                    match entity_db.get_var(&loc_addr.storage_index_using(comp_name.clone())) {
+                        Ok(v) => v.clone(),
+                        Err(e) => {
+                            return CommandResult::Err(Error::new(
+                                location.clone(),
+                                ErrorKind::CoreError(e.to_string()),
+                            ))
+                        }
+                    }
+                }
+                Source::Value(val) => val.clone(),
+                Source::Address(_) => unreachable!(),
+            };
+            let result = entity_db
+                .get_var_mut(&target_addr.storage_index())
+                .and_then(|target| {
+                    *target = target.arith(op, &source)?;
+                    Ok(())
+                });
+            if let Err(e) = result {
+                return CommandResult::Err(Error::new(
+                    location.clone(),
+                    ErrorKind::CoreError(e.to_string()),
+                ));
+            }
+            return CommandResult::Continue;
+        }
+
         match &self.source {
             Source::LocalAddress(loc_addr) => {
                 // entity_db.set_from_addr(&self.target, &addr)
@@ -18,6 +18,32 @@ impl Error {
     pub fn new(location: LocationInfo, kind: ErrorKind) -> Self {
         Self { location, kind }
     }
+
+    pub fn location(&self) -> &LocationInfo {
+        &self.location
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+}
+
+/// Single procedure call as seen in the call chain attached to errors.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CallFrame {
+    /// Name of the called procedure
+    pub proc_name: String,
+    /// Line number of the call as seen in source file
+    pub source_line: Option<usize>,
+}
+
+impl fmt::Display for CallFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.source_line {
+            Some(line) => write!(f, "{} (line {})", self.proc_name, line),
+            None => write!(f, "{}", self.proc_name),
+        }
+    }
 }
 
 impl std::error::Error for Error {}
@@ -58,6 +84,16 @@ pub enum ErrorKind {
     StackEmpty,
     FailedGettingFromStorage(String),
     FailedGettingComponent(String),
+    BudgetExceeded(String),
+    /// Execution panicked, caught before it could bring down the process
+    CaughtPanic(String),
+    /// Error raised while loading or calling a Lua script
+    LuaError(String),
+
+    // procedure calls
+    ProcedureNotFound(String),
+    StackOverflow(Vec<CallFrame>),
+    RecursiveCall(String, Vec<CallFrame>),
 
     Other(String),
 }
@@ -176,9 +212,48 @@ impl fmt::Display for Error {
                 fmt_err_msg(formatter, &self.location, &format!("parse error: {}", msg))
             }
             ErrorKind::Panic => fmt_err_msg(formatter, &self.location, &format!("panic")),
+            ErrorKind::CaughtPanic(ref msg) => fmt_err_msg(
+                formatter,
+                &self.location,
+                &format!("caught panic, component disabled: {}", msg),
+            ),
+            ErrorKind::LuaError(ref msg) => {
+                fmt_err_msg(formatter, &self.location, &format!("lua error: {}", msg))
+            }
             ErrorKind::StackEmpty => {
                 fmt_err_msg(formatter, &self.location, &format!("stack empty"))
             }
+            ErrorKind::BudgetExceeded(ref msg) => fmt_err_msg(
+                formatter,
+                &self.location,
+                &format!("execution budget exceeded: {}", msg),
+            ),
+            ErrorKind::ProcedureNotFound(ref name) => fmt_err_msg(
+                formatter,
+                &self.location,
+                &format!(
+                    "call failed: procedure with the name `{}` doesn't exist in the current scope",
+                    name
+                ),
+            ),
+            ErrorKind::StackOverflow(ref chain) => fmt_err_msg(
+                formatter,
+                &self.location,
+                &format!(
+                    "call stack depth limit ({}) exceeded, call chain: {}",
+                    super::MAX_CALL_DEPTH,
+                    fmt_call_chain(chain)
+                ),
+            ),
+            ErrorKind::RecursiveCall(ref name, ref chain) => fmt_err_msg(
+                formatter,
+                &self.location,
+                &format!(
+                    "recursive call to procedure `{}`, call chain: {}",
+                    name,
+                    fmt_call_chain(chain)
+                ),
+            ),
 
             ErrorKind::Other(ref msg) => {
                 fmt_err_msg(formatter, &self.location, &format!("other error: {}", msg))
@@ -186,6 +261,14 @@ impl fmt::Display for Error {
         }
     }
 }
+fn fmt_call_chain(chain: &Vec<CallFrame>) -> String {
+    chain
+        .iter()
+        .map(|frame| frame.to_string())
+        .collect::<Vec<String>>()
+        .join(" -> ")
+}
+
 fn fmt_err_msg(
     formatter: &mut fmt::Formatter,
     location_info: &LocationInfo,
@@ -4,10 +4,12 @@
 use std::sync::{Arc, Mutex};
 
 use crate::entity::{Entity, EntityNonSer, Storage};
+use crate::rng::EntityRng;
 use crate::{Address, CompName, EntityId, EntityName, StringId};
 use crate::{Sim, SimModel};
 
-use super::cmd::{CentralRemoteCommand, Command, CommandResult, ExtCommand};
+use super::budget::EntityBudget;
+use super::cmd::{CentralRemoteCommand, Command, CommandResult, ExtCommand, Invoke};
 use super::{error::Error, CallStackVec, ExecutionContext, LocationInfo, Registry};
 
 use crate::machine::{ErrorKind, Result};
@@ -24,6 +26,10 @@ pub(crate) fn execute_central_ext(
     for (exe_loc, central_ext_cmd) in central_ext_cmds {
         if let Err(me) = central_ext_cmd.execute(sim, &exe_loc.ent, &exe_loc.comp) {
             error!("{}", me);
+            sim.error_journal.push((exe_loc.clone(), me.clone()));
+            if sim.strict {
+                return Err(me);
+            }
         }
     }
 
@@ -38,6 +44,10 @@ pub(crate) fn execute_ext(
     for (exec_ctx, ext_cmd) in ext_cmds {
         if let Err(e) = ext_cmd.execute(sim, &exec_ctx.ent, &exec_ctx.comp, &exec_ctx.location) {
             error!("{}", e);
+            sim.error_journal.push((exec_ctx.clone(), e.clone()));
+            if sim.strict {
+                return Err(e);
+            }
         }
     }
     Ok(())
@@ -57,23 +67,39 @@ pub(crate) fn execute_ext(
 /// entity level that are targeting execution in a higher context will yield
 /// command results containing either `ext` or `central_ext` commands.
 ///
+/// ### Error journal
+///
+/// Non-breaking errors are collected into the provided error journal, along
+/// with the context in which they occurred. In strict mode the first such
+/// error stops execution and is returned.
+///
 /// ### Optional start and end line arguments
 ///
 /// Execution can optionally be restricted to a subset of all commands using
 /// the start and end line numbers. This is used when executing a selected
 /// state, since states are essentially described using their start and end
 /// line numbers.
+///
+/// ### Execution budget
+///
+/// Each executed command is accounted for using the provided entity budget.
+/// Once the budget is exceeded execution is suspended until the next step,
+/// and the budget exceeded event is invoked.
 pub(crate) fn execute_loc(
     cmds: &Vec<Command>,
     locations: &Vec<LocationInfo>,
     mut ent_storage: &mut Storage,
     mut ent_insta: &mut EntityNonSer,
+    ent_rng: &mut EntityRng,
     mut comp_state: &mut StringId,
     ent_uid: &EntityId,
     comp_uid: &CompName,
     sim_model: &SimModel,
     ext_cmds: &Arc<Mutex<Vec<(ExecutionContext, ExtCommand)>>>,
     central_ext_cmds: &Arc<Mutex<Vec<(ExecutionContext, CentralRemoteCommand)>>>,
+    errors: &Arc<Mutex<Vec<(ExecutionContext, Error)>>>,
+    budget: &mut EntityBudget,
+    strict: bool,
     start: Option<usize>,
     end: Option<usize>,
     #[cfg(feature = "machine_dynlib")] libs: &Libraries,
@@ -111,10 +137,30 @@ pub(crate) fn execute_loc(
         ))?;
         trace!("command: {:?}", loc_cmd);
         trace!("command location_info: {:?}", location_info);
+        if let Some(reason) = budget.consume(cmd_n) {
+            let error = Error::new(location_info.clone(), ErrorKind::BudgetExceeded(reason));
+            warn!("entity {}: {}", ent_uid, error);
+            let exec_ctx = ExecutionContext {
+                ent: *ent_uid,
+                comp: comp_uid.clone(),
+                location: location_info.clone(),
+            };
+            errors.lock().unwrap().push((exec_ctx.clone(), error));
+            central_ext_cmds.lock().unwrap().push((
+                exec_ctx,
+                CentralRemoteCommand::Invoke(Invoke {
+                    events: vec![crate::string::new_truncate(
+                        crate::DEFAULT_BUDGET_EXCEEDED_EVENT,
+                    )],
+                }),
+            ));
+            break;
+        }
         // let mut comp = entity.components.get_mut(&comp_uid).unwrap();
         let results = loc_cmd.execute(
             &mut ent_storage,
             &mut ent_insta,
+            ent_rng,
             &mut comp_state,
             &mut call_stack,
             &mut registry,
@@ -122,6 +168,7 @@ pub(crate) fn execute_loc(
             ent_uid,
             &sim_model,
             location_info,
+            strict,
             #[cfg(feature = "machine_dynlib")]
             libs,
         );
@@ -169,6 +216,17 @@ pub(crate) fn execute_loc(
                     //TODO implement configurable system for deciding whether to
                     // break state, panic or just print when given error occurs
                     error!("{}", e);
+                    errors.lock().unwrap().push((
+                        ExecutionContext {
+                            ent: *ent_uid,
+                            comp: comp_uid.clone(),
+                            location: location_info.clone(),
+                        },
+                        e.clone(),
+                    ));
+                    if strict {
+                        return Err(e);
+                    }
                 }
             }
         }
@@ -249,6 +307,9 @@ pub fn execute(
         let results = loc_cmd.execute(
             &mut entity.storage,
             &mut entity.insta,
+            entity
+                .rng
+                .get_or_insert_with(|| EntityRng::new(sim.model.scenario.manifest.seed, *ent_id)),
             &mut comp_state,
             &mut call_stack,
             &mut registry,
@@ -256,6 +317,7 @@ pub fn execute(
             ent_id,
             &sim.model,
             &location,
+            sim.strict,
             #[cfg(feature = "machine_dynlib")]
             libs,
         );
@@ -278,6 +340,17 @@ pub fn execute(
                     //TODO implement configurable system for deciding whether to
                     // break state, panic or just print when given error occurs
                     error!("{}", e);
+                    sim.error_journal.push((
+                        ExecutionContext {
+                            ent: *ent_id,
+                            comp: comp_uid.clone(),
+                            location: location.clone(),
+                        },
+                        e.clone(),
+                    ));
+                    if sim.strict {
+                        return Err(e);
+                    }
                 }
             }
         }
@@ -285,3 +358,39 @@ pub fn execute(
     }
     Ok(())
 }
+
+#[test]
+fn strict_mode_stops_at_first_error() {
+    use super::cmd::get_set::ExtSetVar;
+    use crate::string::new_truncate;
+
+    let mut sim = crate::SimModelBuilder::new().build_sim().unwrap();
+    // targets an entity that doesn't exist
+    let failing = |entity: &str| {
+        (
+            ExecutionContext {
+                ent: 0,
+                comp: new_truncate("comp"),
+                location: LocationInfo::default(),
+            },
+            ExtCommand::SetVar(ExtSetVar {
+                target: Address {
+                    entity: new_truncate(entity),
+                    component: new_truncate("comp"),
+                    var_type: crate::VarType::Float,
+                    var_name: new_truncate("x"),
+                },
+                source: crate::Var::Float(1.),
+            }),
+        )
+    };
+    let cmds = vec![failing("first"), failing("second")];
+
+    assert!(execute_ext(&cmds, &mut sim).is_ok());
+    assert_eq!(sim.error_journal.len(), 2);
+
+    sim.error_journal.clear();
+    sim.strict = true;
+    assert!(execute_ext(&cmds, &mut sim).is_err());
+    assert_eq!(sim.error_journal.len(), 1);
+}
@@ -0,0 +1,428 @@
+//! Dataflow graphs as an alternative to script-defined component logic.
+//!
+//! A graph is made up of nodes, each producing a single value from the
+//! values of its inputs. Inputs refer to other nodes by id, forming the
+//! data dependencies of the graph. Graphs are meant as a target for visual
+//! editors, which can produce them directly instead of generating scripts.
+//!
+//! A graph is declared on the component in structured data files, and is
+//! evaluated each time the component is triggered, in place of executing
+//! the current state.
+//!
+//! ```yaml
+//! components:
+//!   mover:
+//!     triggers: [step]
+//!     vars:
+//!       float:x: 0
+//!       float:speed: 1.5
+//!     graph:
+//!       - id: x
+//!         op: { get: float:x }
+//!       - id: speed
+//!         op: { get: float:speed }
+//!       - id: next_x
+//!         op: add
+//!         inputs: [x, speed]
+//!       - id: set_x
+//!         op: { set: float:x }
+//!         inputs: [next_x]
+//! ```
+//!
+//! # Nodes
+//!
+//! - `value` outputs a constant
+//! - `get` outputs the local var at the given address
+//! - `set` writes its input to the existing local var at the given
+//!   address, outputting the written value
+//! - `add`, `sub`, `mul`, `div`, `min` and `max` combine two numeric
+//!   inputs, resulting in an int if both inputs are ints, with integer
+//!   overflow resulting in an error
+//! - `neg` negates a numeric input
+//! - `eq`, `lt` and `gt` compare two inputs
+//! - `and`, `or` and `not` are boolean operations
+//! - `select` outputs its second or third input depending on the first
+//! - `command` runs a single script command, but only if it has no inputs
+//!   or its first input is truthy, outputting whether it succeeded
+//!
+//! Nodes are evaluated in dependency order, regardless of the order they
+//! are declared in. Graphs with cycles are rejected when the model is
+//! loaded. The order isn't serialized, it's resolved again when the graph
+//! is deserialized, e.g. as part of a snapshot.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::address::ShortLocalAddress;
+use crate::model::LogicModel;
+use crate::{CompName, Int, Var};
+
+use super::behavior::{BehaviorStatus, TickContext};
+use super::error::{Error, ErrorKind, Result};
+use super::LocationInfo;
+
+/// Dataflow graph defining component logic.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct LogicGraph {
+    pub nodes: Vec<GraphNode>,
+    /// Order of evaluation, resolved when preparing the graph
+    #[serde(skip)]
+    order: Vec<usize>,
+    /// Inputs of each node as indices into the node list
+    #[serde(skip)]
+    inputs: Vec<Vec<usize>>,
+}
+
+/// Single node of a dataflow graph.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GraphNode {
+    /// Identifier unique within the graph
+    pub id: String,
+    pub op: NodeOp,
+    /// Ids of the nodes whose outputs are used as inputs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<String>,
+}
+
+/// Operation performed by a graph node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeOp {
+    Value(Var),
+    /// Local var address, e.g. `float:x` or `other_comp:float:x`
+    Get(String),
+    /// Local var address, e.g. `float:x` or `other_comp:float:x`
+    Set(String),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Min,
+    Max,
+    Neg,
+    Eq,
+    Lt,
+    Gt,
+    And,
+    Or,
+    Not,
+    Select,
+    /// Script command, compiled when loading the model
+    Command(String),
+    /// Command compiled into the component's logic, as start and end line
+    Lines(usize, usize),
+}
+
+impl NodeOp {
+    /// Number of inputs required by the operation.
+    fn arity(&self) -> usize {
+        match self {
+            NodeOp::Value(_) | NodeOp::Get(_) | NodeOp::Command(_) | NodeOp::Lines(..) => 0,
+            NodeOp::Set(_) | NodeOp::Neg | NodeOp::Not => 1,
+            NodeOp::Select => 3,
+            _ => 2,
+        }
+    }
+}
+
+impl LogicGraph {
+    /// Resolves node inputs and the order of evaluation, failing if the
+    /// graph references unknown nodes, has nodes with missing inputs,
+    /// or contains cycles.
+    pub fn prepare(&mut self) -> Result<()> {
+        let ids = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(n, node)| (node.id.as_str(), n))
+            .collect::<HashMap<_, _>>();
+        if ids.len() != self.nodes.len() {
+            return Err(graph_error("node ids must be unique".to_string()));
+        }
+
+        let mut inputs = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            if node.inputs.len() < node.op.arity() {
+                return Err(graph_error(format!(
+                    "node \"{}\" requires {} inputs, got {}",
+                    node.id,
+                    node.op.arity(),
+                    node.inputs.len()
+                )));
+            }
+            let mut node_inputs = Vec::with_capacity(node.inputs.len());
+            for input in &node.inputs {
+                match ids.get(input.as_str()) {
+                    Some(n) => node_inputs.push(*n),
+                    None => {
+                        return Err(graph_error(format!(
+                            "node \"{}\" references unknown node \"{}\"",
+                            node.id, input
+                        )))
+                    }
+                }
+            }
+            inputs.push(node_inputs);
+        }
+
+        // topological sort, visiting inputs of each node before the node
+        // itself
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut marks = vec![Mark::None; self.nodes.len()];
+        for n in 0..self.nodes.len() {
+            self.visit(n, &inputs, &mut marks, &mut order)?;
+        }
+
+        self.order = order;
+        self.inputs = inputs;
+        Ok(())
+    }
+
+    fn visit(
+        &self,
+        n: usize,
+        inputs: &Vec<Vec<usize>>,
+        marks: &mut Vec<Mark>,
+        order: &mut Vec<usize>,
+    ) -> Result<()> {
+        match marks[n] {
+            Mark::Done => return Ok(()),
+            Mark::Visiting => {
+                return Err(graph_error(format!(
+                    "cycle detected at node \"{}\"",
+                    self.nodes[n].id
+                )))
+            }
+            Mark::None => (),
+        }
+        marks[n] = Mark::Visiting;
+        for input in &inputs[n] {
+            self.visit(*input, inputs, marks, order)?;
+        }
+        marks[n] = Mark::Done;
+        order.push(n);
+        Ok(())
+    }
+
+    /// Compiles all command nodes into commands appended to the logic,
+    /// replacing them with references to the appended lines.
+    #[cfg(feature = "machine_script")]
+    pub fn compile(&mut self, logic: &mut LogicModel, comp_name: &CompName) -> Result<()> {
+        use super::behavior::BehaviorNode;
+
+        for node in &mut self.nodes {
+            if let NodeOp::Command(line) = &node.op {
+                let mut command = BehaviorNode::Command(line.clone());
+                command.compile(logic, comp_name)?;
+                if let BehaviorNode::Lines(start, end) = command {
+                    node.op = NodeOp::Lines(start, end);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'de> Deserialize<'de> for LogicGraph {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let mut graph = LogicGraph {
+            nodes: Vec::deserialize(deserializer)?,
+            ..Default::default()
+        };
+        graph.prepare().map_err(serde::de::Error::custom)?;
+        Ok(graph)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mark {
+    None,
+    Visiting,
+    Done,
+}
+
+impl<'a> TickContext<'a> {
+    /// Evaluates all the nodes of a prepared graph.
+    pub fn evaluate(&mut self, graph: &LogicGraph) -> Result<()> {
+        let mut values: Vec<Var> = vec![Var::Bool(false); graph.nodes.len()];
+        for n in &graph.order {
+            let node = &graph.nodes[*n];
+            let inputs = graph.inputs[*n]
+                .iter()
+                .map(|i| &values[*i])
+                .collect::<Vec<_>>();
+            let value = match &node.op {
+                NodeOp::Value(var) => var.clone(),
+                NodeOp::Get(address) => {
+                    let index = self.var_index(address)?;
+                    self.storage
+                        .get_var(&index)
+                        .map_err(|e| {
+                            Error::new(
+                                LocationInfo::empty(),
+                                ErrorKind::FailedGettingFromStorage(e.to_string()),
+                            )
+                        })?
+                        .clone()
+                }
+                NodeOp::Set(address) => {
+                    let index = self.var_index(address)?;
+                    let value = inputs[0].clone();
+                    self.storage
+                        .get_var_mut(&index)
+                        .map_err(|e| {
+                            Error::new(
+                                LocationInfo::empty(),
+                                ErrorKind::FailedGettingFromStorage(e.to_string()),
+                            )
+                        })?
+                        .set_coerce(&value)?;
+                    value
+                }
+                NodeOp::Add => numeric(node, inputs[0], inputs[1], Int::checked_add, |a, b| a + b)?,
+                NodeOp::Sub => numeric(node, inputs[0], inputs[1], Int::checked_sub, |a, b| a - b)?,
+                NodeOp::Mul => numeric(node, inputs[0], inputs[1], Int::checked_mul, |a, b| a * b)?,
+                NodeOp::Div => {
+                    if inputs[0].is_int() && inputs[1].is_int() && inputs[1].to_int() == 0 {
+                        return Err(graph_error(format!(
+                            "division by zero at node \"{}\"",
+                            node.id
+                        )));
+                    }
+                    numeric(node, inputs[0], inputs[1], Int::checked_div, |a, b| a / b)?
+                }
+                NodeOp::Min => numeric(
+                    node,
+                    inputs[0],
+                    inputs[1],
+                    |a, b| Some(a.min(b)),
+                    |a, b| a.min(b),
+                )?,
+                NodeOp::Max => numeric(
+                    node,
+                    inputs[0],
+                    inputs[1],
+                    |a, b| Some(a.max(b)),
+                    |a, b| a.max(b),
+                )?,
+                NodeOp::Neg => match inputs[0] {
+                    Var::Int(v) => Var::Int(v.checked_neg().ok_or_else(|| overflow(node))?),
+                    v => Var::Float(-v.to_float()),
+                },
+                NodeOp::Eq => Var::Bool(inputs[0] == inputs[1]),
+                NodeOp::Lt => Var::Bool(inputs[0].to_float() < inputs[1].to_float()),
+                NodeOp::Gt => Var::Bool(inputs[0].to_float() > inputs[1].to_float()),
+                NodeOp::And => Var::Bool(inputs[0].to_bool() && inputs[1].to_bool()),
+                NodeOp::Or => Var::Bool(inputs[0].to_bool() || inputs[1].to_bool()),
+                NodeOp::Not => Var::Bool(!inputs[0].to_bool()),
+                NodeOp::Select => {
+                    if inputs[0].to_bool() {
+                        inputs[1].clone()
+                    } else {
+                        inputs[2].clone()
+                    }
+                }
+                NodeOp::Lines(start, end) => {
+                    if inputs.first().map(|v| v.to_bool()).unwrap_or(true) {
+                        let status = self.execute_lines(*start, *end)?;
+                        Var::Bool(status == BehaviorStatus::Success)
+                    } else {
+                        Var::Bool(false)
+                    }
+                }
+                NodeOp::Command(line) => {
+                    warn!(
+                        "graph of component \"{}\": command was not compiled: {}",
+                        self.comp_uid, line
+                    );
+                    Var::Bool(false)
+                }
+            };
+            values[*n] = value;
+        }
+        Ok(())
+    }
+
+    fn var_index(&self, address: &str) -> Result<(CompName, crate::VarName)> {
+        let addr = ShortLocalAddress::from_str(address).map_err(|e| {
+            Error::new(
+                LocationInfo::empty(),
+                ErrorKind::InvalidAddress(e.to_string()),
+            )
+        })?;
+        let comp = addr.comp.unwrap_or_else(|| self.comp_uid.clone());
+        Ok((comp, addr.var_name))
+    }
+}
+
+/// Applies the operation, keeping ints as ints and converting to floats
+/// otherwise. Integer operation returning `None` is reported as overflow.
+fn numeric(
+    node: &GraphNode,
+    a: &Var,
+    b: &Var,
+    int_op: impl Fn(Int, Int) -> Option<Int>,
+    float_op: impl Fn(crate::Float, crate::Float) -> crate::Float,
+) -> Result<Var> {
+    match (a, b) {
+        (Var::Int(a), Var::Int(b)) => int_op(*a, *b).map(Var::Int).ok_or_else(|| overflow(node)),
+        _ => Ok(Var::Float(float_op(a.to_float(), b.to_float()))),
+    }
+}
+
+fn overflow(node: &GraphNode) -> Error {
+    graph_error(format!("integer overflow at node \"{}\"", node.id))
+}
+
+fn graph_error(msg: String) -> Error {
+    Error::new(LocationInfo::empty(), ErrorKind::Other(msg))
+}
+
+#[test]
+fn graph_order_restored_on_deserialize() {
+    let node = |id: &str, op, inputs: &[&str]| GraphNode {
+        id: id.to_string(),
+        op,
+        inputs: inputs.iter().map(|i| i.to_string()).collect(),
+    };
+    let mut graph = LogicGraph {
+        nodes: vec![
+            node("set_x", NodeOp::Set("float:x".to_string()), &["next_x"]),
+            node("next_x", NodeOp::Add, &["x", "x"]),
+            node("x", NodeOp::Get("float:x".to_string()), &[]),
+        ],
+        ..Default::default()
+    };
+    graph.prepare().unwrap();
+    assert_eq!(graph.order, vec![2, 1, 0]);
+
+    let bytes = bincode::serialize(&graph).unwrap();
+    let restored: LogicGraph = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(restored, graph);
+}
+
+#[test]
+fn graph_int_overflow_is_error() {
+    let node = GraphNode {
+        id: "sum".to_string(),
+        op: NodeOp::Add,
+        inputs: Vec::new(),
+    };
+    let max = Var::Int(Int::MAX);
+    assert!(numeric(&node, &max, &Var::Int(1), Int::checked_add, |a, b| a + b).is_err());
+    assert!(numeric(
+        &node,
+        &Var::Int(Int::MIN),
+        &Var::Int(-1),
+        Int::checked_div,
+        |a, b| a / b
+    )
+    .is_err());
+    assert_eq!(
+        numeric(&node, &max, &Var::Float(1.), Int::checked_add, |a, b| a + b).unwrap(),
+        Var::Float(Int::MAX as crate::Float + 1.)
+    );
+}
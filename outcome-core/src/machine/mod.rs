@@ -1,11 +1,18 @@
 //! Logic execution capability for the runtime.
 
+pub mod behavior;
+pub mod budget;
 pub mod cmd;
 pub mod error;
 pub mod exec;
+pub mod graph;
 pub mod script;
+pub mod system;
+pub mod watchdog;
 
+pub use budget::ExecBudget;
 pub use error::{Error, ErrorKind, Result};
+pub use watchdog::{WatchdogAction, WatchdogConfig};
 
 use arrayvec::ArrayVec;
 use smallvec::SmallVec;
@@ -16,6 +23,9 @@ use crate::{CompName, EntityId, EntityName, LongString, ShortString, StringId, V
 use std::collections::BTreeMap;
 
 pub const START_STATE_NAME: &'static str = "start";
+/// Reserved state of components disabled after their execution panicked or
+/// was aborted by the watchdog.
+pub const DISABLED_STATE_NAME: &'static str = "_disabled";
 
 #[cfg(feature = "machine_dynlib")]
 pub type Libraries = BTreeMap<String, Library>;
@@ -83,6 +93,12 @@ pub struct CommandPrototype {
 //TODO determine optimal size, determine whether it should be fixed size or not
 pub(crate) type CallStackVec = ArrayVec<[CallInfo; 32]>;
 
+/// Max call stack depth at which new procedure calls are still allowed.
+///
+/// Kept below the call stack capacity to leave room for blocks nested within
+/// the most deeply called procedure.
+pub const MAX_CALL_DEPTH: usize = 24;
+
 /// Collection type used to hold command results.
 pub(crate) type CommandResultVec = SmallVec<[cmd::CommandResult; 2]>;
 
@@ -138,6 +154,7 @@ pub enum CallInfo {
 /// Information about a single procedure call.
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct ProcedureCallInfo {
+    pub proc_name: ShortString,
     pub call_line: usize,
     pub start_line: usize,
     pub end_line: usize,
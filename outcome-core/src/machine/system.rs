@@ -0,0 +1,658 @@
+//! Systems as model-level logic operating over sets of entities.
+//!
+//! Unlike component logic, which runs separately for each component
+//! instance, a system declares a query and runs once per triggering event
+//! over the whole set of matched entities. This makes systems a good fit
+//! for passes that need to look at many entities at once, for example
+//! computing the center of a flock.
+//!
+//! Systems are declared in structured data files, alongside components.
+//!
+//! ```yaml
+//! systems:
+//!   flock_center:
+//!     query: [position, flock]
+//!     triggers: [step]
+//!     ops:
+//!       - reduce: mean
+//!         var: position:float:x
+//!         as: center_x
+//!       - eval: "x + (center_x - x) * 0.01"
+//!         args: { x: position:float:x }
+//!         out: position:float:x
+//! ```
+//!
+//! # Operations
+//!
+//! - `reduce` reduces the var at the given address across all matched
+//!   entities, using one of `sum`, `mean`, `min`, `max` or `count`, making
+//!   the result available to subsequent operations under the given name
+//! - `eval` evaluates an expression for each matched entity, writing the
+//!   result to the output var, with `args` mapping expression names to
+//!   the entity's vars, and all previously reduced values also available
+//!
+//! Operations are executed in the order they are declared. Vars used by
+//! a reduction are first gathered into a single column, and expressions
+//! are evaluated for all matched entities in parallel.
+//!
+//! Addresses used by systems must always include the component name.
+//!
+//! # Scheduling
+//!
+//! Systems can declare the vars they `reads` and `writes`, which are
+//! otherwise derived from their operations, and the systems they need to
+//! run `after`. Operations accessing undeclared vars are rejected when
+//! loading the model.
+//!
+//! ```yaml
+//! systems:
+//!   flock_steer:
+//!     query: [position, flock]
+//!     triggers: [step]
+//!     reads: [position:float:x, flock:float:center_x]
+//!     writes: [position:float:x]
+//!     after: [flock_center]
+//! ```
+//!
+//! Systems are scheduled into stages, see [`SystemSchedule`]. Systems that
+//! access the same vars, where at least one of them writes, must have
+//! their order declared, otherwise loading the model fails.
+//!
+//! In a distributed setting each node runs systems over the entities it
+//! stores locally, which means reductions only cover those entities.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use fasteval::{Compiler, Evaler};
+use fnv::FnvHashMap;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::address::ShortLocalAddress;
+use crate::entity::{Entity, StorageIndex};
+use crate::{string, CompName, EntityId, EventName, StringId, Var};
+
+use super::error::{Error, ErrorKind, Result};
+use super::{ExecutionContext, LocationInfo};
+
+/// Model-level logic block running over all entities matching a query.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SystemModel {
+    /// String identifier of the system
+    pub name: StringId,
+    /// Components an entity needs to have to be matched
+    pub query: Vec<CompName>,
+    /// List of events that serve as triggers for the system
+    pub triggers: Vec<EventName>,
+    /// Operations executed in order each time the system is triggered
+    pub ops: Vec<SystemOp>,
+    /// Vars the system reads
+    pub reads: Vec<StorageIndex>,
+    /// Vars the system writes
+    pub writes: Vec<StorageIndex>,
+    /// Systems that need to run before this one
+    pub after: Vec<StringId>,
+}
+
+/// System as declared in structured data files.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SystemEntry {
+    #[serde(default)]
+    pub query: Vec<String>,
+    #[serde(default)]
+    pub triggers: Vec<String>,
+    #[serde(default)]
+    pub ops: Vec<SystemOp>,
+    /// Declared var read access, derived from operations if not declared
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reads: Option<Vec<String>>,
+    /// Declared var write access, derived from operations if not declared
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub writes: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub after: Vec<String>,
+}
+
+/// Single operation of a system.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SystemOp {
+    Reduce {
+        reduce: Reduction,
+        /// Address of the reduced var, e.g. `position:float:x`
+        var: String,
+        /// Name under which the result is available to later operations
+        #[serde(rename = "as")]
+        name: String,
+    },
+    Eval {
+        eval: String,
+        /// Mapping of expression names to var addresses
+        #[serde(default)]
+        args: BTreeMap<String, String>,
+        /// Address of the var the result is written to
+        out: String,
+    },
+}
+
+/// Reduction applied to a column of values.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reduction {
+    Sum,
+    Mean,
+    Min,
+    Max,
+    Count,
+}
+
+impl Reduction {
+    /// Reduces the column into a single value.
+    pub fn apply(&self, column: &[f64]) -> f64 {
+        match self {
+            Reduction::Sum => column.iter().sum(),
+            Reduction::Mean => {
+                if column.is_empty() {
+                    0.
+                } else {
+                    column.iter().sum::<f64>() / column.len() as f64
+                }
+            }
+            Reduction::Min => column.iter().cloned().fold(f64::INFINITY, f64::min),
+            Reduction::Max => column.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            Reduction::Count => column.len() as f64,
+        }
+    }
+}
+
+impl SystemModel {
+    /// Creates a new system model from a structured data entry, checking
+    /// that its operations only access declared vars.
+    pub fn from_deser(key: &str, val: SystemEntry) -> Result<Self> {
+        let mut system = SystemModel {
+            name: string::new_truncate(key),
+            query: val.query.iter().map(|c| string::new_truncate(c)).collect(),
+            triggers: val
+                .triggers
+                .iter()
+                .map(|t| string::new_truncate(t))
+                .collect(),
+            ops: val.ops,
+            after: val.after.iter().map(|s| string::new_truncate(s)).collect(),
+            ..Default::default()
+        };
+        let (reads, writes) = system.op_access()?;
+        system.reads = match val.reads {
+            Some(declared) => declared
+                .iter()
+                .map(|a| storage_index(a))
+                .collect::<Result<_>>()?,
+            None => reads.clone(),
+        };
+        system.writes = match val.writes {
+            Some(declared) => declared
+                .iter()
+                .map(|a| storage_index(a))
+                .collect::<Result<_>>()?,
+            None => writes.clone(),
+        };
+        for index in &writes {
+            if !system.writes.contains(index) {
+                return Err(system.error(ErrorKind::Initialization(format!(
+                    "system \"{}\" writes undeclared var: {}",
+                    system.name,
+                    index_str(index)
+                ))));
+            }
+        }
+        for index in &reads {
+            if !system.reads.contains(index) && !system.writes.contains(index) {
+                return Err(system.error(ErrorKind::Initialization(format!(
+                    "system \"{}\" reads undeclared var: {}",
+                    system.name,
+                    index_str(index)
+                ))));
+            }
+        }
+        Ok(system)
+    }
+
+    /// Collects the vars read and written by the system's operations.
+    fn op_access(&self) -> Result<(Vec<StorageIndex>, Vec<StorageIndex>)> {
+        let (mut reads, mut writes) = (Vec::new(), Vec::new());
+        for op in &self.ops {
+            match op {
+                SystemOp::Reduce { var, .. } => reads.push(storage_index(var)?),
+                SystemOp::Eval { args, out, .. } => {
+                    writes.push(storage_index(out)?);
+                    for addr in args.values() {
+                        reads.push(storage_index(addr)?);
+                    }
+                }
+            }
+        }
+        Ok((reads, writes))
+    }
+
+    /// Checks whether running the two systems in parallel could result
+    /// in a data race, or in results depending on the order of execution.
+    pub fn conflicts_with(&self, other: &SystemModel) -> bool {
+        self.writes
+            .iter()
+            .any(|w| other.writes.contains(w) || other.reads.contains(w))
+            || other.writes.iter().any(|w| self.reads.contains(w))
+    }
+
+    /// Checks whether the system is triggered by any of the events.
+    pub fn is_triggered(&self, events: &[EventName]) -> bool {
+        self.triggers.iter().any(|t| events.contains(t))
+    }
+
+    /// Checks whether the entity is matched by the system's query.
+    pub fn matches(&self, entity: &Entity) -> bool {
+        self.query.iter().all(|c| entity.components.contains(c))
+    }
+
+    /// Runs all the operations over the matched entities.
+    ///
+    /// Errors for individual entities are collected and returned, while
+    /// processing of other entities continues. An error is only returned
+    /// directly if an expression fails to compile.
+    pub fn run(
+        &self,
+        entities: &mut FnvHashMap<EntityId, Entity>,
+    ) -> Result<Vec<(ExecutionContext, Error)>> {
+        let output = self.compute(entities)?;
+        output.apply(entities);
+        Ok(output.errors)
+    }
+
+    /// Computes the results of all the operations without mutating the
+    /// entities, returning the resulting writes.
+    ///
+    /// Later operations see the values written by earlier ones.
+    pub fn compute(&self, entities: &FnvHashMap<EntityId, Entity>) -> Result<SystemOutput> {
+        let mut output = SystemOutput::default();
+        let mut values: BTreeMap<String, f64> = BTreeMap::new();
+
+        for op in &self.ops {
+            match op {
+                SystemOp::Reduce { reduce, var, name } => {
+                    let index = storage_index(var)?;
+                    #[cfg(feature = "parallel")]
+                    let iter = entities.par_iter();
+                    #[cfg(not(feature = "parallel"))]
+                    let iter = entities.iter();
+                    let column = iter
+                        .filter(|(_, entity)| self.matches(entity))
+                        .filter_map(|(ent_id, entity)| output.get_var(*ent_id, entity, &index))
+                        .map(|v| v.to_float() as f64)
+                        .collect::<Vec<f64>>();
+                    values.insert(name.clone(), reduce.apply(&column));
+                }
+                SystemOp::Eval { eval, args, out } => {
+                    let mut slab = fasteval::Slab::new();
+                    let expr = fasteval::Parser::new()
+                        .parse(eval, &mut slab.ps)
+                        .map_err(|e| self.error(ErrorKind::ParseError(e.to_string())))?
+                        .from(&slab.ps)
+                        .compile(&slab.ps, &mut slab.cs);
+                    let out_index = storage_index(out)?;
+                    let args = args
+                        .iter()
+                        .map(|(name, addr)| Ok((name.clone(), storage_index(addr)?)))
+                        .collect::<Result<Vec<(String, StorageIndex)>>>()?;
+
+                    #[cfg(feature = "parallel")]
+                    let iter = entities.par_iter();
+                    #[cfg(not(feature = "parallel"))]
+                    let iter = entities.iter();
+                    let results = iter
+                        .filter(|(_, entity)| self.matches(entity))
+                        .map(|(ent_id, entity)| {
+                            let mut ns = values.clone();
+                            for (name, index) in &args {
+                                match output.get_var(*ent_id, entity, index) {
+                                    Some(var) => {
+                                        ns.insert(name.clone(), var.to_float() as f64);
+                                    }
+                                    None => return (*ent_id, Err(index_missing(index))),
+                                }
+                            }
+                            let val = match expr.eval(&slab, &mut ns) {
+                                Ok(v) => v,
+                                Err(e) => return (*ent_id, Err(e.to_string())),
+                            };
+                            match output.get_var(*ent_id, entity, &out_index) {
+                                Some(Var::Int(_)) => (*ent_id, Ok(Var::Int(val as crate::Int))),
+                                Some(_) => (*ent_id, Ok(Var::Float(val as crate::Float))),
+                                None => (*ent_id, Err(index_missing(&out_index))),
+                            }
+                        })
+                        .collect::<Vec<(EntityId, std::result::Result<Var, String>)>>();
+
+                    for (ent_id, result) in results {
+                        match result {
+                            Ok(var) => {
+                                output.writes.insert((ent_id, out_index.clone()), var);
+                            }
+                            Err(msg) => output.errors.push((
+                                ExecutionContext {
+                                    ent: ent_id,
+                                    comp: self.name.clone(),
+                                    location: LocationInfo::empty(),
+                                },
+                                self.error(ErrorKind::FailedGettingFromStorage(msg)),
+                            )),
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(output)
+    }
+
+    fn error(&self, kind: ErrorKind) -> Error {
+        let mut location = LocationInfo::empty();
+        location.comp_name = Some(self.name.clone());
+        Error::new(location, kind)
+    }
+}
+
+/// Results of running a system, not yet applied to the entities.
+#[derive(Debug, Default)]
+pub struct SystemOutput {
+    /// Values to be written, the last value written to each var
+    pub writes: FnvHashMap<(EntityId, StorageIndex), Var>,
+    /// Errors for individual entities
+    pub errors: Vec<(ExecutionContext, Error)>,
+}
+
+impl SystemOutput {
+    /// Applies all the writes to the entities.
+    pub fn apply(&self, entities: &mut FnvHashMap<EntityId, Entity>) {
+        for ((ent_id, index), var) in &self.writes {
+            if let Some(entity) = entities.get_mut(ent_id) {
+                if let Ok(target) = entity.storage.get_var_mut(index) {
+                    *target = var.clone();
+                }
+            }
+        }
+    }
+
+    /// Gets the var, taking into account values written so far.
+    fn get_var<'a>(
+        &'a self,
+        ent_id: EntityId,
+        entity: &'a Entity,
+        index: &StorageIndex,
+    ) -> Option<&'a Var> {
+        self.writes
+            .get(&(ent_id, index.clone()))
+            .or_else(|| entity.storage.get_var(index).ok())
+    }
+}
+
+/// Execution order of systems, grouped into stages.
+///
+/// Stages are run one after another, while systems within a single stage
+/// are run in parallel. Systems are placed into stages based on their
+/// declared `after` dependencies. Systems accessing the same vars, with
+/// at least one of them writing, are required to be ordered, either
+/// directly or transitively, otherwise the schedule can't be created.
+#[derive(Debug, Clone, Default)]
+pub struct SystemSchedule {
+    /// Indices into the list of systems for each stage
+    pub stages: Vec<Vec<usize>>,
+}
+
+impl SystemSchedule {
+    /// Creates a schedule for the systems, failing on unknown or cyclic
+    /// dependencies and on conflicting systems with undeclared order.
+    pub fn new(systems: &[SystemModel]) -> Result<Self> {
+        let mut deps = Vec::with_capacity(systems.len());
+        for system in systems {
+            let mut system_deps = Vec::new();
+            for name in &system.after {
+                match systems.iter().position(|s| &s.name == name) {
+                    Some(n) => system_deps.push(n),
+                    None => {
+                        return Err(system.error(ErrorKind::Initialization(format!(
+                            "system \"{}\" is declared to run after unknown system \"{}\"",
+                            system.name, name
+                        ))))
+                    }
+                }
+            }
+            deps.push(system_deps);
+        }
+
+        // stage of each system is one past the latest of its dependencies
+        let mut levels = vec![None; systems.len()];
+        for n in 0..systems.len() {
+            level(n, systems, &deps, &mut levels, &mut Vec::new())?;
+        }
+        let levels = levels
+            .into_iter()
+            .map(|l| l.unwrap())
+            .collect::<Vec<usize>>();
+
+        // all the transitive dependencies of each system
+        let mut preceding = vec![Vec::new(); systems.len()];
+        for n in 0..systems.len() {
+            let mut stack = deps[n].clone();
+            while let Some(d) = stack.pop() {
+                if !preceding[n].contains(&d) {
+                    preceding[n].push(d);
+                    stack.extend(deps[d].iter());
+                }
+            }
+        }
+
+        for a in 0..systems.len() {
+            for b in (a + 1)..systems.len() {
+                if systems[a].conflicts_with(&systems[b])
+                    && !preceding[a].contains(&b)
+                    && !preceding[b].contains(&a)
+                {
+                    return Err(systems[b].error(ErrorKind::Initialization(format!(
+                        "systems \"{}\" and \"{}\" access the same vars, \
+                        one of them needs to be declared to run after the other",
+                        systems[a].name, systems[b].name
+                    ))));
+                }
+            }
+        }
+
+        let mut stages = vec![Vec::new(); levels.iter().max().map(|l| l + 1).unwrap_or(0)];
+        for (n, l) in levels.iter().enumerate() {
+            stages[*l].push(n);
+        }
+        Ok(SystemSchedule { stages })
+    }
+
+    /// Runs all the systems triggered by any of the events, stage by stage.
+    ///
+    /// Returns errors for individual entities, see [`SystemModel::run`].
+    pub fn run(
+        &self,
+        systems: &[SystemModel],
+        events: &[EventName],
+        entities: &mut FnvHashMap<EntityId, Entity>,
+    ) -> Result<Vec<(ExecutionContext, Error)>> {
+        let mut errors = Vec::new();
+        for stage in &self.stages {
+            let shared: &FnvHashMap<EntityId, Entity> = entities;
+            #[cfg(feature = "parallel")]
+            let iter = stage.par_iter();
+            #[cfg(not(feature = "parallel"))]
+            let iter = stage.iter();
+            let outputs = iter
+                .map(|n| &systems[*n])
+                .filter(|system| system.is_triggered(events))
+                .map(|system| system.compute(shared))
+                .collect::<Result<Vec<SystemOutput>>>()?;
+            for output in outputs {
+                output.apply(entities);
+                errors.extend(output.errors);
+            }
+        }
+        Ok(errors)
+    }
+}
+
+fn level(
+    n: usize,
+    systems: &[SystemModel],
+    deps: &Vec<Vec<usize>>,
+    levels: &mut Vec<Option<usize>>,
+    visiting: &mut Vec<usize>,
+) -> Result<usize> {
+    if let Some(l) = levels[n] {
+        return Ok(l);
+    }
+    if visiting.contains(&n) {
+        return Err(systems[n].error(ErrorKind::Initialization(format!(
+            "cyclic order declared for system \"{}\"",
+            systems[n].name
+        ))));
+    }
+    visiting.push(n);
+    let mut l = 0;
+    for d in &deps[n] {
+        l = l.max(level(*d, systems, deps, levels, visiting)? + 1);
+    }
+    visiting.pop();
+    levels[n] = Some(l);
+    Ok(l)
+}
+
+fn index_str(index: &StorageIndex) -> String {
+    format!("{}:{}", index.0, index.1)
+}
+
+fn index_missing(index: &StorageIndex) -> String {
+    format!("missing var: {}", index_str(index))
+}
+
+/// Parses the address, requiring the component to be specified.
+fn storage_index(address: &str) -> Result<StorageIndex> {
+    let addr = ShortLocalAddress::from_str(address).map_err(|e| {
+        Error::new(
+            LocationInfo::empty(),
+            ErrorKind::InvalidAddress(e.to_string()),
+        )
+    })?;
+    match addr.comp {
+        Some(comp) => Ok((comp, addr.var_name)),
+        None => Err(Error::new(
+            LocationInfo::empty(),
+            ErrorKind::InvalidAddress(format!(
+                "system var address must include component: {}",
+                address
+            )),
+        )),
+    }
+}
+
+#[test]
+fn system_runs_over_matched_entities() {
+    let position = |x: crate::Float, flock: bool| {
+        let mut entity = Entity::empty();
+        entity.components.push(string::new_truncate("position"));
+        entity.storage.insert(
+            (string::new_truncate("position"), string::new_truncate("x")),
+            Var::Float(x),
+        );
+        if flock {
+            entity.components.push(string::new_truncate("flock"));
+        }
+        entity
+    };
+    let mut entities = FnvHashMap::default();
+    entities.insert(1, position(0., true));
+    entities.insert(2, position(4., true));
+    entities.insert(3, position(100., false));
+
+    let mut args = BTreeMap::new();
+    args.insert("x".to_string(), "position:float:x".to_string());
+    let entry = SystemEntry {
+        query: vec!["position".to_string(), "flock".to_string()],
+        triggers: vec!["step".to_string()],
+        ops: vec![
+            SystemOp::Reduce {
+                reduce: Reduction::Mean,
+                var: "position:float:x".to_string(),
+                name: "center_x".to_string(),
+            },
+            SystemOp::Eval {
+                eval: "x + (center_x - x) * 0.5".to_string(),
+                args,
+                out: "position:float:x".to_string(),
+            },
+        ],
+        ..Default::default()
+    };
+    let system = SystemModel::from_deser("flock_center", entry.clone()).unwrap();
+    assert!(system.is_triggered(&[string::new_truncate("step")]));
+
+    assert!(system.run(&mut entities).unwrap().is_empty());
+    let x = |ent_id: EntityId| {
+        entities[&ent_id]
+            .storage
+            .get_var(&(string::new_truncate("position"), string::new_truncate("x")))
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(x(1), Var::Float(1.));
+    assert_eq!(x(2), Var::Float(3.));
+    assert_eq!(x(3), Var::Float(100.));
+
+    // writes not covered by the declaration are rejected
+    let undeclared = SystemEntry {
+        writes: Some(Vec::new()),
+        ..entry
+    };
+    assert!(SystemModel::from_deser("flock_center", undeclared).is_err());
+}
+
+#[test]
+fn schedule_orders_conflicting_systems() {
+    let x = (string::new_truncate("position"), string::new_truncate("x"));
+    let y = (string::new_truncate("position"), string::new_truncate("y"));
+    let system = |name: &str, reads: &[&StorageIndex], writes: &[&StorageIndex], after: &[&str]| {
+        SystemModel {
+            name: string::new_truncate(name),
+            reads: reads.iter().map(|i| (*i).clone()).collect(),
+            writes: writes.iter().map(|i| (*i).clone()).collect(),
+            after: after.iter().map(|a| string::new_truncate(a)).collect(),
+            ..Default::default()
+        }
+    };
+
+    let systems = vec![
+        system("steer", &[&x], &[&x], &["center"]),
+        system("center", &[&x], &[], &[]),
+        system("lift", &[], &[&y], &[]),
+    ];
+    let schedule = SystemSchedule::new(&systems).unwrap();
+    assert_eq!(schedule.stages, vec![vec![1, 2], vec![0]]);
+
+    // reading a var written by another system requires declared order
+    let systems = vec![
+        system("steer", &[], &[&x], &[]),
+        system("center", &[&x], &[], &[]),
+    ];
+    assert!(SystemSchedule::new(&systems).is_err());
+
+    let systems = vec![
+        system("steer", &[], &[], &["center"]),
+        system("center", &[], &[], &["steer"]),
+    ];
+    assert!(SystemSchedule::new(&systems).is_err());
+
+    let systems = vec![system("steer", &[], &[], &["missing"])];
+    assert!(SystemSchedule::new(&systems).is_err());
+}
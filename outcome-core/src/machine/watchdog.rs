@@ -0,0 +1,265 @@
+//! Watchdog guarding against steps that get stuck.
+//!
+//! Unlike the execution budget, which is part of the model, the watchdog is
+//! a runtime setting of the simulation instance. When enabled, a separate
+//! thread is started along with the first step and then armed for the
+//! duration of the local execution phase of each step. If the phase exceeds
+//! the configured wall-time, progress information about all the entities
+//! still being processed is logged, and the configured action is taken.
+//!
+//! Aborting components is scoped to the entities that have been executing
+//! their current component for longer than the time limit. Once such entity
+//! moves on to its next component it's no longer considered aborted.
+//!
+//! Aborting is cooperative: running logic is checked before each executed
+//! command, so a single call that never returns, e.g. into a dynamic
+//! library, can be reported but not interrupted.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+
+use crate::{string, CompName, EntityId};
+
+/// Action taken once a step exceeds the watchdog time limit.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchdogAction {
+    /// Only report progress of the stuck entities
+    Report,
+    /// Suspend execution of all the entities until the next step
+    AbortStep,
+    /// Suspend execution of the components that have been running for
+    /// longer than the time limit and disable them
+    AbortComponent,
+}
+
+impl Default for WatchdogAction {
+    fn default() -> Self {
+        WatchdogAction::Report
+    }
+}
+
+/// Watchdog configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchdogConfig {
+    /// Max wall-time of the local execution phase, in milliseconds
+    pub step_millis: u64,
+    /// Action taken once the time limit is exceeded
+    #[serde(default)]
+    pub action: WatchdogAction,
+}
+
+/// Execution progress of a single entity.
+pub(crate) struct EntityProgress {
+    comp: Mutex<CompName>,
+    /// Time the execution of the current component started
+    entered: Mutex<Instant>,
+    cmd: AtomicUsize,
+    cmds: AtomicUsize,
+    abort: AtomicBool,
+}
+
+impl EntityProgress {
+    /// Marks the start of execution of a component. Abort requested for
+    /// the previous component no longer applies.
+    pub fn enter(&self, comp: &CompName) {
+        *self.comp.lock().unwrap() = comp.clone();
+        *self.entered.lock().unwrap() = Instant::now();
+        self.abort.store(false, Ordering::Relaxed);
+    }
+
+    /// Accounts for a command about to be executed.
+    pub fn command(&self, cmd_n: usize) {
+        self.cmd.store(cmd_n, Ordering::Relaxed);
+        self.cmds.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Checks whether the component being executed was marked for aborting
+    /// by the watchdog.
+    pub fn is_aborted(&self) -> bool {
+        self.abort.load(Ordering::Relaxed)
+    }
+}
+
+/// Progress of all the entities being processed during a single step.
+#[derive(Default)]
+pub(crate) struct Progress {
+    entities: Mutex<FnvHashMap<EntityId, Arc<EntityProgress>>>,
+    abort_step: AtomicBool,
+}
+
+impl Progress {
+    /// Registers an entity as being processed.
+    pub fn register(&self, ent: EntityId) -> Arc<EntityProgress> {
+        let progress = Arc::new(EntityProgress {
+            comp: Mutex::new(string::new_truncate("")),
+            entered: Mutex::new(Instant::now()),
+            cmd: AtomicUsize::new(0),
+            cmds: AtomicUsize::new(0),
+            abort: AtomicBool::new(false),
+        });
+        self.entities.lock().unwrap().insert(ent, progress.clone());
+        progress
+    }
+
+    /// Removes an entity once it's done processing.
+    pub fn unregister(&self, ent: &EntityId) {
+        self.entities.lock().unwrap().remove(ent);
+    }
+
+    /// Checks whether the whole step was marked for aborting.
+    pub fn is_step_aborted(&self) -> bool {
+        self.abort_step.load(Ordering::Relaxed)
+    }
+
+    /// Logs progress of all the entities still being processed.
+    fn report(&self, clock: usize, elapsed: Duration) {
+        let entities = self.entities.lock().unwrap();
+        error!(
+            "watchdog: step {} running for {}ms, {} entities still processing",
+            clock,
+            elapsed.as_millis(),
+            entities.len()
+        );
+        let mut ents = entities.iter().collect::<Vec<_>>();
+        ents.sort_unstable_by_key(|(ent, _)| **ent);
+        for (ent, progress) in ents {
+            error!(
+                "watchdog: entity {}, component {}, command {}, {} commands executed",
+                ent,
+                progress.comp.lock().unwrap(),
+                progress.cmd.load(Ordering::Relaxed),
+                progress.cmds.load(Ordering::Relaxed)
+            );
+        }
+    }
+
+    /// Takes the action, aborting only the components that have been
+    /// running for longer than the given limit.
+    fn apply(&self, action: WatchdogAction, limit: Duration) {
+        match action {
+            WatchdogAction::Report => (),
+            WatchdogAction::AbortStep => self.abort_step.store(true, Ordering::Relaxed),
+            WatchdogAction::AbortComponent => {
+                for progress in self.entities.lock().unwrap().values() {
+                    if progress.entered.lock().unwrap().elapsed() >= limit {
+                        progress.abort.store(true, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Step currently being watched.
+struct Watch {
+    config: WatchdogConfig,
+    progress: Arc<Progress>,
+    clock: usize,
+    started: Instant,
+    /// Time of the next progress check
+    next: Instant,
+}
+
+#[derive(Default)]
+struct WatchState {
+    watch: Option<Watch>,
+    shutdown: bool,
+}
+
+/// Handle to the watchdog thread, which is reused across steps.
+pub(crate) struct Watchdog {
+    state: Arc<(Mutex<WatchState>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// Starts the watchdog thread, initially not watching anything.
+    pub fn spawn() -> Self {
+        let state = Arc::new((Mutex::new(WatchState::default()), Condvar::new()));
+        let state_ = state.clone();
+        let handle = std::thread::spawn(move || {
+            let (lock, cvar) = &*state_;
+            let mut state = lock.lock().unwrap();
+            while !state.shutdown {
+                let next = match state.watch.as_ref().map(|watch| watch.next) {
+                    Some(next) => next,
+                    None => {
+                        state = cvar.wait(state).unwrap();
+                        continue;
+                    }
+                };
+                let now = Instant::now();
+                if now < next {
+                    state = cvar.wait_timeout(state, next - now).unwrap().0;
+                    continue;
+                }
+                // progress is reported each time the limit elapses
+                if let Some(watch) = &mut state.watch {
+                    let limit = Duration::from_millis(watch.config.step_millis);
+                    watch.progress.report(watch.clock, watch.started.elapsed());
+                    watch.progress.apply(watch.config.action, limit);
+                    watch.next = now + limit;
+                }
+            }
+        });
+        Self {
+            state,
+            handle: Some(handle),
+        }
+    }
+
+    /// Starts watching the progress of the step at the given clock.
+    pub fn arm(&self, config: WatchdogConfig, progress: Arc<Progress>, clock: usize) {
+        let (lock, cvar) = &*self.state;
+        let started = Instant::now();
+        lock.lock().unwrap().watch = Some(Watch {
+            next: started + Duration::from_millis(config.step_millis),
+            config,
+            progress,
+            clock,
+            started,
+        });
+        cvar.notify_one();
+    }
+
+    /// Stops watching the current step.
+    pub fn disarm(&self) {
+        let (lock, cvar) = &*self.state;
+        lock.lock().unwrap().watch = None;
+        cvar.notify_one();
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        let (lock, cvar) = &*self.state;
+        lock.lock().unwrap().shutdown = true;
+        cvar.notify_one();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[test]
+fn abort_is_scoped_to_stuck_components() {
+    let progress = Progress::default();
+    let stuck = progress.register(0);
+    stuck.enter(&string::new_truncate("slow"));
+    std::thread::sleep(Duration::from_millis(20));
+    let fresh = progress.register(1);
+    fresh.enter(&string::new_truncate("fast"));
+
+    progress.apply(WatchdogAction::AbortComponent, Duration::from_millis(10));
+    assert!(stuck.is_aborted());
+    assert!(!fresh.is_aborted());
+
+    // moving on to the next component clears the abort
+    stuck.enter(&string::new_truncate("next"));
+    assert!(!stuck.is_aborted());
+}
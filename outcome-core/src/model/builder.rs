@@ -0,0 +1,412 @@
+//! Programmatic model construction.
+//!
+//! Models are normally created from scenario trees stored on disk. For
+//! examples, tests and embedding it's often more convenient to construct
+//! the model entirely in code, which is what [`SimModelBuilder`] is for.
+//!
+//! ```
+//! use outcome_core::{SimModelBuilder, Var};
+//!
+//! let sim = SimModelBuilder::new()
+//!     .name("flock")
+//!     .dt(0.1)
+//!     .event("step")
+//!     .component("pos", |c| {
+//!         c.var("float:x", Var::Float(0.))
+//!             .var("float:y", Var::Float(0.))
+//!     })
+//!     .component("vel", |c| {
+//!         c.var("float:x", Var::Float(1.))
+//!             .var("float:y", None)
+//!             .trigger("step")
+//!     })
+//!     .prefab("boid", &["pos", "vel"])
+//!     .prefab_default("boid", "vel", "y", Var::Float(0.5))
+//!     .spawn("boid", Some("first_boid"))
+//!     .build_sim()?;
+//! # let name = outcome_core::string::new_truncate("first_boid");
+//! # assert!(sim.get_entity_by_name(&name).is_ok());
+//! # Ok::<(), outcome_core::error::Error>(())
+//! ```
+//!
+//! Errors, such as malformed var declarations, are collected while
+//! building and the first one is returned from [`SimModelBuilder::build`].
+//! The finished model is checked with [`SimModel::validate`], same as
+//! models edited at runtime.
+
+use std::str::FromStr;
+
+use crate::address::ShortLocalAddress;
+use crate::error::Error;
+use crate::model::{ComponentModel, EntityPrefab, EventModel, Scenario, SimModel, VarModel};
+use crate::order::EntityOrder;
+use crate::{string, Float, Result, Sim, Var};
+
+/// Fluent builder for constructing a [`SimModel`], or a [`Sim`] based on
+/// it, without a scenario tree on disk.
+#[derive(Default)]
+pub struct SimModelBuilder {
+    scenario: Scenario,
+    events: Vec<EventModel>,
+    components: Vec<ComponentBuilder>,
+    prefabs: Vec<EntityPrefab>,
+    defaults: Vec<(String, String, String, Var)>,
+    spawns: Vec<(String, Option<String>)>,
+}
+
+impl SimModelBuilder {
+    /// Creates a new builder for an empty model.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the name of the scenario.
+    pub fn name(mut self, name: &str) -> Self {
+        self.scenario.manifest.name = name.to_string();
+        self
+    }
+
+    /// Sets the seed used for sampling random processes.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.scenario.manifest.seed = seed;
+        self
+    }
+
+    /// Sets the duration of a single step.
+    pub fn dt(mut self, dt: Float) -> Self {
+        self.scenario.manifest.dt = Some(dt);
+        self
+    }
+
+    /// Sets the global event invoked whenever an entity is despawned.
+    pub fn despawn_event(mut self, event: &str) -> Self {
+        self.scenario.manifest.despawn_event = Some(event.to_string());
+        self
+    }
+
+    /// Adds a scenario setting.
+    pub fn setting(mut self, key: &str, value: &str) -> Self {
+        self.scenario
+            .manifest
+            .settings
+            .insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Sets the limits on logic execution within a single step.
+    #[cfg(feature = "machine")]
+    pub fn budget(mut self, budget: crate::machine::ExecBudget) -> Self {
+        self.scenario.manifest.budget = budget;
+        self
+    }
+
+    /// Declares an event, with entities executing in the default order.
+    pub fn event(self, name: &str) -> Self {
+        self.event_ordered(name, EntityOrder::default())
+    }
+
+    /// Declares an event, with entities executing in the given order.
+    pub fn event_ordered(mut self, name: &str, order: EntityOrder) -> Self {
+        self.events.push(EventModel {
+            id: string::new_truncate(name),
+            order,
+        });
+        self
+    }
+
+    /// Declares a component, using the closure to describe it.
+    pub fn component<F>(mut self, name: &str, describe: F) -> Self
+    where
+        F: FnOnce(ComponentBuilder) -> ComponentBuilder,
+    {
+        self.components.push(describe(ComponentBuilder::new(name)));
+        self
+    }
+
+    /// Declares an entity prefab made up of the listed components.
+    pub fn prefab(mut self, name: &str, components: &[&str]) -> Self {
+        self.prefabs.push(EntityPrefab {
+            name: string::new_truncate(name),
+            components: components.iter().map(|c| string::new_truncate(c)).collect(),
+            ..EntityPrefab::default()
+        });
+        self
+    }
+
+    /// Overrides the default value of a component var for entities spawned
+    /// from the prefab.
+    pub fn prefab_default(mut self, prefab: &str, comp: &str, var: &str, value: Var) -> Self {
+        self.defaults
+            .push((prefab.to_string(), comp.to_string(), var.to_string(), value));
+        self
+    }
+
+    /// Spawns an entity from the prefab when building a simulation
+    /// instance. Ignored when only building the model.
+    pub fn spawn(mut self, prefab: &str, name: Option<&str>) -> Self {
+        self.spawns
+            .push((prefab.to_string(), name.map(|n| n.to_string())));
+        self
+    }
+
+    /// Builds the model.
+    pub fn build(self) -> Result<SimModel> {
+        let mut model = SimModel::from_scenario(self.scenario)?;
+
+        for event in self.events {
+            if !model.events.iter().any(|e| e.id == event.id) {
+                model.events.push(event);
+            }
+        }
+        #[cfg(feature = "machine_script")]
+        let mut scripts = Vec::new();
+        for component in self.components {
+            if let Some(error) = component.error {
+                return Err(error);
+            }
+            for var in &component.model.vars {
+                if let Some(default) = &var.default {
+                    if !var.within_bounds(default) {
+                        return Err(Error::Other(format!(
+                            "default value of {}:{} out of bounds: {}",
+                            component.model.name, var.name, default
+                        )));
+                    }
+                }
+            }
+            #[cfg(feature = "machine_script")]
+            if let Some(script) = component.script {
+                scripts.push((component.model.name.clone(), script));
+            }
+            if model.get_component(&component.model.name).is_ok() {
+                return Err(Error::Other(format!(
+                    "component already declared: {}",
+                    component.model.name
+                )));
+            }
+            model.components.push(component.model);
+        }
+        for prefab in self.prefabs {
+            for comp in &prefab.components {
+                model.get_component(comp)?;
+            }
+            if model.get_entity(&prefab.name).is_some() {
+                return Err(Error::Other(format!(
+                    "prefab already declared: {}",
+                    prefab.name
+                )));
+            }
+            model.entities.push(prefab);
+        }
+        for (prefab, comp, var, value) in self.defaults {
+            model.set_prefab_default(
+                &string::new_truncate(&prefab),
+                &string::new_truncate(&comp),
+                &string::new_truncate(&var),
+                value,
+            )?;
+        }
+
+        // scripts are compiled once the whole model is known, as the
+        // preprocessor may refer to other parts of it
+        #[cfg(feature = "machine_script")]
+        for (comp_name, script) in scripts {
+            let logic = crate::model::LogicModel::from_script(&script, &comp_name, &mut model)?;
+            if let Some(comp) = model.get_component_mut(&comp_name) {
+                comp.logic = crate::model::LogicModel {
+                    substeps: comp.logic.substeps,
+                    serial: comp.logic.serial,
+                    ..logic
+                };
+            }
+        }
+
+        model.validate()?;
+        Ok(model)
+    }
+
+    /// Builds the model and creates a new simulation instance based on it,
+    /// spawning the requested entities.
+    pub fn build_sim(self) -> Result<Sim> {
+        let spawns = self.spawns.clone();
+        let mut sim = Sim::from_model(self.build()?)?;
+        for (prefab, name) in spawns {
+            sim.spawn_entity(
+                Some(&string::new_truncate(&prefab)),
+                name.map(|n| string::new_truncate(&n)),
+            )?;
+        }
+        Ok(sim)
+    }
+}
+
+/// Fluent builder for a single component, see [`SimModelBuilder::component`].
+pub struct ComponentBuilder {
+    model: ComponentModel,
+    #[cfg(feature = "machine_script")]
+    script: Option<String>,
+    error: Option<Error>,
+}
+
+impl ComponentBuilder {
+    fn new(name: &str) -> Self {
+        Self {
+            model: ComponentModel {
+                name: string::new_truncate(name),
+                #[cfg(feature = "machine")]
+                logic: crate::model::LogicModel::empty(),
+                ..ComponentModel::default()
+            },
+            #[cfg(feature = "machine_script")]
+            script: None,
+            error: None,
+        }
+    }
+
+    /// Declares a var using a type-prefixed name, e.g. `float:speed`. The
+    /// default value, if provided, is coerced to the declared type.
+    pub fn var<V: Into<Option<Var>>>(mut self, decl: &str, default: V) -> Self {
+        if self.error.is_some() {
+            return self;
+        }
+        let addr = match ShortLocalAddress::from_str(decl) {
+            Ok(a) => a,
+            Err(e) => {
+                self.error = Some(e);
+                return self;
+            }
+        };
+        let default = match default.into().map(|v| v.coerce(addr.var_type)) {
+            Some(Ok(v)) => Some(v),
+            Some(Err(e)) => {
+                self.error = Some(e);
+                return self;
+            }
+            None => None,
+        };
+        self.model.vars.push(VarModel {
+            name: string::new_truncate(&addr.var_name),
+            type_: addr.var_type,
+            default,
+            min: None,
+            max: None,
+            unit: None,
+        });
+        self
+    }
+
+    /// Sets the bounds of the most recently declared numeric var.
+    pub fn bounds(mut self, min: Option<Float>, max: Option<Float>) -> Self {
+        if let Some(var) = self.model.vars.last_mut() {
+            var.min = min;
+            var.max = max;
+        }
+        self
+    }
+
+    /// Sets the unit of measurement of the most recently declared numeric
+    /// var.
+    pub fn unit(mut self, unit: &str) -> Self {
+        if let Err(e) = crate::unit::Unit::from_str(unit) {
+            self.error.get_or_insert(e);
+        }
+        if let Some(var) = self.model.vars.last_mut() {
+            var.unit = Some(unit.to_string());
+        }
+        self
+    }
+
+    /// Adds an event triggering the component.
+    pub fn trigger(mut self, event: &str) -> Self {
+        self.model.triggers.push(string::new_truncate(event));
+        self
+    }
+
+    /// Sets the script source defining the component's logic. The script
+    /// is compiled when the model is built.
+    #[cfg(feature = "machine_script")]
+    pub fn script(mut self, source: &str) -> Self {
+        self.script = Some(source.to_string());
+        self
+    }
+
+    /// Sets the number of times the logic is run within a single step.
+    #[cfg(feature = "machine")]
+    pub fn substeps(mut self, substeps: u32) -> Self {
+        self.model.logic.substeps = substeps;
+        self
+    }
+
+    /// Sets whether the logic has to be run serially, see
+    /// [`LogicModel::serial`].
+    ///
+    /// [`LogicModel::serial`]: crate::model::LogicModel::serial
+    #[cfg(feature = "machine")]
+    pub fn serial(mut self, serial: bool) -> Self {
+        self.model.logic.serial = serial;
+        self
+    }
+}
+
+#[test]
+fn builds_sim_with_prefab_defaults() {
+    let sim = SimModelBuilder::new()
+        .component("pos", |c| {
+            c.var("float:x", Var::Int(1)).var("float:y", None)
+        })
+        .prefab("dot", &["pos"])
+        .prefab_default("dot", "pos", "y", Var::Float(2.))
+        .spawn("dot", Some("first"))
+        .build_sim()
+        .unwrap();
+    let entity = sim
+        .get_entity_by_name(&string::new_truncate("first"))
+        .unwrap();
+    let var = |name: &str| {
+        entity
+            .storage
+            .get_var(&(string::new_truncate("pos"), string::new_truncate(name)))
+            .unwrap()
+            .clone()
+    };
+    // defaults are coerced to the declared type
+    assert_eq!(var("x"), Var::Float(1.));
+    assert_eq!(var("y"), Var::Float(2.));
+}
+
+#[test]
+fn invalid_models_are_rejected_on_build() {
+    // malformed var declaration
+    assert!(SimModelBuilder::new()
+        .component("pos", |c| c.var("x", None))
+        .build()
+        .is_err());
+    // prefab made up of a missing component
+    assert!(SimModelBuilder::new()
+        .prefab("dot", &["pos"])
+        .build()
+        .is_err());
+    // component triggered by an undeclared event
+    assert!(SimModelBuilder::new()
+        .component("pos", |c| c.trigger("tick"))
+        .build()
+        .is_err());
+    // default value outside the declared bounds
+    assert!(SimModelBuilder::new()
+        .component("pos", |c| {
+            c.var("float:x", Var::Float(0.5)).bounds(Some(0.), Some(1.))
+        })
+        .build()
+        .is_ok());
+    assert!(SimModelBuilder::new()
+        .component("pos", |c| {
+            c.var("float:x", Var::Float(2.)).bounds(Some(0.), Some(1.))
+        })
+        .build()
+        .is_err());
+    // spawning from an unknown prefab
+    assert!(SimModelBuilder::new()
+        .spawn("dot", None)
+        .build_sim()
+        .is_err());
+}
@@ -14,7 +14,7 @@
 extern crate linked_hash_map;
 extern crate toml;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use self::linked_hash_map::LinkedHashMap;
 
@@ -38,6 +38,14 @@ pub struct ModuleManifest {
     pub libraries: HashMap<String, toml::Value>,
     #[serde(default)]
     pub services: HashMap<String, toml::Value>,
+    #[serde(default)]
+    pub data: HashMap<String, toml::Value>,
+    #[serde(default)]
+    pub lua: Vec<String>,
+    /// Distribution hints for the module's components, keyed by component
+    /// name
+    #[serde(default)]
+    pub distribution: HashMap<String, DistributionEntry>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -69,7 +77,27 @@ pub struct ScenarioManifest {
     pub settings: HashMap<String, toml::Value>,
     #[serde(default)]
     pub services: HashMap<String, toml::Value>,
+    #[cfg(feature = "machine")]
+    #[serde(default)]
+    pub budget: crate::machine::ExecBudget,
+    #[serde(default)]
+    pub hazards: LinkedHashMap<String, HazardEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HazardEntry {
+    #[serde(default)]
+    pub event: Option<String>,
+    #[serde(flatten)]
+    pub distribution: crate::hazard::Distribution,
+    #[serde(default)]
+    pub modulate: Option<String>,
+    #[serde(default)]
+    pub start: Option<usize>,
+    #[serde(default)]
+    pub end: Option<usize>,
 }
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScenarioManifestScenario {
     // required
@@ -87,6 +115,12 @@ pub struct ScenarioManifestScenario {
     pub author: String,
     #[serde(default)]
     pub website: String,
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default)]
+    pub dt: Option<crate::Float>,
+    #[serde(default)]
+    pub despawn_event: Option<String>,
 }
 
 // TODO
@@ -110,20 +144,76 @@ pub struct ProofManifest {
     website: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct DataFile {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<String>,
+    /// Entity ordering policies for selected events
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub event_order: BTreeMap<String, crate::order::EntityOrder>,
     #[serde(default)]
-    pub components: HashMap<String, Option<ComponentEntry>>,
+    pub components: BTreeMap<String, Option<ComponentEntry>>,
+    /// Entity prefabs, each defined as a list of components
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub prefabs: BTreeMap<String, Vec<String>>,
+    /// Values overriding component var defaults for entities spawned from
+    /// the prefab, keyed by `comp:var_type:var_name`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub prefab_defaults: BTreeMap<String, BTreeMap<String, VarEntry>>,
+    /// Systems running over entities matching a query
+    #[cfg(feature = "machine")]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub systems: BTreeMap<String, crate::machine::system::SystemEntry>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ComponentEntry {
     #[serde(default)]
-    pub vars: HashMap<String, Option<VarEntry>>,
-    #[serde(default)]
-    pub states: HashMap<String, Option<VarEntry>>,
-    #[serde(default)]
+    pub vars: BTreeMap<String, Option<VarEntry>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub triggers: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub states: BTreeMap<String, Option<VarEntry>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub start_state: Option<String>,
+    #[cfg(feature = "machine")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub behavior: Option<crate::machine::behavior::BehaviorNode>,
+    #[cfg(feature = "machine")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub graph: Option<crate::machine::graph::LogicGraph>,
+    /// Number of times logic is run within a single step
+    #[cfg(feature = "machine")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub substeps: Option<u32>,
+    /// Whether logic is run serially, after all the other logic
+    #[cfg(feature = "machine")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<bool>,
+    #[serde(flatten)]
+    pub distribution: DistributionEntry,
+}
+
+/// Hints for placing entities holding a component, e.g.:
+///
+/// ```yaml
+/// pin_to: coordinator
+/// affinity: terrain
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DistributionEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin_to: Option<PinEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub affinity: Option<String>,
+}
+
+/// Pin target, either a node id or a named target such as `coordinator`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PinEntry {
+    Node(u32),
+    Named(String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -134,20 +224,69 @@ pub enum VarEntry {
     Int(crate::Int),
     Bool(bool),
     // IntList(Vec<i64>),
+    /// Full declaration allowing for additional constraints
+    Declared {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        default: Option<Box<VarEntry>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min: Option<crate::Float>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max: Option<crate::Float>,
+        /// Unit of measurement, e.g. `km`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        unit: Option<String>,
+    },
 }
 
+use crate::error::Error;
 use crate::Var;
 
-impl From<VarEntry> for Var {
-    fn from(var_entry: VarEntry) -> Self {
-        let var = match var_entry {
-            VarEntry::String(v) => Var::String(v),
-            VarEntry::Float(v) => Var::Float(v),
-            VarEntry::Int(v) => Var::Int(v),
-            VarEntry::Bool(v) => Var::Bool(v),
-            // VarEntry::IntList(v) => Var::List(v),
-            _ => unimplemented!(),
-        };
-        var
+impl std::convert::TryFrom<VarEntry> for Var {
+    type Error = Error;
+
+    fn try_from(var_entry: VarEntry) -> Result<Self, Self::Error> {
+        match var_entry {
+            VarEntry::String(v) => Ok(Var::String(v)),
+            VarEntry::Float(v) => Ok(Var::Float(v)),
+            VarEntry::Int(v) => Ok(Var::Int(v)),
+            VarEntry::Bool(v) => Ok(Var::Bool(v)),
+            VarEntry::Declared { .. } => Err(Error::FailedCreatingVar(
+                "declaration can't be used as a value".to_string(),
+            )),
+        }
     }
 }
+
+#[test]
+fn nested_declaration_is_not_a_value() {
+    use std::convert::TryFrom;
+    let entry = VarEntry::Declared {
+        default: None,
+        min: None,
+        max: None,
+        unit: None,
+    };
+    assert!(Var::try_from(entry).is_err());
+    assert_eq!(Var::try_from(VarEntry::Int(1)).unwrap(), Var::Int(1));
+}
+
+#[cfg(feature = "machine")]
+#[test]
+fn budget_read_from_scenario_manifest() {
+    let manifest: ScenarioManifest = toml::from_str(
+        r#"
+        [scenario]
+        name = "budgeted"
+        version = "0.1.0"
+        engine = "*"
+
+        [budget]
+        entity_cmds = 100
+        step_millis = 10
+        "#,
+    )
+    .unwrap();
+    assert_eq!(manifest.budget.entity_cmds, Some(100));
+    assert_eq!(manifest.budget.step_millis, Some(10));
+    assert!(manifest.budget.step_cmds.is_none());
+}
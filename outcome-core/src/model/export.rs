@@ -0,0 +1,276 @@
+//! Exporting the model back into module files.
+//!
+//! The in-memory model, including any components and prefabs registered
+//! at runtime, can be written out as module files. This allows for
+//! persisting changes made to the model on a running simulation.
+//!
+//! Export produces the following files:
+//!
+//! - `model.yaml` with events, components, prefabs, including their
+//!   default var overrides, and systems as structured data
+//! - `model.outcome` with components whose logic was created from known
+//!   script source, to be included from the module entry script
+//!
+//! Logic is only exported where the source it was created from is known.
+//! This is the case for logic registered at runtime from script source.
+//! Logic of components defined within module scripts is left to the
+//! original script files. Behavior trees and graphs are not exported, as
+//! their command nodes are compiled into the logic when loading.
+//!
+//! Internal parts of the model, with names starting with an underscore,
+//! are not exported.
+
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, File};
+use std::io::Write;
+use std::path::Path;
+
+use crate::error::Result;
+use crate::order::EntityOrder;
+use crate::var::Var;
+
+use super::deser::{ComponentEntry, DataFile, VarEntry};
+use super::{ComponentModel, EntityPrefab, SimModel, VarModel};
+
+/// Name of the exported structured data file.
+pub const EXPORT_DATA_FILE: &str = "model.yaml";
+/// Name of the exported script file.
+pub const EXPORT_SCRIPT_FILE: &str = "model.outcome";
+
+impl SimModel {
+    /// Writes the model out as module files to the given directory,
+    /// creating the directory if necessary.
+    pub fn export_yaml(&self, dir: &Path) -> Result<()> {
+        create_dir_all(dir)?;
+
+        let mut data = DataFile::default();
+        for event in &self.events {
+            // hardcoded events are added to every model
+            #[cfg(feature = "machine")]
+            if event.id.as_str() == crate::DEFAULT_STEP_EVENT
+                || event.id.as_str() == crate::DEFAULT_BUDGET_EXCEEDED_EVENT
+            {
+                continue;
+            }
+            if is_exported(&event.id) {
+                data.events.push(event.id.to_string());
+                if event.order != EntityOrder::default() {
+                    data.event_order.insert(event.id.to_string(), event.order);
+                }
+            }
+        }
+        let mut script = String::new();
+        for component in &self.components {
+            if !is_exported(&component.name) {
+                continue;
+            }
+            #[cfg(feature = "machine")]
+            if let Some(source) = &component.logic.source {
+                script.push_str(&component_block(component, source));
+                continue;
+            }
+            data.components
+                .insert(component.name.to_string(), Some(component_entry(component)));
+        }
+        for prefab in &self.entities {
+            if is_exported(&prefab.name) {
+                data.prefabs.insert(
+                    prefab.name.to_string(),
+                    prefab.components.iter().map(|c| c.to_string()).collect(),
+                );
+                let defaults = prefab_defaults(prefab);
+                if !defaults.is_empty() {
+                    data.prefab_defaults
+                        .insert(prefab.name.to_string(), defaults);
+                }
+            }
+        }
+        #[cfg(feature = "machine")]
+        for system in &self.systems {
+            if is_exported(&system.name) {
+                data.systems.insert(
+                    system.name.to_string(),
+                    crate::machine::system::SystemEntry {
+                        query: system.query.iter().map(|c| c.to_string()).collect(),
+                        triggers: system.triggers.iter().map(|t| t.to_string()).collect(),
+                        ops: system.ops.clone(),
+                        // access is derived from operations again when
+                        // loading, as declarations can't be recovered
+                        reads: None,
+                        writes: None,
+                        after: system.after.iter().map(|s| s.to_string()).collect(),
+                    },
+                );
+            }
+        }
+
+        let yaml = serde_yaml::to_string(&data)?;
+        File::create(dir.join(EXPORT_DATA_FILE))?.write_all(yaml.as_bytes())?;
+        if !script.is_empty() {
+            File::create(dir.join(EXPORT_SCRIPT_FILE))?.write_all(script.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+fn is_exported(name: &str) -> bool {
+    !name.starts_with('_')
+}
+
+fn component_entry(component: &ComponentModel) -> ComponentEntry {
+    let mut entry = ComponentEntry {
+        vars: component
+            .vars
+            .iter()
+            .map(|var| (var_key(var), var_model_entry(var)))
+            .collect::<BTreeMap<_, _>>(),
+        triggers: component.triggers.iter().map(|t| t.to_string()).collect(),
+        distribution: component.distribution.to_deser(),
+        ..ComponentEntry::default()
+    };
+    #[cfg(feature = "machine")]
+    if component.logic.start_state.as_str() != crate::machine::START_STATE_NAME {
+        entry.start_state = Some(component.logic.start_state.to_string());
+    }
+    #[cfg(feature = "machine")]
+    if component.logic.substeps > 1 {
+        entry.substeps = Some(component.logic.substeps);
+    }
+    #[cfg(feature = "machine")]
+    if component.logic.serial {
+        entry.serial = Some(true);
+    }
+    entry
+}
+
+fn prefab_defaults(prefab: &EntityPrefab) -> BTreeMap<String, VarEntry> {
+    let mut defaults = BTreeMap::new();
+    for ((comp, var), value) in &prefab.defaults {
+        let key = format!("{}:{}:{}", comp, value.get_type().to_str(), var);
+        match var_entry(value) {
+            Some(entry) => {
+                defaults.insert(key, entry);
+            }
+            None => warn!(
+                "default value of var \"{}\" for prefab \"{}\" can't be exported, skipping",
+                key, prefab.name
+            ),
+        }
+    }
+    defaults
+}
+
+fn var_key(var: &VarModel) -> String {
+    format!("{}:{}", var.type_.to_str(), var.name)
+}
+
+fn var_model_entry(var: &VarModel) -> Option<VarEntry> {
+    let default = var.default.as_ref().and_then(|v| {
+        let entry = var_entry(v);
+        if entry.is_none() {
+            warn!(
+                "default value of var \"{}\" can't be exported, skipping",
+                var.name
+            );
+        }
+        entry
+    });
+    if var.min.is_some() || var.max.is_some() || var.unit.is_some() {
+        Some(VarEntry::Declared {
+            default: default.map(Box::new),
+            min: var.min,
+            max: var.max,
+            unit: var.unit.clone(),
+        })
+    } else {
+        default
+    }
+}
+
+fn var_entry(var: &Var) -> Option<VarEntry> {
+    match var {
+        Var::String(v) => Some(VarEntry::String(v.clone())),
+        Var::Int(v) => Some(VarEntry::Int(*v)),
+        Var::Float(v) => Some(VarEntry::Float(*v)),
+        Var::Bool(v) => Some(VarEntry::Bool(*v)),
+        _ => None,
+    }
+}
+
+/// Creates a script component block, declaring the component along with
+/// its logic.
+#[cfg(feature = "machine")]
+fn component_block(component: &ComponentModel, source: &str) -> String {
+    let mut block = format!("component {}\n", component.name);
+    for trigger in &component.triggers {
+        block.push_str(&format!("\ttrigger {}\n", trigger));
+    }
+    for var in &component.vars {
+        match &var.default {
+            Some(Var::String(s)) => {
+                block.push_str(&format!("\tvar {} {}\n", var_key(var), quote(s)))
+            }
+            Some(v) => block.push_str(&format!(
+                "\tvar {} {}\n",
+                var_key(var),
+                script_value(&v.to_string())
+            )),
+            None => block.push_str(&format!("\tvar {}\n", var_key(var))),
+        }
+    }
+    for line in source.lines() {
+        block.push('\t');
+        block.push_str(line);
+        block.push('\n');
+    }
+    block.push_str("end\n\n");
+    block
+}
+
+/// Prepares the value for use as a script command argument, quoting it
+/// if it contains any characters that would otherwise end the argument.
+#[cfg(feature = "machine")]
+fn script_value(value: &str) -> String {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| matches!(c, ' ' | '#' | '"' | '\\' | '=' | '\n' | '\r' | '\t'));
+    match needs_quotes {
+        true => quote(value),
+        false => value.to_string(),
+    }
+}
+
+/// Quotes the value, escaping characters that have special meaning
+/// within quoted script arguments.
+#[cfg(feature = "machine")]
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            // keep variable references from being expanded
+            '$' if chars.peek() == Some(&'{') => quoted.push_str("\\$"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(feature = "machine")]
+#[test]
+fn exported_script_values_are_escaped() {
+    assert_eq!(script_value("10"), "10");
+    assert_eq!(script_value("a b"), "\"a b\"");
+    assert_eq!(
+        quote("say \"hi\"\n# ${name} \\"),
+        "\"say \\\"hi\\\"\\n# \\${name} \\\\\""
+    );
+}
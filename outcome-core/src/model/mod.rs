@@ -3,9 +3,15 @@
 
 #![allow(unused)]
 
+mod builder;
 mod deser;
+#[cfg(feature = "yaml")]
+mod export;
+
+pub use builder::{ComponentBuilder, SimModelBuilder};
 
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fs::{read, read_dir, File};
 use std::io::Read;
 use std::path::{Path, PathBuf};
@@ -17,6 +23,7 @@ use toml::Value;
 
 use crate::address::{Address, LocalAddress, ShortLocalAddress};
 use crate::error::Error;
+use crate::order::EntityOrder;
 use crate::util;
 use crate::{string, ShortString, StringId};
 use crate::{CompName, EntityName, EventName, Result, Var, VarName, VarType};
@@ -63,6 +70,12 @@ pub struct SimModel {
     pub data_files: Vec<DataFileEntry>,
     pub data_imgs: Vec<DataImageEntry>,
     pub services: Vec<ServiceModel>,
+    #[cfg(feature = "machine")]
+    #[serde(default)]
+    pub systems: Vec<crate::machine::system::SystemModel>,
+    #[cfg(feature = "machine_lua")]
+    #[serde(default)]
+    pub lua_scripts: Vec<crate::machine::cmd::lua::LuaScriptEntry>,
 }
 
 impl SimModel {
@@ -79,14 +92,34 @@ impl SimModel {
             data_files: Vec::new(),
             data_imgs: Vec::new(),
             services: Vec::new(),
+            #[cfg(feature = "machine")]
+            systems: Vec::new(),
+            #[cfg(feature = "machine_lua")]
+            lua_scripts: Vec::new(),
         };
 
         // add hardcoded content
         #[cfg(feature = "machine")]
         model.events.push(crate::model::EventModel {
             id: string::new_truncate(crate::DEFAULT_STEP_EVENT),
+            order: EntityOrder::default(),
+        });
+        #[cfg(feature = "machine")]
+        model.events.push(crate::model::EventModel {
+            id: string::new_truncate(crate::DEFAULT_BUDGET_EXCEEDED_EVENT),
+            order: EntityOrder::default(),
         });
 
+        // hazard events don't need to be declared separately
+        for hazard in &scenario.manifest.hazards {
+            if !model.events.iter().any(|e| e.id == hazard.event) {
+                model.events.push(crate::model::EventModel {
+                    id: hazard.event.clone(),
+                    order: EntityOrder::default(),
+                });
+            }
+        }
+
         let mut mod_init_prefab = EntityPrefab {
             name: string::new_truncate("_mod_init"),
             // name: StringId::from(&format!("_mod_init_{}", module.manifest.name)).unwrap(),
@@ -100,6 +133,23 @@ impl SimModel {
                 model.services.push(module_service.clone());
             }
 
+            // data files
+            for data_file in &module.manifest.data_files {
+                model.data_files.push(data_file.clone());
+            }
+
+            // lua scripts
+            #[cfg(feature = "machine_lua")]
+            for script_path in &module.manifest.lua_scripts {
+                let source = std::fs::read_to_string(script_path)?;
+                model
+                    .lua_scripts
+                    .push(crate::machine::cmd::lua::LuaScriptEntry {
+                        name: script_path.clone(),
+                        source,
+                    });
+            }
+
             // load from structured data
             #[cfg(feature = "yaml")]
             {
@@ -125,6 +175,7 @@ impl SimModel {
             {
                 model.events.push(EventModel {
                     id: string::new_truncate("_scr_init"),
+                    order: EntityOrder::default(),
                 });
 
                 let scr_init_mod_template = ComponentModel {
@@ -226,9 +277,24 @@ impl SimModel {
                 mod_init_prefab.components.push(comp_model.name.clone());
                 model.components.push(comp_model);
             }
+
+            // distribution hints declared in the manifest
+            for (comp_name, hints) in &module.manifest.distribution {
+                match model.components.iter_mut().find(|c| &c.name == comp_name) {
+                    Some(component) => component.distribution.merge(hints),
+                    None => warn!(
+                        "distribution hints for unknown component: {}, module: {}",
+                        comp_name, module.manifest.name
+                    ),
+                }
+            }
         }
         model.entities.push(mod_init_prefab);
 
+        // make sure systems can be scheduled
+        #[cfg(feature = "machine")]
+        crate::machine::system::SystemSchedule::new(&model.systems)?;
+
         Ok(model)
     }
 }
@@ -242,6 +308,57 @@ impl SimModel {
                 self.components.push(comp_model);
             }
         }
+        for event in file_struct.events {
+            self.events.push(EventModel {
+                order: file_struct
+                    .event_order
+                    .get(&event)
+                    .copied()
+                    .unwrap_or_default(),
+                id: string::new_truncate(&event),
+            });
+        }
+        for (name, components) in file_struct.prefabs {
+            let mut defaults = FnvHashMap::default();
+            for (key, entry) in file_struct
+                .prefab_defaults
+                .get(&name)
+                .cloned()
+                .unwrap_or_default()
+            {
+                let addr = ShortLocalAddress::from_str(&key)?;
+                let comp = addr
+                    .comp
+                    .ok_or_else(|| Error::InvalidLocalAddress(key.clone()))?;
+                let value = Var::try_from(entry)?;
+                // numbers are declared the same regardless of their type
+                let value = match addr.var_type {
+                    VarType::Float | VarType::Fixed | VarType::Decimal => {
+                        value.coerce(addr.var_type)?
+                    }
+                    _ => value,
+                };
+                if value.get_type() != addr.var_type {
+                    return Err(Error::FailedCreatingVar(format!(
+                        "default for prefab {} doesn't match var type: {}",
+                        name, key
+                    )));
+                }
+                defaults.insert((comp, addr.var_name), value);
+            }
+            self.entities.push(EntityPrefab {
+                name: string::new_truncate(&name),
+                components: components.iter().map(|c| string::new_truncate(c)).collect(),
+                defaults,
+            });
+        }
+        #[cfg(feature = "machine")]
+        for (name, system) in file_struct.systems {
+            self.systems
+                .push(crate::machine::system::SystemModel::from_deser(
+                    &name, system,
+                )?);
+        }
 
         Ok(())
     }
@@ -258,6 +375,49 @@ impl SimModel {
         self.entities.iter_mut().find(|entity| &entity.name == name)
     }
 
+    /// Overrides the default value of a component var for entities spawned
+    /// from the prefab. Already existing entities are not affected.
+    ///
+    /// Component has to be part of the prefab and the value has to match
+    /// the var type.
+    pub fn set_prefab_default(
+        &mut self,
+        prefab: &EntityName,
+        comp: &CompName,
+        var: &VarName,
+        value: Var,
+    ) -> Result<()> {
+        let var_model = self
+            .get_component(comp)?
+            .vars
+            .iter()
+            .find(|v| &v.name == var)
+            .ok_or(Error::Other(format!(
+                "component {} has no var named {}",
+                comp, var
+            )))?;
+        if var_model.type_ != value.get_type() {
+            return Err(Error::Other(format!(
+                "expected value of type {:?} for {}:{}, got {:?}",
+                var_model.type_,
+                comp,
+                var,
+                value.get_type()
+            )));
+        }
+        let prefab = self
+            .get_entity_mut(prefab)
+            .ok_or(Error::NoEntityPrefab(prefab.clone()))?;
+        if !prefab.components.contains(comp) {
+            return Err(Error::Other(format!(
+                "prefab {} doesn't include component {}",
+                prefab.name, comp
+            )));
+        }
+        prefab.defaults.insert((comp.clone(), var.clone()), value);
+        Ok(())
+    }
+
     /// Get reference to component model using `type_` and `id` args.
     pub fn get_component(&self, name: &CompName) -> Result<&ComponentModel> {
         self.components
@@ -266,10 +426,136 @@ impl SimModel {
             .ok_or(Error::NoComponentModel(name.clone()))
     }
 
+    /// Checks whether the var can replace the existing var at the address.
+    /// The type has to stay the same and numeric values have to fall
+    /// within the bounds declared in the model.
+    pub fn validate_var(&self, addr: &Address, existing: &Var, var: &Var) -> Result<()> {
+        if std::mem::discriminant(existing) != std::mem::discriminant(var) {
+            return Err(Error::VarTypeMismatch(
+                addr.clone(),
+                existing.get_type().to_str().to_string(),
+                var.get_type().to_str().to_string(),
+            ));
+        }
+        if let Ok(comp_model) = self.get_component(&addr.component) {
+            if let Some(var_model) = comp_model.vars.iter().find(|v| v.name == addr.var_name) {
+                if !var_model.within_bounds(var) {
+                    return Err(Error::VarOutOfBounds(addr.clone(), var.to_string()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Get reference to event model using `name` arg.
+    pub fn get_event(&self, name: &EventName) -> Option<&EventModel> {
+        self.events.iter().find(|event| &event.id == name)
+    }
+
     /// Get mutable reference to component model using `type_` and `id` args.
     pub fn get_component_mut(&mut self, name: &StringId) -> Option<&mut ComponentModel> {
         self.components.iter_mut().find(|comp| &comp.name == name)
     }
+
+    /// Checks the model for consistency. Names of components and prefabs
+    /// have to be unique, prefabs can only include existing components and
+    /// components can only be triggered by declared events.
+    pub fn validate(&self) -> Result<()> {
+        for (n, component) in self.components.iter().enumerate() {
+            if self.components[..n]
+                .iter()
+                .any(|c| c.name == component.name)
+            {
+                return Err(Error::Other(format!(
+                    "duplicate component: {}",
+                    component.name
+                )));
+            }
+            for trigger in &component.triggers {
+                // init event is built into every entity
+                if trigger.as_str() == crate::DEFAULT_INIT_EVENT {
+                    continue;
+                }
+                if self.get_event(trigger).is_none() {
+                    return Err(Error::Other(format!(
+                        "component {} triggered by undeclared event: {}",
+                        component.name, trigger
+                    )));
+                }
+            }
+        }
+        for (n, prefab) in self.entities.iter().enumerate() {
+            if self.entities[..n].iter().any(|e| e.name == prefab.name) {
+                return Err(Error::Other(format!("duplicate prefab: {}", prefab.name)));
+            }
+            for comp in &prefab.components {
+                self.get_component(comp)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Merges the other model into this one. Events, components and prefabs
+    /// replace the ones with matching names, or are added if there are no
+    /// matches. Same goes for data entries targeting the same address and
+    /// Lua scripts with the same name. Scripts and data files are only added
+    /// if not already present. Scenario and services are left intact.
+    pub fn merge(&mut self, other: SimModel) {
+        for event in other.events {
+            match self.events.iter_mut().find(|e| e.id == event.id) {
+                Some(existing) => *existing = event,
+                None => self.events.push(event),
+            }
+        }
+        for component in other.components {
+            match self.get_component_mut(&component.name) {
+                Some(existing) => *existing = component,
+                None => self.components.push(component),
+            }
+        }
+        for prefab in other.entities {
+            match self.get_entity_mut(&prefab.name) {
+                Some(existing) => *existing = prefab,
+                None => self.entities.push(prefab),
+            }
+        }
+        #[cfg(feature = "machine")]
+        for system in other.systems {
+            match self.systems.iter_mut().find(|s| s.name == system.name) {
+                Some(existing) => *existing = system,
+                None => self.systems.push(system),
+            }
+        }
+        for script in other.scripts {
+            if !self.scripts.contains(&script) {
+                self.scripts.push(script);
+            }
+        }
+        for entry in other.data {
+            let address = entry.address();
+            match self.data.iter_mut().find(|e| e.address() == address) {
+                Some(existing) => *existing = entry,
+                None => self.data.push(entry),
+            }
+        }
+        for entry in other.data_files {
+            if !self.data_files.contains(&entry) {
+                self.data_files.push(entry);
+            }
+        }
+        for entry in other.data_imgs {
+            if !self.data_imgs.contains(&entry) {
+                self.data_imgs.push(entry);
+            }
+        }
+        #[cfg(feature = "machine_lua")]
+        for script in other.lua_scripts {
+            match self.lua_scripts.iter_mut().find(|s| s.name == script.name) {
+                Some(existing) => *existing = script,
+                None => self.lua_scripts.push(script),
+            }
+        }
+    }
 }
 
 /// Scenario manifest model.
@@ -286,6 +572,21 @@ pub struct ScenarioManifest {
     pub mods: Vec<ScenarioModuleDep>,
     /// Map of settings, each being essentially an arbitrary data setter
     pub settings: HashMap<String, String>,
+    /// Limits on logic execution within a single step
+    #[cfg(feature = "machine")]
+    pub budget: crate::machine::ExecBudget,
+    /// Seed used for sampling random processes, such as hazards
+    #[serde(default)]
+    pub seed: u64,
+    /// Duration of a single step, defaults to `1`
+    #[serde(default)]
+    pub dt: Option<crate::Float>,
+    /// Global event invoked whenever an entity is despawned
+    #[serde(default)]
+    pub despawn_event: Option<String>,
+    /// Random event generators
+    #[serde(default)]
+    pub hazards: Vec<HazardModel>,
 
     /// More free-form than the name
     pub title: Option<String>,
@@ -300,6 +601,11 @@ pub struct ScenarioManifest {
 }
 
 impl ScenarioManifest {
+    /// Returns the duration of a single step.
+    pub fn dt(&self) -> crate::Float {
+        self.dt.unwrap_or(1.)
+    }
+
     /// Creates new scenario manifest object from path reference.
     pub fn from_path(path: PathBuf) -> Result<ScenarioManifest> {
         // let manifest_path = path.join(SCENARIO_MANIFEST_FILE);
@@ -323,6 +629,16 @@ impl ScenarioManifest {
                 .iter()
                 .map(|(s, v)| (s.to_string(), v.to_string()))
                 .collect(),
+            #[cfg(feature = "machine")]
+            budget: deser_manifest.budget,
+            seed: deser_manifest.scenario.seed,
+            dt: deser_manifest.scenario.dt,
+            despawn_event: deser_manifest.scenario.despawn_event,
+            hazards: deser_manifest
+                .hazards
+                .into_iter()
+                .map(|(name, entry)| HazardModel::from_deser(&name, entry))
+                .collect::<Result<Vec<_>>>()?,
             title: match deser_manifest.scenario.title.as_str() {
                 "" => None,
                 s => Some(s.to_owned()),
@@ -348,6 +664,48 @@ impl ScenarioManifest {
     }
 }
 
+/// Random event generator model. See the [`hazard`] module for details.
+///
+/// [`hazard`]: crate::hazard
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HazardModel {
+    pub name: String,
+    /// Event added to the event queue when the hazard fires
+    pub event: EventName,
+    pub distribution: crate::hazard::Distribution,
+    /// Address of the var scaling the distribution
+    pub modulate: Option<Address>,
+    /// First step at which the hazard can fire
+    pub start: Option<usize>,
+    /// Last step at which the hazard can fire
+    pub end: Option<usize>,
+}
+
+impl HazardModel {
+    pub fn from_deser(name: &str, entry: deser::HazardEntry) -> Result<Self> {
+        use crate::hazard::Distribution;
+        let valid = match &entry.distribution {
+            Distribution::Bernoulli { p } => (0. ..=1.).contains(p),
+            Distribution::Poisson { rate } => *rate >= 0.,
+            Distribution::Normal { std_dev, .. } => *std_dev >= 0.,
+        };
+        if !valid {
+            return Err(Error::Other(format!(
+                "hazard {}: invalid distribution parameters: {:?}",
+                name, entry.distribution
+            )));
+        }
+        Ok(HazardModel {
+            name: name.to_string(),
+            event: string::new_truncate(entry.event.as_deref().unwrap_or(name)),
+            distribution: entry.distribution,
+            modulate: entry.modulate.map(|a| a.parse()).transpose()?,
+            start: entry.start,
+            end: entry.end,
+        })
+    }
+}
+
 /// Scenario module dependency.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScenarioModuleDep {
@@ -664,6 +1022,14 @@ pub struct ModuleManifest {
 
     pub libraries: Vec<ModuleLib>,
     pub services: Vec<ServiceModel>,
+    /// Data files to be loaded into the simulation, paths are resolved
+    /// relative to the module root
+    pub data_files: Vec<DataFileEntry>,
+    /// Lua script files, paths are resolved relative to the module root
+    pub lua_scripts: Vec<String>,
+    /// Distribution hints for the module's components, applied on top of
+    /// the hints declared on the components themselves
+    pub distribution: HashMap<CompName, DistributionHints>,
 
     // optional
     /// Free-form module name
@@ -829,6 +1195,52 @@ impl ModuleManifest {
             services.push(service);
         }
 
+        let mut data_files = Vec::new();
+        for (data_name, data_value) in deser_manifest.data {
+            let table = match data_value.as_table() {
+                Some(t) => t,
+                None => {
+                    warn!(
+                        "data file entry must be a table: {}, module: {}",
+                        data_name, deser_manifest._mod.name
+                    );
+                    continue;
+                }
+            };
+            let file_path = match table.get("path").and_then(|v| v.as_str()) {
+                Some(p) => path.join(p).to_string_lossy().to_string(),
+                None => {
+                    warn!(
+                        "data file entry is missing path: {}, module: {}",
+                        data_name, deser_manifest._mod.name
+                    );
+                    continue;
+                }
+            };
+            let type_ = table.get("type").and_then(|v| v.as_str()).unwrap_or("");
+            match DataFileEntry::from_type_str(type_, file_path) {
+                Some(entry) => data_files.push(entry),
+                None => warn!(
+                    "unrecognized data file type: {}, entry: {}, module: {}",
+                    type_, data_name, deser_manifest._mod.name
+                ),
+            }
+        }
+
+        let lua_scripts = deser_manifest
+            .lua
+            .iter()
+            .map(|p| path.join(p).to_string_lossy().to_string())
+            .collect();
+
+        let mut distribution = HashMap::new();
+        for (comp_name, entry) in &deser_manifest.distribution {
+            distribution.insert(
+                string::new_truncate(comp_name),
+                DistributionHints::from_deser(entry)?,
+            );
+        }
+
         Ok(ModuleManifest {
             name: deser_manifest._mod.name,
             engine_version_req,
@@ -838,6 +1250,9 @@ impl ModuleManifest {
             reqs: req_vec,
             libraries: libs,
             services,
+            data_files,
+            lua_scripts,
+            distribution,
             title: match deser_manifest._mod.title.as_str() {
                 "" => None,
                 s => Some(s.to_owned()),
@@ -924,6 +1339,9 @@ impl Module {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EventModel {
     pub id: EventName,
+    /// Order in which entities execute commands triggered by the event
+    #[serde(default)]
+    pub order: EntityOrder,
 }
 
 /// Entity prefab model.
@@ -931,6 +1349,50 @@ pub struct EventModel {
 pub struct EntityPrefab {
     pub name: EntityName,
     pub components: Vec<CompName>,
+    /// Values overriding component var defaults for entities spawned from
+    /// this prefab
+    #[serde(default, with = "prefab_defaults")]
+    pub defaults: FnvHashMap<(CompName, VarName), Var>,
+}
+
+/// Serializes prefab defaults with the `(component, var)` keys joined into
+/// `comp:var` strings, as most human-readable formats only allow strings
+/// as map keys.
+mod prefab_defaults {
+    use std::collections::BTreeMap;
+
+    use fnv::FnvHashMap;
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::{string, CompName, Var, VarName};
+
+    pub fn serialize<S: Serializer>(
+        defaults: &FnvHashMap<(CompName, VarName), Var>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        defaults
+            .iter()
+            .map(|((comp, var), value)| (format!("{}:{}", comp, var), value))
+            .collect::<BTreeMap<_, _>>()
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<FnvHashMap<(CompName, VarName), Var>, D::Error> {
+        BTreeMap::<String, Var>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(key, value)| {
+                let (comp, var) = key.split_once(':').ok_or_else(|| {
+                    de::Error::custom(format!("invalid prefab default key: {}", key))
+                })?;
+                Ok((
+                    (string::new_truncate(comp), string::new_truncate(var)),
+                    value,
+                ))
+            })
+            .collect()
+    }
 }
 
 // cfg_if! {
@@ -969,6 +1431,9 @@ pub struct ComponentModel {
     pub vars: Vec<VarModel>,
     /// List of events that serve as triggers for the component
     pub triggers: Vec<StringId>,
+    /// Hints for placing entities holding the component among nodes
+    #[serde(default)]
+    pub distribution: DistributionHints,
 
     /// Logic attached to the component
     #[cfg(feature = "machine")]
@@ -977,22 +1442,118 @@ pub struct ComponentModel {
 
 impl ComponentModel {
     pub fn from_deser(key: &String, val: deser::ComponentEntry) -> Result<Self> {
-        Ok(ComponentModel {
+        #[allow(unused_mut)]
+        let mut component = ComponentModel {
             name: string::new_truncate(key),
             vars: val
                 .vars
                 .into_iter()
                 .filter(|(k, v)| v.is_some())
-                .map(|(k, v)| VarModel::from_deser(&k, v).unwrap())
+                .map(|(k, v)| VarModel::from_deser(&k, v))
+                .collect::<Result<Vec<_>>>()?,
+            triggers: val
+                .triggers
+                .iter()
+                .map(|t| string::new_truncate(t))
                 .collect(),
-            triggers: Vec::new(),
+            distribution: DistributionHints::from_deser(&val.distribution)?,
             #[cfg(feature = "machine")]
             logic: LogicModel {
-                start_state: string::new_truncate(START_STATE_NAME),
+                start_state: string::new_truncate(
+                    val.start_state.as_deref().unwrap_or(START_STATE_NAME),
+                ),
+                behavior: val.behavior,
+                graph: val.graph,
+                substeps: val.substeps.unwrap_or(1),
+                serial: val.serial.unwrap_or(false),
                 ..Default::default()
             },
+        };
+        #[cfg(feature = "machine_script")]
+        if let Some(mut behavior) = component.logic.behavior.take() {
+            behavior.compile(&mut component.logic, &component.name)?;
+            component.logic.behavior = Some(behavior);
+        }
+        #[cfg(feature = "machine")]
+        if let Some(mut graph) = component.logic.graph.take() {
+            graph.prepare()?;
+            #[cfg(feature = "machine_script")]
+            graph.compile(&mut component.logic, &component.name)?;
+            component.logic.graph = Some(graph);
+        }
+        Ok(component)
+    }
+}
+
+/// Target node for pinned entities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PinTarget {
+    /// Node running alongside the central authority
+    Coordinator,
+    /// Node with the given id
+    Node(u32),
+}
+
+/// Hints for distributing entities holding a component among nodes.
+///
+/// Hints are collected from all the components of the prefab an entity is
+/// spawned from. Pinning takes precedence over affinity.
+///
+/// Entities are always placed on a single node, replication among nodes is
+/// not supported.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DistributionHints {
+    /// Node the entities must be placed on
+    pub pin_to: Option<PinTarget>,
+    /// Component whose entities the entities should be co-located with
+    pub affinity: Option<CompName>,
+}
+
+impl DistributionHints {
+    pub fn from_deser(entry: &deser::DistributionEntry) -> Result<Self> {
+        let pin_to = match &entry.pin_to {
+            None => None,
+            Some(deser::PinEntry::Node(id)) => Some(PinTarget::Node(*id)),
+            Some(deser::PinEntry::Named(name)) => match name.as_str() {
+                "coordinator" => Some(PinTarget::Coordinator),
+                _ => {
+                    return Err(Error::Other(format!(
+                        "unrecognized pin target: {}, expected `coordinator` or node id",
+                        name
+                    )))
+                }
+            },
+        };
+        Ok(DistributionHints {
+            pin_to,
+            affinity: entry.affinity.as_deref().map(string::new_truncate),
         })
     }
+
+    pub fn to_deser(&self) -> deser::DistributionEntry {
+        deser::DistributionEntry {
+            pin_to: self.pin_to.map(|pin| match pin {
+                PinTarget::Coordinator => deser::PinEntry::Named("coordinator".to_string()),
+                PinTarget::Node(id) => deser::PinEntry::Node(id),
+            }),
+            affinity: self.affinity.as_ref().map(|c| c.to_string()),
+        }
+    }
+
+    /// Checks whether no hints are set.
+    pub fn is_empty(&self) -> bool {
+        self == &DistributionHints::default()
+    }
+
+    /// Overrides hints with the ones set on `other`.
+    pub fn merge(&mut self, other: &DistributionHints) {
+        if other.pin_to.is_some() {
+            self.pin_to = other.pin_to;
+        }
+        if other.affinity.is_some() {
+            self.affinity = other.affinity.clone();
+        }
+    }
 }
 
 /// Component-bound state machine logic model.
@@ -1011,6 +1572,30 @@ pub struct LogicModel {
     pub procedures: FnvHashMap<ShortString, (usize, usize)>,
     /// Location info mapped for each command on the list by index
     pub cmd_location_map: Vec<crate::machine::LocationInfo>,
+    /// Script source the logic was created from, if known
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Behavior tree executed in place of states
+    #[serde(default)]
+    pub behavior: Option<crate::machine::behavior::BehaviorNode>,
+    /// Dataflow graph evaluated in place of states
+    #[serde(default)]
+    pub graph: Option<crate::machine::graph::LogicGraph>,
+    /// Number of times the logic is run within a single step, each run
+    /// covering an equal part of the step duration
+    ///
+    /// If the component declares a `float:dt` var, it's set to the duration
+    /// of a single sub-step before each run, and restored to its previous
+    /// value once all the sub-steps are done. Values lower than `1` are
+    /// treated as `1`.
+    #[serde(default)]
+    pub substeps: u32,
+    /// Whether the logic is order-sensitive and has to be run serially
+    ///
+    /// Serial logic is run after the logic of all the other components,
+    /// one entity at a time, in the order of entity ids.
+    #[serde(default)]
+    pub serial: bool,
 }
 
 #[cfg(feature = "machine")]
@@ -1023,6 +1608,11 @@ impl LogicModel {
             procedures: FnvHashMap::default(),
             cmd_location_map: Vec::new(),
             pre_commands: FnvHashMap::default(),
+            source: None,
+            behavior: None,
+            graph: None,
+            substeps: 1,
+            serial: false,
         }
     }
 
@@ -1033,6 +1623,68 @@ impl LogicModel {
         // warn!("{:?}", new_logic);
         new_logic
     }
+
+    /// Creates logic model from script source.
+    ///
+    /// Source is run through the preprocessor using the provided model.
+    /// If the source doesn't define any states, all commands are placed
+    /// within the starting state.
+    #[cfg(feature = "machine_script")]
+    pub fn from_script(
+        source: &str,
+        comp_name: &CompName,
+        model: &mut SimModel,
+    ) -> Result<LogicModel> {
+        use crate::machine::cmd::Command;
+        use crate::machine::{CommandPrototype, LocationInfo};
+
+        let mut instructions = parser::parse_lines(source, LocationInfo::empty())?;
+        preprocessor::run(
+            &mut instructions,
+            model,
+            &crate::machine::script::util::get_program_metadata(),
+        )?;
+
+        let mut cmd_prototypes: Vec<CommandPrototype> = Vec::new();
+        let mut cmd_locations: Vec<LocationInfo> = Vec::new();
+        for instruction in instructions {
+            let cmd_prototype = match instruction.type_ {
+                InstructionType::Command(c) => c,
+                _ => continue,
+            };
+            cmd_prototypes.push(cmd_prototype);
+            cmd_locations.push(instruction.location);
+        }
+
+        let mut logic = LogicModel {
+            source: Some(source.to_string()),
+            ..LogicModel::empty()
+        };
+        for (n, cmd_prototype) in cmd_prototypes.iter().enumerate() {
+            let location = &mut cmd_locations[n];
+            location.comp_name = Some(comp_name.clone());
+            location.line = Some(n);
+            let command = Command::from_prototype(cmd_prototype, location, &cmd_prototypes)?;
+            if let Command::Procedure(proc) = &command {
+                logic
+                    .procedures
+                    .insert(proc.name.clone(), (proc.start_line, proc.end_line));
+            }
+            if let Command::State(state) = &command {
+                logic
+                    .states
+                    .insert(state.name.clone(), (state.start_line, state.end_line));
+            }
+            logic.commands.push(command);
+            logic.cmd_location_map.push(location.clone());
+        }
+        if logic.states.is_empty() {
+            logic
+                .states
+                .insert(logic.start_state.clone(), (0, logic.commands.len()));
+        }
+        Ok(logic)
+    }
 }
 
 /// Variable model.
@@ -1041,18 +1693,74 @@ pub struct VarModel {
     pub name: VarName,
     pub type_: VarType,
     pub default: Option<Var>,
+    /// Lower bound for numeric values
+    #[serde(default)]
+    pub min: Option<crate::Float>,
+    /// Upper bound for numeric values
+    #[serde(default)]
+    pub max: Option<crate::Float>,
+    /// Unit of measurement for numeric values
+    #[serde(default)]
+    pub unit: Option<String>,
 }
 
 impl VarModel {
     pub fn from_deser(key: &str, val: Option<deser::VarEntry>) -> Result<VarModel> {
         let addr = ShortLocalAddress::from_str(key)?;
 
+        let (default, min, max, unit) = match val {
+            Some(deser::VarEntry::Declared {
+                default,
+                min,
+                max,
+                unit,
+            }) => (
+                default.map(|v| Var::try_from(*v)).transpose()?,
+                min,
+                max,
+                unit,
+            ),
+            Some(v) => (Some(Var::try_from(v)?), None, None, None),
+            None => (None, None, None, None),
+        };
+        if let Some(unit) = &unit {
+            crate::unit::Unit::from_str(unit)?;
+        }
+        // fixed point and decimal defaults are declared as regular numbers,
+        // decimals can also be declared as strings to keep them exact
+        let default = match (addr.var_type, default) {
+            (VarType::Fixed, Some(v)) => Some(v.coerce(VarType::Fixed)?),
+            (VarType::Decimal, Some(v)) => Some(v.coerce(VarType::Decimal)?),
+            (_, default) => default,
+        };
         Ok(VarModel {
             name: string::new_truncate(&addr.var_name),
             type_: addr.var_type,
-            default: val.map(|v| Var::from(v)),
+            default,
+            min,
+            max,
+            unit,
         })
     }
+
+    /// Checks whether the value falls within declared bounds. Only numeric
+    /// values are checked.
+    pub fn within_bounds(&self, var: &Var) -> bool {
+        match var {
+            Var::Int(_) | Var::Float(_) | Var::Byte(_) | Var::Fixed(_) | Var::Decimal(_) => {
+                let v = var.to_float();
+                self.min.map_or(true, |min| v >= min) && self.max.map_or(true, |max| v <= max)
+            }
+            _ => true,
+        }
+    }
+
+    /// Parses the declared unit, if any.
+    pub fn unit(&self) -> Option<crate::unit::Unit> {
+        self.unit
+            .as_ref()
+            .and_then(|u| crate::unit::Unit::from_str(u).ok())
+    }
 }
 
 /// Data entry model.
@@ -1064,8 +1772,20 @@ pub enum DataEntry {
     Grid((String, Vec<Vec<String>>)),
 }
 
+impl DataEntry {
+    /// Returns the address the data is applied to.
+    pub fn address(&self) -> &str {
+        match self {
+            DataEntry::Simple((addr, _)) => addr,
+            DataEntry::List((addr, _)) => addr,
+            #[cfg(feature = "grids")]
+            DataEntry::Grid((addr, _)) => addr,
+        }
+    }
+}
+
 /// Data file entry model.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum DataFileEntry {
     Json(String),
     JsonList(String),
@@ -1077,8 +1797,27 @@ pub enum DataFileEntry {
     CsvGrid(String),
 }
 
+impl DataFileEntry {
+    /// Creates a new entry for the file at the given path, based on the type
+    /// name as used in module manifests, e.g. `csv_grid`.
+    pub fn from_type_str(type_: &str, path: String) -> Option<DataFileEntry> {
+        let entry = match type_ {
+            "json" => DataFileEntry::Json(path),
+            "json_list" => DataFileEntry::JsonList(path),
+            "json_grid" => DataFileEntry::JsonGrid(path),
+            "yaml" => DataFileEntry::Yaml(path),
+            "yaml_list" => DataFileEntry::YamlList(path),
+            "yaml_grid" => DataFileEntry::YamlGrid(path),
+            "csv_list" => DataFileEntry::CsvList(path),
+            "csv_grid" => DataFileEntry::CsvGrid(path),
+            _ => return None,
+        };
+        Some(entry)
+    }
+}
+
 /// Data image entry model. Used specifically for importing grid data.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum DataImageEntry {
     BmpU8(String, String),
     BmpU8U8U8(String, String),
@@ -1089,3 +1828,95 @@ pub enum DataImageEntry {
     PngU8U8U8Concat(String, String),
     // PngCombineU8U8U8U8(String, String),
 }
+
+#[test]
+fn merge_replaces_and_dedups_entries() {
+    let mut model = SimModel::default();
+    model.scripts.push("init.outcome".to_string());
+    model.data.push(DataEntry::Simple((
+        "a:pos:int:x".to_string(),
+        "1".to_string(),
+    )));
+    model
+        .data_files
+        .push(DataFileEntry::CsvList("prices.csv".to_string()));
+
+    let mut other = model.clone();
+    other.data[0] = DataEntry::Simple(("a:pos:int:x".to_string(), "2".to_string()));
+    model.merge(other);
+
+    assert_eq!(model.scripts.len(), 1);
+    assert_eq!(model.data_files.len(), 1);
+    assert_eq!(model.data.len(), 1);
+    match &model.data[0] {
+        DataEntry::Simple((_, value)) => assert_eq!(value, "2"),
+        _ => panic!("unexpected data entry"),
+    }
+}
+
+#[test]
+fn unknown_unit_fails_loading() {
+    let file: deser::DataFile = toml::from_str(
+        r#"
+        [components.pos.vars]
+        "float:x" = { default = 1.0, unit = "furlong" }
+        "#,
+    )
+    .unwrap();
+    let mut model = SimModel::default();
+    match model.apply_from_structured_file(file) {
+        Err(Error::UnknownUnit(unit)) => assert_eq!(unit, "furlong"),
+        other => panic!("expected unknown unit error, got: {:?}", other),
+    }
+}
+
+#[test]
+fn nested_declaration_as_default_fails_loading() {
+    let file: deser::DataFile = toml::from_str(
+        r#"
+        [components.pos.vars]
+        "float:x" = { default = { default = 1.0, min = 0.0 } }
+        "#,
+    )
+    .unwrap();
+    let mut model = SimModel::default();
+    assert!(model.apply_from_structured_file(file).is_err());
+    assert!(model.components.is_empty());
+}
+
+#[test]
+fn prefab_defaults_survive_export() {
+    let mut model = crate::SimModelBuilder::new()
+        .component("pos", |c| c.var("float:x", None))
+        .prefab("dot", &["pos"])
+        .build()
+        .unwrap();
+    let (prefab, comp, var) = (
+        string::new_truncate("dot"),
+        string::new_truncate("pos"),
+        string::new_truncate("x"),
+    );
+    model
+        .set_prefab_default(&prefab, &comp, &var, Var::Float(2.))
+        .unwrap();
+
+    // tuple keys are written out as strings
+    let yaml = serde_yaml::to_string(model.get_entity(&prefab).unwrap()).unwrap();
+    let decoded: EntityPrefab = serde_yaml::from_str(&yaml).unwrap();
+    assert_eq!(
+        decoded.defaults[&(comp.clone(), var.clone())],
+        Var::Float(2.)
+    );
+
+    let dir = std::env::temp_dir().join(format!("outcome_export_{}", std::process::id()));
+    model.export_yaml(&dir).unwrap();
+    let data: deser::DataFile =
+        serde_yaml::from_slice(&read(dir.join(export::EXPORT_DATA_FILE)).unwrap()).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let mut loaded = SimModel::default();
+    loaded.apply_from_structured_file(data).unwrap();
+    assert_eq!(
+        loaded.get_entity(&prefab).unwrap().defaults[&(comp, var)],
+        Var::Float(2.)
+    );
+}
@@ -0,0 +1,161 @@
+//! Entity ordering policies.
+//!
+//! Entities are processed in parallel during the local phase, but commands
+//! reaching outside the entity, such as setting another entity's var, are
+//! collected and executed one after another in the post phase. The order
+//! in which entities get to execute those commands can bias interaction
+//! outcomes, e.g. the first agent to claim a shared resource always wins.
+//!
+//! Ordering policy can be set for each event within a module data file.
+//! Commands are executed grouped by the event that triggered them, in the
+//! order of the event queue, and within each event group entities are
+//! ordered using the event's policy.
+//!
+//! ```yaml
+//! events:
+//!   - trade
+//!   - migrate
+//! event_order:
+//!   trade: shuffle
+//!   migrate: round_robin
+//! ```
+//!
+//! All policies are deterministic, with shuffling derived from the
+//! scenario seed, the current step and the event name only. Commands
+//! issued by a single entity always keep their original order.
+
+use std::hash::Hasher;
+
+use fnv::FnvHasher;
+
+use crate::{EntityId, EventName};
+
+/// Policy for ordering entities within a single event group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntityOrder {
+    /// Ascending entity id
+    Sorted,
+    /// Random permutation, different for each step
+    Shuffle,
+    /// Ascending entity id, with the starting entity moving forward by one
+    /// each step, wrapping around at the end
+    RoundRobin,
+}
+
+impl Default for EntityOrder {
+    fn default() -> Self {
+        EntityOrder::Sorted
+    }
+}
+
+impl EntityOrder {
+    /// Returns the sorting key of the entity for the given step.
+    ///
+    /// `entities` is the sorted list of all entities, used for calculating
+    /// the round-robin offset.
+    pub fn key(
+        &self,
+        ent: EntityId,
+        entities: &[EntityId],
+        seed: u64,
+        clock: usize,
+        event: &EventName,
+    ) -> u64 {
+        match self {
+            EntityOrder::Sorted => ent as u64,
+            EntityOrder::Shuffle => {
+                let mut hasher = FnvHasher::default();
+                hasher.write_u64(seed);
+                hasher.write_usize(clock);
+                hasher.write(event.as_bytes());
+                hasher.write_u64(ent as u64);
+                hasher.finish()
+            }
+            EntityOrder::RoundRobin => {
+                let len = entities.len().max(1);
+                let pos = match entities.binary_search(&ent) {
+                    Ok(pos) | Err(pos) => pos,
+                };
+                ((pos + len - clock % len) % len) as u64
+            }
+        }
+    }
+}
+
+/// Sorts commands tagged with triggering events, returning them in
+/// execution order with the tags removed.
+///
+/// Commands are grouped by event, following the order of the event queue,
+/// with events not found in the queue placed last. Sorting is stable, so
+/// commands of a single entity keep their relative order.
+pub fn sort_commands<C>(
+    mut cmds: Vec<(EventName, EntityId, C)>,
+    event_queue: &[EventName],
+    policy: impl Fn(&EventName) -> EntityOrder,
+    entities: &[EntityId],
+    seed: u64,
+    clock: usize,
+) -> Vec<C> {
+    cmds.sort_by_cached_key(|(event, ent, _)| {
+        let event_pos = event_queue
+            .iter()
+            .position(|e| e == event)
+            .unwrap_or(event_queue.len());
+        (
+            event_pos,
+            event.clone(),
+            policy(event).key(*ent, entities, seed, clock, event),
+        )
+    });
+    cmds.into_iter().map(|(_, _, cmd)| cmd).collect()
+}
+
+#[test]
+fn commands_are_grouped_by_event_and_ordered_by_policy() {
+    use crate::string::new_truncate;
+
+    let step = new_truncate("step");
+    let trade = new_truncate("trade");
+    let entities = vec![1, 2, 3];
+    let cmds = vec![
+        (trade.clone(), 1, "trade_1a"),
+        (trade.clone(), 3, "trade_3"),
+        (step.clone(), 2, "step_2"),
+        (trade.clone(), 1, "trade_1b"),
+        (new_truncate("unknown"), 1, "unknown_1"),
+        (trade.clone(), 2, "trade_2"),
+        (step.clone(), 1, "step_1"),
+    ];
+    let policy = |event: &EventName| {
+        if event == &trade {
+            EntityOrder::RoundRobin
+        } else {
+            EntityOrder::Sorted
+        }
+    };
+    let queue = vec![step.clone(), trade.clone()];
+
+    assert_eq!(
+        sort_commands(cmds.clone(), &queue, policy, &entities, 0, 1),
+        vec![
+            "step_1",
+            "step_2",
+            "trade_2",
+            "trade_3",
+            "trade_1a",
+            "trade_1b",
+            "unknown_1"
+        ]
+    );
+
+    let shuffled = |seed: u64| {
+        let mut order = entities.clone();
+        order.sort_by_key(|e| EntityOrder::Shuffle.key(*e, &entities, seed, 1, &trade));
+        order
+    };
+    assert_eq!(shuffled(7), shuffled(7));
+    let mut sorted = shuffled(7);
+    sorted.sort_unstable();
+    assert_eq!(sorted, entities);
+}
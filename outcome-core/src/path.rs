@@ -0,0 +1,321 @@
+//! Pathfinding over grid and graph vars.
+//!
+//! Grids are treated as cost maps, with paths moving between horizontally
+//! and vertically adjacent cells. Entering a cell costs the cell's value,
+//! while cells with negative values can't be entered. Graphs use edge
+//! weights as costs, negative weights are not supported.
+//!
+//! Paths are returned as list vars, with grid cells as `vec2` coordinates
+//! and graph nodes as `int` entity ids.
+//!
+//! Requires the `pathfinding` feature.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::hash::Hash;
+use std::str::FromStr;
+
+use fnv::FnvHashMap;
+
+use crate::error::{Error, Result};
+use crate::graph::Graph;
+use crate::{EntityId, Float, Int, Var, VarType};
+
+/// Search algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Algorithm {
+    Dijkstra,
+    /// Dijkstra guided by the Manhattan distance heuristic, only available
+    /// for grids, graphs fall back to regular Dijkstra
+    AStar,
+}
+
+impl Default for Algorithm {
+    fn default() -> Self {
+        Algorithm::AStar
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "dijkstra" => Ok(Algorithm::Dijkstra),
+            "astar" | "a*" => Ok(Algorithm::AStar),
+            _ => Err(Error::Other(format!(
+                "unknown pathfinding algorithm: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Path found between two nodes, including both ends.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path<N> {
+    pub nodes: Vec<N>,
+    pub cost: Float,
+}
+
+/// Finds the cheapest path on a grid or graph var. Grid ends are `vec2`
+/// coordinates, graph ends are entity ids.
+///
+/// Returns the path as a list var along with the total cost, or `None` if
+/// there is no path.
+pub fn find_path(
+    var: &Var,
+    from: &Var,
+    to: &Var,
+    algorithm: Algorithm,
+) -> Result<Option<(Var, Float)>> {
+    match var {
+        Var::Graph(graph) => {
+            let path = graph_path(graph, from.to_int() as EntityId, to.to_int() as EntityId)?;
+            Ok(path.map(|path| {
+                let nodes = path.nodes.into_iter().map(|n| Var::Int(n as Int)).collect();
+                (Var::List(nodes), path.cost)
+            }))
+        }
+        Var::Grid(grid) => Ok(
+            grid_path(grid, cell(from)?, cell(to)?, algorithm)?.map(|path| {
+                let nodes = path
+                    .nodes
+                    .into_iter()
+                    .map(|(x, y)| Var::Vec2(x as Float, y as Float))
+                    .collect();
+                (Var::List(nodes), path.cost)
+            }),
+        ),
+        _ => Err(Error::InvalidVarType(format!(
+            "expected grid or graph, got {}",
+            var.get_type().to_str()
+        ))),
+    }
+}
+
+/// Finds the cheapest path between two graph nodes using Dijkstra's
+/// algorithm.
+pub fn graph_path(graph: &Graph, from: EntityId, to: EntityId) -> Result<Option<Path<EntityId>>> {
+    if graph.adjacency.values().flatten().any(|(_, w)| *w < 0.) {
+        return Err(Error::Other(
+            "pathfinding doesn't support negative edge weights".to_string(),
+        ));
+    }
+    if !graph.contains_node(from) || !graph.contains_node(to) {
+        return Ok(None);
+    }
+    Ok(search(
+        from,
+        to,
+        |node| {
+            graph.adjacency[node]
+                .iter()
+                .map(|(n, w)| (*n, *w))
+                .collect()
+        },
+        |_| 0.,
+    ))
+}
+
+/// Finds the cheapest path between two cells of a numeric grid. Cells are
+/// given as `(x, y)` coordinates.
+pub fn grid_path(
+    grid: &Vec<Vec<Var>>,
+    from: (usize, usize),
+    to: (usize, usize),
+    algorithm: Algorithm,
+) -> Result<Option<Path<(usize, usize)>>> {
+    let cost = |(x, y): (usize, usize)| -> Option<Float> {
+        let c = grid.get(y)?.get(x)?.to_float();
+        if c < 0. {
+            None
+        } else {
+            Some(c)
+        }
+    };
+    for row in grid {
+        for cell in row {
+            match cell.get_type() {
+                VarType::Int | VarType::Float | VarType::Byte => (),
+                t => {
+                    return Err(Error::InvalidVarType(format!(
+                        "expected numeric grid, got cell of type {}",
+                        t.to_str()
+                    )))
+                }
+            }
+        }
+    }
+    if cost(from).is_none() || cost(to).is_none() {
+        return Ok(None);
+    }
+
+    // heuristic needs to never overestimate the remaining cost
+    let min_cost = grid
+        .iter()
+        .flatten()
+        .map(|c| c.to_float())
+        .filter(|c| *c >= 0.)
+        .fold(Float::MAX, Float::min);
+    let heuristic = |(x, y): &(usize, usize)| -> Float {
+        match algorithm {
+            Algorithm::Dijkstra => 0.,
+            Algorithm::AStar => {
+                let dist =
+                    (*x as isize - to.0 as isize).abs() + (*y as isize - to.1 as isize).abs();
+                dist as Float * min_cost
+            }
+        }
+    };
+    let neighbors = |&(x, y): &(usize, usize)| -> Vec<((usize, usize), Float)> {
+        let mut out = Vec::with_capacity(4);
+        let mut push = |cell: (usize, usize)| {
+            if let Some(c) = cost(cell) {
+                out.push((cell, c));
+            }
+        };
+        if x > 0 {
+            push((x - 1, y));
+        }
+        if y > 0 {
+            push((x, y - 1));
+        }
+        push((x + 1, y));
+        push((x, y + 1));
+        out
+    };
+    Ok(search(from, to, neighbors, heuristic))
+}
+
+/// Reads grid cell coordinates from a var.
+fn cell(var: &Var) -> Result<(usize, usize)> {
+    match var {
+        Var::Vec2(x, y) if *x >= 0. && *y >= 0. => Ok((*x as usize, *y as usize)),
+        _ => Err(Error::Other(format!(
+            "expected non-negative vec2 grid coordinates, got {}",
+            var.to_string()
+        ))),
+    }
+}
+
+/// Entry in the open set, ordered so that the binary heap pops the lowest
+/// estimate first.
+struct Open<N> {
+    estimate: Float,
+    cost: Float,
+    node: N,
+}
+
+impl<N> PartialEq for Open<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.estimate == other.estimate
+    }
+}
+
+impl<N> Eq for Open<N> {}
+
+impl<N> PartialOrd for Open<N> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<N> Ord for Open<N> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .estimate
+            .partial_cmp(&self.estimate)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Best-first search, A* with the given heuristic, or Dijkstra with a zero
+/// heuristic.
+fn search<N, FN, FH>(from: N, to: N, neighbors: FN, heuristic: FH) -> Option<Path<N>>
+where
+    N: Copy + Eq + Hash,
+    FN: Fn(&N) -> Vec<(N, Float)>,
+    FH: Fn(&N) -> Float,
+{
+    let mut costs: FnvHashMap<N, Float> = FnvHashMap::default();
+    let mut came_from: FnvHashMap<N, N> = FnvHashMap::default();
+    let mut open = BinaryHeap::new();
+    costs.insert(from, 0.);
+    open.push(Open {
+        estimate: heuristic(&from),
+        cost: 0.,
+        node: from,
+    });
+
+    while let Some(Open { cost, node, .. }) = open.pop() {
+        if node == to {
+            let mut nodes = vec![to];
+            let mut current = to;
+            while let Some(prev) = came_from.get(&current) {
+                nodes.push(*prev);
+                current = *prev;
+            }
+            nodes.reverse();
+            return Some(Path { nodes, cost });
+        }
+        // skip stale entries
+        if costs.get(&node).map_or(false, |c| cost > *c) {
+            continue;
+        }
+        for (next, step_cost) in neighbors(&node) {
+            let next_cost = cost + step_cost;
+            if costs.get(&next).map_or(true, |c| next_cost < *c) {
+                costs.insert(next, next_cost);
+                came_from.insert(next, node);
+                open.push(Open {
+                    estimate: next_cost + heuristic(&next),
+                    cost: next_cost,
+                    node: next,
+                });
+            }
+        }
+    }
+    None
+}
+
+#[test]
+fn grid_paths_avoid_blocked_cells() {
+    let grid = vec![
+        vec![Var::Int(1), Var::Int(1), Var::Int(1)],
+        vec![Var::Int(-1), Var::Int(-1), Var::Int(1)],
+        vec![Var::Int(1), Var::Int(1), Var::Int(1)],
+    ];
+    for algorithm in &[Algorithm::Dijkstra, Algorithm::AStar] {
+        let path = grid_path(&grid, (0, 0), (0, 2), *algorithm)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            path.nodes,
+            vec![(0, 0), (1, 0), (2, 0), (2, 1), (2, 2), (1, 2), (0, 2)]
+        );
+        assert_eq!(path.cost, 6.);
+    }
+    assert_eq!(
+        grid_path(&grid, (0, 0), (1, 1), Algorithm::AStar).unwrap(),
+        None
+    );
+    assert!(find_path(&Var::Int(1), &Var::Int(0), &Var::Int(1), Algorithm::AStar).is_err());
+}
+
+#[test]
+fn graph_paths_follow_cheapest_edges() {
+    let mut graph = Graph::new(false);
+    graph.add_edge(1, 2, 5.);
+    graph.add_edge(1, 3, 1.);
+    graph.add_edge(3, 2, 1.);
+    graph.add_node(4);
+
+    let path = graph_path(&graph, 1, 2).unwrap().unwrap();
+    assert_eq!(path.nodes, vec![1, 3, 2]);
+    assert_eq!(path.cost, 2.);
+    assert_eq!(graph_path(&graph, 1, 4).unwrap(), None);
+
+    graph.add_edge(2, 4, -1.);
+    assert!(graph_path(&graph, 1, 4).is_err());
+}
@@ -0,0 +1,19 @@
+//! Curated set of the most commonly used items.
+//!
+//! Items exported here make up the stable surface of the library, and are
+//! meant to be glob-imported:
+//!
+//! ```ignore
+//! use outcome_core::prelude::*;
+//! ```
+
+pub use crate::address::Address;
+pub use crate::entity::{ComponentView, Entity, EntityRef};
+pub use crate::error::{Error, Result};
+pub use crate::interface::SimInterface;
+pub use crate::model::{SimModel, SimModelBuilder};
+pub use crate::query::{Query, QueryProduct};
+pub use crate::sim::Sim;
+pub use crate::string::new_truncate;
+pub use crate::var::{Var, VarType};
+pub use crate::{CompName, EntityId, EntityName, EventName, Float, Int, StringId, VarName};
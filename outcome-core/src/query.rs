@@ -1,12 +1,18 @@
 //! Data query system.
 
-use crate::entity::Entity;
+use crate::entity::{Entity, StorageIndex};
+use crate::error::Error;
+use crate::geo::GeoPosition;
+use crate::graph::Graph;
 use crate::{
     Address, CompName, EntityId, EntityName, EventName, Float, Int, Result, StringId, Var, VarName,
     VarType,
 };
 use fnv::FnvHashMap;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
 use std::time::Instant;
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -66,12 +72,83 @@ pub struct AddressedTypedMap {
     pub bools: FnvHashMap<Address, bool>,
 }
 
+/// Custom filter function, returning whether the entity should be
+/// selected. Receives the arguments provided with the filter.
+pub type FilterFn = dyn Fn(EntityId, &Entity, &[String]) -> bool + Send + Sync;
+
+/// Custom map function, returning indices of the entity's vars that should
+/// be included in the query product. Receives the arguments provided with
+/// the map.
+pub type MapFn = dyn Fn(EntityId, &Entity, &[String]) -> Vec<StorageIndex> + Send + Sync;
+
+/// Custom filters and maps registered by the embedder.
+///
+/// Registered functions are referenced by name using `Filter::Custom` and
+/// `Map::Custom`, which allows remote clients to make use of
+/// domain-specific selection logic, e.g. geofence tests, without it being
+/// built into the engine.
+#[derive(Clone, Default)]
+pub struct QueryPlugins {
+    filters: FnvHashMap<String, Arc<FilterFn>>,
+    maps: FnvHashMap<String, Arc<MapFn>>,
+}
+
+impl QueryPlugins {
+    /// Registers a custom filter, replacing any filter previously
+    /// registered under the same name.
+    pub fn register_filter<F>(&mut self, name: &str, filter: F)
+    where
+        F: Fn(EntityId, &Entity, &[String]) -> bool + Send + Sync + 'static,
+    {
+        self.filters.insert(name.to_string(), Arc::new(filter));
+    }
+
+    /// Registers a custom map, replacing any map previously registered
+    /// under the same name.
+    pub fn register_map<F>(&mut self, name: &str, map: F)
+    where
+        F: Fn(EntityId, &Entity, &[String]) -> Vec<StorageIndex> + Send + Sync + 'static,
+    {
+        self.maps.insert(name.to_string(), Arc::new(map));
+    }
+
+    pub fn get_filter(&self, name: &str) -> Option<&Arc<FilterFn>> {
+        self.filters.get(name)
+    }
+
+    pub fn get_map(&self, name: &str) -> Option<&Arc<MapFn>> {
+        self.maps.get(name)
+    }
+}
+
+impl fmt::Debug for QueryPlugins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueryPlugins")
+            .field("filters", &self.filters.keys().collect::<Vec<_>>())
+            .field("maps", &self.maps.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
 impl Query {
-    pub fn process(
+    /// Applies query filters, returning the list of selected entities.
+    pub fn select_entities(
         &self,
         entities: &FnvHashMap<u32, Entity>,
         entity_names: &FnvHashMap<EntityName, EntityId>,
-    ) -> Result<QueryProduct> {
+    ) -> Vec<EntityId> {
+        self.select_entities_with(entities, entity_names, &QueryPlugins::default())
+    }
+
+    /// Applies query filters, using the provided plugins for custom
+    /// filters. Custom filters that weren't registered don't select any
+    /// entities.
+    pub fn select_entities_with(
+        &self,
+        entities: &FnvHashMap<u32, Entity>,
+        entity_names: &FnvHashMap<EntityName, EntityId>,
+        plugins: &QueryPlugins,
+    ) -> Vec<EntityId> {
         let mut selected_entities = entities.keys().map(|v| *v).collect::<Vec<u32>>();
         // println!(
         //     "copying all entity keys took: {} ms",
@@ -255,15 +332,132 @@ impl Query {
                         }
                     }
                 }
+                Filter::Neighbors(graph_addr, node) => {
+                    if let Some(graph) = get_graph(graph_addr, entities, entity_names) {
+                        let neighbors = graph.neighbors(*node);
+                        for entity_id in &selected_entities {
+                            if neighbors.contains(entity_id) {
+                                to_retain.push(*entity_id);
+                            }
+                        }
+                    }
+                }
+                Filter::Degree(graph_addr, min, max) => {
+                    if let Some(graph) = get_graph(graph_addr, entities, entity_names) {
+                        for entity_id in &selected_entities {
+                            let degree = graph.degree(*entity_id) as u32;
+                            if degree >= *min && degree <= *max {
+                                to_retain.push(*entity_id);
+                            }
+                        }
+                    }
+                }
+                Filter::GeoDistance(comp_name, var_name, point, max_distance) => {
+                    let idx = (comp_name.clone(), var_name.clone());
+                    for entity_id in &selected_entities {
+                        if let Some(entity) = entities.get(entity_id) {
+                            if let Ok(Var::Geo(pos)) = entity.storage.get_var(&idx) {
+                                if pos.distance(point) <= *max_distance {
+                                    to_retain.push(*entity_id);
+                                }
+                            }
+                        }
+                    }
+                }
+                Filter::VarEquals(comp, var, value) => {
+                    to_retain = select_by_var(&selected_entities, entities, comp, var, |v| {
+                        var_equals(v, value)
+                    })
+                }
+                Filter::VarGreaterThan(comp, var, min) => {
+                    to_retain = select_by_var(&selected_entities, entities, comp, var, |v| {
+                        compare_numeric(v, &Var::Float(*min)) == Some(Ordering::Greater)
+                    })
+                }
+                Filter::VarLessThan(comp, var, max) => {
+                    to_retain = select_by_var(&selected_entities, entities, comp, var, |v| {
+                        compare_numeric(v, &Var::Float(*max)) == Some(Ordering::Less)
+                    })
+                }
+                Filter::VarInRange(comp, var, min, max) => {
+                    to_retain = select_by_var(&selected_entities, entities, comp, var, |v| {
+                        matches!(
+                            compare_numeric(v, &Var::Float(*min)),
+                            Some(Ordering::Greater) | Some(Ordering::Equal)
+                        ) && matches!(
+                            compare_numeric(v, &Var::Float(*max)),
+                            Some(Ordering::Less) | Some(Ordering::Equal)
+                        )
+                    })
+                }
+                Filter::Contains(comp, var, needle) => {
+                    to_retain = select_by_var(&selected_entities, entities, comp, var, |v| {
+                        contains(v, needle)
+                    })
+                }
+                Filter::Custom(name, args) => match plugins.get_filter(name) {
+                    Some(filter) => {
+                        for entity_id in &selected_entities {
+                            if let Some(entity) = entities.get(entity_id) {
+                                if filter(*entity_id, entity, args) {
+                                    to_retain.push(*entity_id);
+                                }
+                            }
+                        }
+                    }
+                    None => warn!("custom query filter not registered: {}", name),
+                },
                 _ => unimplemented!(),
             }
 
             selected_entities = to_retain;
         }
 
+        selected_entities
+    }
+
+    pub fn process(
+        &self,
+        entities: &FnvHashMap<u32, Entity>,
+        entity_names: &FnvHashMap<EntityName, EntityId>,
+    ) -> Result<QueryProduct> {
+        self.process_with(entities, entity_names, &QueryPlugins::default())
+    }
+
+    /// Processes the query, using the provided plugins for custom filters
+    /// and maps. Custom maps that weren't registered result in an error.
+    pub fn process_with(
+        &self,
+        entities: &FnvHashMap<u32, Entity>,
+        entity_names: &FnvHashMap<EntityName, EntityId>,
+        plugins: &QueryPlugins,
+    ) -> Result<QueryProduct> {
+        let selected_entities = self.select_entities_with(entities, entity_names, plugins);
+        self.process_selected(&selected_entities, entities, plugins)
+    }
+
+    /// Processes the query over entities already selected using
+    /// [`Query::select_entities_with`].
+    pub fn process_selected(
+        &self,
+        selected_entities: &[EntityId],
+        entities: &FnvHashMap<u32, Entity>,
+        plugins: &QueryPlugins,
+    ) -> Result<QueryProduct> {
+        for mapping in &self.mappings {
+            if let Map::Custom(name, _) = mapping {
+                if plugins.get_map(name).is_none() {
+                    return Err(Error::Other(format!(
+                        "custom query map not registered: {}",
+                        name
+                    )));
+                }
+            }
+        }
+
         // let insta = std::time::Instant::now();
         let mut mapped_data = FnvHashMap::default();
-        for entity_id in &selected_entities {
+        for entity_id in selected_entities {
             for mapping in &self.mappings {
                 match mapping {
                     Map::All => {
@@ -304,6 +498,19 @@ impl Query {
                             }
                         }
                     }
+                    Map::Custom(name, args) => {
+                        if let (Some(map), Some(entity)) =
+                            (plugins.get_map(name), entities.get(entity_id))
+                        {
+                            for index in map(*entity_id, entity, args) {
+                                if let Some(((comp_name, var_name), var)) =
+                                    entity.storage.map.get_key_value(&index)
+                                {
+                                    mapped_data.insert((entity_id, comp_name, var_name), var);
+                                }
+                            }
+                        }
+                    }
                     _ => unimplemented!(),
                 }
             }
@@ -399,6 +606,96 @@ impl Query {
     }
 }
 
+/// Selects the entities for which the var satisfies the predicate.
+/// Entities without the var are not selected.
+fn select_by_var<F: Fn(&Var) -> bool>(
+    selected_entities: &[EntityId],
+    entities: &FnvHashMap<u32, Entity>,
+    comp_name: &CompName,
+    var_name: &VarName,
+    predicate: F,
+) -> Vec<EntityId> {
+    let idx = (comp_name.clone(), var_name.clone());
+    selected_entities
+        .iter()
+        .filter(|id| {
+            entities
+                .get(id)
+                .and_then(|e| e.storage.get_var(&idx).ok())
+                .map_or(false, |v| predicate(v))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Gets the value of a numeric var as a double precision float, computed
+/// from the raw representation. Other vars can't be compared against
+/// numbers.
+fn numeric(var: &Var) -> Option<f64> {
+    match var {
+        Var::Int(v) => Some(*v as f64),
+        Var::Float(v) => Some(*v as f64),
+        Var::Byte(v) => Some(*v as f64),
+        Var::Fixed(v) => Some(v.raw as f64 / 2f64.powi(v.frac as i32)),
+        Var::Decimal(v) => Some(v.raw as f64 / 10f64.powi(v.scale as i32)),
+        _ => None,
+    }
+}
+
+/// Compares numeric vars by value. Vars of the same type are compared
+/// exactly, only vars of different types are compared as floats.
+fn compare_numeric(a: &Var, b: &Var) -> Option<Ordering> {
+    match (a, b) {
+        (Var::Int(a), Var::Int(b)) => Some(a.cmp(b)),
+        (Var::Byte(a), Var::Byte(b)) => Some(a.cmp(b)),
+        (Var::Float(a), Var::Float(b)) => a.partial_cmp(b),
+        (Var::Fixed(a), Var::Fixed(b)) => a.partial_cmp(b),
+        (Var::Decimal(a), Var::Decimal(b)) => a.partial_cmp(b),
+        _ => numeric(a)?.partial_cmp(&numeric(b)?),
+    }
+}
+
+/// Checks vars for equality, with numeric vars compared by value.
+fn var_equals(var: &Var, value: &Var) -> bool {
+    match compare_numeric(var, value) {
+        Some(ordering) => ordering == Ordering::Equal,
+        None => var == value,
+    }
+}
+
+/// Checks whether the string var contains the substring, or the list var
+/// contains the string.
+fn contains(var: &Var, needle: &str) -> bool {
+    match var {
+        Var::String(s) => s.contains(needle),
+        Var::List(list) => list.iter().any(|v| match v {
+            Var::String(s) => s == needle,
+            _ => false,
+        }),
+        _ => false,
+    }
+}
+
+/// Finds the graph var at address, with the entity given either by name or
+/// by id.
+fn get_graph<'a>(
+    addr: &Address,
+    entities: &'a FnvHashMap<u32, Entity>,
+    entity_names: &FnvHashMap<EntityName, EntityId>,
+) -> Option<&'a Graph> {
+    let entity_id = match entity_names.get(&addr.entity) {
+        Some(entity_id) => *entity_id,
+        None => addr.entity.parse().ok()?,
+    };
+    entities
+        .get(&entity_id)?
+        .storage
+        .get_var(&addr.storage_index())
+        .ok()?
+        .as_graph()
+        .ok()
+}
+
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct GlobAddress {
     pub entity: String,
@@ -440,6 +737,32 @@ pub enum Filter {
     /// Select entities currently stored on selected worker nodes
     /// (0 is local worker)
     Node(u32),
+    /// Select entities connected to the given entity through a single edge
+    /// of the graph at address
+    Neighbors(Address, EntityId),
+    /// Select entities with the number of edges in the graph at address
+    /// within the specified range
+    Degree(Address, u32, u32),
+    /// Filter by geographic distance in meters between the position stored
+    /// in the entity's geo var and the given position
+    GeoDistance(CompName, VarName, GeoPosition, f64),
+    /// Select entities where the var is equal to the given value, numeric
+    /// vars are compared by value regardless of their type
+    VarEquals(CompName, VarName, Var),
+    /// Select entities where the numeric var is greater than the given
+    /// value
+    VarGreaterThan(CompName, VarName, Float),
+    /// Select entities where the numeric var is less than the given value
+    VarLessThan(CompName, VarName, Float),
+    /// Select entities where the numeric var is within the given range,
+    /// inclusive
+    VarInRange(CompName, VarName, Float, Float),
+    /// Select entities where the string var contains the given substring,
+    /// or where the list var contains the given string
+    Contains(CompName, VarName, String),
+    /// Filter using a custom filter registered under the given name, with
+    /// the given arguments
+    Custom(String, Vec<String>),
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -453,6 +776,9 @@ pub enum Map {
     Var(VarType, VarName),
     VarName(VarName),
     VarType(VarType),
+    /// Select data using a custom map registered under the given name,
+    /// with the given arguments
+    Custom(String, Vec<String>),
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -475,3 +801,205 @@ pub enum Layout {
     Typed,
     // TypedSubset(Vec<VarType>),
 }
+
+#[test]
+fn custom_filters_and_maps_are_applied() {
+    use crate::string;
+
+    let index = |comp: &str, var: &str| (string::new_truncate(comp), string::new_truncate(var));
+    let mut entities = FnvHashMap::default();
+    for (id, x) in &[(1, 1.), (2, 5.), (3, 9.)] {
+        let mut entity = Entity::empty();
+        entity.storage.insert(index("pos", "x"), Var::Float(*x));
+        entity.storage.insert(index("pos", "y"), Var::Float(0.));
+        entities.insert(*id, entity);
+    }
+    let mut plugins = QueryPlugins::default();
+    plugins.register_filter("x_above", |_, entity, args| {
+        let min = args[0].parse::<Float>().unwrap();
+        entity
+            .storage
+            .get_var(&(string::new_truncate("pos"), string::new_truncate("x")))
+            .map_or(false, |x| x.to_float() > min)
+    });
+    plugins.register_map("only", |_, _, args| {
+        vec![(
+            string::new_truncate(&args[0]),
+            string::new_truncate(&args[1]),
+        )]
+    });
+
+    let query = Query {
+        trigger: Trigger::Immediate,
+        description: Description::NativeDescribed,
+        layout: Layout::Var,
+        filters: vec![Filter::Custom("x_above".to_string(), vec!["3".to_string()])],
+        mappings: vec![Map::Custom(
+            "only".to_string(),
+            vec!["pos".to_string(), "x".to_string()],
+        )],
+    };
+    let names = FnvHashMap::default();
+    let mut selected = query.select_entities_with(&entities, &names, &plugins);
+    selected.sort_unstable();
+    assert_eq!(selected, vec![2, 3]);
+    match query.process_with(&entities, &names, &plugins).unwrap() {
+        QueryProduct::NativeAddressedVar(data) => {
+            assert_eq!(data.len(), 2);
+            assert_eq!(
+                data.get(&(3, string::new_truncate("pos"), string::new_truncate("x"))),
+                Some(&Var::Float(9.))
+            );
+        }
+        product => panic!("unexpected product: {:?}", product),
+    }
+
+    // unregistered filters select nothing, unregistered maps are errors
+    assert!(query
+        .select_entities_with(&entities, &names, &QueryPlugins::default())
+        .is_empty());
+    assert!(query.process(&entities, &names).is_err());
+}
+
+#[cfg(test)]
+fn select_with_filter(values: Vec<Var>, filter: Filter) -> Vec<EntityId> {
+    use crate::string;
+
+    let mut entities = FnvHashMap::default();
+    for (id, value) in values.into_iter().enumerate() {
+        let mut entity = Entity::empty();
+        entity.storage.insert(
+            (string::new_truncate("stats"), string::new_truncate("v")),
+            value,
+        );
+        entities.insert(id as EntityId, entity);
+    }
+    let query = Query {
+        trigger: Trigger::Immediate,
+        description: Description::NativeDescribed,
+        layout: Layout::Var,
+        filters: vec![filter],
+        mappings: vec![Map::All],
+    };
+    let mut selected =
+        query.select_entities_with(&entities, &FnvHashMap::default(), &QueryPlugins::default());
+    selected.sort_unstable();
+    selected
+}
+
+#[test]
+fn var_equals_compares_same_types_exactly() {
+    use crate::string;
+    use crate::var::{Decimal, Fixed};
+
+    let equals = |value: Var| {
+        Filter::VarEquals(
+            string::new_truncate("stats"),
+            string::new_truncate("v"),
+            value,
+        )
+    };
+    // ints that are indistinguishable as single precision floats
+    assert_eq!(
+        select_with_filter(
+            vec![Var::Int(16777216), Var::Int(16777217)],
+            equals(Var::Int(16777217))
+        ),
+        vec![1]
+    );
+    // fixed and decimal values are compared by value, not representation
+    assert_eq!(
+        select_with_filter(
+            vec![
+                Var::Fixed(Fixed::from_int(3, 8).unwrap()),
+                Var::Fixed(Fixed::from_int(4, 8).unwrap())
+            ],
+            equals(Var::Fixed(Fixed::from_int(3, 16).unwrap()))
+        ),
+        vec![0]
+    );
+    assert_eq!(
+        select_with_filter(
+            vec![
+                Var::Decimal(Decimal {
+                    raw: 1250,
+                    scale: 2
+                }),
+                Var::Decimal(Decimal {
+                    raw: 1251,
+                    scale: 2
+                }),
+            ],
+            equals(Var::Decimal(Decimal { raw: 125, scale: 1 }))
+        ),
+        vec![0]
+    );
+    // numbers of different types are compared by value
+    assert_eq!(
+        select_with_filter(vec![Var::Int(2), Var::Int(3)], equals(Var::Float(2.))),
+        vec![0]
+    );
+    assert_eq!(
+        select_with_filter(
+            vec![Var::String("a".to_string()), Var::Int(1)],
+            equals(Var::String("a".to_string()))
+        ),
+        vec![0]
+    );
+}
+
+#[test]
+fn var_ordering_and_range_filters_select_by_value() {
+    use crate::string;
+
+    let (comp, var) = (string::new_truncate("stats"), string::new_truncate("v"));
+    let values = || {
+        vec![
+            Var::Int(16777216),
+            Var::Int(16777217),
+            Var::Float(0.5),
+            Var::String("x".to_string()),
+        ]
+    };
+    assert_eq!(
+        select_with_filter(
+            values(),
+            Filter::VarGreaterThan(comp.clone(), var.clone(), 16777216.)
+        ),
+        vec![1]
+    );
+    assert_eq!(
+        select_with_filter(values(), Filter::VarLessThan(comp.clone(), var.clone(), 1.)),
+        vec![2]
+    );
+    // range bounds are inclusive
+    assert_eq!(
+        select_with_filter(
+            values(),
+            Filter::VarInRange(comp.clone(), var.clone(), 0.5, 16777216.)
+        ),
+        vec![0, 2]
+    );
+}
+
+#[test]
+fn contains_matches_substrings_and_list_elements() {
+    use crate::string;
+
+    let filter = Filter::Contains(
+        string::new_truncate("stats"),
+        string::new_truncate("v"),
+        "ab".to_string(),
+    );
+    let values = vec![
+        Var::String("cabin".to_string()),
+        Var::String("ba".to_string()),
+        Var::List(vec![
+            Var::String("x".to_string()),
+            Var::String("ab".to_string()),
+        ]),
+        Var::List(vec![Var::String("abc".to_string())]),
+        Var::Int(1),
+    ];
+    assert_eq!(select_with_filter(values, filter), vec![0, 2]);
+}
@@ -0,0 +1,193 @@
+//! Scheduling events for future steps.
+//!
+//! Events added to the event queue fire during the next processed step.
+//! The scheduler allows for firing events at a specific step instead, as
+//! well as for recurring events firing every given number of steps.
+//!
+//! Scheduled events are added to the event queue at the beginning of the
+//! step they're scheduled for. Scheduler is part of the simulation state,
+//! it's stored with snapshots.
+//!
+//! ```ignore
+//! // fire `harvest` during the step processed at clock 100
+//! sim.schedule_event("harvest", 100)?;
+//! // fire `census` every 10 steps, starting 10 steps from now
+//! sim.schedule_recurring_event("census", 10)?;
+//! ```
+
+use crate::distr::SimCentral;
+use crate::error::{Error, Result};
+use crate::{string, EventName, Sim};
+
+/// Event scheduled for a future step.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledEvent {
+    pub event: EventName,
+    /// Clock value of the step the event fires at
+    pub at_step: usize,
+    /// Interval in steps at which the event recurs, none for one-off
+    /// events
+    pub every_n_steps: Option<usize>,
+}
+
+/// Collection of scheduled events.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EventScheduler {
+    events: Vec<ScheduledEvent>,
+}
+
+impl EventScheduler {
+    /// Schedules the event to fire at the given step, recurring every
+    /// `every_n_steps` afterwards if provided.
+    pub fn schedule(
+        &mut self,
+        event: EventName,
+        at_step: usize,
+        every_n_steps: Option<usize>,
+    ) -> Result<()> {
+        if every_n_steps == Some(0) {
+            return Err(Error::Other(format!(
+                "recurring event {} needs an interval of at least one step",
+                event
+            )));
+        }
+        self.events.push(ScheduledEvent {
+            event,
+            at_step,
+            every_n_steps,
+        });
+        Ok(())
+    }
+
+    /// Removes all scheduled occurrences of the event. Returns the number
+    /// of removed entries.
+    pub fn cancel(&mut self, event: &EventName) -> usize {
+        let len = self.events.len();
+        self.events.retain(|e| &e.event != event);
+        len - self.events.len()
+    }
+
+    /// Lists all the scheduled events.
+    pub fn events(&self) -> &[ScheduledEvent] {
+        &self.events
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Takes events due at the given step, rescheduling the recurring ones.
+    ///
+    /// Events scheduled for steps that have already passed are considered
+    /// due as well.
+    pub fn take_due(&mut self, clock: usize) -> Vec<EventName> {
+        let mut due = Vec::new();
+        let mut remaining = Vec::with_capacity(self.events.len());
+        for mut scheduled in self.events.drain(..) {
+            if scheduled.at_step > clock {
+                remaining.push(scheduled);
+                continue;
+            }
+            if !due.contains(&scheduled.event) {
+                due.push(scheduled.event.clone());
+            }
+            if let Some(n) = scheduled.every_n_steps {
+                scheduled.at_step = clock + n;
+                remaining.push(scheduled);
+            }
+        }
+        self.events = remaining;
+        due
+    }
+}
+
+/// Checks that the step is not in the past.
+fn check_step(at_step: usize, clock: usize) -> Result<()> {
+    if at_step < clock {
+        return Err(Error::Other(format!(
+            "can't schedule event at step {}, current clock is {}",
+            at_step, clock
+        )));
+    }
+    Ok(())
+}
+
+impl Sim {
+    /// Schedules the event to fire during the step processed at the given
+    /// clock value. Scheduling at the current clock value fires the event
+    /// during the next processed step.
+    pub fn schedule_event(&mut self, event: &str, at_step: usize) -> Result<()> {
+        check_step(at_step, self.clock)?;
+        self.scheduler
+            .schedule(string::new_truncate(event), at_step, None)
+    }
+
+    /// Schedules the event to fire every `every_n_steps`, starting
+    /// `every_n_steps` from now.
+    pub fn schedule_recurring_event(&mut self, event: &str, every_n_steps: usize) -> Result<()> {
+        self.scheduler.schedule(
+            string::new_truncate(event),
+            self.clock + every_n_steps,
+            Some(every_n_steps),
+        )
+    }
+
+    /// Schedules the event to fire every `every_n_steps`, starting with the
+    /// step processed at the given clock value.
+    pub fn schedule_recurring_event_at(
+        &mut self,
+        event: &str,
+        at_step: usize,
+        every_n_steps: usize,
+    ) -> Result<()> {
+        check_step(at_step, self.clock)?;
+        self.scheduler
+            .schedule(string::new_truncate(event), at_step, Some(every_n_steps))
+    }
+
+    /// Cancels all scheduled occurrences of the event. Returns the number
+    /// of cancelled entries.
+    pub fn cancel_scheduled_event(&mut self, event: &str) -> usize {
+        self.scheduler.cancel(&string::new_truncate(event))
+    }
+}
+
+impl SimCentral {
+    /// Schedules the event to fire during the step processed at the given
+    /// clock value. Scheduling at the current clock value fires the event
+    /// during the next processed step.
+    pub fn schedule_event(&mut self, event: &str, at_step: usize) -> Result<()> {
+        check_step(at_step, self.clock)?;
+        self.scheduler
+            .schedule(string::new_truncate(event), at_step, None)
+    }
+
+    /// Schedules the event to fire every `every_n_steps`, starting
+    /// `every_n_steps` from now.
+    pub fn schedule_recurring_event(&mut self, event: &str, every_n_steps: usize) -> Result<()> {
+        self.scheduler.schedule(
+            string::new_truncate(event),
+            self.clock + every_n_steps,
+            Some(every_n_steps),
+        )
+    }
+
+    /// Schedules the event to fire every `every_n_steps`, starting with the
+    /// step processed at the given clock value.
+    pub fn schedule_recurring_event_at(
+        &mut self,
+        event: &str,
+        at_step: usize,
+        every_n_steps: usize,
+    ) -> Result<()> {
+        check_step(at_step, self.clock)?;
+        self.scheduler
+            .schedule(string::new_truncate(event), at_step, Some(every_n_steps))
+    }
+
+    /// Cancels all scheduled occurrences of the event. Returns the number
+    /// of cancelled entries.
+    pub fn cancel_scheduled_event(&mut self, event: &str) -> usize {
+        self.scheduler.cancel(&string::new_truncate(event))
+    }
+}
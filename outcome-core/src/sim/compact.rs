@@ -0,0 +1,275 @@
+//! Entity storage compaction.
+//!
+//! Long running simulations with frequent spawning and despawning of
+//! entities end up with a sparse entity id range, as well as maps holding
+//! on to capacity they no longer need. Compaction reassigns entity ids so
+//! that they make up a contiguous range again and shrinks internal maps.
+//!
+//! Compaction changes the integer ids of entities. Entity names are
+//! preserved, and should be used for any references that need to stay
+//! valid across compaction. Ids kept by the simulation itself, such as
+//! graph nodes and addresses of recorded vars, are updated, with graph
+//! nodes of entities that no longer exist removed. Integer ids stored
+//! inside other vars are not updated.
+//!
+//! Callers holding on to entity ids can use the returned [`IdRemap`] to
+//! update them, along with [`remap_address`] and [`remap_query`] for
+//! addresses and queries referencing entities by their ids.
+//!
+//! Compaction is recorded in the replay log, so that the ids used by
+//! mutations logged afterwards stay valid during replay.
+
+use fnv::FnvHashMap;
+use id_pool::IdPool;
+
+use crate::address::Address;
+use crate::error::{Error, Result};
+use crate::query::{Filter, Query};
+use crate::{string, EntityId, EntityName, Var};
+
+use super::replay::Mutation;
+use super::Sim;
+
+/// Mapping of old entity ids to new ones, only includes ids that changed.
+pub type IdRemap = FnvHashMap<EntityId, EntityId>;
+
+impl Sim {
+    /// Returns the fraction of the used id range not occupied by any
+    /// existing entity.
+    pub fn fragmentation(&self) -> f32 {
+        let range = match self.entities.keys().max() {
+            Some(max) => *max as f32 + 1.,
+            None => return 0.,
+        };
+        1. - self.entities.len() as f32 / range
+    }
+
+    /// Reassigns entity ids so that they make up a contiguous range, and
+    /// shrinks internal maps. Returns mapping of changed ids.
+    pub fn compact_entities(&mut self) -> Result<IdRemap> {
+        self.log_mutation(Mutation::CompactEntities)?;
+
+        let mut ids = self.entities.keys().cloned().collect::<Vec<_>>();
+        ids.sort_unstable();
+
+        let mut pool = IdPool::new();
+        let mut remap = IdRemap::default();
+        for id in &ids {
+            let new_id = pool.request_id().ok_or(Error::RequestIdError)?;
+            if new_id != *id {
+                remap.insert(*id, new_id);
+            }
+        }
+
+        if !remap.is_empty() {
+            let mut entities = FnvHashMap::default();
+            entities.reserve(self.entities.len());
+            for (id, entity) in self.entities.drain() {
+                entities.insert(*remap.get(&id).unwrap_or(&id), entity);
+            }
+            self.entities = entities;
+
+            for id in self.entity_idx.values_mut() {
+                if let Some(new_id) = remap.get(id) {
+                    *id = *new_id;
+                }
+            }
+
+            // removals refer to old ids, changes up to this point can only
+            // be fetched in full
+            self.removals = super::removals::Removals::since(self.clock + 1);
+
+            // entities found under the new ids are stored in full with
+            // the next delta snapshot
+            if let Some(baseline) = &mut self.delta_baseline {
+                for new_id in remap.values() {
+                    baseline.forget(new_id);
+                }
+            }
+
+            // graph vars are rewritten through the storage so that the
+            // changes are tracked
+            for entity in self.entities.values_mut() {
+                let graphs = entity
+                    .storage
+                    .map
+                    .iter()
+                    .filter_map(|(idx, var)| match var {
+                        Var::Graph(_) => Some(idx.clone()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                for idx in graphs {
+                    if let Ok(Var::Graph(graph)) = entity.storage.get_var_mut(&idx) {
+                        graph.map_nodes(|id| match remap.get(&id) {
+                            Some(new_id) => Some(*new_id),
+                            None => ids.binary_search(&id).ok().map(|_| id),
+                        });
+                    }
+                }
+            }
+
+            #[cfg(feature = "recorder")]
+            if let Some(recorder) = &mut self.recorder {
+                for addr in &mut recorder.config.addrs {
+                    remap_address(addr, &remap, &self.entity_idx);
+                }
+            }
+
+            #[cfg(feature = "machine")]
+            for (ctx, _) in &mut self.error_journal {
+                if let Some(new_id) = remap.get(&ctx.ent) {
+                    ctx.ent = *new_id;
+                }
+            }
+
+            #[cfg(feature = "machine_lua")]
+            {
+                self.entity_lua_state = self
+                    .entity_lua_state
+                    .drain()
+                    .map(|(id, state)| (*remap.get(&id).unwrap_or(&id), state))
+                    .collect();
+            }
+        }
+        self.entity_pool = pool;
+
+        self.entities.shrink_to_fit();
+        self.entity_idx.shrink_to_fit();
+        for entity in self.entities.values_mut() {
+            entity.storage.map.shrink_to_fit();
+            entity.components.shrink_to_fit();
+        }
+
+        Ok(remap)
+    }
+
+    /// Removes string indexes pointing to entities that no longer exist.
+    pub fn rebuild_entity_index(&mut self) {
+        let entities = &self.entities;
+        self.entity_idx.retain(|_, id| entities.contains_key(id));
+    }
+
+    /// Shrinks storage of the selected entities, without reassigning any
+    /// ids. Entities that don't exist are skipped.
+    pub fn shrink_entities(&mut self, ids: &[EntityId]) {
+        for id in ids {
+            if let Some(entity) = self.entities.get_mut(id) {
+                entity.storage.map.shrink_to_fit();
+                entity.components.shrink_to_fit();
+            }
+        }
+    }
+
+    /// Shrinks the maps holding entities and their string indexes.
+    pub fn shrink_entity_maps(&mut self) {
+        self.entities.shrink_to_fit();
+        self.entity_idx.shrink_to_fit();
+    }
+}
+
+/// Updates the address referencing an entity by its integer id, so that it
+/// references the same entity after compaction. Addresses using entity
+/// names are left as they are. Returns whether the address changed.
+pub fn remap_address(
+    addr: &mut Address,
+    remap: &IdRemap,
+    entity_idx: &FnvHashMap<EntityName, EntityId>,
+) -> bool {
+    if entity_idx.contains_key(&addr.entity) {
+        return false;
+    }
+    let new_id = match addr
+        .entity
+        .parse::<EntityId>()
+        .ok()
+        .and_then(|id| remap.get(&id))
+    {
+        Some(id) => *id,
+        None => return false,
+    };
+    addr.entity = string::new_truncate(&new_id.to_string());
+    true
+}
+
+/// Updates entity ids and addresses used by the query filters, so that
+/// they reference the same entities after compaction.
+pub fn remap_query(
+    query: &mut Query,
+    remap: &IdRemap,
+    entity_idx: &FnvHashMap<EntityName, EntityId>,
+) {
+    let remap_id = |id: &mut EntityId| {
+        if let Some(new_id) = remap.get(id) {
+            *id = *new_id;
+        }
+    };
+    for filter in &mut query.filters {
+        match filter {
+            Filter::Id(ids) => ids.iter_mut().for_each(remap_id),
+            Filter::Neighbors(addr, id) => {
+                remap_address(addr, remap, entity_idx);
+                remap_id(id);
+            }
+            Filter::VarRange(addr, ..) | Filter::Degree(addr, ..) => {
+                remap_address(addr, remap, entity_idx);
+            }
+            Filter::Distance(x, y, z, ..) => {
+                for addr in vec![x, y, z] {
+                    remap_address(addr, remap, entity_idx);
+                }
+            }
+            Filter::DistanceMultiPoint(points) => {
+                for (x, y, z, ..) in points {
+                    for addr in vec![x, y, z] {
+                        remap_address(addr, remap, entity_idx);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[test]
+fn compaction_remaps_graph_nodes() {
+    use crate::graph::Graph;
+    use std::str::FromStr;
+
+    let mut sim = crate::SimModelBuilder::new()
+        .component("net", |c| c.var("graph:links", None))
+        .prefab("node", &["net"])
+        .build_sim()
+        .unwrap();
+    let a = sim
+        .spawn_entity(Some(&string::new_truncate("node")), None)
+        .unwrap();
+    let b = sim
+        .spawn_entity(Some(&string::new_truncate("node")), None)
+        .unwrap();
+    let c = sim
+        .spawn_entity(Some(&string::new_truncate("node")), None)
+        .unwrap();
+    let mut graph = Graph::new(false);
+    graph.add_edge(a, c, 1.);
+    graph.add_edge(b, c, 1.);
+    let addr = Address::from_str(&format!("{}:net:graph:links", c)).unwrap();
+    *sim.get_var_mut(&addr).unwrap() = Var::Graph(graph);
+    sim.despawn_entity(&b).unwrap();
+
+    let remap = sim.compact_entities().unwrap();
+    let new_c = remap[&c];
+    let mut addr = addr;
+    assert!(remap_address(&mut addr, &remap, &sim.entity_idx));
+    match sim.get_var(&addr).unwrap() {
+        Var::Graph(graph) => {
+            // edge to the despawned entity is gone
+            assert_eq!(
+                graph.adjacency.keys().copied().collect::<Vec<_>>(),
+                vec![a, new_c]
+            );
+            assert_eq!(graph.neighbors(new_c), vec![a]);
+        }
+        _ => panic!("expected graph var"),
+    }
+}
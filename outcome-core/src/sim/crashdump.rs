@@ -0,0 +1,64 @@
+//! Diagnostics written when a step fails.
+//!
+//! Failures in long runs can be hard to reproduce. When a step is aborted,
+//! e.g. with strict mode enabled, a crash dump can be written to
+//! a diagnostics directory for post-mortem analysis. Each dump gets it's
+//! own directory, named after the clock and the time of the failure,
+//! containing:
+//!
+//! - `error.txt` with the error that aborted the step
+//! - `snapshot` with the uncompressed state at the time of the failure,
+//! which can be loaded back using [`Sim::from_snapshot`]
+//! - `error_journal.txt` with the logic errors recorded during the step,
+//! if the `machine` feature is enabled
+//!
+//! [`Sim::from_snapshot`]: crate::snapshot::Snap::from_snapshot
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::snapshot::Snap;
+
+use super::Sim;
+
+/// Creates a new directory for a single crash dump within the given
+/// diagnostics directory.
+pub fn create_crashdump_dir(dir: &Path, clock: usize) -> Result<PathBuf> {
+    let name = format!(
+        "crash-{}-{}",
+        clock,
+        chrono::Utc::now().format("%Y%m%d%H%M%S%3f")
+    );
+    let path = dir.join(name);
+    fs::create_dir_all(&path)?;
+    Ok(path)
+}
+
+impl Sim {
+    /// Writes a crash dump for the step that failed with the given error,
+    /// returning the path to the newly created dump directory.
+    pub fn write_crashdump(&self, dir: &Path, error: &Error) -> Result<PathBuf> {
+        let path = create_crashdump_dir(dir, self.clock)?;
+        fs::write(
+            path.join("error.txt"),
+            format!(
+                "clock: {}\nevents: {:?}\nerror: {}\n",
+                self.clock, self.event_queue, error
+            ),
+        )?;
+        fs::write(path.join("snapshot"), self.to_snapshot()?)?;
+
+        #[cfg(feature = "machine")]
+        {
+            use std::fmt::Write;
+            let mut journal = String::new();
+            for (ctx, e) in &self.error_journal {
+                let _ = writeln!(journal, "{:?}: {}", ctx, e);
+            }
+            fs::write(path.join("error_journal.txt"), journal)?;
+        }
+
+        Ok(path)
+    }
+}
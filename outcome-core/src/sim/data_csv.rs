@@ -0,0 +1,165 @@
+//! Loading model data from CSV files.
+//!
+//! CSV files are declared in module manifests:
+//!
+//! ```toml
+//! [data.prices]
+//! path = "data/prices.csv"
+//! type = "csv_list"
+//!
+//! [data.terrain]
+//! path = "data/terrain.csv"
+//! type = "csv_grid"
+//! ```
+//!
+//! Addressing is based on the header row. In list files each header cell
+//! holds the address of a list var, and the values in the column below it
+//! make up the list. In grid files the first header cell holds the address
+//! of a grid var, and each of the following rows makes up a single row of
+//! the grid.
+//!
+//! Values are coerced to the element type of the target var. Failing that,
+//! loading the simulation fails with an error pointing at the offending
+//! file and line.
+
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::address::Address;
+use crate::error::{Error, Result};
+use crate::{Sim, Var, VarType};
+
+/// Returns the type of elements of the given list or grid var type. None
+/// means the element type is to be inferred from each value.
+fn element_type(var_type: VarType) -> Result<Option<VarType>> {
+    let elem = match var_type {
+        VarType::StringList | VarType::StringGrid => VarType::String,
+        VarType::IntList | VarType::IntGrid => VarType::Int,
+        VarType::FloatList | VarType::FloatGrid => VarType::Float,
+        VarType::BoolList | VarType::BoolGrid => VarType::Bool,
+        VarType::ByteList | VarType::ByteGrid => VarType::Byte,
+        VarType::Vec2List | VarType::Vec2Grid => VarType::Vec2,
+        VarType::Vec3List | VarType::Vec3Grid => VarType::Vec3,
+        VarType::VarList | VarType::VarGrid => return Ok(None),
+        _ => {
+            return Err(Error::Other(format!(
+                "expected list or grid var type, got {}",
+                var_type.to_str()
+            )))
+        }
+    };
+    Ok(Some(elem))
+}
+
+/// Coerces a single value, adding file and line context to any error.
+fn coerce(value: &str, elem_type: Option<VarType>, path: &str, line: u64) -> Result<Var> {
+    Var::from_str(value.trim(), elem_type).map_err(|e| {
+        Error::FailedLoadingDataFile(
+            path.to_string(),
+            line as usize,
+            format!("can't coerce \"{}\": {}", value, e),
+        )
+    })
+}
+
+fn line_of(record: &csv::StringRecord) -> u64 {
+    record.position().map(|p| p.line()).unwrap_or(0)
+}
+
+impl Sim {
+    /// Applies list data from the CSV file at the given path.
+    pub(crate) fn apply_csv_list(&mut self, path: &str) -> Result<()> {
+        debug!("loading csv list data from: {}", path);
+        let mut reader = open(path)?;
+        let headers = reader
+            .headers()
+            .map_err(|e| Error::FailedLoadingDataFile(path.to_string(), 1, e.to_string()))?
+            .clone();
+        let mut columns = Vec::new();
+        for header in headers.iter() {
+            let address = Address::from_str(header.trim())
+                .map_err(|e| Error::FailedLoadingDataFile(path.to_string(), 1, e.to_string()))?;
+            let elem_type = element_type(address.var_type)
+                .map_err(|e| Error::FailedLoadingDataFile(path.to_string(), 1, e.to_string()))?;
+            columns.push((address, elem_type, Vec::new()));
+        }
+        for record in reader.records() {
+            let record = record.map_err(|e| csv_error(path, e))?;
+            let line = line_of(&record);
+            for (n, value) in record.iter().enumerate() {
+                // shorter columns leave empty cells at the bottom
+                if value.trim().is_empty() {
+                    continue;
+                }
+                let (_, elem_type, values) = columns.get_mut(n).ok_or_else(|| {
+                    Error::FailedLoadingDataFile(
+                        path.to_string(),
+                        line as usize,
+                        format!("value in column {} has no address in the header", n + 1),
+                    )
+                })?;
+                values.push(coerce(value, *elem_type, path, line)?);
+            }
+        }
+        for (address, _, values) in columns {
+            *self.get_var_mut(&address)? = Var::List(values);
+        }
+        Ok(())
+    }
+
+    /// Applies grid data from the CSV file at the given path.
+    pub(crate) fn apply_csv_grid(&mut self, path: &str) -> Result<()> {
+        debug!("loading csv grid data from: {}", path);
+        let mut reader = open(path)?;
+        let headers = reader
+            .headers()
+            .map_err(|e| Error::FailedLoadingDataFile(path.to_string(), 1, e.to_string()))?
+            .clone();
+        let header = headers.get(0).unwrap_or("").trim();
+        let address = Address::from_str(header)
+            .map_err(|e| Error::FailedLoadingDataFile(path.to_string(), 1, e.to_string()))?;
+        let elem_type = element_type(address.var_type)
+            .map_err(|e| Error::FailedLoadingDataFile(path.to_string(), 1, e.to_string()))?;
+        let mut rows: Vec<Vec<Var>> = Vec::new();
+        for record in reader.records() {
+            let record = record.map_err(|e| csv_error(path, e))?;
+            let line = line_of(&record);
+            let row = record
+                .iter()
+                .map(|value| coerce(value, elem_type, path, line))
+                .collect::<Result<Vec<Var>>>()?;
+            if let Some(first) = rows.first() {
+                if first.len() != row.len() {
+                    return Err(Error::FailedLoadingDataFile(
+                        path.to_string(),
+                        line as usize,
+                        format!("row has {} values, expected {}", row.len(), first.len()),
+                    ));
+                }
+            }
+            rows.push(row);
+        }
+        *self.get_var_mut(&address)? = Var::Grid(rows);
+        Ok(())
+    }
+}
+
+fn open(path: &str) -> Result<csv::Reader<std::fs::File>> {
+    if !Path::new(path).is_file() {
+        return Err(Error::FailedLoadingDataFile(
+            path.to_string(),
+            0,
+            "file not found".to_string(),
+        ));
+    }
+    csv::ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .from_path(path)
+        .map_err(|e| csv_error(path, e))
+}
+
+fn csv_error(path: &str, e: csv::Error) -> Error {
+    let line = e.position().map(|p| p.line()).unwrap_or(0);
+    Error::FailedLoadingDataFile(path.to_string(), line as usize, e.to_string())
+}
@@ -0,0 +1,390 @@
+//! Incremental snapshots.
+//!
+//! Full snapshots serialize the whole simulation state, which gets costly
+//! when saving frequently. Delta snapshots only record the changes made
+//! since a baseline: vars that changed value, entities that were spawned
+//! or removed, plus the small bits of global state like the clock and the
+//! event queue.
+//!
+//! Tracking is enabled by setting a baseline, either explicitly with
+//! [`Sim::set_delta_baseline`] or by saving a full snapshot with
+//! [`Sim::save_baseline_snapshot`]. Each created delta moves the baseline
+//! to the current state, so creating a delta after every step results in
+//! a chain of per-step deltas. State can then be reconstructed from the
+//! full snapshot and the chain of deltas using [`Sim::from_snapshot_chain`].
+//!
+//! Changed vars are found using var-level change tracking, see
+//! [`Sim::collect_changed_since`]. The baseline itself only keeps the
+//! structure of each entity, such as the list of attached components and
+//! the state of its machines, used for finding entities that have to be
+//! stored in full, along with values of vars changed during the current
+//! step.
+//!
+//! Deltas don't include the model, the model stored with the full snapshot
+//! the chain starts from is used when reconstructing. Lua states can't be
+//! serialized, applying a delta resets them the same way loading a full
+//! snapshot does.
+
+use std::fs::File;
+use std::io::{Read, Write};
+
+use fnv::FnvHashMap;
+use id_pool::IdPool;
+
+use crate::entity::{Entity, StorageIndex};
+use crate::error::{Error, Result};
+use crate::rng::EntityRng;
+use crate::scheduler::EventScheduler;
+use crate::{CompName, EntityId, EntityName, EventName, StringId, Var};
+
+use super::Sim;
+
+/// State against which changes are recorded.
+pub struct DeltaBaseline {
+    clock: usize,
+    /// Structure of each entity, `None` for entities replaced since the
+    /// baseline was set
+    shapes: FnvHashMap<EntityId, Option<EntityShape>>,
+    /// Values of vars changed during the step the baseline was set at,
+    /// needed for telling apart changes made after the baseline was set
+    recent: FnvHashMap<EntityId, FnvHashMap<StorageIndex, Var>>,
+}
+
+impl DeltaBaseline {
+    /// Forgets the entity stored under the id, causing any entity found
+    /// under the id to be stored in full with the next delta.
+    pub(crate) fn forget(&mut self, id: &EntityId) {
+        if let Some(shape) = self.shapes.get_mut(id) {
+            *shape = None;
+        }
+    }
+}
+
+/// Parts of the entity state other than var values.
+#[derive(PartialEq)]
+struct EntityShape {
+    components: Vec<CompName>,
+    var_count: usize,
+    rng: Option<EntityRng>,
+    #[cfg(feature = "machine")]
+    comp_state: FnvHashMap<CompName, StringId>,
+    #[cfg(feature = "machine")]
+    comp_queue: FnvHashMap<EventName, Vec<CompName>>,
+    #[cfg(feature = "machine")]
+    event_queue: Vec<EventName>,
+}
+
+impl EntityShape {
+    fn of(entity: &Entity) -> Self {
+        EntityShape {
+            components: entity.components.clone(),
+            var_count: entity.storage.map.len(),
+            rng: entity.rng,
+            #[cfg(feature = "machine")]
+            comp_state: entity.comp_state.clone(),
+            #[cfg(feature = "machine")]
+            comp_queue: entity.comp_queue.clone(),
+            #[cfg(feature = "machine")]
+            event_queue: entity.event_queue.clone(),
+        }
+    }
+}
+
+/// Changes made to the simulation state between two points in time.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DeltaSnapshot {
+    /// Clock value of the state the delta applies to
+    pub base_clock: usize,
+    /// Clock value after the delta is applied
+    pub clock: usize,
+    pub event_queue: Vec<EventName>,
+    #[serde(default)]
+    pub scheduler: EventScheduler,
+    pub entity_idx: FnvHashMap<EntityName, EntityId>,
+    pub entity_pool: IdPool,
+    /// Entities that were spawned, or changed in ways other than var
+    /// values, stored in full
+    pub entities: FnvHashMap<EntityId, Entity>,
+    /// Entities that were removed
+    pub removed: Vec<EntityId>,
+    /// Changed var values for the remaining entities
+    pub vars: FnvHashMap<EntityId, Vec<(StorageIndex, Var)>>,
+}
+
+impl DeltaSnapshot {
+    /// Checks whether the delta carries any entity changes.
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty() && self.removed.is_empty() && self.vars.is_empty()
+    }
+
+    /// Serializes the delta, optionally compressing it using LZ4.
+    pub fn to_bytes(&self, compress: bool) -> Result<Vec<u8>> {
+        let mut data =
+            bincode::serialize(&self).map_err(|e| Error::FailedCreatingSnapshot(e.to_string()))?;
+        #[cfg(feature = "lz4")]
+        {
+            if compress {
+                data = lz4::block::compress(&data, None, true)?;
+            }
+        }
+        Ok(data)
+    }
+
+    /// Deserializes the delta, decompressing it first if needed.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        #[cfg(feature = "lz4")]
+        {
+            if let Ok(data) = lz4::block::decompress(bytes, None) {
+                if let Ok(delta) = bincode::deserialize(&data) {
+                    return Ok(delta);
+                }
+            }
+        }
+        bincode::deserialize(bytes).map_err(|e| Error::FailedReadingSnapshot(e.to_string()))
+    }
+}
+
+impl Sim {
+    /// Sets the current state as the baseline for the next delta.
+    pub fn set_delta_baseline(&mut self) {
+        let mut recent = FnvHashMap::default();
+        for (id, entity) in &self.entities {
+            let vars = entity
+                .storage
+                .changed_since(self.clock)
+                .map(|(idx, var)| (idx.clone(), var.clone()))
+                .collect::<FnvHashMap<_, _>>();
+            if !vars.is_empty() {
+                recent.insert(*id, vars);
+            }
+        }
+        self.delta_baseline = Some(DeltaBaseline {
+            clock: self.clock,
+            shapes: self
+                .entities
+                .iter()
+                .map(|(id, entity)| (*id, Some(EntityShape::of(entity))))
+                .collect(),
+            recent,
+        });
+    }
+
+    /// Stops tracking changes, dropping the baseline.
+    pub fn clear_delta_baseline(&mut self) {
+        self.delta_baseline = None;
+    }
+
+    /// Creates a delta of changes made since the baseline, and moves the
+    /// baseline to the current state.
+    pub fn delta_snapshot(&mut self) -> Result<DeltaSnapshot> {
+        let baseline = self.delta_baseline.take().ok_or_else(|| {
+            Error::FailedCreatingSnapshot("no baseline set for delta snapshot".to_string())
+        })?;
+
+        let mut delta = DeltaSnapshot {
+            base_clock: baseline.clock,
+            clock: self.clock,
+            event_queue: self.event_queue.clone(),
+            scheduler: self.scheduler.clone(),
+            entity_idx: self.entity_idx.clone(),
+            entity_pool: self.entity_pool.clone(),
+            entities: FnvHashMap::default(),
+            removed: baseline
+                .shapes
+                .keys()
+                .filter(|id| !self.entities.contains_key(id))
+                .cloned()
+                .collect(),
+            vars: FnvHashMap::default(),
+        };
+
+        // changes made before tracking started are unknown
+        let all = baseline.clock < self.change_tracking_start;
+        for (id, entity) in &self.entities {
+            match baseline.shapes.get(id) {
+                Some(Some(shape)) if shape == &EntityShape::of(entity) => (),
+                _ => {
+                    delta.entities.insert(*id, entity.clone());
+                    continue;
+                }
+            }
+            let changed = match all {
+                true => entity
+                    .storage
+                    .map
+                    .iter()
+                    .map(|(idx, var)| (idx.clone(), var.clone()))
+                    .collect::<Vec<_>>(),
+                false => {
+                    let recent = baseline.recent.get(id);
+                    entity
+                        .storage
+                        .changed_since(baseline.clock)
+                        .filter(|(idx, var)| recent.and_then(|r| r.get(*idx)) != Some(*var))
+                        .map(|(idx, var)| (idx.clone(), var.clone()))
+                        .collect()
+                }
+            };
+            if !changed.is_empty() {
+                delta.vars.insert(*id, changed);
+            }
+        }
+
+        self.set_delta_baseline();
+        Ok(delta)
+    }
+
+    /// Applies the delta to the current state.
+    ///
+    /// The delta has to be based on the current clock value, otherwise an
+    /// error is returned and the state is left untouched.
+    pub fn apply_delta(&mut self, delta: DeltaSnapshot) -> Result<()> {
+        if delta.base_clock != self.clock {
+            return Err(Error::FailedReadingSnapshot(format!(
+                "delta based on clock {} can't be applied at clock {}",
+                delta.base_clock, self.clock
+            )));
+        }
+        for id in &delta.removed {
+            self.entities.remove(id);
+        }
+        for (id, entity) in delta.entities {
+            self.entities.insert(id, entity);
+        }
+        for (id, vars) in delta.vars {
+            let entity = self
+                .entities
+                .get_mut(&id)
+                .ok_or(Error::FailedGettingEntityById(id))?;
+            for (idx, var) in vars {
+                entity.storage.map.insert(idx, var);
+            }
+        }
+        #[cfg(feature = "machine_lua")]
+        for entity in self.entities.values_mut() {
+            entity.insta.lua_state = None;
+        }
+        self.clock = delta.clock;
+        self.change_tracking_start = delta.clock;
+        self.removals = super::removals::Removals::since(delta.clock);
+        self.event_queue = delta.event_queue;
+        self.scheduler = delta.scheduler;
+        self.entity_idx = delta.entity_idx;
+        self.entity_pool = delta.entity_pool;
+        if self.delta_baseline.is_some() {
+            self.set_delta_baseline();
+        }
+        Ok(())
+    }
+
+    /// Saves a full snapshot and sets the current state as the baseline
+    /// for the next delta.
+    pub fn save_baseline_snapshot(&mut self, name: &str, compress: bool) -> Result<()> {
+        self.save_snapshot(name, compress)?;
+        self.set_delta_baseline();
+        Ok(())
+    }
+
+    /// Saves a delta of changes made since the baseline to the project's
+    /// snapshots directory, and moves the baseline to the current state.
+    pub fn save_delta_snapshot(&mut self, name: &str, compress: bool) -> Result<()> {
+        let data = self.delta_snapshot()?.to_bytes(compress)?;
+        let project_path = crate::util::find_project_root(self.model.scenario.path.clone(), 3)?;
+        let snapshot_path = project_path.join(crate::SNAPSHOTS_DIR_NAME).join(name);
+        let mut file = File::create(snapshot_path)?;
+        file.write_all(&data)?;
+        Ok(())
+    }
+
+    /// Creates a new simulation instance from a full snapshot at the
+    /// given path, applying a chain of deltas in order.
+    ///
+    /// Further changes are tracked against the reconstructed state.
+    pub fn from_snapshot_chain(baseline: &str, deltas: &[&str]) -> Result<Self> {
+        let mut sim = Sim::from_snapshot_at(baseline)?;
+        for path in deltas {
+            let mut file =
+                File::open(path).map_err(|e| Error::FailedReadingSnapshot(e.to_string()))?;
+            let mut bytes = Vec::new();
+            file.read_to_end(&mut bytes)?;
+            sim.apply_delta(DeltaSnapshot::from_bytes(&bytes)?)?;
+        }
+        sim.set_delta_baseline();
+        Ok(sim)
+    }
+}
+
+#[cfg(test)]
+fn delta_test_sim() -> (Sim, EntityId, EntityId) {
+    let mut sim = crate::SimModelBuilder::new()
+        .component("pos", |c| {
+            c.var("int:x", Var::Int(0)).var("int:y", Var::Int(0))
+        })
+        .prefab("thing", &["pos"])
+        .build_sim()
+        .unwrap();
+    let thing = crate::string::new_truncate("thing");
+    let a = sim.spawn_entity(Some(&thing), None).unwrap();
+    let b = sim.spawn_entity(Some(&thing), None).unwrap();
+    (sim, a, b)
+}
+
+#[test]
+fn delta_only_carries_changed_vars() {
+    use crate::snapshot::Snapshot;
+    use std::str::FromStr;
+
+    let (mut sim, a, b) = delta_test_sim();
+    let mut base = sim.to_snapshot().unwrap();
+    sim.set_delta_baseline();
+
+    let addr = crate::Address::from_str(&format!("{}:pos:int:x", a)).unwrap();
+    *sim.get_var_mut(&addr).unwrap() = Var::Int(5);
+    let delta = sim.delta_snapshot().unwrap();
+    assert!(delta.entities.is_empty());
+    assert_eq!(delta.vars.len(), 1);
+    assert_eq!(delta.vars[&a].len(), 1);
+    assert!(!delta.vars.contains_key(&b));
+
+    let mut restored = Sim::from_snapshot(&mut base).unwrap();
+    restored.apply_delta(delta).unwrap();
+    assert_eq!(restored.get_var(&addr).unwrap(), &Var::Int(5));
+
+    // nothing changed since the last delta
+    assert!(sim.delta_snapshot().unwrap().is_empty());
+}
+
+#[test]
+fn delta_stores_spawned_and_removed_entities() {
+    let (mut sim, a, _) = delta_test_sim();
+    sim.set_delta_baseline();
+    sim.despawn_entity(&a).unwrap();
+    let c = sim
+        .spawn_entity(Some(&crate::string::new_truncate("thing")), None)
+        .unwrap();
+    let delta = sim.delta_snapshot().unwrap();
+    assert!(delta.entities.contains_key(&c));
+    if c != a {
+        assert_eq!(delta.removed, vec![a]);
+    }
+
+    // entity spawned under the id of a removed one
+    sim.despawn_entity(&c).unwrap();
+    let d = sim
+        .spawn_entity(Some(&crate::string::new_truncate("thing")), None)
+        .unwrap();
+    let delta = sim.delta_snapshot().unwrap();
+    assert!(delta.entities.contains_key(&d));
+}
+
+#[test]
+fn delta_stores_compacted_entities_in_full() {
+    let (mut sim, a, b) = delta_test_sim();
+    sim.despawn_entity(&a).unwrap();
+    sim.set_delta_baseline();
+    sim.compact_entities().unwrap();
+    let delta = sim.delta_snapshot().unwrap();
+    // the remaining entity moved into the freed id
+    assert!(delta.entities.contains_key(&a));
+    assert_eq!(delta.removed, vec![b]);
+}
@@ -0,0 +1,338 @@
+//! Engine introspection through a reserved pseudo-entity.
+//!
+//! Engine statistics are exposed as regular vars stored on a reserved
+//! entity, which makes it possible to monitor the engine itself using the
+//! existing query, transfer and watch machinery. Stats are updated at the
+//! end of each step.
+//!
+//! Available vars:
+//!
+//! - `/_engine/_stats/int/clock`
+//! - `/_engine/_stats/int/entity_count`
+//! - `/_engine/_stats/float/last_step_ms`
+//! - `/_engine/_stats/int/memory_estimate` (in bytes)
+//! - `/_engine/_comp_count/int/<component>` for each component in use
+//!
+//! Vars stored on the engine entity are read-only, attempts at getting
+//! mutable access to them result in an error.
+//!
+//! The engine entity is not counted by [`Sim::entity_count`], and is left
+//! out of query results unless the query explicitly selects it, using
+//! either a name or an id filter.
+//!
+//! The same figures are also accumulated over the whole run into
+//! [`Sim::run_stats`], used for producing a summary once the run ends.
+//!
+//! More detailed breakdown of resource usage per component is available
+//! through [`Sim::attribution_report`].
+
+use std::mem::size_of;
+use std::time::Duration;
+
+use fnv::FnvHashMap;
+
+use crate::address::Address;
+use crate::entity::{Entity, StorageIndex};
+use crate::error::{Error, Result};
+use crate::query::{Filter, Query};
+use crate::{string, CompName, EntityId, Float, Int, Var};
+
+use super::Sim;
+
+/// Name of the reserved engine entity.
+pub const ENGINE_ENTITY: &str = "_engine";
+/// Component holding general engine stats.
+pub const ENGINE_STATS_COMPONENT: &str = "_stats";
+/// Component holding number of entities each component is attached to.
+pub const ENGINE_COMP_COUNT_COMPONENT: &str = "_comp_count";
+
+/// Resource usage attributed to a single component.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ComponentAttribution {
+    pub component: String,
+    /// Number of entities the component is attached to
+    pub entities: u32,
+    /// Estimated number of bytes taken up by the component's vars across
+    /// all entities
+    pub storage_bytes: u64,
+    /// Total machine execution time since the simulation was started
+    pub exec_time: Duration,
+    /// Number of times the component's machine was executed
+    pub exec_count: u64,
+}
+
+/// Breakdown of storage and execution time for each component, sorted
+/// by storage size in descending order.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AttributionReport {
+    pub components: Vec<ComponentAttribution>,
+}
+
+impl Sim {
+    /// Returns an error if the address points to the read-only engine
+    /// entity, either by name or by id.
+    pub(crate) fn check_writable(&self, addr: &Address) -> Result<()> {
+        let is_engine = addr.entity.as_str() == ENGINE_ENTITY
+            || match self.entity_idx.get(&string::new_truncate(ENGINE_ENTITY)) {
+                Some(id) => addr.entity.parse::<EntityId>().ok() == Some(*id),
+                None => false,
+            };
+        if is_engine {
+            return Err(Error::Other(format!(
+                "vars on the reserved entity \"{}\" are read-only",
+                ENGINE_ENTITY
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the id of the engine entity, if it was already spawned.
+    pub fn engine_entity_id(&self) -> Option<EntityId> {
+        self.entity_idx
+            .get(&string::new_truncate(ENGINE_ENTITY))
+            .copied()
+    }
+
+    /// Removes the engine entity from the selection, unless the query
+    /// explicitly asks for it by name or id.
+    pub(crate) fn hide_engine_entity(&self, query: &Query, selected: &mut Vec<EntityId>) {
+        let engine_id = match self.engine_entity_id() {
+            Some(id) => id,
+            None => return,
+        };
+        let engine_name = string::new_truncate(ENGINE_ENTITY);
+        let explicit = query.filters.iter().any(|filter| match filter {
+            Filter::Name(names) => names.contains(&engine_name),
+            Filter::Id(ids) => ids.contains(&engine_id),
+            _ => false,
+        });
+        if !explicit {
+            selected.retain(|id| *id != engine_id);
+        }
+    }
+
+    /// Creates a report attributing storage and machine execution time to
+    /// individual components.
+    pub fn attribution_report(&self) -> AttributionReport {
+        let mut components: FnvHashMap<CompName, ComponentAttribution> = FnvHashMap::default();
+        for entity in self.entities.values() {
+            for comp in &entity.components {
+                let attr = components.entry(comp.clone()).or_default();
+                attr.entities += 1;
+            }
+            for ((comp, _), var) in &entity.storage.map {
+                let attr = components.entry(comp.clone()).or_default();
+                attr.storage_bytes +=
+                    (size_of::<StorageIndex>() + size_of::<Var>() + var_heap_size(var)) as u64;
+            }
+        }
+        #[cfg(feature = "machine")]
+        for (comp, (time, count)) in &self.exec_times {
+            let attr = components.entry(comp.clone()).or_default();
+            attr.exec_time = *time;
+            attr.exec_count = *count;
+        }
+
+        let mut components = components
+            .into_iter()
+            .map(|(name, mut attr)| {
+                attr.component = name.to_string();
+                attr
+            })
+            .collect::<Vec<_>>();
+        components.sort_by(|a, b| b.storage_bytes.cmp(&a.storage_bytes));
+        AttributionReport { components }
+    }
+
+    /// Returns the id of the engine entity, spawning it if it doesn't
+    /// exist yet. The entity is only spawned once, later calls return the
+    /// existing id.
+    fn engine_entity(&mut self) -> Result<EntityId> {
+        let engine_name = string::new_truncate(ENGINE_ENTITY);
+        match self.entity_idx.get(&engine_name) {
+            Some(id) => Ok(*id),
+            None => self.spawn_entity(None, Some(engine_name)),
+        }
+    }
+
+    /// Updates engine stats stored on the engine entity in place.
+    pub(crate) fn update_engine_stats(&mut self, step_duration: Duration) -> Result<()> {
+        let engine_id = self.engine_entity()?;
+
+        let mut comp_counts: FnvHashMap<CompName, Int> = FnvHashMap::default();
+        let mut memory_estimate = 0;
+        for (id, entity) in &self.entities {
+            if *id == engine_id {
+                continue;
+            }
+            for comp in &entity.components {
+                *comp_counts.entry(comp.clone()).or_insert(0) += 1;
+            }
+            memory_estimate += size_of::<Entity>()
+                + entity.storage.map.len() * (size_of::<StorageIndex>() + size_of::<Var>())
+                + entity.components.len() * size_of::<CompName>();
+        }
+        let entity_count = self.entity_count();
+        let clock = self.clock;
+
+        #[cfg(feature = "machine")]
+        let errors = self.error_journal.len();
+        #[cfg(not(feature = "machine"))]
+        let errors = 0;
+        self.run_stats
+            .record_step(clock, step_duration, entity_count, memory_estimate, errors);
+
+        let engine = self.get_entity_mut(&engine_id)?;
+        let stats = string::new_truncate(ENGINE_STATS_COMPONENT);
+        let storage = &mut engine.storage;
+
+        // components no longer attached to any entity are removed
+        let comp_count = string::new_truncate(ENGINE_COMP_COUNT_COMPONENT);
+        storage
+            .map
+            .retain(|(comp, name), _| comp != &comp_count || comp_counts.contains_key(name));
+
+        let mut set = |index: StorageIndex, var: Var| match storage.get_var_mut(&index) {
+            Ok(existing) => *existing = var,
+            Err(_) => storage.insert(index, var),
+        };
+        set(
+            (stats.clone(), string::new_truncate("clock")),
+            Var::Int(clock as Int),
+        );
+        set(
+            (stats.clone(), string::new_truncate("entity_count")),
+            Var::Int(entity_count as Int),
+        );
+        set(
+            (stats.clone(), string::new_truncate("last_step_ms")),
+            Var::Float(step_duration.as_secs_f64() as Float * 1000.),
+        );
+        set(
+            (stats, string::new_truncate("memory_estimate")),
+            Var::Int(memory_estimate as Int),
+        );
+        for (comp, count) in comp_counts {
+            set((comp_count.clone(), comp), Var::Int(count));
+        }
+
+        Ok(())
+    }
+}
+
+/// Estimates the number of bytes the var holds on the heap.
+fn var_heap_size(var: &Var) -> usize {
+    match var {
+        Var::String(s) => s.capacity(),
+        Var::List(v) => v.iter().map(|v| size_of::<Var>() + var_heap_size(v)).sum(),
+        Var::Grid(v) => v
+            .iter()
+            .flatten()
+            .map(|v| size_of::<Var>() + var_heap_size(v))
+            .sum(),
+        Var::Map(m) => m
+            .iter()
+            .map(|(k, v)| 2 * size_of::<Var>() + var_heap_size(k) + var_heap_size(v))
+            .sum(),
+        Var::Graph(g) => g
+            .adjacency
+            .values()
+            .map(|edges| {
+                size_of::<crate::EntityId>()
+                    + edges.len() * (size_of::<crate::EntityId>() + size_of::<crate::Float>())
+            })
+            .sum(),
+        // approximated with the serialized size
+        Var::Custom(v) => v.0.to_bytes().len(),
+        _ => 0,
+    }
+}
+
+#[test]
+fn engine_stats_updated_in_place() {
+    let mut sim = crate::SimModelBuilder::new()
+        .component("pos", |c| c.var("int:x", Var::Int(0)))
+        .prefab("thing", &["pos"])
+        .spawn("thing", None)
+        .build_sim()
+        .unwrap();
+    sim.step().unwrap();
+    let engine_id = sim.entity_idx[&string::new_truncate(ENGINE_ENTITY)];
+    let entity_count = sim.entities.len();
+    sim.step().unwrap();
+    assert_eq!(
+        sim.entity_idx[&string::new_truncate(ENGINE_ENTITY)],
+        engine_id
+    );
+    assert_eq!(sim.entities.len(), entity_count);
+    let clock = sim.entities[&engine_id]
+        .storage
+        .get_var(&(
+            string::new_truncate(ENGINE_STATS_COMPONENT),
+            string::new_truncate("clock"),
+        ))
+        .unwrap();
+    assert_eq!(clock, &Var::Int(2));
+}
+
+#[test]
+fn attribution_report_sorted_by_storage() {
+    let sim = crate::SimModelBuilder::new()
+        .component("pos", |c| c.var("float:x", None).var("float:y", None))
+        .component("mass", |c| c.var("float:kg", None))
+        .prefab("thing", &["pos", "mass"])
+        .spawn("thing", None)
+        .spawn("thing", None)
+        .build_sim()
+        .unwrap();
+    let report = sim.attribution_report();
+    let names = report
+        .components
+        .iter()
+        .map(|c| c.component.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["pos", "mass"]);
+    assert!(report.components.iter().all(|c| c.entities == 2));
+    assert_eq!(
+        report.components[0].storage_bytes,
+        2 * report.components[1].storage_bytes
+    );
+}
+
+#[test]
+fn engine_entity_hidden_from_counts_and_queries() {
+    use crate::query::{Description, Layout, Map, Trigger};
+
+    let mut sim = crate::SimModelBuilder::new()
+        .component("pos", |c| c.var("int:x", Var::Int(0)))
+        .prefab("thing", &["pos"])
+        .spawn("thing", None)
+        .build_sim()
+        .unwrap();
+    sim.step().unwrap();
+    let engine_id = sim.engine_entity_id().unwrap();
+    assert_eq!(sim.entity_count(), 1);
+
+    let query = |filters| Query {
+        trigger: Trigger::Immediate,
+        description: Description::NativeDescribed,
+        layout: Layout::Var,
+        filters,
+        mappings: vec![Map::All],
+    };
+    let selected = sim.select_entities(&query(vec![]));
+    assert_eq!(selected.len(), 1);
+    assert!(!selected.contains(&engine_id));
+    match sim.query(&query(vec![])).unwrap() {
+        crate::QueryProduct::NativeAddressedVar(map) => {
+            assert!(map.keys().all(|(id, _, _)| *id != engine_id))
+        }
+        product => panic!("unexpected product: {:?}", product),
+    }
+
+    // the engine entity is still available when asked for explicitly
+    let by_name = query(vec![Filter::Name(vec![string::new_truncate(ENGINE_ENTITY)])]);
+    assert!(sim.select_entities(&by_name).contains(&engine_id));
+    let by_id = query(vec![Filter::Id(vec![engine_id])]);
+    assert_eq!(sim.select_entities(&by_id), vec![engine_id]);
+}
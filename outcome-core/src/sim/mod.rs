@@ -1,5 +1,17 @@
 //! Local simulation abstraction.
 
+pub mod compact;
+pub mod crashdump;
+#[cfg(feature = "load_csv")]
+mod data_csv;
+pub mod delta;
+pub mod dump;
+pub mod introspect;
+#[cfg(feature = "recorder")]
+pub mod recorder;
+pub mod removals;
+pub mod replay;
+pub mod stats;
 pub mod step;
 
 use std::collections::{BTreeMap, HashMap};
@@ -9,7 +21,7 @@ use std::path::PathBuf;
 use std::process::Stdio;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 #[cfg(feature = "load_img")]
 use image;
@@ -22,9 +34,15 @@ use fnv::FnvHashMap;
 use id_pool::IdPool;
 
 use crate::address::Address;
-use crate::entity::{Entity, Storage};
+use crate::entity::{Entity, EntityRef, Storage, StorageIndex};
 use crate::error::Error;
-use crate::model::{DataEntry, DataImageEntry, EventModel, Scenario};
+#[cfg(feature = "grids")]
+use crate::grid::{BoolGrid, ByteGrid, FloatGrid, IntGrid, StringGrid};
+#[cfg(feature = "machine")]
+use crate::machine::{self, ExecutionContext};
+use crate::model::{DataEntry, DataFileEntry, DataImageEntry, EventModel, Scenario};
+use crate::query::{Query, QueryPlugins, QueryProduct};
+use crate::scheduler::EventScheduler;
 use crate::snapshot::{Snap, Snapshot};
 use crate::{
     model, string, CompName, EntityId, EntityName, EventName, Result, SimModel, SimStarter,
@@ -58,29 +76,116 @@ use crate::{
 #[derive(Serialize, Deserialize)]
 pub struct Sim {
     /// Serves as the base for creation and runtime processing of the
-    /// simulation
+    /// simulation, replace it using [`Sim::update_model`] so that state
+    /// derived from it is kept up to date
     pub model: SimModel,
 
     /// Number of steps that have been processed so far
     pub(crate) clock: usize,
     /// Global queue of events waiting for execution
+    #[cfg(feature = "unstable")]
     pub event_queue: Vec<EventName>,
+    #[cfg(not(feature = "unstable"))]
+    pub(crate) event_queue: Vec<EventName>,
+    /// Events scheduled for future steps
+    #[serde(default)]
+    pub scheduler: EventScheduler,
 
     /// All entities that exist within the simulation are stored here
+    #[cfg(feature = "unstable")]
     pub entities: FnvHashMap<EntityId, Entity>,
+    #[cfg(not(feature = "unstable"))]
+    pub(crate) entities: FnvHashMap<EntityId, Entity>,
     /// Map of string indexes for entities (string indexes are optional)
+    #[cfg(feature = "unstable")]
     pub entity_idx: FnvHashMap<EntityName, EntityId>,
+    #[cfg(not(feature = "unstable"))]
+    pub(crate) entity_idx: FnvHashMap<EntityName, EntityId>,
     /// Pool of integer identifiers for entities
+    #[cfg(feature = "unstable")]
     pub entity_pool: IdPool,
+    #[cfg(not(feature = "unstable"))]
+    pub(crate) entity_pool: IdPool,
+    /// Custom query filters and maps registered by the embedder
+    #[serde(skip)]
+    pub query_plugins: QueryPlugins,
+    /// Statistics of the current run, updated at the end of each step
+    #[serde(skip)]
+    pub run_stats: stats::RunStats,
+    /// State against which changes are recorded for delta snapshots,
+    /// changes are not tracked if not set
+    #[serde(skip)]
+    pub delta_baseline: Option<delta::DeltaBaseline>,
+    /// Clock value since which var changes are tracked, changes made
+    /// before it are unknown
+    #[serde(skip)]
+    pub change_tracking_start: usize,
+    /// Entities and vars removed during recent steps
+    #[serde(skip)]
+    pub removals: removals::Removals,
+    /// Ids of the despawned entities the despawn event was fired for, in
+    /// order of despawning, kept until the event is processed
+    #[serde(skip)]
+    pub despawned: Vec<EntityId>,
+    /// Recorder sampling selected vars at the end of each step
+    #[cfg(feature = "recorder")]
+    #[serde(skip)]
+    pub recorder: Option<recorder::Recorder>,
+    /// Log of external mutations and processed steps, used for replaying
+    /// the run
+    #[serde(skip)]
+    pub replay_log: Option<replay::ReplayLog>,
+
+    /// Logic errors recorded while processing the last step
+    #[cfg(feature = "machine")]
+    #[serde(skip)]
+    pub error_journal: Vec<(ExecutionContext, machine::Error)>,
+    /// Execution order of the model's systems, built on the first step
+    /// after the model is replaced
+    #[cfg(feature = "machine")]
+    #[serde(skip)]
+    pub(crate) system_schedule: Option<machine::system::SystemSchedule>,
+    /// Whether logic errors should abort the step instead of only being
+    /// recorded
+    ///
+    /// Strict mode is only supported on local sims. Nodes of a distributed
+    /// sim always record logic errors without aborting the step, as
+    /// aborting on a single node would leave the union out of sync.
+    #[cfg(feature = "machine")]
+    #[serde(skip)]
+    pub strict: bool,
+    /// Watchdog guarding against steps stuck in the local execution phase,
+    /// disabled if not set
+    #[cfg(feature = "machine")]
+    #[serde(skip)]
+    pub watchdog: Option<machine::WatchdogConfig>,
+    /// Watchdog thread, started along with the first watched step
+    #[cfg(feature = "machine")]
+    #[serde(skip)]
+    pub(crate) watchdog_thread: Option<machine::watchdog::Watchdog>,
+    /// Accumulated machine execution time and number of executions for
+    /// each component
+    #[cfg(all(feature = "machine", feature = "unstable"))]
+    #[serde(skip)]
+    pub exec_times: FnvHashMap<CompName, (Duration, u64)>,
+    #[cfg(all(feature = "machine", not(feature = "unstable")))]
+    #[serde(skip)]
+    pub(crate) exec_times: FnvHashMap<CompName, (Duration, u64)>,
 
     /// Lua state for selected entities
-    #[cfg(feature = "machine_lua")]
+    #[cfg(all(feature = "machine_lua", feature = "unstable"))]
     #[serde(skip)]
     pub entity_lua_state: FnvHashMap<EntityId, Arc<Mutex<Lua>>>,
+    #[cfg(all(feature = "machine_lua", not(feature = "unstable")))]
+    #[serde(skip)]
+    pub(crate) entity_lua_state: FnvHashMap<EntityId, Arc<Mutex<Lua>>>,
     /// Loaded dynamic libraries by name
-    #[cfg(feature = "machine_dynlib")]
+    #[cfg(all(feature = "machine_dynlib", feature = "unstable"))]
     #[serde(skip)]
     pub libs: BTreeMap<String, libloading::Library>,
+    #[cfg(all(feature = "machine_dynlib", not(feature = "unstable")))]
+    #[serde(skip)]
+    pub(crate) libs: BTreeMap<String, libloading::Library>,
 }
 
 /// Snapshot functionality.
@@ -214,11 +319,33 @@ impl Sim {
             model: SimModel::default(),
             clock: 0,
             event_queue: Vec::new(),
+            scheduler: Default::default(),
             entities: FnvHashMap::default(),
             entity_idx: FnvHashMap::default(),
             entity_pool: id_pool::IdPool::new(),
+            query_plugins: Default::default(),
+            run_stats: Default::default(),
+            delta_baseline: None,
+            change_tracking_start: 0,
+            removals: Default::default(),
+            despawned: Vec::new(),
+            #[cfg(feature = "machine")]
+            error_journal: Vec::new(),
+            #[cfg(feature = "machine")]
+            system_schedule: None,
+            #[cfg(feature = "machine")]
+            strict: false,
+            #[cfg(feature = "machine")]
+            watchdog: None,
+            #[cfg(feature = "machine")]
+            watchdog_thread: None,
+            #[cfg(feature = "machine")]
+            exec_times: Default::default(),
             #[cfg(feature = "machine_lua")]
             entity_lua_state: Default::default(),
+            #[cfg(feature = "recorder")]
+            recorder: None,
+            replay_log: None,
             #[cfg(feature = "machine_dynlib")]
             libs: Default::default(),
         }
@@ -264,11 +391,33 @@ impl Sim {
             model,
             clock: 0,
             event_queue: Vec::new(),
+            scheduler: Default::default(),
             entities: FnvHashMap::default(),
             entity_idx: FnvHashMap::default(),
             entity_pool: id_pool::IdPool::new(),
+            query_plugins: Default::default(),
+            run_stats: Default::default(),
+            delta_baseline: None,
+            change_tracking_start: 0,
+            removals: Default::default(),
+            despawned: Vec::new(),
+            #[cfg(feature = "machine")]
+            error_journal: Vec::new(),
+            #[cfg(feature = "machine")]
+            system_schedule: None,
+            #[cfg(feature = "machine")]
+            strict: false,
+            #[cfg(feature = "machine")]
+            watchdog: None,
+            #[cfg(feature = "machine")]
+            watchdog_thread: None,
+            #[cfg(feature = "machine")]
+            exec_times: Default::default(),
             #[cfg(feature = "machine_lua")]
             entity_lua_state: Default::default(),
+            #[cfg(feature = "recorder")]
+            recorder: None,
+            replay_log: None,
             #[cfg(feature = "machine_dynlib")]
             libs: Default::default(),
         };
@@ -390,6 +539,7 @@ impl Sim {
         sim.apply_data_reg();
         #[cfg(all(feature = "grids", feature = "load_img"))]
         sim.apply_data_img();
+        sim.apply_data_files()?;
 
         // apply settings from scenario manifest
         sim.apply_settings();
@@ -455,8 +605,212 @@ impl Sim {
         Ok(new_uid)
     }
 
+    /// Removes the entity from the simulation, freeing up it's id.
+    ///
+    /// If the scenario declares a despawn event, it's invoked globally and
+    /// processed during the next step. The id of the despawned entity is
+    /// kept in `despawned` until then.
+    pub fn despawn_entity(&mut self, id: &EntityId) -> Result<()> {
+        if self.entities.remove(id).is_none() {
+            return Err(Error::FailedGettingEntityById(*id));
+        }
+        let name = self
+            .entity_idx
+            .iter()
+            .find(|(_, _id)| *_id == id)
+            .map(|(name, _)| name.clone());
+        if let Some(name) = &name {
+            self.entity_idx.remove(name);
+        }
+        self.removals.push(removals::Removal {
+            clock: self.clock,
+            entity: *id,
+            name,
+            var: None,
+        });
+        self.entity_pool
+            .return_id(*id)
+            .map_err(|_| Error::ReturnIdError)?;
+        #[cfg(feature = "machine_lua")]
+        self.entity_lua_state.remove(id);
+        if let Some(baseline) = &mut self.delta_baseline {
+            baseline.forget(id);
+        }
+        // graph vars are rewritten through the storage so that the changes
+        // are tracked
+        for entity in self.entities.values_mut() {
+            let graphs = entity
+                .storage
+                .map
+                .iter()
+                .filter_map(|(idx, var)| match var {
+                    Var::Graph(graph) if graph.contains_node(*id) => Some(idx.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            for idx in graphs {
+                if let Ok(Var::Graph(graph)) = entity.storage.get_var_mut(&idx) {
+                    graph.remove_node(*id);
+                }
+            }
+        }
+        if let Some(event) = &self.model.scenario.manifest.despawn_event {
+            let event = string::new_truncate(event);
+            if !self.event_queue.contains(&event) {
+                self.event_queue.push(event);
+            }
+            self.despawned.push(*id);
+        }
+        Ok(())
+    }
+
+    /// Removes the entity with the given name from the simulation.
+    pub fn despawn_entity_by_name(&mut self, name: &EntityName) -> Result<()> {
+        let id = *self
+            .entity_idx
+            .get(name)
+            .ok_or_else(|| Error::FailedGettingEntityByName(name.to_string()))?;
+        self.despawn_entity(&id)
+    }
+
+    /// Invokes the event for a single entity. Event is processed during the
+    /// next step.
+    #[cfg(feature = "machine")]
+    pub fn invoke_entity_event(&mut self, id: &EntityId, event: EventName) -> Result<()> {
+        let entity = self.get_entity_mut(id)?;
+        if !entity.event_queue.contains(&event) {
+            entity.event_queue.push(event);
+        }
+        Ok(())
+    }
+
+    /// Attaches the component to a live entity, initializing the
+    /// component's vars with their default values.
+    ///
+    /// Component's machine starts processing from the next step on, if it's
+    /// triggered by any of the events fired then.
+    ///
+    /// Only local sims can be modified this way, entities of distributed
+    /// sims are owned by the workers' nodes.
+    pub fn attach_component(&mut self, entity_id: &EntityId, comp_name: &CompName) -> Result<()> {
+        let entity = self
+            .entities
+            .get_mut(entity_id)
+            .ok_or(Error::FailedGettingEntityById(*entity_id))?;
+        if entity.components.contains(comp_name) {
+            return Err(Error::Other(format!(
+                "component {} already attached to entity {}",
+                comp_name, entity_id
+            )));
+        }
+        entity.attach(comp_name.clone(), &self.model)
+    }
+
+    /// Detaches the component from a live entity, removing the component's
+    /// vars from the entity storage.
+    pub fn detach_component(&mut self, entity_id: &EntityId, comp_name: &CompName) -> Result<()> {
+        let entity = self
+            .entities
+            .get_mut(entity_id)
+            .ok_or(Error::FailedGettingEntityById(*entity_id))?;
+        if !entity.components.contains(comp_name) {
+            return Err(Error::Other(format!(
+                "component {} not attached to entity {}",
+                comp_name, entity_id
+            )));
+        }
+        entity.detach(comp_name, &self.model)?;
+        let name = self
+            .entity_idx
+            .iter()
+            .find(|(_, id)| *id == entity_id)
+            .map(|(name, _)| name.clone());
+        for var in &self.model.get_component(comp_name)?.vars {
+            self.removals.push(removals::Removal {
+                clock: self.clock,
+                entity: *entity_id,
+                name: name.clone(),
+                var: Some((comp_name.clone(), var.name.clone())),
+            });
+        }
+        Ok(())
+    }
+
+    /// Replaces the model, migrating live entities to the updated
+    /// components.
+    ///
+    /// Vars added to a component are inserted with their default values,
+    /// vars whose type changed are coerced to the new type, falling back to
+    /// the default value, and vars no longer declared are removed. Updates
+    /// removing components still attached to live entities are rejected,
+    /// leaving the simulation intact.
+    pub fn update_model(&mut self, model: SimModel) -> Result<()> {
+        for (entity_id, entity) in &self.entities {
+            for comp_name in &entity.components {
+                if model.get_component(comp_name).is_err() {
+                    return Err(Error::Other(format!(
+                        "component {} is still attached to entity {}",
+                        comp_name, entity_id
+                    )));
+                }
+            }
+        }
+
+        let names = self
+            .entity_idx
+            .iter()
+            .map(|(name, id)| (*id, name.clone()))
+            .collect::<FnvHashMap<_, _>>();
+        for (entity_id, entity) in &mut self.entities {
+            for comp_name in &entity.components {
+                let comp_model = model.get_component(comp_name)?;
+                for var_model in &comp_model.vars {
+                    let idx = (comp_name.clone(), var_model.name.clone());
+                    let default = || {
+                        var_model
+                            .default
+                            .clone()
+                            .unwrap_or(var_model.type_.default_value())
+                    };
+                    let migrated = match entity.storage.get_var(&idx) {
+                        Ok(var) if var.get_type() == var_model.type_ => continue,
+                        Ok(var) => var.coerce(var_model.type_).unwrap_or_else(|_| default()),
+                        Err(_) => default(),
+                    };
+                    entity.storage.insert(idx, migrated);
+                }
+                let previous = match self.model.get_component(comp_name) {
+                    Ok(previous) => previous,
+                    Err(_) => continue,
+                };
+                for var_model in &previous.vars {
+                    if comp_model.vars.iter().any(|v| v.name == var_model.name) {
+                        continue;
+                    }
+                    let idx = (comp_name.clone(), var_model.name.clone());
+                    entity.storage.remove(&idx);
+                    self.removals.push(removals::Removal {
+                        clock: self.clock,
+                        entity: *entity_id,
+                        name: names.get(entity_id).cloned(),
+                        var: Some(idx),
+                    });
+                }
+            }
+        }
+        self.model = model;
+        #[cfg(feature = "machine")]
+        {
+            self.system_schedule = None;
+        }
+        Ok(())
+    }
+
     pub fn add_event(&mut self, name: EventName) -> Result<()> {
-        self.model.events.push(EventModel { id: name.clone() });
+        self.model.events.push(EventModel {
+            id: name.clone(),
+            order: Default::default(),
+        });
         self.event_queue.push(name);
         Ok(())
     }
@@ -466,65 +820,18 @@ impl Sim {
 #[cfg(feature = "machine_lua")]
 impl Sim {
     /// Setup lua states for the individual entities.
-    /// This is used during initialization from snapshot.
-    pub fn setup_lua_state_ent(&mut self) {
-        let mut map: HashMap<StringId, Vec<PathBuf>> = HashMap::new();
-        for ent_type in &self.model.entity_types {
-            let mut scripts_vec = Vec::new();
-            for comp in self
-                .model
-                .components
-                .iter()
-                .filter(|c| &c.entity_type == &ent_type.id)
-                .collect::<Vec<&model::ComponentModel>>()
-            {
-                for script_file in &comp.script_files {
-                    if !scripts_vec.contains(script_file) {
-                        scripts_vec.push(script_file.clone());
-                    }
-                }
-                //                println!("ent_type: {}",
-                // ent_type.id);
-                // println!("script files: {:?}",
-                // comp.script_files);
-            }
-            map.insert(StringId::from_truncate(&ent_type.id), scripts_vec);
-        }
-
-        for ((mut ent_type, mut ent_id), mut entity) in &mut self.entities {
+    ///
+    /// States are otherwise created lazily on first `lua_call`, this can be
+    /// used to surface script errors up front, e.g. after initialization
+    /// from snapshot.
+    pub fn setup_lua_state_ent(&mut self) -> Result<()> {
+        for entity in self.entities.values_mut() {
             if entity.insta.lua_state.is_some() {
                 continue;
             }
-            if let Some(scripts) = map.get(&ent_type) {
-                let mut lua_state = Lua::new();
-                lua_state.context(|ctx| {
-                    for script_path in scripts {
-                        let mut file = match File::open(script_path) {
-                            Ok(f) => f,
-                            Err(e) => {
-                                warn!("{}", e);
-                                continue;
-                            }
-                        };
-                        let mut contents = String::new();
-                        file.read_to_string(&mut contents);
-                        let script_path_str =
-                            script_path.to_str().expect("failed to_str on pathbuf");
-                        let last_three = script_path_str.rsplitn(4, "/").collect::<Vec<&str>>();
-                        let last_three =
-                            format!("{}/{}/{}", last_three[2], last_three[1], last_three[0]);
-                        ctx.load(&contents)
-                            .set_name(&last_three)
-                            .expect(
-                                "failed set_name() on lua chunk while setting entity's lua_state",
-                            )
-                            .exec()
-                            .expect("failed exec() on lua chunk while setting entity's lua_state");
-                    }
-                });
-                entity.insta.lua_state = Some(Arc::new(Mutex::new(lua_state)));
-            }
+            entity.insta.lua_state = Some(machine::cmd::lua::LuaState::new(&self.model)?);
         }
+        Ok(())
     }
 }
 
@@ -573,6 +880,36 @@ impl Sim {
         Ok(out)
     }
 
+    /// Collects vars changed during or after the step with the given clock
+    /// value.
+    ///
+    /// Changes are only known since the simulation was created, or since
+    /// it was last loaded from a snapshot or delta. Asking for changes from
+    /// before that point returns all the vars.
+    pub fn collect_changed_since(&self, step: usize) -> Vec<(EntityId, StorageIndex, Var)> {
+        let all = step < self.change_tracking_start;
+        let mut out = Vec::new();
+        for (ent_id, entity) in &self.entities {
+            if all {
+                out.extend(
+                    entity
+                        .storage
+                        .map
+                        .iter()
+                        .map(|(idx, var)| (*ent_id, idx.clone(), var.clone())),
+                );
+            } else {
+                out.extend(
+                    entity
+                        .storage
+                        .changed_since(step)
+                        .map(|(idx, var)| (*ent_id, idx.clone(), var.clone())),
+                );
+            }
+        }
+        out
+    }
+
     /// Get a `Var` from the sim using an absolute address.
     pub fn get_var(&self, addr: &Address) -> Result<&Var> {
         if let Some(ent_uid) = self.entity_idx.get(&addr.entity) {
@@ -594,6 +931,7 @@ impl Sim {
 
     /// Get a variable from the sim using an absolute address.
     pub fn get_var_mut(&mut self, addr: &Address) -> Result<&mut Var> {
+        self.check_writable(addr)?;
         if let Some(ent_uid) = self.entity_idx.get(&addr.entity) {
             if let Some(ent) = self.entities.get_mut(ent_uid) {
                 return ent.storage.get_var_mut(&addr.storage_index());
@@ -611,6 +949,48 @@ impl Sim {
         Err(Error::FailedGettingVarFromSim(addr.clone()))
     }
 
+    /// Checks whether the provided var can be written at the given address.
+    ///
+    /// The address has to point to an existing var of the same type, and
+    /// the value has to fall within the bounds declared in the model.
+    pub fn validate_var(&self, addr: &Address, var: &Var) -> Result<()> {
+        self.model.validate_var(addr, self.get_var(addr)?, var)
+    }
+
+    /// Converts the provided numeric var from the given unit into the unit
+    /// declared for the var at the address.
+    ///
+    /// Vars without a declared unit are returned unchanged. Values in units
+    /// of a different dimension are rejected. In strict mode values in any
+    /// other unit than the declared one are rejected instead of converted.
+    pub fn convert_unit(&self, addr: &Address, var: Var, unit: &str) -> Result<Var> {
+        let target = match self
+            .model
+            .get_component(&addr.component)
+            .ok()
+            .and_then(|c| c.vars.iter().find(|v| v.name == addr.var_name))
+            .and_then(|v| v.unit())
+        {
+            Some(t) => t,
+            None => return Ok(var),
+        };
+        let source = crate::unit::Unit::from_str(unit)?;
+        if source.is_same(&target) {
+            return Ok(var);
+        }
+        #[cfg(feature = "machine")]
+        if self.strict {
+            return Err(Error::UnitMismatch(source.symbol, target.symbol));
+        }
+        let value = source.convert(var.to_float() as f64, &target)?;
+        Ok(match var {
+            Var::Int(_) => Var::Int(value.round() as crate::Int),
+            Var::Fixed(f) => Var::Fixed(crate::var::Fixed::from_float(value, f.frac)),
+            Var::Decimal(d) => Var::Decimal(crate::var::Decimal::from_float(value, d.scale)),
+            _ => Var::Float(value as crate::Float),
+        })
+    }
+
     /// Set a var at address using a string value as input.
     pub fn set_from_string(&mut self, addr: &Address, val: &String) -> Result<()> {
         match addr.var_type {
@@ -676,41 +1056,21 @@ impl Sim {
 impl Sim {
     /// Set a var of any type using a string grid as input.
     pub fn set_from_string_grid(&mut self, addr: &Address, vec2d: &Vec<Vec<String>>) -> Result<()> {
-        match addr.var_type {
-            // VarType::StringGrid => {
-            //     *self.get_var_mut(&addr)?.as_str_grid_mut()? = vec2d.clone();
-            // }
-            // VarType::IntGrid => {
-            //     *self.get_var_mut(&addr)?.as_int_grid_mut()? = vec2d
-            //         .iter()
-            //         .map(|v| {
-            //             v.iter()
-            //                 .map(|is| is.parse::<crate::Int>().unwrap())
-            //                 .collect()
-            //         })
-            //         .collect();
-            // }
-            // VarType::FloatGrid => {
-            //     *self.get_var_mut(&addr)?.as_float_grid_mut()? = vec2d
-            //         .iter()
-            //         .map(|v| {
-            //             v.iter()
-            //                 .map(|fs| fs.parse::<crate::Float>().unwrap())
-            //                 .collect()
-            //         })
-            //         .collect();
-            // }
-            // VarType::BoolGrid => {
-            //     *self.get_var_mut(&addr)?.as_bool_grid_mut()? = vec2d
-            //         .iter()
-            //         .map(|v| v.iter().map(|bs| bs.parse::<bool>().unwrap()).collect())
-            //         .collect();
-            // }
-            _ => error!(
-                "set_from_string_grid not yet implemented for var type {:?}",
-                addr.var_type
-            ),
-        }
+        let var: Var = match addr.var_type {
+            VarType::StringGrid => StringGrid::from_str_rows(vec2d)?.into(),
+            VarType::IntGrid => IntGrid::from_str_rows(vec2d)?.into(),
+            VarType::FloatGrid => FloatGrid::from_str_rows(vec2d)?.into(),
+            VarType::BoolGrid => BoolGrid::from_str_rows(vec2d)?.into(),
+            VarType::ByteGrid => ByteGrid::from_str_rows(vec2d)?.into(),
+            _ => {
+                error!(
+                    "set_from_string_grid not yet implemented for var type {:?}",
+                    addr.var_type
+                );
+                return Ok(());
+            }
+        };
+        *self.get_var_mut(&addr)? = var;
         Ok(())
     }
 
@@ -809,6 +1169,39 @@ impl Sim {
 // TODO revise data applying
 /// Data applying functions.
 impl Sim {
+    /// Applies data from files declared in module manifests.
+    ///
+    /// Declared CSV files can't be loaded without the `load_csv` feature,
+    /// which is reported as an error. Other formats are not supported yet
+    /// and are skipped with a warning.
+    fn apply_data_files(&mut self) -> Result<()> {
+        for entry in &self.model.data_files.clone() {
+            match entry {
+                #[cfg(feature = "load_csv")]
+                DataFileEntry::CsvList(path) => self.apply_csv_list(path)?,
+                #[cfg(feature = "load_csv")]
+                DataFileEntry::CsvGrid(path) => self.apply_csv_grid(path)?,
+                #[cfg(not(feature = "load_csv"))]
+                DataFileEntry::CsvList(path) | DataFileEntry::CsvGrid(path) => {
+                    return Err(Error::FailedLoadingDataFile(
+                        path.to_string(),
+                        0,
+                        "loading csv data requires the load_csv feature".to_string(),
+                    ))
+                }
+                DataFileEntry::Json(path)
+                | DataFileEntry::JsonList(path)
+                | DataFileEntry::JsonGrid(path)
+                | DataFileEntry::Yaml(path)
+                | DataFileEntry::YamlList(path)
+                | DataFileEntry::YamlGrid(path) => {
+                    warn!("skipping data file, format not supported yet: {}", path)
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Apply regular data as found in data declarations in user files.
     fn apply_data_reg(&mut self) {
         for de in &self.model.data.clone() {
@@ -932,6 +1325,72 @@ impl Sim {
             .ok_or(Error::FailedGettingEntityById(*entity_id))
     }
 
+    /// Iterates over all entities, in no particular order.
+    pub fn iter_entities(&self) -> impl Iterator<Item = EntityRef> {
+        crate::entity::handle::iter(&self.entities, &self.entity_idx)
+    }
+
+    /// Iterates over all entities in parallel.
+    #[cfg(feature = "rayon")]
+    pub fn par_iter_entities(&self) -> impl rayon::iter::ParallelIterator<Item = EntityRef> {
+        crate::entity::handle::par_iter(&self.entities, &self.entity_idx)
+    }
+
+    /// Iterates over ids of all entities, in no particular order.
+    pub fn entity_ids(&self) -> impl Iterator<Item = &EntityId> {
+        self.entities.keys()
+    }
+
+    /// Returns the number of existing entities, not counting the reserved
+    /// engine entity.
+    pub fn entity_count(&self) -> usize {
+        match self.engine_entity_id() {
+            Some(_) => self.entities.len() - 1,
+            None => self.entities.len(),
+        }
+    }
+
+    /// Gets the id of the entity with the given string id, if any.
+    pub fn entity_id_by_name(&self, name: &EntityName) -> Option<EntityId> {
+        self.entity_idx.get(name).copied()
+    }
+
+    /// Returns the map of entity string ids to integer ids.
+    pub fn entity_index(&self) -> &FnvHashMap<EntityName, EntityId> {
+        &self.entity_idx
+    }
+
+    /// Returns the events queued up for processing during the next step.
+    pub fn queued_events(&self) -> &[EventName] {
+        &self.event_queue
+    }
+
+    /// Queues up a global event for processing during the next step.
+    /// Events already queued are not queued again.
+    pub fn invoke_event(&mut self, event: EventName) {
+        if !self.event_queue.contains(&event) {
+            self.event_queue.push(event);
+        }
+    }
+
+    /// Processes the query against the current simulation state, using
+    /// registered query plugins.
+    ///
+    /// The reserved engine entity is only included if the query selects it
+    /// explicitly, see [`introspect`].
+    pub fn query(&self, query: &Query) -> Result<QueryProduct> {
+        let selected = self.select_entities(query);
+        query.process_selected(&selected, &self.entities, &self.query_plugins)
+    }
+
+    /// Applies query filters, returning ids of the selected entities.
+    pub fn select_entities(&self, query: &Query) -> Vec<EntityId> {
+        let mut selected =
+            query.select_entities_with(&self.entities, &self.entity_idx, &self.query_plugins);
+        self.hide_engine_entity(query, &mut selected);
+        selected
+    }
+
     /// Gets references to all entity objects
     pub fn get_entities(&self) -> Vec<&Entity> {
         self.entities.values().collect()
@@ -997,3 +1456,141 @@ fn sim_step() {
     assert!(sim.step().is_ok());
     assert!(sim.step().is_ok());
 }
+
+#[test]
+fn sim_data_files_without_loader() {
+    let mut model = crate::SimModelBuilder::new().build().unwrap();
+    model
+        .data_files
+        .push(DataFileEntry::Json("missing.json".to_string()));
+    assert!(Sim::from_model(model.clone()).is_ok());
+
+    model
+        .data_files
+        .push(DataFileEntry::CsvList("missing.csv".to_string()));
+    assert!(Sim::from_model(model).is_err());
+}
+
+#[test]
+fn sim_update_model_migrates_entities() {
+    let comp = string::new_truncate("pos");
+    let var = |name: &str| (comp.clone(), string::new_truncate(name));
+    let mut sim = crate::SimModelBuilder::new()
+        .component("pos", |c| {
+            c.var("int:x", Var::Int(3)).var("int:y", Var::Int(0))
+        })
+        .prefab("thing", &["pos"])
+        .spawn("thing", Some("a"))
+        .build_sim()
+        .unwrap();
+    let id = sim.entity_idx[&string::new_truncate("a")];
+
+    // component attached to a live entity can't be dropped
+    let empty = crate::SimModelBuilder::new().build().unwrap();
+    assert!(sim.update_model(empty).is_err());
+    assert!(sim.model.get_component(&comp).is_ok());
+
+    let updated = crate::SimModelBuilder::new()
+        .component("pos", |c| {
+            c.var("float:x", Var::Float(0.)).var("int:z", Var::Int(7))
+        })
+        .prefab("thing", &["pos"])
+        .build()
+        .unwrap();
+    sim.update_model(updated).unwrap();
+    let storage = &sim.entities[&id].storage;
+    assert_eq!(storage.get_var(&var("x")).unwrap(), &Var::Float(3.));
+    assert_eq!(storage.get_var(&var("z")).unwrap(), &Var::Int(7));
+    assert!(storage.get_var(&var("y")).is_err());
+    let removed = sim.collect_removed_since(0).unwrap();
+    assert_eq!(removed.len(), 1);
+    assert_eq!(removed[0].var, Some(var("y")));
+}
+
+#[test]
+fn despawn_removes_graph_nodes() {
+    use crate::graph::Graph;
+
+    let mut sim = crate::SimModelBuilder::new()
+        .component("net", |c| c.var("graph:links", None))
+        .prefab("node", &["net"])
+        .build_sim()
+        .unwrap();
+    let a = sim
+        .spawn_entity(Some(&string::new_truncate("node")), None)
+        .unwrap();
+    let b = sim
+        .spawn_entity(Some(&string::new_truncate("node")), None)
+        .unwrap();
+    let mut graph = Graph::new(false);
+    graph.add_edge(a, b, 1.);
+    let addr = Address::from_str(&format!("{}:net:graph:links", a)).unwrap();
+    *sim.get_var_mut(&addr).unwrap() = Var::Graph(graph);
+
+    sim.despawn_entity(&b).unwrap();
+    match sim.get_var(&addr).unwrap() {
+        Var::Graph(graph) => {
+            assert!(!graph.contains_node(b));
+            assert!(graph.neighbors(a).is_empty());
+        }
+        _ => panic!("expected graph var"),
+    }
+}
+
+#[test]
+fn despawn_event_carries_entity_id() {
+    let mut sim = crate::SimModelBuilder::new()
+        .component("pos", |c| c.var("float:x", None))
+        .prefab("dot", &["pos"])
+        .despawn_event("despawned")
+        .build_sim()
+        .unwrap();
+    let id = sim
+        .spawn_entity(Some(&string::new_truncate("dot")), None)
+        .unwrap();
+    sim.despawn_entity(&id).unwrap();
+    assert!(sim.event_queue.contains(&string::new_truncate("despawned")));
+    assert_eq!(sim.despawned, vec![id]);
+
+    sim.step().unwrap();
+    assert!(sim.despawned.is_empty());
+}
+
+#[test]
+fn components_are_attached_and_detached_at_runtime() {
+    let (pos, tag) = (string::new_truncate("pos"), string::new_truncate("tag"));
+    let mut sim = crate::SimModelBuilder::new()
+        .component("pos", |c| c.var("float:x", None))
+        .component("tag", |c| c.var("int:n", Var::Int(3)))
+        .prefab("dot", &["pos"])
+        .spawn("dot", Some("a"))
+        .build_sim()
+        .unwrap();
+    let id = sim.entity_idx[&string::new_truncate("a")];
+    let n = (tag.clone(), string::new_truncate("n"));
+
+    sim.attach_component(&id, &tag).unwrap();
+    assert_eq!(sim.entities[&id].storage.get_var(&n).unwrap(), &Var::Int(3));
+    assert!(sim.entities[&id].components.contains(&tag));
+    // attaching twice or to a missing entity fails
+    assert!(sim.attach_component(&id, &tag).is_err());
+    assert!(sim.attach_component(&(id + 1), &tag).is_err());
+    assert!(sim
+        .attach_component(&id, &string::new_truncate("missing"))
+        .is_err());
+
+    sim.detach_component(&id, &tag).unwrap();
+    assert!(sim.entities[&id].storage.get_var(&n).is_err());
+    assert!(!sim.entities[&id].components.contains(&tag));
+    let removed = sim.collect_removed_since(0).unwrap();
+    assert_eq!(removed.len(), 1);
+    assert_eq!(removed[0].var, Some(n));
+
+    // detaching a component that's not attached leaves the entity intact
+    assert!(sim.detach_component(&id, &tag).is_err());
+    assert!(sim.entities[&id].components.contains(&pos));
+    assert!(sim.entities[&id]
+        .storage
+        .get_var(&(pos, string::new_truncate("x")))
+        .is_ok());
+}
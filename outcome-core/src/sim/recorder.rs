@@ -0,0 +1,338 @@
+//! Recording selected vars over time.
+//!
+//! Recorder samples a list of addresses every `interval` steps and writes
+//! the values to a file on disk, for later analysis. Samples are buffered
+//! in memory and written out once the buffer is full, when the recording
+//! is stopped or when explicitly flushed.
+//!
+//! ```ignore
+//! use outcome_core::sim::recorder::{RecordFormat, RecorderConfig};
+//!
+//! sim.start_recording(RecorderConfig {
+//!     addrs: vec!["world:clock:float:temperature".parse()?],
+//!     interval: 10,
+//!     format: RecordFormat::Csv,
+//!     path: "temperature.csv".into(),
+//!     ..RecorderConfig::default()
+//! })?;
+//! ```
+//!
+//! Supported formats:
+//!
+//! - `csv`: one row per sample, the first column holds the clock, the
+//! following ones hold values of the recorded addresses
+//! - `jsonl`: one JSON object per sample, keyed by address, with the clock
+//! under the `clock` key
+//! - `columnar`: one JSON object per flushed buffer, with each column
+//! stored as an array of values, similar to row groups of columnar
+//! formats such as Parquet
+//!
+//! Vars that can't be read at the time of sampling, e.g. because the
+//! entity was despawned, are recorded as empty values.
+//!
+//! Appending to an existing CSV file is only allowed if it's header matches
+//! the recorded addresses.
+//!
+//! Recording is stopped if writing the samples fails during a step, the
+//! step itself proceeds.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde_json::{Map, Value};
+
+use crate::address::Address;
+use crate::error::{Error, Result};
+use crate::{Sim, Var};
+
+/// Output format of the recording.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RecordFormat {
+    Csv,
+    JsonLines,
+    Columnar,
+}
+
+impl Default for RecordFormat {
+    fn default() -> Self {
+        RecordFormat::Csv
+    }
+}
+
+impl FromStr for RecordFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(RecordFormat::Csv),
+            "jsonl" | "json_lines" => Ok(RecordFormat::JsonLines),
+            "columnar" => Ok(RecordFormat::Columnar),
+            _ => Err(Error::Other(format!(
+                "unknown record format: {}, expected one of: csv, jsonl, columnar",
+                s
+            ))),
+        }
+    }
+}
+
+/// Recorder configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecorderConfig {
+    /// Addresses of the recorded vars
+    pub addrs: Vec<Address>,
+    /// Number of steps between samples
+    pub interval: usize,
+    pub format: RecordFormat,
+    /// Path to the output file, created if it doesn't exist, appended to
+    /// otherwise
+    pub path: PathBuf,
+    /// Number of samples buffered before writing them to the file
+    pub buffer_len: usize,
+}
+
+impl Default for RecorderConfig {
+    fn default() -> Self {
+        RecorderConfig {
+            addrs: Vec::new(),
+            interval: 1,
+            format: RecordFormat::default(),
+            path: PathBuf::from("recording.csv"),
+            buffer_len: 100,
+        }
+    }
+}
+
+/// Records values of selected vars to disk.
+pub struct Recorder {
+    pub config: RecorderConfig,
+    /// Whether samples are currently being taken
+    pub recording: bool,
+    /// Buffered samples, as `(clock, values)` pairs
+    samples: Vec<(usize, Vec<Option<Var>>)>,
+    file: Option<BufWriter<File>>,
+}
+
+impl Recorder {
+    pub fn new(config: RecorderConfig) -> Self {
+        Recorder {
+            config,
+            recording: false,
+            samples: Vec::new(),
+            file: None,
+        }
+    }
+
+    /// Opens the output file and starts taking samples.
+    pub fn start(&mut self) -> Result<()> {
+        if self.file.is_none() {
+            let header = self.csv_header();
+            let mut existing = String::new();
+            if self.config.format == RecordFormat::Csv && self.config.path.exists() {
+                BufReader::new(File::open(&self.config.path)?).read_line(&mut existing)?;
+                let existing = existing.trim_end_matches(|c| c == '\n' || c == '\r');
+                if !existing.is_empty() && existing != header {
+                    return Err(Error::Other(format!(
+                        "can't append to {}, header doesn't match the recorded addresses",
+                        self.config.path.display()
+                    )));
+                }
+            }
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.config.path)?;
+            let mut file = BufWriter::new(file);
+            if existing.is_empty() && self.config.format == RecordFormat::Csv {
+                writeln!(file, "{}", header)?;
+            }
+            self.file = Some(file);
+        }
+        self.recording = true;
+        Ok(())
+    }
+
+    fn csv_header(&self) -> String {
+        let mut header = vec!["clock".to_string()];
+        header.extend(self.config.addrs.iter().map(|a| csv_field(&a.to_string())));
+        header.join(",")
+    }
+
+    /// Stops taking samples and writes out the buffered ones.
+    pub fn stop(&mut self) -> Result<()> {
+        self.recording = false;
+        self.flush()
+    }
+
+    /// Takes a sample if recording and the interval has passed, writing
+    /// buffered samples out if the buffer is full.
+    pub fn sample(&mut self, sim: &Sim) -> Result<()> {
+        let clock = sim.get_clock();
+        if !self.recording || clock % self.config.interval.max(1) != 0 {
+            return Ok(());
+        }
+        let values = self
+            .config
+            .addrs
+            .iter()
+            .map(|addr| sim.get_var(addr).ok().cloned())
+            .collect();
+        self.samples.push((clock, values));
+        if self.samples.len() >= self.config.buffer_len {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes buffered samples to the output file.
+    pub fn flush(&mut self) -> Result<()> {
+        let file = match &mut self.file {
+            Some(f) => f,
+            None => return Ok(()),
+        };
+        if !self.samples.is_empty() {
+            match self.config.format {
+                RecordFormat::Csv => {
+                    for (clock, values) in &self.samples {
+                        let mut row = vec![clock.to_string()];
+                        row.extend(values.iter().map(|v| match v {
+                            Some(var) => csv_field(&var.to_string()),
+                            None => String::new(),
+                        }));
+                        writeln!(file, "{}", row.join(","))?;
+                    }
+                }
+                RecordFormat::JsonLines => {
+                    for (clock, values) in &self.samples {
+                        let mut object = Map::new();
+                        object.insert("clock".to_string(), Value::from(*clock));
+                        for (addr, value) in self.config.addrs.iter().zip(values) {
+                            object.insert(addr.to_string(), opt_var_to_json(value));
+                        }
+                        writeln!(file, "{}", Value::Object(object))?;
+                    }
+                }
+                RecordFormat::Columnar => {
+                    let mut object = Map::new();
+                    object.insert(
+                        "clock".to_string(),
+                        self.samples.iter().map(|(c, _)| Value::from(*c)).collect(),
+                    );
+                    for (n, addr) in self.config.addrs.iter().enumerate() {
+                        object.insert(
+                            addr.to_string(),
+                            self.samples
+                                .iter()
+                                .map(|(_, values)| opt_var_to_json(&values[n]))
+                                .collect(),
+                        );
+                    }
+                    writeln!(file, "{}", Value::Object(object))?;
+                }
+            }
+            self.samples.clear();
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            warn!("failed flushing recorder on drop: {}", e);
+        }
+    }
+}
+
+/// Recording functionality.
+impl Sim {
+    /// Starts recording using the given configuration. Recording already
+    /// in progress is stopped first.
+    pub fn start_recording(&mut self, config: RecorderConfig) -> Result<()> {
+        self.stop_recording()?;
+        let mut recorder = Recorder::new(config);
+        recorder.start()?;
+        self.recorder = Some(recorder);
+        Ok(())
+    }
+
+    /// Stops recording, writing out all the buffered samples.
+    pub fn stop_recording(&mut self) -> Result<()> {
+        match self.recorder.take() {
+            Some(mut recorder) => recorder.stop(),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes out buffered samples without stopping the recording.
+    pub fn flush_recording(&mut self) -> Result<()> {
+        match &mut self.recorder {
+            Some(recorder) => recorder.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Quotes the field if it contains characters with special meaning in CSV.
+fn csv_field(s: &str) -> String {
+    if s.contains(|c| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn opt_var_to_json(var: &Option<Var>) -> Value {
+    match var {
+        Some(v) => var_to_json(v),
+        None => Value::Null,
+    }
+}
+
+/// Converts a var into a plain JSON value. Collections are converted into
+/// arrays, types without a JSON counterpart are converted into strings.
+fn var_to_json(var: &Var) -> Value {
+    match var {
+        Var::String(v) => Value::from(v.as_str()),
+        Var::Int(v) => Value::from(*v),
+        Var::Float(v) => Value::from(*v),
+        Var::Bool(v) => Value::from(*v),
+        Var::Byte(v) => Value::from(*v),
+        Var::Vec2(x, y) => Value::from(vec![*x, *y]),
+        Var::Vec3(x, y, z) => Value::from(vec![*x, *y, *z]),
+        Var::List(list) => list.iter().map(var_to_json).collect(),
+        Var::Grid(rows) => rows
+            .iter()
+            .map(|row| row.iter().map(var_to_json).collect::<Value>())
+            .collect(),
+        _ => Value::from(var.to_string()),
+    }
+}
+
+#[test]
+fn csv_append_checks_header() {
+    let path = std::env::temp_dir().join(format!("outcome_recorder_{}.csv", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let config = |addr: &str| RecorderConfig {
+        addrs: vec![Address::from_str(addr).unwrap()],
+        path: path.clone(),
+        ..RecorderConfig::default()
+    };
+
+    Recorder::new(config("world:clock:float:temp"))
+        .start()
+        .unwrap();
+    // same columns can be appended to
+    Recorder::new(config("world:clock:float:temp"))
+        .start()
+        .unwrap();
+    assert!(Recorder::new(config("world:clock:float:other"))
+        .start()
+        .is_err());
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(contents.lines().count(), 1);
+    let _ = std::fs::remove_file(&path);
+}
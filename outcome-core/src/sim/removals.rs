@@ -0,0 +1,138 @@
+//! Tracking of removed entities and vars.
+//!
+//! Var-level change tracking only covers vars that still exist. Entities
+//! that were despawned, as well as vars removed by detaching components,
+//! are recorded separately so that consumers of changes, such as `Diff`
+//! data transfers, can drop them as well.
+//!
+//! Removals are only kept for a limited number of steps. Asking for
+//! removals from before that point, or from before the point the
+//! simulation was created or loaded, yields `None`, meaning the full state
+//! has to be fetched instead.
+
+use std::collections::VecDeque;
+
+use crate::entity::StorageIndex;
+use crate::{EntityId, EntityName};
+
+use super::Sim;
+
+/// Number of steps removals are kept for.
+pub const REMOVALS_RETAINED_STEPS: usize = 1024;
+
+/// Single removed entity or var.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Removal {
+    /// Clock value at the time of removal
+    pub clock: usize,
+    pub entity: EntityId,
+    /// Name of the entity at the time of removal, if it had one
+    pub name: Option<EntityName>,
+    /// Removed var, `None` if the whole entity was removed
+    pub var: Option<StorageIndex>,
+}
+
+/// Removals recorded since a given clock value.
+#[derive(Debug, Clone, Default)]
+pub struct Removals {
+    /// Clock value since which removals are known
+    start: usize,
+    entries: VecDeque<Removal>,
+}
+
+impl Removals {
+    /// Creates an empty record, with removals known since the given clock
+    /// value.
+    pub fn since(clock: usize) -> Self {
+        Removals {
+            start: clock,
+            entries: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, removal: Removal) {
+        self.entries.push_back(removal);
+    }
+
+    /// Drops removals older than the retention window.
+    pub(crate) fn prune(&mut self, clock: usize) {
+        let start = clock.saturating_sub(REMOVALS_RETAINED_STEPS);
+        if start <= self.start {
+            return;
+        }
+        while self.entries.front().map_or(false, |r| r.clock < start) {
+            self.entries.pop_front();
+        }
+        self.start = start;
+    }
+}
+
+impl Sim {
+    /// Collects entities and vars removed during or after the step with
+    /// the given clock value. Returns `None` if removals from that point
+    /// are no longer known.
+    pub fn collect_removed_since(&self, step: usize) -> Option<Vec<&Removal>> {
+        if step < self.removals.start {
+            return None;
+        }
+        Some(
+            self.removals
+                .entries
+                .iter()
+                .filter(|r| r.clock >= step)
+                .collect(),
+        )
+    }
+}
+
+#[test]
+fn removals_are_pruned() {
+    let mut removals = Removals::since(0);
+    for clock in 0..REMOVALS_RETAINED_STEPS + 10 {
+        removals.push(Removal {
+            clock,
+            entity: 0,
+            name: None,
+            var: None,
+        });
+    }
+    removals.prune(REMOVALS_RETAINED_STEPS + 10);
+    assert_eq!(removals.start, 10);
+    assert_eq!(removals.entries.len(), REMOVALS_RETAINED_STEPS);
+}
+
+#[test]
+fn despawned_entities_and_detached_vars_are_recorded() {
+    use crate::string::new_truncate;
+
+    let mut sim = crate::SimModelBuilder::new()
+        .component("pos", |c| c.var("int:x", crate::Var::Int(0)))
+        .component("tag", |c| c.var("int:y", crate::Var::Int(0)))
+        .prefab("thing", &["pos", "tag"])
+        .build_sim()
+        .unwrap();
+    let a = sim
+        .spawn_entity(Some(&new_truncate("thing")), Some(new_truncate("a")))
+        .unwrap();
+    let b = sim
+        .spawn_entity(Some(&new_truncate("thing")), None)
+        .unwrap();
+    sim.spawn_entity(Some(&new_truncate("thing")), None)
+        .unwrap();
+    sim.detach_component(&a, &new_truncate("tag")).unwrap();
+    sim.despawn_entity(&b).unwrap();
+
+    let removed = sim.collect_removed_since(0).unwrap();
+    assert_eq!(removed.len(), 2);
+    assert_eq!(removed[0].name, Some(new_truncate("a")));
+    assert_eq!(
+        removed[0].var,
+        Some((new_truncate("tag"), new_truncate("y")))
+    );
+    assert_eq!(removed[1].entity, b);
+    assert_eq!(removed[1].var, None);
+
+    // ids are reassigned, earlier removals are no longer meaningful
+    sim.compact_entities().unwrap();
+    assert!(sim.collect_removed_since(0).is_none());
+}
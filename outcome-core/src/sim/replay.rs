@@ -0,0 +1,349 @@
+//! Recording and replaying runs.
+//!
+//! With stepping being deterministic, a run can be reproduced exactly from
+//! its starting state and the list of mutations applied to it from the
+//! outside, e.g. vars pulled in by clients, entities spawned on request or
+//! model edits. Replay log captures both: it starts with a snapshot of the
+//! simulation taken when logging was started, followed by every external
+//! mutation and every successfully processed step, in order. Mutations are
+//! validated the same way when replayed as they are in a live run.
+//!
+//! ```ignore
+//! sim.start_replay_log("run.replay")?;
+//! sim.apply_mutation(Mutation::Invoke(string::new_truncate("rain")))?;
+//! sim.step()?;
+//! sim.stop_replay_log()?;
+//!
+//! let replayed = Sim::replay_from("run.replay")?;
+//! ```
+//!
+//! Mutations applied directly, instead of through [`Sim::apply_mutation`],
+//! have to be recorded using [`Sim::log_mutation`], otherwise the replay
+//! will diverge from the original run.
+//!
+//! Replay checks the clock of each entry, as well as the ids of spawned
+//! entities, against the replayed simulation, and fails as soon as they
+//! stop matching.
+//!
+//! Lua states of entities can't be captured in the snapshot. They're reset
+//! when logging starts instead, so that both the logged run and the replay
+//! continue with freshly created states.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+use crate::address::Address;
+use crate::error::{Error, Result};
+use crate::snapshot::Snap;
+use crate::{CompName, EntityId, EntityName, EventName, PrefabName, Sim, SimModel, Var};
+
+/// Mutation applied to the simulation from the outside.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Mutation {
+    SetVar(Address, Var),
+    Spawn {
+        prefab: Option<PrefabName>,
+        name: Option<EntityName>,
+        /// Id the entity was assigned
+        id: EntityId,
+    },
+    Despawn(EntityId),
+    AttachComponent(EntityId, CompName),
+    DetachComponent(EntityId, CompName),
+    Invoke(EventName),
+    /// Model replaced as a whole
+    Model(SimModel),
+    /// Entity ids compacted, see [`Sim::compact_entities`]
+    CompactEntities,
+    /// Event scheduled to fire at the given step, optionally repeating
+    ScheduleEvent {
+        event: EventName,
+        at_step: usize,
+        every_n_steps: Option<usize>,
+    },
+}
+
+/// Single replay log entry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LogEntry {
+    Mutation(Mutation),
+    /// Step processed at the given clock
+    Step,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LogHeader {
+    clock: usize,
+    snapshot: Vec<u8>,
+}
+
+/// Replay log being written.
+pub struct ReplayLog {
+    file: BufWriter<File>,
+}
+
+impl ReplayLog {
+    /// Creates a new log file, starting it with a snapshot of the current
+    /// state of the simulation.
+    pub fn create<P: AsRef<Path>>(path: P, sim: &Sim) -> Result<Self> {
+        let mut log = ReplayLog {
+            file: BufWriter::new(File::create(path)?),
+        };
+        log.write_frame(&LogHeader {
+            clock: sim.get_clock(),
+            snapshot: sim.to_snapshot()?,
+        })?;
+        Ok(log)
+    }
+
+    /// Appends an entry to the log.
+    pub fn append(&mut self, clock: usize, entry: &LogEntry) -> Result<()> {
+        self.write_frame(&(clock, entry))
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.file.flush()?;
+        Ok(())
+    }
+
+    fn write_frame<T: serde::Serialize>(&mut self, value: &T) -> Result<()> {
+        let bytes = bincode::serialize(value)?;
+        self.file.write_all(&(bytes.len() as u64).to_le_bytes())?;
+        self.file.write_all(&bytes)?;
+        Ok(())
+    }
+}
+
+impl Drop for ReplayLog {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            warn!("failed flushing replay log on drop: {}", e);
+        }
+    }
+}
+
+/// Run being replayed from a log.
+pub struct Replay {
+    pub sim: Sim,
+    reader: BufReader<File>,
+}
+
+impl Replay {
+    /// Opens the log and restores the simulation from the snapshot it
+    /// starts with.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header: LogHeader = read_frame(&mut reader)?
+            .ok_or_else(|| Error::Other("replay log is empty".to_string()))?;
+        let sim = Sim::from_snapshot(&mut header.snapshot)?;
+        if sim.get_clock() != header.clock {
+            return Err(Error::Other(format!(
+                "replay log snapshot clock mismatch: expected {}, got {}",
+                header.clock,
+                sim.get_clock()
+            )));
+        }
+        Ok(Replay { sim, reader })
+    }
+
+    /// Applies the next entry from the log. Returns false once the end of
+    /// the log is reached.
+    pub fn next_entry(&mut self) -> Result<bool> {
+        let (clock, entry): (usize, LogEntry) = match read_frame(&mut self.reader)? {
+            Some(e) => e,
+            None => return Ok(false),
+        };
+        if clock != self.sim.get_clock() {
+            return Err(Error::Other(format!(
+                "replay diverged: entry logged at clock {}, replay is at clock {}",
+                clock,
+                self.sim.get_clock()
+            )));
+        }
+        match entry {
+            LogEntry::Mutation(mutation) => self.sim.apply_mutation(mutation)?,
+            LogEntry::Step => self.sim.step()?,
+        }
+        Ok(true)
+    }
+
+    /// Replays entries until the simulation reaches the given clock or the
+    /// log ends.
+    pub fn run_until(&mut self, clock: usize) -> Result<()> {
+        while self.sim.get_clock() < clock {
+            if !self.next_entry()? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replays all the remaining entries.
+    pub fn run(mut self) -> Result<Sim> {
+        while self.next_entry()? {}
+        Ok(self.sim)
+    }
+}
+
+/// Reads a single length-prefixed frame. Returns `None` if the reader is
+/// at the end.
+///
+/// Memory is only allocated for the bytes actually read, so a corrupted
+/// length prefix results in an error instead of a huge allocation.
+fn read_frame<T: serde::de::DeserializeOwned, R: Read>(reader: &mut R) -> Result<Option<T>> {
+    let mut len = [0; 8];
+    match reader.read_exact(&mut len) {
+        Ok(()) => (),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u64::from_le_bytes(len);
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        return Err(Error::Other(format!(
+            "replay log frame truncated: expected {} bytes, got {}",
+            len,
+            bytes.len()
+        )));
+    }
+    Ok(Some(bincode::deserialize(&bytes)?))
+}
+
+/// Replay functionality.
+impl Sim {
+    /// Starts logging mutations and steps to a new replay log at the given
+    /// path. Log already in progress is closed first.
+    pub fn start_replay_log<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.stop_replay_log()?;
+        #[cfg(feature = "machine_lua")]
+        for entity in self.entities.values_mut() {
+            entity.insta.lua_state = None;
+        }
+        self.replay_log = Some(ReplayLog::create(path, self)?);
+        Ok(())
+    }
+
+    /// Closes the replay log, if any.
+    pub fn stop_replay_log(&mut self) -> Result<()> {
+        match self.replay_log.take() {
+            Some(mut log) => log.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Records a mutation that was applied to the simulation, if replay
+    /// logging is enabled.
+    pub fn log_mutation(&mut self, mutation: Mutation) -> Result<()> {
+        let clock = self.clock;
+        match &mut self.replay_log {
+            Some(log) => log.append(clock, &LogEntry::Mutation(mutation)),
+            None => Ok(()),
+        }
+    }
+
+    /// Applies the mutation, recording it if replay logging is enabled.
+    pub fn apply_mutation(&mut self, mutation: Mutation) -> Result<()> {
+        match &mutation {
+            Mutation::SetVar(addr, var) => {
+                self.validate_var(addr, var)?;
+                *self.get_var_mut(addr)? = var.clone();
+            }
+            Mutation::Spawn { prefab, name, id } => {
+                let spawned = self.spawn_entity(prefab.as_ref(), name.clone())?;
+                if spawned != *id {
+                    return Err(Error::Other(format!(
+                        "replay diverged: expected spawned entity id {}, got {}",
+                        id, spawned
+                    )));
+                }
+            }
+            Mutation::Despawn(id) => self.despawn_entity(id)?,
+            Mutation::AttachComponent(id, comp) => self.attach_component(id, comp)?,
+            Mutation::DetachComponent(id, comp) => self.detach_component(id, comp)?,
+            Mutation::Invoke(event) => {
+                if !self.event_queue.contains(event) {
+                    self.event_queue.push(event.clone());
+                }
+            }
+            Mutation::Model(model) => self.update_model(model.clone())?,
+            Mutation::ScheduleEvent {
+                event,
+                at_step,
+                every_n_steps,
+            } => self
+                .scheduler
+                .schedule(event.clone(), *at_step, *every_n_steps)?,
+            // compaction records itself
+            Mutation::CompactEntities => return self.compact_entities().map(|_| ()),
+        }
+        self.log_mutation(mutation)
+    }
+
+    /// Replays the run recorded in the replay log at the given path,
+    /// returning the simulation in the state it was in at the end of the
+    /// log.
+    pub fn replay_from<P: AsRef<Path>>(path: P) -> Result<Sim> {
+        Replay::open(path)?.run()
+    }
+}
+
+#[test]
+fn oversized_frame_length_is_rejected() {
+    let mut bytes = u64::MAX.to_le_bytes().to_vec();
+    bytes.extend_from_slice(&[0; 16]);
+    let result: Result<Option<(usize, LogEntry)>> = read_frame(&mut bytes.as_slice());
+    assert!(result.is_err());
+}
+
+#[test]
+fn replay_reproduces_recorded_run() {
+    use crate::sim::dump::DumpOptions;
+    use crate::string::new_truncate;
+    use std::str::FromStr;
+
+    let mut sim = crate::SimModelBuilder::new()
+        .component("pos", |c| {
+            c.var("float:x", Var::Float(0.)).bounds(Some(0.), Some(10.))
+        })
+        .prefab("dot", &["pos"])
+        .event("rain")
+        .spawn("dot", Some("first"))
+        .build_sim()
+        .unwrap();
+    sim.step().unwrap();
+
+    let path = std::env::temp_dir().join(format!("outcome_replay_{}.replay", std::process::id()));
+    sim.start_replay_log(&path).unwrap();
+    let addr = Address::from_str("first:pos:float:x").unwrap();
+    sim.apply_mutation(Mutation::SetVar(addr.clone(), Var::Float(2.5)))
+        .unwrap();
+    // out of bounds, rejected same as in a live run and not recorded
+    assert!(sim
+        .apply_mutation(Mutation::SetVar(addr.clone(), Var::Float(20.)))
+        .is_err());
+    sim.step().unwrap();
+    let id = sim
+        .spawn_entity(Some(&new_truncate("dot")), Some(new_truncate("second")))
+        .unwrap();
+    sim.log_mutation(Mutation::Spawn {
+        prefab: Some(new_truncate("dot")),
+        name: Some(new_truncate("second")),
+        id,
+    })
+    .unwrap();
+    sim.apply_mutation(Mutation::Invoke(new_truncate("rain")))
+        .unwrap();
+    sim.step().unwrap();
+    sim.step().unwrap();
+    sim.stop_replay_log().unwrap();
+
+    let replayed = Sim::replay_from(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(replayed.get_clock(), sim.get_clock());
+    assert_eq!(
+        replayed.dump_text(&DumpOptions::default()),
+        sim.dump_text(&DumpOptions::default())
+    );
+}
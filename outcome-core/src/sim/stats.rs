@@ -0,0 +1,235 @@
+//! Bookkeeping of simulation run statistics.
+//!
+//! [`RunStats`] is updated at the end of each processed step. Once the run
+//! is over, a [`RunSummary`] can be produced from it, for example to be
+//! printed out when quitting the program or saved to a file.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the step duration histogram buckets, in seconds.
+pub const STEP_DURATION_BUCKETS: [f64; 16] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1., 2.5, 5.,
+    10.,
+];
+
+/// Histogram of step durations, taking up the same amount of memory
+/// regardless of the number of recorded steps.
+#[derive(Debug, Clone, Default)]
+pub struct StepHistogram {
+    /// Number of steps falling into each of the buckets declared with
+    /// [`STEP_DURATION_BUCKETS`], the last one counting steps longer than
+    /// the highest bound
+    pub buckets: [u64; 17],
+    pub count: u64,
+    pub sum: Duration,
+    pub max: Duration,
+    /// Duration of the most recently recorded step
+    pub last: Duration,
+}
+
+impl StepHistogram {
+    pub fn record(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let bucket = STEP_DURATION_BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(STEP_DURATION_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += duration;
+        self.max = self.max.max(duration);
+        self.last = duration;
+    }
+
+    /// Approximates the percentile with the upper bound of the bucket it
+    /// falls into, capped at the longest recorded duration.
+    pub fn percentile(&self, p: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::default();
+        }
+        let rank = ((self.count as f64 * p).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (n, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return match STEP_DURATION_BUCKETS.get(n) {
+                    Some(bound) => Duration::from_secs_f64(*bound).min(self.max),
+                    None => self.max,
+                };
+            }
+        }
+        self.max
+    }
+}
+
+/// Statistics collected over the course of a single run.
+#[derive(Debug, Clone)]
+pub struct RunStats {
+    started: Instant,
+    /// Durations of the processed steps
+    pub step_durations: StepHistogram,
+    /// Entity count at the start of the run and after each step where
+    /// it changed, as `(clock, entity_count)` pairs
+    pub entity_counts: Vec<(usize, usize)>,
+    /// Highest storage memory estimate recorded, in bytes
+    pub peak_memory: usize,
+    /// Total number of logic errors recorded
+    pub error_count: usize,
+    /// Number of steps during which at least one logic error was recorded
+    pub steps_with_errors: usize,
+}
+
+impl Default for RunStats {
+    fn default() -> Self {
+        RunStats {
+            started: Instant::now(),
+            step_durations: StepHistogram::default(),
+            entity_counts: Vec::new(),
+            peak_memory: 0,
+            error_count: 0,
+            steps_with_errors: 0,
+        }
+    }
+}
+
+impl RunStats {
+    /// Records a single processed step.
+    pub fn record_step(
+        &mut self,
+        clock: usize,
+        duration: Duration,
+        entity_count: usize,
+        memory: usize,
+        errors: usize,
+    ) {
+        self.step_durations.record(duration);
+        if self.entity_counts.last().map(|(_, c)| *c) != Some(entity_count) {
+            self.entity_counts.push((clock, entity_count));
+        }
+        self.peak_memory = self.peak_memory.max(memory);
+        self.error_count += errors;
+        if errors > 0 {
+            self.steps_with_errors += 1;
+        }
+    }
+
+    /// Time elapsed since the stats were created.
+    pub fn wall_time(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Creates a summary of the run so far.
+    ///
+    /// Step duration percentiles are approximated, see
+    /// [`StepHistogram::percentile`].
+    pub fn summary(&self) -> RunSummary {
+        let durations = &self.step_durations;
+        let ms = |d: Duration| d.as_secs_f64() * 1000.;
+        RunSummary {
+            steps: durations.count as usize,
+            wall_time_secs: self.wall_time().as_secs_f64(),
+            step_avg_ms: match durations.count {
+                0 => 0.,
+                n => ms(durations.sum) / n as f64,
+            },
+            step_p50_ms: ms(durations.percentile(0.5)),
+            step_p90_ms: ms(durations.percentile(0.9)),
+            step_p99_ms: ms(durations.percentile(0.99)),
+            step_max_ms: ms(durations.max),
+            peak_memory: self.peak_memory,
+            entity_counts: self.entity_counts.clone(),
+            error_count: self.error_count,
+            steps_with_errors: self.steps_with_errors,
+        }
+    }
+}
+
+/// Summary report of a simulation run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunSummary {
+    /// Number of steps executed
+    pub steps: usize,
+    /// Wall time of the whole run, in seconds
+    pub wall_time_secs: f64,
+    pub step_avg_ms: f64,
+    pub step_p50_ms: f64,
+    pub step_p90_ms: f64,
+    pub step_p99_ms: f64,
+    pub step_max_ms: f64,
+    /// Peak storage memory estimate, in bytes
+    pub peak_memory: usize,
+    /// Entity count trajectory as `(clock, entity_count)` pairs, only
+    /// including the steps where the count changed
+    pub entity_counts: Vec<(usize, usize)>,
+    /// Total number of logic errors
+    pub error_count: usize,
+    pub steps_with_errors: usize,
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Run summary:")?;
+        writeln!(f, "  steps executed: {}", self.steps)?;
+        writeln!(f, "  wall time: {:.3}s", self.wall_time_secs)?;
+        writeln!(
+            f,
+            "  step duration: avg {:.3}ms, p50 {:.3}ms, p90 {:.3}ms, p99 {:.3}ms, max {:.3}ms",
+            self.step_avg_ms,
+            self.step_p50_ms,
+            self.step_p90_ms,
+            self.step_p99_ms,
+            self.step_max_ms
+        )?;
+        writeln!(f, "  peak memory (estimate): {} bytes", self.peak_memory)?;
+        let trajectory = self
+            .entity_counts
+            .iter()
+            .map(|(clock, count)| format!("{}@{}", count, clock))
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        writeln!(f, "  entity count: {}", trajectory)?;
+        write!(
+            f,
+            "  errors: {} (in {} steps)",
+            self.error_count, self.steps_with_errors
+        )
+    }
+}
+
+#[test]
+fn step_histogram_percentiles() {
+    let mut histogram = StepHistogram::default();
+    for _ in 0..90 {
+        histogram.record(Duration::from_micros(800));
+    }
+    for _ in 0..10 {
+        histogram.record(Duration::from_millis(20));
+    }
+    assert_eq!(histogram.count, 100);
+    assert_eq!(histogram.percentile(0.5), Duration::from_millis(1));
+    assert_eq!(histogram.percentile(0.99), Duration::from_millis(20));
+    assert_eq!(histogram.max, Duration::from_millis(20));
+}
+
+#[test]
+fn run_summary_tracks_entity_counts_and_errors() {
+    let mut stats = RunStats::default();
+    let step = Duration::from_millis(2);
+    stats.record_step(1, step, 10, 1000, 0);
+    stats.record_step(2, step, 10, 3000, 2);
+    stats.record_step(3, step, 12, 2000, 1);
+
+    let summary = stats.summary();
+    assert_eq!(summary.steps, 3);
+    assert_eq!(summary.entity_counts, vec![(1, 10), (3, 12)]);
+    assert_eq!(summary.peak_memory, 3000);
+    assert_eq!(summary.error_count, 3);
+    assert_eq!(summary.steps_with_errors, 2);
+    assert!((summary.step_avg_ms - 2.).abs() < 1e-9);
+
+    let printed = summary.to_string();
+    assert!(printed.contains("steps executed: 3"));
+    assert!(printed.contains("entity count: 10@1 -> 12@3"));
+    assert!(printed.ends_with("errors: 3 (in 2 steps)"));
+}
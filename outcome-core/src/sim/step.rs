@@ -1,14 +1,40 @@
 //! Step processing functions for the `Sim` struct.
 
+#[cfg(feature = "machine")]
+use std::any::Any;
+#[cfg(feature = "machine")]
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
 
 use crate::entity::Entity;
 use crate::error::Error;
-use crate::{string, EntityId, EntityName, SimModel, StringId};
+use crate::rng::EntityRng;
+use crate::{hazard, string, CompName, EntityId, EntityName, SimModel, StringId};
 
+#[cfg(feature = "machine")]
+use crate::machine::behavior::TickContext;
+#[cfg(feature = "machine")]
+use crate::machine::budget::StepBudget;
+#[cfg(feature = "machine")]
+use crate::machine::system::SystemSchedule;
+#[cfg(feature = "machine")]
+use crate::machine::watchdog::{Progress, Watchdog};
+#[cfg(feature = "machine")]
+use crate::machine::Error as MachineError;
 #[cfg(feature = "machine")]
 use crate::machine::{cmd::CentralRemoteCommand, cmd::ExtCommand, exec, ExecutionContext};
 #[cfg(feature = "machine")]
+use crate::machine::{ErrorKind as MachineErrorKind, LocationInfo, DISABLED_STATE_NAME};
+#[cfg(feature = "machine")]
+use crate::scheduler::EventScheduler;
+#[cfg(feature = "machine")]
+use crate::{order, EventName, Float, Var, VarType};
+#[cfg(feature = "machine")]
+use id_pool::IdPool;
+#[cfg(all(feature = "machine", feature = "parallel"))]
 use rayon::prelude::*;
 
 #[cfg(feature = "machine_dynlib")]
@@ -18,7 +44,14 @@ use libloading::Library;
 
 use std::collections::BTreeMap;
 
-use super::Sim;
+#[cfg(feature = "machine")]
+use super::removals::Removals;
+use super::{replay, Sim};
+
+/// Name of the float var set to the sub-step duration for components
+/// running multiple sub-steps per step.
+#[cfg(feature = "machine")]
+pub const SUBSTEP_DT_VAR_NAME: &str = "dt";
 
 /// Single step processing functions.
 impl Sim {
@@ -26,17 +59,47 @@ impl Sim {
     ///
     /// # Process description
     ///
+    /// Scenario hazards are sampled first, adding events of the hazards
+    /// that fired to the event queue.
+    ///
     /// This function uses a parallel iterator to iterate over all entities.
     /// Each entity-owning thread then makes a list of components to process
     /// using entity's component queue to find matches based on the triggered
-    /// events.
+    /// events. Without the `parallel` feature entities are iterated over
+    /// serially instead.
     ///
     /// For each processed component, current state value is found.
     /// Logic processing utility function is used to process component
-    /// commands. Once parallel iteration over entities is done, last thing
-    /// to do is executing external and central-external commands that have
-    /// been accumulated during parallel iteration stage.
+    /// commands. Components marked as serial in the model are skipped, and
+    /// processed afterwards one entity at a time, in the order of entity
+    /// ids. Once iteration over entities is done, systems
+    /// triggered by any of the events are run over their matched entities,
+    /// in the order given by the system schedule.
+    /// Last thing to do is executing external and central-external commands
+    /// that have been accumulated during parallel iteration stage. Commands
+    /// are executed in the order given by the ordering policies of the
+    /// events that triggered them, see the [`order`] module.
+    ///
+    /// [`order`]: crate::order
+    ///
+    /// Logic errors occurring during the step are recorded into the error
+    /// journal, which is cleared at the beginning of each step. If the step
+    /// fails, the events it was processing are put back into the event
+    /// queue, so that the step can be retried.
+    ///
+    /// # Strict mode
+    ///
+    /// With strict mode enabled the first logic error aborts the step and
+    /// is returned to the caller. Errors are ordered by entity id, so the
+    /// error returned doesn't depend on thread scheduling. The clock is not
+    /// advanced and changes made by the step are rolled back, including
+    /// external commands executed before the failing one, so retrying the
+    /// step doesn't apply them twice. The error journal is kept.
+    ///
+    /// Only successful steps are recorded into the replay log.
     pub fn step(&mut self) -> Result<(), Error> {
+        let step_start = Instant::now();
+
         // clone event queue into a local variable
         let mut event_queue = self.event_queue.clone();
 
@@ -45,39 +108,48 @@ impl Sim {
             event_queue.push(arrstr_step.clone());
         }
         self.event_queue.clear();
+        // entities despawned while processing the step get their despawn
+        // event fired during the next one
+        let despawned = self.despawned.len();
+
+        let manifest = &self.model.scenario.manifest;
+        let hazard_events = hazard::sample_hazards(
+            &manifest.hazards,
+            manifest.seed,
+            self.clock,
+            |addr| match self.get_var(addr) {
+                Ok(var) => Some(var.to_float() as f64),
+                Err(e) => {
+                    warn!("failed reading hazard modulation var {}: {}", addr, e);
+                    None
+                }
+            },
+        );
+        for event in hazard_events {
+            if !event_queue.contains(&event) {
+                event_queue.push(event);
+            }
+        }
+        for event in self.scheduler.take_due(self.clock) {
+            if !event_queue.contains(&event) {
+                event_queue.push(event);
+            }
+        }
 
         #[cfg(feature = "machine")]
         {
-            let model = &self.model;
-
-            #[cfg(feature = "machine_dynlib")]
-            let libs = &self.libs;
-
-            // declare atomic vecs for ext and central-ext commands
-            let ext_cmds: Arc<Mutex<Vec<(ExecutionContext, ExtCommand)>>> =
-                Arc::new(Mutex::new(Vec::new()));
-            let central_ext_cmds: Arc<Mutex<Vec<(ExecutionContext, CentralRemoteCommand)>>> =
-                Arc::new(Mutex::new(Vec::new()));
-
-            // loc phase
-            self.entities.par_iter_mut().for_each(
-                |(ent_uid, mut entity): (&EntityId, &mut Entity)| {
-                    step_entity_local(
-                        model,
-                        &event_queue,
-                        ent_uid,
-                        entity,
-                        &ext_cmds,
-                        &central_ext_cmds,
-                        #[cfg(feature = "machine_dynlib")]
-                        libs,
-                    );
-                },
-            );
-
-            // post phase
-            exec::execute_ext(&ext_cmds.lock().unwrap(), self)?;
-            exec::execute_central_ext(&central_ext_cmds.lock().unwrap(), self)?;
+            let rollback = match self.strict {
+                true => Some(StepRollback::new(self)),
+                false => None,
+            };
+            if let Err(e) = self.process_logic(&event_queue) {
+                if let Some(rollback) = rollback {
+                    rollback.restore(self);
+                }
+                // keep the events so that the step can be retried
+                self.event_queue = event_queue;
+                return Err(e);
+            }
         }
 
         // let arrstr_step = StringId::from_unchecked("step");
@@ -87,12 +159,183 @@ impl Sim {
         // self.event_queue.clear();
         // self.event_queue = event_queue;
 
+        if let Some(log) = &mut self.replay_log {
+            log.append(self.clock, &replay::LogEntry::Step)?;
+            log.flush()?;
+        }
+
+        for entity in self.entities.values_mut() {
+            entity.storage.stamp_changes(self.clock);
+        }
         self.clock += 1;
 
         if !self.event_queue.contains(&arrstr_step) {
             self.event_queue.push(arrstr_step);
         }
 
+        self.despawned.drain(..despawned);
+
+        self.update_engine_stats(step_start.elapsed())?;
+        self.removals.prune(self.clock);
+
+        // failing recorder doesn't fail the step
+        #[cfg(feature = "recorder")]
+        if let Some(mut recorder) = self.recorder.take() {
+            if let Err(e) = recorder.sample(self) {
+                error!("recording failed, recorder disabled: {}", e);
+                recorder.recording = false;
+            }
+            self.recorder = Some(recorder);
+        }
+
+        Ok(())
+    }
+
+    /// Runs the component logic and systems triggered by the events,
+    /// then executes the resulting external commands.
+    #[cfg(feature = "machine")]
+    fn process_logic(&mut self, event_queue: &Vec<EventName>) -> Result<(), Error> {
+        self.error_journal.clear();
+
+        let model = &self.model;
+
+        #[cfg(feature = "machine_dynlib")]
+        let libs = &self.libs;
+
+        // declare atomic vecs for ext and central-ext commands
+        let ext_cmds: EventCommands<ExtCommand> = Arc::new(Mutex::new(Vec::new()));
+        let central_ext_cmds: EventCommands<CentralRemoteCommand> =
+            Arc::new(Mutex::new(Vec::new()));
+        let errors: Arc<Mutex<Vec<(ExecutionContext, MachineError)>>> =
+            Arc::new(Mutex::new(Vec::new()));
+
+        let progress = Arc::new(Progress::default());
+        let budget = match &self.watchdog {
+            Some(_) => StepBudget::with_progress(&model.scenario.manifest.budget, &progress),
+            None => StepBudget::new(&model.scenario.manifest.budget),
+        };
+        if let Some(config) = self.watchdog.clone() {
+            self.watchdog_thread
+                .get_or_insert_with(Watchdog::spawn)
+                .arm(config, progress.clone(), self.clock);
+        }
+        let strict = self.strict;
+
+        // loc phase, order-sensitive components are left for the
+        // serial pass
+        let has_serial = model.components.iter().any(|c| c.logic.serial);
+        let step_entity = |(ent_uid, entity): (&EntityId, &mut Entity),
+                           serial: Option<bool>,
+                           exec_times: &mut ExecTimes| {
+            step_entity_local(
+                model,
+                &event_queue,
+                ent_uid,
+                entity,
+                &ext_cmds,
+                &central_ext_cmds,
+                &errors,
+                exec_times,
+                &budget,
+                strict,
+                serial,
+                #[cfg(feature = "machine_dynlib")]
+                libs,
+            )
+        };
+        let parallel_pass = match has_serial {
+            true => Some(false),
+            false => None,
+        };
+        let entities = &mut self.entities;
+        let local_result = (|| -> Result<ExecTimes, Error> {
+            // execution times are collected separately by each thread,
+            // the error of the lowest entity id is returned regardless of
+            // which thread got to it first
+            #[cfg(feature = "parallel")]
+            let mut exec_times = {
+                let failures = Mutex::new(Vec::new());
+                let exec_times = entities
+                    .par_iter_mut()
+                    .fold(ExecTimes::default, |mut times, entry| {
+                        let ent_uid = *entry.0;
+                        if let Err(e) = step_entity(entry, parallel_pass, &mut times) {
+                            failures.lock().unwrap().push((ent_uid, e));
+                        }
+                        times
+                    })
+                    .reduce(ExecTimes::default, merge_exec_times);
+                let failures = failures.into_inner().unwrap();
+                if let Some((_, e)) = failures.into_iter().min_by_key(|(ent_uid, _)| *ent_uid) {
+                    return Err(e);
+                }
+                exec_times
+            };
+            #[cfg(not(feature = "parallel"))]
+            let mut exec_times = ExecTimes::default();
+            #[cfg(not(feature = "parallel"))]
+            {
+                let mut entities = entities.iter_mut().collect::<Vec<_>>();
+                entities.sort_unstable_by_key(|(ent_uid, _)| **ent_uid);
+                for entry in entities {
+                    step_entity(entry, parallel_pass, &mut exec_times)?;
+                }
+            }
+            if has_serial {
+                let mut entities = entities.iter_mut().collect::<Vec<_>>();
+                entities.sort_unstable_by_key(|(ent_uid, _)| **ent_uid);
+                for entry in entities {
+                    step_entity(entry, Some(true), &mut exec_times)?;
+                }
+            }
+            Ok(exec_times)
+        })();
+        if let Some(watchdog) = &self.watchdog_thread {
+            watchdog.disarm();
+        }
+
+        // errors are journaled in the order of entity ids, so that the
+        // first one doesn't depend on thread scheduling
+        let mut entity_errors = std::mem::take(&mut *errors.lock().unwrap());
+        entity_errors.sort_by_key(|(ctx, _)| ctx.ent);
+        self.error_journal.extend(entity_errors);
+        let exec_times = local_result?;
+
+        // systems phase, the schedule is only rebuilt after model changes
+        if self.system_schedule.is_none() {
+            self.system_schedule = Some(SystemSchedule::new(&model.systems)?);
+        }
+        let schedule = self.system_schedule.as_ref().unwrap();
+        let system_errors = schedule.run(&model.systems, &event_queue, &mut self.entities)?;
+        self.error_journal.extend(system_errors);
+
+        self.exec_times = merge_exec_times(std::mem::take(&mut self.exec_times), exec_times);
+        if strict {
+            if let Some((_, e)) = self.error_journal.first().cloned() {
+                return Err(Error::MachinePanic(e));
+            }
+        }
+
+        // post phase, executing commands in the order given by the
+        // ordering policies of the events
+        let mut entities = self.entities.keys().copied().collect::<Vec<_>>();
+        entities.sort_unstable();
+        let ext_cmds = order_commands(
+            std::mem::take(&mut *ext_cmds.lock().unwrap()),
+            &self.model,
+            &event_queue,
+            &entities,
+            self.clock,
+        );
+        let central_ext_cmds = order_commands(
+            std::mem::take(&mut *central_ext_cmds.lock().unwrap()),
+            &self.model,
+            &event_queue,
+            &entities,
+            self.clock,
+        );
+        exec::execute_ext(&ext_cmds, self)?;
+        exec::execute_central_ext(&central_ext_cmds, self)?;
         Ok(())
     }
 }
@@ -103,48 +346,247 @@ pub(crate) fn step_entity_local(
     event_queue: &Vec<StringId>,
     ent_uid: &EntityId,
     mut entity: &mut Entity,
-    ext_cmds: &Arc<Mutex<Vec<(ExecutionContext, ExtCommand)>>>,
-    central_ext_cmds: &Arc<Mutex<Vec<(ExecutionContext, CentralRemoteCommand)>>>,
+    ext_cmds: &EventCommands<ExtCommand>,
+    central_ext_cmds: &EventCommands<CentralRemoteCommand>,
+    errors: &Arc<Mutex<Vec<(ExecutionContext, MachineError)>>>,
+    exec_times: &mut ExecTimes,
+    step_budget: &StepBudget,
+    strict: bool,
+    serial: Option<bool>,
     #[cfg(feature = "machine_dynlib")] libs: &Libraries,
 ) -> Result<(), Error> {
     trace!(
         "step_entity_local(): entity.comp_queue: {:?}",
         entity.comp_queue
     );
-    for event in event_queue {
+    let mut budget = step_budget.entity(*ent_uid);
+    // entity events are kept around for the serial pass
+    let entity_events = match serial {
+        Some(false) => entity.event_queue.clone(),
+        _ => std::mem::take(&mut entity.event_queue),
+    };
+    let events = event_queue
+        .iter()
+        .chain(entity_events.iter().filter(|e| !event_queue.contains(e)));
+
+    // commands are collected separately for each event, so that they can
+    // be tagged with the event before being moved to the shared collections
+    let event_ext_cmds = Arc::new(Mutex::new(Vec::new()));
+    let event_central_ext_cmds = Arc::new(Mutex::new(Vec::new()));
+    let flush = |event: &EventName| {
+        ext_cmds.lock().unwrap().extend(
+            event_ext_cmds
+                .lock()
+                .unwrap()
+                .drain(..)
+                .map(|(ctx, cmd)| (event.clone(), ctx, cmd)),
+        );
+        central_ext_cmds.lock().unwrap().extend(
+            event_central_ext_cmds
+                .lock()
+                .unwrap()
+                .drain(..)
+                .map(|(ctx, cmd)| (event.clone(), ctx, cmd)),
+        );
+    };
+    let ext_cmds = &event_ext_cmds;
+    let central_ext_cmds = &event_central_ext_cmds;
+
+    for event in events {
         if let Some(event_comp_queue) = entity.comp_queue.get(event) {
             // debug!("event_queue: {:?}", event_queue);
             for comp_uid in event_comp_queue {
-                if let Some(comp_state) = entity.comp_state.get_mut(comp_uid) {
+                let comp_model = match model.get_component(comp_uid) {
+                    Ok(comp_model) => comp_model,
+                    Err(_) => continue,
+                };
+                if serial.map_or(false, |serial| serial != comp_model.logic.serial) {
+                    continue;
+                }
+                trace!("comp_model: {:?}", comp_model);
+                let substeps = comp_model.logic.substeps.max(1);
+                let dt_index = (comp_uid.clone(), string::new_truncate(SUBSTEP_DT_VAR_NAME));
+                let declares_dt = comp_model
+                    .vars
+                    .iter()
+                    .any(|v| v.name == dt_index.1 && v.type_ == VarType::Float);
+                let substep_dt = model.scenario.manifest.dt() / substeps as Float;
+                // value of the declared var, restored once the sub-steps
+                // are done
+                let declared_dt = match declares_dt {
+                    true => entity.storage.get_var(&dt_index).ok().cloned(),
+                    false => None,
+                };
+                for _ in 0..substeps {
+                    // entity execution is suspended until next step
+                    if budget.is_exceeded() {
+                        if let Some(reason) = budget.take_skip_reason() {
+                            warn!(
+                                "entity {}: skipping component {}: {}",
+                                ent_uid, comp_uid, reason
+                            );
+                            central_ext_cmds.lock().unwrap().push((
+                                ExecutionContext {
+                                    ent: *ent_uid,
+                                    comp: comp_uid.clone(),
+                                    location: LocationInfo::empty(),
+                                },
+                                CentralRemoteCommand::Invoke(crate::machine::cmd::Invoke {
+                                    events: vec![string::new_truncate(
+                                        crate::DEFAULT_BUDGET_EXCEEDED_EVENT,
+                                    )],
+                                }),
+                            ));
+                        }
+                        if let Some(dt) = declared_dt {
+                            entity.storage.insert(dt_index, dt);
+                        }
+                        flush(event);
+                        return Ok(());
+                    }
+                    let comp_state = match entity.comp_state.get_mut(comp_uid) {
+                        Some(comp_state) => comp_state,
+                        None => break,
+                    };
                     debug!("comp_state: {}", comp_state);
-                    // let comp_curr_state = &comp.current_state;
-                    if comp_state.as_str() == "idle" {
-                        continue;
+                    if comp_state.as_str() == "idle" || comp_state.as_str() == DISABLED_STATE_NAME {
+                        break;
                     }
-                    if let Ok(comp_model) = model.get_component(comp_uid) {
-                        trace!("comp_model: {:?}", comp_model);
-                        let (start, end) = match comp_model.logic.states.get(comp_state) {
-                            Some((s, e)) => (Some(*s), Some(*e)),
-                            None => continue,
-                        };
-                        crate::machine::exec::execute_loc(
-                            &comp_model.logic.commands,
-                            &comp_model.logic.cmd_location_map,
-                            &mut entity.storage,
-                            &mut entity.insta,
-                            comp_state,
-                            //TODO
-                            ent_uid,
-                            &comp_uid,
-                            &model,
-                            &ext_cmds,
-                            &central_ext_cmds,
-                            start,
-                            end,
-                            #[cfg(feature = "machine_dynlib")]
-                            libs,
-                        )?;
+                    if declares_dt {
+                        entity
+                            .storage
+                            .insert(dt_index.clone(), Var::Float(substep_dt));
                     }
+                    let exec_start = Instant::now();
+                    budget.enter(comp_uid);
+                    // panics, e.g. within dynlib calls, are contained to the
+                    // component, which gets disabled
+                    let storage = &mut entity.storage;
+                    let insta = &mut entity.insta;
+                    let seed = model.scenario.manifest.seed;
+                    let rng = entity
+                        .rng
+                        .get_or_insert_with(|| EntityRng::new(seed, *ent_uid));
+                    let result =
+                        panic::catch_unwind(AssertUnwindSafe(|| -> Result<bool, Error> {
+                            if comp_model.logic.behavior.is_some()
+                                || comp_model.logic.graph.is_some()
+                            {
+                                let mut ctx = TickContext {
+                                    logic: &comp_model.logic,
+                                    storage,
+                                    insta,
+                                    rng,
+                                    comp_state,
+                                    ent_uid,
+                                    comp_uid,
+                                    model,
+                                    ext_cmds,
+                                    central_ext_cmds,
+                                    errors,
+                                    budget: &mut budget,
+                                    strict,
+                                    #[cfg(feature = "machine_dynlib")]
+                                    libs,
+                                };
+                                if let Some(behavior) = &comp_model.logic.behavior {
+                                    ctx.tick(behavior)?;
+                                }
+                                if let Some(graph) = &comp_model.logic.graph {
+                                    ctx.evaluate(graph)?;
+                                }
+                            } else {
+                                let (start, end) = match comp_model.logic.states.get(comp_state) {
+                                    Some((s, e)) => (Some(*s), Some(*e)),
+                                    None => return Ok(false),
+                                };
+                                crate::machine::exec::execute_loc(
+                                    &comp_model.logic.commands,
+                                    &comp_model.logic.cmd_location_map,
+                                    storage,
+                                    insta,
+                                    rng,
+                                    comp_state,
+                                    //TODO
+                                    ent_uid,
+                                    &comp_uid,
+                                    &model,
+                                    &ext_cmds,
+                                    &central_ext_cmds,
+                                    &errors,
+                                    &mut budget,
+                                    strict,
+                                    start,
+                                    end,
+                                    #[cfg(feature = "machine_dynlib")]
+                                    libs,
+                                )?;
+                            }
+                            Ok(true)
+                        }));
+                    match result {
+                        Ok(Ok(true)) => (),
+                        Ok(Ok(false)) => break,
+                        Ok(Err(e)) if strict => {
+                            if let Some(dt) = declared_dt {
+                                entity.storage.insert(dt_index, dt);
+                            }
+                            return Err(e);
+                        }
+                        // errors not recorded by command execution, e.g.
+                        // errors of graph nodes, end the component's run
+                        Ok(Err(e)) => {
+                            error!("component {} of entity {}: {}", comp_uid, ent_uid, e);
+                            if let Error::MachinePanic(e) = e {
+                                errors.lock().unwrap().push((
+                                    ExecutionContext {
+                                        ent: *ent_uid,
+                                        comp: comp_uid.clone(),
+                                        location: LocationInfo::empty(),
+                                    },
+                                    e,
+                                ));
+                            }
+                            break;
+                        }
+                        Err(payload) => {
+                            let msg = panic_message(&payload);
+                            error!(
+                                "component {} of entity {} panicked, disabling: {}",
+                                comp_uid, ent_uid, msg
+                            );
+                            errors.lock().unwrap().push((
+                                ExecutionContext {
+                                    ent: *ent_uid,
+                                    comp: comp_uid.clone(),
+                                    location: LocationInfo::empty(),
+                                },
+                                MachineError::new(
+                                    LocationInfo::empty(),
+                                    MachineErrorKind::CaughtPanic(msg),
+                                ),
+                            ));
+                            if let Some(comp_state) = entity.comp_state.get_mut(comp_uid) {
+                                *comp_state = string::new_truncate(DISABLED_STATE_NAME);
+                            }
+                            break;
+                        }
+                    }
+                    if budget.is_aborted() {
+                        warn!(
+                            "component {} of entity {} aborted by watchdog, disabling",
+                            comp_uid, ent_uid
+                        );
+                        if let Some(comp_state) = entity.comp_state.get_mut(comp_uid) {
+                            *comp_state = string::new_truncate(DISABLED_STATE_NAME);
+                        }
+                    }
+                    let entry = exec_times.entry(comp_uid.clone()).or_default();
+                    entry.0 += exec_start.elapsed();
+                    entry.1 += 1;
+                }
+                if let Some(dt) = declared_dt {
+                    entity.storage.insert(dt_index, dt);
                 }
             }
         } else {
@@ -191,7 +633,430 @@ pub(crate) fn step_entity_local(
         //         .unwrap();
         //     entity.components.queue.get_mut(event).unwrap().remove(n);
         // }
+
+        flush(event);
     }
 
     Ok(())
 }
+
+/// State changed by the logic of a step, taken before the logic runs in
+/// strict mode so that an aborted step can be undone.
+#[cfg(feature = "machine")]
+struct StepRollback {
+    model: SimModel,
+    entities: FnvHashMap<EntityId, Entity>,
+    entity_idx: FnvHashMap<EntityName, EntityId>,
+    entity_pool: IdPool,
+    scheduler: EventScheduler,
+    removals: Removals,
+    despawned: Vec<EntityId>,
+}
+
+#[cfg(feature = "machine")]
+impl StepRollback {
+    fn new(sim: &Sim) -> Self {
+        Self {
+            model: sim.model.clone(),
+            entities: sim.entities.clone(),
+            entity_idx: sim.entity_idx.clone(),
+            entity_pool: sim.entity_pool.clone(),
+            scheduler: sim.scheduler.clone(),
+            removals: sim.removals.clone(),
+            despawned: sim.despawned.clone(),
+        }
+    }
+
+    /// Puts the sim back into the state from before the step. Errors
+    /// journaled during the step are kept.
+    fn restore(self, sim: &mut Sim) {
+        sim.model = self.model;
+        sim.entities = self.entities;
+        sim.entity_idx = self.entity_idx;
+        sim.entity_pool = self.entity_pool;
+        sim.scheduler = self.scheduler;
+        sim.removals = self.removals;
+        sim.despawned = self.despawned;
+        // external commands may have changed the model
+        sim.system_schedule = None;
+    }
+}
+
+/// Collection of commands tagged with the events that triggered them.
+#[cfg(feature = "machine")]
+pub(crate) type EventCommands<C> = Arc<Mutex<Vec<(EventName, ExecutionContext, C)>>>;
+
+/// Accumulated machine execution time and number of executions for each
+/// component.
+#[cfg(feature = "machine")]
+pub(crate) type ExecTimes = FnvHashMap<CompName, (Duration, u64)>;
+
+/// Adds up execution times collected separately.
+#[cfg(feature = "machine")]
+fn merge_exec_times(mut times: ExecTimes, other: ExecTimes) -> ExecTimes {
+    for (comp, (time, count)) in other {
+        let entry = times.entry(comp).or_default();
+        entry.0 += time;
+        entry.1 += count;
+    }
+    times
+}
+
+/// Extracts the message from a caught panic payload.
+#[cfg(feature = "machine")]
+fn panic_message(payload: &Box<dyn Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Sorts tagged commands into execution order using the ordering policies
+/// of the triggering events, see the [`order`] module.
+///
+/// [`order`]: crate::order
+#[cfg(feature = "machine")]
+pub(crate) fn order_commands<C>(
+    cmds: Vec<(EventName, ExecutionContext, C)>,
+    model: &SimModel,
+    event_queue: &[EventName],
+    entities: &[EntityId],
+    clock: usize,
+) -> Vec<(ExecutionContext, C)> {
+    let cmds = cmds
+        .into_iter()
+        .map(|(event, ctx, cmd)| (event, ctx.ent, (ctx, cmd)))
+        .collect();
+    order::sort_commands(
+        cmds,
+        event_queue,
+        |event| model.get_event(event).map(|e| e.order).unwrap_or_default(),
+        entities,
+        model.scenario.manifest.seed,
+        clock,
+    )
+}
+
+#[cfg(feature = "machine")]
+#[test]
+fn logic_runs_once_per_substep() {
+    use std::str::FromStr;
+
+    use crate::address::Address;
+    use crate::machine::graph::{GraphNode, LogicGraph, NodeOp};
+
+    let node = |id: &str, op, inputs: &[&str]| GraphNode {
+        id: id.to_string(),
+        op,
+        inputs: inputs.iter().map(|i| i.to_string()).collect(),
+    };
+    // counts runs and accumulates the sub-step duration
+    let mut graph = LogicGraph {
+        nodes: vec![
+            node("count", NodeOp::Get("int:count".to_string()), &[]),
+            node("one", NodeOp::Value(Var::Int(1)), &[]),
+            node("next_count", NodeOp::Add, &["count", "one"]),
+            node(
+                "set_count",
+                NodeOp::Set("int:count".to_string()),
+                &["next_count"],
+            ),
+            node("dt", NodeOp::Get("float:dt".to_string()), &[]),
+            node("elapsed", NodeOp::Get("float:elapsed".to_string()), &[]),
+            node("next_elapsed", NodeOp::Add, &["elapsed", "dt"]),
+            node(
+                "set_elapsed",
+                NodeOp::Set("float:elapsed".to_string()),
+                &["next_elapsed"],
+            ),
+        ],
+        ..Default::default()
+    };
+    graph.prepare().unwrap();
+
+    let mut model = crate::SimModelBuilder::new()
+        .dt(2.)
+        .component("clock", |c| {
+            c.var("int:count", Var::Int(0))
+                .var("float:elapsed", Var::Float(0.))
+                .var("float:dt", Var::Float(-1.))
+                .trigger(crate::DEFAULT_STEP_EVENT)
+                .substeps(4)
+        })
+        .prefab("timer", &["clock"])
+        .build()
+        .unwrap();
+    model
+        .get_component_mut(&string::new_truncate("clock"))
+        .unwrap()
+        .logic
+        .graph = Some(graph);
+    let mut sim = Sim::from_model(model).unwrap();
+    sim.spawn_entity(
+        Some(&string::new_truncate("timer")),
+        Some(string::new_truncate("timer")),
+    )
+    .unwrap();
+    sim.step().unwrap();
+
+    let get = |addr: &str| {
+        sim.get_var(&Address::from_str(addr).unwrap())
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(get("timer:clock:int:count"), Var::Int(4));
+    assert_eq!(get("timer:clock:float:elapsed"), Var::Float(2.));
+    // declared value is restored once the sub-steps are done
+    assert_eq!(get("timer:clock:float:dt"), Var::Float(-1.));
+}
+
+#[cfg(feature = "machine")]
+#[test]
+fn system_schedule_is_kept_until_model_changes() {
+    use crate::machine::system::SystemModel;
+
+    let mut sim = crate::SimModelBuilder::new().build_sim().unwrap();
+    assert!(sim.system_schedule.is_none());
+    sim.step().unwrap();
+    assert!(sim.system_schedule.as_ref().unwrap().stages.is_empty());
+
+    let mut model = sim.model.clone();
+    model.systems.push(SystemModel {
+        name: string::new_truncate("noop"),
+        triggers: vec![string::new_truncate(crate::DEFAULT_STEP_EVENT)],
+        ..Default::default()
+    });
+    sim.update_model(model).unwrap();
+    assert!(sim.system_schedule.is_none());
+    sim.step().unwrap();
+    assert_eq!(sim.system_schedule.as_ref().unwrap().stages, vec![vec![0]]);
+}
+
+#[cfg(feature = "machine")]
+#[test]
+fn aborted_strict_step_is_rolled_back() {
+    use std::str::FromStr;
+
+    use crate::address::Address;
+    use crate::machine::graph::{GraphNode, LogicGraph, NodeOp};
+
+    let node = |id: &str, op, inputs: &[&str]| GraphNode {
+        id: id.to_string(),
+        op,
+        inputs: inputs.iter().map(|i| i.to_string()).collect(),
+    };
+    // increments the counter, then fails dividing by zero
+    let mut graph = LogicGraph {
+        nodes: vec![
+            node("count", NodeOp::Get("int:count".to_string()), &[]),
+            node("one", NodeOp::Value(Var::Int(1)), &[]),
+            node("zero", NodeOp::Value(Var::Int(0)), &[]),
+            node("next_count", NodeOp::Add, &["count", "one"]),
+            node(
+                "set_count",
+                NodeOp::Set("int:count".to_string()),
+                &["next_count"],
+            ),
+            node("fail", NodeOp::Div, &["set_count", "zero"]),
+        ],
+        ..Default::default()
+    };
+    graph.prepare().unwrap();
+
+    let mut model = crate::SimModelBuilder::new()
+        .component("counter", |c| {
+            c.var("int:count", Var::Int(0))
+                .trigger(crate::DEFAULT_STEP_EVENT)
+        })
+        .prefab("counter", &["counter"])
+        .build()
+        .unwrap();
+    model
+        .get_component_mut(&string::new_truncate("counter"))
+        .unwrap()
+        .logic
+        .graph = Some(graph);
+    let mut sim = Sim::from_model(model).unwrap();
+    sim.spawn_entity(
+        Some(&string::new_truncate("counter")),
+        Some(string::new_truncate("counter")),
+    )
+    .unwrap();
+    let count = |sim: &Sim| {
+        sim.get_var(&Address::from_str("counter:counter:int:count").unwrap())
+            .unwrap()
+            .clone()
+    };
+
+    sim.strict = true;
+    for _ in 0..2 {
+        assert!(sim.step().is_err());
+        assert_eq!(count(&sim), Var::Int(0));
+        assert_eq!(sim.get_clock(), 0);
+    }
+
+    // without strict mode the error is journaled and the change is kept
+    sim.strict = false;
+    sim.step().unwrap();
+    assert_eq!(count(&sim), Var::Int(1));
+    assert_eq!(sim.error_journal.len(), 1);
+    assert_eq!(sim.get_clock(), 1);
+}
+
+/// Sim with entities carrying a counting component and a component whose
+/// logic panics when run.
+#[cfg(all(test, feature = "machine"))]
+fn panicking_sim(seed: u64, entities: usize) -> Sim {
+    use crate::machine::graph::{GraphNode, LogicGraph, NodeOp};
+
+    let node = |id: &str, op, inputs: &[&str]| GraphNode {
+        id: id.to_string(),
+        op,
+        inputs: inputs.iter().map(|i| i.to_string()).collect(),
+    };
+    let mut counter = LogicGraph {
+        nodes: vec![
+            node("count", NodeOp::Get("int:count".to_string()), &[]),
+            node("one", NodeOp::Value(Var::Int(1)), &[]),
+            node("next_count", NodeOp::Add, &["count", "one"]),
+            node(
+                "set_count",
+                NodeOp::Set("int:count".to_string()),
+                &["next_count"],
+            ),
+        ],
+        ..Default::default()
+    };
+    counter.prepare().unwrap();
+    // node removed after preparing is still evaluated, indexing out of
+    // bounds, same as a misbehaving dynlib call would panic
+    let mut broken = LogicGraph {
+        nodes: vec![
+            node("one", NodeOp::Value(Var::Int(1)), &[]),
+            node("set_x", NodeOp::Set("int:x".to_string()), &["one"]),
+        ],
+        ..Default::default()
+    };
+    broken.prepare().unwrap();
+    broken.nodes.pop();
+
+    let mut model = crate::SimModelBuilder::new()
+        .seed(seed)
+        .component("counter", |c| {
+            c.var("int:count", Var::Int(0))
+                .trigger(crate::DEFAULT_STEP_EVENT)
+        })
+        .component("broken", |c| {
+            c.var("int:x", Var::Int(0))
+                .trigger(crate::DEFAULT_STEP_EVENT)
+        })
+        .prefab("thing", &["counter", "broken"])
+        .build()
+        .unwrap();
+    model
+        .get_component_mut(&string::new_truncate("counter"))
+        .unwrap()
+        .logic
+        .graph = Some(counter);
+    model
+        .get_component_mut(&string::new_truncate("broken"))
+        .unwrap()
+        .logic
+        .graph = Some(broken);
+    let mut sim = Sim::from_model(model).unwrap();
+    for n in 0..entities {
+        sim.spawn_entity(
+            Some(&string::new_truncate("thing")),
+            Some(string::new_truncate(&format!("thing_{}", n))),
+        )
+        .unwrap();
+    }
+    sim
+}
+
+#[cfg(feature = "machine")]
+#[test]
+fn panicking_component_is_disabled() {
+    use std::str::FromStr;
+
+    use crate::address::Address;
+
+    let mut sim = panicking_sim(1, 3);
+    let broken = string::new_truncate("broken");
+    sim.step().unwrap();
+    assert_eq!(sim.error_journal.len(), 3);
+    for (ctx, error) in &sim.error_journal {
+        assert_eq!(ctx.comp, broken);
+        assert!(matches!(error.kind(), MachineErrorKind::CaughtPanic(_)));
+    }
+    for entity in sim.entities.values() {
+        assert_eq!(entity.comp_state[&broken].as_str(), DISABLED_STATE_NAME);
+    }
+
+    // other components keep running, disabled ones are skipped
+    sim.step().unwrap();
+    assert!(sim.error_journal.is_empty());
+    for n in 0..3 {
+        let addr = Address::from_str(&format!("thing_{}:counter:int:count", n)).unwrap();
+        assert_eq!(sim.get_var(&addr).unwrap(), &Var::Int(2));
+    }
+}
+
+#[cfg(feature = "machine")]
+#[test]
+fn runs_with_same_seed_give_same_results() {
+    use std::str::FromStr;
+
+    use crate::address::Address;
+
+    let run = |seed| {
+        let mut sim = panicking_sim(seed, 16);
+        let mut journals = Vec::new();
+        for _ in 0..3 {
+            sim.step().unwrap();
+            journals.push(
+                sim.error_journal
+                    .iter()
+                    .map(|(ctx, e)| (ctx.ent, ctx.comp.clone(), e.to_string()))
+                    .collect::<Vec<_>>(),
+            );
+        }
+        let mut states = sim
+            .entity_idx
+            .iter()
+            .map(|(name, id)| {
+                let mut state = sim.entities[id]
+                    .comp_state
+                    .iter()
+                    .map(|(comp, state)| (comp.to_string(), state.to_string()))
+                    .collect::<Vec<_>>();
+                state.sort();
+                let count = Address::from_str(&format!("{}:counter:int:count", name)).unwrap();
+                (
+                    *id,
+                    name.to_string(),
+                    state,
+                    sim.get_var(&count).unwrap().clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        states.sort_by_key(|(id, ..)| *id);
+        (journals, states, sim.get_clock())
+    };
+
+    let first = run(7);
+    // errors are journaled in entity order, regardless of which thread
+    // processed the entity
+    let ents = first.0[0]
+        .iter()
+        .map(|(ent, _, _)| *ent)
+        .collect::<Vec<_>>();
+    let mut sorted = ents.clone();
+    sorted.sort_unstable();
+    assert_eq!(ents.len(), 16);
+    assert_eq!(ents, sorted);
+    assert_eq!(run(7), first);
+}
@@ -9,6 +9,7 @@ use id_pool::IdPool;
 use crate::distr::SimNode;
 use crate::entity::Entity;
 use crate::error::Error;
+use crate::scheduler::EventScheduler;
 use crate::{EntityId, EntityName, EventName, Result, Sim, SimModel, SimStarter};
 use std::io::Read;
 
@@ -38,6 +39,7 @@ impl Snap for Sim {
             model: self.model.clone(),
             entities_idx: self.entity_idx.clone(),
             event_queue: self.event_queue.clone(),
+            scheduler: self.scheduler.clone(),
             entity_pool: self.entity_pool.clone(),
         };
         let part = SnapshotPart {
@@ -58,11 +60,33 @@ impl Snap for Sim {
             model: header.model,
             clock: header.clock,
             event_queue: header.event_queue,
+            scheduler: header.scheduler,
             entities: part.entities,
             entity_idx: header.entities_idx,
             entity_pool: header.entity_pool,
+            query_plugins: Default::default(),
+            run_stats: Default::default(),
+            delta_baseline: None,
+            change_tracking_start: header.clock,
+            removals: crate::sim::removals::Removals::since(header.clock),
+            despawned: Vec::new(),
+            #[cfg(feature = "machine")]
+            error_journal: Vec::new(),
+            #[cfg(feature = "machine")]
+            system_schedule: None,
+            #[cfg(feature = "machine")]
+            strict: false,
+            #[cfg(feature = "machine")]
+            watchdog: None,
+            #[cfg(feature = "machine")]
+            watchdog_thread: None,
+            #[cfg(feature = "machine")]
+            exec_times: Default::default(),
             #[cfg(feature = "machine_lua")]
             entity_lua_state: Default::default(),
+            #[cfg(feature = "recorder")]
+            recorder: None,
+            replay_log: None,
             #[cfg(feature = "machine_dynlib")]
             libs: Default::default(),
         })
@@ -88,11 +112,33 @@ impl SnapPart for Sim {
             model: header.model,
             clock: header.clock,
             event_queue: header.event_queue,
+            scheduler: header.scheduler,
             entities: part.entities,
             entity_idx: header.entities_idx,
             entity_pool: header.entity_pool,
+            query_plugins: Default::default(),
+            run_stats: Default::default(),
+            delta_baseline: None,
+            change_tracking_start: header.clock,
+            removals: crate::sim::removals::Removals::since(header.clock),
+            despawned: Vec::new(),
+            #[cfg(feature = "machine")]
+            error_journal: Vec::new(),
+            #[cfg(feature = "machine")]
+            system_schedule: None,
+            #[cfg(feature = "machine")]
+            strict: false,
+            #[cfg(feature = "machine")]
+            watchdog: None,
+            #[cfg(feature = "machine")]
+            watchdog_thread: None,
+            #[cfg(feature = "machine")]
+            exec_times: Default::default(),
             #[cfg(feature = "machine_lua")]
             entity_lua_state: Default::default(),
+            #[cfg(feature = "recorder")]
+            recorder: None,
+            replay_log: None,
             #[cfg(feature = "machine_dynlib")]
             libs: Default::default(),
         };
@@ -100,17 +146,88 @@ impl SnapPart for Sim {
     }
 }
 
+impl Snap for SimNode {
+    /// Creates a snapshot of the node's part of the simulation state.
+    ///
+    /// Entity id pool is reconstructed from the ids of entities stored on
+    /// the node, meaning the snapshot can be loaded as a standalone
+    /// simulation.
+    fn to_snapshot(&self) -> Result<Vec<u8>> {
+        let mut entity_pool = IdPool::new();
+        if let Some(max) = self.entities.keys().max() {
+            let mut unused = Vec::new();
+            loop {
+                let id = entity_pool.request_id().ok_or(Error::RequestIdError)?;
+                if !self.entities.contains_key(&id) {
+                    unused.push(id);
+                }
+                if id >= *max {
+                    break;
+                }
+            }
+            for id in unused {
+                entity_pool
+                    .return_id(id)
+                    .map_err(|_| Error::ReturnIdError)?;
+            }
+        }
+        let header = SnapshotHeader {
+            metadata: SnapshotMetadata {
+                created: Utc::now(),
+                starter: SimStarter::Scenario("".to_string()),
+            },
+            clock: self.clock,
+            model: self.model.clone(),
+            entities_idx: self.entities_idx.clone(),
+            event_queue: self.event_queue.clone(),
+            scheduler: Default::default(),
+            entity_pool,
+        };
+        let part = SnapshotPart {
+            entities: self.entities.clone(),
+        };
+        let mut bytes = bincode::serialize(&header)
+            .map_err(|e| Error::FailedCreatingSnapshot(e.to_string()))?;
+        bytes.extend(
+            bincode::serialize(&part).map_err(|e| Error::FailedCreatingSnapshot(e.to_string()))?,
+        );
+        Ok(bytes)
+    }
+
+    fn from_snapshot(mut bytes: &mut Vec<u8>) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        let header = extract_header(&mut bytes)?;
+        let part = extract_part(&mut bytes)?;
+        Ok(Self {
+            clock: header.clock,
+            model: header.model,
+            event_queue: header.event_queue,
+            entities: part.entities,
+            entities_idx: header.entities_idx,
+            query_plugins: Default::default(),
+            #[cfg(feature = "machine")]
+            error_journal: Vec::new(),
+            #[cfg(feature = "machine")]
+            system_schedule: None,
+        })
+    }
+}
+
 /// Extracts snapshot header from the provided bytes.
 pub fn extract_header(mut bytes: &mut Vec<u8>) -> Result<SnapshotHeader> {
     let mut cursor = &bytes[..];
-    let mut header: SnapshotHeader = bincode::deserialize_from(&mut cursor).unwrap();
+    let mut header: SnapshotHeader = bincode::deserialize_from(&mut cursor)
+        .map_err(|e| Error::FailedReadingSnapshot(e.to_string()))?;
     *bytes = cursor.to_owned();
     Ok(header)
 }
 
 pub fn extract_part(mut bytes: &mut Vec<u8>) -> Result<SnapshotPart> {
     let mut cursor = &bytes[..];
-    let mut part: SnapshotPart = bincode::deserialize_from(&mut cursor).unwrap();
+    let mut part: SnapshotPart = bincode::deserialize_from(&mut cursor)
+        .map_err(|e| Error::FailedReadingSnapshot(e.to_string()))?;
     *bytes = cursor.to_owned();
     Ok(part)
 }
@@ -137,6 +254,9 @@ pub struct SnapshotHeader {
     pub model: SimModel,
     pub entities_idx: FnvHashMap<EntityName, EntityId>,
     pub event_queue: Vec<EventName>,
+    /// Events scheduled for future steps
+    #[serde(default)]
+    pub scheduler: EventScheduler,
     pub entity_pool: IdPool,
 }
 
@@ -150,7 +270,7 @@ pub struct SnapshotMetadata {
 
 /// Partial snapshot, used when partitioning large snapshots.
 // TODO support snapshot partitioning
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SnapshotPart {
     pub entities: FnvHashMap<EntityId, Entity>,
 }
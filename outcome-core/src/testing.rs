@@ -0,0 +1,363 @@
+//! Property-based testing utilities.
+//!
+//! Provides [`proptest`] strategies for generating vars, addresses and
+//! storage, along with checks for the invariants the engine guarantees
+//! for them. Downstream crates can use these to test their own
+//! integrations, e.g. services moving data in and out of a simulation.
+//!
+//! Checks return a `TestCaseError` on failure, which makes it possible
+//! to use them directly inside `proptest!` blocks with the `?` operator.
+//!
+//! Requires the `testing` feature.
+
+use std::fmt::Debug;
+use std::str::FromStr;
+
+use proptest::collection::{btree_map, hash_map, vec};
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::address::Address;
+use crate::custom_var::CustomValue;
+use crate::entity::{Storage, StorageIndex};
+use crate::geo::GeoPosition;
+use crate::graph::Graph;
+use crate::var::{Decimal, Fixed, MAX_DECIMAL_SCALE};
+use crate::{string, Float, Int, StringId, Var, VarType};
+
+/// Max length of generated string ids, short enough to fit with the
+/// `short_stringid` feature enabled.
+pub const MAX_ID_LEN: usize = 10;
+/// Max number of elements in generated collections.
+pub const MAX_COLLECTION_LEN: usize = 8;
+
+/// Generates valid string ids.
+pub fn arb_string_id() -> impl Strategy<Value = StringId> {
+    "[a-z_][a-z0-9_]{0,9}".prop_map(|s| string::new_truncate(&s))
+}
+
+/// Generates any var type.
+pub fn arb_var_type() -> impl Strategy<Value = VarType> {
+    prop_oneof![
+        Just(VarType::String),
+        Just(VarType::Int),
+        Just(VarType::Float),
+        Just(VarType::Bool),
+        Just(VarType::Byte),
+        Just(VarType::Vec2),
+        Just(VarType::Vec3),
+        Just(VarType::StringList),
+        Just(VarType::IntList),
+        Just(VarType::FloatList),
+        Just(VarType::BoolList),
+        Just(VarType::ByteList),
+        Just(VarType::Vec2List),
+        Just(VarType::Vec3List),
+        Just(VarType::VarList),
+        Just(VarType::StringGrid),
+        Just(VarType::IntGrid),
+        Just(VarType::FloatGrid),
+        Just(VarType::BoolGrid),
+        Just(VarType::ByteGrid),
+        Just(VarType::Vec2Grid),
+        Just(VarType::Vec3Grid),
+        Just(VarType::VarGrid),
+        Just(VarType::Map),
+        Just(VarType::Fixed),
+        Just(VarType::Decimal),
+        Just(VarType::Graph),
+        Just(VarType::Geo),
+        Just(VarType::Custom),
+    ]
+}
+
+/// Generates finite floats, so that generated vars are always equal to
+/// themselves.
+pub fn arb_float() -> impl Strategy<Value = Float> {
+    -1e6 as Float..1e6 as Float
+}
+
+/// Generates any var.
+pub fn arb_var() -> BoxedStrategy<Var> {
+    arb_var_type().prop_flat_map(arb_var_of_type).boxed()
+}
+
+/// Generates vars of the given type. Lists and grids of a specific type
+/// only contain elements of that type.
+pub fn arb_var_of_type(var_type: VarType) -> BoxedStrategy<Var> {
+    match var_type {
+        VarType::String => "[ -~]{0,16}".prop_map(Var::String).boxed(),
+        VarType::Int => any::<Int>().prop_map(Var::Int).boxed(),
+        VarType::Float => arb_float().prop_map(Var::Float).boxed(),
+        VarType::Bool => any::<bool>().prop_map(Var::Bool).boxed(),
+        VarType::Byte => any::<u8>().prop_map(Var::Byte).boxed(),
+        VarType::Vec2 => (arb_float(), arb_float())
+            .prop_map(|(x, y)| Var::Vec2(x, y))
+            .boxed(),
+        VarType::Vec3 => (arb_float(), arb_float(), arb_float())
+            .prop_map(|(x, y, z)| Var::Vec3(x, y, z))
+            .boxed(),
+        VarType::StringList
+        | VarType::IntList
+        | VarType::FloatList
+        | VarType::BoolList
+        | VarType::ByteList
+        | VarType::Vec2List
+        | VarType::Vec3List
+        | VarType::VarList => vec(arb_element(var_type), 0..MAX_COLLECTION_LEN)
+            .prop_map(Var::List)
+            .boxed(),
+        VarType::StringGrid
+        | VarType::IntGrid
+        | VarType::FloatGrid
+        | VarType::BoolGrid
+        | VarType::ByteGrid
+        | VarType::Vec2Grid
+        | VarType::Vec3Grid
+        | VarType::VarGrid => (1..MAX_COLLECTION_LEN, 1..MAX_COLLECTION_LEN)
+            .prop_flat_map(move |(width, height)| vec(vec(arb_element(var_type), width), height))
+            .prop_map(Var::Grid)
+            .boxed(),
+        VarType::Fixed => (any::<i64>(), 0..=62u8)
+            .prop_map(|(raw, frac)| Var::Fixed(Fixed { raw, frac }))
+            .boxed(),
+        VarType::Decimal => (any::<i64>(), 0..=MAX_DECIMAL_SCALE)
+            .prop_map(|(raw, scale)| {
+                Var::Decimal(Decimal {
+                    raw: raw as i128,
+                    scale,
+                })
+            })
+            .boxed(),
+        VarType::Graph => (
+            any::<bool>(),
+            vec((0..64u32, 0..64u32, arb_float()), 0..MAX_COLLECTION_LEN),
+        )
+            .prop_map(|(directed, edges)| {
+                let mut graph = Graph::new(directed);
+                for (from, to, weight) in edges {
+                    graph.add_edge(from, to, weight);
+                }
+                Var::Graph(graph)
+            })
+            .boxed(),
+        VarType::Geo => (-90f64..=90., -180f64..=180.)
+            .prop_map(|(lat, lon)| Var::Geo(GeoPosition { lat, lon }))
+            .boxed(),
+        VarType::Map => btree_map(arb_scalar_var(), arb_scalar_var(), 0..MAX_COLLECTION_LEN)
+            .prop_map(Var::Map)
+            .boxed(),
+        // custom kinds are registered by embedders, only the empty value
+        // is always available
+        VarType::Custom => Just(Var::Custom(CustomValue::default())).boxed(),
+    }
+}
+
+/// Generates vars of any of the non-collection types.
+pub fn arb_scalar_var() -> BoxedStrategy<Var> {
+    prop_oneof![
+        arb_var_of_type(VarType::String),
+        arb_var_of_type(VarType::Int),
+        arb_var_of_type(VarType::Float),
+        arb_var_of_type(VarType::Bool),
+        arb_var_of_type(VarType::Byte),
+        arb_var_of_type(VarType::Vec2),
+        arb_var_of_type(VarType::Vec3),
+        arb_var_of_type(VarType::Fixed),
+        arb_var_of_type(VarType::Decimal),
+        arb_var_of_type(VarType::Geo),
+    ]
+    .boxed()
+}
+
+/// Generates elements for a list or grid of the given type.
+fn arb_element(collection_type: VarType) -> BoxedStrategy<Var> {
+    match collection_type {
+        VarType::StringList | VarType::StringGrid => arb_var_of_type(VarType::String),
+        VarType::IntList | VarType::IntGrid => arb_var_of_type(VarType::Int),
+        VarType::FloatList | VarType::FloatGrid => arb_var_of_type(VarType::Float),
+        VarType::BoolList | VarType::BoolGrid => arb_var_of_type(VarType::Bool),
+        VarType::ByteList | VarType::ByteGrid => arb_var_of_type(VarType::Byte),
+        VarType::Vec2List | VarType::Vec2Grid => arb_var_of_type(VarType::Vec2),
+        VarType::Vec3List | VarType::Vec3Grid => arb_var_of_type(VarType::Vec3),
+        _ => arb_scalar_var(),
+    }
+}
+
+/// Generates addresses with valid ids.
+pub fn arb_address() -> impl Strategy<Value = Address> {
+    (
+        arb_string_id(),
+        arb_string_id(),
+        arb_var_type(),
+        arb_string_id(),
+    )
+        .prop_map(|(entity, component, var_type, var_name)| Address {
+            entity,
+            component,
+            var_type,
+            var_name,
+        })
+}
+
+/// Generates storage filled with arbitrary vars.
+pub fn arb_storage() -> impl Strategy<Value = Storage> {
+    hash_map(
+        (arb_string_id(), arb_string_id()),
+        arb_var(),
+        0..MAX_COLLECTION_LEN * 2,
+    )
+    .prop_map(|map| Storage {
+        map: map.into_iter().collect(),
+        ..Default::default()
+    })
+}
+
+/// Checks that a var inserted into storage can be retrieved unchanged.
+pub fn check_storage_set_get(
+    storage: &mut Storage,
+    idx: StorageIndex,
+    var: Var,
+) -> Result<(), TestCaseError> {
+    storage.insert(idx.clone(), var.clone());
+    let stored = storage
+        .get_var(&idx)
+        .map_err(|e| TestCaseError::fail(e.to_string()))?;
+    prop_assert_eq!(stored, &var);
+    Ok(())
+}
+
+/// Checks that the address is unchanged after conversion to string and
+/// back.
+pub fn check_address_str_roundtrip(address: &Address) -> Result<(), TestCaseError> {
+    let parsed =
+        Address::from_str(&address.to_string()).map_err(|e| TestCaseError::fail(e.to_string()))?;
+    prop_assert_eq!(&parsed, address);
+    Ok(())
+}
+
+/// Checks that the value is unchanged after serialization and
+/// deserialization, using each of the encodings available in the crate.
+pub fn check_serde_roundtrip<T>(value: &T) -> Result<(), TestCaseError>
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let bytes = bincode::serialize(value).map_err(|e| TestCaseError::fail(e.to_string()))?;
+    let decoded: T =
+        bincode::deserialize(&bytes).map_err(|e| TestCaseError::fail(e.to_string()))?;
+    prop_assert_eq!(&decoded, value);
+
+    #[cfg(feature = "yaml")]
+    {
+        let yaml = serde_yaml::to_string(value).map_err(|e| TestCaseError::fail(e.to_string()))?;
+        let decoded: T =
+            serde_yaml::from_str(&yaml).map_err(|e| TestCaseError::fail(e.to_string()))?;
+        prop_assert_eq!(&decoded, value);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+proptest! {
+    #[test]
+    fn var_serde_roundtrip(var in arb_var()) {
+        check_serde_roundtrip(&var)?;
+    }
+
+    #[test]
+    fn fixed_str_roundtrip(raw in any::<i64>(), frac in 0..=62u8) {
+        let fixed = Fixed { raw, frac };
+        prop_assert_eq!(fixed.to_string().parse::<Fixed>().ok(), Some(fixed));
+    }
+
+    #[test]
+    fn fixed_ordering_matches_value(a in any::<i32>(), b in any::<i32>(), frac in 0..=31u8) {
+        let fa = Fixed::from_int(a as i64, frac).unwrap();
+        let fb = Fixed::from_int(b as i64, 31 - frac).unwrap();
+        prop_assert_eq!(fa.partial_cmp(&fb), a.partial_cmp(&b));
+    }
+
+    #[test]
+    fn address_str_roundtrip(address in arb_address()) {
+        check_address_str_roundtrip(&address)?;
+    }
+
+    #[test]
+    fn storage_set_get(mut storage in arb_storage(), address in arb_address(), var in arb_var()) {
+        check_storage_set_get(&mut storage, address.storage_index(), var)?;
+    }
+
+    #[test]
+    fn storage_serde_roundtrip(storage in arb_storage()) {
+        check_serde_roundtrip(&storage.map)?;
+    }
+}
+
+#[test]
+fn decimal_overflow_is_reported() {
+    use crate::var::RoundingMode;
+
+    assert!(Decimal::from_int(1, 39).is_err());
+    let min = Decimal {
+        raw: i128::MIN,
+        scale: 0,
+    };
+    let minus_one = Decimal { raw: -1, scale: 0 };
+    assert!(min.div(minus_one, RoundingMode::HalfEven).is_err());
+    assert!(min.add(minus_one, RoundingMode::HalfEven).is_err());
+    assert_eq!(
+        Decimal { raw: 1, scale: 40 }.to_string(),
+        "0.0000000000000000000000000000000000000001"
+    );
+}
+
+#[test]
+fn fixed_overflow_is_reported() {
+    use crate::var::ArithOp;
+
+    let fixed = |raw: i64| Fixed { raw, frac: 8 };
+    assert!(Fixed::from_int(i64::MAX >> 8, 8).is_ok());
+    assert!(Fixed::from_int((i64::MAX >> 8) + 1, 8).is_err());
+    assert!(Fixed::from_int(i64::MIN >> 8, 8).is_ok());
+    assert!(Fixed::from_int((i64::MIN >> 8) - 1, 8).is_err());
+
+    assert_eq!(fixed(i64::MAX - 1).add(fixed(1)).unwrap(), fixed(i64::MAX));
+    assert!(fixed(i64::MAX).add(fixed(1)).is_err());
+    assert_eq!(fixed(i64::MIN + 1).sub(fixed(1)).unwrap(), fixed(i64::MIN));
+    assert!(fixed(i64::MIN).sub(fixed(1)).is_err());
+    assert_eq!(fixed(i64::MAX).neg().unwrap(), fixed(-i64::MAX));
+    assert!(fixed(i64::MIN).neg().is_err());
+    assert!(fixed(i64::MIN).div(fixed(-256)).is_err());
+
+    // 2^27 * 2^27 still fits with 8 fractional bits, 2^28 * 2^27 doesn't
+    let a = Fixed::from_int(1 << 27, 8).unwrap();
+    let b = Fixed::from_int(1 << 28, 8).unwrap();
+    assert_eq!(a.mul(a).unwrap(), Fixed::from_int(1 << 54, 8).unwrap());
+    assert!(b.mul(a).is_err());
+    // the result is checked, not the intermediate product
+    assert_eq!(
+        fixed(i64::MAX).mul(Fixed { raw: 1, frac: 0 }).unwrap(),
+        fixed(i64::MAX)
+    );
+
+    assert!(Fixed {
+        raw: 1 << 61,
+        frac: 0
+    }
+    .rescale(1)
+    .is_ok());
+    assert!(Fixed {
+        raw: 1 << 62,
+        frac: 0
+    }
+    .rescale(1)
+    .is_err());
+    assert!("36028797018963967q8".parse::<Fixed>().is_ok());
+    assert!("36028797018963968q8".parse::<Fixed>().is_err());
+    assert!(Var::Fixed(fixed(i64::MAX))
+        .arith(ArithOp::Add, &Var::Int(1))
+        .is_err());
+}
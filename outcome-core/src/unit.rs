@@ -0,0 +1,122 @@
+//! Units of measurement for numeric vars.
+//!
+//! Vars can declare the unit their values are expressed in. Units are
+//! used to check and convert values, catching mistakes such as mixing up
+//! kilometers and meters.
+//!
+//! Each unit belongs to a dimension, and can be converted to any other
+//! unit of the same dimension.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+
+/// Physical dimension shared by convertible units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Dimension {
+    Length,
+    Time,
+    Mass,
+    Temperature,
+    Speed,
+    Angle,
+}
+
+/// Unit of measurement.
+///
+/// Value expressed in the base unit of the dimension is calculated as
+/// `value * scale + offset`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Unit {
+    pub symbol: String,
+    pub dimension: Dimension,
+    pub scale: f64,
+    pub offset: f64,
+}
+
+impl FromStr for Unit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (dimension, scale, offset) = match s {
+            "m" => (Dimension::Length, 1., 0.),
+            "km" => (Dimension::Length, 1000., 0.),
+            "cm" => (Dimension::Length, 0.01, 0.),
+            "mm" => (Dimension::Length, 0.001, 0.),
+            "mi" => (Dimension::Length, 1609.344, 0.),
+            "s" => (Dimension::Time, 1., 0.),
+            "ms" => (Dimension::Time, 0.001, 0.),
+            "min" => (Dimension::Time, 60., 0.),
+            "h" => (Dimension::Time, 3600., 0.),
+            "d" => (Dimension::Time, 86400., 0.),
+            "kg" => (Dimension::Mass, 1., 0.),
+            "g" => (Dimension::Mass, 0.001, 0.),
+            "t" => (Dimension::Mass, 1000., 0.),
+            "K" => (Dimension::Temperature, 1., 0.),
+            "C" | "°C" | "degC" => (Dimension::Temperature, 1., 273.15),
+            "F" | "°F" | "degF" => (Dimension::Temperature, 5. / 9., 273.15 - 32. * 5. / 9.),
+            "m/s" => (Dimension::Speed, 1., 0.),
+            "km/h" => (Dimension::Speed, 1000. / 3600., 0.),
+            "mph" => (Dimension::Speed, 1609.344 / 3600., 0.),
+            "rad" => (Dimension::Angle, 1., 0.),
+            "deg" | "°" => (Dimension::Angle, std::f64::consts::PI / 180., 0.),
+            _ => return Err(Error::UnknownUnit(s.to_string())),
+        };
+        Ok(Unit {
+            symbol: s.to_string(),
+            dimension,
+            scale,
+            offset,
+        })
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.symbol)
+    }
+}
+
+impl Unit {
+    /// Checks whether values can be converted between the two units.
+    pub fn is_compatible(&self, other: &Unit) -> bool {
+        self.dimension == other.dimension
+    }
+
+    /// Checks whether the two units are the same, even if they use
+    /// different symbols.
+    pub fn is_same(&self, other: &Unit) -> bool {
+        self.dimension == other.dimension
+            && self.scale == other.scale
+            && self.offset == other.offset
+    }
+
+    /// Converts the value expressed in this unit into the target unit.
+    pub fn convert(&self, value: f64, target: &Unit) -> Result<f64> {
+        if !self.is_compatible(target) {
+            return Err(Error::UnitMismatch(
+                self.symbol.clone(),
+                target.symbol.clone(),
+            ));
+        }
+        let base = value * self.scale + self.offset;
+        Ok((base - target.offset) / target.scale)
+    }
+}
+
+#[test]
+fn values_are_converted_within_dimension() {
+    let unit = |s: &str| Unit::from_str(s).unwrap();
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+    assert!(close(unit("km").convert(1.5, &unit("m")).unwrap(), 1500.));
+    assert!(close(unit("C").convert(100., &unit("F")).unwrap(), 212.));
+    assert!(close(unit("F").convert(32., &unit("K")).unwrap(), 273.15));
+    assert!(close(unit("km/h").convert(36., &unit("m/s")).unwrap(), 10.));
+    assert!(unit("C").is_same(&unit("degC")));
+    assert!(!unit("C").is_same(&unit("K")));
+
+    assert!(unit("km").convert(1., &unit("s")).is_err());
+    assert!(Unit::from_str("parsec").is_err());
+}
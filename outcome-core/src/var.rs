@@ -2,12 +2,16 @@
 
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
+use std::convert::TryFrom;
 use std::fmt;
 
 use fnv::FnvHashMap;
 use serde_repr::*;
 
+use crate::custom_var::CustomValue;
 use crate::error::{Error, Result};
+use crate::geo::GeoPosition;
+use crate::graph::Graph;
 use crate::{Float, Int};
 
 // default values for base var types
@@ -17,6 +21,18 @@ const DEFAULT_FLOAT_VALUE: Float = 0.;
 const DEFAULT_BOOL_VALUE: bool = false;
 const DEFAULT_BYTE_VALUE: u8 = 0;
 
+/// Default number of fractional bits of fixed point values.
+pub const DEFAULT_FIXED_FRAC_BITS: u8 = 16;
+/// Marks the number of fractional bits when parsing fixed point values,
+/// e.g. `1.5q8`.
+const FIXED_FRAC_BITS_MARKER: char = 'q';
+
+/// Default number of decimal places of decimal values created from
+/// numbers other than decimals.
+pub const DEFAULT_DECIMAL_SCALE: u8 = 2;
+/// Max number of decimal places of decimal values.
+pub const MAX_DECIMAL_SCALE: u8 = 28;
+
 const STRING_VAR_TYPE_NAME: &str = "str";
 const INT_VAR_TYPE_NAME: &str = "int";
 const FLOAT_VAR_TYPE_NAME: &str = "float";
@@ -24,6 +40,11 @@ const BOOL_VAR_TYPE_NAME: &str = "bool";
 const BYTE_VAR_TYPE_NAME: &str = "byte";
 const VEC2_VAR_TYPE_NAME: &str = "vec2";
 const VEC3_VAR_TYPE_NAME: &str = "vec3";
+const FIXED_VAR_TYPE_NAME: &str = "fixed";
+const DECIMAL_VAR_TYPE_NAME: &str = "dec";
+const GRAPH_VAR_TYPE_NAME: &str = "graph";
+const GEO_VAR_TYPE_NAME: &str = "geo";
+const CUSTOM_VAR_TYPE_NAME: &str = "custom";
 
 const LIST_VAR_TYPE_NAME: &str = "list";
 const GRID_VAR_TYPE_NAME: &str = "grid";
@@ -66,6 +87,12 @@ pub enum VarType {
     VarGrid,
 
     Map,
+
+    Fixed,
+    Decimal,
+    Graph,
+    Geo,
+    Custom,
 }
 
 impl fmt::Display for VarType {
@@ -85,6 +112,11 @@ impl VarType {
             BYTE_VAR_TYPE_NAME => VarType::Byte,
             VEC2_VAR_TYPE_NAME => VarType::Vec2,
             VEC3_VAR_TYPE_NAME => VarType::Vec3,
+            FIXED_VAR_TYPE_NAME => VarType::Fixed,
+            DECIMAL_VAR_TYPE_NAME => VarType::Decimal,
+            GRAPH_VAR_TYPE_NAME => VarType::Graph,
+            GEO_VAR_TYPE_NAME => VarType::Geo,
+            CUSTOM_VAR_TYPE_NAME => VarType::Custom,
             _ => {
                 let split = s.split(VAR_TYPE_NAME_SEPARATOR).collect::<Vec<&str>>();
                 if split.len() != 2 {
@@ -130,6 +162,11 @@ impl VarType {
             BYTE_VAR_TYPE_NAME => VarType::Byte,
             VEC2_VAR_TYPE_NAME => VarType::Vec2,
             VEC3_VAR_TYPE_NAME => VarType::Vec3,
+            FIXED_VAR_TYPE_NAME => VarType::Fixed,
+            DECIMAL_VAR_TYPE_NAME => VarType::Decimal,
+            GRAPH_VAR_TYPE_NAME => VarType::Graph,
+            GEO_VAR_TYPE_NAME => VarType::Geo,
+            CUSTOM_VAR_TYPE_NAME => VarType::Custom,
             LIST_VAR_TYPE_NAME => VarType::VarList,
             GRID_VAR_TYPE_NAME => VarType::VarGrid,
             MAP_VAR_TYPE_NAME => VarType::Map,
@@ -148,6 +185,11 @@ impl VarType {
             VarType::Byte => BYTE_VAR_TYPE_NAME,
             VarType::Vec2 => VEC2_VAR_TYPE_NAME,
             VarType::Vec3 => VEC3_VAR_TYPE_NAME,
+            VarType::Fixed => FIXED_VAR_TYPE_NAME,
+            VarType::Decimal => DECIMAL_VAR_TYPE_NAME,
+            VarType::Graph => GRAPH_VAR_TYPE_NAME,
+            VarType::Geo => GEO_VAR_TYPE_NAME,
+            VarType::Custom => CUSTOM_VAR_TYPE_NAME,
             VarType::VarList => LIST_VAR_TYPE_NAME,
             VarType::VarGrid => GRID_VAR_TYPE_NAME,
             VarType::Map => MAP_VAR_TYPE_NAME,
@@ -182,6 +224,11 @@ impl VarType {
                 DEFAULT_FLOAT_VALUE,
                 DEFAULT_FLOAT_VALUE,
             ),
+            VarType::Fixed => Var::Fixed(Fixed::default()),
+            VarType::Decimal => Var::Decimal(Decimal::default()),
+            VarType::Graph => Var::Graph(Graph::default()),
+            VarType::Geo => Var::Geo(GeoPosition::default()),
+            VarType::Custom => Var::Custom(CustomValue::default()),
             VarType::StringList
             | VarType::IntList
             | VarType::FloatList
@@ -217,6 +264,15 @@ pub enum Var {
     List(Vec<Var>),
     Grid(Vec<Vec<Var>>),
     Map(BTreeMap<Var, Var>),
+    Fixed(Fixed),
+    Decimal(Decimal),
+    Graph(Graph),
+    Geo(GeoPosition),
+    /// Value of a custom kind registered by the embedder, see the
+    /// [`custom_var`] module
+    ///
+    /// [`custom_var`]: crate::custom_var
+    Custom(CustomValue),
 }
 
 impl Eq for Var {}
@@ -241,6 +297,11 @@ impl Var {
                 DEFAULT_FLOAT_VALUE,
                 DEFAULT_FLOAT_VALUE,
             ),
+            VarType::Fixed => Var::Fixed(Fixed::default()),
+            VarType::Decimal => Var::Decimal(Decimal::default()),
+            VarType::Graph => Var::Graph(Graph::default()),
+            VarType::Geo => Var::Geo(GeoPosition::default()),
+            VarType::Custom => Var::Custom(CustomValue::default()),
             VarType::StringList
             | VarType::IntList
             | VarType::FloatList
@@ -307,7 +368,11 @@ impl Var {
                 }
             }
             Var::Map(_) => VarType::Map,
-            _ => unimplemented!(),
+            Var::Fixed(_) => VarType::Fixed,
+            Var::Decimal(_) => VarType::Decimal,
+            Var::Graph(_) => VarType::Graph,
+            Var::Geo(_) => VarType::Geo,
+            Var::Custom(_) => VarType::Custom,
         }
     }
 
@@ -317,6 +382,8 @@ impl Var {
             Var::Int(v) => *v = other.to_int(),
             Var::Float(v) => *v = other.to_float(),
             Var::Bool(v) => *v = other.to_bool(),
+            Var::Fixed(v) => *v = other.to_fixed(v.frac)?,
+            Var::Decimal(v) => *v = other.to_decimal(v.scale)?,
             // Var::Byte(v) => *v = other.to_byte()?,
             _ => unimplemented!(),
         }
@@ -329,6 +396,12 @@ impl Var {
             VarType::Int => Var::Int(self.to_int()),
             VarType::Float => Var::Float(self.to_float()),
             VarType::Bool => Var::Bool(self.to_bool()),
+            VarType::Fixed => Var::Fixed(self.to_fixed(DEFAULT_FIXED_FRAC_BITS)?),
+            VarType::Decimal => match self {
+                // keep the number of decimal places as written
+                Var::String(v) => Var::Decimal(v.parse()?),
+                _ => Var::Decimal(self.to_decimal(DEFAULT_DECIMAL_SCALE)?),
+            },
             // Var::Byte(v) => *v = other.to_byte()?,
             _ => unimplemented!(),
         };
@@ -364,6 +437,20 @@ impl Var {
             _ => false,
         }
     }
+
+    pub fn is_fixed(&self) -> bool {
+        match self {
+            Var::Fixed(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_decimal(&self) -> bool {
+        match self {
+            Var::Decimal(_) => true,
+            _ => false,
+        }
+    }
 }
 
 impl Var {
@@ -447,6 +534,115 @@ impl Var {
         }
     }
 
+    pub fn as_fixed(&self) -> Result<&Fixed> {
+        match self {
+            Var::Fixed(v) => Ok(v),
+            _ => Err(Error::InvalidVarType(format!(
+                "expected fixed, got {}",
+                self.get_type().to_str()
+            ))),
+        }
+    }
+
+    pub fn as_fixed_mut(&mut self) -> Result<&mut Fixed> {
+        match self {
+            Var::Fixed(v) => Ok(v),
+            _ => Err(Error::InvalidVarType(format!(
+                "expected fixed, got {}",
+                self.get_type().to_str()
+            ))),
+        }
+    }
+
+    pub fn as_decimal(&self) -> Result<&Decimal> {
+        match self {
+            Var::Decimal(v) => Ok(v),
+            _ => Err(Error::InvalidVarType(format!(
+                "expected decimal, got {}",
+                self.get_type().to_str()
+            ))),
+        }
+    }
+
+    pub fn as_decimal_mut(&mut self) -> Result<&mut Decimal> {
+        match self {
+            Var::Decimal(v) => Ok(v),
+            _ => Err(Error::InvalidVarType(format!(
+                "expected decimal, got {}",
+                self.get_type().to_str()
+            ))),
+        }
+    }
+
+    /// Returns a reference to the custom value if it's of the given kind.
+    pub fn as_custom<T: crate::custom_var::CustomVar>(&self) -> Result<&T> {
+        match self {
+            Var::Custom(v) => v.downcast_ref().ok_or_else(|| {
+                Error::InvalidVarType(format!("unexpected custom var kind: {}", v.0.kind()))
+            }),
+            _ => Err(Error::InvalidVarType(format!(
+                "expected custom, got {}",
+                self.get_type().to_str()
+            ))),
+        }
+    }
+
+    pub fn as_custom_mut<T: crate::custom_var::CustomVar>(&mut self) -> Result<&mut T> {
+        let type_ = self.get_type();
+        match self {
+            Var::Custom(v) => {
+                let kind = v.0.kind().to_string();
+                v.downcast_mut().ok_or_else(|| {
+                    Error::InvalidVarType(format!("unexpected custom var kind: {}", kind))
+                })
+            }
+            _ => Err(Error::InvalidVarType(format!(
+                "expected custom, got {}",
+                type_.to_str()
+            ))),
+        }
+    }
+
+    pub fn as_geo(&self) -> Result<&GeoPosition> {
+        match self {
+            Var::Geo(v) => Ok(v),
+            _ => Err(Error::InvalidVarType(format!(
+                "expected geo, got {}",
+                self.get_type().to_str()
+            ))),
+        }
+    }
+
+    pub fn as_geo_mut(&mut self) -> Result<&mut GeoPosition> {
+        match self {
+            Var::Geo(v) => Ok(v),
+            _ => Err(Error::InvalidVarType(format!(
+                "expected geo, got {}",
+                self.get_type().to_str()
+            ))),
+        }
+    }
+
+    pub fn as_graph(&self) -> Result<&Graph> {
+        match self {
+            Var::Graph(v) => Ok(v),
+            _ => Err(Error::InvalidVarType(format!(
+                "expected graph, got {}",
+                self.get_type().to_str()
+            ))),
+        }
+    }
+
+    pub fn as_graph_mut(&mut self) -> Result<&mut Graph> {
+        match self {
+            Var::Graph(v) => Ok(v),
+            _ => Err(Error::InvalidVarType(format!(
+                "expected graph, got {}",
+                self.get_type().to_str()
+            ))),
+        }
+    }
+
     pub fn as_list(&self) -> Result<&Vec<Var>> {
         match self {
             Var::List(v) => Ok(v),
@@ -688,6 +884,40 @@ impl Var {
     pub fn is_bool_grid(&self) -> bool {
         self.get_type() == VarType::BoolGrid
     }
+
+    /// Returns grid dimensions as `(width, height)`.
+    pub fn grid_size(&self) -> Result<(usize, usize)> {
+        match self {
+            Var::Grid(v) => Ok((v.first().map(|row| row.len()).unwrap_or(0), v.len())),
+            _ => Err(Error::InvalidVarType(format!(
+                "expected grid, got {}",
+                self.get_type().to_str()
+            ))),
+        }
+    }
+
+    /// Returns a copy of a rectangular region of the grid. Region is clipped
+    /// to the grid bounds.
+    pub fn grid_region(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<Vec<Vec<Var>>> {
+        match self {
+            Var::Grid(v) => Ok(v
+                .iter()
+                .skip(y)
+                .take(height)
+                .map(|row| row.iter().skip(x).take(width).cloned().collect())
+                .collect()),
+            _ => Err(Error::InvalidVarType(format!(
+                "expected grid, got {}",
+                self.get_type().to_str()
+            ))),
+        }
+    }
 }
 
 impl Var {
@@ -732,8 +962,19 @@ impl Var {
                 | VarType::ByteGrid
                 | VarType::Vec2Grid
                 | VarType::Vec3Grid
-                | VarType::VarGrid => unimplemented!(),
-                VarType::Map => unimplemented!(),
+                | VarType::VarGrid
+                | VarType::Map => {
+                    return Err(Error::FailedCreatingVar(format!(
+                        "{} can't be parsed from string: {}",
+                        tt.to_str(),
+                        s
+                    )))
+                }
+                VarType::Fixed => Var::Fixed(s.parse()?),
+                VarType::Decimal => Var::Decimal(s.parse()?),
+                VarType::Graph => Var::Graph(s.parse()?),
+                VarType::Geo => Var::Geo(s.parse()?),
+                VarType::Custom => Var::Custom(CustomValue(crate::custom_var::parse(s)?)),
             },
             None => {
                 if s.starts_with('"') {
@@ -767,6 +1008,11 @@ impl Var {
             Var::List(v) => format!("{:?}", v),
             Var::Grid(v) => format!("{:?}", v),
             Var::Map(v) => format!("{:?}", v),
+            Var::Fixed(v) => v.to_string(),
+            Var::Decimal(v) => v.to_string(),
+            Var::Graph(v) => v.to_string(),
+            Var::Geo(v) => v.to_string(),
+            Var::Custom(v) => v.to_string(),
         }
     }
 
@@ -788,6 +1034,11 @@ impl Var {
             Var::List(v) => v.len() as Int,
             Var::Grid(v) => v.len() as Int,
             Var::Map(v) => v.len() as Int,
+            Var::Fixed(v) => v.to_int(),
+            Var::Decimal(v) => v.to_int(),
+            Var::Graph(v) => v.node_count() as Int,
+            Var::Geo(v) => v.lat as Int + v.lon as Int,
+            Var::Custom(_) => 0,
         }
     }
 
@@ -809,6 +1060,11 @@ impl Var {
             Var::List(v) => v.len() as Float,
             Var::Grid(v) => v.len() as Float,
             Var::Map(v) => v.len() as Float,
+            Var::Fixed(v) => v.to_float(),
+            Var::Decimal(v) => v.to_float(),
+            Var::Graph(v) => v.node_count() as Float,
+            Var::Geo(v) => (v.lat + v.lon) as Float,
+            Var::Custom(_) => 0.,
         }
     }
 
@@ -824,8 +1080,123 @@ impl Var {
             Var::List(v) => v.len() > 0,
             Var::Grid(v) => v.len() > 0,
             Var::Map(v) => v.len() > 0,
+            Var::Fixed(v) => v.raw > 0,
+            Var::Decimal(v) => v.raw > 0,
+            Var::Graph(v) => v.node_count() > 0,
+            Var::Geo(v) => v.lat != 0. || v.lon != 0.,
+            Var::Custom(v) => !v.is_empty(),
         }
     }
+
+    /// Converts to a fixed point value with the given number of fractional
+    /// bits. Ints and other fixed point values are converted exactly, while
+    /// floats are rounded to the nearest value. Fails if an int or fixed
+    /// point value doesn't fit with the given number of fractional bits.
+    pub fn to_fixed(&self, frac: u8) -> Result<Fixed> {
+        match self {
+            Var::Int(v) => Fixed::from_int(*v as i64, frac),
+            Var::Byte(v) => Fixed::from_int(*v as i64, frac),
+            Var::Fixed(v) => v.rescale(frac),
+            _ => Ok(Fixed::from_float(self.to_float() as f64, frac)),
+        }
+    }
+
+    /// Converts to a decimal value with the given number of decimal places.
+    /// Ints and other decimal values are converted exactly, as long as no
+    /// decimal places are lost, otherwise values are rounded half to even.
+    /// Fails if the value doesn't fit with the given number of decimal
+    /// places.
+    pub fn to_decimal(&self, scale: u8) -> Result<Decimal> {
+        match self {
+            Var::Int(v) => Decimal::from_int(*v as i128, scale),
+            Var::Byte(v) => Decimal::from_int(*v as i128, scale),
+            Var::Decimal(v) => v.rescale(scale, RoundingMode::HalfEven),
+            Var::String(v) => match v.parse::<Decimal>() {
+                Ok(d) => d.rescale(scale, RoundingMode::HalfEven),
+                Err(_) => Decimal::from_int(0, scale),
+            },
+            _ => Ok(Decimal::from_float(self.to_float() as f64, scale)),
+        }
+    }
+}
+
+/// Arithmetic operation applicable to numeric vars.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl Var {
+    /// Applies the arithmetic operation, with the result keeping the type
+    /// of `self`. The other operand is converted as needed.
+    ///
+    /// Operations on ints wrap around on overflow, operations on fixed point
+    /// values fail on overflow. Results of operations on decimals keep the
+    /// number of decimal places of `self`, rounding half to even, and fail
+    /// on overflow.
+    pub fn arith(&self, op: ArithOp, other: &Var) -> Result<Var> {
+        let out = match self {
+            Var::Int(a) => {
+                let b = other.to_int();
+                Var::Int(match op {
+                    ArithOp::Add => a.wrapping_add(b),
+                    ArithOp::Sub => a.wrapping_sub(b),
+                    ArithOp::Mul => a.wrapping_mul(b),
+                    ArithOp::Div => a
+                        .checked_div(b)
+                        .ok_or_else(|| Error::Other("division by zero".to_string()))?,
+                })
+            }
+            Var::Float(a) => {
+                let b = other.to_float();
+                Var::Float(match op {
+                    ArithOp::Add => a + b,
+                    ArithOp::Sub => a - b,
+                    ArithOp::Mul => a * b,
+                    ArithOp::Div => a / b,
+                })
+            }
+            Var::Decimal(a) => {
+                let b = match other {
+                    Var::Decimal(d) => *d,
+                    _ => other.to_decimal(a.scale)?,
+                };
+                let mode = RoundingMode::HalfEven;
+                Var::Decimal(match op {
+                    ArithOp::Add => a.add(b, mode)?,
+                    ArithOp::Sub => a.sub(b, mode)?,
+                    ArithOp::Mul => a.mul(b, mode)?,
+                    ArithOp::Div => a
+                        .div(b, mode)?
+                        .ok_or_else(|| Error::Other("division by zero".to_string()))?,
+                })
+            }
+            Var::Fixed(a) => {
+                let b = match other {
+                    Var::Fixed(f) => *f,
+                    _ => other.to_fixed(a.frac)?,
+                };
+                Var::Fixed(match op {
+                    ArithOp::Add => a.add(b)?,
+                    ArithOp::Sub => a.sub(b)?,
+                    ArithOp::Mul => a.mul(b)?,
+                    ArithOp::Div => a
+                        .div(b)?
+                        .ok_or_else(|| Error::Other("division by zero".to_string()))?,
+                })
+            }
+            _ => {
+                return Err(Error::InvalidVarType(format!(
+                    "expected numeric var, got {}",
+                    self.get_type().to_str()
+                )))
+            }
+        };
+        Ok(out)
+    }
 }
 
 // TODO support nested lists
@@ -847,3 +1218,537 @@ fn list_from_str(s: &str, var_type: VarType) -> Result<Var> {
     }
     Ok(Var::List(vec))
 }
+
+/// Integer-backed binary fixed point number.
+///
+/// Value is stored as a raw integer scaled by `2^frac`, where `frac` is the
+/// number of fractional bits. Arithmetic on fixed point values only uses
+/// integer operations, which makes results bit-exact across platforms,
+/// unlike with floats.
+///
+/// Results of arithmetic between values with different number of
+/// fractional bits use the number of fractional bits of the left operand.
+/// Arithmetic fails instead of wrapping around when the result doesn't fit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Fixed {
+    pub raw: i64,
+    pub frac: u8,
+}
+
+impl Default for Fixed {
+    fn default() -> Self {
+        Fixed {
+            raw: 0,
+            frac: DEFAULT_FIXED_FRAC_BITS,
+        }
+    }
+}
+
+impl Fixed {
+    pub fn from_int(v: i64, frac: u8) -> Result<Fixed> {
+        Ok(Fixed {
+            raw: fit(shl(v as i128, frac))?,
+            frac,
+        })
+    }
+
+    pub fn from_float(v: f64, frac: u8) -> Fixed {
+        Fixed {
+            raw: (v * (1u64 << frac) as f64).round() as i64,
+            frac,
+        }
+    }
+
+    /// Truncates toward negative infinity.
+    pub fn to_int(&self) -> Int {
+        (self.raw >> self.frac) as Int
+    }
+
+    pub fn to_float(&self) -> Float {
+        (self.raw as f64 / (1u64 << self.frac) as f64) as Float
+    }
+
+    /// Converts to a different number of fractional bits, truncating toward
+    /// negative infinity if bits are lost.
+    pub fn rescale(&self, frac: u8) -> Result<Fixed> {
+        let raw = if frac >= self.frac {
+            fit(shl(self.raw as i128, frac - self.frac))?
+        } else {
+            self.raw >> (self.frac - frac)
+        };
+        Ok(Fixed { raw, frac })
+    }
+
+    pub fn add(self, rhs: Fixed) -> Result<Fixed> {
+        Ok(Fixed {
+            raw: fit((self.raw as i128).checked_add(rhs.rescale(self.frac)?.raw as i128))?,
+            frac: self.frac,
+        })
+    }
+
+    pub fn sub(self, rhs: Fixed) -> Result<Fixed> {
+        Ok(Fixed {
+            raw: fit((self.raw as i128).checked_sub(rhs.rescale(self.frac)?.raw as i128))?,
+            frac: self.frac,
+        })
+    }
+
+    pub fn mul(self, rhs: Fixed) -> Result<Fixed> {
+        // product of two 64 bit values always fits into 128 bits
+        Ok(Fixed {
+            raw: fit(Some((self.raw as i128 * rhs.raw as i128) >> rhs.frac))?,
+            frac: self.frac,
+        })
+    }
+
+    /// Returns `None` when dividing by zero.
+    pub fn div(self, rhs: Fixed) -> Result<Option<Fixed>> {
+        let rhs = rhs.rescale(self.frac)?;
+        if rhs.raw == 0 {
+            return Ok(None);
+        }
+        Ok(Some(Fixed {
+            raw: fit(shl(self.raw as i128, self.frac).and_then(|v| v.checked_div(rhs.raw as i128)))?,
+            frac: self.frac,
+        }))
+    }
+
+    pub fn neg(self) -> Result<Fixed> {
+        Ok(Fixed {
+            raw: fit(Some(-(self.raw as i128)))?,
+            frac: self.frac,
+        })
+    }
+}
+
+/// Shifts the raw value left by the number of bits, failing if bits would
+/// be lost.
+fn shl(v: i128, bits: u8) -> Option<i128> {
+    match bits {
+        0..=63 => v.checked_mul(1 << bits),
+        _ if v == 0 => Some(0),
+        _ => None,
+    }
+}
+
+/// Fails if the raw value doesn't fit into 64 bits.
+fn fit(v: Option<i128>) -> Result<i64> {
+    v.and_then(|v| i64::try_from(v).ok())
+        .ok_or_else(|| Error::FixedOutOfRange("arithmetic overflow".to_string()))
+}
+
+impl PartialOrd for Fixed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        // widen before aligning the fractional bits so that nothing is
+        // lost to overflow
+        let frac = self.frac.max(other.frac);
+        let a = (self.raw as i128) << (frac - self.frac);
+        let b = (other.raw as i128) << (frac - other.frac);
+        a.partial_cmp(&b)
+    }
+}
+
+impl fmt::Display for Fixed {
+    /// Writes the exact decimal expansion of the value, followed by the
+    /// number of fractional bits if it's not the default one, e.g. `1.5q8`.
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        let magnitude = (self.raw as i128).abs() as u128;
+        let mask = (1u128 << self.frac) - 1;
+        if self.raw < 0 {
+            write!(formatter, "-")?;
+        }
+        write!(formatter, "{}", magnitude >> self.frac)?;
+        let mut rest = magnitude & mask;
+        if rest != 0 {
+            write!(formatter, ".")?;
+            // each binary fractional digit adds exactly one decimal digit
+            while rest != 0 {
+                rest *= 10;
+                write!(formatter, "{}", rest >> self.frac)?;
+                rest &= mask;
+            }
+        }
+        if self.frac != DEFAULT_FIXED_FRAC_BITS {
+            write!(formatter, "{}{}", FIXED_FRAC_BITS_MARKER, self.frac)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Fixed {
+    type Err = Error;
+
+    /// Parses a decimal number, with the number of fractional bits
+    /// optionally given after a `q`, e.g. `1.5q8`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (value, frac) = match s.split_once(FIXED_FRAC_BITS_MARKER) {
+            Some((value, frac)) => (value, frac.parse::<u8>()?),
+            None => (s, DEFAULT_FIXED_FRAC_BITS),
+        };
+        if frac > 62 {
+            return Err(Error::FailedCreatingVar(s.to_string()));
+        }
+        // keep plain decimals exact regardless of their magnitude
+        match parse_fixed_exact(value, frac) {
+            Some(v) => v,
+            None => Ok(Fixed::from_float(value.parse::<f64>()?, frac)),
+        }
+    }
+}
+
+/// Parses a plain decimal number into a fixed point value without going
+/// through floats, rounding half away from zero if the value can't be
+/// represented exactly. Returns `None` if the string is not a plain
+/// decimal number, e.g. when it uses the exponent notation, and an error
+/// if the number doesn't fit.
+fn parse_fixed_exact(s: &str, frac: u8) -> Option<Result<Fixed>> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let (int_digits, frac_digits) = digits.split_once('.').unwrap_or((digits, ""));
+    if (int_digits.is_empty() && frac_digits.is_empty())
+        || !int_digits.bytes().all(|b| b.is_ascii_digit())
+        || !frac_digits.bytes().all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    let int = match int_digits {
+        "" => Some(0),
+        _ => int_digits.parse::<i128>().ok(),
+    };
+
+    // repeatedly double the decimal fraction, each carry out of it is the
+    // next binary fractional digit, with one extra digit for rounding
+    let mut decimal = frac_digits.bytes().map(|b| b - b'0').collect::<Vec<u8>>();
+    let mut bits: u64 = 0;
+    for _ in 0..=frac {
+        let mut carry = 0;
+        for digit in decimal.iter_mut().rev() {
+            let doubled = *digit * 2 + carry;
+            *digit = doubled % 10;
+            carry = doubled / 10;
+        }
+        bits = (bits << 1) | carry as u64;
+    }
+    let bits = (bits >> 1) + (bits & 1);
+
+    // fail on overflow, same as with arithmetic
+    let magnitude = int
+        .and_then(|int| shl(int, frac))
+        .and_then(|int| int.checked_add(bits as i128));
+    let raw = if negative {
+        magnitude.map(|m| -m)
+    } else {
+        magnitude
+    };
+    Some(fit(raw).map(|raw| Fixed { raw, frac }))
+}
+
+/// Rounding applied when decimal places are lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+    /// Round to nearest, ties to even, also known as banker's rounding
+    HalfEven,
+    /// Round to nearest, ties away from zero
+    HalfUp,
+    /// Round toward zero
+    Down,
+    /// Round toward negative infinity
+    Floor,
+    /// Round toward positive infinity
+    Ceiling,
+}
+
+impl Default for RoundingMode {
+    fn default() -> Self {
+        RoundingMode::HalfEven
+    }
+}
+
+/// Integer-backed decimal number.
+///
+/// Value is stored as a raw integer scaled by `10^scale`, where `scale` is
+/// the number of decimal places. Unlike floats, decimals represent values
+/// such as `0.1` exactly, which makes them suitable for money. Addition
+/// and subtraction of decimals with the same scale, as well as
+/// multiplication by ints, are always exact.
+///
+/// Results of arithmetic keep the scale of the left operand, rounding
+/// with the given mode where decimal places are lost. Arithmetic fails
+/// instead of wrapping around when the result doesn't fit.
+///
+/// Decimals are encoded as strings, e.g. `"12.50"`, with human-readable
+/// encodings, and as raw value and scale with binary encodings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Decimal {
+    pub raw: i128,
+    pub scale: u8,
+}
+
+impl Default for Decimal {
+    fn default() -> Self {
+        Decimal {
+            raw: 0,
+            scale: DEFAULT_DECIMAL_SCALE,
+        }
+    }
+}
+
+impl Decimal {
+    pub fn from_int(v: i128, scale: u8) -> Result<Decimal> {
+        Ok(Decimal {
+            raw: checked(v.checked_mul(pow10(scale)?))?,
+            scale,
+        })
+    }
+
+    pub fn from_float(v: f64, scale: u8) -> Decimal {
+        Decimal {
+            raw: (v * 10f64.powi(scale as i32)).round() as i128,
+            scale,
+        }
+    }
+
+    /// Truncates toward zero.
+    pub fn to_int(&self) -> Int {
+        // values with more decimal places than fit are always below one
+        pow10(self.scale)
+            .map(|div| (self.raw / div) as Int)
+            .unwrap_or(0)
+    }
+
+    pub fn to_float(&self) -> Float {
+        (self.raw as f64 / 10f64.powi(self.scale as i32)) as Float
+    }
+
+    /// Converts to a different number of decimal places, rounding with the
+    /// given mode if decimal places are lost.
+    pub fn rescale(&self, scale: u8, mode: RoundingMode) -> Result<Decimal> {
+        let raw = if scale >= self.scale {
+            checked(self.raw.checked_mul(pow10(scale - self.scale)?))?
+        } else {
+            div_round(self.raw, pow10(self.scale - scale)?, mode)?
+        };
+        Ok(Decimal { raw, scale })
+    }
+
+    pub fn add(self, rhs: Decimal, mode: RoundingMode) -> Result<Decimal> {
+        Ok(Decimal {
+            raw: checked(self.raw.checked_add(rhs.rescale(self.scale, mode)?.raw))?,
+            scale: self.scale,
+        })
+    }
+
+    pub fn sub(self, rhs: Decimal, mode: RoundingMode) -> Result<Decimal> {
+        Ok(Decimal {
+            raw: checked(self.raw.checked_sub(rhs.rescale(self.scale, mode)?.raw))?,
+            scale: self.scale,
+        })
+    }
+
+    pub fn mul(self, rhs: Decimal, mode: RoundingMode) -> Result<Decimal> {
+        Ok(Decimal {
+            raw: div_round(
+                checked(self.raw.checked_mul(rhs.raw))?,
+                pow10(rhs.scale)?,
+                mode,
+            )?,
+            scale: self.scale,
+        })
+    }
+
+    /// Returns `None` when dividing by zero.
+    pub fn div(self, rhs: Decimal, mode: RoundingMode) -> Result<Option<Decimal>> {
+        if rhs.raw == 0 {
+            return Ok(None);
+        }
+        Ok(Some(Decimal {
+            raw: div_round(
+                checked(self.raw.checked_mul(pow10(rhs.scale)?))?,
+                rhs.raw,
+                mode,
+            )?,
+            scale: self.scale,
+        }))
+    }
+}
+
+/// Fails if the power of ten doesn't fit, which is the case for exponents
+/// above 38.
+fn pow10(exp: u8) -> Result<i128> {
+    10i128
+        .checked_pow(exp as u32)
+        .ok_or_else(|| Error::DecimalOutOfRange(format!("10^{}", exp)))
+}
+
+fn checked(v: Option<i128>) -> Result<i128> {
+    v.ok_or_else(|| Error::DecimalOutOfRange("arithmetic overflow".to_string()))
+}
+
+/// Divides, rounding the quotient with the given mode. Fails on overflow,
+/// i.e. when dividing `i128::MIN` by `-1`.
+fn div_round(n: i128, d: i128, mode: RoundingMode) -> Result<i128> {
+    let (q, r) = (checked(n.checked_div(d))?, checked(n.checked_rem(d))?);
+    if r == 0 {
+        return Ok(q);
+    }
+    let positive = (n < 0) == (d < 0);
+    // the remainder is non-zero so the quotient is less than `n` in
+    // magnitude, stepping away from zero can't overflow
+    let away = if positive { q + 1 } else { q - 1 };
+    let twice_r = r.unsigned_abs() * 2;
+    let d_abs = d.unsigned_abs();
+    let rounded = match mode {
+        RoundingMode::Down => q,
+        RoundingMode::Floor => {
+            if positive {
+                q
+            } else {
+                away
+            }
+        }
+        RoundingMode::Ceiling => {
+            if positive {
+                away
+            } else {
+                q
+            }
+        }
+        RoundingMode::HalfUp => {
+            if twice_r >= d_abs {
+                away
+            } else {
+                q
+            }
+        }
+        RoundingMode::HalfEven => {
+            if twice_r > d_abs || (twice_r == d_abs && q % 2 != 0) {
+                away
+            } else {
+                q
+            }
+        }
+    };
+    Ok(rounded)
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let scale = self.scale.max(other.scale);
+        match (
+            self.rescale(scale, RoundingMode::Down),
+            other.rescale(scale, RoundingMode::Down),
+        ) {
+            (Ok(a), Ok(b)) => a.raw.partial_cmp(&b.raw),
+            // a non-zero value that overflows when rescaled is larger in
+            // magnitude than the other value
+            (Err(_), Ok(_)) if self.raw != 0 => Some(self.raw.cmp(&0)),
+            (Ok(_), Err(_)) if other.raw != 0 => Some(0.cmp(&other.raw)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        let sign = if self.raw < 0 { "-" } else { "" };
+        let digits = self.raw.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+        if scale == 0 {
+            write!(formatter, "{}{}", sign, digits)
+        } else {
+            // pad with zeros so that there's at least one integer digit
+            let digits = format!("{:0>width$}", digits, width = scale + 1);
+            let (int_part, frac_part) = digits.split_at(digits.len() - scale);
+            write!(formatter, "{}{}.{}", sign, int_part, frac_part)
+        }
+    }
+}
+
+impl std::str::FromStr for Decimal {
+    type Err = Error;
+
+    /// Parses a decimal number, with the scale given by the number of
+    /// digits after the decimal point, e.g. `12.50`.
+    fn from_str(s: &str) -> Result<Self> {
+        let err = || Error::FailedCreatingVar(s.to_string());
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (int_part, frac_part) = digits.split_once('.').unwrap_or((digits, ""));
+        if (int_part.is_empty() && frac_part.is_empty())
+            || !int_part
+                .chars()
+                .chain(frac_part.chars())
+                .all(|c| c.is_ascii_digit())
+            || frac_part.len() > MAX_DECIMAL_SCALE as usize
+        {
+            return Err(err());
+        }
+        let raw = format!("{}{}", int_part, frac_part)
+            .parse::<i128>()
+            .map_err(|_| err())?;
+        Ok(Decimal {
+            raw: if negative { -raw } else { raw },
+            scale: frac_part.len() as u8,
+        })
+    }
+}
+
+impl serde::Serialize for Decimal {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.to_string())
+        } else {
+            (self.raw.to_le_bytes(), self.scale).serialize(serializer)
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Decimal {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            s.parse().map_err(serde::de::Error::custom)
+        } else {
+            let (bytes, scale) = <([u8; 16], u8)>::deserialize(deserializer)?;
+            if scale > MAX_DECIMAL_SCALE {
+                return Err(serde::de::Error::custom(format!(
+                    "decimal scale out of range: {}",
+                    scale
+                )));
+            }
+            Ok(Decimal {
+                raw: i128::from_le_bytes(bytes),
+                scale,
+            })
+        }
+    }
+}
+
+#[test]
+fn grid_region_is_clipped_to_bounds() {
+    let grid = Var::Grid(
+        (0..3)
+            .map(|y| (0..4).map(|x| Var::Int(y * 4 + x)).collect())
+            .collect(),
+    );
+    assert_eq!(grid.grid_size().unwrap(), (4, 3));
+    assert_eq!(
+        grid.grid_region(2, 1, 10, 10).unwrap(),
+        vec![
+            vec![Var::Int(6), Var::Int(7)],
+            vec![Var::Int(10), Var::Int(11)]
+        ]
+    );
+    assert!(grid.grid_region(5, 0, 1, 1).unwrap()[0].is_empty());
+    assert!(Var::Int(0).grid_size().is_err());
+}
@@ -0,0 +1,534 @@
+//! Checkpointing the simulation union.
+//!
+//! Checkpoint captures the state of the whole union at a step boundary, so
+//! that the cluster can later be restarted from it, see
+//! [`Organizer::from_checkpoint`]. Organizer asks each of the workers to
+//! write the state of it's node to a shared checkpoint store. Once all of
+//! them are done, organizer writes a manifest holding the central state
+//! along with the list of written parts.
+//!
+//! No steps are processed while a checkpoint is being taken or restored,
+//! and a checkpoint can't be started while entities are being migrated
+//! between workers. Workers refuse to write their state if their clock
+//! doesn't match the clock the checkpoint is taken at.
+//!
+//! Checkpoints are laid out within the store as follows:
+//!
+//! ```text
+//! latest                            name of the most recent checkpoint
+//! checkpoint-<clock>/manifest       central state and the list of parts
+//! checkpoint-<clock>/worker-<id>    state of a single worker node
+//! ```
+//!
+//! Store location is either a path to a local directory or a `file://` URL.
+//! The directory has to be accessible to the organizer and all the workers,
+//! e.g. using a network file system. Other URL schemes are reserved for
+//! object stores and are not supported yet.
+
+use std::path::PathBuf;
+
+use fnv::{FnvHashMap, FnvHashSet};
+use outcome::distr::{CentralCommunication, Signal, SimCentral, SimNode};
+use outcome::{EntityId, SimStarter};
+
+use crate::organizer::{Organizer, OrganizerTask};
+use crate::worker::WorkerId;
+use crate::{sig, Error, Result, TaskId};
+
+/// Key under which the name of the most recent checkpoint is stored.
+const LATEST_KEY: &str = "latest";
+
+/// Storage for checkpoint data, addressed by string keys.
+pub trait CheckpointStore {
+    /// Writes the value under the given key, replacing any existing one.
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<()>;
+    /// Reads the value stored under the given key.
+    fn get(&self, key: &str) -> Result<Vec<u8>>;
+}
+
+/// Checkpoint store backed by a directory, with keys being paths relative
+/// to it.
+pub struct DirStore {
+    pub root: PathBuf,
+}
+
+impl CheckpointStore for DirStore {
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<()> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // readers never see a partially written value
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, bytes)?;
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>> {
+        Ok(std::fs::read(self.root.join(key))?)
+    }
+}
+
+/// Opens the checkpoint store at the given location.
+pub fn open_store(location: &str) -> Result<Box<dyn CheckpointStore>> {
+    let split = location.splitn(2, "://").collect::<Vec<&str>>();
+    match (split[0], split.get(1)) {
+        (path, None) | ("file", Some(path)) => Ok(Box::new(DirStore {
+            root: PathBuf::from(path),
+        })),
+        (scheme, Some(_)) => Err(Error::Other(format!(
+            "unsupported checkpoint store: {}://",
+            scheme
+        ))),
+    }
+}
+
+/// Checkpoint entry point, written once all the parts are stored.
+#[derive(Serialize, Deserialize)]
+struct CheckpointManifest {
+    clock: usize,
+    /// Serialized central state
+    central: Vec<u8>,
+    /// Keys of the stored node parts, along with the entities each of them
+    /// holds
+    parts: Vec<(String, Vec<EntityId>)>,
+}
+
+/// Parts of the checkpoint being restored.
+pub(crate) struct PendingRestore {
+    location: String,
+    /// Parts not yet assigned to any worker
+    parts: Vec<(String, Vec<EntityId>)>,
+    /// Parts assigned to workers that didn't confirm restoring them yet
+    assigned: Vec<(WorkerId, String, Vec<EntityId>)>,
+}
+
+impl Organizer {
+    /// Starts taking a checkpoint of the union, written to the store at the
+    /// given location. Returns the id of the task tracking the checkpoint,
+    /// see [`Organizer::checkpoint_status`]. If any of the workers can't be
+    /// asked to write it's part, the task is dropped and the error returned.
+    pub fn checkpoint(&mut self, location: &str) -> Result<TaskId> {
+        // fail early on unsupported stores
+        open_store(location)?;
+        if self.net.workers.is_empty() {
+            return Err(Error::Other("no workers to checkpoint".to_string()));
+        }
+        if self.is_migrating() || self.is_checkpointing() {
+            return Err(Error::Other(
+                "can't checkpoint while migrating entities or checkpointing".to_string(),
+            ));
+        }
+
+        let clock = self.central.clock;
+        let name = format!("checkpoint-{}", clock);
+        let worker_ids = self.net.workers.keys().copied().collect::<Vec<_>>();
+        let task_id = self.register_task(OrganizerTask::Checkpoint {
+            location: location.to_string(),
+            name: name.clone(),
+            clock,
+            remaining: worker_ids.len() as u32,
+            parts: Vec::new(),
+            error: None,
+            finished: false,
+        })?;
+        for worker_id in worker_ids {
+            let key = format!("{}/worker-{}", name, worker_id);
+            let sig = sig::Signal::from(
+                task_id,
+                Signal::Checkpoint(location.to_string(), key, clock),
+            );
+            // sent directly, so that failing to reach a worker is reported
+            let result = match self.net.workers.get_mut(&worker_id) {
+                Some(worker) => worker.connection.send_sig(sig, None),
+                None => Err(Error::Other(format!("worker {} not found", worker_id))),
+            };
+            if let Err(e) = result {
+                // the checkpoint can't complete, don't leave it blocking
+                // steps and migrations
+                self.unregister_task(task_id)?;
+                return Err(e);
+            }
+        }
+        Ok(task_id)
+    }
+
+    /// Checks on the checkpoint task. Returns `None` while the checkpoint
+    /// is still being taken, otherwise the task is removed and either the
+    /// name of the checkpoint or the error is returned.
+    pub fn checkpoint_status(&mut self, task_id: TaskId) -> Option<Result<String>> {
+        match self.tasks.get(&task_id) {
+            Some(OrganizerTask::Checkpoint { finished: true, .. }) => (),
+            Some(OrganizerTask::Checkpoint { .. }) => return None,
+            _ => {
+                return Some(Err(Error::Other(format!(
+                    "unknown checkpoint task: {}",
+                    task_id
+                ))))
+            }
+        }
+        let result = match self.tasks.remove(&task_id) {
+            Some(OrganizerTask::Checkpoint { error: Some(e), .. }) => Err(Error::Other(e)),
+            Some(OrganizerTask::Checkpoint { name, .. }) => Ok(name),
+            _ => unreachable!(),
+        };
+        let _ = self.net.task_id_pool.return_id(task_id);
+        Some(result)
+    }
+
+    /// Checks whether a checkpoint is currently being either taken or
+    /// restored.
+    pub fn is_checkpointing(&self) -> bool {
+        self.pending_restore.is_some()
+            || self.tasks.values().any(|t| match t {
+                OrganizerTask::Checkpoint { finished, .. } => !finished,
+                _ => false,
+            })
+    }
+
+    pub(crate) fn handle_checkpoint_signal(
+        &mut self,
+        worker_id: WorkerId,
+        task_id: TaskId,
+        sig: Signal,
+    ) -> Result<()> {
+        // signals without a task are responses to restore requests
+        if task_id == 0 {
+            return self.handle_restore_signal(worker_id, sig);
+        }
+        let (location, name, clock, parts) = match self.tasks.get_mut(&task_id) {
+            Some(OrganizerTask::Checkpoint {
+                location,
+                name,
+                clock,
+                remaining,
+                parts,
+                error,
+                finished,
+            }) if !*finished => {
+                match sig {
+                    Signal::CheckpointSaved(key) => parts.push((worker_id, key)),
+                    Signal::CheckpointFailed(e) => {
+                        error.get_or_insert(format!("worker {}: {}", worker_id, e));
+                    }
+                    _ => (),
+                }
+                *remaining = remaining.saturating_sub(1);
+                if *remaining > 0 {
+                    return Ok(());
+                }
+                if let Some(e) = error {
+                    warn!("checkpoint {} failed: {}", name, e);
+                    *finished = true;
+                    return Ok(());
+                }
+                (location.clone(), name.clone(), *clock, parts.clone())
+            }
+            _ => {
+                warn!("checkpoint signal for unknown task {}: {:?}", task_id, sig);
+                return Ok(());
+            }
+        };
+
+        let result = self.write_manifest(&location, &name, clock, parts);
+        if let Some(OrganizerTask::Checkpoint {
+            error, finished, ..
+        }) = self.tasks.get_mut(&task_id)
+        {
+            match result {
+                Ok(()) => info!("checkpoint {} written to {}", name, location),
+                Err(e) => {
+                    warn!("failed writing checkpoint {} manifest: {}", name, e);
+                    *error = Some(e.to_string());
+                }
+            }
+            *finished = true;
+        }
+        Ok(())
+    }
+
+    fn write_manifest(
+        &self,
+        location: &str,
+        name: &str,
+        clock: usize,
+        parts: Vec<(WorkerId, String)>,
+    ) -> Result<()> {
+        let manifest = CheckpointManifest {
+            clock,
+            central: bincode::serialize(&self.central)?,
+            parts: parts
+                .into_iter()
+                .map(|(worker_id, key)| {
+                    let entities = self
+                        .central
+                        .node_entities
+                        .get(&worker_id)
+                        .cloned()
+                        .unwrap_or_default();
+                    (key, entities)
+                })
+                .collect(),
+        };
+        let store = open_store(location)?;
+        store.put(
+            &format!("{}/manifest", name),
+            &bincode::serialize(&manifest)?,
+        )?;
+        store.put(LATEST_KEY, name.as_bytes())
+    }
+
+    /// Creates a new organizer restoring the union from the most recent
+    /// checkpoint in the store at the given location.
+    ///
+    /// Checkpoint parts are spread across the workers listed here, or if
+    /// there are none, assigned to the first worker to connect. The number
+    /// of workers doesn't have to match the number of workers the
+    /// checkpoint was taken with.
+    pub fn from_checkpoint(location: &str, addr: &str, worker_addrs: Vec<String>) -> Result<Self> {
+        let store = open_store(location)?;
+        let name = String::from_utf8_lossy(&store.get(LATEST_KEY)?)
+            .trim()
+            .to_string();
+        let manifest: CheckpointManifest =
+            bincode::deserialize(&store.get(&format!("{}/manifest", name))?)?;
+        let mut central: SimCentral = bincode::deserialize(&manifest.central)?;
+        if central.clock != manifest.clock {
+            return Err(Error::Other(format!(
+                "checkpoint {} is inconsistent: central at clock {}, expected {}",
+                name, central.clock, manifest.clock
+            )));
+        }
+        // entities are assigned to the new workers as parts are restored,
+        // while the starter makes sure the initial entities aren't spawned
+        // again
+        central.node_entities.clear();
+        central.starter = Some(SimStarter::Snapshot(name.clone()));
+
+        let mut organ = Organizer::new(central, addr, worker_addrs)?;
+        organ.pending_restore = Some(PendingRestore {
+            location: location.to_string(),
+            parts: manifest.parts,
+            assigned: Vec::new(),
+        });
+        organ.restore_checkpoint_parts()?;
+        info!("restoring checkpoint {} from {}", name, location);
+        Ok(organ)
+    }
+
+    /// Assigns pending checkpoint parts to the connected workers.
+    pub(crate) fn restore_checkpoint_parts(&mut self) -> Result<()> {
+        let mut worker_ids = self.net.workers.keys().copied().collect::<Vec<_>>();
+        worker_ids.sort();
+        let restore = match &mut self.pending_restore {
+            Some(restore) if !worker_ids.is_empty() => restore,
+            _ => return Ok(()),
+        };
+        for (n, (key, entities)) in restore.parts.drain(..).enumerate() {
+            let worker_id = worker_ids[n % worker_ids.len()];
+            self.net.send_sig_to_node(
+                worker_id,
+                0,
+                Signal::RestoreCheckpoint(restore.location.clone(), key),
+            )?;
+            for entity in &entities {
+                self.net.routing_table.insert(*entity, worker_id);
+            }
+            self.central
+                .node_entities
+                .entry(worker_id)
+                .or_default()
+                .extend(entities.iter().copied());
+            restore.assigned.push((worker_id, key, entities));
+        }
+        if restore.assigned.is_empty() {
+            self.pending_restore = None;
+        }
+        Ok(())
+    }
+
+    fn handle_restore_signal(&mut self, worker_id: WorkerId, sig: Signal) -> Result<()> {
+        let restore = match &mut self.pending_restore {
+            Some(restore) => restore,
+            None => {
+                warn!(
+                    "unexpected checkpoint signal from worker {}: {:?}",
+                    worker_id, sig
+                );
+                return Ok(());
+            }
+        };
+        // parts are restored in the order they were assigned in
+        let position = match &sig {
+            Signal::CheckpointRestored(key) => restore
+                .assigned
+                .iter()
+                .position(|(id, k, _)| *id == worker_id && k == key),
+            Signal::CheckpointFailed(_) => restore
+                .assigned
+                .iter()
+                .position(|(id, _, _)| *id == worker_id),
+            _ => None,
+        };
+        let (_, key, entities) = match position {
+            Some(idx) => restore.assigned.remove(idx),
+            None => {
+                warn!(
+                    "unexpected checkpoint signal from worker {}: {:?}",
+                    worker_id, sig
+                );
+                return Ok(());
+            }
+        };
+        let done = restore.assigned.is_empty() && restore.parts.is_empty();
+        match sig {
+            Signal::CheckpointFailed(e) => {
+                error!(
+                    "worker {} failed restoring checkpoint part {}, it's entities are lost: {}",
+                    worker_id, key, e
+                );
+                if let Some(assigned) = self.central.node_entities.get_mut(&worker_id) {
+                    assigned.retain(|id| !entities.contains(id));
+                }
+                for entity in &entities {
+                    self.net.routing_table.remove(entity);
+                }
+                self.drop_lost_entities(worker_id, entities);
+            }
+            _ => debug!("worker {} restored checkpoint part {}", worker_id, key),
+        }
+        if done {
+            self.pending_restore = None;
+            info!("checkpoint restored at clock {}", self.central.clock);
+        }
+        Ok(())
+    }
+
+    /// Takes back the checkpoint parts the dead worker didn't confirm
+    /// restoring, so that they can be assigned to other workers. Returns
+    /// the entities of the taken back parts.
+    pub(crate) fn requeue_restore_parts(&mut self, worker_id: WorkerId) -> FnvHashSet<EntityId> {
+        let mut requeued = FnvHashSet::default();
+        let restore = match &mut self.pending_restore {
+            Some(restore) => restore,
+            None => return requeued,
+        };
+        let (dead, assigned) = restore
+            .assigned
+            .drain(..)
+            .partition::<Vec<_>, _>(|(id, _, _)| *id == worker_id);
+        restore.assigned = assigned;
+        for (_, key, entities) in dead {
+            warn!(
+                "worker {} died before restoring checkpoint part {}, reassigning",
+                worker_id, key
+            );
+            requeued.extend(entities.iter().copied());
+            restore.parts.push((key, entities));
+        }
+        requeued
+    }
+}
+
+/// Writes the node state to the checkpoint store, making sure the node is
+/// at the expected clock.
+pub(crate) fn save_node(node: &SimNode, location: &str, key: &str, clock: usize) -> Result<()> {
+    if node.clock != clock {
+        return Err(Error::Other(format!(
+            "node at clock {}, checkpoint taken at clock {}",
+            node.clock, clock
+        )));
+    }
+    open_store(location)?.put(key, &bincode::serialize(node)?)
+}
+
+/// Reads node state from the checkpoint store, merging it into the given
+/// node.
+pub(crate) fn restore_node(node: &mut SimNode, location: &str, key: &str) -> Result<()> {
+    let restored: SimNode = bincode::deserialize(&open_store(location)?.get(key)?)?;
+    // checked up front so that the node is never left partially restored
+    if let Some(entity_id) = restored
+        .entities
+        .keys()
+        .find(|id| node.entities.contains_key(id))
+    {
+        return Err(Error::Other(format!(
+            "failed restoring checkpoint part {}: entity with id {} already exists",
+            key, entity_id
+        )));
+    }
+    let mut names = restored
+        .entities_idx
+        .into_iter()
+        .map(|(name, id)| (id, name))
+        .collect::<FnvHashMap<_, _>>();
+    node.clock = restored.clock;
+    node.event_queue = restored.event_queue;
+    for (entity_id, entity) in restored.entities {
+        node.insert_entity(entity_id, names.remove(&entity_id), entity)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn restore_node_is_all_or_nothing() {
+    use outcome::entity::Entity;
+
+    let model = outcome::SimModelBuilder::new().build().unwrap();
+    let location = std::env::temp_dir().join("outcome_checkpoint_restore");
+    let location = location.to_str().unwrap();
+    let mut saved = SimNode::from_model(&model).unwrap();
+    saved
+        .insert_entity(
+            1,
+            Some(outcome::string::new_truncate("first")),
+            Entity::empty(),
+        )
+        .unwrap();
+    saved.insert_entity(2, None, Entity::empty()).unwrap();
+    save_node(&saved, location, "part", saved.clock).unwrap();
+
+    let mut node = SimNode::from_model(&model).unwrap();
+    node.insert_entity(2, None, Entity::empty()).unwrap();
+    assert!(restore_node(&mut node, location, "part").is_err());
+    assert_eq!(node.entities.len(), 1);
+    assert!(node.entities_idx.is_empty());
+
+    let mut node = SimNode::from_model(&model).unwrap();
+    restore_node(&mut node, location, "part").unwrap();
+    assert_eq!(node.entities.len(), 2);
+    assert_eq!(
+        node.entities_idx
+            .get(&outcome::string::new_truncate("first")),
+        Some(&1)
+    );
+}
+
+#[test]
+fn failed_checkpoint_request_does_not_block_steps() {
+    use std::time::Instant;
+
+    use crate::organizer::Worker;
+    use crate::socket::{Socket, SocketAddress, Transport};
+
+    let model = outcome::SimModelBuilder::new().build().unwrap();
+    let central = SimCentral::from_model(model, None).unwrap();
+    let mut organ = Organizer::new_at_any(central, vec![]).unwrap();
+    // worker that can't be reached
+    organ.net.workers.insert(
+        0,
+        Worker {
+            address: SocketAddress::Unavailable,
+            entities: vec![],
+            connection: Socket::new(None, Transport::Tcp).unwrap(),
+            is_blocking_step: false,
+            last_seen: Instant::now(),
+        },
+    );
+
+    let location = std::env::temp_dir().join("outcome_checkpoint_unreachable");
+    assert!(organ.checkpoint(location.to_str().unwrap()).is_err());
+    assert!(!organ.is_checkpointing());
+    assert!(organ.tasks.is_empty());
+}
@@ -1,19 +1,46 @@
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
 use crate::msg::{
-    DataTransferRequest, DataTransferResponse, ExportSnapshotRequest, ExportSnapshotResponse,
-    Message, PingRequest, RegisterClientRequest, RegisterClientResponse,
-    ScheduledDataTransferRequest, StatusRequest, StatusResponse, TransferResponseData,
-    TurnAdvanceRequest, TypedSimDataPack,
+    AddPrefabRequest, AttributionReportRequest, AttributionReportResponse, CreateSelectionRequest,
+    CreateSelectionResponse, DataPullRequest, DataPullResponse, DataTransferRequest,
+    DataTransferResponse, DespawnEntitiesRequest, DespawnEntitiesResponse, ErrorFields,
+    ExportSnapshotChunk, ExportSnapshotRequest, ExportSnapshotResponse, FindPathRequest,
+    FindPathResponse, FloatPrecision, GetRuntimeErrorsRequest, GetRuntimeErrorsResponse,
+    GridRegionRequest, GridRegionResponse, InvokeEventsRequest, InvokeEventsResponse,
+    LoadSnapshotRequest, LoadSnapshotResponse, Message, MessageType, ModelEditResponse,
+    ModelUpdateRequest, ModifyEntityRequest, ModifyEntityResponse, NativeQueryRequest,
+    NativeQueryResponse, Payload, PingRequest, PullRequestData, RecorderRequest, RecorderResponse,
+    RefreshSelectionRequest, RefreshSelectionResponse, RegisterClientRequest,
+    RegisterClientResponse, RegisterComponentRequest, ResponseError, ScheduleEventRequest,
+    ScheduleEventResponse, ScheduledDataTransferRequest, SelectionOperation,
+    SelectionOperationRequest, SelectionOperationResponse, SetPrefabDefaultsRequest,
+    SetStepTriggerRequest, SetStepTriggerResponse, SnapshotLoadMode, SpawnEntitiesRequest,
+    SpawnEntitiesResponse, StartSimRequest, StartSimResponse, StatusRequest, StatusResponse,
+    SubscribeRequest, SubscribeResponse, SubscriptionUpdate, TransferResponseData,
+    TurnAdvanceRequest, TurnAdvanceResponse, TypedSimDataPack, UnsubscribeRequest,
+    UnsubscribeResponse, UpdateComponentLogicRequest, VarSimDataPackOrdered,
 };
 use crate::socket::{
     CompositeSocketAddress, Encoding, Socket, SocketAddress, SocketConfig, SocketType, Transport,
 };
-use crate::{error::Error, Result};
+use crate::subscriber::Subscriber;
+use crate::trace::{self, TraceId};
+use crate::{error::Error, Result, TaskId};
+
+use fnv::FnvHashMap;
+use outcome::query::{Description, Filter, Layout, Map, Trigger};
+use outcome::{
+    Address, CompName, EntityId, EntityName, EventName, PrefabName, QueryProduct, SimInterface, Var,
+};
+use serde::Serialize;
+
+/// Size of a single chunk when uploading snapshots to the server.
+const SNAPSHOT_CHUNK_SIZE: usize = 1024 * 1024;
 
 /// List of available compression policies for outgoing messages.
 #[derive(Debug)]
@@ -52,6 +79,30 @@ impl CompressionPolicy {
     }
 }
 
+/// Policy for reconnecting to the server after the connection was lost.
+///
+/// Delay between attempts doubles with each failed attempt, up to
+/// `max_delay`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Maximum number of reconnection attempts
+    pub max_attempts: u32,
+    /// Delay before the first attempt
+    pub delay: Duration,
+    /// Upper bound for the delay between attempts
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
 /// Configuration settings for client.
 #[derive(Debug)]
 pub struct ClientConfig {
@@ -61,12 +112,20 @@ pub struct ClientConfig {
     pub heartbeat: Option<Duration>,
     /// Blocking client requires server to wait for it's explicit step advance
     pub is_blocking: bool,
+    /// Observer client only has read-only access, in exchange the server
+    /// guarantees it's requests won't affect the simulation
+    pub is_observer: bool,
     /// Compression policy for outgoing messages
     pub compress: CompressionPolicy,
     /// Supported encodings, first is most preferred
     pub encodings: Vec<Encoding>,
     /// Supported transports
     pub transports: Vec<Transport>,
+    /// Precision of numbers in data transfers, can be overridden for
+    /// single requests
+    pub float_precision: FloatPrecision,
+    /// Reconnection policy, none disables automatic reconnection
+    pub reconnect: Option<RetryPolicy>,
 }
 
 impl Default for ClientConfig {
@@ -75,9 +134,12 @@ impl Default for ClientConfig {
             name: "default_client".to_string(),
             heartbeat: Some(Duration::from_secs(1)),
             is_blocking: false,
+            is_observer: false,
             compress: CompressionPolicy::OnlyDataTransfers,
             encodings: vec![Encoding::Bincode],
             transports: vec![Transport::Tcp],
+            float_precision: FloatPrecision::Native,
+            reconnect: None,
         }
     }
 }
@@ -93,6 +155,26 @@ impl Default for ClientConfig {
 /// may have multiple blocking clients connected to it, and second on the level
 /// of the coordinator, which has the ultimate authority when it comes to
 /// advancing the simulation clock.
+///
+/// # Observer client
+///
+/// An observer is a non-blocking client limited to read-only requests. The
+/// server serves it's data transfers from a per-step cache and rate-limits
+/// it's requests, so that observers can be attached to running simulations
+/// without affecting them.
+///
+/// # Reconnection
+///
+/// With a retry policy set in the config, client automatically reconnects
+/// when it finds the connection lost. Server issues each client a session
+/// token, using which the reconnected client resumes it's previous session,
+/// including scheduled transfers and queries, blocking status and stored
+/// orders, as long as the server still retains it.
+///
+/// Requests that failed to be sent are sent again once reconnected. Responses
+/// lost along with the connection are not recovered, the receiving call
+/// returns the original error after reconnecting, at which point the request
+/// can be retried.
 pub struct Client {
     /// Configuration struct
     config: ClientConfig,
@@ -100,6 +182,21 @@ pub struct Client {
     pub connection: Socket,
     /// Current connection status
     connected: bool,
+    /// Trace id assigned to the most recent request
+    last_trace_id: TraceId,
+    /// Address of the server's publishing socket, if there is one
+    publish_address: Option<String>,
+    /// Greeter address and password used for the most recent connection
+    greeter: Option<(String, Option<String>)>,
+    /// Token issued by the server for resuming the session
+    session_token: Option<String>,
+    /// Task id to be attached to the next tagged request
+    next_task_id: TaskId,
+    /// Messages received while waiting for a response to a tagged request
+    queued: VecDeque<(SocketAddress, Message)>,
+    /// Most recent ordered var sets received for each order, used as the
+    /// base for applying deltas
+    ordered: FnvHashMap<u32, VarSimDataPackOrdered>,
 }
 
 impl Client {
@@ -132,6 +229,13 @@ impl Client {
             config,
             connection,
             connected: false,
+            last_trace_id: trace::NO_TRACE,
+            publish_address: None,
+            greeter: None,
+            session_token: None,
+            next_task_id: 1,
+            queued: VecDeque::new(),
+            ordered: Default::default(),
         };
         Ok(client)
     }
@@ -145,6 +249,8 @@ impl Client {
     /// to that address is then initiated by the client.
     pub fn connect(&mut self, greeter_addr: &str, password: Option<String>) -> Result<()> {
         info!("dialing server greeter at: {}", greeter_addr);
+        self.connected = false;
+        self.greeter = Some((greeter_addr.to_string(), password.clone()));
 
         let greeter_composite: CompositeSocketAddress = greeter_addr.parse()?;
 
@@ -162,20 +268,32 @@ impl Client {
             RegisterClientRequest {
                 name: self.config.name.clone(),
                 is_blocking: self.config.is_blocking,
-                auth_pair: None,
+                is_observer: self.config.is_observer,
+                auth_pair: password.map(|p| (self.config.name.clone(), p)),
                 encodings: self.config.encodings.clone(),
                 transports: self.config.transports.clone(),
+                float_precision: self.config.float_precision,
+                session_token: self.session_token.clone(),
             },
             None,
         )?;
         debug!("sent client registration request");
 
         let resp: RegisterClientResponse = self
-            .connection
             .recv_msg()?
             .1
             .unpack_payload(self.connection.encoding())?;
         debug!("got response from server: {:?}", resp);
+        if !resp.error.is_empty() {
+            return Err(Error::HandshakeFailed(resp.error));
+        }
+        self.publish_address = resp.publish_address.clone();
+        if self.session_token.is_some() && !resp.resumed {
+            warn!("previous session expired, starting a new one");
+        }
+        if !resp.session_token.is_empty() {
+            self.session_token = Some(resp.session_token.clone());
+        }
 
         // perform redirection using address provided by the server
         if !resp.address.is_empty() {
@@ -196,93 +314,1170 @@ impl Client {
             self.connection.connect(composite.address)?;
         }
 
-        // if !resp.error.is_empty() {
-        //     return Err(Error::Other(resp.error));
-        // }
-
         self.connected = true;
 
         Ok(())
     }
 
+    /// Sends a request to the server, assigning it a new trace id.
+    pub(crate) fn send_payload<P: Payload + Serialize>(
+        &mut self,
+        payload: P,
+        addr: Option<SocketAddress>,
+    ) -> Result<()> {
+        self.send_payload_with_task(payload, 0, addr)
+    }
+
+    /// Sends a request to the server tagged with the task id, assigning it
+    /// a new trace id.
+    fn send_payload_with_task<P: Payload + Serialize>(
+        &mut self,
+        payload: P,
+        task_id: TaskId,
+        addr: Option<SocketAddress>,
+    ) -> Result<()> {
+        if self.config.is_observer && !payload.type_().is_read_only() {
+            return Err(Error::Other(format!(
+                "observer client can't send {:?}",
+                payload.type_()
+            )));
+        }
+        self.last_trace_id = trace::new_id();
+        let _trace = trace::enter(self.last_trace_id);
+        match self
+            .connection
+            .send_payload_with_task(payload.clone(), task_id, addr.clone())
+        {
+            Err(e) if self.should_reconnect(&e) => {
+                warn!("failed sending request, reconnecting: {}", e);
+                self.reconnect()?;
+                self.connection
+                    .send_payload_with_task(payload, task_id, addr)
+            }
+            result => result,
+        }
+    }
+
+    /// Sends a request tagged with a new task id and waits for the response
+    /// of the given type carrying the same id, or an error response to the
+    /// request.
+    ///
+    /// Other messages received in the meantime, such as pushed data, are
+    /// queued and returned by subsequent calls to [`Client::recv_msg`].
+    fn request<P: Payload + Serialize>(
+        &mut self,
+        payload: P,
+        response_type: MessageType,
+    ) -> Result<Message> {
+        let task_id = self.next_task_id;
+        // zero is used by untagged messages
+        self.next_task_id = self.next_task_id.checked_add(1).unwrap_or(1);
+        self.send_payload_with_task(payload, task_id, None)?;
+        loop {
+            let (addr, msg) = self.recv_from_connection()?;
+            if msg.task_id == task_id
+                && (msg.type_ == response_type || msg.type_ == MessageType::ErrorResponse)
+            {
+                return Ok(msg);
+            }
+            self.queued.push_back((addr, msg));
+        }
+    }
+
+    /// Checks whether the error means the connection was lost and the
+    /// client is set to reconnect.
+    fn should_reconnect(&self, error: &Error) -> bool {
+        let lost = matches!(
+            error,
+            Error::Disconnect(_) | Error::SocketNotConnected | Error::HostUnreachable
+        );
+        lost && self.connected && self.config.reconnect.is_some()
+    }
+
+    /// Reconnects to the server, resuming the session if the server still
+    /// retains it.
+    ///
+    /// Attempts are made according to the retry policy from the config,
+    /// without a policy only a single attempt is made.
+    pub fn reconnect(&mut self) -> Result<()> {
+        let (greeter_addr, password) = self.greeter.clone().ok_or(Error::Other(
+            "can't reconnect, client was never connected".to_string(),
+        ))?;
+        let policy = self.config.reconnect.clone().unwrap_or(RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        });
+        let mut delay = policy.delay;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            thread::sleep(delay);
+            match self.connect(&greeter_addr, password.clone()) {
+                Ok(()) => {
+                    info!("reconnected to server after {} attempt(s)", attempt);
+                    return Ok(());
+                }
+                Err(e) if attempt >= policy.max_attempts => return Err(e),
+                Err(e) => {
+                    debug!("reconnection attempt {} failed: {}", attempt, e);
+                    delay = (delay * 2).min(policy.max_delay);
+                }
+            }
+        }
+    }
+
+    /// Trace id of the most recent request, useful for finding log entries
+    /// related to that request across the cluster.
+    pub fn last_trace_id(&self) -> TraceId {
+        self.last_trace_id
+    }
+
+    /// Receives the next message from the server, skipping any busy
+    /// heartbeats sent while the server is working on a request.
+    ///
+    /// Messages queued while waiting for responses to typed operations are
+    /// returned first.
+    pub fn recv_msg(&mut self) -> Result<(SocketAddress, Message)> {
+        match self.queued.pop_front() {
+            Some(queued) => Ok(queued),
+            None => self.recv_from_connection(),
+        }
+    }
+
+    fn recv_from_connection(&mut self) -> Result<(SocketAddress, Message)> {
+        loop {
+            let (addr, msg) = match self.connection.recv_msg() {
+                Err(e) if self.should_reconnect(&e) => {
+                    warn!("connection lost, reconnecting: {}", e);
+                    self.reconnect()?;
+                    return Err(e);
+                }
+                result => result?,
+            };
+            if msg.type_ == MessageType::BusyHeartbeat {
+                trace!("server busy, waiting for response");
+                continue;
+            }
+            return Ok((addr, msg));
+        }
+    }
+
+    /// Subscribes to the data published by the server, such as step
+    /// completed notices and var streams. Fails if the server doesn't
+    /// have publishing enabled.
+    pub fn subscribe(&self) -> Result<Subscriber> {
+        let address = self.publish_address.as_ref().ok_or(Error::Other(
+            "server doesn't have publishing enabled".to_string(),
+        ))?;
+        Subscriber::connect(address)
+    }
+
     pub fn disconnect(&mut self) -> Result<()> {
         self.connected = false;
         self.connection.disconnect(None)
     }
 
     pub fn server_status(&mut self) -> Result<StatusResponse> {
-        self.connection.send_payload(
+        self.send_payload(
             StatusRequest {
                 format: "".to_string(),
             },
             None,
         )?;
         debug!("sent server status request to server");
-        let (_, msg) = self.connection.recv_msg()?;
+        let (_, msg) = self.recv_msg()?;
         let resp: StatusResponse = msg.unpack_payload(self.connection.encoding())?;
         Ok(resp)
     }
 
+    /// Requests logic errors that occurred during steps beginning at or
+    /// after `since_clock`, or during the last processed step if `None`.
+    pub fn get_runtime_errors(
+        &mut self,
+        since_clock: Option<usize>,
+    ) -> Result<GetRuntimeErrorsResponse> {
+        self.send_payload(GetRuntimeErrorsRequest { since_clock }, None)?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: GetRuntimeErrorsResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    pub fn invoke_events(&mut self, events: Vec<String>) -> Result<InvokeEventsResponse> {
+        self.send_payload(InvokeEventsRequest { events }, None)?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: InvokeEventsResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    /// Schedules the event to fire at the given step, recurring every
+    /// `every_n_steps` if provided. If no step is given the recurring event
+    /// first fires `every_n_steps` from the current step.
+    pub fn schedule_event(
+        &mut self,
+        event: &str,
+        at_step: Option<u64>,
+        every_n_steps: Option<u64>,
+    ) -> Result<ScheduleEventResponse> {
+        self.send_payload(
+            ScheduleEventRequest {
+                event: event.to_string(),
+                at_step,
+                every_n_steps,
+            },
+            None,
+        )?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: ScheduleEventResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    pub fn set_step_trigger(&mut self, trigger: &str) -> Result<SetStepTriggerResponse> {
+        self.send_payload(
+            SetStepTriggerRequest {
+                trigger: trigger.to_string(),
+            },
+            None,
+        )?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: SetStepTriggerResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    pub fn create_selection(
+        &mut self,
+        name: &str,
+        query: crate::msg::query::Query,
+        dynamic: bool,
+    ) -> Result<CreateSelectionResponse> {
+        self.send_payload(
+            CreateSelectionRequest {
+                name: name.to_string(),
+                query,
+                dynamic,
+            },
+            None,
+        )?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: CreateSelectionResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    pub fn refresh_selection(&mut self, name: &str) -> Result<RefreshSelectionResponse> {
+        self.send_payload(
+            RefreshSelectionRequest {
+                name: name.to_string(),
+            },
+            None,
+        )?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: RefreshSelectionResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    pub fn apply_to_selection(
+        &mut self,
+        name: &str,
+        operation: SelectionOperation,
+    ) -> Result<SelectionOperationResponse> {
+        self.send_payload(
+            SelectionOperationRequest {
+                name: name.to_string(),
+                operation,
+            },
+            None,
+        )?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: SelectionOperationResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    /// Subscribes to changes of vars selected by the query. Returns the
+    /// subscription id, updates are then pushed by the server after each
+    /// step, see [`Client::recv_subscription_update`].
+    pub fn subscribe_query(&mut self, query: crate::msg::query::Query) -> Result<u32> {
+        self.send_payload(SubscribeRequest { query }, None)?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: SubscribeResponse = msg.unpack_payload(self.connection.encoding())?;
+        resp.error_result()?;
+        Ok(resp.subscription_id)
+    }
+
+    pub fn unsubscribe_query(&mut self, subscription_id: u32) -> Result<UnsubscribeResponse> {
+        self.send_payload(UnsubscribeRequest { subscription_id }, None)?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: UnsubscribeResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    /// Waits for the next subscription update, skipping any other
+    /// messages received in the meantime.
+    pub fn recv_subscription_update(&mut self) -> Result<SubscriptionUpdate> {
+        loop {
+            let (_, msg) = self.recv_msg()?;
+            if msg.type_ == MessageType::SubscriptionUpdate {
+                return msg.unpack_payload(self.connection.encoding());
+            }
+        }
+    }
+
+    /// Requests a breakdown of storage and execution time per component.
+    ///
+    /// Only servers backed by a local simulation can provide the report,
+    /// others respond with an `Unsupported` error code.
+    pub fn attribution_report(&mut self) -> Result<AttributionReportResponse> {
+        self.send_payload(AttributionReportRequest {}, None)?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: AttributionReportResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    /// Registers a new component with the model, optionally with logic
+    /// created from the provided script source. Requires admin scope.
+    pub fn register_component(
+        &mut self,
+        name: &str,
+        vars: Vec<outcome::model::VarModel>,
+        triggers: Vec<String>,
+        logic: Option<String>,
+    ) -> Result<ModelEditResponse> {
+        self.send_payload(
+            RegisterComponentRequest {
+                name: name.to_string(),
+                vars,
+                triggers,
+                logic,
+            },
+            None,
+        )?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: ModelEditResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    /// Replaces the logic of an existing component with logic created from
+    /// the provided script source. Requires admin scope.
+    pub fn update_component_logic(&mut self, name: &str, logic: &str) -> Result<ModelEditResponse> {
+        self.send_payload(
+            UpdateComponentLogicRequest {
+                name: name.to_string(),
+                logic: logic.to_string(),
+            },
+            None,
+        )?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: ModelEditResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    /// Registers a new entity prefab with the model. Requires admin scope.
+    pub fn add_prefab(&mut self, name: &str, components: Vec<String>) -> Result<ModelEditResponse> {
+        self.send_payload(
+            AddPrefabRequest {
+                name: name.to_string(),
+                components,
+            },
+            None,
+        )?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: ModelEditResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    /// Overrides default var values for entities spawned from the prefab
+    /// from now on. Defaults are given as `(component, var, value)`
+    /// triples. Requires admin scope.
+    pub fn set_prefab_defaults(
+        &mut self,
+        prefab: &str,
+        defaults: Vec<(String, String, Var)>,
+    ) -> Result<ModelEditResponse> {
+        self.send_payload(
+            SetPrefabDefaultsRequest {
+                prefab: prefab.to_string(),
+                defaults,
+            },
+            None,
+        )?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: ModelEditResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    /// Pushes a new model to the server. Partial models are merged into
+    /// the current one instead of replacing it. Requires admin scope.
+    pub fn update_model(
+        &mut self,
+        model: outcome::SimModel,
+        partial: bool,
+    ) -> Result<ModelEditResponse> {
+        self.send_payload(ModelUpdateRequest { model, partial }, None)?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: ModelEditResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    /// Starts, stops or flushes recording of selected vars on the server.
+    /// Requires admin scope.
+    pub fn control_recorder(&mut self, req: RecorderRequest) -> Result<RecorderResponse> {
+        self.send_payload(req, None)?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: RecorderResponse = msg.unpack_payload(self.connection.encoding())?;
+        resp.error_result()?;
+        Ok(resp)
+    }
+
+    /// Requests a rectangular region of a grid var. Region is clipped to
+    /// the grid bounds by the server.
+    pub fn get_grid_region(
+        &mut self,
+        address: &str,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<GridRegionResponse> {
+        self.send_payload(
+            GridRegionRequest {
+                address: address.to_string(),
+                x,
+                y,
+                width,
+                height,
+            },
+            None,
+        )?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: GridRegionResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    /// Requests the cheapest path on a grid or graph var. Grid ends are
+    /// given as `vec2` coordinates, graph ends as `int` entity ids.
+    pub fn find_path(
+        &mut self,
+        address: &str,
+        from: outcome::Var,
+        to: outcome::Var,
+        algorithm: &str,
+    ) -> Result<FindPathResponse> {
+        self.send_payload(
+            FindPathRequest {
+                address: address.to_string(),
+                from,
+                to,
+                algorithm: algorithm.to_string(),
+            },
+            None,
+        )?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: FindPathResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    pub fn native_query(&mut self, query: outcome::Query) -> Result<NativeQueryResponse> {
+        let msg = self.request(
+            NativeQueryRequest { query },
+            MessageType::NativeQueryResponse,
+        )?;
+        let resp: NativeQueryResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
     pub fn server_step_request(&mut self, steps: u32) -> Result<Message> {
-        self.connection.send_payload(
+        self.send_payload(
             TurnAdvanceRequest {
                 step_count: steps,
                 wait: false,
             },
             None,
         )?;
-        let (_, resp) = self.connection.recv_msg()?;
+        let (_, resp) = self.recv_msg()?;
         Ok(resp)
     }
 
     // data querying
-    pub fn get_var_as_string(&self, addr: &str) -> Result<String> {
-        unimplemented!();
+    pub fn get_var_as_string(&mut self, addr: &str) -> Result<String> {
+        self.get_vars_as_strings(&vec![addr.to_string()])?
+            .pop()
+            .flatten()
+            .ok_or(Error::Other(format!("var not found: {}", addr)))
     }
-    pub fn get_vars_as_strings(&self, addrs: &Vec<String>) -> Result<Vec<String>> {
-        unimplemented!();
+
+    /// Gets the values of the vars at the provided addresses. Values for
+    /// addresses that couldn't be found are returned as `None`.
+    pub fn get_vars_as_strings(&mut self, addrs: &Vec<String>) -> Result<Vec<Option<String>>> {
+        self.send_payload(
+            DataTransferRequest {
+                transfer_type: "SelectVar".to_string(),
+                selection: addrs.clone(),
+                precision: None,
+                since_step: None,
+                order_id: None,
+            },
+            None,
+        )?;
+        let resp: DataTransferResponse = self
+            .recv_msg()?
+            .1
+            .unpack_payload(self.connection.encoding())?;
+        let vars = match resp.data.expand() {
+            TransferResponseData::AddressedVar(vars) => vars,
+            _ => return Err(Error::Other("unexpected transfer response".to_string())),
+        };
+        Ok(addrs
+            .iter()
+            .map(|addr| {
+                outcome::Address::from_str(addr)
+                    .ok()
+                    .and_then(|a| vars.get(&a))
+                    .map(|v| v.to_string())
+            })
+            .collect())
     }
 
     pub fn get_vars(&mut self) -> Result<TransferResponseData> {
-        self.connection.send_payload(
+        self.send_payload(
             DataTransferRequest {
                 transfer_type: "Full".to_string(),
                 selection: vec![],
+                precision: None,
+                since_step: None,
+                order_id: None,
+            },
+            None,
+        )?;
+        let resp: DataTransferResponse = self
+            .recv_msg()?
+            .1
+            .unpack_payload(self.connection.encoding())?;
+
+        Ok(resp.data.expand())
+    }
+
+    /// Gets the vars that changed since the given step, or since the
+    /// previous call if no step is given.
+    pub fn get_changed_vars(&mut self, since_step: Option<u64>) -> Result<TransferResponseData> {
+        self.send_payload(
+            DataTransferRequest {
+                transfer_type: "Diff".to_string(),
+                selection: vec![],
+                precision: None,
+                since_step,
+                order_id: None,
             },
             None,
         )?;
         let resp: DataTransferResponse = self
-            .connection
             .recv_msg()?
             .1
             .unpack_payload(self.connection.encoding())?;
 
-        Ok(resp.data)
+        Ok(resp.data.expand())
+    }
+
+    /// Gets the selected vars as an ordered set, returning the id of the
+    /// order stored on the server along with the vars.
+    ///
+    /// Empty selection reuses the given order, or the last created one.
+    /// With `delta` enabled the server only sends the vars that changed
+    /// since the previous transfer of the order, which are applied to the
+    /// set received before. If the delta can't be applied the whole set is
+    /// requested again.
+    pub fn get_vars_ordered(
+        &mut self,
+        selection: Vec<String>,
+        order_id: Option<u32>,
+        delta: bool,
+    ) -> Result<(u32, Vec<Var>)> {
+        let transfer_type = match delta {
+            true => "SelectVarOrderedDelta",
+            false => "SelectVarOrdered",
+        };
+        let msg = self.request(
+            DataTransferRequest {
+                transfer_type: transfer_type.to_string(),
+                selection,
+                precision: None,
+                since_step: None,
+                order_id,
+            },
+            MessageType::DataTransferResponse,
+        )?;
+        let resp: DataTransferResponse = msg.unpack_payload(self.connection.encoding())?;
+        let data = match self.resolve_ordered(resp.data) {
+            Ok(data) => data,
+            Err(order_id) => {
+                let msg = self.request(
+                    DataTransferRequest {
+                        transfer_type: "SelectVarOrdered".to_string(),
+                        selection: vec![],
+                        precision: None,
+                        since_step: None,
+                        order_id: Some(order_id),
+                    },
+                    MessageType::DataTransferResponse,
+                )?;
+                let resp: DataTransferResponse = msg.unpack_payload(self.connection.encoding())?;
+                self.resolve_ordered(resp.data).map_err(|order_id| {
+                    Error::Other(format!("failed resyncing ordered set {}", order_id))
+                })?
+            }
+        };
+        match data {
+            TransferResponseData::VarOrdered(order_id, pack) => Ok((order_id, pack.vars)),
+            _ => Err(Error::Other("unexpected transfer response".to_string())),
+        }
+    }
+
+    /// Resolves ordered transfer data received from the server, including
+    /// pushed scheduled transfers, into whole ordered sets.
+    ///
+    /// Deltas are applied to the previously received set of the same order.
+    /// If there's no matching set, for example because an earlier transfer
+    /// was lost, a transfer of the whole set is requested and `None` is
+    /// returned, the set arrives as a regular `DataTransferResponse`. Other
+    /// data is returned unchanged.
+    pub fn apply_ordered(
+        &mut self,
+        data: TransferResponseData,
+    ) -> Result<Option<TransferResponseData>> {
+        match self.resolve_ordered(data) {
+            Ok(data) => Ok(Some(data)),
+            Err(order_id) => {
+                self.send_payload(
+                    DataTransferRequest {
+                        transfer_type: "SelectVarOrdered".to_string(),
+                        selection: vec![],
+                        precision: None,
+                        since_step: None,
+                        order_id: Some(order_id),
+                    },
+                    None,
+                )?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Applies ordered transfer data to the stored sets, returning the id
+    /// of the order that needs to be resynced if a delta can't be applied.
+    fn resolve_ordered(
+        &mut self,
+        data: TransferResponseData,
+    ) -> std::result::Result<TransferResponseData, u32> {
+        match data.expand() {
+            TransferResponseData::VarOrdered(order_id, pack) => {
+                self.ordered.insert(order_id, pack.clone());
+                Ok(TransferResponseData::VarOrdered(order_id, pack))
+            }
+            TransferResponseData::VarOrderedDelta(order_id, delta) => {
+                let pack = self.ordered.get_mut(&order_id).ok_or(order_id)?;
+                match pack.apply_delta(delta) {
+                    Ok(()) => Ok(TransferResponseData::VarOrdered(order_id, pack.clone())),
+                    Err(e) => {
+                        warn!("ordered set {} out of sync: {}", order_id, e);
+                        self.ordered.remove(&order_id);
+                        Err(order_id)
+                    }
+                }
+            }
+            data => Ok(data),
+        }
     }
 
     pub fn reg_scheduled_transfer(&mut self) -> Result<()> {
-        self.connection.send_payload(
+        self.send_payload(
             ScheduledDataTransferRequest {
                 event_triggers: vec!["step".to_string()],
                 transfer_type: "SelectVarOrdered".to_string(),
                 selection: vec!["*:position:float:x".to_string()],
+                precision: None,
             },
             None,
         )
     }
 
-    // blocking
+    /// Requests the server to overwrite the vars at the provided addresses.
+    pub fn pull_vars(&mut self, vars: FnvHashMap<Address, Var>) -> Result<DataPullResponse> {
+        let msg = self.request(
+            DataPullRequest {
+                data: PullRequestData::AddressedVars(vars),
+                idempotency_key: None,
+                units: Default::default(),
+            },
+            MessageType::DataPullResponse,
+        )?;
+        let resp: DataPullResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    /// Requests the server to spawn entities from the provided prefabs.
+    /// Names can be left empty.
+    pub fn spawn_entities(
+        &mut self,
+        prefabs: Vec<String>,
+        names: Vec<String>,
+    ) -> Result<SpawnEntitiesResponse> {
+        self.send_payload(
+            SpawnEntitiesRequest {
+                entity_prefabs: prefabs,
+                entity_names: names,
+                idempotency_key: None,
+            },
+            None,
+        )?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: SpawnEntitiesResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    /// Requests the server to despawn entities, each referenced either by
+    /// its integer id or its name.
+    pub fn despawn_entities(&mut self, entities: Vec<String>) -> Result<DespawnEntitiesResponse> {
+        self.send_payload(
+            DespawnEntitiesRequest {
+                entities,
+                idempotency_key: None,
+            },
+            None,
+        )?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: DespawnEntitiesResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    /// Requests the server to attach and detach components on the entity,
+    /// referenced either by its integer id or its name. Detaching is
+    /// performed first.
+    pub fn modify_entity(
+        &mut self,
+        entity: String,
+        attach: Vec<String>,
+        detach: Vec<String>,
+    ) -> Result<ModifyEntityResponse> {
+        self.send_payload(
+            ModifyEntityRequest {
+                entity,
+                attach,
+                detach,
+                idempotency_key: None,
+            },
+            None,
+        )?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: ModifyEntityResponse = msg.unpack_payload(self.connection.encoding())?;
+        Ok(resp)
+    }
+
+    /// Requests the server to export a snapshot and send it back, returning
+    /// the snapshot bytes. Compression is used for the transfer if
+    /// available.
     pub fn snapshot_request(&mut self, name: String, save_to_disk: bool) -> Result<Vec<u8>> {
-        let req = ExportSnapshotRequest {
-            name,
-            save_to_disk,
-            send_back: true,
+        self.export_snapshot(name, save_to_disk, cfg!(feature = "lz4"))
+    }
+
+    /// Requests the server to export a snapshot and send it back, blocking
+    /// until all the snapshot chunks are received.
+    ///
+    /// If `compress` is set the snapshot is compressed for the transfer,
+    /// returned bytes are always decompressed. Messages other than snapshot
+    /// chunks received in the meantime are discarded.
+    pub fn export_snapshot(
+        &mut self,
+        name: String,
+        save_to_disk: bool,
+        compress: bool,
+    ) -> Result<Vec<u8>> {
+        self.send_payload(
+            ExportSnapshotRequest {
+                name,
+                save_to_disk,
+                send_back: true,
+                chunk_size: None,
+                compress,
+            },
+            None,
+        )?;
+        let resp: ExportSnapshotResponse = loop {
+            let (_, msg) = self.recv_msg()?;
+            if msg.type_ == MessageType::ExportSnapshotResponse {
+                break msg.unpack_payload(self.connection.encoding())?;
+            }
         };
-        self.connection.send_payload(req, None)?;
-        println!("sent");
-        let (_, v) = self.connection.recv_msg()?;
-        println!("received: message type: {:?}", v.type_);
-        let resp: ExportSnapshotResponse = v.unpack_payload(self.connection.encoding())?;
-        Ok(resp.snapshot)
+        resp.error_result()?;
+
+        let mut bytes = Vec::with_capacity(resp.total_len as usize);
+        while (bytes.len() as u64) < resp.total_len {
+            let (_, msg) = self.recv_msg()?;
+            if msg.type_ == MessageType::ExportSnapshotResponse {
+                // sent in place of the remaining chunks if the export fails
+                let resp: ExportSnapshotResponse =
+                    msg.unpack_payload(self.connection.encoding())?;
+                resp.error_result()?;
+                continue;
+            }
+            if msg.type_ != MessageType::ExportSnapshotChunk {
+                continue;
+            }
+            let chunk: ExportSnapshotChunk = msg.unpack_payload(self.connection.encoding())?;
+            if chunk.offset != bytes.len() as u64 {
+                return Err(Error::Other(format!(
+                    "unexpected snapshot chunk offset: expected {}, got {}",
+                    bytes.len(),
+                    chunk.offset
+                )));
+            }
+            bytes.extend_from_slice(&chunk.chunk);
+        }
+
+        if resp.compressed {
+            #[cfg(feature = "lz4")]
+            {
+                bytes = lz4::block::decompress(&bytes, None)
+                    .map_err(|e| Error::Other(format!("failed decompressing snapshot: {}", e)))?;
+            }
+            #[cfg(not(feature = "lz4"))]
+            return Err(Error::Other(
+                "received compressed snapshot, lz4 feature not enabled".to_string(),
+            ));
+        }
+        Ok(bytes)
+    }
+
+    /// Uploads a snapshot to the server in chunks, restoring the server's
+    /// simulation from it once the upload is complete. Requires the admin
+    /// scope.
+    ///
+    /// Snapshot may be compressed. Each chunk is sent only after the
+    /// previous one was acknowledged by the server.
+    pub fn load_snapshot(&mut self, snapshot: &[u8], mode: SnapshotLoadMode) -> Result<()> {
+        let total_len = snapshot.len() as u64;
+        let mut offset = 0;
+        // empty snapshot still needs a single request to get rejected
+        let chunks: Vec<&[u8]> = match snapshot.is_empty() {
+            true => vec![snapshot],
+            false => snapshot.chunks(SNAPSHOT_CHUNK_SIZE).collect(),
+        };
+        for chunk in chunks {
+            self.send_payload(
+                LoadSnapshotRequest {
+                    total_len,
+                    offset,
+                    chunk: chunk.to_vec(),
+                    mode: mode.clone(),
+                },
+                None,
+            )?;
+            let (_, msg) = self.recv_msg()?;
+            let resp: LoadSnapshotResponse = msg.unpack_payload(self.connection.encoding())?;
+            resp.error_result()?;
+            offset = resp.received;
+        }
+        Ok(())
+    }
+
+    /// Starts a simulation on an idle server, using the scenario found at
+    /// the given path on the server. Requires the admin scope.
+    ///
+    /// Provided seed and settings take precedence over the ones from the
+    /// scenario manifest.
+    pub fn start_sim(
+        &mut self,
+        scenario: &str,
+        seed: Option<u64>,
+        settings: HashMap<String, String>,
+    ) -> Result<()> {
+        self.send_payload(
+            StartSimRequest {
+                scenario: scenario.to_string(),
+                seed,
+                settings,
+            },
+            None,
+        )?;
+        let (_, msg) = self.recv_msg()?;
+        let resp: StartSimResponse = msg.unpack_payload(self.connection.encoding())?;
+        resp.error_result()?;
+        Ok(())
+    }
+}
+
+/// Typed operations.
+///
+/// Each of these handles the full request/response round trip, surfacing
+/// errors reported by the server as `Err`. Addresses are given in the full
+/// `entity:component:type:var` form.
+///
+/// Requests are tagged with a task id and only the matching response is
+/// consumed, other messages received in the meantime are left for
+/// [`Client::recv_msg`].
+impl Client {
+    /// Advances the simulation by the given number of steps, returning once
+    /// the steps were processed.
+    pub fn step(&mut self, steps: u32) -> Result<()> {
+        let msg = self.request(
+            TurnAdvanceRequest {
+                step_count: steps,
+                wait: true,
+            },
+            MessageType::TurnAdvanceResponse,
+        )?;
+        let resp: TurnAdvanceResponse = msg.unpack_payload(self.connection.encoding())?;
+        resp.error_result()?;
+        Ok(())
+    }
+
+    /// Gets the value of the var at the given address.
+    pub fn get_var(&mut self, addr: &str) -> Result<Var> {
+        let addr = crate::msg::parse_var_address(addr)?;
+        self.get_var_at(&addr)
+    }
+
+    /// Sets the value of the var at the given address.
+    pub fn set_var(&mut self, addr: &str, var: Var) -> Result<()> {
+        let addr = crate::msg::parse_var_address(addr)?;
+        self.set_var_at(&addr, var)
+    }
+
+    /// Runs the query on the server, returning the product.
+    pub fn query(&mut self, query: outcome::Query) -> Result<QueryProduct> {
+        let resp = self.native_query(query)?;
+        match resp.error {
+            Some(e) => Err(ResponseError::from_fields(e, resp.code).into()),
+            None => Ok(resp.query_product),
+        }
+    }
+
+    fn get_var_at(&mut self, addr: &Address) -> Result<Var> {
+        let msg = self.request(
+            DataTransferRequest {
+                transfer_type: "SelectVar".to_string(),
+                selection: vec![addr.to_string()],
+                precision: None,
+                since_step: None,
+                order_id: None,
+            },
+            MessageType::DataTransferResponse,
+        )?;
+        let resp: DataTransferResponse = msg.unpack_payload(self.connection.encoding())?;
+        match resp.data.expand() {
+            TransferResponseData::AddressedVar(mut vars) => vars
+                .remove(addr)
+                .ok_or_else(|| Error::Other(format!("var not found: {}", addr))),
+            _ => Err(Error::Other("unexpected transfer response".to_string())),
+        }
+    }
+
+    fn set_var_at(&mut self, addr: &Address, var: Var) -> Result<()> {
+        let mut vars = FnvHashMap::default();
+        vars.insert(addr.clone(), var);
+        let resp = self.pull_vars(vars)?;
+        resp.error_result()?;
+        if let Some(rejected) = resp.rejected.first() {
+            return Err(Error::Other(format!("{:?}", rejected)));
+        }
+        Ok(())
+    }
+}
+
+/// Operations are performed remotely, each blocking until the server
+/// responds.
+impl SimInterface for Client {
+    fn get_clock(&mut self) -> outcome::Result<usize> {
+        Ok(self.server_status()?.current_tick)
+    }
+
+    fn get_var(&mut self, addr: &Address) -> outcome::Result<Var> {
+        Ok(self.get_var_at(addr)?)
+    }
+
+    fn set_var(&mut self, addr: &Address, var: Var) -> outcome::Result<()> {
+        Ok(self.set_var_at(addr, var)?)
+    }
+
+    fn get_entities_of_type(&mut self, type_: &[CompName]) -> outcome::Result<Vec<EntityId>> {
+        let resp = self.native_query(outcome::Query {
+            trigger: Trigger::Immediate,
+            description: Description::NativeDescribed,
+            layout: Layout::Var,
+            filters: vec![Filter::AllComponents(type_.to_vec())],
+            mappings: vec![Map::Components(type_.to_vec())],
+        })?;
+        if let Some(e) = resp.error {
+            return Err(Error::from(ResponseError::from_fields(e, resp.code)).into());
+        }
+        let mut ids = match resp.query_product {
+            QueryProduct::NativeAddressedVar(vars) => {
+                vars.keys().map(|(id, _, _)| *id).collect::<Vec<_>>()
+            }
+            _ => vec![],
+        };
+        ids.sort_unstable();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    /// Server only supports spawning entities from prefabs.
+    fn spawn_entity(
+        &mut self,
+        prefab: Option<PrefabName>,
+        name: Option<EntityName>,
+    ) -> outcome::Result<EntityId> {
+        let prefab = prefab.ok_or(outcome::error::Error::Other(
+            "remote spawning requires a prefab".to_string(),
+        ))?;
+        let resp = self.spawn_entities(
+            vec![prefab.to_string()],
+            vec![name.map(|n| n.to_string()).unwrap_or_default()],
+        )?;
+        resp.error_result().map_err(Error::from)?;
+        resp.entity_names
+            .first()
+            .and_then(|id| id.parse().ok())
+            .ok_or(outcome::error::Error::Other(
+                "server didn't return spawned entity id".to_string(),
+            ))
+    }
+
+    fn add_event(&mut self, name: EventName) -> outcome::Result<()> {
+        let resp = self.invoke_events(vec![name.to_string()])?;
+        resp.error_result().map_err(Error::from)?;
+        Ok(())
+    }
+
+    fn step(&mut self) -> outcome::Result<()> {
+        self.server_step_request(1)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn typed_operations_round_trip() {
+    use crate::harness::TestServer;
+
+    let model = outcome::SimModelBuilder::new()
+        .component("counter", |c| c.var("int:count", Var::Int(0)))
+        .prefab("thing", &["counter"])
+        .spawn("thing", Some("first"))
+        .build()
+        .unwrap();
+    let server = TestServer::start(model).unwrap();
+    let mut client = server.client().unwrap();
+    client
+        .set_var("first:counter:int:count", Var::Int(5))
+        .unwrap();
+    client.step(2).unwrap();
+    assert_eq!(
+        client.get_var("first:counter:int:count").unwrap(),
+        Var::Int(5)
+    );
+    client.disconnect().unwrap();
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn diff_reports_removed_entities() {
+    use crate::harness::TestServer;
+
+    let model = outcome::SimModelBuilder::new()
+        .component("counter", |c| c.var("int:count", Var::Int(0)))
+        .prefab("thing", &["counter"])
+        .spawn("thing", Some("first"))
+        .spawn("thing", Some("second"))
+        .build()
+        .unwrap();
+    let server = TestServer::start(model).unwrap();
+    let mut client = server.client().unwrap();
+    client.get_changed_vars(None).unwrap();
+    client.despawn_entities(vec!["second".to_string()]).unwrap();
+    client
+        .set_var("first:counter:int:count", Var::Int(5))
+        .unwrap();
+    match client.get_changed_vars(None).unwrap() {
+        TransferResponseData::VarDiff(pack, removed) => {
+            assert!(!removed.full);
+            assert_eq!(
+                removed.entities,
+                vec![outcome::string::new_truncate("second")]
+            );
+            assert_eq!(pack.vars.len(), 1);
+        }
+        _ => panic!("unexpected transfer response"),
+    }
+    client.disconnect().unwrap();
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn export_rejects_snapshot_names_escaping_snapshots_dir() {
+    use crate::harness::TestServer;
+
+    let model = outcome::SimModelBuilder::new()
+        .component("counter", |c| c.var("int:count", Var::Int(0)))
+        .prefab("thing", &["counter"])
+        .spawn("thing", Some("first"))
+        .build()
+        .unwrap();
+    let server = TestServer::start(model).unwrap();
+    let mut client = server.client().unwrap();
+    assert!(client
+        .export_snapshot("../escape".to_string(), true, false)
+        .is_err());
+    client.disconnect().unwrap();
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn ordered_deltas_are_applied_and_resynced() {
+    use crate::harness::TestServer;
+    use crate::msg::VarOrderedDelta;
+
+    let model = outcome::SimModelBuilder::new()
+        .component("counter", |c| c.var("int:count", Var::Int(0)))
+        .prefab("thing", &["counter"])
+        .spawn("thing", Some("first"))
+        .spawn("thing", Some("second"))
+        .build()
+        .unwrap();
+    let server = TestServer::start(model).unwrap();
+    let mut client = server.client().unwrap();
+    let selection = vec![
+        "first:counter:int:count".to_string(),
+        "second:counter:int:count".to_string(),
+    ];
+    let (order_id, vars) = client.get_vars_ordered(selection, None, true).unwrap();
+    assert_eq!(vars, vec![Var::Int(0), Var::Int(0)]);
+
+    client
+        .set_var("second:counter:int:count", Var::Int(3))
+        .unwrap();
+    let (_, vars) = client
+        .get_vars_ordered(vec![], Some(order_id), true)
+        .unwrap();
+    assert_eq!(vars, vec![Var::Int(0), Var::Int(3)]);
+
+    // delta that doesn't match the stored set triggers a resync
+    let delta = VarOrderedDelta {
+        len: 5,
+        changed: vec![],
+    };
+    assert!(client
+        .apply_ordered(TransferResponseData::VarOrderedDelta(order_id, delta))
+        .unwrap()
+        .is_none());
+    let resp: DataTransferResponse = client
+        .recv_msg()
+        .unwrap()
+        .1
+        .unpack_payload(client.connection.encoding())
+        .unwrap();
+    match client.apply_ordered(resp.data).unwrap() {
+        Some(TransferResponseData::VarOrdered(id, pack)) => {
+            assert_eq!(id, order_id);
+            assert_eq!(pack.vars, vec![Var::Int(0), Var::Int(3)]);
+        }
+        _ => panic!("expected whole ordered set"),
     }
+    client.disconnect().unwrap();
+    server.shutdown().unwrap();
 }
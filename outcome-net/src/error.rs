@@ -1,4 +1,4 @@
-use crate::msg::{Message, MessageType};
+use crate::msg::{ErrorCode, Message, MessageType, ResponseError};
 use crate::server::ClientId;
 use crate::{msg, Transport};
 use num_enum::TryFromPrimitiveError;
@@ -27,6 +27,18 @@ pub enum Error {
     HandshakeFailed(String),
     #[error("failed getting client by id: {0}")]
     FailedGettingClientById(ClientId),
+    #[error("signal #{0} not acknowledged after maximum number of retries")]
+    SignalDeliveryFailed(u32),
+    #[error("no simulation running, server is idle")]
+    SimNotStarted,
+    #[error("server under memory pressure ({0} bytes resident), refusing to spawn entities")]
+    MemoryPressure(u64),
+    #[error("request {0:?} rejected: {1}")]
+    RequestRejected(MessageType, ResponseError),
+    #[error("request failed: {0}")]
+    RequestFailed(ResponseError),
+    #[error("query incomplete, some of the workers failed to respond")]
+    IncompleteQuery,
 
     #[error("other: {0}")]
     Other(String),
@@ -64,7 +76,11 @@ pub enum Error {
     #[error("rmp_serde encode error")]
     RmpsEncodeError(#[from] rmp_serde::encode::Error),
 
-    #[cfg(feature = "json_encoding")]
+    #[cfg(any(
+        feature = "json_encoding",
+        feature = "kafka_sink",
+        feature = "nats_sink"
+    ))]
     #[error("serde_json error: {0}")]
     JsonError(#[from] serde_json::Error),
 
@@ -80,3 +96,29 @@ pub enum Error {
     #[error("unknown error")]
     Unknown,
 }
+
+impl Error {
+    /// Returns the code that would be reported to clients for this error.
+    pub fn code(&self) -> ErrorCode {
+        self.into()
+    }
+}
+
+impl From<ResponseError> for Error {
+    fn from(e: ResponseError) -> Self {
+        Error::RequestFailed(e)
+    }
+}
+
+impl From<Error> for outcome_core::error::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::CoreError(e) => e,
+            Error::WouldBlock => outcome_core::error::Error::WouldBlock,
+            e @ Error::SignalDeliveryFailed(_) => {
+                outcome_core::error::Error::NetworkError(e.to_string())
+            }
+            e => outcome_core::error::Error::Other(e.to_string()),
+        }
+    }
+}
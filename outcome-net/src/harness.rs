@@ -0,0 +1,392 @@
+//! Harness for end-to-end tests of the distributed setup.
+//!
+//! Unlike the test doubles from the [`mock`] module, [`TestCluster`] runs
+//! the real thing: an [`Organizer`] with a number of [`Worker`]s, and a
+//! [`Server`] on top, all communicating over localhost sockets. Each
+//! participant runs on its own thread within the test process, so there's
+//! no need for any binaries to be built beforehand.
+//!
+//! Tests drive the cluster using regular [`Client`]s, either directly or
+//! running scripted in the background. Once the cluster is shut down,
+//! errors encountered by any of the participants are reported.
+//!
+//! ```
+//! use outcome_core::{SimModelBuilder, Var};
+//! use outcome_net::harness::TestCluster;
+//!
+//! # fn main() -> outcome_net::Result<()> {
+//! let model = SimModelBuilder::new()
+//!     .component("counter", |c| c.var("int:count", Var::Int(0)))
+//!     .build()?;
+//! let cluster = TestCluster::start(model, 2)?;
+//!
+//! let mut client = cluster.client()?;
+//! client.step(10)?;
+//! assert_eq!(client.server_status()?.current_tick, 10);
+//!
+//! cluster.shutdown()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! For testing server behavior that doesn't depend on the distributed
+//! setup, [`TestServer`] runs a server backed by a local simulation.
+//!
+//! [`mock`]: crate::mock
+//! [`Organizer`]: crate::Organizer
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use outcome::distr::SimCentral;
+use outcome::{Sim, SimModel};
+
+use crate::{
+    Client, ClientConfig, Error, Organizer, Result, Server, ServerConfig, SimConnection, Worker,
+};
+
+/// Test cluster configuration.
+pub struct ClusterConfig {
+    /// Number of workers to start
+    pub workers: usize,
+    /// Config of the server fronting the cluster
+    pub server: ServerConfig,
+    /// Max time to wait for all the participants to get up and running
+    pub startup_timeout: Duration,
+    /// Time between polls of the worker sockets
+    pub worker_poll_wait: Duration,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            workers: 1,
+            server: ServerConfig::default(),
+            startup_timeout: Duration::from_secs(5),
+            worker_poll_wait: Duration::from_millis(1),
+        }
+    }
+}
+
+/// Organizer, workers and server running in the current process.
+///
+/// Participants are stopped when the cluster is dropped, use
+/// [`TestCluster::shutdown`] to also learn about any errors they
+/// encountered.
+pub struct TestCluster {
+    server_addr: String,
+    worker_addrs: Vec<String>,
+    running: Arc<AtomicBool>,
+    threads: Vec<(String, JoinHandle<Result<()>>)>,
+}
+
+impl TestCluster {
+    /// Starts a cluster running the model, with the given number of
+    /// workers and default config otherwise.
+    pub fn start(model: SimModel, workers: usize) -> Result<Self> {
+        Self::start_with_config(
+            model,
+            ClusterConfig {
+                workers,
+                ..ClusterConfig::default()
+            },
+        )
+    }
+
+    /// Starts a cluster running the model using the provided config.
+    pub fn start_with_config(model: SimModel, config: ClusterConfig) -> Result<Self> {
+        let central = SimCentral::from_model(model, None)?;
+        Self::start_with_central(central, config)
+    }
+
+    /// Starts a cluster coordinated by an already created central.
+    pub fn start_with_central(central: SimCentral, config: ClusterConfig) -> Result<Self> {
+        let mut cluster = Self {
+            server_addr: String::new(),
+            worker_addrs: Vec::new(),
+            running: Arc::new(AtomicBool::new(true)),
+            threads: Vec::new(),
+        };
+
+        // workers need to be listening before the organizer reaches out
+        // to them
+        for n in 0..config.workers {
+            let (sender, receiver) = channel();
+            let running = cluster.running.clone();
+            let poll_wait = config.worker_poll_wait;
+            let handle = thread::spawn(move || run_worker(sender, running, poll_wait));
+            cluster.threads.push((format!("worker {}", n), handle));
+            let addr = receiver
+                .recv_timeout(config.startup_timeout)
+                .map_err(|_| Error::Other(format!("worker {} failed to start", n)))?;
+            cluster.worker_addrs.push(addr);
+        }
+
+        let (sender, receiver) = channel();
+        let running = cluster.running.clone();
+        let worker_addrs = cluster.worker_addrs.clone();
+        let server_config = config.server;
+        let handle = thread::spawn(move || {
+            run_server(central, worker_addrs, server_config, sender, running)
+        });
+        cluster.threads.push(("server".to_string(), handle));
+        cluster.server_addr = receiver
+            .recv_timeout(config.startup_timeout)
+            .map_err(|_| Error::Other("server failed to start".to_string()))?;
+
+        Ok(cluster)
+    }
+
+    /// Address at which clients can connect to the server.
+    pub fn server_addr(&self) -> &str {
+        &self.server_addr
+    }
+
+    /// Addresses of the workers making up the cluster.
+    pub fn worker_addrs(&self) -> &[String] {
+        &self.worker_addrs
+    }
+
+    /// Creates a new client connected to the server.
+    pub fn client(&self) -> Result<Client> {
+        self.client_with_config(ClientConfig::default())
+    }
+
+    /// Creates a new client connected to the server, using the provided
+    /// config.
+    pub fn client_with_config(&self, config: ClientConfig) -> Result<Client> {
+        let mut client = Client::new_with_config(config)?;
+        client.connect(&self.server_addr, None)?;
+        Ok(client)
+    }
+
+    /// Runs a scripted client on a separate thread. The script is given a
+    /// client already connected to the server.
+    ///
+    /// The returned handle can be joined to get the result of the script.
+    pub fn run_client<F, T>(&self, config: ClientConfig, script: F) -> JoinHandle<Result<T>>
+    where
+        F: FnOnce(&mut Client) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let addr = self.server_addr.clone();
+        thread::spawn(move || {
+            let mut client = Client::new_with_config(config)?;
+            client.connect(&addr, None)?;
+            let result = script(&mut client);
+            client.disconnect()?;
+            result
+        })
+    }
+
+    /// Stops all the participants, returning the first error encountered
+    /// by any of them.
+    pub fn shutdown(mut self) -> Result<()> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.running.store(false, Ordering::SeqCst);
+        let mut result = Ok(());
+        // server goes down first, as it owns the organizer
+        for (name, handle) in self.threads.drain(..).rev() {
+            let outcome = match handle.join() {
+                Ok(r) => r,
+                Err(_) => Err(Error::Other(format!("{} panicked", name))),
+            };
+            if let Err(e) = outcome {
+                warn!("test cluster: {} failed: {}", name, e);
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Drop for TestCluster {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// Server backed by a local simulation, or idle, running in the current
+/// process.
+///
+/// The server is stopped when dropped, use [`TestServer::shutdown`] to
+/// also learn about any error it encountered.
+pub struct TestServer {
+    server_addr: String,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl TestServer {
+    /// Starts a server running the model, with default config.
+    pub fn start(model: SimModel) -> Result<Self> {
+        Self::start_with_config(Sim::from_model(model)?, ServerConfig::default())
+    }
+
+    /// Starts a server running an already created simulation, using the
+    /// provided config.
+    pub fn start_with_config(sim: Sim, config: ServerConfig) -> Result<Self> {
+        Self::start_with_connection(SimConnection::Local(sim), config)
+    }
+
+    /// Starts an idle server, waiting for a client to start a simulation.
+    pub fn start_idle(config: ServerConfig) -> Result<Self> {
+        Self::start_with_connection(SimConnection::Idle, config)
+    }
+
+    fn start_with_connection(sim: SimConnection, config: ServerConfig) -> Result<Self> {
+        let (sender, receiver) = channel();
+        let running = Arc::new(AtomicBool::new(true));
+        let _running = running.clone();
+        let thread = thread::spawn(move || run_server_with(sim, config, sender, _running));
+        let server_addr = match receiver.recv_timeout(Duration::from_secs(5)) {
+            Ok(addr) => addr,
+            Err(_) => {
+                running.store(false, Ordering::SeqCst);
+                return match thread.join() {
+                    Ok(Err(e)) => Err(e),
+                    _ => Err(Error::Other("server failed to start".to_string())),
+                };
+            }
+        };
+        Ok(Self {
+            server_addr,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    /// Address at which clients can connect to the server.
+    pub fn server_addr(&self) -> &str {
+        &self.server_addr
+    }
+
+    /// Creates a new client connected to the server.
+    pub fn client(&self) -> Result<Client> {
+        self.client_with_config(ClientConfig::default())
+    }
+
+    /// Creates a new client connected to the server, using the provided
+    /// config.
+    pub fn client_with_config(&self, config: ClientConfig) -> Result<Client> {
+        let mut client = Client::new_with_config(config)?;
+        client.connect(&self.server_addr, None)?;
+        Ok(client)
+    }
+
+    /// Stops the server, returning the error it encountered, if any.
+    pub fn shutdown(mut self) -> Result<()> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.running.store(false, Ordering::SeqCst);
+        match self.thread.take().map(|handle| handle.join()) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(Error::Other("server panicked".to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// Runs a worker until the cluster is stopped, reporting its address once
+/// it's listening.
+pub(crate) fn run_worker(
+    addr: Sender<String>,
+    running: Arc<AtomicBool>,
+    poll_wait: Duration,
+) -> Result<()> {
+    let mut worker = Worker::new(Some("127.0.0.1:0"))?;
+    let _ = addr.send(worker.addr.clone());
+    // the organizer might never show up if the cluster fails to start
+    while !worker.try_handle_coordinator()? {
+        if !running.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        thread::sleep(poll_wait);
+    }
+    while running.load(Ordering::SeqCst) {
+        worker.manual_poll()?;
+        thread::sleep(poll_wait);
+    }
+    Ok(())
+}
+
+/// Runs the organizer along with the server until the cluster is stopped,
+/// reporting the server address once it's listening.
+fn run_server(
+    central: SimCentral,
+    worker_addrs: Vec<String>,
+    config: ServerConfig,
+    addr: Sender<String>,
+    running: Arc<AtomicBool>,
+) -> Result<()> {
+    let organizer = Organizer::new(central, "127.0.0.1:0", worker_addrs)?;
+    run_server_with(
+        SimConnection::UnionOrganizer(organizer),
+        config,
+        addr,
+        running,
+    )
+}
+
+/// Runs a server on top of the given simulation connection until stopped,
+/// reporting the server address once it's listening.
+fn run_server_with(
+    sim: SimConnection,
+    config: ServerConfig,
+    addr: Sender<String>,
+    running: Arc<AtomicBool>,
+) -> Result<()> {
+    let mut server = Server::new_with_config("tcp://127.0.0.1:0", config, sim)?;
+    server.initialize_services()?;
+    let greeter = server
+        .greeters
+        .first()
+        .ok_or_else(|| Error::Other("server has no listeners".to_string()))?;
+    let _ = addr.send(greeter.listener_addr()?.to_string());
+    let result = server.start_polling(running);
+    server.cleanup()?;
+    result
+}
+
+#[test]
+fn local_server_steps() {
+    use outcome::{SimModelBuilder, Var};
+
+    let model = SimModelBuilder::new()
+        .component("counter", |c| c.var("int:count", Var::Int(0)))
+        .build()
+        .unwrap();
+    let server = TestServer::start(model).unwrap();
+    let mut client = server.client().unwrap();
+    client.step(3).unwrap();
+    assert_eq!(client.server_status().unwrap().current_tick, 3);
+    client.disconnect().unwrap();
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn cluster_shuts_down_before_organizer_connects() {
+    let (sender, receiver) = channel();
+    let running = Arc::new(AtomicBool::new(true));
+    let _running = running.clone();
+    let handle = thread::spawn(move || run_worker(sender, _running, Duration::from_millis(1)));
+    receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    running.store(false, Ordering::SeqCst);
+    assert!(handle.join().unwrap().is_ok());
+}
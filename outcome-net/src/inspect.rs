@@ -0,0 +1,66 @@
+//! Low-level inspection of organizers and workers.
+//!
+//! [`Inspector`] connects directly to the greeter socket of an [`Organizer`]
+//! or a [`Worker`], bypassing the server layer. It's intended for debugging
+//! simulation unions, allowing to look at things like entities stored on
+//! a particular node, entity routing or unfinished tasks.
+//!
+//! Workers additionally accept a restricted set of text diagnostics
+//! commands, see [`Worker::diagnose`].
+//!
+//! [`Organizer`]: crate::Organizer
+//! [`Worker`]: crate::Worker
+//! [`Worker::diagnose`]: crate::Worker::diagnose
+
+use crate::msg::coord_worker::{
+    DiagnosticsRequest, DiagnosticsResponse, InspectReport, InspectRequest, InspectResponse,
+    InspectTarget,
+};
+use crate::socket::{Socket, Transport};
+use crate::{Error, Result};
+
+/// Connection to an organizer or worker used for inspecting it's state.
+pub struct Inspector {
+    connection: Socket,
+}
+
+impl Inspector {
+    /// Connects to the greeter socket at the given address.
+    pub fn connect(addr: &str) -> Result<Self> {
+        let mut connection = Socket::new(None, Transport::Tcp)?;
+        connection.connect(addr.parse()?)?;
+        Ok(Inspector { connection })
+    }
+
+    /// Requests a report on the target.
+    pub fn inspect(&mut self, target: InspectTarget) -> Result<InspectReport> {
+        self.connection
+            .send_payload(InspectRequest { target }, None)?;
+        let (_, msg) = self.connection.recv_msg()?;
+        let resp: InspectResponse = msg.unpack_payload(self.connection.encoding())?;
+        if !resp.error.is_empty() {
+            return Err(Error::Other(resp.error));
+        }
+        Ok(resp.report)
+    }
+
+    /// Runs a diagnostics command on a worker, returning it's output.
+    pub fn diagnostics(&mut self, command: &str) -> Result<String> {
+        self.connection.send_payload(
+            DiagnosticsRequest {
+                command: command.to_string(),
+            },
+            None,
+        )?;
+        let (_, msg) = self.connection.recv_msg()?;
+        let resp: DiagnosticsResponse = msg.unpack_payload(self.connection.encoding())?;
+        if !resp.error.is_empty() {
+            return Err(Error::Other(resp.error));
+        }
+        Ok(resp.output)
+    }
+
+    pub fn disconnect(&mut self) -> Result<()> {
+        self.connection.disconnect(None)
+    }
+}
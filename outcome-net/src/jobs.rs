@@ -0,0 +1,436 @@
+//! Running queues of simulation jobs on a standing cluster.
+//!
+//! Organizer can be given a queue of jobs, each describing a single run:
+//! the scenario to use, overrides for it's seed and settings, and the number
+//! of steps to process. Jobs are run one after another, reusing the workers
+//! already connected to the organizer. Starting a job tears down the state
+//! left over from the previous run, both on the organizer and on the
+//! workers.
+//!
+//! Once a job reaches it's step budget, results are collected into
+//! a [`JobResult`], including the run summary and the products of any
+//! queries attached to the job. Steps are processed continuously while a job
+//! is running, regardless of the configured step trigger.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use outcome::distr::{Signal, SimCentral};
+use outcome::model::Scenario;
+use outcome::sim::stats::RunSummary;
+use outcome::{Query, QueryProduct, SimModel, SimStarter};
+
+use crate::organizer::{Organizer, OrganizerTask};
+use crate::{sig, Result, TaskId};
+
+/// Single simulation run to be processed by the organizer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    /// Name identifying the job among the results
+    pub name: String,
+    /// Path to the scenario
+    pub scenario: String,
+    /// Seed overriding the one found in the scenario manifest
+    pub seed: Option<u64>,
+    /// Settings overriding the ones found in the scenario manifest
+    pub settings: HashMap<String, String>,
+    /// Number of steps to process before the run is finished
+    pub steps: usize,
+    /// Queries evaluated once the run is finished
+    pub collect: Vec<Query>,
+}
+
+/// Outcome of a single finished job.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobResult {
+    /// Name of the job
+    pub name: String,
+    /// Clock at which the run was finished
+    pub clock: usize,
+    /// Summary of the run, missing if the job failed to start
+    pub summary: Option<RunSummary>,
+    /// Products of the queries attached to the job, in order, with each
+    /// query producing one product per worker
+    pub products: Vec<Vec<QueryProduct>>,
+    /// Error that caused the job to fail, if any
+    pub error: Option<String>,
+}
+
+/// Job currently being processed.
+pub(crate) struct ActiveJob {
+    job: Job,
+    stage: JobStage,
+    products: Vec<Vec<QueryProduct>>,
+    /// Indices of queries some of the workers failed to respond to
+    incomplete: Vec<usize>,
+}
+
+enum JobStage {
+    /// Steps are being processed until the step budget is reached
+    Running,
+    /// Waiting for workers to respond to the query at the given index
+    Collecting(usize, TaskId),
+}
+
+impl Organizer {
+    /// Adds the job to the end of the queue.
+    pub fn enqueue_job(&mut self, job: Job) {
+        info!("queued job: {}", job.name);
+        self.jobs.push_back(job);
+    }
+
+    /// Checks whether a job is currently running and needs more steps.
+    pub(crate) fn job_wants_step(&self) -> bool {
+        match &self.active_job {
+            Some(active) => match active.stage {
+                JobStage::Running => self.central.clock < active.job.steps,
+                JobStage::Collecting(..) => false,
+            },
+            None => false,
+        }
+    }
+
+    /// Advances the job queue, starting new jobs and collecting results of
+    /// the finished ones. Called on each organizer poll.
+    pub(crate) fn progress_jobs(&mut self) -> Result<()> {
+        let mut active = match self.active_job.take() {
+            Some(a) => a,
+            None => {
+                if self.net.workers.is_empty() {
+                    return Ok(());
+                }
+                let job = match self.jobs.pop_front() {
+                    Some(j) => j,
+                    None => return Ok(()),
+                };
+                if let Err(e) = self.start_job(&job) {
+                    error!("failed starting job {}: {}", job.name, e);
+                    self.job_results.push(JobResult {
+                        name: job.name,
+                        clock: 0,
+                        summary: None,
+                        products: vec![],
+                        error: Some(e.to_string()),
+                    });
+                    return Ok(());
+                }
+                ActiveJob {
+                    job,
+                    stage: JobStage::Running,
+                    products: vec![],
+                    incomplete: vec![],
+                }
+            }
+        };
+
+        if let Err(e) = self.advance_job(&mut active) {
+            self.fail_job(active, e.to_string());
+            return Ok(());
+        }
+
+        if let JobStage::Running = active.stage {
+            if self.central.clock >= active.job.steps {
+                info!("finished job: {}", active.job.name);
+                self.job_results.push(JobResult {
+                    name: active.job.name,
+                    clock: self.central.clock,
+                    summary: Some(self.run_stats.summary()),
+                    products: active.products,
+                    error: match active.incomplete.is_empty() {
+                        true => None,
+                        false => Some(format!(
+                            "incomplete query products at indices: {:?}",
+                            active.incomplete
+                        )),
+                    },
+                });
+                return Ok(());
+            }
+        }
+        self.active_job = Some(active);
+        Ok(())
+    }
+
+    /// Fails the currently running job, if any, storing a result with the
+    /// given error.
+    pub(crate) fn fail_active_job(&mut self, error: String) {
+        if let Some(active) = self.active_job.take() {
+            self.fail_job(active, error);
+        }
+    }
+
+    fn fail_job(&mut self, active: ActiveJob, error: String) {
+        error!("job {} failed: {}", active.job.name, error);
+        if let JobStage::Collecting(_, task_id) = active.stage {
+            if let Err(e) = self.unregister_task(task_id) {
+                warn!("failed unregistering task {}: {}", task_id, e);
+            }
+        }
+        self.job_results.push(JobResult {
+            name: active.job.name,
+            clock: self.central.clock,
+            summary: Some(self.run_stats.summary()),
+            products: active.products,
+            error: Some(error),
+        });
+    }
+
+    /// Moves the job on to collecting query products once it reaches it's
+    /// step budget, and from one query to the next as the workers respond.
+    fn advance_job(&mut self, active: &mut ActiveJob) -> Result<()> {
+        match active.stage {
+            JobStage::Running if self.central.clock >= active.job.steps => {
+                active.stage = self.collect_query(&active.job, 0)?;
+            }
+            JobStage::Collecting(idx, task_id) => {
+                if self.tasks.get(&task_id).map_or(true, |t| t.is_finished()) {
+                    if let Some(OrganizerTask::WaitForQueryResponses {
+                        products, partial, ..
+                    }) = self.tasks.remove(&task_id)
+                    {
+                        if partial {
+                            warn!("job {}: query {} is incomplete", active.job.name, idx);
+                            active.incomplete.push(idx);
+                        }
+                        active.products.push(products);
+                    }
+                    // the task is already gone, make sure it's not cleaned
+                    // up again if anything below fails
+                    active.stage = JobStage::Running;
+                    self.unregister_task(task_id)?;
+                    active.stage = self.collect_query(&active.job, idx + 1)?;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Broadcasts the query at the given index to the workers, returning
+    /// the resulting stage. If there are no more queries to collect the job
+    /// stays in the running stage.
+    fn collect_query(&mut self, job: &Job, idx: usize) -> Result<JobStage> {
+        let query = match job.collect.get(idx) {
+            Some(q) => q.clone(),
+            None => return Ok(JobStage::Running),
+        };
+        let task_id = self.register_task(OrganizerTask::WaitForQueryResponses {
+            remaining: self.net.workers.len() as u32,
+            products: vec![],
+            responded: vec![],
+            partial: false,
+        })?;
+        self.net
+            .broadcast(sig::Signal::from(task_id, Signal::QueryRequest(query)));
+        Ok(JobStage::Collecting(idx, task_id))
+    }
+
+    /// Replaces the current simulation with a new one based on the job,
+    /// reinitializing all the connected workers.
+    fn start_job(&mut self, job: &Job) -> Result<()> {
+        info!("starting job: {}", job.name);
+        let scenario_path = PathBuf::from(&job.scenario);
+        let mut scenario = Scenario::from_path(scenario_path.clone())?;
+        if let Some(seed) = job.seed {
+            scenario.manifest.seed = seed;
+        }
+        scenario.manifest.settings.extend(job.settings.clone());
+        let model = SimModel::from_scenario(scenario)?;
+        let starter = SimStarter::Scenario(
+            scenario_path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or(&job.scenario)
+                .to_string(),
+        );
+        self.central = SimCentral::from_model(model, Some(starter))?;
+
+        // tear down state left over from the previous run, workers replace
+        // their nodes once initialized with the new model
+        self.net.routing_table.clear();
+        self.run_stats = Default::default();
+        self.initialized = false;
+        let worker_ids = self.net.workers.keys().cloned().collect::<Vec<_>>();
+        for worker_id in &worker_ids {
+            self.net
+                .workers
+                .get_mut(worker_id)
+                .unwrap()
+                .entities
+                .clear();
+            self.central.node_entities.insert(*worker_id, Vec::new());
+        }
+        for worker_id in &worker_ids {
+            self.initialize_worker_node(worker_id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a scenario without any mods to a temporary project directory,
+/// returning the path to the scenario manifest.
+#[cfg(test)]
+fn empty_scenario(project: &str) -> String {
+    let dir = std::env::temp_dir().join(project).join(outcome::SCENARIOS_DIR_NAME);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("empty.toml");
+    std::fs::write(
+        &path,
+        "[scenario]\nname = \"empty\"\nversion = \"0.1.0\"\nengine = \"*\"\n",
+    )
+    .unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[cfg(test)]
+fn job(name: &str, scenario: &str, steps: usize) -> Job {
+    Job {
+        name: name.to_string(),
+        scenario: scenario.to_string(),
+        seed: None,
+        settings: HashMap::new(),
+        steps,
+        collect: vec![],
+    }
+}
+
+#[cfg(test)]
+fn unreachable_worker() -> crate::organizer::Worker {
+    use crate::socket::{Socket, SocketAddress, Transport};
+
+    crate::organizer::Worker {
+        address: SocketAddress::Unavailable,
+        entities: vec![],
+        connection: Socket::new(None, Transport::Tcp).unwrap(),
+        is_blocking_step: false,
+        last_seen: std::time::Instant::now(),
+    }
+}
+
+#[test]
+fn failed_jobs_are_reported_in_queue_order() {
+    let model = outcome::SimModelBuilder::new().build().unwrap();
+    let central = SimCentral::from_model(model, None).unwrap();
+    let mut organ = Organizer::new_at_any(central, vec![]).unwrap();
+    organ.enqueue_job(job("first", "missing/scenarios/first.toml", 1));
+    organ.enqueue_job(job("second", "missing/scenarios/second.toml", 1));
+
+    // jobs wait for workers to show up
+    organ.progress_jobs().unwrap();
+    assert_eq!(organ.jobs.len(), 2);
+    assert!(organ.job_results.is_empty());
+
+    organ.net.workers.insert(0, unreachable_worker());
+    organ.progress_jobs().unwrap();
+    organ.progress_jobs().unwrap();
+    assert!(organ.jobs.is_empty());
+    assert!(organ.active_job.is_none());
+    assert_eq!(
+        organ
+            .job_results
+            .iter()
+            .map(|r| r.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["first", "second"]
+    );
+    for result in &organ.job_results {
+        assert!(result.summary.is_none());
+        assert!(result.error.is_some());
+    }
+}
+
+#[test]
+fn starting_job_tears_down_previous_run() {
+    let model = outcome::SimModelBuilder::new().build().unwrap();
+    let central = SimCentral::from_model(model, None).unwrap();
+    let mut organ = Organizer::new_at_any(central, vec![]).unwrap();
+    let mut worker = unreachable_worker();
+    worker.entities = vec![1, 2];
+    organ.net.workers.insert(0, worker);
+    organ.net.routing_table.insert(1, 0);
+    organ.net.routing_table.insert(2, 0);
+    organ.central.node_entities.insert(0, vec![1, 2]);
+    organ.initialized = true;
+
+    let scenario = empty_scenario("outcome_jobs_teardown");
+    organ.enqueue_job(job("teardown", &scenario, 1));
+    organ.progress_jobs().unwrap();
+
+    // the worker can't be reached, but the state left over from
+    // the previous run is gone regardless
+    assert!(organ.job_results[0].error.is_some());
+    assert!(organ.net.workers[&0].entities.is_empty());
+    assert!(organ.net.routing_table.is_empty());
+    assert_eq!(organ.central.node_entities.get(&0), Some(&vec![]));
+    assert!(!organ.initialized);
+}
+
+#[test]
+fn jobs_reuse_workers_and_collect_results() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use outcome::query::{Description, Layout, Map, Trigger};
+
+    let (sender, receiver) = channel();
+    let running = Arc::new(AtomicBool::new(true));
+    let _running = running.clone();
+    let handle = thread::spawn(move || {
+        crate::harness::run_worker(sender, _running, Duration::from_millis(1))
+    });
+    let worker_addr = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    let model = outcome::SimModelBuilder::new().build().unwrap();
+    let central = SimCentral::from_model(model, None).unwrap();
+    let mut organ = Organizer::new(central, "127.0.0.1:0", vec![worker_addr]).unwrap();
+    let worker_ids = organ.net.workers.keys().cloned().collect::<Vec<_>>();
+
+    let scenario = empty_scenario("outcome_jobs_cluster");
+    let query = Query {
+        trigger: Trigger::Immediate,
+        description: Description::NativeDescribed,
+        layout: Layout::Var,
+        filters: vec![],
+        mappings: vec![Map::All],
+    };
+    let mut first = job("first", &scenario, 3);
+    first.collect = vec![query.clone()];
+    let mut second = job("second", &scenario, 5);
+    second.seed = Some(7);
+    second.collect = vec![query.clone(), query];
+    organ.enqueue_job(first);
+    organ.enqueue_job(second);
+
+    let started = Instant::now();
+    while organ.job_results.len() < 2 && started.elapsed() < Duration::from_secs(10) {
+        organ.manual_poll().unwrap();
+        thread::sleep(Duration::from_millis(1));
+    }
+    running.store(false, Ordering::SeqCst);
+    assert!(handle.join().unwrap().is_ok());
+
+    assert_eq!(organ.job_results.len(), 2);
+    let (first, second) = (&organ.job_results[0], &organ.job_results[1]);
+    assert_eq!(first.name, "first");
+    assert_eq!(second.name, "second");
+    // each run starts from scratch instead of carrying on the previous one
+    assert_eq!(first.clock, 3);
+    assert_eq!(second.clock, 5);
+    for (result, queries) in &[(first, 1), (second, 2)] {
+        assert!(result.error.is_none(), "{:?}", result.error);
+        assert!(result.summary.is_some());
+        assert_eq!(result.products.len(), *queries);
+        for products in &result.products {
+            // one product per worker
+            assert_eq!(products.len(), 1);
+        }
+    }
+    assert!(organ.tasks.is_empty());
+    assert_eq!(
+        organ.net.workers.keys().cloned().collect::<Vec<_>>(),
+        worker_ids
+    );
+}
@@ -60,6 +60,47 @@
 //! [`Worker`]. This way you could skip some of the *IPC* overhead and gain
 //! direct access to the entities stored on the node attached to that worker.
 //!
+//! For many use cases forking isn't necessary, as workers can load plugins
+//! declared in their config file. Plugins can hook into step processing,
+//! register custom query filters and maps, and provide services. See the
+//! [`plugin`] module for details (requires the `worker_plugins` feature).
+//!
+//!
+//! # Testing integrations
+//!
+//! Services can be tested without running a simulation using the test
+//! doubles provided in the [`mock`] module (requires the `mock` feature).
+//!
+//! For testing the distributed protocols themselves, the [`harness`] module
+//! can spin up a whole cluster within a single test process (requires the
+//! `harness` feature).
+//!
+//!
+//! # API stability
+//!
+//! Items available through the [`prelude`] are considered the dependable
+//! surface of the library. Server state is best accessed using the provided
+//! accessors, such as [`Server::clients_iter`], rather than through the raw
+//! fields backing it.
+//!
+//! Raw internals backing the state are not covered by semver guarantees
+//! and may change without a major version bump. They're only made public
+//! with the `unstable` feature enabled.
+//!
+//!
+//! # Tracing requests
+//!
+//! Messages and signals carry a correlation id, allowing a single client
+//! request to be followed through the server, organizer and workers in
+//! the logs. See the [`trace`] module.
+//!
+//!
+//! # Monitoring
+//!
+//! Servers, organizers and workers can expose metrics for scraping by
+//! Prometheus. See the [`metrics`] module (requires the `metrics`
+//! feature).
+//!
 //!
 //! # Using different transports and encodings
 //!
@@ -70,6 +111,9 @@
 //! [`Server`]s can support multiple transports and encodings at once, allowing
 //! connections from widely different [`Client`]s.
 //!
+//! With the `websocket_transport` feature, browser-based clients can connect
+//! to a [`Server`] directly, using addresses like `ws://127.0.0.1:9123`.
+//!
 //!
 //! [`SimCentral`]: outcome_core::distr::SimCentral
 //! [`SimNode`]: outcome_core::distr::SimNode
@@ -90,24 +134,47 @@ pub use socket::Encoding;
 pub use socket::Transport;
 pub use socket::{SocketEvent, SocketEventType};
 
-pub use client::{Client, ClientConfig, CompressionPolicy};
-pub use server::{Server, ServerConfig, SimConnection};
+pub use client::{Client, ClientConfig, CompressionPolicy, RetryPolicy};
+pub use server::{ClientHandle, ClientId, Permission, Server, ServerConfig, SimConnection};
+#[cfg(any(feature = "kafka_sink", feature = "nats_sink"))]
+pub use server::{SinkBackend, SinkConfig};
+pub use subscriber::{Published, Subscriber};
 
-pub use organizer::Organizer;
+pub use checkpoint::{CheckpointStore, DirStore};
+pub use inspect::Inspector;
+pub use jobs::{Job, JobResult};
+pub use organizer::{DeadWorkerPolicy, Organizer, StepTrigger};
 pub use relay::Relay;
 pub use worker::Worker;
 
+#[cfg(any(test, feature = "harness"))]
+pub mod harness;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "mock")]
+pub mod mock;
+#[cfg(feature = "mqtt_bridge")]
+pub mod mqtt;
 pub mod msg;
+#[cfg(feature = "worker_plugins")]
+pub mod plugin;
+pub mod prelude;
+
+pub mod trace;
 
 mod sig;
 
+mod checkpoint;
 mod client;
 mod error;
+mod inspect;
+mod jobs;
 mod organizer;
 mod relay;
 mod server;
 mod service;
 mod socket;
+mod subscriber;
 mod util;
 mod worker;
 
@@ -0,0 +1,350 @@
+//! Prometheus metrics.
+//!
+//! Servers, organizers and workers can track basic metrics about their
+//! operation and expose them over HTTP, in the Prometheus text format,
+//! for scraping at the `/metrics` path.
+//!
+//! ```ignore
+//! let mut config = ServerConfig::default();
+//! config.metrics_address = Some("0.0.0.0:9100".to_string());
+//! ```
+//!
+//! Organizers and workers enable the endpoint using `enable_metrics`.
+//!
+//! Tracked metrics include received messages and signals by type, bytes
+//! sent to and received from each connection, step durations and entity
+//! counts. Pending scrape requests are picked up during regular polling,
+//! the metrics are rendered right away and each request is then answered on
+//! a short-lived thread of its own, so a slow scraper can't stall polling.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+use outcome::sim::stats::{StepHistogram, STEP_DURATION_BUCKETS};
+
+use crate::organizer::Organizer;
+use crate::server::{Server, SimConnection};
+use crate::socket::traffic::TrafficStats;
+use crate::worker::Worker;
+use crate::Result;
+
+/// Prefix shared by the names of all the exposed metrics.
+const PREFIX: &str = "outcome";
+
+/// Metrics tracked by a single server, organizer or worker, along with the
+/// endpoint they're exposed at.
+pub struct Metrics {
+    listener: TcpListener,
+    /// Role reported with each metric, e.g. `server`
+    role: &'static str,
+    /// Received messages and signals counted by type
+    received: BTreeMap<String, u64>,
+    /// Durations of the recorded steps
+    steps: StepHistogram,
+}
+
+impl Metrics {
+    /// Binds the scrape endpoint to the given address.
+    pub fn bind(address: &str, role: &'static str) -> Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            role,
+            received: BTreeMap::new(),
+            steps: StepHistogram::default(),
+        })
+    }
+
+    /// Returns the address the endpoint is bound to.
+    pub fn address(&self) -> Result<String> {
+        Ok(self.listener.local_addr()?.to_string())
+    }
+
+    /// Counts a received message or signal of the given type.
+    pub fn count_received(&mut self, type_: &str) {
+        match self.received.get_mut(type_) {
+            Some(count) => *count += 1,
+            None => {
+                self.received.insert(type_.to_string(), 1);
+            }
+        }
+    }
+
+    /// Records a processed step.
+    ///
+    /// Only used where step durations are not already tracked elsewhere,
+    /// otherwise they're added to the exposition using
+    /// [`Exposition::steps`].
+    pub fn record_step(&mut self, duration: Duration) {
+        self.steps.record(duration);
+    }
+
+    /// Serves pending scrape requests, if there are any.
+    ///
+    /// Metrics other than the ones tracked here are added to the response
+    /// using the provided function, which is only called if there's
+    /// a request to serve.
+    pub fn serve(&mut self, extra: impl FnOnce(&mut Exposition)) -> Result<()> {
+        let mut pending = Vec::new();
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => pending.push(stream),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let mut exposition = self.exposition();
+        extra(&mut exposition);
+        let body = Arc::new(exposition.text);
+        for stream in pending {
+            let body = body.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = respond(stream, &body) {
+                    debug!("failed serving metrics scrape: {}", e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Renders the metrics tracked here.
+    fn exposition(&self) -> Exposition {
+        let mut exp = Exposition::new(self.role);
+        exp.family(
+            "received_total",
+            "counter",
+            "Received messages and signals by type",
+        );
+        for (type_, count) in &self.received {
+            exp.sample("received_total", &[("type", type_)], *count as f64);
+        }
+        if self.steps.count > 0 {
+            exp.steps(&self.steps);
+        }
+        exp
+    }
+}
+
+/// Metrics rendered in the Prometheus text format.
+pub struct Exposition {
+    role: &'static str,
+    text: String,
+}
+
+impl Exposition {
+    fn new(role: &'static str) -> Self {
+        Self {
+            role,
+            text: String::new(),
+        }
+    }
+
+    /// Declares a metric family, `type_` being one of the Prometheus metric
+    /// types, e.g. `counter` or `gauge`.
+    pub fn family(&mut self, name: &str, type_: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {}_{} {}", PREFIX, name, help);
+        let _ = writeln!(self.text, "# TYPE {}_{} {}", PREFIX, name, type_);
+    }
+
+    /// Adds a single sample, labeled with the role in addition to the
+    /// provided labels.
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let _ = write!(self.text, "{}_{}{{role=\"{}\"", PREFIX, name, self.role);
+        for (label, label_value) in labels {
+            let _ = write!(self.text, ",{}=\"{}\"", label, escape(label_value));
+        }
+        let _ = writeln!(self.text, "}} {}", value);
+    }
+
+    /// Adds the step duration metrics.
+    pub fn steps(&mut self, steps: &StepHistogram) {
+        self.family("step_duration_seconds", "histogram", "Step processing time");
+        let mut cumulative = 0;
+        for (bound, count) in STEP_DURATION_BUCKETS.iter().zip(steps.buckets.iter()) {
+            cumulative += count;
+            let bound = bound.to_string();
+            let labels = [("le", bound.as_str())];
+            self.sample("step_duration_seconds_bucket", &labels, cumulative as f64);
+        }
+        let labels = [("le", "+Inf")];
+        self.sample("step_duration_seconds_bucket", &labels, steps.count as f64);
+        self.sample("step_duration_seconds_sum", &[], steps.sum.as_secs_f64());
+        self.sample("step_duration_seconds_count", &[], steps.count as f64);
+        self.family(
+            "last_step_duration_seconds",
+            "gauge",
+            "Processing time of the most recent step",
+        );
+        self.sample("last_step_duration_seconds", &[], steps.last.as_secs_f64());
+    }
+
+    /// Adds the numbers of bytes received from and sent to each of the
+    /// named connections.
+    pub fn traffic(&mut self, connections: &[(String, TrafficStats)]) {
+        self.family(
+            "received_bytes_total",
+            "counter",
+            "Bytes received by connection",
+        );
+        for (name, stats) in connections {
+            let labels = [("connection", name.as_str())];
+            self.sample("received_bytes_total", &labels, stats.bytes_in as f64);
+        }
+        self.family("sent_bytes_total", "counter", "Bytes sent by connection");
+        for (name, stats) in connections {
+            let labels = [("connection", name.as_str())];
+            self.sample("sent_bytes_total", &labels, stats.bytes_out as f64);
+        }
+    }
+
+    /// Adds a single gauge without any additional labels.
+    pub fn gauge(&mut self, name: &str, help: &str, value: f64) {
+        self.family(name, "gauge", help);
+        self.sample(name, &[], value);
+    }
+}
+
+/// Escapes the label value as required by the text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Reads the request and writes back the metrics, only the `/metrics` path
+/// is served.
+fn respond(mut stream: TcpStream, body: &str) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(1)))?;
+    let mut buf = [0; 1024];
+    let read = stream.read(&mut buf)?;
+    let request = String::from_utf8_lossy(&buf[..read]);
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+    let response = match path {
+        "/metrics" => format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        ),
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+    stream.write_all(response.as_bytes())?;
+    Ok(())
+}
+
+impl Server {
+    /// Serves pending scrape requests, if metrics are enabled.
+    pub(crate) fn serve_metrics(&mut self) {
+        let metrics = match &mut self.metrics {
+            Some(metrics) => metrics,
+            None => return,
+        };
+        let sim = &self.sim;
+        let clients = &mut self.clients;
+        let result = metrics.serve(|exp| {
+            exp.gauge("clients", "Connected clients", clients.len() as f64);
+            let traffic = clients
+                .iter_mut()
+                .map(|(id, client)| (id.to_string(), client.connection.traffic()))
+                .collect::<Vec<_>>();
+            exp.traffic(&traffic);
+            match sim {
+                SimConnection::Local(sim) => {
+                    exp.gauge("clock", "Current clock value", sim.get_clock() as f64);
+                    exp.gauge("entities", "Number of entities", sim.entity_count() as f64);
+                    exp.steps(&sim.run_stats.step_durations);
+                }
+                SimConnection::UnionOrganizer(organizer) => {
+                    exp.gauge(
+                        "clock",
+                        "Current clock value",
+                        organizer.central.get_clock() as f64,
+                    );
+                    exp.steps(&organizer.run_stats.step_durations);
+                }
+                SimConnection::UnionWorker(_) | SimConnection::Idle => (),
+            }
+        });
+        if let Err(e) = result {
+            warn!("failed serving metrics: {}", e);
+        }
+    }
+}
+
+impl Organizer {
+    /// Exposes the organizer's metrics for scraping at the given address.
+    pub fn enable_metrics(&mut self, address: &str) -> Result<()> {
+        self.metrics = Some(Metrics::bind(address, "organizer")?);
+        Ok(())
+    }
+
+    /// Serves pending scrape requests, if metrics are enabled.
+    pub(crate) fn serve_metrics(&mut self) {
+        let metrics = match &mut self.metrics {
+            Some(metrics) => metrics,
+            None => return,
+        };
+        let central = &self.central;
+        let workers = &mut self.net.workers;
+        let run_stats = &self.run_stats;
+        let result = metrics.serve(|exp| {
+            exp.gauge("clock", "Current clock value", central.get_clock() as f64);
+            exp.family("worker_entities", "gauge", "Number of entities by worker");
+            for (worker_id, entities) in &central.node_entities {
+                let worker_id = worker_id.to_string();
+                let labels = [("worker", worker_id.as_str())];
+                exp.sample("worker_entities", &labels, entities.len() as f64);
+            }
+            let traffic = workers
+                .iter_mut()
+                .map(|(id, worker)| (format!("worker {}", id), worker.connection.traffic()))
+                .collect::<Vec<_>>();
+            exp.traffic(&traffic);
+            exp.steps(&run_stats.step_durations);
+        });
+        if let Err(e) = result {
+            warn!("failed serving metrics: {}", e);
+        }
+    }
+}
+
+impl Worker {
+    /// Exposes the worker's metrics for scraping at the given address.
+    pub fn enable_metrics(&mut self, address: &str) -> Result<()> {
+        self.metrics = Some(Metrics::bind(address, "worker")?);
+        Ok(())
+    }
+
+    /// Serves pending scrape requests, if metrics are enabled.
+    pub(crate) fn serve_metrics(&mut self) {
+        let metrics = match &mut self.metrics {
+            Some(metrics) => metrics,
+            None => return,
+        };
+        let node = &self.sim_node;
+        let organizer = &mut self.network.organizer;
+        let result = metrics.serve(|exp| {
+            if let Some(node) = node {
+                exp.gauge("clock", "Current clock value", node.clock as f64);
+                exp.gauge("entities", "Number of entities", node.entities.len() as f64);
+            }
+            if let Some(organizer) = organizer {
+                exp.traffic(&[("organizer".to_string(), organizer.traffic())]);
+            }
+        });
+        if let Err(e) = result {
+            warn!("failed serving metrics: {}", e);
+        }
+    }
+}
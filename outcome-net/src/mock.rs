@@ -0,0 +1,392 @@
+//! Test doubles for the server and client.
+//!
+//! [`MockServer`] stands in for a real [`Server`] in unit tests of services
+//! and other client-side integration logic. Instead of running a simulation
+//! it answers requests with responses scripted by the test, and records all
+//! the received messages so that they can be asserted on afterwards.
+//!
+//! [`MockClient`]s are connected to the mock server using an in-memory
+//! transport. Messages are still fully encoded and decoded on the way, same
+//! as they would be when sent over a socket, but no sockets or threads are
+//! involved. Responses are delivered synchronously, meaning the response to
+//! a request is available right after it was sent.
+//!
+//! ```
+//! use outcome_net::mock::MockServer;
+//! use outcome_net::msg::*;
+//!
+//! let server = MockServer::new();
+//! server.respond_always(
+//!     MessageType::InvokeEventsRequest,
+//!     InvokeEventsResponse { error: String::new(), code: None },
+//! )?;
+//!
+//! let client = server.client();
+//! let resp: InvokeEventsResponse = client.request(InvokeEventsRequest {
+//!     events: vec!["init".to_string()],
+//! })?;
+//! assert!(resp.error.is_empty());
+//!
+//! server.assert_received_count(MessageType::InvokeEventsRequest, 1);
+//! # Ok::<(), outcome_net::Error>(())
+//! ```
+//!
+//! [`Server`]: crate::Server
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::msg::{msg_bytes_from_payload, Message, MessageType, Payload};
+use crate::socket::Encoding;
+use crate::{Error, Result};
+
+/// Identifier of a mock client, assigned in the order of creation.
+pub type MockClientId = usize;
+
+/// Time a mock client waits for a message before giving up.
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Response scripted for a single request type.
+struct Script {
+    request: MessageType,
+    /// Encoded responses sent once each, in order
+    once: VecDeque<Vec<u8>>,
+    /// Encoded response sent once the one-shot responses run out
+    always: Option<Vec<u8>>,
+}
+
+#[derive(Default)]
+struct MockState {
+    scripts: Vec<Script>,
+    received: Vec<(MockClientId, Message)>,
+    clients: Vec<Sender<Vec<u8>>>,
+}
+
+impl MockState {
+    fn script_mut(&mut self, request: MessageType) -> &mut Script {
+        match self.scripts.iter().position(|s| s.request == request) {
+            Some(n) => &mut self.scripts[n],
+            None => {
+                self.scripts.push(Script {
+                    request,
+                    once: VecDeque::new(),
+                    always: None,
+                });
+                self.scripts.last_mut().unwrap()
+            }
+        }
+    }
+
+    fn next_response(&mut self, request: MessageType) -> Option<Vec<u8>> {
+        let script = self.scripts.iter_mut().find(|s| s.request == request)?;
+        script.once.pop_front().or_else(|| script.always.clone())
+    }
+}
+
+/// Server test double answering requests with scripted responses.
+///
+/// Cloning the mock server gives another handle to the same server.
+#[derive(Clone)]
+pub struct MockServer {
+    state: Arc<Mutex<MockState>>,
+    encoding: Encoding,
+}
+
+impl MockServer {
+    /// Creates a new mock server using the default bincode encoding.
+    pub fn new() -> Self {
+        Self::with_encoding(Encoding::Bincode)
+    }
+
+    /// Creates a new mock server using the given encoding.
+    pub fn with_encoding(encoding: Encoding) -> Self {
+        Self {
+            state: Default::default(),
+            encoding,
+        }
+    }
+
+    /// Creates a new client connected to this server.
+    pub fn client(&self) -> MockClient {
+        let (sender, receiver) = channel();
+        let mut state = self.state.lock().unwrap();
+        state.clients.push(sender);
+        MockClient {
+            id: state.clients.len() - 1,
+            server: self.clone(),
+            inbound: receiver,
+        }
+    }
+
+    /// Scripts a response sent once for the next request of the given type.
+    /// Multiple one-shot responses for the same type are sent in the order
+    /// they were scripted.
+    pub fn respond_once<P: Payload + Serialize>(
+        &self,
+        request: MessageType,
+        response: P,
+    ) -> Result<()> {
+        let bytes = msg_bytes_from_payload(response, 0, &self.encoding)?;
+        let mut state = self.state.lock().unwrap();
+        state.script_mut(request).once.push_back(bytes);
+        Ok(())
+    }
+
+    /// Scripts a response sent for every request of the given type, once
+    /// there are no more one-shot responses left.
+    pub fn respond_always<P: Payload + Serialize>(
+        &self,
+        request: MessageType,
+        response: P,
+    ) -> Result<()> {
+        let bytes = msg_bytes_from_payload(response, 0, &self.encoding)?;
+        let mut state = self.state.lock().unwrap();
+        state.script_mut(request).always = Some(bytes);
+        Ok(())
+    }
+
+    /// Sends a message to all connected clients without a request, same as
+    /// the real server does with pushed data and notices.
+    pub fn push<P: Payload + Serialize>(&self, payload: P) -> Result<()> {
+        let bytes = msg_bytes_from_payload(payload, 0, &self.encoding)?;
+        for client in &self.state.lock().unwrap().clients {
+            // disconnected clients are skipped
+            let _ = client.send(bytes.clone());
+        }
+        Ok(())
+    }
+
+    /// Returns all the messages received so far, in order.
+    pub fn received(&self) -> Vec<Message> {
+        self.state
+            .lock()
+            .unwrap()
+            .received
+            .iter()
+            .map(|(_, msg)| msg.clone())
+            .collect()
+    }
+
+    /// Returns the messages received so far from the given client.
+    pub fn received_from(&self, client: MockClientId) -> Vec<Message> {
+        self.state
+            .lock()
+            .unwrap()
+            .received
+            .iter()
+            .filter(|(id, _)| *id == client)
+            .map(|(_, msg)| msg.clone())
+            .collect()
+    }
+
+    /// Returns the messages of the given type received so far.
+    pub fn received_of(&self, type_: MessageType) -> Vec<Message> {
+        self.received()
+            .into_iter()
+            .filter(|msg| msg.type_ == type_)
+            .collect()
+    }
+
+    /// Unpacks the payloads of all received messages of the given type.
+    pub fn received_payloads<P: Payload + DeserializeOwned>(
+        &self,
+        type_: MessageType,
+    ) -> Result<Vec<P>> {
+        self.received_of(type_)
+            .iter()
+            .map(|msg| msg.unpack_payload(&self.encoding))
+            .collect()
+    }
+
+    /// Forgets all the messages received so far.
+    pub fn clear_received(&self) {
+        self.state.lock().unwrap().received.clear();
+    }
+
+    /// Panics if no message of the given type was received.
+    pub fn assert_received(&self, type_: MessageType) {
+        if self.received_of(type_).is_empty() {
+            panic!(
+                "expected mock server to receive {:?}, received: {:?}",
+                type_,
+                self.received_types()
+            );
+        }
+    }
+
+    /// Panics if any message of the given type was received.
+    pub fn assert_not_received(&self, type_: MessageType) {
+        let count = self.received_of(type_).len();
+        if count > 0 {
+            panic!(
+                "expected mock server not to receive {:?}, received it {} times",
+                type_, count
+            );
+        }
+    }
+
+    /// Panics unless exactly `count` messages of the given type were
+    /// received.
+    pub fn assert_received_count(&self, type_: MessageType, count: usize) {
+        let received = self.received_of(type_).len();
+        if received != count {
+            panic!(
+                "expected mock server to receive {:?} {} times, received it {} times",
+                type_, count, received
+            );
+        }
+    }
+
+    fn received_types(&self) -> Vec<MessageType> {
+        self.received().iter().map(|msg| msg.type_).collect()
+    }
+
+    /// Records the incoming message and sends back the scripted response,
+    /// if any.
+    fn handle(&self, client: MockClientId, bytes: Vec<u8>) -> Result<()> {
+        let msg = Message::from_bytes(bytes, &self.encoding)?;
+        let mut state = self.state.lock().unwrap();
+        let response = state.next_response(msg.type_);
+        if response.is_none() {
+            debug!("mock server: no response scripted for {:?}", msg.type_);
+        }
+        state.received.push((client, msg));
+        if let Some(bytes) = response {
+            state.clients[client]
+                .send(bytes)
+                .map_err(|_| Error::SocketNotConnected)?;
+        }
+        Ok(())
+    }
+}
+
+/// Client test double connected to a [`MockServer`].
+pub struct MockClient {
+    id: MockClientId,
+    server: MockServer,
+    inbound: Receiver<Vec<u8>>,
+}
+
+impl MockClient {
+    /// Identifier of this client as seen by the mock server.
+    pub fn id(&self) -> MockClientId {
+        self.id
+    }
+
+    /// Sends a message to the mock server.
+    pub fn send_payload<P: Payload + Serialize>(&self, payload: P) -> Result<()> {
+        let bytes = msg_bytes_from_payload(payload, 0, &self.server.encoding)?;
+        self.server.handle(self.id, bytes)
+    }
+
+    /// Receives the next message from the mock server, failing if nothing
+    /// arrives within a short timeout.
+    pub fn recv_msg(&self) -> Result<Message> {
+        match self.inbound.recv_timeout(RECV_TIMEOUT) {
+            Ok(bytes) => Message::from_bytes(bytes, &self.server.encoding),
+            Err(RecvTimeoutError::Timeout) => Err(Error::TimedOut),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::SocketNotConnected),
+        }
+    }
+
+    /// Receives the next message from the mock server, returning
+    /// immediately if there's none available.
+    pub fn try_recv_msg(&self) -> Result<Message> {
+        match self.inbound.try_recv() {
+            Ok(bytes) => Message::from_bytes(bytes, &self.server.encoding),
+            Err(TryRecvError::Empty) => Err(Error::WouldBlock),
+            Err(TryRecvError::Disconnected) => Err(Error::SocketNotConnected),
+        }
+    }
+
+    /// Sends a request and unpacks the scripted response.
+    pub fn request<P, R>(&self, payload: P) -> Result<R>
+    where
+        P: Payload + Serialize,
+        R: Payload + DeserializeOwned,
+    {
+        self.send_payload(payload)?;
+        let msg = self.recv_msg()?;
+        msg.unpack_payload(&self.server.encoding)
+    }
+}
+
+#[cfg(test)]
+use crate::msg::{InvokeEventsRequest, InvokeEventsResponse};
+
+#[cfg(test)]
+fn invoke_response(error: &str) -> InvokeEventsResponse {
+    InvokeEventsResponse {
+        error: error.to_string(),
+        code: None,
+    }
+}
+
+#[test]
+fn scripted_responses_are_sent_in_order() {
+    let server = MockServer::new();
+    let type_ = MessageType::InvokeEventsRequest;
+    server
+        .respond_once(type_, invoke_response("first"))
+        .unwrap();
+    server
+        .respond_once(type_, invoke_response("second"))
+        .unwrap();
+    server
+        .respond_always(type_, invoke_response("always"))
+        .unwrap();
+
+    let client = server.client();
+    let mut errors = Vec::new();
+    for _ in 0..4 {
+        let resp: InvokeEventsResponse = client
+            .request(InvokeEventsRequest { events: vec![] })
+            .unwrap();
+        errors.push(resp.error);
+    }
+    assert_eq!(errors, vec!["first", "second", "always", "always"]);
+    server.assert_received_count(type_, 4);
+}
+
+#[test]
+fn received_messages_are_recorded_per_client() {
+    let server = MockServer::with_encoding(Encoding::Json);
+    let first = server.client();
+    let second = server.client();
+    first
+        .send_payload(InvokeEventsRequest {
+            events: vec!["init".to_string()],
+        })
+        .unwrap();
+
+    // nothing was scripted, so there's no response
+    assert!(matches!(first.try_recv_msg(), Err(Error::WouldBlock)));
+    assert!(matches!(first.recv_msg(), Err(Error::TimedOut)));
+
+    assert_eq!(server.received_from(first.id()).len(), 1);
+    assert!(server.received_from(second.id()).is_empty());
+    let payloads: Vec<InvokeEventsRequest> = server
+        .received_payloads(MessageType::InvokeEventsRequest)
+        .unwrap();
+    assert_eq!(payloads[0].events, vec!["init".to_string()]);
+    server.assert_not_received(MessageType::InvokeEventsResponse);
+
+    server.clear_received();
+    server.assert_received_count(MessageType::InvokeEventsRequest, 0);
+}
+
+#[test]
+fn pushed_messages_reach_all_clients() {
+    let server = MockServer::new();
+    let clients = vec![server.client(), server.client()];
+    server.push(invoke_response("pushed")).unwrap();
+    for client in &clients {
+        let msg = client.recv_msg().unwrap();
+        assert_eq!(msg.type_, MessageType::InvokeEventsResponse);
+    }
+}
@@ -0,0 +1,321 @@
+//! Bridging simulation data with MQTT topics.
+//!
+//! [`MqttBridge`] connects to a server as a regular client and to an MQTT
+//! broker, translating between the two in both directions based on
+//! a declarative [`BridgeConfig`]:
+//!
+//! - values of selected vars are published to topics after each step
+//! - messages arriving on subscribed topics are written to vars or invoke
+//!   events
+//!
+//! This allows for hardware-in-the-loop setups and for using existing
+//! dashboards that already speak MQTT. Payloads are plain strings, using
+//! the same format as var values read through the client.
+//!
+//! Config is usually read from a toml file:
+//!
+//! ```toml
+//! broker = "127.0.0.1:1883"
+//! server = "127.0.0.1:9123"
+//!
+//! [[publish]]
+//! address = "1:position:float:x"
+//! topic = "sim/car/x"
+//!
+//! [[subscribe]]
+//! topic = "sensors/throttle"
+//! var = "1:control:float:throttle"
+//!
+//! [[subscribe]]
+//! topic = "sensors/+/reset"
+//! event = "reset"
+//! ```
+
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use fnv::FnvHashMap;
+use outcome::{Address, Var};
+use rumqttc::{Event, MqttOptions, Packet, QoS};
+
+use crate::msg::ErrorFields;
+use crate::{Client, ClientConfig, Error, Result};
+
+/// Declarative configuration of the bridge.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BridgeConfig {
+    /// Address of the MQTT broker, as `host:port`
+    pub broker: String,
+    /// Address of the server to connect to
+    pub server: String,
+    /// Client id used with the broker
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    /// Time between polls, in milliseconds
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval: u64,
+    /// Topic to publish the clock to after each step
+    #[serde(default)]
+    pub step_topic: Option<String>,
+    /// Vars published to topics after each step
+    #[serde(default)]
+    pub publish: Vec<PublishMapping>,
+    /// Topics translated into var writes or event invocations
+    #[serde(default)]
+    pub subscribe: Vec<SubscribeMapping>,
+}
+
+fn default_client_id() -> String {
+    "outcome-bridge".to_string()
+}
+
+fn default_poll_interval() -> u64 {
+    100
+}
+
+/// Var published to a topic.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublishMapping {
+    pub address: String,
+    pub topic: String,
+    /// Whether the broker should retain the last published value
+    #[serde(default)]
+    pub retain: bool,
+}
+
+/// Topic filter translated into a var write or an event invocation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubscribeMapping {
+    /// Topic filter, can include the `+` and `#` wildcards
+    pub topic: String,
+    #[serde(flatten)]
+    pub target: SubscribeTarget,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscribeTarget {
+    /// Write the message payload to the var at the address
+    Var(String),
+    /// Invoke the event, ignoring the payload
+    Event(String),
+}
+
+/// Bridge between a server and an MQTT broker.
+pub struct MqttBridge {
+    config: BridgeConfig,
+    client: Client,
+    mqtt: rumqttc::Client,
+    /// Messages received on subscribed topics, as `(topic, payload)`
+    incoming: Receiver<(String, Vec<u8>)>,
+    /// Clock at which vars were last published
+    last_clock: Option<usize>,
+}
+
+impl MqttBridge {
+    /// Connects to both the server and the broker, subscribing to all the
+    /// configured topics.
+    pub fn new(config: BridgeConfig) -> Result<Self> {
+        let mut client = Client::new_with_config(ClientConfig {
+            name: config.client_id.clone(),
+            is_blocking: false,
+            ..Default::default()
+        })?;
+        client.connect(&config.server, None)?;
+
+        let (host, port) = config.broker.rsplit_once(':').ok_or(Error::Other(format!(
+            "invalid broker address: {}",
+            config.broker
+        )))?;
+        let mut options =
+            MqttOptions::new(config.client_id.clone(), host.to_string(), port.parse()?);
+        options.set_keep_alive(5);
+        let (mut mqtt, mut connection) = rumqttc::Client::new(options, 64);
+        for mapping in &config.subscribe {
+            mqtt.subscribe(&mapping.topic, QoS::AtLeastOnce)
+                .map_err(|e| Error::Other(e.to_string()))?;
+        }
+
+        // broker connection has to be driven continuously, incoming
+        // publishes are passed over to the bridge
+        let (sender, incoming) = channel();
+        thread::spawn(move || {
+            for notification in connection.iter() {
+                match notification {
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        if sender
+                            .send((publish.topic, publish.payload.to_vec()))
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Ok(_) => (),
+                    Err(e) => {
+                        warn!("mqtt connection error: {}", e);
+                        thread::sleep(Duration::from_secs(1));
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            config,
+            client,
+            mqtt,
+            incoming,
+            last_clock: None,
+        })
+    }
+
+    /// Forwards messages received from the broker to the server, then
+    /// publishes the selected vars if the simulation has advanced.
+    pub fn manual_poll(&mut self) -> Result<()> {
+        while let Ok((topic, payload)) = self.incoming.try_recv() {
+            if let Err(e) = self.handle_incoming(&topic, &payload) {
+                warn!("failed handling message on topic {}: {}", topic, e);
+            }
+        }
+
+        let clock = self.client.server_status()?.current_tick;
+        if self.last_clock == Some(clock) {
+            return Ok(());
+        }
+        self.last_clock = Some(clock);
+
+        if let Some(topic) = &self.config.step_topic {
+            publish(&mut self.mqtt, topic, false, clock.to_string())?;
+        }
+        let addrs = self
+            .config
+            .publish
+            .iter()
+            .map(|m| m.address.clone())
+            .collect::<Vec<_>>();
+        if addrs.is_empty() {
+            return Ok(());
+        }
+        let values = self.client.get_vars_as_strings(&addrs)?;
+        for (mapping, value) in self.config.publish.iter().zip(values) {
+            if let Some(value) = value {
+                publish(&mut self.mqtt, &mapping.topic, mapping.retain, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Polls the bridge at the configured interval, until the `running`
+    /// bool gets flipped to false.
+    pub fn start_polling(&mut self, running: Arc<AtomicBool>) -> Result<()> {
+        let interval = Duration::from_millis(self.config.poll_interval);
+        while running.load(Ordering::SeqCst) {
+            thread::sleep(interval);
+            if let Err(e) = self.manual_poll() {
+                warn!("bridge error: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_incoming(&mut self, topic: &str, payload: &[u8]) -> Result<()> {
+        let targets = self
+            .config
+            .subscribe
+            .iter()
+            .filter(|m| topic_matches(&m.topic, topic))
+            .map(|m| m.target.clone())
+            .collect::<Vec<_>>();
+        for target in targets {
+            match target {
+                SubscribeTarget::Var(addr) => {
+                    let address = Address::from_str(&addr)?;
+                    let value = String::from_utf8_lossy(payload);
+                    let var = Var::from_str(value.trim(), Some(address.var_type))?;
+                    let mut vars = FnvHashMap::default();
+                    vars.insert(address, var);
+                    let resp = self.client.pull_vars(vars)?;
+                    resp.error_result()?;
+                }
+                SubscribeTarget::Event(event) => {
+                    let resp = self.client.invoke_events(vec![event])?;
+                    resp.error_result()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn publish(mqtt: &mut rumqttc::Client, topic: &str, retain: bool, payload: String) -> Result<()> {
+    mqtt.publish(topic, QoS::AtLeastOnce, retain, payload.into_bytes())
+        .map_err(|e| Error::Other(e.to_string()))
+}
+
+/// Checks whether the topic matches the filter, supporting the single
+/// level `+` and multi level `#` wildcards.
+///
+/// Following the MQTT spec, `#` is only valid as the last level of the
+/// filter, and topics starting with `$` are not matched by filters starting
+/// with a wildcard.
+fn topic_matches(filter: &str, topic: &str) -> bool {
+    if topic.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
+        return false;
+    }
+    let mut filter_levels = filter.split('/').peekable();
+    let mut topic_levels = topic.split('/');
+    while let Some(level) = filter_levels.next() {
+        match (level, topic_levels.next()) {
+            ("#", _) => return filter_levels.peek().is_none(),
+            ("+", Some(_)) => continue,
+            (l, Some(t)) if l == t => continue,
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
+
+#[test]
+fn topic_filters_match_by_level() {
+    let cases = [
+        // (filter, topic, matches)
+        ("sim/clock", "sim/clock", true),
+        ("sim/clock", "sim/clock/extra", false),
+        ("sim/clock/extra", "sim/clock", false),
+        ("sim/+", "sim/clock", true),
+        ("sim/+", "sim/clock/extra", false),
+        ("sim/+/x", "sim/1/x", true),
+        ("+/+", "sim/clock", true),
+        // `#` matches the parent level as well as any number of children
+        ("sim/#", "sim", true),
+        ("sim/#", "sim/1/pos/x", true),
+        ("#", "sim/clock", true),
+        ("sim/#", "other/clock", false),
+        // `#` anywhere but at the end makes the filter invalid
+        ("sim/#/x", "sim/1/x", false),
+        ("#/x", "sim/x", false),
+        // empty levels are levels too
+        ("sim//x", "sim//x", true),
+        ("sim/+/x", "sim//x", true),
+        ("sim/+", "sim/", true),
+        ("sim/+", "sim", false),
+        ("sim/x", "sim//x", false),
+        // wildcards at the first level don't match `$` topics
+        ("#", "$SYS/clients", false),
+        ("+/clients", "$SYS/clients", false),
+        ("$SYS/#", "$SYS/clients", true),
+        ("$SYS/+", "$SYS/clients", true),
+    ];
+    for (filter, topic, matches) in cases.iter() {
+        assert_eq!(
+            topic_matches(filter, topic),
+            *matches,
+            "filter: {}, topic: {}",
+            filter,
+            topic
+        );
+    }
+}
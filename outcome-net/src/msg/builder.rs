@@ -0,0 +1,129 @@
+//! Helpers for constructing data packs.
+//!
+//! ```ignore
+//! use outcome_net::msg::DataPackBuilder;
+//!
+//! let data = DataPackBuilder::new()
+//!     .set("2:greeting:str:hello", Var::String("hi".to_string()))
+//!     .extend(vec![
+//!         ("3:position:float:x", Var::Float(1.)),
+//!         ("3:position:float:y", Var::Float(2.)),
+//!     ])
+//!     .build()?;
+//! ```
+
+use outcome::{Address, CompName, EntityName, Var, VarName, VarType};
+
+use crate::msg::VarSimDataPack;
+use crate::{Error, Result};
+
+/// Parses a full var address in the `entity:component:type:var` form.
+///
+/// Unlike the address parsing available in core, parts that are empty or
+/// too long to be stored are rejected instead of being truncated.
+pub fn parse_var_address(addr: &str) -> Result<Address> {
+    let split = addr.split(':').collect::<Vec<&str>>();
+    if split.len() != 4 || split.iter().any(|s| s.is_empty()) {
+        return Err(outcome::error::Error::FailedCreatingAddress(addr.to_string()).into());
+    }
+    Ok(Address {
+        entity: outcome::string::new(split[0])?,
+        component: outcome::string::new(split[1])?,
+        var_type: VarType::from_str(split[2])?,
+        var_name: outcome::string::new(split[3])?,
+    })
+}
+
+/// Parses a full var address into a key used by [`VarSimDataPack`].
+pub fn parse_data_pack_key(addr: &str) -> Result<(EntityName, CompName, VarName)> {
+    let addr = parse_var_address(addr)?;
+    Ok((addr.entity, addr.component, addr.var_name))
+}
+
+/// Builder for [`VarSimDataPack`]s.
+///
+/// Each inserted var is validated against the type included in its
+/// address. Errors are collected while building and the first one is
+/// returned from [`DataPackBuilder::build`].
+#[derive(Default)]
+pub struct DataPackBuilder {
+    pack: VarSimDataPack,
+    error: Option<Error>,
+}
+
+impl DataPackBuilder {
+    /// Creates a new builder for an empty data pack.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the value of the var at the given address, in the
+    /// `entity:component:type:var` form.
+    pub fn set(mut self, addr: &str, var: Var) -> Self {
+        if self.error.is_some() {
+            return self;
+        }
+        match parse_var_address(addr) {
+            Ok(addr) => self.set_addr(&addr, var),
+            Err(e) => {
+                self.error = Some(e);
+                self
+            }
+        }
+    }
+
+    /// Sets the value of the var at the given address.
+    pub fn set_addr(mut self, addr: &Address, var: Var) -> Self {
+        if self.error.is_some() {
+            return self;
+        }
+        if var.get_type() != addr.var_type {
+            self.error = Some(
+                outcome::error::Error::VarTypeMismatch(
+                    addr.clone(),
+                    addr.var_type.to_str().to_string(),
+                    var.get_type().to_str().to_string(),
+                )
+                .into(),
+            );
+            return self;
+        }
+        self.pack.vars.insert(
+            (
+                addr.entity.clone(),
+                addr.component.clone(),
+                addr.var_name.clone(),
+            ),
+            var,
+        );
+        self
+    }
+
+    /// Sets the values of all the vars from the iterator of address and
+    /// value pairs.
+    pub fn extend<I, S>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (S, Var)>,
+        S: AsRef<str>,
+    {
+        for (addr, var) in vars {
+            self = self.set(addr.as_ref(), var);
+        }
+        self
+    }
+
+    /// Builds the data pack.
+    pub fn build(self) -> Result<VarSimDataPack> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.pack),
+        }
+    }
+}
+
+impl VarSimDataPack {
+    /// Creates a new builder for a data pack.
+    pub fn builder() -> DataPackBuilder {
+        DataPackBuilder::new()
+    }
+}
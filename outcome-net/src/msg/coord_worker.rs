@@ -42,6 +42,7 @@ use std::net::TcpStream;
 pub use crate::msg::{Message, Payload};
 
 use crate::msg::MessageType;
+use outcome::EntityId;
 use serde::{Deserialize, Serialize};
 
 pub enum SignalType {}
@@ -120,3 +121,94 @@ impl Payload for IntroduceCoordResponse {
         MessageType::IntroduceCoordResponse
     }
 }
+
+/// Low-level debugging request, answered by organizers and workers on
+/// their greeter sockets.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct InspectRequest {
+    pub target: InspectTarget,
+}
+
+impl Payload for InspectRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::InspectRequest
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct InspectResponse {
+    pub report: InspectReport,
+    pub error: String,
+}
+
+impl Payload for InspectResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::InspectResponse
+    }
+}
+
+/// Part of the organizer or worker state to be inspected.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum InspectTarget {
+    /// General information
+    Status,
+    /// List of known entities
+    Entities,
+    /// All the data stored on a single entity
+    Entity(EntityId),
+    /// Locations of entities across the union
+    Routing,
+    /// Unfinished tasks and other pending work
+    Pending,
+}
+
+/// Inspection results, matching the requested target.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum InspectReport {
+    /// List of named values
+    Status(Vec<(String, String)>),
+    Entities(Vec<EntitySummary>),
+    /// List of addresses and values of vars stored on the entity
+    Entity(Vec<(String, String)>),
+    /// List of entities along with the ids of workers storing them
+    Routing(Vec<(EntityId, u32)>),
+    /// Descriptions of pending work items
+    Pending(Vec<String>),
+    Empty,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct EntitySummary {
+    pub id: EntityId,
+    pub name: Option<String>,
+    /// Worker storing the entity, if known
+    pub worker: Option<u32>,
+    /// Attached components, empty if not known
+    pub components: Vec<String>,
+}
+
+/// Diagnostics command sent to a worker, see `Worker::diagnose` for the
+/// available commands.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct DiagnosticsRequest {
+    pub command: String,
+}
+
+impl Payload for DiagnosticsRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::DiagnosticsRequest
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct DiagnosticsResponse {
+    /// Readable command output
+    pub output: String,
+    pub error: String,
+}
+
+impl Payload for DiagnosticsResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::DiagnosticsResponse
+    }
+}
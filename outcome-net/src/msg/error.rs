@@ -0,0 +1,289 @@
+//! Structured errors carried by response messages.
+//!
+//! Responses report failures through the `error` string field, same as
+//! before, with an additional optional `code` field holding a
+//! machine-readable [`ErrorCode`]. Clients should match on the code instead
+//! of the message, which is not guaranteed to stay the same between
+//! versions. Empty error message means the request succeeded.
+//!
+//! [`ResponseError`] pairs the two together, it's what the server builds
+//! while handling a request and what clients get back as an error.
+
+use std::fmt;
+use std::ops::Deref;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Enumeration of error kinds reported back to clients.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum ErrorCode {
+    /// Error that doesn't fall into any of the other categories
+    Other,
+    /// Request was malformed, e.g. contained an invalid address
+    InvalidRequest,
+    /// Requested entity, var, selection or other object doesn't exist
+    NotFound,
+    /// Object the request tried to create already exists
+    AlreadyExists,
+    /// Client is not allowed to perform the request
+    Unauthorized,
+    /// Request can't be completed right now, e.g. because of other clients
+    /// blocking the step
+    WouldBlock,
+    /// Var is of a different type than the one requested
+    WrongVarType,
+    /// Request is not supported by the current backend or server build
+    Unsupported,
+    /// No simulation is running on the server
+    SimNotStarted,
+    /// Server is out of some resource, e.g. memory
+    ResourceExhausted,
+}
+
+impl Default for ErrorCode {
+    fn default() -> Self {
+        ErrorCode::Other
+    }
+}
+
+impl From<&outcome::error::Error> for ErrorCode {
+    fn from(e: &outcome::error::Error) -> Self {
+        use outcome::error::Error as CoreError;
+        match e {
+            CoreError::WouldBlock => ErrorCode::WouldBlock,
+            CoreError::InvalidVarType(_) | CoreError::VarTypeMismatch(..) => {
+                ErrorCode::WrongVarType
+            }
+            CoreError::NoEntityPrefab(_)
+            | CoreError::NoComponentModel(_)
+            | CoreError::FailedGettingEntityById(_)
+            | CoreError::FailedGettingEntityByName(_)
+            | CoreError::FailedGettingVarFromSim(_)
+            | CoreError::FailedGettingVarFromEntityStorage(_) => ErrorCode::NotFound,
+            CoreError::InvalidAddress(_)
+            | CoreError::InvalidLocalAddress(_)
+            | CoreError::FailedCreatingAddress(_)
+            | CoreError::VarOutOfBounds(..)
+            | CoreError::ParsingError(_)
+            | CoreError::ParseIntError(_)
+            | CoreError::ParseFloatError(_)
+            | CoreError::ParseBoolError(_) => ErrorCode::InvalidRequest,
+            CoreError::RequiredEngineFeatureNotAvailable(..) => ErrorCode::Unsupported,
+            _ => ErrorCode::Other,
+        }
+    }
+}
+
+impl From<&Error> for ErrorCode {
+    fn from(e: &Error) -> Self {
+        match e {
+            Error::WouldBlock => ErrorCode::WouldBlock,
+            Error::SimNotStarted => ErrorCode::SimNotStarted,
+            Error::MemoryPressure(_) => ErrorCode::ResourceExhausted,
+            Error::FailedGettingClientById(_) => ErrorCode::NotFound,
+            Error::HandshakeFailed(_) => ErrorCode::Unauthorized,
+            Error::TransportUnavailable(_) => ErrorCode::Unsupported,
+            Error::RequestRejected(_, e) | Error::RequestFailed(e) => e.code,
+            Error::CoreError(e) => e.into(),
+            _ => ErrorCode::Other,
+        }
+    }
+}
+
+/// Error reported in a response, split into the `error` and `code` fields
+/// of the response message. Default value signals no error.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct ResponseError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ResponseError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Puts together the error fields of a response message. Missing code
+    /// is reported as `ErrorCode::Other`.
+    pub fn from_fields(message: String, code: Option<ErrorCode>) -> Self {
+        Self {
+            code: code.unwrap_or_default(),
+            message,
+        }
+    }
+
+    /// Splits into the `error` and `code` fields of a response message.
+    pub fn into_fields(self) -> (String, Option<ErrorCode>) {
+        if self.is_err() {
+            (self.message, Some(self.code))
+        } else {
+            (self.message, None)
+        }
+    }
+
+    /// Error for requests not supported by the current backend.
+    pub fn unsupported(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Unsupported, message)
+    }
+
+    /// Checks whether the response reported an error.
+    pub fn is_err(&self) -> bool {
+        !self.message.is_empty()
+    }
+
+    /// Converts into a result, with the error case if an error was reported.
+    pub fn into_result(self) -> Result<(), ResponseError> {
+        if self.is_err() {
+            Err(self)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Deref for ResponseError {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.message
+    }
+}
+
+impl PartialEq<str> for ResponseError {
+    fn eq(&self, other: &str) -> bool {
+        self.message == other
+    }
+}
+
+impl PartialEq<&str> for ResponseError {
+    fn eq(&self, other: &&str) -> bool {
+        self.message == *other
+    }
+}
+
+impl From<String> for ResponseError {
+    fn from(message: String) -> Self {
+        Self::new(ErrorCode::Other, message)
+    }
+}
+
+impl From<&str> for ResponseError {
+    fn from(message: &str) -> Self {
+        Self::new(ErrorCode::Other, message)
+    }
+}
+
+impl From<ResponseError> for String {
+    fn from(e: ResponseError) -> Self {
+        e.message
+    }
+}
+
+impl From<outcome::error::Error> for ResponseError {
+    fn from(e: outcome::error::Error) -> Self {
+        Self::new((&e).into(), e.to_string())
+    }
+}
+
+impl From<Error> for ResponseError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::RequestRejected(_, e) | Error::RequestFailed(e) => e,
+            e => Self::new((&e).into(), e.to_string()),
+        }
+    }
+}
+
+/// Implemented by response messages reporting errors through the `error`
+/// and `code` fields.
+pub trait ErrorFields {
+    fn error_fields(&self) -> (&str, Option<ErrorCode>);
+    fn error_fields_mut(&mut self) -> (&mut String, &mut Option<ErrorCode>);
+
+    /// Sets the reported error, overwriting any previous one.
+    fn set_error(&mut self, error: impl Into<ResponseError>) {
+        let (message, code) = error.into().into_fields();
+        let (error_field, code_field) = self.error_fields_mut();
+        *error_field = message;
+        *code_field = code;
+    }
+
+    /// Sets the reported error, unless another one was already reported.
+    fn set_first_error(&mut self, error: impl Into<ResponseError>) {
+        if self.error_fields().0.is_empty() {
+            self.set_error(error);
+        }
+    }
+
+    /// Returns the reported error, if any.
+    fn error_result(&self) -> Result<(), ResponseError> {
+        let (message, code) = self.error_fields();
+        ResponseError::from_fields(message.to_string(), code).into_result()
+    }
+}
+
+/// Implements [`ErrorFields`] for response messages with `error` and `code`
+/// fields.
+macro_rules! impl_error_fields {
+    ($($response:ty),* $(,)?) => {
+        $(
+            impl $crate::msg::ErrorFields for $response {
+                fn error_fields(&self) -> (&str, Option<$crate::msg::ErrorCode>) {
+                    (&self.error, self.code)
+                }
+                fn error_fields_mut(
+                    &mut self,
+                ) -> (&mut String, &mut Option<$crate::msg::ErrorCode>) {
+                    (&mut self.error, &mut self.code)
+                }
+            }
+        )*
+    };
+}
+
+#[test]
+fn response_error_fields_roundtrip() {
+    let error = ResponseError::new(ErrorCode::NotFound, "entity not found: 1");
+    let (message, code) = error.clone().into_fields();
+    assert_eq!(code, Some(ErrorCode::NotFound));
+    assert_eq!(ResponseError::from_fields(message, code), error);
+
+    let (message, code) = ResponseError::default().into_fields();
+    assert!(message.is_empty());
+    assert_eq!(code, None);
+}
+
+#[test]
+fn error_fields_keep_first_error() {
+    let mut resp = crate::msg::DespawnEntitiesResponse {
+        entity_ids: Vec::new(),
+        error: String::new(),
+        code: None,
+    };
+    assert!(resp.error_result().is_ok());
+    resp.set_first_error(ResponseError::new(ErrorCode::NotFound, "first"));
+    resp.set_first_error(ResponseError::unsupported("second"));
+    assert_eq!(resp.error, "first");
+    assert_eq!(resp.code, Some(ErrorCode::NotFound));
+}
+
+#[test]
+fn response_without_code_deserializes() {
+    let resp: crate::msg::InvokeEventsResponse =
+        serde_json::from_str(r#"{"error":"invoking events not available on worker"}"#).unwrap();
+    assert_eq!(resp.code, None);
+    let error = resp.error_result().unwrap_err();
+    assert_eq!(error.code, ErrorCode::Other);
+    assert_eq!(error.message, "invoking events not available on worker");
+}
@@ -11,20 +11,30 @@ use num_enum::TryFromPrimitive;
 use serde::{Deserialize, Serialize};
 use serde_repr::*;
 
+#[macro_use]
+mod error;
+
 pub mod coord_worker;
 pub mod server_client;
 
+mod builder;
 mod query;
 
+pub use builder::{parse_data_pack_key, parse_var_address, DataPackBuilder};
+pub use error::{ErrorCode, ErrorFields, ResponseError};
 pub use server_client::*;
 
 use crate::socket::{pack, unpack, Encoding};
+use crate::trace::{self, TraceId};
 use crate::{error::Error, Result, TaskId};
 use fnv::FnvHashMap;
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
 
 /// Enumeration of all available message types.
+///
+/// Message types are serialized as their discriminants, new types have to
+/// be added at the end so that existing types keep their values.
 #[derive(Debug, Clone, Copy, PartialEq, TryFromPrimitive, Deserialize_repr, Serialize_repr)]
 #[repr(u8)]
 pub enum MessageType {
@@ -73,6 +83,136 @@ pub enum MessageType {
 
     SpawnEntitiesRequest,
     SpawnEntitiesResponse,
+    DespawnEntitiesRequest,
+    DespawnEntitiesResponse,
+
+    GetRuntimeErrorsRequest,
+    GetRuntimeErrorsResponse,
+
+    InvokeEventsRequest,
+    InvokeEventsResponse,
+    ScheduleEventRequest,
+    ScheduleEventResponse,
+    SetStepTriggerRequest,
+    SetStepTriggerResponse,
+
+    CreateSelectionRequest,
+    CreateSelectionResponse,
+    RefreshSelectionRequest,
+    RefreshSelectionResponse,
+    SelectionOperationRequest,
+    SelectionOperationResponse,
+
+    SubscribeRequest,
+    SubscribeResponse,
+    UnsubscribeRequest,
+    UnsubscribeResponse,
+    SubscriptionUpdate,
+
+    GridRegionRequest,
+    GridRegionResponse,
+
+    AttributionReportRequest,
+    AttributionReportResponse,
+
+    RegisterComponentRequest,
+    UpdateComponentLogicRequest,
+    AddPrefabRequest,
+    ModelEditResponse,
+
+    FindPathRequest,
+    FindPathResponse,
+
+    InspectRequest,
+    InspectResponse,
+
+    DiagnosticsRequest,
+    DiagnosticsResponse,
+
+    BusyHeartbeat,
+
+    LoadSnapshotRequest,
+    LoadSnapshotResponse,
+
+    StartSimRequest,
+    StartSimResponse,
+
+    SetPrefabDefaultsRequest,
+
+    StepCompletedNotice,
+    VarStreamNotice,
+
+    MemoryPressureNotice,
+
+    ErrorResponse,
+
+    ModifyEntityRequest,
+    ModifyEntityResponse,
+
+    ModelUpdateRequest,
+
+    RecorderRequest,
+    RecorderResponse,
+
+    ExportSnapshotChunk,
+}
+
+/// Priority class used when queueing incoming messages for handling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessagePriority {
+    /// Control messages, including turn advancement
+    Control,
+    Normal,
+    /// Data transfers, queries and other potentially large messages
+    Bulk,
+}
+
+impl MessageType {
+    pub fn priority(&self) -> MessagePriority {
+        match self {
+            MessageType::PingRequest
+            | MessageType::RegisterClientRequest
+            | MessageType::StatusRequest
+            | MessageType::TurnAdvanceRequest
+            | MessageType::GetRuntimeErrorsRequest
+            | MessageType::SetStepTriggerRequest => MessagePriority::Control,
+            MessageType::NativeQueryRequest
+            | MessageType::QueryRequest
+            | MessageType::DataTransferRequest
+            | MessageType::TypedDataTransferRequest
+            | MessageType::JsonPullRequest
+            | MessageType::DataPullRequest
+            | MessageType::TypedDataPullRequest
+            | MessageType::ExportSnapshotRequest
+            | MessageType::LoadSnapshotRequest
+            | MessageType::GridRegionRequest
+            | MessageType::FindPathRequest => MessagePriority::Bulk,
+            _ => MessagePriority::Normal,
+        }
+    }
+
+    /// Checks whether handling the message leaves the simulation intact.
+    /// Observer clients are limited to sending read-only messages.
+    pub fn is_read_only(&self) -> bool {
+        match self {
+            MessageType::PingRequest
+            | MessageType::StatusRequest
+            | MessageType::NativeQueryRequest
+            | MessageType::QueryRequest
+            | MessageType::DataTransferRequest
+            | MessageType::TypedDataTransferRequest
+            | MessageType::ScheduledDataTransferRequest
+            | MessageType::GetRuntimeErrorsRequest
+            | MessageType::CreateSelectionRequest
+            | MessageType::RefreshSelectionRequest
+            | MessageType::SubscribeRequest
+            | MessageType::UnsubscribeRequest
+            | MessageType::GridRegionRequest
+            | MessageType::AttributionReportRequest
+            | MessageType::FindPathRequest => true,
+            _ => false,
+        }
+    }
 }
 
 /// Self-described message structure wrapping a byte payload.
@@ -80,6 +220,9 @@ pub enum MessageType {
 pub struct Message {
     /// Integer identifier allowing for custom message filtering
     pub task_id: TaskId,
+    /// Correlation id used for tracing requests across the cluster
+    #[serde(default)]
+    pub trace_id: TraceId,
     /// Describes what is stored within the payload
     pub type_: MessageType,
     /// Byte representation of the message payload
@@ -102,6 +245,7 @@ where
             // let msg_bytes = prefix_with_msg_code(payload_bytes, type_);
             let msg = Message {
                 task_id,
+                trace_id: trace::current(),
                 type_: payload.type_(),
                 payload: pack_payload(payload, encoding)?,
             };
@@ -113,6 +257,7 @@ where
             let payload_bytes = pack_payload(payload, encoding)?;
             let msg = Message {
                 task_id,
+                trace_id: trace::current(),
                 type_,
                 payload: payload_bytes,
             };
@@ -136,6 +281,7 @@ impl Message {
         let bytes = pack_payload(payload, encoding)?;
         Ok(Message {
             task_id: 0,
+            trace_id: trace::current(),
             type_: msg_type,
             payload: bytes,
         })
@@ -152,10 +298,20 @@ impl Message {
     }
 
     /// Unpacks message payload into a payload struct of provided type.
+    ///
+    /// If the message is an `ErrorResponse` the error it carries is
+    /// returned instead.
     pub fn unpack_payload<'de, P: Payload + Deserialize<'de>>(
         &'de self,
         encoding: &Encoding,
     ) -> Result<P> {
+        if self.type_ == MessageType::ErrorResponse {
+            let resp: ErrorResponse = unpack(&self.payload, encoding)?;
+            return Err(Error::RequestRejected(
+                resp.request,
+                ResponseError::from_fields(resp.error, resp.code),
+            ));
+        }
         let unpacked = unpack(&self.payload, encoding)?;
         Ok(unpacked)
     }
@@ -216,6 +372,12 @@ impl From<outcome::Var> for VarJson {
             outcome::Var::Float(v) => VarJson::Float(v),
             outcome::Var::Bool(v) => VarJson::Bool(v),
             outcome::Var::Byte(v) => VarJson::Byte(v),
+            outcome::Var::Fixed(v) => VarJson::Float(v.to_float()),
+            // keep decimals exact
+            outcome::Var::Decimal(v) => VarJson::String(v.to_string()),
+            outcome::Var::Graph(v) => VarJson::String(v.to_string()),
+            outcome::Var::Geo(v) => VarJson::String(v.to_string()),
+            outcome::Var::Custom(v) => VarJson::String(v.to_string()),
             _ => unimplemented!(),
         }
     }
@@ -232,3 +394,11 @@ impl Into<outcome::Var> for VarJson {
         }
     }
 }
+
+#[test]
+fn baseline_message_types_keep_their_values() {
+    assert_eq!(MessageType::ExportSnapshotResponse as u8, 9);
+    assert_eq!(MessageType::RegisterRequest as u8, 10);
+    assert_eq!(MessageType::TurnAdvanceRequest as u8, 30);
+    assert_eq!(MessageType::SpawnEntitiesResponse as u8, 33);
+}
@@ -45,6 +45,9 @@ pub enum FilterType {
     AttrRange,
     Distance,
     Node,
+    /// Custom filter registered on the server, with the name as the first
+    /// argument
+    Custom,
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -62,6 +65,9 @@ pub enum MapType {
     Var,
     VarName,
     VarType,
+    /// Custom map registered on the server, with the name as the first
+    /// argument
+    Custom,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize_repr, Deserialize_repr)]
@@ -154,6 +160,14 @@ impl TryInto<outcome::Query> for Query {
                     filter.args[4].parse().unwrap(),
                     filter.args[5].parse().unwrap(),
                 ),
+                FilterType::Custom => {
+                    let mut args = filter.args.into_iter();
+                    outcome::query::Filter::Custom(
+                        args.next()
+                            .ok_or(Error::Other("missing custom filter name".to_string()))?,
+                        args.collect(),
+                    )
+                }
                 _ => unimplemented!(),
             };
             query.filters.push(_filter);
@@ -178,6 +192,14 @@ impl TryInto<outcome::Query> for Query {
                         .map(|s| outcome::string::new_truncate(s))
                         .collect(),
                 ),
+                MapType::Custom => {
+                    let mut args = map.args.into_iter();
+                    outcome::query::Map::Custom(
+                        args.next()
+                            .ok_or(Error::Other("missing custom map name".to_string()))?,
+                        args.collect(),
+                    )
+                }
                 _ => unimplemented!()
                 // MapType::SelectAddr => outcome::query::Map::SelectAddr(
                 //     map.args
@@ -1,8 +1,10 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
 
-use crate::msg::{MessageType, Payload, VarJson};
+use crate::msg::{ErrorCode, MessageType, Payload, ResponseError, VarJson};
 use outcome::{CompName, EntityId, Var, VarName};
 
+use crate::socket::traffic::TrafficStats;
 use crate::{Encoding, Transport};
 use fnv::FnvHashMap;
 use outcome::Address;
@@ -53,6 +55,11 @@ pub struct StatusResponse {
     pub description: String,
     // pub address: String,
     pub connected_clients: Vec<String>,
+    /// Number of pushed frames dropped for each of the connected clients
+    pub dropped_pushes: Vec<(String, u64)>,
+    /// Data sent and received over each client connection, and over each
+    /// worker link when the server is backed by an organizer
+    pub traffic: Vec<(String, TrafficStats)>,
     pub engine_version: String,
     pub uptime: usize,
     pub current_tick: usize,
@@ -92,9 +99,18 @@ impl Payload for StatusResponse {
 pub struct RegisterClientRequest {
     pub name: String,
     pub is_blocking: bool,
+    /// Requests read-only observer access, see [`crate::Client`]
+    #[serde(default)]
+    pub is_observer: bool,
     pub auth_pair: Option<(String, String)>,
     pub encodings: Vec<Encoding>,
     pub transports: Vec<Transport>,
+    /// Default precision for numbers in data transfers sent to the client
+    #[serde(default)]
+    pub float_precision: FloatPrecision,
+    /// Token of the session to be resumed, see [`RegisterClientResponse`]
+    #[serde(default)]
+    pub session_token: Option<String>,
 }
 pub(crate) const REGISTER_CLIENT_REQUEST: &str = "RegisterClientRequest";
 impl Payload for RegisterClientRequest {
@@ -109,6 +125,21 @@ pub struct RegisterClientResponse {
     pub encoding: Encoding,
     pub transport: Transport,
     pub address: String,
+    /// Address of the server's publishing socket, if there is one
+    #[serde(default)]
+    pub publish_address: Option<String>,
+    /// Reason for turning the client away, empty if registration succeeded
+    #[serde(default)]
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+    /// Token the client can use to resume it's session after reconnecting
+    #[serde(default)]
+    pub session_token: String,
+    /// Whether the session the client asked for was resumed
+    #[serde(default)]
+    pub resumed: bool,
 }
 pub(crate) const REGISTER_CLIENT_RESPONSE: &str = "RegisterClientResponse";
 impl Payload for RegisterClientResponse {
@@ -143,6 +174,9 @@ impl Payload for NativeQueryRequest {
 pub struct NativeQueryResponse {
     pub query_product: outcome::QueryProduct,
     pub error: Option<String>,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
 }
 pub(crate) const NATIVE_QUERY_RESPONSE: &str = "NativeQueryResponse";
 impl Payload for NativeQueryResponse {
@@ -156,13 +190,38 @@ impl Payload for NativeQueryResponse {
 /// `transfer_type` defines the process of data selection:
 ///     - `Full` get all the data from the sim database (ignores `selection`)
 ///     - `Selected` get some selected data, based on the `selection` list
+///     - `SelectVar` get selected vars of any type, addressed, skipping
+///     addresses that couldn't be found
+///     - `SelectVarOrdered` get selected vars as an ordered set, with the
+///     order stored on the server, requests with empty `selection` reuse
+///     the order given in `order_id`, or the last created order, always
+///     sending the whole set
+///     - `SelectVarOrderedDelta` same as `SelectVarOrdered`, but enables delta
+///     encoding for the order, transfers reusing the order this way only
+///     include the vars that changed since the previous transfer
+///     - `Diff` get all the vars that changed since the step given in
+///     `since_step`, or since the previous `Diff` transfer if not provided
+///     (ignores `selection`)
 ///
 /// `selection` is a list of addresses that can be used to select data
 /// for transfer.
+///
+/// `precision` overrides the precision negotiated at registration for this
+/// single transfer.
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct DataTransferRequest {
     pub transfer_type: String,
     pub selection: Vec<String>,
+    #[serde(default)]
+    pub precision: Option<FloatPrecision>,
+    /// Clock value of the step since which changes are requested, only
+    /// used with the `Diff` transfer type
+    #[serde(default)]
+    pub since_step: Option<u64>,
+    /// Id of the stored order used by the ordered transfer types, a new
+    /// order is created if not provided
+    #[serde(default)]
+    pub order_id: Option<u32>,
 }
 pub(crate) const DATA_TRANSFER_REQUEST: &str = "DataTransferRequest";
 impl Payload for DataTransferRequest {
@@ -178,6 +237,195 @@ pub enum TransferResponseData {
     Var(VarSimDataPack),
     AddressedVar(FnvHashMap<Address, Var>),
     VarOrdered(u32, VarSimDataPackOrdered),
+    /// Any of the var based variants, with numbers sent at reduced
+    /// precision
+    Compact(FloatPrecision, CompactData),
+    /// Changes to the ordered set since the previous transfer for the same
+    /// order, see [`VarSimDataPackOrdered::apply_delta`]
+    VarOrderedDelta(u32, VarOrderedDelta),
+    /// Vars changed since the previous `Diff` transfer, along with the
+    /// removed entities and vars
+    VarDiff(VarSimDataPack, VarRemovals),
+}
+
+impl TransferResponseData {
+    /// Converts var based data to the compact form using the given
+    /// precision. Native precision and typed data are left unchanged.
+    pub fn with_precision(self, precision: FloatPrecision) -> Self {
+        if precision == FloatPrecision::Native {
+            return self;
+        }
+        let compact = match self {
+            TransferResponseData::Var(pack) => CompactData::Var(
+                pack.vars
+                    .into_iter()
+                    .map(|(k, v)| (k, precision.encode(v)))
+                    .collect(),
+            ),
+            TransferResponseData::AddressedVar(vars) => CompactData::AddressedVar(
+                vars.into_iter()
+                    .map(|(k, v)| (k, precision.encode(v)))
+                    .collect(),
+            ),
+            TransferResponseData::VarOrdered(order_id, pack) => CompactData::VarOrdered(
+                order_id,
+                pack.vars.into_iter().map(|v| precision.encode(v)).collect(),
+            ),
+            TransferResponseData::VarDiff(pack, removed) => CompactData::VarDiff(
+                pack.vars
+                    .into_iter()
+                    .map(|(k, v)| (k, precision.encode(v)))
+                    .collect(),
+                removed,
+            ),
+            data => return data,
+        };
+        TransferResponseData::Compact(precision, compact)
+    }
+
+    /// Converts compact data back to the regular var based form.
+    pub fn expand(self) -> Self {
+        match self {
+            TransferResponseData::Compact(precision, compact) => match compact {
+                CompactData::Var(vars) => TransferResponseData::Var(VarSimDataPack {
+                    vars: vars
+                        .into_iter()
+                        .map(|(k, v)| (k, precision.decode(v)))
+                        .collect(),
+                }),
+                CompactData::AddressedVar(vars) => TransferResponseData::AddressedVar(
+                    vars.into_iter()
+                        .map(|(k, v)| (k, precision.decode(v)))
+                        .collect(),
+                ),
+                CompactData::VarOrdered(order_id, vars) => TransferResponseData::VarOrdered(
+                    order_id,
+                    VarSimDataPackOrdered {
+                        vars: vars.into_iter().map(|v| precision.decode(v)).collect(),
+                    },
+                ),
+                CompactData::VarDiff(vars, removed) => TransferResponseData::VarDiff(
+                    VarSimDataPack {
+                        vars: vars
+                            .into_iter()
+                            .map(|(k, v)| (k, precision.decode(v)))
+                            .collect(),
+                    },
+                    removed,
+                ),
+            },
+            data => data,
+        }
+    }
+}
+
+/// Precision of numbers sent in data transfers, independent of the
+/// engine's `Float` and `Int` types.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum FloatPrecision {
+    /// Numbers are sent as stored by the engine
+    Native,
+    /// Floats are sent as `f32`, ints as `i32` where they fit
+    F32,
+    /// Floats are sent as `i32` multiples of the given step, e.g. with the
+    /// step of `0.01` the value of `12.345` is sent as `1235`, floats not
+    /// fitting in `i32` at the given step are sent as stored, ints are sent
+    /// as `i32` where they fit
+    Quantized(f32),
+}
+
+impl Default for FloatPrecision {
+    fn default() -> Self {
+        FloatPrecision::Native
+    }
+}
+
+impl std::str::FromStr for FloatPrecision {
+    type Err = crate::Error;
+    fn from_str(s: &str) -> crate::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "native" | "full" => Ok(FloatPrecision::Native),
+            "f32" | "single" => Ok(FloatPrecision::F32),
+            _ => match s.split_once(':') {
+                Some(("quantized", step)) | Some(("q", step)) => {
+                    let step: f32 = step
+                        .parse()
+                        .map_err(|e| crate::Error::Other(format!("invalid step: {}", e)))?;
+                    if !(step > 0.) {
+                        return Err(crate::Error::Other(
+                            "quantization step must be positive".to_string(),
+                        ));
+                    }
+                    Ok(FloatPrecision::Quantized(step))
+                }
+                _ => Err(crate::Error::Other(format!(
+                    "failed parsing float precision from string: {}, expected \
+                    native, f32 or quantized:<step>",
+                    s
+                ))),
+            },
+        }
+    }
+}
+
+impl FloatPrecision {
+    pub fn encode(&self, var: Var) -> WireVar {
+        match (self, var) {
+            (FloatPrecision::Native, var) => WireVar::Native(var),
+            (FloatPrecision::F32, Var::Float(f)) => WireVar::F32(f as f32),
+            (FloatPrecision::Quantized(step), Var::Float(f)) => {
+                let q = (f as f64 / *step as f64).round();
+                if q >= i32::MIN as f64 && q <= i32::MAX as f64 {
+                    WireVar::Quantized(q as i32)
+                } else {
+                    // doesn't fit at the given step, including nan and
+                    // infinite values
+                    WireVar::Native(Var::Float(f))
+                }
+            }
+            (_, Var::Int(i)) => match i32::try_from(i) {
+                Ok(i) => WireVar::I32(i),
+                Err(_) => WireVar::Native(Var::Int(i)),
+            },
+            (_, var) => WireVar::Native(var),
+        }
+    }
+
+    pub fn decode(&self, var: WireVar) -> Var {
+        match var {
+            WireVar::Native(var) => var,
+            WireVar::F32(f) => Var::Float(f as outcome::Float),
+            WireVar::Quantized(q) => {
+                let step = match self {
+                    FloatPrecision::Quantized(step) => *step as f64,
+                    _ => 1.,
+                };
+                Var::Float((q as f64 * step) as outcome::Float)
+            }
+            WireVar::I32(i) => Var::Int(i as outcome::Int),
+        }
+    }
+}
+
+/// Var as sent over the wire at reduced precision.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum WireVar {
+    Native(Var),
+    F32(f32),
+    Quantized(i32),
+    I32(i32),
+}
+
+/// Compact counterparts of the var based transfer data variants.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum CompactData {
+    Var(FnvHashMap<(outcome::EntityName, outcome::CompName, outcome::VarName), WireVar>),
+    AddressedVar(FnvHashMap<Address, WireVar>),
+    VarOrdered(u32, Vec<WireVar>),
+    VarDiff(
+        FnvHashMap<(outcome::EntityName, outcome::CompName, outcome::VarName), WireVar>,
+        VarRemovals,
+    ),
 }
 
 /// Response to `DataTransferRequest`.
@@ -188,6 +436,10 @@ pub enum TransferResponseData {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct DataTransferResponse {
     pub data: TransferResponseData,
+    /// Values of the vars designated for interpolation as of the previous
+    /// step, present if the server has interpolation configured
+    #[serde(default)]
+    pub interpolation: Option<Interpolation>,
 }
 pub(crate) const DATA_TRANSFER_RESPONSE: &str = "DataTransferResponse";
 impl Payload for DataTransferResponse {
@@ -196,11 +448,52 @@ impl Payload for DataTransferResponse {
     }
 }
 
+/// Metadata allowing clients to smoothly interpolate float vars between
+/// steps, e.g. when rendering at a higher rate than the step rate.
+///
+/// Timestamps are in milliseconds since the unix epoch, as measured on
+/// the server. Current values are found in the transferred data itself.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Interpolation {
+    /// Time at which the previous step was processed
+    pub prev_step_at: u64,
+    /// Time at which the current step was processed
+    pub step_at: u64,
+    /// Values of the designated vars as of the previous step
+    pub previous: FnvHashMap<Address, Var>,
+}
+
+impl Interpolation {
+    /// Interpolates the value of a designated float var at the given time,
+    /// shifted back by a single step interval so that the current value is
+    /// reached once another full interval has passed. Returns the current
+    /// value if there is no previous value for the address.
+    pub fn interpolate(
+        &self,
+        address: &Address,
+        current: outcome::Float,
+        now: u64,
+    ) -> outcome::Float {
+        let previous = match self.previous.get(address) {
+            Some(Var::Float(f)) => *f,
+            _ => return current,
+        };
+        let interval = self.step_at.saturating_sub(self.prev_step_at);
+        if interval == 0 {
+            return current;
+        }
+        let t = (now.saturating_sub(self.step_at) as f64 / interval as f64).min(1.);
+        previous + (current - previous) * t as outcome::Float
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct ScheduledDataTransferRequest {
     pub event_triggers: Vec<String>,
     pub transfer_type: String,
     pub selection: Vec<String>,
+    #[serde(default)]
+    pub precision: Option<FloatPrecision>,
 }
 pub(crate) const SCHEDULED_DATA_TRANSFER_REQUEST: &str = "ScheduledDataTransferRequest";
 impl Payload for ScheduledDataTransferRequest {
@@ -214,11 +507,72 @@ pub struct VarSimDataPackOrdered {
     pub vars: Vec<outcome::Var>,
 }
 
+impl VarSimDataPackOrdered {
+    /// Creates a delta containing only the vars that differ from the
+    /// previous set. Returns `None` if the sets differ in length, in which
+    /// case the whole set needs to be sent.
+    pub fn delta_from(&self, previous: &VarSimDataPackOrdered) -> Option<VarOrderedDelta> {
+        if self.vars.len() != previous.vars.len() {
+            return None;
+        }
+        let changed = self
+            .vars
+            .iter()
+            .zip(previous.vars.iter())
+            .enumerate()
+            .filter(|(_, (new, old))| new != old)
+            .map(|(idx, (new, _))| (idx as u32, new.clone()))
+            .collect();
+        Some(VarOrderedDelta {
+            len: self.vars.len() as u32,
+            changed,
+        })
+    }
+
+    /// Applies changes received as a delta to the previously received set.
+    pub fn apply_delta(&mut self, delta: VarOrderedDelta) -> crate::Result<()> {
+        if self.vars.len() != delta.len as usize {
+            return Err(crate::Error::Other(format!(
+                "delta for ordered set of length {} applied to set of length {}",
+                delta.len,
+                self.vars.len()
+            )));
+        }
+        for (idx, var) in delta.changed {
+            let slot = self.vars.get_mut(idx as usize).ok_or_else(|| {
+                crate::Error::Other(format!("delta index out of bounds: {}", idx))
+            })?;
+            *slot = var;
+        }
+        Ok(())
+    }
+}
+
+/// Changes to an ordered set of vars, as `(index, new value)` pairs.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct VarOrderedDelta {
+    /// Length of the whole set, used for validating the delta is applied
+    /// to the right base
+    pub len: u32,
+    pub changed: Vec<(u32, outcome::Var)>,
+}
+
 #[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
 pub struct VarSimDataPack {
     pub vars: FnvHashMap<(outcome::EntityName, outcome::CompName, outcome::VarName), outcome::Var>,
 }
 
+/// Entities and vars removed since the previous `Diff` transfer.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct VarRemovals {
+    /// Whether removals since the previous transfer are unknown, in which
+    /// case the accompanying data holds all the vars and replaces any
+    /// previously received data
+    pub full: bool,
+    pub entities: Vec<outcome::EntityName>,
+    pub vars: Vec<(outcome::EntityName, outcome::CompName, outcome::VarName)>,
+}
+
 /// Structure holding all data organized based on data types.
 ///
 /// Each data type is represented by a set of key-value pairs, where
@@ -310,6 +664,9 @@ impl Payload for TypedDataTransferRequest {
 pub struct TypedDataTransferResponse {
     pub data: TypedSimDataPack,
     pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
 }
 pub(crate) const TYPED_DATA_TRANSFER_RESPONSE: &str = "TypedDataTransferResponse";
 impl Payload for TypedDataTransferResponse {
@@ -337,6 +694,12 @@ pub enum PullRequestData {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct DataPullRequest {
     pub data: PullRequestData,
+    /// Optional key used to deduplicate retried requests
+    pub idempotency_key: Option<String>,
+    /// Units the provided values are expressed in, values for vars with
+    /// a declared unit are converted or rejected accordingly
+    #[serde(default)]
+    pub units: FnvHashMap<Address, String>,
 }
 pub(crate) const DATA_PULL_REQUEST: &str = "DataPullRequest";
 impl Payload for DataPullRequest {
@@ -362,9 +725,38 @@ impl Payload for JsonPullRequest {
 /// Response to `DataPullRequest`.
 ///
 /// `error` contains the report of any errors that might have occurred.
+/// Each item that failed validation is listed in `rejected`, items that
+/// were pulled successfully are not listed.
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct DataPullResponse {
     pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+    /// Number of items that were successfully pulled
+    pub pulled: u32,
+    pub rejected: Vec<PullItemError>,
+}
+
+/// Describes why a single item from a pull request was rejected.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct PullItemError {
+    pub address: String,
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+
+impl PullItemError {
+    pub fn new(address: String, error: impl Into<ResponseError>) -> Self {
+        let (error, code) = error.into().into_fields();
+        Self {
+            address,
+            error,
+            code,
+        }
+    }
 }
 pub(crate) const DATA_PULL_RESPONSE: &str = "DataPullResponse";
 impl Payload for DataPullResponse {
@@ -392,6 +784,9 @@ impl Payload for TypedDataPullRequest {
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct TypedDataPullResponse {
     pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
 }
 pub(crate) const TYPED_DATA_PULL_RESPONSE: &str = "TypedDataPullResponse";
 impl Payload for TypedDataPullResponse {
@@ -442,6 +837,9 @@ impl Payload for TurnAdvanceRequest {
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct TurnAdvanceResponse {
     pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
 }
 pub(crate) const TURN_ADVANCE_RESPONSE: &str = "TurnAdvanceResponse";
 impl Payload for TurnAdvanceResponse {
@@ -458,6 +856,8 @@ pub struct SpawnEntitiesRequest {
     /// List of names for the new entities to be spawned, has to be the same
     /// length as `entity_prefabs`
     pub entity_names: Vec<String>,
+    /// Optional key used to deduplicate retried requests
+    pub idempotency_key: Option<String>,
 }
 pub(crate) const SPAWN_ENTITIES_REQUEST: &str = "SpawnEntitiesRequest";
 impl Payload for SpawnEntitiesRequest {
@@ -472,6 +872,9 @@ pub struct SpawnEntitiesResponse {
     /// order from the request is preserved
     pub entity_names: Vec<String>,
     pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
 }
 pub(crate) const SPAWN_ENTITIES_RESPONSE: &str = "SpawnEntitiesResponse";
 impl Payload for SpawnEntitiesResponse {
@@ -480,6 +883,649 @@ impl Payload for SpawnEntitiesResponse {
     }
 }
 
+/// Requests the server to despawn a number of entities.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct DespawnEntitiesRequest {
+    /// List of entities to be despawned, each referenced either by its
+    /// integer id or its name
+    pub entities: Vec<String>,
+    /// Optional key used to deduplicate retried requests
+    pub idempotency_key: Option<String>,
+}
+pub(crate) const DESPAWN_ENTITIES_REQUEST: &str = "DespawnEntitiesRequest";
+impl Payload for DespawnEntitiesRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::DespawnEntitiesRequest
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct DespawnEntitiesResponse {
+    /// Ids of entities that were despawned as the result of the request
+    pub entity_ids: Vec<EntityId>,
+    /// First error encountered, entities following a failed despawn are
+    /// still processed
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+pub(crate) const DESPAWN_ENTITIES_RESPONSE: &str = "DespawnEntitiesResponse";
+impl Payload for DespawnEntitiesResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::DespawnEntitiesResponse
+    }
+}
+
+/// Requests the server to attach components to, or detach them from,
+/// a live entity.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ModifyEntityRequest {
+    /// Entity referenced either by its integer id or its name
+    pub entity: String,
+    /// Components to be attached, vars are initialized with default values
+    pub attach: Vec<String>,
+    /// Components to be detached, along with their vars
+    pub detach: Vec<String>,
+    /// Optional key used to deduplicate retried requests
+    pub idempotency_key: Option<String>,
+}
+pub(crate) const MODIFY_ENTITY_REQUEST: &str = "ModifyEntityRequest";
+impl Payload for ModifyEntityRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::ModifyEntityRequest
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ModifyEntityResponse {
+    /// Components that were attached as the result of the request
+    pub attached: Vec<String>,
+    /// Components that were detached as the result of the request
+    pub detached: Vec<String>,
+    /// First error encountered, components following a failed operation
+    /// are still processed
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+pub(crate) const MODIFY_ENTITY_RESPONSE: &str = "ModifyEntityResponse";
+impl Payload for ModifyEntityResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::ModifyEntityResponse
+    }
+}
+
+/// Requests the list of logic errors that occurred during processed steps.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct GetRuntimeErrorsRequest {
+    /// Clock value of the earliest step to include errors from, defaults
+    /// to the last processed step
+    #[serde(default)]
+    pub since_clock: Option<usize>,
+}
+pub(crate) const GET_RUNTIME_ERRORS_REQUEST: &str = "GetRuntimeErrorsRequest";
+impl Payload for GetRuntimeErrorsRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::GetRuntimeErrorsRequest
+    }
+}
+
+/// Single logic error as recorded during step processing.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RuntimeError {
+    /// Clock value at the beginning of the step the error occurred in
+    #[serde(default)]
+    pub clock: usize,
+    pub entity: EntityId,
+    pub component: String,
+    /// Path to the source file, relative to project root
+    pub source: String,
+    /// Line number as seen in source file
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct GetRuntimeErrorsResponse {
+    /// Clock value at the time the errors were retrieved
+    pub clock: usize,
+    pub errors: Vec<RuntimeError>,
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+pub(crate) const GET_RUNTIME_ERRORS_RESPONSE: &str = "GetRuntimeErrorsResponse";
+impl Payload for GetRuntimeErrorsResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::GetRuntimeErrorsResponse
+    }
+}
+
+/// Invokes events on the simulation. Invoked events are processed during
+/// the next step.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct InvokeEventsRequest {
+    pub events: Vec<String>,
+}
+pub(crate) const INVOKE_EVENTS_REQUEST: &str = "InvokeEventsRequest";
+impl Payload for InvokeEventsRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::InvokeEventsRequest
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct InvokeEventsResponse {
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+pub(crate) const INVOKE_EVENTS_RESPONSE: &str = "InvokeEventsResponse";
+impl Payload for InvokeEventsResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::InvokeEventsResponse
+    }
+}
+
+/// Schedules an event to fire at a future step, optionally recurring.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ScheduleEventRequest {
+    pub event: String,
+    /// Clock value of the step the event fires at, for recurring events
+    /// defaults to `every_n_steps` from the current step
+    pub at_step: Option<u64>,
+    /// Interval in steps at which the event recurs, none for one-off
+    /// events
+    pub every_n_steps: Option<u64>,
+}
+pub(crate) const SCHEDULE_EVENT_REQUEST: &str = "ScheduleEventRequest";
+impl Payload for ScheduleEventRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::ScheduleEventRequest
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ScheduleEventResponse {
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+pub(crate) const SCHEDULE_EVENT_RESPONSE: &str = "ScheduleEventResponse";
+impl Payload for ScheduleEventResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::ScheduleEventResponse
+    }
+}
+
+/// Administrative request for changing the policy used by the organizer
+/// to trigger new steps.
+///
+/// Trigger is provided in one of the following forms: `clients`,
+/// `continuous`, `event:<event_name>`, `interval:<millis>`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SetStepTriggerRequest {
+    pub trigger: String,
+}
+pub(crate) const SET_STEP_TRIGGER_REQUEST: &str = "SetStepTriggerRequest";
+impl Payload for SetStepTriggerRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::SetStepTriggerRequest
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SetStepTriggerResponse {
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+pub(crate) const SET_STEP_TRIGGER_RESPONSE: &str = "SetStepTriggerResponse";
+impl Payload for SetStepTriggerResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::SetStepTriggerResponse
+    }
+}
+
+/// Creates a named selection of entities on the server, based on the
+/// provided query. Selection can then be used for issuing operations against
+/// all of the selected entities without retransmitting entity lists.
+///
+/// Creating a selection with an already existing name replaces the old one.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct CreateSelectionRequest {
+    pub name: String,
+    pub query: crate::msg::query::Query,
+    /// Whether the selection is kept updated by re-evaluating the query
+    /// after each step, otherwise it has to be refreshed explicitly
+    pub dynamic: bool,
+}
+pub(crate) const CREATE_SELECTION_REQUEST: &str = "CreateSelectionRequest";
+impl Payload for CreateSelectionRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::CreateSelectionRequest
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct CreateSelectionResponse {
+    /// Number of selected entities
+    pub count: u32,
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+pub(crate) const CREATE_SELECTION_RESPONSE: &str = "CreateSelectionResponse";
+impl Payload for CreateSelectionResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::CreateSelectionResponse
+    }
+}
+
+/// Re-evaluates the query the selection was created with.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RefreshSelectionRequest {
+    pub name: String,
+}
+pub(crate) const REFRESH_SELECTION_REQUEST: &str = "RefreshSelectionRequest";
+impl Payload for RefreshSelectionRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::RefreshSelectionRequest
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RefreshSelectionResponse {
+    /// Number of selected entities
+    pub count: u32,
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+pub(crate) const REFRESH_SELECTION_RESPONSE: &str = "RefreshSelectionResponse";
+impl Payload for RefreshSelectionResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::RefreshSelectionResponse
+    }
+}
+
+/// Operation applied to each of the entities in a selection.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum SelectionOperation {
+    /// Sets the value of the given component var
+    SetVar {
+        component: String,
+        var: String,
+        value: Var,
+    },
+    /// Invokes the event for the selected entities only
+    InvokeEvent(String),
+    /// Removes the selected entities from the simulation
+    Despawn,
+}
+
+/// Applies the operation to all the entities in a named selection.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SelectionOperationRequest {
+    pub name: String,
+    pub operation: SelectionOperation,
+}
+pub(crate) const SELECTION_OPERATION_REQUEST: &str = "SelectionOperationRequest";
+impl Payload for SelectionOperationRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::SelectionOperationRequest
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SelectionOperationResponse {
+    /// Number of entities the operation was successfully applied to
+    pub affected: u32,
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+pub(crate) const SELECTION_OPERATION_RESPONSE: &str = "SelectionOperationResponse";
+impl Payload for SelectionOperationResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::SelectionOperationResponse
+    }
+}
+
+/// Subscribes to updates of vars selected by the query. The query is
+/// re-evaluated after each step and changes are pushed to the client as
+/// `SubscriptionUpdate`s.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SubscribeRequest {
+    pub query: crate::msg::query::Query,
+}
+pub(crate) const SUBSCRIBE_REQUEST: &str = "SubscribeRequest";
+impl Payload for SubscribeRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::SubscribeRequest
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SubscribeResponse {
+    /// Id of the created subscription, included with each update
+    pub subscription_id: u32,
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+pub(crate) const SUBSCRIBE_RESPONSE: &str = "SubscribeResponse";
+impl Payload for SubscribeResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::SubscribeResponse
+    }
+}
+
+/// Cancels a subscription.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct UnsubscribeRequest {
+    pub subscription_id: u32,
+}
+pub(crate) const UNSUBSCRIBE_REQUEST: &str = "UnsubscribeRequest";
+impl Payload for UnsubscribeRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::UnsubscribeRequest
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct UnsubscribeResponse {
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+pub(crate) const UNSUBSCRIBE_RESPONSE: &str = "UnsubscribeResponse";
+impl Payload for UnsubscribeResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::UnsubscribeResponse
+    }
+}
+
+/// Pushed by the server to subscribed clients, contains only the vars
+/// that changed since the previous update, unless it's a full update.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SubscriptionUpdate {
+    pub subscription_id: u32,
+    /// Clock at which the values were read
+    pub clock: usize,
+    /// Vars with changed values, or newly selected ones
+    pub vars: FnvHashMap<Address, Var>,
+    /// Vars no longer selected by the query
+    pub removed: Vec<Address>,
+    /// Update carries all the selected vars, replacing any previously
+    /// received values. Sent first and after a previous update was lost.
+    #[serde(default)]
+    pub full: bool,
+}
+pub(crate) const SUBSCRIPTION_UPDATE: &str = "SubscriptionUpdate";
+impl Payload for SubscriptionUpdate {
+    fn type_(&self) -> MessageType {
+        MessageType::SubscriptionUpdate
+    }
+}
+
+/// Requests a rectangular region of a grid var.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct GridRegionRequest {
+    /// Address of the grid var
+    pub address: String,
+    /// Horizontal offset of the region
+    pub x: u32,
+    /// Vertical offset of the region
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+pub(crate) const GRID_REGION_REQUEST: &str = "GridRegionRequest";
+impl Payload for GridRegionRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::GridRegionRequest
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct GridRegionResponse {
+    /// Dimensions of the whole grid as `(width, height)`
+    pub grid_size: (u32, u32),
+    /// Requested region as a list of rows, clipped to the grid bounds
+    pub region: Vec<Vec<Var>>,
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+pub(crate) const GRID_REGION_RESPONSE: &str = "GridRegionResponse";
+impl Payload for GridRegionResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::GridRegionResponse
+    }
+}
+
+/// Requests the cheapest path on a grid or graph var. Requires the
+/// `pathfinding` feature on the server.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct FindPathRequest {
+    /// Address of the grid or graph var
+    pub address: String,
+    /// Start of the path, `vec2` coordinates for grids, entity id for graphs
+    pub from: Var,
+    /// End of the path, `vec2` coordinates for grids, entity id for graphs
+    pub to: Var,
+    /// Either `astar` or `dijkstra`, defaults to `astar` if empty
+    pub algorithm: String,
+}
+pub(crate) const FIND_PATH_REQUEST: &str = "FindPathRequest";
+impl Payload for FindPathRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::FindPathRequest
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct FindPathResponse {
+    /// Path as a list var including both ends, empty if there is no path
+    pub path: Var,
+    /// Total cost of the path, `-1` if there is no path
+    pub cost: f64,
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+pub(crate) const FIND_PATH_RESPONSE: &str = "FindPathResponse";
+impl Payload for FindPathResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::FindPathResponse
+    }
+}
+
+/// Requests a breakdown of storage and execution time per component.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct AttributionReportRequest {}
+pub(crate) const ATTRIBUTION_REPORT_REQUEST: &str = "AttributionReportRequest";
+impl Payload for AttributionReportRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::AttributionReportRequest
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct AttributionReportResponse {
+    pub report: outcome::sim::introspect::AttributionReport,
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+pub(crate) const ATTRIBUTION_REPORT_RESPONSE: &str = "AttributionReportResponse";
+impl Payload for AttributionReportResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::AttributionReportResponse
+    }
+}
+
+/// Registers a new component with the model. Requires admin scope.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RegisterComponentRequest {
+    pub name: String,
+    pub vars: Vec<outcome::model::VarModel>,
+    /// Events triggering the component's logic
+    pub triggers: Vec<String>,
+    /// Script source for the component's logic
+    pub logic: Option<String>,
+}
+pub(crate) const REGISTER_COMPONENT_REQUEST: &str = "RegisterComponentRequest";
+impl Payload for RegisterComponentRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::RegisterComponentRequest
+    }
+}
+
+/// Replaces the logic of an existing component with logic created from
+/// the provided script source. Requires admin scope.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct UpdateComponentLogicRequest {
+    pub name: String,
+    pub logic: String,
+}
+pub(crate) const UPDATE_COMPONENT_LOGIC_REQUEST: &str = "UpdateComponentLogicRequest";
+impl Payload for UpdateComponentLogicRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::UpdateComponentLogicRequest
+    }
+}
+
+/// Registers a new entity prefab with the model. Requires admin scope.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct AddPrefabRequest {
+    pub name: String,
+    pub components: Vec<String>,
+}
+pub(crate) const ADD_PREFAB_REQUEST: &str = "AddPrefabRequest";
+impl Payload for AddPrefabRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::AddPrefabRequest
+    }
+}
+
+/// Overrides default var values for entities spawned from the prefab,
+/// given as `(component, var, value)` triples. Requires admin scope.
+///
+/// Only affects entities spawned after the change is applied.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SetPrefabDefaultsRequest {
+    pub prefab: String,
+    pub defaults: Vec<(String, String, Var)>,
+}
+pub(crate) const SET_PREFAB_DEFAULTS_REQUEST: &str = "SetPrefabDefaultsRequest";
+impl Payload for SetPrefabDefaultsRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::SetPrefabDefaultsRequest
+    }
+}
+
+/// Pushes a new model to the server, replacing the current one. Requires
+/// admin scope.
+///
+/// Partial updates are merged into the current model instead, replacing
+/// events, components and prefabs with matching names and adding the rest.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ModelUpdateRequest {
+    pub model: outcome::SimModel,
+    pub partial: bool,
+}
+pub(crate) const MODEL_UPDATE_REQUEST: &str = "ModelUpdateRequest";
+impl Payload for ModelUpdateRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::ModelUpdateRequest
+    }
+}
+
+/// Response to any of the model editing requests.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ModelEditResponse {
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+pub(crate) const MODEL_EDIT_RESPONSE: &str = "ModelEditResponse";
+impl Payload for ModelEditResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::ModelEditResponse
+    }
+}
+
+/// Action performed on the server's recorder.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum RecorderAction {
+    /// Starts recording, replacing any recording already in progress
+    Start,
+    /// Stops recording, writing out all the buffered samples
+    Stop,
+    /// Writes out buffered samples without stopping the recording
+    Flush,
+}
+
+/// Controls recording of selected vars to disk on the server. Requires the
+/// admin scope.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RecorderRequest {
+    pub action: RecorderAction,
+    /// Addresses of the recorded vars, only used when starting
+    #[serde(default)]
+    pub addrs: Vec<String>,
+    /// Number of steps between samples, defaults to every step
+    #[serde(default)]
+    pub interval: u32,
+    /// Output format, one of `csv`, `jsonl` or `columnar`, defaults to
+    /// `csv`
+    #[serde(default)]
+    pub format: String,
+    /// Path to the output file, relative to the project's recordings
+    /// directory
+    #[serde(default)]
+    pub path: String,
+}
+pub(crate) const RECORDER_REQUEST: &str = "RecorderRequest";
+impl Payload for RecorderRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::RecorderRequest
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RecorderResponse {
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+    /// Whether recording is in progress after the request was handled
+    pub recording: bool,
+}
+pub(crate) const RECORDER_RESPONSE: &str = "RecorderResponse";
+impl Payload for RecorderResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::RecorderResponse
+    }
+}
+
 /// Requests the server to export a snapshot.
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct ExportSnapshotRequest {
@@ -489,6 +1535,14 @@ pub struct ExportSnapshotRequest {
     pub save_to_disk: bool,
     /// Whether the snapshot should be send back.
     pub send_back: bool,
+    /// Size of the chunks the snapshot is sent back in, overrides the
+    /// server's default
+    #[serde(default)]
+    pub chunk_size: Option<u32>,
+    /// Whether the snapshot should be compressed before sending it back,
+    /// ignored if the server doesn't support compression
+    #[serde(default)]
+    pub compress: bool,
 }
 pub(crate) const EXPORT_SNAPSHOT_REQUEST: &str = "ExportSnapshotRequest";
 impl Payload for ExportSnapshotRequest {
@@ -497,10 +1551,19 @@ impl Payload for ExportSnapshotRequest {
     }
 }
 
+/// Sent in response to the export request. If the snapshot is to be sent
+/// back, the response is followed by a series of snapshot chunks.
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct ExportSnapshotResponse {
     pub error: String,
-    pub snapshot: Vec<u8>,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+    /// Total size of the snapshot being sent back in bytes, zero if
+    /// nothing is sent back
+    pub total_len: u64,
+    /// Whether the sent back snapshot is compressed using LZ4
+    pub compressed: bool,
 }
 pub(crate) const EXPORT_SNAPSHOT_RESPONSE: &str = "ExportSnapshotResponse";
 impl Payload for ExportSnapshotResponse {
@@ -509,6 +1572,203 @@ impl Payload for ExportSnapshotResponse {
     }
 }
 
+/// Single chunk of an exported snapshot. Chunks are sent in order, starting
+/// at offset zero.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ExportSnapshotChunk {
+    /// Total size of the snapshot in bytes
+    pub total_len: u64,
+    /// Position of the chunk within the snapshot
+    pub offset: u64,
+    #[serde(with = "serde_bytes")]
+    pub chunk: Vec<u8>,
+}
+pub(crate) const EXPORT_SNAPSHOT_CHUNK: &str = "ExportSnapshotChunk";
+impl Payload for ExportSnapshotChunk {
+    fn type_(&self) -> MessageType {
+        MessageType::ExportSnapshotChunk
+    }
+}
+
+/// Determines what happens to the current simulation once an uploaded
+/// snapshot is loaded.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum SnapshotLoadMode {
+    /// Current simulation is discarded
+    Replace,
+    /// Current simulation is saved to disk on the server under the given
+    /// name before being replaced, so that it can be loaded again later
+    Fork(String),
+}
+
+/// Uploads a single chunk of a snapshot to the server. Once the last chunk
+/// is received the server restores the simulation from the snapshot.
+///
+/// Chunks have to be sent in order, starting at offset zero. Sending a
+/// chunk at offset zero discards any upload already in progress. Requires
+/// the admin scope.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct LoadSnapshotRequest {
+    /// Total size of the snapshot in bytes
+    pub total_len: u64,
+    /// Position of the chunk within the snapshot
+    pub offset: u64,
+    #[serde(with = "serde_bytes")]
+    pub chunk: Vec<u8>,
+    pub mode: SnapshotLoadMode,
+}
+pub(crate) const LOAD_SNAPSHOT_REQUEST: &str = "LoadSnapshotRequest";
+impl Payload for LoadSnapshotRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::LoadSnapshotRequest
+    }
+}
+
+/// Sent in response to each uploaded snapshot chunk.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct LoadSnapshotResponse {
+    /// Number of snapshot bytes received so far
+    pub received: u64,
+    /// Whether the simulation was restored from the snapshot
+    pub loaded: bool,
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+pub(crate) const LOAD_SNAPSHOT_RESPONSE: &str = "LoadSnapshotResponse";
+impl Payload for LoadSnapshotResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::LoadSnapshotResponse
+    }
+}
+
+/// Requests an idle server to start a new simulation, using a scenario
+/// available locally on the server. Requires the admin scope.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct StartSimRequest {
+    /// Path to the scenario, relative to the server's scenarios directory
+    pub scenario: String,
+    /// Seed overriding the one found in the scenario manifest
+    pub seed: Option<u64>,
+    /// Settings overriding the ones found in the scenario manifest, keyed by
+    /// var address
+    pub settings: HashMap<String, String>,
+}
+pub(crate) const START_SIM_REQUEST: &str = "StartSimRequest";
+impl Payload for StartSimRequest {
+    fn type_(&self) -> MessageType {
+        MessageType::StartSimRequest
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct StartSimResponse {
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+pub(crate) const START_SIM_RESPONSE: &str = "StartSimResponse";
+impl Payload for StartSimResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::StartSimResponse
+    }
+}
+
+/// Sent by the server in place of the regular response if the request was
+/// rejected before being handled, e.g. due to insufficient permissions.
+///
+/// Receiving it while unpacking any other payload results in an error, see
+/// [`Message::unpack_payload`]. The error code can be used to tell apart
+/// different reasons for rejection, e.g. `ErrorCode::Unauthorized`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ErrorResponse {
+    /// Type of the rejected request
+    pub request: MessageType,
+    pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
+}
+pub(crate) const ERROR_RESPONSE: &str = "ErrorResponse";
+impl Payload for ErrorResponse {
+    fn type_(&self) -> MessageType {
+        MessageType::ErrorResponse
+    }
+}
+
+/// Sent periodically by the server to clients waiting on in-flight
+/// operations, letting them know the server is still working on their
+/// requests. Clients should skip it when waiting for a response.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct BusyHeartbeat {
+    /// Number of client's operations still in progress
+    pub in_flight: u32,
+}
+pub(crate) const BUSY_HEARTBEAT: &str = "BusyHeartbeat";
+impl Payload for BusyHeartbeat {
+    fn type_(&self) -> MessageType {
+        MessageType::BusyHeartbeat
+    }
+}
+
+/// Published by the server each time a simulation step is completed.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct StepCompletedNotice {
+    /// Clock after the step was processed
+    pub clock: usize,
+}
+pub(crate) const STEP_COMPLETED_NOTICE: &str = "StepCompletedNotice";
+impl Payload for StepCompletedNotice {
+    fn type_(&self) -> MessageType {
+        MessageType::StepCompletedNotice
+    }
+}
+
+/// Published by the server after each step for each of the var streams
+/// defined in the server config.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct VarStreamNotice {
+    /// Name of the stream
+    pub stream: String,
+    /// Clock at which the values were read
+    pub clock: usize,
+    pub vars: FnvHashMap<Address, Var>,
+}
+pub(crate) const VAR_STREAM_NOTICE: &str = "VarStreamNotice";
+impl Payload for VarStreamNotice {
+    fn type_(&self) -> MessageType {
+        MessageType::VarStreamNotice
+    }
+}
+
+/// Level of memory pressure the server process is under.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Deserialize, Serialize)]
+pub enum MemoryPressure {
+    /// Memory use is below the configured limits
+    Normal,
+    /// Soft limit was exceeded, caches and buffers are being released
+    Soft,
+    /// Hard limit was exceeded, spawning new entities is refused
+    Hard,
+}
+
+/// Sent to all clients, and published to subscribers, each time the level
+/// of memory pressure the server is under changes.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct MemoryPressureNotice {
+    pub level: MemoryPressure,
+    /// Resident memory of the server process, in bytes
+    pub resident: u64,
+}
+pub(crate) const MEMORY_PRESSURE_NOTICE: &str = "MemoryPressureNotice";
+impl Payload for MemoryPressureNotice {
+    fn type_(&self) -> MessageType {
+        MessageType::MemoryPressureNotice
+    }
+}
+
 /// Requests the server to list all local (available on the
 /// server) scenarios.
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -525,6 +1785,9 @@ pub struct ListLocalScenariosRequest {}
 pub struct ListLocalScenariosResponse {
     pub scenarios: Vec<String>,
     pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
 }
 
 /// Requests the server to load a local (available on the
@@ -542,6 +1805,9 @@ pub struct LoadLocalScenarioRequest {
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct LoadLocalScenarioResponse {
     pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
 }
 
 /// Requests the server to load a scenario included in the message.
@@ -566,6 +1832,9 @@ pub struct LoadRemoteScenarioRequest {
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct LoadRemoteScenarioResponse {
     pub error: String,
+    /// Kind of the reported error, if any
+    #[serde(default)]
+    pub code: Option<ErrorCode>,
 }
 
 // #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -644,3 +1913,55 @@ impl Payload for LoadRemoteScenarioResponse {
 //         }
 //     }
 // }
+
+impl_error_fields!(
+    RegisterClientResponse,
+    TypedDataTransferResponse,
+    DataPullResponse,
+    PullItemError,
+    TypedDataPullResponse,
+    TurnAdvanceResponse,
+    SpawnEntitiesResponse,
+    DespawnEntitiesResponse,
+    ModifyEntityResponse,
+    GetRuntimeErrorsResponse,
+    InvokeEventsResponse,
+    ScheduleEventResponse,
+    SetStepTriggerResponse,
+    CreateSelectionResponse,
+    RefreshSelectionResponse,
+    SelectionOperationResponse,
+    SubscribeResponse,
+    UnsubscribeResponse,
+    GridRegionResponse,
+    FindPathResponse,
+    AttributionReportResponse,
+    ModelEditResponse,
+    RecorderResponse,
+    ExportSnapshotResponse,
+    LoadSnapshotResponse,
+    StartSimResponse,
+    ErrorResponse,
+    ListLocalScenariosResponse,
+    LoadLocalScenarioResponse,
+    LoadRemoteScenarioResponse,
+);
+
+#[test]
+fn quantized_floats_out_of_range_are_sent_natively() {
+    let precision = FloatPrecision::Quantized(0.01);
+    assert_eq!(
+        precision.encode(Var::Float(12.345)),
+        WireVar::Quantized(1235)
+    );
+    for f in vec![1e12, -1e12, outcome::Float::INFINITY] {
+        assert_eq!(
+            precision.encode(Var::Float(f)),
+            WireVar::Native(Var::Float(f))
+        );
+    }
+    assert!(matches!(
+        precision.encode(Var::Float(outcome::Float::NAN)),
+        WireVar::Native(Var::Float(f)) if f.is_nan()
+    ));
+}
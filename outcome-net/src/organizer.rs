@@ -1,37 +1,52 @@
 #![allow(unused)]
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::io::Write;
 use std::net::{IpAddr, SocketAddr, TcpListener};
 use std::ops::DerefMut;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{io, thread};
 
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
 use id_pool::IdPool;
 
+use outcome::address::Address;
 use outcome::distr::{CentralCommunication, Signal, SimCentral, SimNode};
+use outcome::entity::Entity;
 use outcome::model::Scenario;
+use outcome::query::{Description, Filter, Layout, Map, Trigger};
+use outcome::sim::stats::RunStats;
 use outcome::SimStarter;
-use outcome::{distr, EntityId, SimModel};
+use outcome::{distr, CompName, EntityId, EntityName, EventName, PrefabName, Query, QueryProduct};
+use outcome::{SimInterface, SimModel, Var};
 
 use crate::error::{Error, Result};
+use crate::jobs::{ActiveJob, Job, JobResult};
 use crate::msg::coord_worker::{
+    EntitySummary, InspectReport, InspectRequest, InspectResponse, InspectTarget,
     IntroduceCoordRequest, IntroduceCoordResponse, IntroduceWorkerToCoordResponse,
     IntroduceWorkerToOrganizerRequest,
 };
 use crate::msg::{Message, MessageType};
 use crate::socket::{CompositeSocketAddress, Socket, SocketAddress, Transport};
 use crate::worker::{WorkerId, WorkerTask};
-use crate::{sig, TaskId};
+use crate::{sig, trace, TaskId};
 use std::convert::TryFrom;
 
 const COORD_ADDRESS: &str = "0.0.0.0:5912";
 
+/// Time to wait for workers to respond to requests made through the
+/// `SimInterface`.
+const INTERFACE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time for which results of finished migrations are kept around for
+/// [`Organizer::migration_status`] to pick up.
+const MIGRATION_RESULT_RETENTION: Duration = Duration::from_secs(60);
+
 /// Single worker as seen by the organizer.
 pub struct Worker {
     //pub id: WorkerId,
@@ -42,6 +57,9 @@ pub struct Worker {
     /// that are also servers can block processing of further steps if any of
     /// their connected clients blocks.
     pub is_blocking_step: bool,
+    /// Time any signal, including heartbeats, was last received from the
+    /// worker
+    pub last_seen: Instant,
 }
 
 /// Organizer's networking capabilities.
@@ -57,6 +75,12 @@ pub struct OrganizerNet {
     /// Entity-worker routing table
     pub routing_table: HashMap<EntityId, WorkerId>,
 
+    /// Time after which a worker that wasn't heard from is considered dead,
+    /// none disables tracking worker liveness. Workers don't send
+    /// heartbeats while processing a step, so the timeout should be longer
+    /// than the longest expected step
+    pub heartbeat_timeout: Option<Duration>,
+
     task_id_pool: IdPool,
 }
 
@@ -65,10 +89,45 @@ pub enum OrganizerTask {
     WaitForQueryResponses {
         remaining: u32,
         products: Vec<outcome::query::QueryProduct>,
+        /// Workers that already responded, or declined to
+        responded: Vec<WorkerId>,
+        /// Whether some of the workers declined to respond, leaving the
+        /// products incomplete
+        partial: bool,
     },
     WaitForSnapshotResponses {
         remaining: u32,
         snapshots: Vec<outcome::snapshot::SnapshotPart>,
+        /// Workers that already responded, or declined to
+        responded: Vec<WorkerId>,
+    },
+    /// Entity being moved between workers, see [`Organizer::migrate_entity`]
+    MigrateEntity {
+        entity: EntityId,
+        source: WorkerId,
+        target: WorkerId,
+        /// Copy of the entity handed over by the source worker, kept until
+        /// the target worker takes it in
+        data: Option<(Option<EntityName>, Entity)>,
+        /// Set once the migration has failed, the entity is then being
+        /// returned to the source worker if it was already handed over
+        error: Option<String>,
+        /// Time at which the migration finished, the task expires after
+        /// [`MIGRATION_RESULT_RETENTION`] if the result isn't collected
+        finished: Option<Instant>,
+    },
+    /// Checkpoint being taken, see [`Organizer::checkpoint`]
+    Checkpoint {
+        /// Location of the checkpoint store
+        location: String,
+        name: String,
+        clock: usize,
+        /// Number of workers yet to write their part
+        remaining: u32,
+        /// Keys of the parts written by each worker
+        parts: Vec<(WorkerId, String)>,
+        error: Option<String>,
+        finished: bool,
     },
 }
 
@@ -77,7 +136,75 @@ impl OrganizerTask {
         match self {
             OrganizerTask::WaitForQueryResponses { remaining, .. } => *remaining == 0,
             OrganizerTask::WaitForSnapshotResponses { remaining, .. } => *remaining == 0,
-            _ => unimplemented!(),
+            OrganizerTask::MigrateEntity { finished, .. } => finished.is_some(),
+            OrganizerTask::Checkpoint { finished, .. } => *finished,
+        }
+    }
+}
+
+/// Policy deciding when the organizer advances the simulation union.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum StepTrigger {
+    /// Steps are driven by clients and workers requesting turn advancement
+    Clients,
+    /// Steps are processed one after another, as soon as possible
+    Continuous,
+    /// Step is processed whenever the given event is invoked externally
+    Event(EventName),
+    /// Steps are processed following a wall-clock schedule
+    Interval(Duration),
+}
+
+impl Default for StepTrigger {
+    fn default() -> Self {
+        StepTrigger::Clients
+    }
+}
+
+/// Policy for recovering from losing a worker, see
+/// [`Organizer::handle_dead_worker`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DeadWorkerPolicy {
+    /// Entities held by the dead worker are dropped, the simulation
+    /// carries on with the remaining ones
+    Abort,
+    /// Entities held by the dead worker are restored from the last
+    /// distributed snapshot onto the surviving workers. Entities missing
+    /// from the snapshot are dropped
+    Respawn,
+}
+
+impl Default for DeadWorkerPolicy {
+    fn default() -> Self {
+        DeadWorkerPolicy::Abort
+    }
+}
+
+impl FromStr for DeadWorkerPolicy {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "abort" => Ok(DeadWorkerPolicy::Abort),
+            "respawn" => Ok(DeadWorkerPolicy::Respawn),
+            _ => Err(Error::Other(format!("invalid dead worker policy: {}", s))),
+        }
+    }
+}
+
+impl FromStr for StepTrigger {
+    type Err = Error;
+    /// Parses the trigger from one of the following forms: `clients`,
+    /// `continuous`, `event:<event_name>`, `interval:<millis>`.
+    fn from_str(s: &str) -> Result<Self> {
+        let split = s.splitn(2, ':').collect::<Vec<&str>>();
+        match (split[0], split.get(1)) {
+            ("clients", None) => Ok(StepTrigger::Clients),
+            ("continuous", None) => Ok(StepTrigger::Continuous),
+            ("event", Some(event)) => Ok(StepTrigger::Event(outcome::string::new_truncate(event))),
+            ("interval", Some(millis)) => Ok(StepTrigger::Interval(Duration::from_millis(
+                millis.parse()?,
+            ))),
+            _ => Err(Error::Other(format!("invalid step trigger: {}", s))),
         }
     }
 }
@@ -117,6 +244,54 @@ pub struct Organizer {
     /// organizer poller will check on the status of the task, and progress
     /// further with that particular body of work if possible.
     pub tasks: HashMap<u32, OrganizerTask>,
+
+    /// Policy for triggering new steps, in addition to client-driven turns
+    pub trigger: StepTrigger,
+    /// Time of the last processed step
+    last_step: Instant,
+    /// Statistics of the current run, updated after each step
+    ///
+    /// Organizer doesn't store entity data itself, so memory and logic
+    /// error figures are not tracked.
+    pub run_stats: RunStats,
+
+    /// Jobs waiting to be run, see [`Organizer::enqueue_job`]
+    pub jobs: VecDeque<Job>,
+    /// Job currently being run
+    pub(crate) active_job: Option<ActiveJob>,
+    /// Results of the finished jobs, in order of completion
+    pub job_results: Vec<JobResult>,
+
+    /// Directory where crash dumps are written when a step fails, none
+    /// disables crash dumps
+    pub crashdump_dir: Option<PathBuf>,
+
+    /// Policy applied when a worker is found dead
+    pub dead_worker_policy: DeadWorkerPolicy,
+    /// Number of steps between snapshots taken for recovering entities of
+    /// dead workers, none disables periodic snapshots. Snapshots
+    /// downloaded for other reasons are used for recovery as well
+    pub recovery_snapshot_interval: Option<usize>,
+    /// Most recent snapshot parts received from each of the workers
+    recovery_parts: FnvHashMap<WorkerId, outcome::snapshot::SnapshotPart>,
+    /// Task collecting the periodic recovery snapshot
+    recovery_task: Option<TaskId>,
+    /// Entities of dead workers handed to the surviving workers, kept
+    /// until taken in, along with the workers that failed to take them in
+    respawning: FnvHashMap<EntityId, (Option<EntityName>, Entity, Vec<WorkerId>)>,
+    /// Checkpoint being restored, see [`Organizer::from_checkpoint`]
+    pub(crate) pending_restore: Option<crate::checkpoint::PendingRestore>,
+    /// Location of the store periodic checkpoints are written to, none
+    /// disables periodic checkpoints
+    pub checkpoint_store: Option<String>,
+    /// Number of steps between periodic checkpoints
+    pub checkpoint_interval: usize,
+    /// Task taking the periodic checkpoint
+    checkpoint_task: Option<TaskId>,
+
+    /// Metrics exposed for scraping, see [`Organizer::enable_metrics`]
+    #[cfg(feature = "metrics")]
+    pub metrics: Option<crate::metrics::Metrics>,
 }
 
 impl Organizer {
@@ -134,6 +309,7 @@ impl Organizer {
             inviter: Socket::new(None, greeter_target.transport.unwrap_or(Transport::Tcp))?,
             workers: Default::default(),
             routing_table: Default::default(),
+            heartbeat_timeout: None,
             task_id_pool: IdPool::new(),
         };
         let mut organ = Self {
@@ -147,6 +323,29 @@ impl Organizer {
 
             // task_id_pool: IdPool::new(),
             tasks: Default::default(),
+
+            trigger: StepTrigger::default(),
+            last_step: Instant::now(),
+            run_stats: Default::default(),
+
+            jobs: VecDeque::new(),
+            active_job: None,
+            job_results: Vec::new(),
+
+            crashdump_dir: None,
+
+            dead_worker_policy: DeadWorkerPolicy::default(),
+            recovery_snapshot_interval: None,
+            recovery_parts: Default::default(),
+            recovery_task: None,
+            respawning: Default::default(),
+            pending_restore: None,
+            checkpoint_store: None,
+            checkpoint_interval: 100,
+            checkpoint_task: None,
+
+            #[cfg(feature = "metrics")]
+            metrics: None,
         };
         for worker_addr in &worker_addrs {
             organ.add_worker(worker_addr)?;
@@ -174,6 +373,7 @@ impl Organizer {
             entities: vec![],
             connection: socket,
             is_blocking_step: true,
+            last_seen: Instant::now(),
         };
         self.net.workers.insert(id, worker);
         self.central.node_entities.insert(id, Vec::new());
@@ -228,7 +428,7 @@ impl Organizer {
         Ok(())
     }
 
-    fn initialize_worker_node(&mut self, id: &u32) -> Result<()> {
+    pub(crate) fn initialize_worker_node(&mut self, id: &u32) -> Result<()> {
         let (worker_id, worker) = self
             .net
             .workers
@@ -283,6 +483,7 @@ impl Organizer {
                 warn!("no starter");
             }
         }
+        self.restore_checkpoint_parts()?;
         Ok(())
     }
 
@@ -310,6 +511,10 @@ impl Organizer {
     pub fn manual_poll(&mut self) -> Result<()> {
         // TODO support less frequent polling of the greeter socket
         if let Ok((address, msg)) = &self.net.greeter.try_recv_msg() {
+            #[cfg(feature = "metrics")]
+            if let Some(metrics) = &mut self.metrics {
+                metrics.count_received(&format!("{:?}", msg.type_));
+            }
             match msg.type_ {
                 MessageType::IntroduceWorkerToCoordRequest => {
                     debug!("handling new worker connection request");
@@ -343,6 +548,20 @@ impl Organizer {
 
                     self.net.greeter.disconnect(None);
                 }
+                MessageType::InspectRequest => {
+                    let req: InspectRequest = msg.unpack_payload(self.net.greeter.encoding())?;
+                    let resp = match self.inspect(req.target) {
+                        Ok(report) => InspectResponse {
+                            report,
+                            error: String::new(),
+                        },
+                        Err(e) => InspectResponse {
+                            report: InspectReport::Empty,
+                            error: e.to_string(),
+                        },
+                    };
+                    self.net.greeter.send_payload(resp, Some(address.clone()))?;
+                }
                 _ => trace!("msg.kind: {:?}", msg.type_),
             }
         }
@@ -350,9 +569,19 @@ impl Organizer {
         let mut do_step = false;
         let mut to_unregister = Vec::new();
         let mut to_initialize_node = Vec::new();
+        let mut migration_sigs = Vec::new();
+        let mut respawn_sigs = Vec::new();
+        let mut checkpoint_sigs = Vec::new();
         for (worker_id, worker) in self.net.workers.iter_mut() {
             if let Ok((addr, sig)) = worker.connection.try_recv_sig() {
+                worker.last_seen = Instant::now();
+                let trace_id = sig.trace_id();
+                let _trace = trace::enter(trace_id);
                 let (task_id, sig) = sig.into_inner();
+                #[cfg(feature = "metrics")]
+                if let Some(metrics) = &mut self.metrics {
+                    metrics.count_received(sig.name());
+                }
                 match sig {
                     Signal::WorkerConnected => {
                         warn!(
@@ -371,6 +600,19 @@ impl Organizer {
                     Signal::WorkerStepAdvanceRequest(steps) => {
                         do_step = true;
                     }
+                    Signal::AddEvent(event) => {
+                        if !self.central.event_queue.contains(&event) {
+                            self.central.event_queue.push(event);
+                        }
+                    }
+                    // model edited through a worker, propagated to all
+                    // workers with the next step
+                    Signal::UpdateModel(model) => match model.validate() {
+                        Ok(()) => self.central.model = model,
+                        Err(e) => {
+                            warn!("rejected model update from worker {}: {}", worker_id, e)
+                        }
+                    },
                     Signal::DataRequestAll => {
                         debug!("got signal from worker {}: DataRequestAll ", worker_id);
                         worker.connection.send_sig(
@@ -379,43 +621,292 @@ impl Organizer {
                         )?;
                     }
                     Signal::QueryResponse(product) => {
+                        debug!(
+                            "{} query response from worker {}",
+                            trace::Display(trace_id),
+                            worker_id
+                        );
                         if let Some(OrganizerTask::WaitForQueryResponses {
                             remaining,
                             products,
+                            responded,
+                            ..
                         }) = self.tasks.get_mut(&task_id)
                         {
-                            *remaining -= 1;
+                            *remaining = remaining.saturating_sub(1);
                             products.push(product);
+                            responded.push(*worker_id);
+                        } else {
+                            warn!(
+                                "{} query response for unknown task {}",
+                                trace::Display(trace_id),
+                                task_id
+                            );
+                        }
+                    }
+                    Signal::SnapshotResponse(part) => {
+                        debug!(
+                            "{} snapshot response from worker {}",
+                            trace::Display(trace_id),
+                            worker_id
+                        );
+                        self.recovery_parts.insert(*worker_id, part.clone());
+                        if let Some(OrganizerTask::WaitForSnapshotResponses {
+                            remaining,
+                            snapshots,
+                            responded,
+                        }) = self.tasks.get_mut(&task_id)
+                        {
+                            *remaining = remaining.saturating_sub(1);
+                            snapshots.push(part);
+                            responded.push(*worker_id);
+                        } else {
+                            warn!(
+                                "{} snapshot response for unknown task {}",
+                                trace::Display(trace_id),
+                                task_id
+                            );
                         }
                     }
-                    signal => debug!("{:?}", signal),
+                    // entities restored after losing a worker aren't
+                    // tracked by any task
+                    Signal::EntityIngested(_) | Signal::MigrationFailed(..) if task_id == 0 => {
+                        respawn_sigs.push((*worker_id, sig));
+                    }
+                    Signal::MigratingEntity(..)
+                    | Signal::EntityIngested(_)
+                    | Signal::MigrationFailed(..) => {
+                        migration_sigs.push((*worker_id, task_id, sig));
+                    }
+                    Signal::CheckpointSaved(_)
+                    | Signal::CheckpointRestored(_)
+                    | Signal::CheckpointFailed(_) => {
+                        checkpoint_sigs.push((*worker_id, task_id, sig));
+                    }
+                    Signal::DeadlineExceeded(clock) => {
+                        warn!(
+                            "{} worker {} rejected task {} past deadline at clock {}",
+                            trace::Display(trace_id),
+                            worker_id,
+                            task_id,
+                            clock
+                        );
+                        // the worker won't respond, stop waiting for it
+                        match self.tasks.get_mut(&task_id) {
+                            Some(OrganizerTask::WaitForQueryResponses {
+                                remaining,
+                                responded,
+                                partial,
+                                ..
+                            }) => {
+                                *remaining = remaining.saturating_sub(1);
+                                responded.push(*worker_id);
+                                *partial = true;
+                            }
+                            Some(OrganizerTask::WaitForSnapshotResponses {
+                                remaining,
+                                responded,
+                                ..
+                            }) => {
+                                *remaining = remaining.saturating_sub(1);
+                                responded.push(*worker_id);
+                            }
+                            Some(_) | None => (),
+                        }
+                    }
+                    signal => debug!("{} {:?}", trace::Display(trace_id), signal),
                 }
             }
         }
         for worker_id in to_initialize_node {
             self.initialize_worker_node(&worker_id)?;
         }
+        for (worker_id, task_id, sig) in migration_sigs {
+            self.handle_migration_signal(worker_id, task_id, sig)?;
+        }
+        for (worker_id, sig) in respawn_sigs {
+            self.handle_respawn_signal(worker_id, sig)?;
+        }
+        for (worker_id, task_id, sig) in checkpoint_sigs {
+            self.handle_checkpoint_signal(worker_id, task_id, sig)?;
+        }
         for task_id in to_unregister {
             self.unregister_task(task_id)?;
         }
+        self.expire_migration_results()?;
+        if let Some(task_id) = self.recovery_task {
+            if self.tasks.get(&task_id).map_or(true, |t| t.is_finished()) {
+                self.recovery_task = None;
+                self.unregister_task(task_id)?;
+            }
+        }
+        if let Some(task_id) = self.checkpoint_task {
+            match self.checkpoint_status(task_id) {
+                Some(Ok(name)) => debug!("periodic checkpoint {} finished", name),
+                Some(Err(e)) => error!("periodic checkpoint failed: {}", e),
+                None => (),
+            }
+            if !self.tasks.contains_key(&task_id) {
+                self.checkpoint_task = None;
+            }
+        }
+        for worker_id in self.net.dead_nodes() {
+            self.handle_dead_worker(worker_id)?;
+        }
 
+        match &self.trigger {
+            StepTrigger::Clients => (),
+            StepTrigger::Continuous => do_step = self.initialized,
+            StepTrigger::Event(event) => {
+                if self.central.event_queue.contains(event) {
+                    do_step = true;
+                }
+            }
+            StepTrigger::Interval(interval) => {
+                if self.initialized && self.last_step.elapsed() >= *interval {
+                    do_step = true;
+                }
+            }
+        }
+        // running jobs are stepped through as fast as possible
+        if self.job_wants_step() {
+            do_step = self.initialized;
+        }
+
+        // entities in transit would be left out of the step, checkpoints
+        // are only consistent between steps
         if do_step
             && !self.net.workers.iter().any(|(_, w)| w.is_blocking_step)
             && !self.is_blocking_step
+            && !self.is_migrating()
+            && !self.is_checkpointing()
         {
             info!("stepping");
-            let mut event_queue = self.central.event_queue.clone();
-            let step_event_name = outcome::string::new_truncate("step");
-            if !event_queue.contains(&step_event_name) {
-                event_queue.push(step_event_name);
+            if let Err(e) = self.step() {
+                error!("failed processing step: {}", e);
+                if let Some(dir) = &self.crashdump_dir {
+                    match self.write_crashdump(dir, &e.to_string()) {
+                        Ok(path) => error!("crash dump written to: {}", path.display()),
+                        Err(e) => error!("failed writing crash dump: {}", e),
+                    }
+                }
+                self.fail_active_job(format!("failed processing step: {}", e));
             }
-            self.central.event_queue.clear();
-            self.central.step_network(&mut self.net, event_queue);
-            self.central.clock += 1;
         }
+
+        self.progress_jobs()?;
+
+        #[cfg(feature = "metrics")]
+        self.serve_metrics();
+
         Ok(())
     }
 
+    /// Writes a crash dump for the step that failed with the given error,
+    /// returning the path to the newly created dump directory.
+    ///
+    /// Entity data is stored on the workers, so unlike with the local
+    /// backend no snapshot is included. Instead the dump lists the workers
+    /// along with the number of entities each of them holds.
+    pub fn write_crashdump(&self, dir: &Path, error: &str) -> Result<PathBuf> {
+        let path = outcome::sim::crashdump::create_crashdump_dir(dir, self.central.clock)?;
+        let mut report = format!(
+            "clock: {}\nevents: {:?}\nerror: {}\n\nworkers:\n",
+            self.central.clock, self.central.event_queue, error
+        );
+        for (worker_id, entities) in &self.central.node_entities {
+            report.push_str(&format!("{}: {} entities\n", worker_id, entities.len()));
+        }
+        std::fs::write(path.join("error.txt"), report)?;
+        Ok(path)
+    }
+
+    /// Processes a single step across all the workers.
+    pub fn step(&mut self) -> Result<()> {
+        let mut event_queue = self.central.event_queue.clone();
+        let step_event_name = outcome::string::new_truncate("step");
+        if !event_queue.contains(&step_event_name) {
+            event_queue.push(step_event_name);
+        }
+        let pending_events = std::mem::take(&mut self.central.event_queue);
+        self.last_step = Instant::now();
+        if let Err(e) = self.central.step_network(&mut self.net, event_queue) {
+            // step was aborted, keep the events for the retried step
+            self.central.event_queue = pending_events;
+            for worker_id in self.net.dead_nodes() {
+                self.handle_dead_worker(worker_id)?;
+            }
+            return Err(e.into());
+        }
+        self.central.clock += 1;
+        let entity_count = self.central.node_entities.values().map(|e| e.len()).sum();
+        self.run_stats.record_step(
+            self.central.clock,
+            self.last_step.elapsed(),
+            entity_count,
+            0,
+            0,
+        );
+        if let Some(interval) = self.recovery_snapshot_interval {
+            if self.recovery_task.is_none() && self.central.clock % interval.max(1) == 0 {
+                self.recovery_task = Some(self.download_snapshots()?);
+            }
+        }
+        if let Some(location) = &self.checkpoint_store {
+            if self.checkpoint_task.is_none()
+                && self.central.clock % self.checkpoint_interval.max(1) == 0
+            {
+                let location = location.clone();
+                match self.checkpoint(&location) {
+                    Ok(task_id) => self.checkpoint_task = Some(task_id),
+                    Err(e) => warn!("failed starting periodic checkpoint: {}", e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Broadcasts the query to all the workers and waits for their
+    /// responses, returning the collected products.
+    ///
+    /// Returns an error if not all of the workers responded within the
+    /// timeout, or if any of them declined to respond, e.g. because the
+    /// query arrived past it's deadline.
+    pub fn query_blocking(&mut self, query: Query, timeout: Duration) -> Result<Vec<QueryProduct>> {
+        let task_id = self.register_task(OrganizerTask::WaitForQueryResponses {
+            remaining: self.net.workers.len() as u32,
+            products: vec![],
+            responded: vec![],
+            partial: false,
+        })?;
+        // query is only relevant for the current step
+        self.net.broadcast(
+            sig::Signal::from(task_id, Signal::QueryRequest(query))
+                .with_deadline(self.central.clock),
+        );
+        let start = Instant::now();
+        loop {
+            self.manual_poll()?;
+            if self.tasks.get(&task_id).map_or(true, |t| t.is_finished()) {
+                break;
+            }
+            if start.elapsed() > timeout {
+                self.unregister_task(task_id)?;
+                return Err(Error::TimedOut);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        let task = self.tasks.remove(&task_id);
+        self.net.task_id_pool.return_id(task_id).unwrap();
+        match task {
+            Some(OrganizerTask::WaitForQueryResponses { partial: true, .. }) => {
+                Err(Error::IncompleteQuery)
+            }
+            Some(OrganizerTask::WaitForQueryResponses { products, .. }) => Ok(products),
+            _ => Ok(vec![]),
+        }
+    }
+
     /// Creates a new cluster coordinator and initializes workers.
     pub fn new_with_path(
         scenario_path: &str,
@@ -458,12 +949,413 @@ impl Organizer {
         let task_id = self.register_task(OrganizerTask::WaitForSnapshotResponses {
             remaining: self.net.workers.len() as u32,
             snapshots: vec![],
+            responded: vec![],
         })?;
         self.net.broadcast_sig(task_id, Signal::SnapshotRequest)?;
         Ok(task_id)
     }
 }
 
+/// Entity migration.
+///
+/// Entities are moved between workers through the organizer. The source
+/// worker hands the entity over, and the organizer passes it on to the
+/// target worker. Entity assignment on central, as well as the routing
+/// table, is only updated once the target worker confirms taking in the
+/// entity. If the target worker fails to do so, the entity is returned to
+/// the source worker.
+///
+/// No steps are processed while any migration is in progress.
+impl Organizer {
+    /// Starts moving the entity to the target worker, returning the id of
+    /// the task tracking the migration. See [`Organizer::migration_status`].
+    pub fn migrate_entity(&mut self, entity_id: EntityId, target: WorkerId) -> Result<TaskId> {
+        if !self.net.workers.contains_key(&target) {
+            return Err(Error::Other(format!("no worker with id: {}", target)));
+        }
+        let source = self
+            .entity_routing()
+            .into_iter()
+            .find(|(id, _)| *id == entity_id)
+            .map(|(_, worker)| worker)
+            .ok_or_else(|| Error::Other(format!("entity not found: {}", entity_id)))?;
+        if source == target {
+            return Err(Error::Other(format!(
+                "entity {} is already on worker {}",
+                entity_id, target
+            )));
+        }
+        if self.tasks.values().any(|t| match t {
+            OrganizerTask::MigrateEntity {
+                entity, finished, ..
+            } => *entity == entity_id && finished.is_none(),
+            _ => false,
+        }) {
+            return Err(Error::Other(format!(
+                "entity {} is already being migrated",
+                entity_id
+            )));
+        }
+
+        let task_id = self.register_task(OrganizerTask::MigrateEntity {
+            entity: entity_id,
+            source,
+            target,
+            data: None,
+            error: None,
+            finished: None,
+        })?;
+        self.net
+            .send_sig_to_node(source, task_id, Signal::MigrateEntity(entity_id, target))?;
+        Ok(task_id)
+    }
+
+    /// Checks on the migration task. Returns `None` while the migration is
+    /// still in progress, otherwise the task is removed and it's result
+    /// returned.
+    pub fn migration_status(&mut self, task_id: TaskId) -> Option<Result<()>> {
+        match self.tasks.get(&task_id) {
+            Some(OrganizerTask::MigrateEntity {
+                finished: Some(_), ..
+            }) => (),
+            Some(OrganizerTask::MigrateEntity { .. }) => return None,
+            _ => {
+                return Some(Err(Error::Other(format!(
+                    "unknown migration task: {}",
+                    task_id
+                ))))
+            }
+        }
+        let result = match self.tasks.remove(&task_id) {
+            Some(OrganizerTask::MigrateEntity { error: Some(e), .. }) => Err(Error::Other(e)),
+            _ => Ok(()),
+        };
+        let _ = self.net.task_id_pool.return_id(task_id);
+        Some(result)
+    }
+
+    /// Removes finished migration tasks whose results weren't collected
+    /// in time.
+    fn expire_migration_results(&mut self) -> Result<()> {
+        let expired = self
+            .tasks
+            .iter()
+            .filter_map(|(task_id, task)| match task {
+                OrganizerTask::MigrateEntity {
+                    finished: Some(at), ..
+                } if at.elapsed() > MIGRATION_RESULT_RETENTION => Some(*task_id),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for task_id in expired {
+            debug!("migration task {} expired", task_id);
+            self.unregister_task(task_id)?;
+        }
+        Ok(())
+    }
+
+    /// Checks whether any entities are currently being moved between
+    /// workers.
+    pub fn is_migrating(&self) -> bool {
+        // entities of dead workers being taken in by the surviving ones
+        // count as well
+        !self.respawning.is_empty()
+            || self.tasks.values().any(|t| match t {
+                OrganizerTask::MigrateEntity { finished, .. } => finished.is_none(),
+                _ => false,
+            })
+    }
+
+    fn handle_migration_signal(
+        &mut self,
+        worker_id: WorkerId,
+        task_id: TaskId,
+        sig: Signal,
+    ) -> Result<()> {
+        let (entity, source, target, data, error, finished) = match self.tasks.get_mut(&task_id) {
+            Some(OrganizerTask::MigrateEntity {
+                entity,
+                source,
+                target,
+                data,
+                error,
+                finished,
+            }) => (*entity, *source, *target, data, error, finished),
+            _ => {
+                warn!("migration signal for unknown task {}: {:?}", task_id, sig);
+                return Ok(());
+            }
+        };
+        match sig {
+            Signal::MigratingEntity(id, name, ent) => {
+                *data = Some((name.clone(), ent.clone()));
+                self.net
+                    .send_sig_to_node(target, task_id, Signal::IngestEntity(id, name, ent))?;
+            }
+            Signal::EntityIngested(_) => {
+                *data = None;
+                *finished = Some(Instant::now());
+                // entity made it back to the source after a failure
+                if error.is_some() {
+                    return Ok(());
+                }
+                // the entity lives on the target now, routing has to follow
+                // even if the central assignment turns out inconsistent
+                self.net.routing_table.insert(entity, target);
+                if let Err(e) = self.central.move_entity(entity, source, target) {
+                    error!(
+                        "entity {} migrated to worker {}, but failed updating assignment: {}",
+                        entity, target, e
+                    );
+                    for entities in self.central.node_entities.values_mut() {
+                        entities.retain(|id| *id != entity);
+                    }
+                    self.central
+                        .node_entities
+                        .entry(target)
+                        .or_default()
+                        .push(entity);
+                }
+                debug!("entity {} migrated to worker {}", entity, target);
+            }
+            Signal::MigrationFailed(_, e) => {
+                warn!("failed migrating entity {}: {}", entity, e);
+                if error.is_some() || worker_id == source {
+                    if error.is_some() {
+                        error!("entity {} lost during migration: {}", entity, e);
+                    }
+                    *error = Some(e);
+                    *data = None;
+                    *finished = Some(Instant::now());
+                    return Ok(());
+                }
+                *error = Some(e);
+                // target failed taking in the entity, return it to source
+                match data.take() {
+                    Some((name, ent)) => self.net.send_sig_to_node(
+                        source,
+                        task_id,
+                        Signal::IngestEntity(entity, name, ent),
+                    )?,
+                    None => *finished = Some(Instant::now()),
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+/// Worker failure recovery.
+///
+/// Workers send heartbeats to the organizer, and any worker that wasn't
+/// heard from within [`OrganizerNet::heartbeat_timeout`] is considered
+/// dead. Step waiting on a dead worker is aborted, and the worker is
+/// removed from the union, with it's entities handled according to the
+/// [`DeadWorkerPolicy`].
+impl Organizer {
+    /// Removes the dead worker from the union, recovering it's entities
+    /// if the policy allows it.
+    ///
+    /// Recovered entities are restored in the state they were in at the
+    /// time of the last snapshot, see
+    /// [`Organizer::recovery_snapshot_interval`].
+    pub fn handle_dead_worker(&mut self, worker_id: WorkerId) -> Result<()> {
+        if self.net.workers.remove(&worker_id).is_none() {
+            return Ok(());
+        }
+        let _ = self.worker_pool.return_id(worker_id);
+        // checkpoint parts the worker didn't get to restore are handed to
+        // the other workers instead
+        let requeued = self.requeue_restore_parts(worker_id);
+        let entities = self
+            .central
+            .node_entities
+            .remove(&worker_id)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|id| !requeued.contains(id))
+            .collect::<Vec<_>>();
+        let part = self.recovery_parts.remove(&worker_id);
+        for entity in entities.iter().chain(requeued.iter()) {
+            self.net.routing_table.remove(entity);
+        }
+        // migrations involving the dead worker, as well as checkpoints,
+        // can't complete, while requests waiting on responses from all the
+        // workers stop waiting for the dead one
+        for task in self.tasks.values_mut() {
+            match task {
+                OrganizerTask::WaitForQueryResponses {
+                    remaining,
+                    responded,
+                    partial,
+                    ..
+                } => {
+                    if !responded.contains(&worker_id) {
+                        *remaining = remaining.saturating_sub(1);
+                        responded.push(worker_id);
+                        *partial = true;
+                    }
+                }
+                OrganizerTask::WaitForSnapshotResponses {
+                    remaining,
+                    responded,
+                    ..
+                } => {
+                    if !responded.contains(&worker_id) {
+                        *remaining = remaining.saturating_sub(1);
+                        responded.push(worker_id);
+                    }
+                }
+                OrganizerTask::MigrateEntity {
+                    source,
+                    target,
+                    error,
+                    finished,
+                    ..
+                } if *source == worker_id || *target == worker_id => {
+                    if finished.is_none() {
+                        *error = Some(format!("worker {} died", worker_id));
+                        *finished = Some(Instant::now());
+                    }
+                }
+                OrganizerTask::Checkpoint {
+                    error, finished, ..
+                } => {
+                    if !*finished {
+                        *error = Some(format!("worker {} died", worker_id));
+                        *finished = true;
+                    }
+                }
+                _ => (),
+            }
+        }
+        error!(
+            "worker {} is dead, {} entities affected, policy: {:?}",
+            worker_id,
+            entities.len(),
+            self.dead_worker_policy
+        );
+
+        let mut lost = Vec::new();
+        match self.dead_worker_policy {
+            DeadWorkerPolicy::Respawn => {
+                let mut part_entities = part.map(|p| p.entities).unwrap_or_default();
+                let names = self
+                    .central
+                    .entities_idx
+                    .iter()
+                    .map(|(name, id)| (*id, name.clone()))
+                    .collect::<FnvHashMap<_, _>>();
+                for entity_id in entities {
+                    // entities still being respawned from an earlier
+                    // failure are more recent than the snapshot
+                    let respawn = match self.respawning.remove(&entity_id) {
+                        Some(r) => Some(r),
+                        None => part_entities
+                            .remove(&entity_id)
+                            .map(|e| (names.get(&entity_id).cloned(), e, Vec::new())),
+                    };
+                    match respawn {
+                        Some((name, entity, failed)) => {
+                            if !self.respawn_entity(entity_id, name, entity, failed)? {
+                                lost.push(entity_id);
+                            }
+                        }
+                        None => lost.push(entity_id),
+                    }
+                }
+            }
+            DeadWorkerPolicy::Abort => lost = entities,
+        }
+
+        self.drop_lost_entities(worker_id, lost);
+        self.restore_checkpoint_parts()
+    }
+
+    /// Hands the entity over to the least loaded of the surviving workers,
+    /// skipping the ones that already failed to take it in. Entity is
+    /// assigned to the worker right away, and kept around until the worker
+    /// confirms taking it in. Returns false if there's no worker left to
+    /// take the entity.
+    fn respawn_entity(
+        &mut self,
+        entity_id: EntityId,
+        name: Option<EntityName>,
+        entity: Entity,
+        failed: Vec<WorkerId>,
+    ) -> Result<bool> {
+        let loads = self.central.node_loads();
+        let target = self
+            .net
+            .workers
+            .keys()
+            .filter(|id| !failed.contains(id))
+            .min_by_key(|id| loads.get(id).copied().unwrap_or(0))
+            .copied();
+        let target = match target {
+            Some(t) => t,
+            None => return Ok(false),
+        };
+        self.net.send_sig_to_node(
+            target,
+            0,
+            Signal::IngestEntity(entity_id, name.clone(), entity.clone()),
+        )?;
+        self.central
+            .node_entities
+            .entry(target)
+            .or_default()
+            .push(entity_id);
+        self.net.routing_table.insert(entity_id, target);
+        self.respawning.insert(entity_id, (name, entity, failed));
+        Ok(true)
+    }
+
+    /// Handles the response of a worker to taking in an entity of a dead
+    /// worker. If the worker failed to take it in, the entity is handed to
+    /// another one.
+    fn handle_respawn_signal(&mut self, worker_id: WorkerId, sig: Signal) -> Result<()> {
+        match sig {
+            Signal::EntityIngested(entity_id) => {
+                debug!("entity {} restored on worker {}", entity_id, worker_id);
+                self.respawning.remove(&entity_id);
+            }
+            Signal::MigrationFailed(entity_id, e) => {
+                error!(
+                    "failed restoring entity {} on worker {}: {}",
+                    entity_id, worker_id, e
+                );
+                if let Some(entities) = self.central.node_entities.get_mut(&worker_id) {
+                    entities.retain(|id| *id != entity_id);
+                }
+                self.net.routing_table.remove(&entity_id);
+                if let Some((name, entity, mut failed)) = self.respawning.remove(&entity_id) {
+                    failed.push(worker_id);
+                    if !self.respawn_entity(entity_id, name, entity, failed)? {
+                        self.drop_lost_entities(worker_id, vec![entity_id]);
+                    }
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Removes entities that couldn't be recovered from the union.
+    pub(crate) fn drop_lost_entities(&mut self, worker_id: WorkerId, lost: Vec<EntityId>) {
+        if lost.is_empty() {
+            return;
+        }
+        warn!("lost {} entities of worker {}", lost.len(), worker_id);
+        let lost = lost.into_iter().collect::<FnvHashSet<_>>();
+        self.central.entities_idx.retain(|_, id| !lost.contains(id));
+        for entity_id in lost {
+            let _ = self.central.entity_idpool.return_id(entity_id);
+        }
+    }
+}
+
 impl outcome::distr::CentralCommunication for OrganizerNet {
     fn request_task_id(&mut self) -> outcome::Result<u32> {
         self.task_id_pool
@@ -487,12 +1379,13 @@ impl outcome::distr::CentralCommunication for OrganizerNet {
         for (worker_id, worker) in &mut self.workers {
             match worker.connection.try_recv_sig() {
                 Ok((addr, sig)) => {
+                    worker.last_seen = Instant::now();
                     let (_task_id, _sig) = sig.into_inner();
                     return Ok((*worker_id, _task_id, _sig));
                 }
                 Err(e) => match e {
                     Error::WouldBlock => continue,
-                    _ => return Err(outcome::error::Error::Other(e.to_string())),
+                    _ => return Err(e.into()),
                 },
             }
         }
@@ -508,11 +1401,11 @@ impl outcome::distr::CentralCommunication for OrganizerNet {
                 node_id
             )))?;
         match worker.connection.try_recv_sig() {
-            Ok((addr, sig)) => Ok(sig.into_inner()),
-            Err(e) => match e {
-                Error::WouldBlock => Err(outcome::error::Error::WouldBlock),
-                _ => Err(outcome::error::Error::Other(e.to_string())),
-            },
+            Ok((addr, sig)) => {
+                worker.last_seen = Instant::now();
+                Ok(sig.into_inner())
+            }
+            Err(e) => Err(e.into()),
         }
     }
 
@@ -558,7 +1451,27 @@ impl outcome::distr::CentralCommunication for OrganizerNet {
     }
 
     fn broadcast_sig(&mut self, task_id: u32, signal: Signal) -> outcome::Result<()> {
-        let signal = sig::Signal::from(task_id, signal);
+        self.broadcast(sig::Signal::from(task_id, signal));
+        Ok(())
+    }
+
+    fn dead_nodes(&self) -> Vec<u32> {
+        match self.heartbeat_timeout {
+            Some(timeout) => self
+                .workers
+                .iter()
+                .filter(|(_, worker)| worker.last_seen.elapsed() > timeout)
+                .map(|(worker_id, _)| *worker_id)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl OrganizerNet {
+    /// Sends the signal to all the workers, allowing for setting signal
+    /// metadata such as priority or deadline.
+    pub fn broadcast(&mut self, signal: sig::Signal) {
         let len = self.workers.len();
         for (idx, (worker_id, worker)) in &mut self.workers.iter_mut().enumerate() {
             trace!(
@@ -572,6 +1485,373 @@ impl outcome::distr::CentralCommunication for OrganizerNet {
                 .send_sig(signal.clone(), None)
                 .unwrap_or_else(|e| error!("{:?}", e));
         }
+    }
+}
+
+impl Organizer {
+    /// Creates a report on the selected part of the organizer state.
+    pub fn inspect(&mut self, target: InspectTarget) -> Result<InspectReport> {
+        let report = match target {
+            InspectTarget::Status => InspectReport::Status(
+                vec![
+                    ("role".to_string(), "organizer".to_string()),
+                    ("address".to_string(), self.address.to_string()),
+                    ("clock".to_string(), self.central.clock.to_string()),
+                    ("initialized".to_string(), self.initialized.to_string()),
+                    ("trigger".to_string(), format!("{:?}", self.trigger)),
+                    ("workers".to_string(), self.net.workers.len().to_string()),
+                    (
+                        "event_queue".to_string(),
+                        format!("{:?}", self.central.event_queue),
+                    ),
+                ]
+                .into_iter()
+                .chain(self.net.workers.iter_mut().map(|(worker_id, worker)| {
+                    (
+                        format!("worker {} traffic", worker_id),
+                        worker.connection.traffic().to_string(),
+                    )
+                }))
+                .collect(),
+            ),
+            InspectTarget::Entities => {
+                let routing = self.entity_routing();
+                let mut entities = routing
+                    .iter()
+                    .map(|(id, worker)| EntitySummary {
+                        id: *id,
+                        name: None,
+                        worker: Some(*worker),
+                        components: vec![],
+                    })
+                    .collect::<Vec<_>>();
+                for (name, id) in &self.central.entities_idx {
+                    match entities.iter_mut().find(|e| e.id == *id) {
+                        Some(e) => e.name = Some(name.to_string()),
+                        None => entities.push(EntitySummary {
+                            id: *id,
+                            name: Some(name.to_string()),
+                            worker: None,
+                            components: vec![],
+                        }),
+                    }
+                }
+                entities.sort_by_key(|e| e.id);
+                InspectReport::Entities(entities)
+            }
+            InspectTarget::Entity(id) => {
+                let vars = self.collect_vars(Filter::Id(vec![id]), Map::All)?;
+                let mut vars = vars
+                    .into_iter()
+                    .map(|((_, comp, var_name), var)| {
+                        (
+                            format!("{}:{}:{}", comp, var.get_type().to_str(), var_name),
+                            var.to_string(),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                vars.sort();
+                InspectReport::Entity(vars)
+            }
+            InspectTarget::Routing => InspectReport::Routing(self.entity_routing()),
+            InspectTarget::Pending => {
+                let mut pending = Vec::new();
+                for (task_id, task) in &self.tasks {
+                    pending.push(match task {
+                        OrganizerTask::WaitForQueryResponses { remaining, .. } => format!(
+                            "task {}: waiting for {} query responses",
+                            task_id, remaining
+                        ),
+                        OrganizerTask::WaitForSnapshotResponses { remaining, .. } => format!(
+                            "task {}: waiting for {} snapshot responses",
+                            task_id, remaining
+                        ),
+                        OrganizerTask::MigrateEntity {
+                            entity,
+                            source,
+                            target,
+                            ..
+                        } => format!(
+                            "task {}: migrating entity {} from worker {} to worker {}",
+                            task_id, entity, source, target
+                        ),
+                        OrganizerTask::Checkpoint {
+                            name, remaining, ..
+                        } => format!(
+                            "task {}: waiting for {} workers to write checkpoint {}",
+                            task_id, remaining, name
+                        ),
+                    });
+                }
+                for (worker_id, worker) in &self.net.workers {
+                    if worker.is_blocking_step {
+                        pending.push(format!("worker {}: blocking step", worker_id));
+                    }
+                }
+                if self.is_blocking_step {
+                    pending.push("organizer: blocking step".to_string());
+                }
+                InspectReport::Pending(pending)
+            }
+        };
+        Ok(report)
+    }
+
+    /// Collects known entity locations, from both the routing table and
+    /// the entity assignments made by central.
+    fn entity_routing(&self) -> Vec<(EntityId, WorkerId)> {
+        let mut routing = self
+            .net
+            .routing_table
+            .iter()
+            .map(|(id, worker)| (*id, *worker))
+            .collect::<Vec<_>>();
+        for (worker, entities) in &self.central.node_entities {
+            for id in entities {
+                if !routing.iter().any(|(_id, _)| _id == id) {
+                    routing.push((*id, *worker));
+                }
+            }
+        }
+        routing.sort();
+        routing
+    }
+
+    /// Resolves the entity part of the address into an entity id.
+    fn resolve_entity(&self, addr: &Address) -> Result<EntityId> {
+        if let Some(id) = self.central.entities_idx.get(&addr.entity) {
+            return Ok(*id);
+        }
+        addr.entity
+            .parse()
+            .map_err(|_| Error::Other(format!("entity not found: {}", addr.entity)))
+    }
+
+    fn collect_vars(
+        &mut self,
+        filter: Filter,
+        map: Map,
+    ) -> Result<FnvHashMap<(EntityId, CompName, outcome::VarName), Var>> {
+        let query = Query {
+            trigger: Trigger::Immediate,
+            description: Description::NativeDescribed,
+            layout: Layout::Var,
+            filters: vec![filter],
+            mappings: vec![map],
+        };
+        let mut vars = FnvHashMap::default();
+        for product in self.query_blocking(query, INTERFACE_TIMEOUT)? {
+            if let QueryProduct::NativeAddressedVar(map) = product {
+                vars.extend(map);
+            }
+        }
+        Ok(vars)
+    }
+}
+
+/// Requests for entity data are broadcast to all the workers, blocking
+/// until all of them respond.
+impl SimInterface for Organizer {
+    fn get_clock(&mut self) -> outcome::Result<usize> {
+        Ok(self.central.clock)
+    }
+
+    fn get_var(&mut self, addr: &Address) -> outcome::Result<Var> {
+        let id = self.resolve_entity(addr)?;
+        let vars = self.collect_vars(
+            Filter::Id(vec![id]),
+            Map::Components(vec![addr.component.clone()]),
+        )?;
+        vars.get(&(id, addr.component.clone(), addr.var_name.clone()))
+            .cloned()
+            .ok_or(outcome::error::Error::FailedGettingVarFromSim(addr.clone()))
+    }
+
+    /// Pull request is sent to all the workers, with only the worker
+    /// storing the entity applying it. Doesn't wait for confirmation.
+    fn set_var(&mut self, addr: &Address, var: Var) -> outcome::Result<()> {
+        let mut addr = addr.clone();
+        addr.entity = outcome::string::new_truncate(&self.resolve_entity(&addr)?.to_string());
+        self.net
+            .broadcast_sig(0, Signal::DataPullRequest(vec![(addr, var)]))?;
+        Ok(())
+    }
+
+    /// Entities are found based on the vars they store, entities that
+    /// don't store any vars for the listed components are not included.
+    fn get_entities_of_type(&mut self, type_: &[CompName]) -> outcome::Result<Vec<EntityId>> {
+        let vars = self.collect_vars(
+            Filter::AllComponents(type_.to_vec()),
+            Map::Components(type_.to_vec()),
+        )?;
+        let mut ids = vars.keys().map(|(id, _, _)| *id).collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    fn spawn_entity(
+        &mut self,
+        prefab: Option<PrefabName>,
+        name: Option<EntityName>,
+    ) -> outcome::Result<EntityId> {
+        let id = self
+            .central
+            .spawn_entity(prefab, name, distr::DistributionPolicy::Random)?;
+        self.central.flush_queue(&mut self.net)?;
+        Ok(id)
+    }
+
+    fn add_event(&mut self, name: EventName) -> outcome::Result<()> {
+        if !self.central.event_queue.contains(&name) {
+            self.central.event_queue.push(name);
+        }
         Ok(())
     }
+
+    fn step(&mut self) -> outcome::Result<()> {
+        Ok(Organizer::step(self)?)
+    }
+}
+
+/// Organizer with two workers that can't be reached, with a single entity
+/// stored on the first one.
+#[cfg(test)]
+fn test_organizer() -> Organizer {
+    let model = outcome::SimModelBuilder::new().build().unwrap();
+    let central = SimCentral::from_model(model, None).unwrap();
+    let mut organ = Organizer::new_at_any(central, vec![]).unwrap();
+    for (worker_id, entities) in vec![(0, vec![1]), (1, vec![])] {
+        organ.net.workers.insert(
+            worker_id,
+            Worker {
+                address: SocketAddress::Unavailable,
+                entities: entities.clone(),
+                connection: Socket::new(None, Transport::Tcp).unwrap(),
+                is_blocking_step: false,
+                last_seen: Instant::now(),
+            },
+        );
+        organ.central.node_entities.insert(worker_id, entities);
+    }
+    organ.net.routing_table.insert(1, 0);
+    organ
+}
+
+#[test]
+fn migrated_entity_is_assigned_to_target() {
+    let mut organ = test_organizer();
+    let task_id = organ.migrate_entity(1, 1).unwrap();
+    assert!(organ.is_migrating());
+    // entity can't be moved again while in transit
+    assert!(organ.migrate_entity(1, 1).is_err());
+
+    organ
+        .handle_migration_signal(
+            0,
+            task_id,
+            Signal::MigratingEntity(1, None, Entity::empty()),
+        )
+        .unwrap();
+    assert!(organ.migration_status(task_id).is_none());
+    // assignment only changes once the target confirms
+    assert_eq!(organ.net.routing_table.get(&1), Some(&0));
+
+    organ
+        .handle_migration_signal(1, task_id, Signal::EntityIngested(1))
+        .unwrap();
+    assert!(!organ.is_migrating());
+    assert!(organ.migration_status(task_id).unwrap().is_ok());
+    assert_eq!(organ.net.routing_table.get(&1), Some(&1));
+    assert_eq!(organ.central.node_entities[&0], Vec::<EntityId>::new());
+    assert_eq!(organ.central.node_entities[&1], vec![1]);
+}
+
+#[test]
+fn entity_is_returned_to_source_if_target_fails() {
+    let mut organ = test_organizer();
+    let task_id = organ.migrate_entity(1, 1).unwrap();
+    organ
+        .handle_migration_signal(
+            0,
+            task_id,
+            Signal::MigratingEntity(1, None, Entity::empty()),
+        )
+        .unwrap();
+
+    // target refuses the entity, it's sent back to the source
+    organ
+        .handle_migration_signal(
+            1,
+            task_id,
+            Signal::MigrationFailed(1, "refused".to_string()),
+        )
+        .unwrap();
+    assert!(organ.is_migrating());
+    assert!(organ.migration_status(task_id).is_none());
+
+    organ
+        .handle_migration_signal(0, task_id, Signal::EntityIngested(1))
+        .unwrap();
+    assert!(!organ.is_migrating());
+    match organ.migration_status(task_id) {
+        Some(Err(e)) => assert!(e.to_string().contains("refused")),
+        _ => panic!("expected failed migration"),
+    }
+    // entity stays where it was
+    assert_eq!(organ.net.routing_table.get(&1), Some(&0));
+    assert_eq!(organ.central.node_entities[&0], vec![1]);
+    assert_eq!(organ.central.node_entities[&1], Vec::<EntityId>::new());
+}
+
+#[test]
+fn workers_not_heard_from_in_time_are_dead() {
+    let mut organ = test_organizer();
+    organ.net.workers.get_mut(&0).unwrap().last_seen = Instant::now() - Duration::from_secs(10);
+    // liveness is not tracked without a timeout
+    assert!(organ.net.dead_nodes().is_empty());
+    organ.net.heartbeat_timeout = Some(Duration::from_secs(1));
+    assert_eq!(organ.net.dead_nodes(), vec![0]);
+}
+
+#[test]
+fn entities_of_dead_worker_are_dropped_with_abort_policy() {
+    let mut organ = test_organizer();
+    organ
+        .central
+        .entities_idx
+        .insert(outcome::string::new_truncate("first"), 1);
+    let task_id = organ.migrate_entity(1, 1).unwrap();
+
+    organ.handle_dead_worker(0).unwrap();
+    assert!(!organ.net.workers.contains_key(&0));
+    assert!(!organ.central.node_entities.contains_key(&0));
+    assert!(organ.net.routing_table.is_empty());
+    assert!(organ.central.entities_idx.is_empty());
+    // migration from the dead worker can't complete
+    assert!(!organ.is_migrating());
+    assert!(organ.migration_status(task_id).unwrap().is_err());
+    // handling the same worker again is a no-op
+    organ.handle_dead_worker(0).unwrap();
+}
+
+#[test]
+fn entities_of_dead_worker_are_respawned_from_snapshot() {
+    let mut organ = test_organizer();
+    organ.dead_worker_policy = DeadWorkerPolicy::Respawn;
+    let mut part = outcome::snapshot::SnapshotPart {
+        entities: Default::default(),
+    };
+    part.entities.insert(1, Entity::empty());
+    organ.recovery_parts.insert(0, part);
+
+    organ.handle_dead_worker(0).unwrap();
+    assert_eq!(organ.central.node_entities[&1], vec![1]);
+    assert_eq!(organ.net.routing_table.get(&1), Some(&1));
+    // entity is kept around until the surviving worker takes it in
+    assert!(organ.is_migrating());
+    organ
+        .handle_respawn_signal(1, Signal::EntityIngested(1))
+        .unwrap();
+    assert!(!organ.is_migrating());
 }
@@ -0,0 +1,220 @@
+//! Worker plugins loaded from dynamic libraries.
+//!
+//! Plugins provide an officially supported way of customizing a [`Worker`]
+//! without forking the crate. They're declared in the worker config file
+//! and loaded when the worker starts, separately from any model libraries.
+//!
+//! ```toml
+//! [[plugins]]
+//! path = "plugins/libmy_plugin.so"
+//! ```
+//!
+//! Plugin support requires the `worker_plugins` feature.
+//!
+//! A plugin is a `cdylib` exporting a registration function, declared using
+//! the [`declare_worker_plugin`] macro:
+//!
+//! ```ignore
+//! use outcome_net::plugin::PluginRegistrar;
+//!
+//! fn register(registrar: &mut PluginRegistrar) {
+//!     registrar.register_post_step_hook(|node| {
+//!         info!("node finished step {}", node.clock);
+//!     });
+//!     registrar.register_filter("even", |id, _entity, _args| id % 2 == 0);
+//! }
+//!
+//! outcome_net::declare_worker_plugin!(register);
+//! ```
+//!
+//! Plugins can register step hooks run on the worker's node around each
+//! step, custom query filters and maps made available to the node, and
+//! services started along with a server backed by the worker.
+//!
+//! # Safety
+//!
+//! Plugins are passed Rust types directly, without a stable ABI. They must
+//! be built with the same compiler version and the same version of this
+//! crate as the worker loading them.
+//!
+//! [`Worker`]: crate::Worker
+
+use std::path::PathBuf;
+
+use libloading::{Library, Symbol};
+use outcome::distr::SimNode;
+use outcome::entity::{Entity, StorageIndex};
+use outcome::model::ServiceModel;
+use outcome::query::QueryPlugins;
+use outcome::EntityId;
+
+use crate::{Error, Result};
+
+/// Name of the registration function exported by plugin libraries.
+pub const PLUGIN_ENTRY_SYMBOL: &str = "outcome_worker_plugin";
+
+/// Signature of the registration function exported by plugin libraries.
+pub type PluginEntryFn = unsafe extern "C" fn(&mut PluginRegistrar);
+
+/// Hook run on the worker's node before or after processing a step.
+pub type StepHook = dyn FnMut(&mut SimNode) + Send;
+
+/// Worker configuration, usually read from a toml file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WorkerConfig {
+    /// Plugins loaded at startup, in order
+    #[serde(default)]
+    pub plugins: Vec<PluginConfig>,
+}
+
+/// Single plugin entry of the worker config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PluginConfig {
+    /// Path to the plugin library
+    pub path: PathBuf,
+}
+
+/// Collects everything registered by plugins.
+#[derive(Default)]
+pub struct PluginRegistrar {
+    pub(crate) pre_step_hooks: Vec<Box<StepHook>>,
+    pub(crate) post_step_hooks: Vec<Box<StepHook>>,
+    pub(crate) query_plugins: QueryPlugins,
+    pub(crate) services: Vec<ServiceModel>,
+}
+
+impl PluginRegistrar {
+    /// Registers a hook run before the node processes each step.
+    pub fn register_pre_step_hook(&mut self, hook: impl FnMut(&mut SimNode) + Send + 'static) {
+        self.pre_step_hooks.push(Box::new(hook));
+    }
+
+    /// Registers a hook run after the node processes each step.
+    pub fn register_post_step_hook(&mut self, hook: impl FnMut(&mut SimNode) + Send + 'static) {
+        self.post_step_hooks.push(Box::new(hook));
+    }
+
+    /// Registers a custom query filter, see [`QueryPlugins::register_filter`].
+    pub fn register_filter(
+        &mut self,
+        name: &str,
+        filter: impl Fn(EntityId, &Entity, &[String]) -> bool + Send + Sync + 'static,
+    ) {
+        self.query_plugins.register_filter(name, filter);
+    }
+
+    /// Registers a custom query map, see [`QueryPlugins::register_map`].
+    pub fn register_map(
+        &mut self,
+        name: &str,
+        map: impl Fn(EntityId, &Entity, &[String]) -> Vec<StorageIndex> + Send + Sync + 'static,
+    ) {
+        self.query_plugins.register_map(name, map);
+    }
+
+    /// Registers a service started along with a server backed by the
+    /// worker.
+    pub fn register_service(&mut self, service: ServiceModel) {
+        self.services.push(service);
+    }
+}
+
+/// Plugins loaded by a worker.
+#[derive(Default)]
+pub struct WorkerPlugins {
+    pub registrar: PluginRegistrar,
+    /// Loaded libraries, kept alive for as long as anything they registered
+    /// might be used. Declared last so that it's dropped last.
+    libs: Vec<Library>,
+}
+
+impl WorkerPlugins {
+    /// Loads a single plugin library, calling its registration function.
+    pub fn load_plugin(&mut self, path: &PathBuf) -> Result<()> {
+        info!("loading worker plugin: {}", path.display());
+        let lib = Library::new(path).map_err(|e| {
+            Error::Other(format!("failed loading plugin {}: {}", path.display(), e))
+        })?;
+        unsafe {
+            let entry: Symbol<PluginEntryFn> =
+                lib.get(PLUGIN_ENTRY_SYMBOL.as_bytes()).map_err(|e| {
+                    Error::Other(format!(
+                        "plugin {} doesn't export {}: {}",
+                        path.display(),
+                        PLUGIN_ENTRY_SYMBOL,
+                        e
+                    ))
+                })?;
+            entry(&mut self.registrar);
+        }
+        self.libs.push(lib);
+        Ok(())
+    }
+
+    /// Runs all registered pre-step hooks on the node.
+    pub fn pre_step(&mut self, node: &mut SimNode) {
+        for hook in &mut self.registrar.pre_step_hooks {
+            hook(node);
+        }
+    }
+
+    /// Runs all registered post-step hooks on the node.
+    pub fn post_step(&mut self, node: &mut SimNode) {
+        for hook in &mut self.registrar.post_step_hooks {
+            hook(node);
+        }
+    }
+
+    /// Services registered by plugins.
+    pub fn services(&self) -> &[ServiceModel] {
+        &self.registrar.services
+    }
+}
+
+/// Declares the registration function of a worker plugin.
+///
+/// Takes a path to a function accepting `&mut PluginRegistrar`.
+#[macro_export]
+macro_rules! declare_worker_plugin {
+    ($register:path) => {
+        #[no_mangle]
+        #[allow(improper_ctypes_definitions)]
+        pub unsafe extern "C" fn outcome_worker_plugin(
+            registrar: &mut $crate::plugin::PluginRegistrar,
+        ) {
+            $register(registrar)
+        }
+    };
+}
+
+#[test]
+fn registered_hooks_run_around_steps() {
+    let config: WorkerConfig =
+        serde_json::from_str(r#"{"plugins": [{"path": "plugins/missing.so"}]}"#).unwrap();
+    assert_eq!(config.plugins[0].path, PathBuf::from("plugins/missing.so"));
+
+    let mut plugins = WorkerPlugins::default();
+    assert!(plugins.load_plugin(&config.plugins[0].path).is_err());
+
+    plugins
+        .registrar
+        .register_pre_step_hook(|node| node.clock *= 10);
+    plugins
+        .registrar
+        .register_post_step_hook(|node| node.clock += 1);
+    plugins
+        .registrar
+        .register_post_step_hook(|node| node.clock *= 2);
+    plugins
+        .registrar
+        .register_filter("even", |id, _, _| id % 2 == 0);
+    assert!(plugins.registrar.query_plugins.get_filter("even").is_some());
+
+    let model = outcome::SimModelBuilder::new().build().unwrap();
+    let mut node = SimNode::from_model(&model).unwrap();
+    node.clock = 1;
+    plugins.pre_step(&mut node);
+    assert_eq!(node.clock, 10);
+    plugins.post_step(&mut node);
+    assert_eq!(node.clock, 22);
+}
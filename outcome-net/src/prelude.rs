@@ -0,0 +1,15 @@
+//! Curated set of the most commonly used items.
+//!
+//! Items exported here make up the stable surface of the library, and are
+//! meant to be glob-imported:
+//!
+//! ```ignore
+//! use outcome_net::prelude::*;
+//! ```
+
+pub use crate::client::{Client, ClientConfig, CompressionPolicy, RetryPolicy};
+pub use crate::error::{Error, Result};
+pub use crate::server::{ClientHandle, ClientId, Permission, Server, ServerConfig, SimConnection};
+pub use crate::socket::{Encoding, Transport};
+pub use crate::subscriber::{Published, Subscriber};
+pub use crate::{Organizer, Worker};
@@ -0,0 +1,86 @@
+//! Async polling mode.
+//!
+//! Instead of polling at a fixed interval like [`Server::start_polling`],
+//! the server waits for the sockets to report incoming events, sleeping
+//! in-between. This saves CPU when traffic is low, and lets requests be
+//! handled as soon as they arrive when it's high.
+//!
+//! Not all transports are able to report incoming events, and some of the
+//! server's work, like keepalive checks or timed step triggers, isn't
+//! driven by incoming events at all. The server is therefore still polled
+//! at least once every [`ServerConfig::async_max_wait`].
+//!
+//! ```ignore
+//! let runtime = tokio::runtime::Builder::new_current_thread()
+//!     .enable_time()
+//!     .build()?;
+//! runtime.block_on(server.run_async(running))?;
+//! ```
+//!
+//! [`ServerConfig::async_max_wait`]: crate::ServerConfig::async_max_wait
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+
+use crate::server::Server;
+use crate::socket::Waker;
+use crate::{Error, Result};
+
+impl Server {
+    /// Runs the server until the `running` flag gets flipped to false,
+    /// waiting for incoming events instead of polling at a fixed interval.
+    ///
+    /// Requires a tokio runtime with the time driver enabled.
+    pub async fn run_async(&mut self, running: Arc<AtomicBool>) -> Result<()> {
+        let notify = Arc::new(Notify::new());
+        let waker: Waker = {
+            let notify = notify.clone();
+            Arc::new(move || notify.notify_one())
+        };
+        for greeter in &mut self.greeters {
+            greeter.set_waker(waker.clone());
+        }
+
+        let mut last_poll = Instant::now();
+        loop {
+            if !running.load(Ordering::SeqCst) {
+                break;
+            }
+
+            // manual poll assumes it's called every `poll_wait`, account
+            // for the actual time spent waiting
+            let elapsed = last_poll.elapsed();
+            last_poll = Instant::now();
+            self.uptime += elapsed;
+            self.time_since_last_msg += elapsed.saturating_sub(self.config.poll_wait);
+
+            if let Err(err) = self.manual_poll() {
+                match err {
+                    Error::ServerKeepaliveLimitReached(_) => return Err(err),
+                    _ => warn!("server error: {:?}", err),
+                }
+            }
+
+            // connections of newly accepted clients need the waker too
+            for client in self.clients.values_mut() {
+                client.connection.set_waker(waker.clone());
+            }
+
+            let busy = self.time_since_last_msg == Duration::from_millis(0) || self.lanes.len() > 0;
+            if busy {
+                // there may be more events left than a single poll handles
+                tokio::task::yield_now().await;
+            } else if !self.tasks.is_empty() {
+                // responses from the cluster don't wake the server
+                tokio::time::sleep(self.config.poll_wait).await;
+            } else {
+                // either some events arrive or it's time for a periodic poll
+                let _ = tokio::time::timeout(self.config.async_max_wait, notify.notified()).await;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+//! Audit trail of client-originated writes.
+//!
+//! With audit mode enabled, every mutation applied to the simulation on
+//! behalf of a client is appended to the audit file as a single CSV line
+//! with the following columns:
+//!
+//! `timestamp,client_id,clock,action,target,before,after`
+//!
+//! Only writes that were actually applied are recorded, rejected items are
+//! not.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use outcome::{Address, Var};
+
+use crate::server::ClientId;
+use crate::Result;
+
+const HEADER: &str = "timestamp,client_id,clock,action,target,before,after";
+
+/// Single audited mutation.
+pub enum AuditAction<'a> {
+    /// Var value was overwritten
+    Pull {
+        address: &'a Address,
+        before: &'a Var,
+        after: &'a Var,
+    },
+    /// Entity was spawned from a prefab
+    Spawn { prefab: &'a str, entity: String },
+    /// Entity was removed
+    Despawn { entity: String },
+    /// Component was attached to the entity
+    Attach { component: &'a str, entity: String },
+    /// Component was detached from the entity
+    Detach { component: &'a str, entity: String },
+    /// Event was invoked, either globally or for a single entity
+    Invoke {
+        event: &'a str,
+        entity: Option<String>,
+    },
+}
+
+/// Append-only log of client-originated writes.
+pub struct AuditLog {
+    writer: BufWriter<File>,
+}
+
+impl AuditLog {
+    /// Opens the audit file for appending, writing the header if the file
+    /// is new.
+    pub fn open(path: &Path) -> Result<Self> {
+        let is_new = !path.exists();
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut writer = BufWriter::new(file);
+        if is_new {
+            writeln!(writer, "{}", HEADER)?;
+        }
+        Ok(Self { writer })
+    }
+
+    /// Appends a record of the action to the log.
+    pub fn record(&mut self, client_id: &ClientId, clock: usize, action: AuditAction) {
+        let (name, target, before, after) = match action {
+            AuditAction::Pull {
+                address,
+                before,
+                after,
+            } => (
+                "pull",
+                address.to_string(),
+                before.to_string(),
+                after.to_string(),
+            ),
+            AuditAction::Spawn { prefab, entity } => {
+                ("spawn", entity, String::new(), prefab.to_string())
+            }
+            AuditAction::Despawn { entity } => ("despawn", entity, String::new(), String::new()),
+            AuditAction::Attach { component, entity } => {
+                ("attach", entity, String::new(), component.to_string())
+            }
+            AuditAction::Detach { component, entity } => {
+                ("detach", entity, component.to_string(), String::new())
+            }
+            AuditAction::Invoke { event, entity } => (
+                "invoke",
+                entity.unwrap_or_default(),
+                String::new(),
+                event.to_string(),
+            ),
+        };
+        let line = format!(
+            "{},{},{},{},{},{},{}",
+            chrono::Utc::now().to_rfc3339(),
+            client_id,
+            clock,
+            name,
+            escape(&target),
+            escape(&before),
+            escape(&after)
+        );
+        if let Err(e) = writeln!(self.writer, "{}", line).and_then(|_| self.writer.flush()) {
+            error!("failed writing to audit log: {}", e);
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    if s.contains(',') || s.contains('"') || s.contains('\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[test]
+fn records_are_appended_as_csv_lines() {
+    use std::str::FromStr;
+
+    let path = std::env::temp_dir().join("outcome_audit_log.csv");
+    let _ = std::fs::remove_file(&path);
+
+    let address = Address::from_str("first:pos:float:x").unwrap();
+    let mut log = AuditLog::open(&path).unwrap();
+    log.record(
+        &3,
+        7,
+        AuditAction::Pull {
+            address: &address,
+            before: &Var::Float(0.),
+            after: &Var::Vec2(1., 2.),
+        },
+    );
+    log.record(
+        &3,
+        8,
+        AuditAction::Invoke {
+            event: "step",
+            entity: None,
+        },
+    );
+    drop(log);
+
+    // reopening an existing file doesn't write the header again
+    let mut log = AuditLog::open(&path).unwrap();
+    log.record(
+        &4,
+        9,
+        AuditAction::Spawn {
+            prefab: "dot",
+            entity: "second".to_string(),
+        },
+    );
+    drop(log);
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let lines = contents.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], HEADER);
+    let fields = |line: &str| line.splitn(2, ',').nth(1).unwrap().to_string();
+    assert_eq!(
+        fields(lines[1]),
+        "3,7,pull,first:pos:float:x,0,\"x: 1, y: 2\""
+    );
+    assert_eq!(fields(lines[2]), "3,8,invoke,,,step");
+    assert_eq!(fields(lines[3]), "4,9,spawn,second,,dot");
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,186 @@
+//! Client authorization and permission levels.
+//!
+//! With authorization enabled, clients have to provide one of the user and
+//! password pairs listed in the config when registering, otherwise they're
+//! turned away. Each registered client is assigned a permission level based
+//! on the user it authorized as:
+//! - users listed as admin users get the admin level
+//! - users listed as read-only users get the read-only level
+//! - all other users get the read-write level
+//!
+//! With authorization disabled all clients get the read-write level, unless
+//! the server is explicitly configured to grant anonymous clients the admin
+//! level. Observer clients always get the read-only level.
+//!
+//! Messages that would modify the simulation are rejected for read-only
+//! clients, the client is sent back an `ErrorResponse` instead.
+
+use crate::msg::{
+    ErrorCode, ErrorResponse, Message, MessageType, RegisterClientRequest, ResponseError,
+};
+use crate::server::{ClientId, Server, ServerConfig};
+use crate::{Error, Result};
+
+/// Level of access granted to a client.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Permission {
+    /// Only messages leaving the simulation intact are allowed
+    ReadOnly,
+    /// Reading and writing simulation data is allowed
+    ReadWrite,
+    /// Full access, including editing the model, starting simulations and
+    /// loading snapshots
+    Admin,
+}
+
+impl Permission {
+    /// Checks whether a client with this permission level can send
+    /// a message of the given type.
+    ///
+    /// Handlers of messages requiring the admin level check for it on
+    /// their own, responding with a message specific error.
+    pub fn allows(&self, type_: MessageType) -> bool {
+        match self {
+            Permission::ReadOnly => type_.is_read_only(),
+            Permission::ReadWrite | Permission::Admin => true,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Decides on the permission level for the registering client.
+    ///
+    /// Returns an error if authorization is enabled and the client didn't
+    /// provide valid credentials.
+    pub(crate) fn authorize(&self, req: &RegisterClientRequest) -> Result<Permission> {
+        if !self.use_auth {
+            return match req.is_observer {
+                true => Ok(Permission::ReadOnly),
+                false if self.anonymous_admin => Ok(Permission::Admin),
+                false => Ok(Permission::ReadWrite),
+            };
+        }
+        let (user, _) = match &req.auth_pair {
+            Some(pair) if self.auth_pairs.contains(pair) => pair,
+            Some((user, _)) => {
+                return Err(Error::HandshakeFailed(format!(
+                    "invalid credentials for user: {}",
+                    user
+                )))
+            }
+            None => {
+                return Err(Error::HandshakeFailed(
+                    "server requires authorization".to_string(),
+                ))
+            }
+        };
+        if req.is_observer || self.read_only_users.contains(user) {
+            Ok(Permission::ReadOnly)
+        } else if self.admin_users.contains(user) {
+            Ok(Permission::Admin)
+        } else {
+            Ok(Permission::ReadWrite)
+        }
+    }
+}
+
+impl Server {
+    /// Checks whether the client is allowed to send the message, responding
+    /// with an error if it's not.
+    pub(crate) fn check_permission(&mut self, msg: &Message, client_id: &ClientId) -> Result<bool> {
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        if client.permission.allows(msg.type_) {
+            return Ok(true);
+        }
+        warn!(
+            "rejecting {:?} from client {}, permission level: {:?}",
+            msg.type_, client_id, client.permission
+        );
+        let (error, code) = ResponseError::new(
+            ErrorCode::Unauthorized,
+            format!("{:?} requires write access", msg.type_),
+        )
+        .into_fields();
+        client.connection.send_payload_with_task(
+            ErrorResponse {
+                request: msg.type_,
+                error,
+                code,
+            },
+            msg.task_id,
+            None,
+        )?;
+        Ok(false)
+    }
+}
+
+#[test]
+fn anonymous_clients_get_read_write_by_default() {
+    let mut req = RegisterClientRequest {
+        name: "client".to_string(),
+        is_blocking: false,
+        is_observer: false,
+        auth_pair: None,
+        encodings: Vec::new(),
+        transports: Vec::new(),
+        float_precision: Default::default(),
+        session_token: None,
+    };
+    let mut config = ServerConfig::default();
+    assert_eq!(config.authorize(&req).unwrap(), Permission::ReadWrite);
+    config.anonymous_admin = true;
+    assert_eq!(config.authorize(&req).unwrap(), Permission::Admin);
+    req.is_observer = true;
+    assert_eq!(config.authorize(&req).unwrap(), Permission::ReadOnly);
+
+    assert!(!Permission::ReadOnly.allows(MessageType::TurnAdvanceRequest));
+    assert!(!Permission::ReadOnly.allows(MessageType::ExportSnapshotRequest));
+    assert!(Permission::ReadWrite.allows(MessageType::TurnAdvanceRequest));
+    assert!(!Permission::ReadOnly.allows(MessageType::DataPullRequest));
+}
+
+#[test]
+fn read_only_clients_cant_invoke_events_or_change_step_trigger() {
+    use crate::harness::TestServer;
+    use crate::{Client, ClientConfig};
+
+    assert!(!Permission::ReadOnly.allows(MessageType::InvokeEventsRequest));
+    assert!(!Permission::ReadOnly.allows(MessageType::SetStepTriggerRequest));
+
+    let sim = outcome::SimModelBuilder::new()
+        .component("counter", |c| c.var("int:count", outcome::Var::Int(0)))
+        .prefab("thing", &["counter"])
+        .spawn("thing", Some("first"))
+        .build_sim()
+        .unwrap();
+    let config = ServerConfig {
+        use_auth: true,
+        auth_pairs: vec![("viewer".to_string(), "secret".to_string())],
+        read_only_users: vec!["viewer".to_string()],
+        ..ServerConfig::default()
+    };
+    let server = TestServer::start_with_config(sim, config).unwrap();
+    let mut client = Client::new_with_config(ClientConfig {
+        name: "viewer".to_string(),
+        ..ClientConfig::default()
+    })
+    .unwrap();
+    client
+        .connect(server.server_addr(), Some("secret".to_string()))
+        .unwrap();
+    let results = vec![
+        client.invoke_events(vec!["step".to_string()]).map(|_| ()),
+        client.set_step_trigger("continuous").map(|_| ()),
+    ];
+    for result in results {
+        match result {
+            Err(Error::RequestRejected(_, e)) => assert_eq!(e.code, ErrorCode::Unauthorized),
+            other => panic!("expected unauthorized, got: {:?}", other),
+        }
+    }
+    client.disconnect().unwrap();
+    server.shutdown().unwrap();
+}
@@ -0,0 +1,79 @@
+//! Crash dumps written when a step fails.
+//!
+//! With a diagnostics directory configured, the server writes a crash dump
+//! whenever a step is aborted, see [`outcome::sim::crashdump`]. On top of
+//! what's written by the backend, each dump includes `messages.log` with
+//! the most recent messages received from clients, oldest first.
+
+use std::collections::VecDeque;
+use std::path::Path;
+
+use outcome::Sim;
+
+use crate::msg::Message;
+use crate::server::{ClientId, Server};
+use crate::{Organizer, Result};
+
+impl Server {
+    /// Records the message in the log of recent messages.
+    pub(crate) fn log_message(&mut self, msg: &Message, client_id: &ClientId) {
+        if self.config.message_log_len == 0 {
+            return;
+        }
+        while self.message_log.len() >= self.config.message_log_len {
+            self.message_log.pop_front();
+        }
+        self.message_log.push_back(format!(
+            "{} client {}: {:?} (task: {}, trace: {})",
+            chrono::Utc::now().to_rfc3339(),
+            client_id,
+            msg.type_,
+            msg.task_id,
+            msg.trace_id
+        ));
+    }
+}
+
+/// Writes a crash dump for the local simulation.
+pub(crate) fn write_local(
+    dir: &Path,
+    sim: &Sim,
+    error: &outcome::error::Error,
+    message_log: &VecDeque<String>,
+) {
+    let result = sim
+        .write_crashdump(dir, error)
+        .map_err(|e| e.into())
+        .and_then(|path| write_message_log(&path, message_log).map(|_| path));
+    report(result);
+}
+
+/// Writes a crash dump for the distributed simulation.
+pub(crate) fn write_organizer(
+    dir: &Path,
+    organizer: &Organizer,
+    error: &str,
+    message_log: &VecDeque<String>,
+) {
+    let result = organizer
+        .write_crashdump(dir, error)
+        .and_then(|path| write_message_log(&path, message_log).map(|_| path));
+    report(result);
+}
+
+fn write_message_log(path: &Path, message_log: &VecDeque<String>) -> Result<()> {
+    let mut log = String::new();
+    for entry in message_log {
+        log.push_str(entry);
+        log.push('\n');
+    }
+    std::fs::write(path.join("messages.log"), log)?;
+    Ok(())
+}
+
+fn report(result: Result<std::path::PathBuf>) {
+    match result {
+        Ok(path) => error!("step failed, crash dump written to: {}", path.display()),
+        Err(e) => error!("failed writing crash dump: {}", e),
+    }
+}
@@ -0,0 +1,318 @@
+//! Runtime editing of the simulation model.
+//!
+//! Clients granted the admin scope can register new components and
+//! prefabs, override default var values of existing prefabs, replace the
+//! logic of existing components, as well as push whole models. Edits are
+//! made to a copy of the model, which is validated before being applied,
+//! so edits resulting in an inconsistent model are rejected. With the
+//! local backend changes are applied to the model directly. With the
+//! organizer backend they are applied to the central model, which gets
+//! propagated to the workers on the next step. With the worker backend the
+//! edited model is forwarded to the organizer, and propagated from there.
+
+use outcome::distr::Signal;
+use outcome::error::Error as CoreError;
+use outcome::model::{ComponentModel, EntityPrefab, SimModel};
+use outcome::sim::replay::Mutation;
+use outcome::string;
+
+use crate::msg::{
+    AddPrefabRequest, ErrorCode, Message, ModelEditResponse, ModelUpdateRequest,
+    RegisterComponentRequest, ResponseError, SetPrefabDefaultsRequest, UpdateComponentLogicRequest,
+};
+use crate::server::{replay, ClientId, Permission};
+use crate::{Error, Result};
+use crate::{Server, SimConnection};
+
+impl Server {
+    pub fn handle_register_component_request(
+        &mut self,
+        msg: Message,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: RegisterComponentRequest = msg.unpack_payload(client.connection.encoding())?;
+        self.edit_model(client_id, move |model| {
+            let name = string::new_truncate(&req.name);
+            if model.get_component(&name).is_ok() {
+                return Err(CoreError::Other(format!(
+                    "component already exists: {}",
+                    req.name
+                )));
+            }
+            let mut component = ComponentModel {
+                name,
+                vars: req.vars,
+                triggers: req
+                    .triggers
+                    .iter()
+                    .map(|t| string::new_truncate(t))
+                    .collect(),
+                ..ComponentModel::default()
+            };
+            if let Some(source) = &req.logic {
+                set_logic_from_script(&mut component, source, model)?;
+            }
+            model.components.push(component);
+            Ok(())
+        })
+    }
+
+    pub fn handle_update_component_logic_request(
+        &mut self,
+        msg: Message,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: UpdateComponentLogicRequest = msg.unpack_payload(client.connection.encoding())?;
+        self.edit_model(client_id, move |model| {
+            let mut component = model
+                .get_component(&string::new_truncate(&req.name))?
+                .clone();
+            set_logic_from_script(&mut component, &req.logic, model)?;
+            // component presence was checked above
+            *model.get_component_mut(&component.name).unwrap() = component;
+            Ok(())
+        })
+    }
+
+    pub fn handle_add_prefab_request(&mut self, msg: Message, client_id: &ClientId) -> Result<()> {
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: AddPrefabRequest = msg.unpack_payload(client.connection.encoding())?;
+        self.edit_model(client_id, move |model| {
+            let name = string::new_truncate(&req.name);
+            if model.get_entity(&name).is_some() {
+                return Err(CoreError::Other(format!(
+                    "prefab already exists: {}",
+                    req.name
+                )));
+            }
+            let mut components = Vec::new();
+            for comp in &req.components {
+                components.push(
+                    model
+                        .get_component(&string::new_truncate(comp))?
+                        .name
+                        .clone(),
+                );
+            }
+            model.entities.push(EntityPrefab {
+                name,
+                components,
+                ..EntityPrefab::default()
+            });
+            Ok(())
+        })
+    }
+
+    pub fn handle_set_prefab_defaults_request(
+        &mut self,
+        msg: Message,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: SetPrefabDefaultsRequest = msg.unpack_payload(client.connection.encoding())?;
+        self.edit_model(client_id, move |model| {
+            // validate all the values before applying any of them
+            let prefab = string::new_truncate(&req.prefab);
+            let mut edited = model.clone();
+            for (comp, var, value) in req.defaults {
+                edited.set_prefab_default(
+                    &prefab,
+                    &string::new_truncate(&comp),
+                    &string::new_truncate(&var),
+                    value,
+                )?;
+            }
+            *model = edited;
+            Ok(())
+        })
+    }
+
+    pub fn handle_model_update_request(
+        &mut self,
+        msg: Message,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: ModelUpdateRequest = msg.unpack_payload(client.connection.encoding())?;
+        self.edit_model(client_id, move |model| {
+            let updated = if req.partial {
+                let mut merged = model.clone();
+                merged.merge(req.model);
+                merged
+            } else {
+                req.model
+            };
+            updated.validate()?;
+            info!(
+                "model updated: {} components, {} prefabs, {} events",
+                updated.components.len(),
+                updated.entities.len(),
+                updated.events.len()
+            );
+            *model = updated;
+            Ok(())
+        })
+    }
+
+    /// Applies the edit to the model if the client has the admin scope,
+    /// responding with the outcome.
+    fn edit_model<F>(&mut self, client_id: &ClientId, edit: F) -> Result<()>
+    where
+        F: FnOnce(&mut SimModel) -> outcome::Result<()>,
+    {
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let result = if client.permission < Permission::Admin {
+            Err(ResponseError::new(
+                ErrorCode::Unauthorized,
+                "editing the model requires admin scope",
+            ))
+        } else {
+            match &mut self.sim {
+                SimConnection::Local(sim) => {
+                    let mut model = sim.model.clone();
+                    match edit(&mut model)
+                        .and_then(|_| model.validate())
+                        .and_then(|_| sim.update_model(model))
+                    {
+                        Ok(()) => {
+                            let model = sim.model.clone();
+                            replay::log_mutation(sim, Mutation::Model(model));
+                            Ok(())
+                        }
+                        Err(e) => Err(ResponseError::from(e)),
+                    }
+                }
+                SimConnection::UnionOrganizer(organizer) => {
+                    // only valid models replace the central one
+                    let mut model = organizer.central.model.clone();
+                    match edit(&mut model).and_then(|_| model.validate()) {
+                        Ok(()) => {
+                            organizer.central.model = model;
+                            Ok(())
+                        }
+                        Err(e) => Err(ResponseError::from(e)),
+                    }
+                }
+                SimConnection::UnionWorker(worker) => {
+                    match worker.sim_node.as_ref().map(|node| node.model.clone()) {
+                        Some(mut model) => edit(&mut model)
+                            .and_then(|_| model.validate())
+                            .and_then(|_| worker.send_central(Signal::UpdateModel(model)))
+                            .map_err(ResponseError::from),
+                        None => Err(Error::SimNotStarted.into()),
+                    }
+                }
+                SimConnection::Idle => Err(Error::SimNotStarted.into()),
+            }
+        };
+        if let Err(e) = &result {
+            warn!("client {} failed editing the model: {}", client_id, e);
+        }
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let (error, code) = result.err().unwrap_or_default().into_fields();
+        client
+            .connection
+            .send_payload(ModelEditResponse { error, code }, None)
+    }
+}
+
+#[cfg(feature = "machine_script")]
+fn set_logic_from_script(
+    component: &mut ComponentModel,
+    source: &str,
+    model: &mut SimModel,
+) -> outcome::Result<()> {
+    component.logic = outcome::model::LogicModel::from_script(source, &component.name, model)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "machine_script"))]
+fn set_logic_from_script(
+    _component: &mut ComponentModel,
+    _source: &str,
+    _model: &mut SimModel,
+) -> outcome::Result<()> {
+    Err(CoreError::Other(
+        "creating logic from script requires the machine_script feature".to_string(),
+    ))
+}
+
+#[test]
+fn anonymous_clients_cant_edit_the_model_by_default() {
+    use crate::harness::TestServer;
+    use crate::ServerConfig;
+
+    let sim = outcome::SimModelBuilder::new()
+        .component("pos", |c| c.var("float:x", None))
+        .prefab("dot", &["pos"])
+        .build_sim()
+        .unwrap();
+    let server = TestServer::start_with_config(sim, ServerConfig::default()).unwrap();
+    let mut client = server.client().unwrap();
+    let resp = client.add_prefab("dot2", vec!["pos".to_string()]).unwrap();
+    assert_eq!(resp.code, Some(ErrorCode::Unauthorized));
+    client.disconnect().unwrap();
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn invalid_edits_are_rejected_by_the_organizer() {
+    use crate::harness::{ClusterConfig, TestCluster};
+    use crate::ServerConfig;
+
+    let model = outcome::SimModelBuilder::new()
+        .component("pos", |c| c.var("float:x", None))
+        .prefab("dot", &["pos"])
+        .build()
+        .unwrap();
+    let config = ClusterConfig {
+        server: ServerConfig {
+            anonymous_admin: true,
+            ..ServerConfig::default()
+        },
+        ..ClusterConfig::default()
+    };
+    let cluster = TestCluster::start_with_config(model, config).unwrap();
+    let mut client = cluster.client().unwrap();
+
+    // triggered by an event that's not declared in the model
+    let resp = client
+        .register_component("vel", vec![], vec!["undeclared".to_string()], None)
+        .unwrap();
+    assert!(resp.code.is_some());
+    // the rejected component was not added to the model
+    let resp = client
+        .register_component("vel", vec![], vec![], None)
+        .unwrap();
+    assert_eq!(resp.code, None);
+    let resp = client
+        .add_prefab("mover", vec!["pos".to_string(), "vel".to_string()])
+        .unwrap();
+    assert_eq!(resp.code, None);
+
+    client.disconnect().unwrap();
+    cluster.shutdown().unwrap();
+}
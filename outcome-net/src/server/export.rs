@@ -0,0 +1,157 @@
+//! Exporting snapshots to clients.
+//!
+//! Snapshots can get large, so instead of sending one huge message the
+//! snapshot is streamed to the client in chunks. The export response comes
+//! first, announcing the total size of the snapshot, followed by the chunks
+//! in order. Only a limited number of chunks is sent on each poll, so that
+//! other clients are not stalled while a large snapshot is being exported.
+//! If the stream can't be completed, another export response carrying the
+//! error is sent in place of the remaining chunks.
+//!
+//! Snapshot is optionally compressed using LZ4 before being chunked.
+//!
+//! With the organizer backend the snapshot is consolidated from parts sent
+//! by all the workers. A worker exports only the part of the simulation
+//! state it holds.
+
+use std::fs::File;
+use std::io::Write;
+
+use outcome::SimModel;
+
+use crate::msg::{
+    ExportSnapshotChunk, ExportSnapshotRequest, ExportSnapshotResponse, ResponseError,
+};
+use crate::server::restore::validate_snapshot_name;
+use crate::server::{Client, Server};
+use crate::{Error, Result};
+
+/// Snapshot in the process of being sent to the client.
+pub struct SnapshotDownload {
+    bytes: Vec<u8>,
+    offset: usize,
+    chunk_size: usize,
+}
+
+impl Client {
+    /// Sends the export response and starts streaming the snapshot to the
+    /// client. Replaces any export already in progress.
+    pub fn start_snapshot_download(
+        &mut self,
+        mut bytes: Vec<u8>,
+        req: &ExportSnapshotRequest,
+        default_chunk_size: usize,
+    ) -> Result<()> {
+        let mut compressed = false;
+        #[cfg(feature = "lz4")]
+        {
+            if req.compress {
+                bytes = lz4::block::compress(&bytes, None, true)
+                    .map_err(|e| Error::Other(format!("failed compressing snapshot: {}", e)))?;
+                compressed = true;
+            }
+        }
+        self.connection.send_payload(
+            ExportSnapshotResponse {
+                error: String::new(),
+                code: None,
+                total_len: bytes.len() as u64,
+                compressed,
+            },
+            None,
+        )?;
+        self.snapshot_download = match bytes.is_empty() {
+            true => None,
+            false => Some(SnapshotDownload {
+                bytes,
+                offset: 0,
+                chunk_size: req
+                    .chunk_size
+                    .map(|size| size as usize)
+                    .unwrap_or(default_chunk_size)
+                    .max(1),
+            }),
+        };
+        Ok(())
+    }
+
+    /// Sends up to `limit` snapshot chunks to the client. Returns the
+    /// number of chunks sent.
+    ///
+    /// A chunk that couldn't be sent is not skipped, the download picks up
+    /// from it on the next call.
+    pub fn flush_snapshot_download(&mut self, limit: usize) -> Result<usize> {
+        let download = match &mut self.snapshot_download {
+            Some(d) => d,
+            None => return Ok(0),
+        };
+        let mut sent = 0;
+        while sent < limit && download.offset < download.bytes.len() {
+            let end = (download.offset + download.chunk_size).min(download.bytes.len());
+            self.connection.send_payload(
+                ExportSnapshotChunk {
+                    total_len: download.bytes.len() as u64,
+                    offset: download.offset as u64,
+                    chunk: download.bytes[download.offset..end].to_vec(),
+                },
+                None,
+            )?;
+            download.offset = end;
+            sent += 1;
+        }
+        if download.offset >= download.bytes.len() {
+            self.snapshot_download = None;
+        }
+        Ok(sent)
+    }
+}
+
+impl Server {
+    /// Sends at most the configured number of snapshot chunks to each
+    /// client with an export in progress.
+    pub(crate) fn flush_snapshot_downloads(&mut self) {
+        let limit = self.config.snapshot_chunks_per_poll;
+        for (client_id, client) in &mut self.clients {
+            if client.snapshot_download.is_none() {
+                continue;
+            }
+            match client.flush_snapshot_download(limit) {
+                Ok(_) => (),
+                // connection is busy, try again on the next poll
+                Err(Error::WouldBlock) => (),
+                Err(e) => {
+                    warn!(
+                        "[client: {}] failed sending snapshot chunk: {}",
+                        client_id, e
+                    );
+                    client.snapshot_download = None;
+                    let (error, code) =
+                        ResponseError::from(format!("failed sending snapshot: {}", e))
+                            .into_fields();
+                    let resp = ExportSnapshotResponse {
+                        error,
+                        code,
+                        total_len: 0,
+                        compressed: false,
+                    };
+                    if let Err(e) = client.connection.send_payload(resp, None) {
+                        debug!(
+                            "[client: {}] failed reporting export error: {}",
+                            client_id, e
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Saves snapshot bytes to the snapshots directory of the model's project.
+pub(crate) fn save_snapshot_bytes(model: &SimModel, name: &str, bytes: &[u8]) -> Result<()> {
+    validate_snapshot_name(name)?;
+    let project_path = outcome::util::find_project_root(model.scenario.path.clone(), 3)?;
+    let snapshot_path = project_path.join(outcome::SNAPSHOTS_DIR_NAME).join(name);
+    let mut file = File::create(snapshot_path)?;
+    file.write_all(bytes)?;
+    Ok(())
+}
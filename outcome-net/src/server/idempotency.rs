@@ -0,0 +1,119 @@
+//! Deduplication of mutating requests using client-provided keys.
+//!
+//! Clients implementing retry logic can attach an idempotency key to
+//! mutating requests. Responses to such requests are retained for the
+//! duration of the dedup window, and any repeated request with the same key
+//! is answered with the retained response instead of being applied again.
+//!
+//! Keys are scoped to the client session, so that a key reused by another
+//! client doesn't get the response meant for someone else. Since sessions survive
+//! reconnects, requests retried after reconnecting are still deduplicated.
+
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::msg::{msg_bytes_from_payload, Message, Payload};
+use crate::server::{ClientId, Server};
+use crate::{Error, Result, TaskId};
+
+impl Server {
+    /// Answers the request using the retained response, if the key was
+    /// already seen within the dedup window. Returns whether the response
+    /// was replayed.
+    ///
+    /// Replayed response carries the task id of the repeated request.
+    pub(crate) fn replay_idempotent_response(
+        &mut self,
+        key: &Option<String>,
+        task_id: TaskId,
+        client_id: &ClientId,
+    ) -> Result<bool> {
+        let key = match key {
+            Some(k) => k,
+            None => return Ok(false),
+        };
+        let window = self.config.idempotency_window;
+        self.idempotency_cache
+            .retain(|_, (time, _)| time.elapsed() < window);
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let cache_key = (client.session_token.clone(), key.clone());
+        if let Some((_, bytes)) = self.idempotency_cache.get(&cache_key) {
+            debug!(
+                "[client: {}] replaying response for idempotency key: {}",
+                client_id, key
+            );
+            let encoding = client.connection.encoding();
+            let mut msg = Message::from_bytes(bytes.clone(), encoding)?;
+            msg.task_id = task_id;
+            client
+                .connection
+                .send_bytes(msg.to_bytes(encoding)?, None)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Sends the response to the client, retaining it if an idempotency key
+    /// was provided.
+    pub(crate) fn send_idempotent_response<P: Payload + Serialize>(
+        &mut self,
+        resp: P,
+        key: Option<String>,
+        task_id: TaskId,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let bytes = msg_bytes_from_payload(resp, task_id, client.connection.encoding())?;
+        client.connection.send_bytes(bytes.clone(), None)?;
+        if let Some(key) = key {
+            let cache_key = (client.session_token.clone(), key);
+            self.idempotency_cache
+                .insert(cache_key, (Instant::now(), bytes));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn repeated_requests_are_answered_with_retained_response() {
+    use crate::harness::TestServer;
+    use crate::msg::{SpawnEntitiesRequest, SpawnEntitiesResponse};
+
+    let model = outcome::SimModelBuilder::new()
+        .component("pos", |c| c.var("float:x", None))
+        .prefab("dot", &["pos"])
+        .build()
+        .unwrap();
+    let server = TestServer::start(model).unwrap();
+    let mut client = server.client().unwrap();
+    let mut spawn = |key: Option<&str>| -> SpawnEntitiesResponse {
+        client
+            .send_payload(
+                SpawnEntitiesRequest {
+                    entity_prefabs: vec!["dot".to_string()],
+                    entity_names: vec![String::new()],
+                    idempotency_key: key.map(|k| k.to_string()),
+                },
+                None,
+            )
+            .unwrap();
+        let (_, msg) = client.recv_msg().unwrap();
+        msg.unpack_payload(client.connection.encoding()).unwrap()
+    };
+
+    let first = spawn(Some("key"));
+    assert_eq!(first.entity_names.len(), 1);
+    // retried request doesn't spawn another entity
+    assert_eq!(spawn(Some("key")).entity_names, first.entity_names);
+    assert_ne!(spawn(None).entity_names, first.entity_names);
+
+    client.disconnect().unwrap();
+    server.shutdown().unwrap();
+}
@@ -0,0 +1,96 @@
+//! Interpolation of float vars between steps.
+//!
+//! Clients visualizing the simulation at a higher frame rate than the step
+//! rate can smoothly interpolate designated float vars instead of jumping
+//! between stepped values. After each step the server records the values
+//! of the vars listed in the config, along with the time at which the step
+//! was processed. Data transfer responses then carry the values recorded
+//! after the previous step and the step timestamps, sparing clients from
+//! requesting history.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use fnv::FnvHashMap;
+use outcome::{Address, Sim, Var};
+
+use crate::msg::Interpolation;
+
+/// Values of the designated vars as of the last two steps.
+#[derive(Default)]
+pub(crate) struct InterpolationState {
+    prev_step_at: u64,
+    step_at: u64,
+    previous: FnvHashMap<Address, Var>,
+    current: FnvHashMap<Address, Var>,
+}
+
+impl InterpolationState {
+    /// Records the values of the designated vars, to be called right
+    /// after the simulation is stepped. Addresses that can't be found or
+    /// don't point to float vars are skipped.
+    pub fn capture(&mut self, vars: &[Address], sim: &Sim) {
+        if vars.is_empty() {
+            return;
+        }
+        self.previous = std::mem::take(&mut self.current);
+        for address in vars {
+            if let Ok(var) = sim.get_var(address) {
+                if let Var::Float(_) = var {
+                    self.current.insert(address.clone(), var.clone());
+                }
+            }
+        }
+        self.prev_step_at = self.step_at;
+        self.step_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+    }
+
+    /// Drops the captured values, releasing the memory held. Metadata is
+    /// not available again until values are captured after two more
+    /// steps.
+    pub fn clear(&mut self) {
+        self.previous = FnvHashMap::default();
+        self.current = FnvHashMap::default();
+    }
+
+    /// Returns the metadata to be attached to data transfer responses, if
+    /// any values were captured.
+    pub fn metadata(&self) -> Option<Interpolation> {
+        if self.previous.is_empty() {
+            return None;
+        }
+        Some(Interpolation {
+            prev_step_at: self.prev_step_at,
+            step_at: self.step_at,
+            previous: self.previous.clone(),
+        })
+    }
+}
+
+#[test]
+fn previous_values_come_from_the_previous_step() {
+    use std::str::FromStr;
+
+    let mut sim = outcome::SimModelBuilder::new()
+        .component("pos", |c| c.var("float:x", None))
+        .prefab("dot", &["pos"])
+        .build_sim()
+        .unwrap();
+    let id = sim
+        .spawn_entity(Some(&outcome::string::new_truncate("dot")), None)
+        .unwrap();
+    let address = Address::from_str(&format!("{}:pos:float:x", id)).unwrap();
+    let vars = vec![address.clone()];
+
+    let mut state = InterpolationState::default();
+    *sim.get_var_mut(&address).unwrap() = Var::Float(1.);
+    state.capture(&vars, &sim);
+    assert!(state.metadata().is_none());
+
+    *sim.get_var_mut(&address).unwrap() = Var::Float(2.);
+    state.capture(&vars, &sim);
+    let metadata = state.metadata().unwrap();
+    assert_eq!(metadata.previous[&address], Var::Float(1.));
+}
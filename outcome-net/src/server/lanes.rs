@@ -0,0 +1,171 @@
+//! Prioritized queueing of incoming client messages.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+use crate::msg::{Message, MessagePriority, MessageType};
+use crate::server::{ClientId, Server};
+use crate::{trace, Result};
+
+/// Separate queues for messages of different priority.
+///
+/// Messages are handled lane by lane, starting with the control lane. Bulk
+/// lane is additionally limited in the number of messages handled per poll,
+/// so that a flood of data requests can't delay turn advancement.
+///
+/// Priority only applies between clients. Messages from a single client
+/// are always handled in the order they were received, a message is
+/// queued behind the client's earlier messages waiting in lower priority
+/// lanes.
+#[derive(Default)]
+pub(crate) struct MessageLanes {
+    control: VecDeque<(ClientId, Message)>,
+    normal: VecDeque<(ClientId, Message)>,
+    bulk: VecDeque<(ClientId, Message)>,
+    /// Number of messages waiting in the normal and bulk lanes, per client
+    queued: HashMap<ClientId, QueuedCount>,
+}
+
+#[derive(Default)]
+struct QueuedCount {
+    normal: usize,
+    bulk: usize,
+}
+
+impl MessageLanes {
+    pub fn push(&mut self, client_id: ClientId, msg: Message) {
+        let priority = match (msg.type_.priority(), self.queued.get(&client_id)) {
+            (_, Some(count)) if count.bulk > 0 => MessagePriority::Bulk,
+            (MessagePriority::Control, Some(count)) if count.normal > 0 => MessagePriority::Normal,
+            (priority, _) => priority,
+        };
+        self.push_to(client_id, msg, priority);
+    }
+
+    /// Queues the message in the bulk lane regardless of it's type.
+    pub fn push_bulk(&mut self, client_id: ClientId, msg: Message) {
+        self.push_to(client_id, msg, MessagePriority::Bulk);
+    }
+
+    fn push_to(&mut self, client_id: ClientId, msg: Message, priority: MessagePriority) {
+        match priority {
+            MessagePriority::Control => self.control.push_back((client_id, msg)),
+            MessagePriority::Normal => {
+                self.queued.entry(client_id).or_default().normal += 1;
+                self.normal.push_back((client_id, msg));
+            }
+            MessagePriority::Bulk => {
+                self.queued.entry(client_id).or_default().bulk += 1;
+                self.bulk.push_back((client_id, msg));
+            }
+        }
+    }
+
+    fn pop(&mut self, priority: MessagePriority) -> Option<(ClientId, Message)> {
+        let (client_id, msg) = match priority {
+            MessagePriority::Control => return self.control.pop_front(),
+            MessagePriority::Normal => self.normal.pop_front()?,
+            MessagePriority::Bulk => self.bulk.pop_front()?,
+        };
+        if let Some(count) = self.queued.get_mut(&client_id) {
+            match priority {
+                MessagePriority::Normal => count.normal -= 1,
+                _ => count.bulk -= 1,
+            }
+            if count.normal == 0 && count.bulk == 0 {
+                self.queued.remove(&client_id);
+            }
+        }
+        Some((client_id, msg))
+    }
+
+    /// Removes all queued messages from the given client.
+    pub fn remove_client(&mut self, client_id: &ClientId) {
+        self.control.retain(|(id, _)| id != client_id);
+        self.normal.retain(|(id, _)| id != client_id);
+        self.bulk.retain(|(id, _)| id != client_id);
+        self.queued.remove(client_id);
+    }
+
+    /// Counts queued messages from the given client.
+    pub fn count_client(&self, client_id: &ClientId) -> usize {
+        self.control
+            .iter()
+            .chain(self.normal.iter())
+            .chain(self.bulk.iter())
+            .filter(|(id, _)| id == client_id)
+            .count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.control.len() + self.normal.len() + self.bulk.len()
+    }
+}
+
+impl Server {
+    /// Handles queued messages, in the order of priority.
+    pub(crate) fn handle_message_lanes(&mut self) -> Result<()> {
+        while let Some((client_id, msg)) = self.lanes.pop(MessagePriority::Control) {
+            self.handle_queued_message(msg, &client_id);
+        }
+        while let Some((client_id, msg)) = self.lanes.pop(MessagePriority::Normal) {
+            self.handle_queued_message(msg, &client_id);
+        }
+        for _ in 0..self.config.bulk_msgs_per_poll {
+            match self.lanes.pop(MessagePriority::Bulk) {
+                Some((client_id, msg)) => self.handle_queued_message(msg, &client_id),
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_queued_message(&mut self, msg: Message, client_id: &ClientId) {
+        // client might have been removed since the message was queued
+        if !self.clients.contains_key(client_id) {
+            return;
+        }
+        // anything sent out while handling the message carries it's trace id
+        let trace_id = msg.trace_id;
+        let _trace = trace::enter(trace_id);
+        if let Err(e) = self.handle_message(msg, client_id) {
+            if let crate::Error::WouldBlock = e {
+                //
+            } else {
+                error!("{} {}", trace::Display(trace_id), e);
+            }
+        }
+        // time spent handling the message doesn't count towards client
+        // idle time
+        if let Some(client) = self.clients.get_mut(client_id) {
+            client.last_event = Instant::now();
+        }
+    }
+}
+
+#[test]
+fn client_messages_keep_their_order_across_lanes() {
+    let msg = |type_| Message {
+        task_id: 0,
+        trace_id: Default::default(),
+        type_,
+        payload: vec![],
+    };
+    let mut lanes = MessageLanes::default();
+    lanes.push(1, msg(MessageType::DataTransferRequest));
+    lanes.push(1, msg(MessageType::TurnAdvanceRequest));
+    lanes.push(2, msg(MessageType::TurnAdvanceRequest));
+
+    // other clients' control messages still go first
+    let (client_id, _) = lanes.pop(MessagePriority::Control).unwrap();
+    assert_eq!(client_id, 2);
+    assert!(lanes.pop(MessagePriority::Control).is_none());
+    let (_, first) = lanes.pop(MessagePriority::Bulk).unwrap();
+    assert_eq!(first.type_, MessageType::DataTransferRequest);
+    let (_, second) = lanes.pop(MessagePriority::Bulk).unwrap();
+    assert_eq!(second.type_, MessageType::TurnAdvanceRequest);
+
+    // with nothing left queued control messages are prioritized again
+    lanes.push(1, msg(MessageType::TurnAdvanceRequest));
+    assert!(lanes.pop(MessagePriority::Control).is_some());
+}
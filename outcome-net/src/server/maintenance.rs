@@ -0,0 +1,327 @@
+//! Housekeeping performed during idle time between steps.
+//!
+//! While the server waits for blocking clients to agree on advancing the
+//! simulation, the time can be put to use for maintenance work. Tasks are
+//! processed in small chunks, with the amount of work done on each poll
+//! limited by the configured time slice.
+//!
+//! Work still in progress when a step begins is cancelled, as the state it
+//! operates on is about to change. The exception is snapshot serialization,
+//! which is finished before the step proceeds so that the snapshot reflects
+//! the state at the time it was requested.
+
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::time::Instant;
+
+use fnv::FnvHashMap;
+use outcome::sim::compact::{remap_address, remap_query, IdRemap};
+use outcome::snapshot::Snap;
+use outcome::{Address, EntityId, EntityName, EventName};
+
+use crate::msg::{
+    DataTransferRequest, ExportSnapshotRequest, ExportSnapshotResponse, ResponseError,
+};
+use crate::server::{
+    handle_data_transfer_request_local, ClientId, Selection, Server, SimConnection, Subscription,
+};
+use crate::{Result, TaskId};
+
+/// Number of entities processed at once when compacting memory.
+const COMPACTION_CHUNK: usize = 64;
+
+/// Single unit of maintenance work.
+pub enum MaintenanceTask {
+    /// Reassigns entity ids to get rid of gaps in the id range, updating
+    /// entity ids held by the server
+    CompactEntities,
+    /// Removes stale entries from the entity name index
+    RebuildIndex,
+    /// Releases excess memory held by entity storage, processing the listed
+    /// entities in chunks
+    CompactMemory { remaining: Vec<EntityId> },
+    /// Prepares server-side caches for the next round of transfers, gathering
+    /// the data observers requested during the previous step
+    WarmCache,
+    /// Serializes a snapshot requested by the client and saves it to disk
+    SerializeSnapshot {
+        client_id: ClientId,
+        req: ExportSnapshotRequest,
+    },
+}
+
+impl MaintenanceTask {
+    /// Checks whether the task can be abandoned when a step begins.
+    pub fn is_cancellable(&self) -> bool {
+        match self {
+            MaintenanceTask::SerializeSnapshot { .. } => false,
+            _ => true,
+        }
+    }
+}
+
+impl Server {
+    /// Queues up routine maintenance tasks. Called after each processed
+    /// step.
+    pub(crate) fn schedule_maintenance(&mut self) {
+        if self.config.maintenance_slice.is_none() {
+            return;
+        }
+        let sim = match &self.sim {
+            SimConnection::Local(sim) => sim,
+            _ => return,
+        };
+        if !self.maintenance.is_empty() {
+            return;
+        }
+        if let Some(threshold) = self.config.compaction_threshold {
+            if sim.fragmentation() > threshold {
+                self.maintenance.push_back(MaintenanceTask::CompactEntities);
+            }
+        }
+        self.maintenance.push_back(MaintenanceTask::RebuildIndex);
+        self.maintenance.push_back(MaintenanceTask::CompactMemory {
+            remaining: sim.entity_ids().cloned().collect(),
+        });
+        self.maintenance.push_back(MaintenanceTask::WarmCache);
+    }
+
+    /// Processes queued maintenance tasks for the duration of the configured
+    /// time slice.
+    pub(crate) fn run_maintenance(&mut self) -> Result<()> {
+        let slice = match self.config.maintenance_slice {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        let start = Instant::now();
+        while start.elapsed() < slice {
+            let mut task = match self.maintenance.pop_front() {
+                Some(t) => t,
+                None => break,
+            };
+            if !self.process_maintenance_task(&mut task)? {
+                self.maintenance.push_front(task);
+            }
+        }
+        Ok(())
+    }
+
+    /// Cancels the maintenance work in progress, finishing any tasks that
+    /// can't be abandoned. Called before a step begins.
+    pub(crate) fn interrupt_maintenance(&mut self) -> Result<()> {
+        let tasks = self.maintenance.drain(..).collect::<VecDeque<_>>();
+        let mut cancelled = 0;
+        for mut task in tasks {
+            if task.is_cancellable() {
+                cancelled += 1;
+                continue;
+            }
+            while !self.process_maintenance_task(&mut task)? {}
+        }
+        if cancelled > 0 {
+            debug!("step begins, cancelled {} maintenance tasks", cancelled);
+        }
+        Ok(())
+    }
+
+    /// Performs a chunk of work on the task. Returns whether the task was
+    /// completed.
+    fn process_maintenance_task(&mut self, task: &mut MaintenanceTask) -> Result<bool> {
+        let sim = match &mut self.sim {
+            SimConnection::Local(sim) => sim,
+            _ => return Ok(true),
+        };
+        match task {
+            MaintenanceTask::CompactEntities => {
+                let remap = sim.compact_entities()?;
+                if remap.is_empty() {
+                    return Ok(true);
+                }
+                debug!("compacted entities, {} ids changed", remap.len());
+                let entity_idx = sim.entity_index();
+                for (_, client) in &mut self.clients {
+                    remap_selections(&mut client.selections, &remap);
+                    remap_orders(&mut client.order_store, &remap, entity_idx);
+                    remap_transfers(&mut client.scheduled_transfers, &remap, entity_idx);
+                    remap_queries(&mut client.scheduled_queries, &remap, entity_idx);
+                    remap_subscriptions(&mut client.subscriptions, &remap, entity_idx);
+                }
+                self.sessions.remap_entities(&remap, entity_idx);
+                for task in &mut self.maintenance {
+                    if let MaintenanceTask::CompactMemory { remaining } = task {
+                        for id in remaining {
+                            if let Some(new_id) = remap.get(id) {
+                                *id = *new_id;
+                            }
+                        }
+                    }
+                }
+                Ok(true)
+            }
+            MaintenanceTask::RebuildIndex => {
+                sim.rebuild_entity_index();
+                Ok(true)
+            }
+            MaintenanceTask::CompactMemory { remaining } => {
+                let split = remaining.len().saturating_sub(COMPACTION_CHUNK);
+                sim.shrink_entities(&remaining.split_off(split));
+                if remaining.is_empty() {
+                    sim.shrink_entity_maps();
+                    return Ok(true);
+                }
+                Ok(false)
+            }
+            MaintenanceTask::WarmCache => {
+                let window = self.config.idempotency_window;
+                self.idempotency_cache
+                    .retain(|_, (time, _)| time.elapsed() < window);
+                // gather the transfers observers asked for during the previous
+                // step, so that their next requests are served from the cache
+                let clock = sim.get_clock();
+                for (client_id, dtr) in self.observer_cache.to_warm(clock) {
+                    let client = match self.clients.get_mut(&client_id) {
+                        Some(c) => c,
+                        None => continue,
+                    };
+                    match handle_data_transfer_request_local(&dtr, sim, client) {
+                        Ok(response) => self.observer_cache.insert(clock, &dtr, response.data),
+                        Err(e) => {
+                            debug!("failed warming up {} transfer: {}", dtr.transfer_type, e)
+                        }
+                    }
+                }
+                Ok(true)
+            }
+            MaintenanceTask::SerializeSnapshot { client_id, req } => {
+                let result = match sim.save_snapshot(&req.name, false) {
+                    Ok(_) if req.send_back => sim.to_snapshot(),
+                    Ok(_) => Ok(Vec::new()),
+                    Err(e) => Err(e),
+                };
+                if req.send_back {
+                    let chunk_size = self.config.snapshot_chunk_size;
+                    if let Some(client) = self.clients.get_mut(client_id) {
+                        match result {
+                            Ok(bytes) => client.start_snapshot_download(bytes, req, chunk_size)?,
+                            Err(e) => {
+                                let (error, code) = ResponseError::from(e).into_fields();
+                                client.connection.send_payload(
+                                    ExportSnapshotResponse {
+                                        error,
+                                        code,
+                                        total_len: 0,
+                                        compressed: false,
+                                    },
+                                    None,
+                                )?
+                            }
+                        }
+                    }
+                }
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// Updates addresses stored in var orders after entity compaction.
+pub(crate) fn remap_orders(
+    orders: &mut FnvHashMap<u32, Vec<Address>>,
+    remap: &IdRemap,
+    entity_idx: &FnvHashMap<EntityName, EntityId>,
+) {
+    for addr in orders.values_mut().flatten() {
+        remap_address(addr, remap, entity_idx);
+    }
+}
+
+/// Updates addresses selected by scheduled transfers after entity
+/// compaction.
+pub(crate) fn remap_transfers(
+    transfers: &mut FnvHashMap<EventName, Vec<DataTransferRequest>>,
+    remap: &IdRemap,
+    entity_idx: &FnvHashMap<EntityName, EntityId>,
+) {
+    for request in transfers.values_mut().flatten() {
+        for selected in &mut request.selection {
+            if let Ok(mut addr) = Address::from_str(selected) {
+                if remap_address(&mut addr, remap, entity_idx) {
+                    *selected = addr.to_string();
+                }
+            }
+        }
+    }
+}
+
+/// Updates entities held by selections after entity compaction.
+pub(crate) fn remap_selections(selections: &mut HashMap<String, Selection>, remap: &IdRemap) {
+    for id in selections.values_mut().flat_map(|s| s.entities.iter_mut()) {
+        if let Some(new_id) = remap.get(id) {
+            *id = *new_id;
+        }
+    }
+}
+
+/// Updates subscription queries after entity compaction.
+pub(crate) fn remap_subscriptions(
+    subscriptions: &mut FnvHashMap<u32, Subscription>,
+    remap: &IdRemap,
+    entity_idx: &FnvHashMap<EntityName, EntityId>,
+) {
+    for subscription in subscriptions.values_mut() {
+        remap_query(&mut subscription.query, remap, entity_idx);
+        // previously sent addresses may now point elsewhere
+        subscription.last.clear();
+        subscription.resync = true;
+    }
+}
+
+/// Updates scheduled queries after entity compaction.
+pub(crate) fn remap_queries(
+    queries: &mut FnvHashMap<EventName, Vec<(TaskId, outcome::Query)>>,
+    remap: &IdRemap,
+    entity_idx: &FnvHashMap<EntityName, EntityId>,
+) {
+    for (_, query) in queries.values_mut().flatten() {
+        remap_query(query, remap, entity_idx);
+    }
+}
+
+#[test]
+fn scheduled_transfers_follow_compacted_ids() {
+    let request = DataTransferRequest {
+        transfer_type: "Select".to_string(),
+        selection: vec!["3:pos:float:x".to_string(), "named:pos:float:x".to_string()],
+        precision: None,
+        since_step: None,
+        order_id: None,
+    };
+    let mut transfers = FnvHashMap::default();
+    transfers.insert(outcome::string::new_truncate("step"), vec![request]);
+    let mut remap = IdRemap::default();
+    remap.insert(3, 1);
+    let mut entity_idx = FnvHashMap::default();
+    entity_idx.insert(outcome::string::new_truncate("named"), 5);
+
+    remap_transfers(&mut transfers, &remap, &entity_idx);
+    let selection = &transfers.values().next().unwrap()[0].selection;
+    assert_eq!(selection[0], "1:pos:float:x");
+    // named entities are addressed by name, which stays the same
+    assert_eq!(selection[1], "named:pos:float:x");
+}
+
+#[test]
+fn snapshot_serialization_is_not_cancelled() {
+    assert!(MaintenanceTask::WarmCache.is_cancellable());
+    assert!(!MaintenanceTask::SerializeSnapshot {
+        client_id: 0,
+        req: ExportSnapshotRequest {
+            name: "snap".to_string(),
+            save_to_disk: true,
+            send_back: false,
+            chunk_size: None,
+            compress: false,
+        },
+    }
+    .is_cancellable());
+}
@@ -0,0 +1,151 @@
+//! Graceful degradation under memory pressure.
+//!
+//! Long running simulations can slowly grow their memory footprint, until
+//! the process gets taken down by the system. To prevent that, the server
+//! can periodically check the resident memory of its process against the
+//! limits defined in the config.
+//!
+//! Past the soft limit, memory held by the server for convenience is
+//! released: var history kept for interpolation, the recent message log,
+//! the observer transfer cache and expired idempotency entries. Responses
+//! still within the dedup window are kept, as dropping them could get
+//! retried requests applied twice. Past the hard limit, requests to spawn
+//! new entities are additionally refused with a memory pressure error.
+//!
+//! Each time the level of memory pressure changes a notice is published
+//! to subscribers. Clients that don't expect unsolicited messages would
+//! trip over them, so pushing notices to clients has to be enabled in the
+//! config.
+//!
+//! Resident memory is read from procfs, on other platforms memory is not
+//! monitored.
+
+use std::time::Instant;
+
+use crate::msg::{MemoryPressure, MemoryPressureNotice};
+use crate::server::Server;
+use crate::Result;
+
+/// State of the memory monitoring.
+pub(crate) struct MemoryMonitor {
+    last_check: Instant,
+    pub level: MemoryPressure,
+    pub resident: u64,
+}
+
+impl Default for MemoryMonitor {
+    fn default() -> Self {
+        Self {
+            last_check: Instant::now(),
+            level: MemoryPressure::Normal,
+            resident: 0,
+        }
+    }
+}
+
+impl Server {
+    /// Checks process memory against the configured limits, applying
+    /// mitigations and notifying clients as needed. Checks are performed no
+    /// more often than the configured interval.
+    pub(crate) fn check_memory(&mut self) -> Result<()> {
+        if self.config.memory_soft_limit.is_none() && self.config.memory_hard_limit.is_none() {
+            return Ok(());
+        }
+        if self.memory.last_check.elapsed() < self.config.memory_check_interval {
+            return Ok(());
+        }
+        self.memory.last_check = Instant::now();
+
+        let resident = match resident_memory() {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        self.memory.resident = resident;
+
+        let level = if self
+            .config
+            .memory_hard_limit
+            .map_or(false, |l| resident > l)
+        {
+            MemoryPressure::Hard
+        } else if self
+            .config
+            .memory_soft_limit
+            .map_or(false, |l| resident > l)
+        {
+            MemoryPressure::Soft
+        } else {
+            MemoryPressure::Normal
+        };
+
+        if level >= MemoryPressure::Soft {
+            self.release_memory();
+        }
+
+        if level != self.memory.level {
+            match level {
+                MemoryPressure::Normal => {
+                    info!("memory pressure relieved ({} bytes resident)", resident)
+                }
+                _ => warn!(
+                    "memory pressure level {:?} reached ({} bytes resident)",
+                    level, resident
+                ),
+            }
+            self.memory.level = level;
+            self.notify_memory_pressure()?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether spawning new entities is currently refused.
+    pub(crate) fn refuses_spawns(&self) -> bool {
+        self.memory.level == MemoryPressure::Hard
+    }
+
+    /// Releases memory held by server-side caches and buffers.
+    fn release_memory(&mut self) {
+        self.interpolation.clear();
+        self.message_log.clear();
+        self.message_log.shrink_to_fit();
+        self.observer_cache = Default::default();
+        let window = self.config.idempotency_window;
+        self.idempotency_cache
+            .retain(|_, (time, _)| time.elapsed() < window);
+        self.idempotency_cache.shrink_to_fit();
+    }
+
+    /// Lets the subscribers, and the clients if enabled, know about the
+    /// current level of memory pressure.
+    fn notify_memory_pressure(&mut self) -> Result<()> {
+        let notice = MemoryPressureNotice {
+            level: self.memory.level,
+            resident: self.memory.resident,
+        };
+        if self.config.memory_pressure_pushes {
+            for (_, client) in &mut self.clients {
+                client.queue_push(notice.clone(), 0, self.config.push_buffer_size)?;
+            }
+        }
+        if let Some(publisher) = &self.publisher {
+            publisher.publish(notice)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads resident memory of the current process, in bytes.
+#[cfg(target_os = "linux")]
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(kb * 1024)
+}
+
+/// Reads resident memory of the current process, in bytes.
+#[cfg(not(target_os = "linux"))]
+fn resident_memory() -> Option<u64> {
+    None
+}
@@ -1,6 +1,6 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::convert::{TryFrom, TryInto};
-use std::io::{ErrorKind, Write};
+use std::io::ErrorKind;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -10,33 +10,105 @@ use std::time::{Duration, Instant};
 
 use fnv::FnvHashMap;
 use id_pool::IdPool;
-use outcome::{string, Address, EventName, Sim, SimModel, StringId, VarType};
+use outcome::snapshot::Snap;
+use outcome::{string, Address, EntityId, EventName, Sim, SimModel, StringId, Var, VarType};
 
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
 use crate::msg::*;
 use crate::service::Service;
 
+use audit::{AuditAction, AuditLog};
+use export::SnapshotDownload;
+use interpolation::InterpolationState;
+use lanes::MessageLanes;
+use maintenance::MaintenanceTask;
+use memory::MemoryMonitor;
+use observer::ObserverCache;
+use publish::Publisher;
+use runtime_errors::RuntimeErrorLog;
+#[cfg(any(feature = "kafka_sink", feature = "nats_sink"))]
+use sink::Sink;
+
+pub use auth::Permission;
+use selection::Selection;
+use session::Sessions;
+#[cfg(any(feature = "kafka_sink", feature = "nats_sink"))]
+pub use sink::{SinkBackend, SinkConfig};
+use subscription::Subscription;
+
 use crate::msg::TransferResponseData::AddressedVar;
 use crate::organizer::OrganizerTask;
 use crate::socket::{
     pack, unpack, CompositeSocketAddress, Encoding, Socket, SocketAddress, SocketConfig,
     SocketEvent, SocketEventType, SocketType, Transport,
 };
+use crate::trace::{self, TraceId};
 use crate::{error::Error, Result, TaskId};
 use crate::{Organizer, Worker};
 use outcome::distr::{CentralCommunication, NodeCommunication, Signal};
-use std::fs::File;
+use outcome::sim::replay::Mutation;
 use std::str::FromStr;
 
+#[cfg(feature = "async")]
+mod async_poll;
+mod audit;
+mod auth;
+mod crashdump;
+mod edit;
+mod export;
+mod idempotency;
+mod interpolation;
+mod lanes;
+mod maintenance;
+mod memory;
+mod observer;
+mod publish;
 mod pull;
+mod push;
 mod query;
+mod recorder;
+mod replay;
+mod restore;
+mod runtime_errors;
+mod selection;
+mod session;
+#[cfg(any(feature = "kafka_sink", feature = "nats_sink"))]
+mod sink;
+mod start;
+mod subscription;
 mod turn;
 
 pub type ClientId = u32;
 
 pub enum ServerTask {
-    WaitForOrganizerSnapshotResponses(ClientId, ExportSnapshotRequest),
+    WaitForOrganizerSnapshotResponses(ClientId, ExportSnapshotRequest, TraceId),
+
+    WaitForCoordQueryResponse(ClientId, TraceId),
+
+    /// Data transfer requested by an observer, along with the task id of
+    /// the request.
+    WaitForObserverTransfer(ClientId, DataTransferRequest, TaskId, TraceId),
+}
+
+impl ServerTask {
+    /// Client that's waiting on the task.
+    pub fn client_id(&self) -> ClientId {
+        match self {
+            ServerTask::WaitForOrganizerSnapshotResponses(client_id, ..)
+            | ServerTask::WaitForCoordQueryResponse(client_id, _)
+            | ServerTask::WaitForObserverTransfer(client_id, ..) => *client_id,
+        }
+    }
 
-    WaitForCoordQueryResponse(ClientId),
+    /// Trace id of the request that started the task.
+    pub fn trace_id(&self) -> TraceId {
+        match self {
+            ServerTask::WaitForOrganizerSnapshotResponses(_, _, trace_id)
+            | ServerTask::WaitForCoordQueryResponse(_, trace_id)
+            | ServerTask::WaitForObserverTransfer(_, _, _, trace_id) => *trace_id,
+        }
+    }
 }
 
 /// High-level representation of the simulation interface.
@@ -45,6 +117,9 @@ pub enum SimConnection {
     UnionOrganizer(Organizer),
     UnionWorker(Worker),
     // UnionRelay(Relay),
+    /// No simulation running yet, waiting for a client to start one with
+    /// a `StartSimRequest`
+    Idle,
 }
 
 /// Connected client as seen by the server.
@@ -59,6 +134,11 @@ pub struct Client {
     /// Blocking client has to explicitly agree to let server continue to next turn,
     /// while non-blocking client is more of a passive observer
     pub is_blocking: bool,
+    /// Observer client has read-only access and is never blocking
+    pub is_observer: bool,
+    /// Start of the current rate limiting window and the number of messages
+    /// accepted within it, only tracked for observers
+    pub msg_window: (Instant, u32),
     /// Furthest simulation step client has announced it's ready to proceed to.
     /// If this is bigger than the current step that client counts as
     /// ready for processing to next common furthest step.
@@ -67,24 +147,68 @@ pub struct Client {
     /// Client-specific keepalive value, if none server config value applies
     pub keepalive: Option<Duration>,
     pub last_event: Instant,
+    /// Time the last busy heartbeat was sent to the client
+    pub last_busy_heartbeat: Instant,
 
     /// Authentication pair used by the client
     pub auth_pair: Option<(String, String)>,
+    /// Level of access granted to the client, see [`Permission`]
+    pub permission: Permission,
     /// Self-assigned name
     pub name: String,
+    /// Token allowing the client to resume it's session after reconnecting
+    pub session_token: String,
 
     /// List of scheduled data transfers
     pub scheduled_transfers: FnvHashMap<EventName, Vec<DataTransferRequest>>,
     /// List of scheduled queries
     pub scheduled_queries: FnvHashMap<EventName, Vec<(TaskId, outcome::Query)>>,
-    /// Clock step on which client needs to be notified of step advance success
-    pub scheduled_advance_response: Option<usize>,
+    /// Clock step on which client needs to be notified of step advance
+    /// success, along with the task id of the request
+    pub scheduled_advance_response: Option<(usize, TaskId)>,
+    /// Events scheduled by the client that are yet to fire, counted
+    /// against the configured limit
+    pub scheduled_events: Vec<outcome::scheduler::ScheduledEvent>,
 
     pub order_store: FnvHashMap<u32, Vec<Address>>,
     pub order_id_pool: IdPool,
+    /// Order created by the latest ordered transfer, reused by requests
+    /// with an empty selection
+    pub last_order: Option<u32>,
+    /// Last sent values for orders with delta encoding enabled
+    pub order_deltas: FnvHashMap<u32, VarSimDataPackOrdered>,
+    /// Clock value at the time of the previous `Diff` transfer
+    pub last_diff_clock: Option<usize>,
+
+    /// Outbound buffer for pushed data, e.g. scheduled transfers
+    pub pushes: VecDeque<Vec<u8>>,
+    /// Number of pushed frames sent to the client
+    pub pushes_sent: u64,
+    /// Number of pushed frames dropped because the buffer was full
+    pub pushes_dropped: u64,
+
+    /// Named entity selections created by the client
+    pub selections: HashMap<String, Selection>,
+    /// Query subscriptions created by the client
+    pub subscriptions: FnvHashMap<u32, Subscription>,
+    pub subscription_id_pool: IdPool,
+
+    /// Precision for numbers in data transfers, unless overridden by
+    /// the request
+    pub float_precision: FloatPrecision,
+
+    /// Snapshot bytes uploaded so far by the client
+    pub snapshot_upload: Vec<u8>,
+    /// Snapshot currently being exported to the client
+    pub snapshot_download: Option<SnapshotDownload>,
 }
 
 impl Client {
+    /// Returns the precision to be used for the given transfer request.
+    pub fn precision_for(&self, request: &DataTransferRequest) -> FloatPrecision {
+        request.precision.unwrap_or(self.float_precision)
+    }
+
     pub fn push_event_triggered_query(
         &mut self,
         event: EventName,
@@ -101,6 +225,90 @@ impl Client {
 
         Ok(())
     }
+
+    /// Removes references to a despawned entity from the client's var
+    /// orders, subscriptions and selections.
+    pub(crate) fn forget_entity(&mut self, id: EntityId, name: Option<&outcome::EntityName>) {
+        let id_str = id.to_string();
+        for (order_id, order) in &mut self.order_store {
+            let len = order.len();
+            order.retain(|addr| addr.entity.as_str() != id_str && Some(&addr.entity) != name);
+            // delta can't be computed against a set of different size
+            if order.len() != len {
+                self.order_deltas.remove(order_id);
+            }
+        }
+        for subscription in self.subscriptions.values_mut() {
+            forget_entity_in_query(&mut subscription.query, id);
+        }
+        for selection in self.selections.values_mut() {
+            forget_entity_in_query(&mut selection.query, id);
+            selection.entities.retain(|e| *e != id);
+        }
+    }
+}
+
+/// Removes the entity from any id filters of the query.
+fn forget_entity_in_query(query: &mut outcome::Query, id: EntityId) {
+    for filter in &mut query.filters {
+        if let outcome::query::Filter::Id(ids) = filter {
+            ids.retain(|_id| *_id != id);
+        }
+    }
+}
+
+/// Read-only view of a client connected to the server.
+#[derive(Clone, Copy)]
+pub struct ClientHandle<'a> {
+    client: &'a Client,
+}
+
+impl<'a> ClientHandle<'a> {
+    /// Unique id assigned at registration.
+    pub fn id(&self) -> ClientId {
+        self.client.id
+    }
+
+    /// Self-assigned name of the client.
+    pub fn name(&self) -> &str {
+        &self.client.name
+    }
+
+    /// Address of the client.
+    pub fn addr(&self) -> &str {
+        &self.client.addr
+    }
+
+    /// Whether the client has to agree before the simulation is advanced.
+    pub fn is_blocking(&self) -> bool {
+        self.client.is_blocking
+    }
+
+    /// Whether the client only has read-only access.
+    pub fn is_observer(&self) -> bool {
+        self.client.is_observer
+    }
+
+    /// Whether the client was granted the admin scope.
+    pub fn is_admin(&self) -> bool {
+        self.client.permission == Permission::Admin
+    }
+
+    /// Level of access granted to the client.
+    pub fn permission(&self) -> Permission {
+        self.client.permission
+    }
+
+    /// Furthest simulation step the client announced it's ready to proceed
+    /// to.
+    pub fn furthest_step(&self) -> usize {
+        self.client.furthest_step
+    }
+
+    /// Numbers of pushed frames sent to, and dropped for, the client.
+    pub fn pushes(&self) -> (u64, u64) {
+        (self.client.pushes_sent, self.client.pushes_dropped)
+    }
 }
 
 /// Configuration settings for server.
@@ -115,11 +323,23 @@ pub struct ServerConfig {
     pub self_keepalive: Option<Duration>,
     /// Time between polls in the main loop
     pub poll_wait: Duration,
+    /// Max time between polls when running in async mode, see
+    /// [`Server::run_async`]
+    #[cfg(feature = "async")]
+    pub async_max_wait: Duration,
     /// Delay between polling for new incoming client connections
     pub accept_delay: Duration,
 
-    /// Time since last traffic from client until connection is terminated
+    /// Time since last traffic from client until connection is terminated.
+    /// Clients waiting on in-flight operations are never considered idle
     pub client_keepalive: Option<Duration>,
+    /// Time for which the state of removed clients is retained, allowing
+    /// them to resume their session after reconnecting. None disables
+    /// session resumption
+    pub session_retention: Option<Duration>,
+    /// Interval at which busy heartbeats are sent to clients waiting on
+    /// in-flight operations, none disables busy heartbeats
+    pub busy_heartbeat_interval: Option<Duration>,
     /// Compress outgoing messages
     pub use_compression: bool,
 
@@ -127,11 +347,111 @@ pub struct ServerConfig {
     pub use_auth: bool,
     /// User and password pairs for client authorization
     pub auth_pairs: Vec<(String, String)>,
+    /// Users granted the admin scope
+    pub admin_users: Vec<String>,
+    /// Whether to grant the admin scope to all non-observer clients when
+    /// authorization is disabled, otherwise they get read-write access
+    pub anonymous_admin: bool,
+    /// Users granted read-only access, users not listed here nor as admin
+    /// users are granted read-write access
+    pub read_only_users: Vec<String>,
 
     /// List of transports supported for client connections
     pub transports: Vec<Transport>,
     /// List of encodings supported for client connections
     pub encodings: Vec<Encoding>,
+
+    /// Time for which responses to requests carrying idempotency keys are
+    /// retained for deduplication
+    pub idempotency_window: Duration,
+
+    /// Max number of events received from a single client per poll
+    pub client_events_per_poll: usize,
+    /// Max number of bulk messages, like data transfers, handled per poll
+    pub bulk_msgs_per_poll: usize,
+    /// Max number of messages accepted from a single observer client per
+    /// second, none disables the limit
+    pub observer_rate_limit: Option<u32>,
+    /// Whether ordered transfers requested by observers always use delta
+    /// encoding, regardless of the requested transfer type
+    pub observer_delta_transfers: bool,
+    /// Max number of events a single client can have scheduled at once,
+    /// recurring events count until the end of the run, none disables the
+    /// limit
+    pub max_scheduled_events: Option<usize>,
+
+    /// Max number of pushed frames buffered for a single client, oldest
+    /// frames are dropped once the limit is reached
+    pub push_buffer_size: usize,
+    /// Max number of pushed frames sent to a single client per poll
+    pub pushes_per_poll: usize,
+
+    /// Max time spent on maintenance work per poll while waiting between
+    /// steps, none disables maintenance entirely
+    pub maintenance_slice: Option<Duration>,
+    /// Fraction of unoccupied entity ids above which entities get compacted
+    /// during maintenance, none disables compaction. Compaction changes
+    /// entity ids
+    pub compaction_threshold: Option<f32>,
+
+    /// Path to the file where all client-originated writes are recorded,
+    /// none disables the audit trail
+    pub audit_log: Option<PathBuf>,
+    /// Path to the file where client-originated mutations and processed
+    /// steps are logged for replaying the run, none disables logging. Only
+    /// supported with the local backend
+    pub replay_log: Option<PathBuf>,
+    /// Directory containing the scenarios clients can start on an idle
+    /// server, requested scenario paths are resolved relative to it and
+    /// can't point outside of it
+    pub scenarios_dir: PathBuf,
+
+    /// Max size of a snapshot uploaded by a client, in bytes
+    pub max_snapshot_upload: usize,
+    /// Size of the chunks exported snapshots are sent in, unless
+    /// overridden by the request
+    pub snapshot_chunk_size: usize,
+    /// Max number of exported snapshot chunks sent to a single client per
+    /// poll
+    pub snapshot_chunks_per_poll: usize,
+
+    /// Float vars for which interpolation metadata is attached to data
+    /// transfer responses, only supported with the local backend
+    pub interpolated_vars: Vec<Address>,
+
+    /// Address for the publishing socket broadcasting step notices and var
+    /// streams to subscribers, none disables publishing
+    pub publish_address: Option<String>,
+    /// Named sets of vars published after each step, only supported with
+    /// the local backend
+    pub var_streams: HashMap<String, Vec<Address>>,
+
+    /// Sink streaming the event journal and var changes to an external
+    /// pipeline, none disables streaming
+    #[cfg(any(feature = "kafka_sink", feature = "nats_sink"))]
+    pub sink: Option<SinkConfig>,
+
+    /// Directory where crash dumps are written when a step fails, none
+    /// disables crash dumps
+    pub crashdump_dir: Option<PathBuf>,
+    /// Number of most recent client messages kept for crash dumps
+    pub message_log_len: usize,
+
+    /// Resident memory of the server process, in bytes, above which caches
+    /// and buffers are released, none disables the soft limit
+    pub memory_soft_limit: Option<u64>,
+    /// Resident memory of the server process, in bytes, above which
+    /// spawning new entities is refused, none disables the hard limit
+    pub memory_hard_limit: Option<u64>,
+    /// Interval between checks of the process memory against the limits
+    pub memory_check_interval: Duration,
+    /// Push memory pressure notices to connected clients, by default
+    /// notices are only sent out on the publishing socket
+    pub memory_pressure_pushes: bool,
+
+    /// Address for the Prometheus scrape endpoint, none disables metrics
+    #[cfg(feature = "metrics")]
+    pub metrics_address: Option<String>,
 }
 
 impl Default for ServerConfig {
@@ -141,13 +461,20 @@ impl Default for ServerConfig {
             description: "".to_string(),
             self_keepalive: None,
             poll_wait: Duration::from_millis(1),
+            #[cfg(feature = "async")]
+            async_max_wait: Duration::from_millis(50),
             accept_delay: Duration::from_millis(200),
 
             client_keepalive: Some(Duration::from_secs(4)),
+            session_retention: Some(Duration::from_secs(60)),
+            busy_heartbeat_interval: Some(Duration::from_secs(1)),
             use_compression: false,
 
             use_auth: false,
             auth_pairs: Vec::new(),
+            admin_users: Vec::new(),
+            anonymous_admin: false,
+            read_only_users: Vec::new(),
 
             transports: vec![
                 Transport::Tcp,
@@ -155,16 +482,96 @@ impl Default for ServerConfig {
                 Transport::LaminarUdp,
                 #[cfg(feature = "zmq_transport")]
                 Transport::ZmqTcp,
+                #[cfg(feature = "websocket_transport")]
+                Transport::WebSocket,
             ],
             encodings: vec![
                 Encoding::Bincode,
                 #[cfg(feature = "msgpack_encoding")]
                 Encoding::MsgPack,
             ],
+
+            idempotency_window: Duration::from_secs(60),
+
+            client_events_per_poll: 8,
+            bulk_msgs_per_poll: 16,
+            observer_rate_limit: Some(10),
+            observer_delta_transfers: false,
+            max_scheduled_events: Some(100),
+
+            push_buffer_size: 32,
+            pushes_per_poll: 4,
+
+            maintenance_slice: Some(Duration::from_millis(2)),
+            compaction_threshold: None,
+
+            audit_log: None,
+            replay_log: None,
+            scenarios_dir: PathBuf::from("."),
+
+            max_snapshot_upload: 256 * 1024 * 1024,
+            snapshot_chunk_size: 1024 * 1024,
+            snapshot_chunks_per_poll: 4,
+
+            interpolated_vars: Vec::new(),
+
+            publish_address: None,
+            var_streams: HashMap::new(),
+
+            #[cfg(any(feature = "kafka_sink", feature = "nats_sink"))]
+            sink: None,
+
+            crashdump_dir: None,
+            message_log_len: 64,
+
+            memory_soft_limit: None,
+            memory_hard_limit: None,
+            memory_check_interval: Duration::from_secs(1),
+            memory_pressure_pushes: false,
+
+            #[cfg(feature = "metrics")]
+            metrics_address: None,
         }
     }
 }
 
+/// Accessors forming the stable interface to the server state.
+impl Server {
+    /// Iterates over all connected clients, in no particular order.
+    pub fn clients_iter(&self) -> impl Iterator<Item = ClientHandle> {
+        self.clients.values().map(|client| ClientHandle { client })
+    }
+
+    /// Gets the client with the given id, if it's connected.
+    pub fn client(&self, id: ClientId) -> Option<ClientHandle> {
+        self.clients.get(&id).map(|client| ClientHandle { client })
+    }
+
+    /// Returns the number of connected clients.
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Returns the connection with the simulation.
+    pub fn sim_connection(&self) -> &SimConnection {
+        &self.sim
+    }
+
+    /// Returns the local simulation instance, if the server is backed by
+    /// one.
+    pub fn local_sim(&self) -> Option<&Sim> {
+        match &self.sim {
+            SimConnection::Local(sim) => Some(sim),
+            _ => None,
+        }
+    }
+
+    /// Time since creation of the server.
+    pub fn uptime(&self) -> Duration {
+        self.uptime
+    }
+}
+
 // TODO add an optional http interface to the server as a crate feature
 /// Connection entry point for clients.
 ///
@@ -192,25 +599,76 @@ pub struct Server {
     pub config: ServerConfig,
 
     /// Connection with the simulation
+    #[cfg(feature = "unstable")]
     pub sim: SimConnection,
+    #[cfg(not(feature = "unstable"))]
+    pub(crate) sim: SimConnection,
     /// Outward facing sockets
+    #[cfg(feature = "unstable")]
     pub greeters: Vec<Socket>,
+    #[cfg(not(feature = "unstable"))]
+    pub(crate) greeters: Vec<Socket>,
     /// Counter used for assigning client ids
+    #[cfg(feature = "unstable")]
     pub port_count: u32,
+    #[cfg(not(feature = "unstable"))]
+    pub(crate) port_count: u32,
 
     /// List of clients
+    #[cfg(feature = "unstable")]
     pub clients: HashMap<ClientId, Client>,
+    #[cfg(not(feature = "unstable"))]
+    pub(crate) clients: HashMap<ClientId, Client>,
     /// Time since creation of this server
+    #[cfg(feature = "unstable")]
     pub uptime: Duration,
+    #[cfg(not(feature = "unstable"))]
+    pub(crate) uptime: Duration,
 
     /// Time since last message received
     time_since_last_msg: Duration,
     /// Time since last new client connection accepted
     last_accept_time: Instant,
 
+    #[cfg(feature = "unstable")]
     pub services: Vec<Service>,
+    #[cfg(not(feature = "unstable"))]
+    pub(crate) services: Vec<Service>,
 
+    #[cfg(feature = "unstable")]
     pub tasks: HashMap<TaskId, ServerTask>,
+    #[cfg(not(feature = "unstable"))]
+    pub(crate) tasks: HashMap<TaskId, ServerTask>,
+
+    /// Retained responses to requests carrying idempotency keys, keyed by
+    /// client session token and the idempotency key
+    idempotency_cache: HashMap<(String, String), (Instant, Vec<u8>)>,
+    /// State of removed clients, retained by session token
+    sessions: Sessions,
+    /// Incoming messages waiting to be handled
+    lanes: MessageLanes,
+    /// Maintenance work waiting to be performed between steps
+    maintenance: VecDeque<MaintenanceTask>,
+    /// Data transfers served to observers during the current step
+    observer_cache: ObserverCache,
+    /// Previous values of the vars designated for interpolation
+    interpolation: InterpolationState,
+    /// Logic errors collected from the recently processed steps
+    runtime_errors: RuntimeErrorLog,
+    /// Socket broadcasting data to subscribers, if enabled
+    publisher: Option<Publisher>,
+    /// Connection to the external pipeline, if enabled
+    #[cfg(any(feature = "kafka_sink", feature = "nats_sink"))]
+    sink: Option<Sink>,
+    /// Record of client-originated writes, if enabled
+    audit: Option<AuditLog>,
+    /// Most recent messages received from clients, included in crash dumps
+    message_log: VecDeque<String>,
+    /// Process memory monitoring state
+    memory: MemoryMonitor,
+    /// Metrics exposed for scraping, if enabled
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics: Option<Metrics>,
 }
 
 impl Server {
@@ -230,7 +688,11 @@ impl Server {
     }
 
     /// Creates a new server using provided address and config.
-    pub fn new_with_config(addr: &str, config: ServerConfig, sim: SimConnection) -> Result<Self> {
+    pub fn new_with_config(
+        addr: &str,
+        config: ServerConfig,
+        mut sim: SimConnection,
+    ) -> Result<Self> {
         let greeter_addr: CompositeSocketAddress = addr.parse()?;
         println!(
             "encoding: {:?}, transport: {:?}, address: {:?}",
@@ -334,6 +796,28 @@ impl Server {
             }
         }
 
+        let audit = match &config.audit_log {
+            Some(path) => Some(AuditLog::open(path)?),
+            None => None,
+        };
+        if let (Some(path), SimConnection::Local(sim)) = (&config.replay_log, &mut sim) {
+            sim.start_replay_log(path)?;
+        }
+        let publisher = match &config.publish_address {
+            Some(address) => Some(Publisher::bind(address)?),
+            None => None,
+        };
+        #[cfg(any(feature = "kafka_sink", feature = "nats_sink"))]
+        let sink = match &config.sink {
+            Some(sink_config) => Some(Sink::connect(sink_config.clone())?),
+            None => None,
+        };
+        #[cfg(feature = "metrics")]
+        let metrics = match &config.metrics_address {
+            Some(address) => Some(Metrics::bind(address, "server")?),
+            None => None,
+        };
+
         Ok(Self {
             sim,
             config,
@@ -346,6 +830,21 @@ impl Server {
             last_accept_time: Instant::now(),
             services: vec![],
             tasks: Default::default(),
+            idempotency_cache: Default::default(),
+            sessions: Default::default(),
+            lanes: Default::default(),
+            maintenance: VecDeque::new(),
+            observer_cache: Default::default(),
+            interpolation: Default::default(),
+            runtime_errors: Default::default(),
+            publisher,
+            #[cfg(any(feature = "kafka_sink", feature = "nats_sink"))]
+            sink,
+            audit,
+            message_log: VecDeque::new(),
+            memory: Default::default(),
+            #[cfg(feature = "metrics")]
+            metrics,
         })
     }
 
@@ -376,27 +875,32 @@ impl Server {
                 }
             }
             SimConnection::UnionWorker(worker) => {
+                let mut service_models = Vec::new();
                 if let Some(node) = &worker.sim_node {
-                    for service_model in &node.model.services {
-                        if self
-                            .services
-                            .iter()
-                            .find(|s| s.name == service_model.name)
-                            .is_none()
-                        {
-                            info!("starting service: {}", service_model.name);
-                            let service = Service::start_from_model(
-                                service_model.clone(),
-                                self.greeters.first().unwrap().listener_addr()?.to_string(),
-                            )?;
-                            self.services.push(service);
-                        }
+                    service_models.extend(node.model.services.iter().cloned());
+                }
+                #[cfg(feature = "worker_plugins")]
+                service_models.extend(worker.plugins.services().iter().cloned());
+                for service_model in &service_models {
+                    if self
+                        .services
+                        .iter()
+                        .find(|s| s.name == service_model.name)
+                        .is_none()
+                    {
+                        info!("starting service: {}", service_model.name);
+                        let service = Service::start_from_model(
+                            service_model.clone(),
+                            self.greeters.first().unwrap().listener_addr()?.to_string(),
+                        )?;
+                        self.services.push(service);
                     }
                 }
             }
             SimConnection::UnionOrganizer(coord) => {
                 // warn!("not starting any services since it's a coordinator-backed server");
             }
+            SimConnection::Idle => (),
         }
 
         Ok(())
@@ -444,9 +948,47 @@ impl Server {
             }
         }
 
+        // keep track of subscribers connecting to the publisher
+        if let Some(publisher) = &mut self.publisher {
+            publisher.poll();
+        }
+
+        // send out journal records held for too long
+        #[cfg(any(feature = "kafka_sink", feature = "nats_sink"))]
+        if let Some(sink) = &mut self.sink {
+            if let Err(e) = sink.flush_due() {
+                warn!("failed flushing event sink: {}", e);
+            }
+        }
+
+        // release memory and refuse spawns past the configured limits
+        self.check_memory()?;
+
+        #[cfg(feature = "metrics")]
+        self.serve_metrics();
+
         // handle idle clients
+        let in_flight = self.in_flight_operations();
         let mut clients_to_remove = Vec::new();
         for (client_id, client) in &mut self.clients {
+            // clients waiting on in-flight operations are not idle
+            if let Some(count) = in_flight.get(client_id) {
+                client.last_event = Instant::now();
+                if let Some(interval) = self.config.busy_heartbeat_interval {
+                    if client.last_busy_heartbeat.elapsed() >= interval {
+                        client.last_busy_heartbeat = Instant::now();
+                        if let Err(e) = client.connection.send_payload(
+                            BusyHeartbeat {
+                                in_flight: *count as u32,
+                            },
+                            None,
+                        ) {
+                            warn!("failed sending busy heartbeat: {}", e);
+                        }
+                    }
+                }
+                continue;
+            }
             let time_since_last_event = Instant::now() - client.last_event;
             // println!(
             //     "time since last event for client {}: {}ms",
@@ -461,12 +1003,7 @@ impl Server {
         }
         for client_id in clients_to_remove {
             info!("removing idle client: {}", client_id);
-            self.clients
-                .get_mut(&client_id)
-                .unwrap()
-                .connection
-                .disconnect(None);
-            self.clients.remove(&client_id);
+            self.remove_client(&client_id);
         }
 
         // handle coord poll if applicable
@@ -474,7 +1011,13 @@ impl Server {
             // perform the manual poll
             organ.manual_poll()?;
             // handle any tasks that might have been finished
-            Server::handle_coord_tasks(&mut self.tasks, &self.clients, organ)?;
+            Server::handle_coord_tasks(
+                &mut self.tasks,
+                &mut self.clients,
+                &mut self.observer_cache,
+                self.config.snapshot_chunk_size,
+                organ,
+            )?;
         }
 
         // handle worker poll if applicable
@@ -482,43 +1025,105 @@ impl Server {
             worker.manual_poll()?;
         }
 
-        // handle events from clients
+        // handle events from clients, messages are queued based on priority
         let client_ids: Vec<u32> = self.clients.keys().cloned().collect();
         for client_id in client_ids {
-            // self.clients.get_mut(&client_id).unwrap().connection.
-            let (addr, event) = match self
-                .clients
-                .get_mut(&client_id)
-                .unwrap()
-                .connection
-                .try_recv()
-            {
-                Ok(e) => e,
-                Err(e) => match e {
-                    Error::WouldBlock => {
-                        continue;
+            for _ in 0..self.config.client_events_per_poll {
+                // observers over the limit are left waiting in the socket
+                if self.observer_throttled(&client_id) {
+                    break;
+                }
+                // client may have been removed after disconnecting
+                let client = match self.clients.get_mut(&client_id) {
+                    Some(c) => c,
+                    None => break,
+                };
+                let (addr, event) = match client.connection.try_recv() {
+                    Ok(e) => e,
+                    Err(e) => match e {
+                        Error::WouldBlock => {
+                            break;
+                        }
+                        _ => {
+                            warn!("try_handle_client failed: {:?}", e);
+                            break;
+                        }
+                    },
+                };
+                if let Some(client) = self.clients.get_mut(&client_id) {
+                    client.last_event = Instant::now();
+                    if client.is_observer {
+                        client.msg_window.1 += 1;
                     }
-                    _ => {
-                        warn!("try_handle_client failed: {:?}", e);
-                        continue;
+                    if client.addr != addr.to_string() {
+                        client.addr = addr.to_string();
                     }
-                },
-            };
-            if let Some(client) = self.clients.get_mut(&client_id) {
-                client.last_event = Instant::now();
-                if client.addr != addr.to_string() {
-                    client.addr = addr.to_string();
                 }
-            }
-            self.time_since_last_msg = Duration::from_millis(0);
-            if let Err(e) = self.handle_event(event, &client_id) {
-                if let Error::WouldBlock = e {
-                    //
-                } else {
-                    error!("{}", e);
+                self.time_since_last_msg = Duration::from_millis(0);
+                if let Err(e) = self.handle_event(event, &client_id) {
+                    if let Error::WouldBlock = e {
+                        //
+                    } else {
+                        error!("{}", e);
+                    }
                 }
             }
         }
+
+        // handle queued messages
+        self.handle_message_lanes()?;
+
+        // send out buffered pushes
+        self.flush_pushes();
+        self.flush_snapshot_downloads();
+
+        // use the idle time for maintenance
+        if self.lanes.len() == 0 {
+            self.run_maintenance()?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the client, retaining it's session so that it can be resumed
+    /// after reconnecting.
+    fn remove_client(&mut self, client_id: &ClientId) {
+        if let Some(mut client) = self.clients.remove(client_id) {
+            client.connection.disconnect(None);
+            self.lanes.remove_client(client_id);
+            self.sessions.retain(client, self.config.session_retention);
+        }
+    }
+
+    /// Counts operations in progress for each client, including queued
+    /// messages and unfinished tasks.
+    fn in_flight_operations(&self) -> HashMap<ClientId, usize> {
+        let mut in_flight = HashMap::new();
+        for task in self.tasks.values() {
+            *in_flight.entry(task.client_id()).or_insert(0) += 1;
+        }
+        for client_id in self.clients.keys() {
+            let count = self.lanes.count_client(client_id);
+            if count > 0 {
+                *in_flight.entry(*client_id).or_insert(0) += count;
+            }
+        }
+        in_flight
+    }
+
+    /// Disconnects all clients, as well as organizer's workers if the
+    /// server is backed by an organizer.
+    pub fn disconnect_all(&mut self) -> Result<()> {
+        for (_, client) in &mut self.clients {
+            client.connection.disconnect(None);
+        }
+        if let SimConnection::UnionOrganizer(coord) = &self.sim {
+            for (_, worker) in &coord.net.workers {
+                worker
+                    .connection
+                    .send_event(SocketEvent::new(SocketEventType::Disconnect), None)?;
+            }
+        }
         Ok(())
     }
 
@@ -560,6 +1165,7 @@ impl Server {
     ///
     /// On success returns a newly assigned client id.
     pub fn try_accept_client(&mut self, redirect: bool) -> Result<u32> {
+        let publish_address = self.publisher.as_ref().and_then(|p| p.address().ok());
         for mut greeter in &mut self.greeters {
             let (peer_addr, msg) = match greeter.try_recv_msg() {
                 Ok(msg) => msg,
@@ -581,17 +1187,43 @@ impl Server {
             info!("greeter received message from a new client: \"{}\" at: {} (supported transports: {:?}, supported encodings: {:?})", 
                   req.name, peer_addr, req.transports, req.encodings);
             debug!("client registration request contents: {:?}", req);
+
+            let permission = match self.config.authorize(&req) {
+                Ok(permission) => permission,
+                Err(e) => {
+                    warn!("turning away client at {}: {}", peer_addr, e);
+                    let (error, code) = ResponseError::from(e).into_fields();
+                    greeter.send_payload(
+                        RegisterClientResponse {
+                            encoding: *greeter.encoding(),
+                            transport: greeter.transport(),
+                            address: String::new(),
+                            publish_address: None,
+                            error,
+                            code,
+                            session_token: String::new(),
+                            resumed: false,
+                        },
+                        Some(peer_addr.clone()),
+                    )?;
+                    return Err(e);
+                }
+            };
             self.port_count += 1;
 
+            let auth_pair = req.auth_pair.clone();
+            let float_precision = req.float_precision;
+            let resume_token = req.session_token.clone();
+
             // negotiate transport and encoding for the communication channel
             let mut new_config = greeter.config();
             let mut new_transport = greeter.transport();
-            println!(
+            debug!(
                 "transports available on server: {:?}",
                 self.config.transports
             );
             for transport in req.transports {
-                println!("checking: {}", transport);
+                trace!("checking: {}", transport);
                 if self.config.transports.contains(&transport) {
                     new_transport = transport;
                     break;
@@ -618,7 +1250,7 @@ impl Server {
                 _ => unimplemented!(),
             };
 
-            println!("new_transport: {}", new_transport);
+            debug!("new_transport: {}", new_transport);
 
             let socket =
                 Socket::new_with_config(Some(new_address.clone()), new_transport, new_config)?;
@@ -626,30 +1258,21 @@ impl Server {
             let socket_addr = socket.listener_addr_composite()?;
             debug!("redirect address: {:?}", socket_addr);
 
-            let resp = RegisterClientResponse {
-                encoding: socket_addr.encoding.unwrap(),
-                transport: socket_addr.transport.unwrap(),
-                address: socket_addr.address.to_string(),
-            };
-
-            println!("peer_addr: {:?}", peer_addr);
-            greeter.send_payload(resp, Some(peer_addr.clone()))?;
-            // greeter.disconnect(Some(greeter.listener_addr()?));
-            // greeter.disconnect(Some(peer_addr.clone()))?;
-            // greeter.send_payload(resp, None)?;
-
-            debug!("responded to client: {}", self.port_count);
-
             debug!("client is blocking? {}", req.is_blocking);
-            let client = Client {
+            let mut client = Client {
                 id: self.port_count,
                 addr: peer_addr.to_string(),
                 connection: socket,
-                is_blocking: req.is_blocking,
+                is_blocking: req.is_blocking && !req.is_observer,
+                is_observer: req.is_observer,
+                msg_window: (Instant::now(), 0),
                 keepalive: self.config.client_keepalive,
                 last_event: Instant::now(),
-                auth_pair: None,
+                last_busy_heartbeat: Instant::now(),
+                auth_pair,
+                permission,
                 name: "".to_string(),
+                session_token: session::new_token(),
                 // furthest_step: None,
                 furthest_step: match &self.sim {
                     SimConnection::Local(sim) => sim.get_clock(),
@@ -661,14 +1284,54 @@ impl Server {
                             unimplemented!()
                         }
                     }
+                    SimConnection::Idle => 0,
                 },
                 scheduled_transfers: Default::default(),
                 scheduled_queries: Default::default(),
                 scheduled_advance_response: None,
+                scheduled_events: Vec::new(),
                 order_store: Default::default(),
                 order_id_pool: IdPool::new(),
+                last_order: None,
+                order_deltas: Default::default(),
+                last_diff_clock: None,
+                pushes: VecDeque::new(),
+                pushes_sent: 0,
+                pushes_dropped: 0,
+                selections: HashMap::new(),
+                subscriptions: Default::default(),
+                subscription_id_pool: IdPool::new(),
+                float_precision,
+                snapshot_upload: Vec::new(),
+                snapshot_download: None,
+            };
+            let resumed = match &resume_token {
+                Some(token) => {
+                    self.sessions
+                        .resume(token, &mut client, self.config.session_retention)
+                }
+                None => false,
             };
 
+            let resp = RegisterClientResponse {
+                encoding: socket_addr.encoding.unwrap(),
+                transport: socket_addr.transport.unwrap(),
+                address: socket_addr.address.to_string(),
+                publish_address: publish_address.clone(),
+                error: String::new(),
+                code: None,
+                session_token: client.session_token.clone(),
+                resumed,
+            };
+
+            debug!("peer_addr: {:?}", peer_addr);
+            greeter.send_payload(resp, Some(peer_addr.clone()))?;
+            // greeter.disconnect(Some(greeter.listener_addr()?));
+            // greeter.disconnect(Some(peer_addr.clone()))?;
+            // greeter.send_payload(resp, None)?;
+
+            debug!("responded to client: {}", self.port_count);
+
             self.clients.insert(self.port_count, client);
             return Ok(self.port_count);
         }
@@ -724,21 +1387,22 @@ impl Server {
     /// Handle message, delegating further processing to a specialized function.
     fn handle_event(&mut self, event: SocketEvent, client_id: &ClientId) -> Result<()> {
         debug!("handling event: {:?}, from client_id: {}", event, client_id);
-        let encoding = self
-            .clients
-            .get(client_id)
-            .unwrap()
-            .connection
-            .encoding()
-            .clone();
+        let client = self.clients.get(client_id).unwrap();
+        let encoding = client.connection.encoding().clone();
+        let is_observer = client.is_observer;
         match event.type_ {
             SocketEventType::Heartbeat => (),
-            SocketEventType::Bytes => {
-                self.handle_message(Message::from_bytes(event.bytes, &encoding)?, client_id)?
-            }
-            SocketEventType::Connect => println!("new connection event from client: {}", client_id),
+            // observers never take precedence over other clients
+            SocketEventType::Bytes if is_observer => self
+                .lanes
+                .push_bulk(*client_id, Message::from_bytes(event.bytes, &encoding)?),
+            SocketEventType::Bytes => self
+                .lanes
+                .push(*client_id, Message::from_bytes(event.bytes, &encoding)?),
+            SocketEventType::Connect => debug!("new connection event from client: {}", client_id),
             SocketEventType::Disconnect => {
-                println!("disconnected event from client: {}", client_id)
+                info!("client {} disconnected", client_id);
+                self.remove_client(client_id);
             }
             _ => unimplemented!(),
         }
@@ -747,6 +1411,49 @@ impl Server {
     }
 
     fn handle_message(&mut self, msg: Message, client_id: &ClientId) -> Result<()> {
+        self.log_message(&msg, client_id);
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &mut self.metrics {
+            metrics.count_received(&format!("{:?}", msg.type_));
+        }
+        if let SimConnection::Idle = self.sim {
+            match msg.type_ {
+                MessageType::PingRequest
+                | MessageType::StatusRequest
+                | MessageType::StartSimRequest => (),
+                _ => {
+                    warn!(
+                        "rejecting {:?} from client {}, no simulation running",
+                        msg.type_, client_id
+                    );
+                    let client = self
+                        .clients
+                        .get(client_id)
+                        .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+                    let (error, code) = ResponseError::from(Error::SimNotStarted).into_fields();
+                    return client.connection.send_payload_with_task(
+                        ErrorResponse {
+                            request: msg.type_,
+                            error,
+                            code,
+                        },
+                        msg.task_id,
+                        None,
+                    );
+                }
+            }
+        }
+        if self.clients.get(client_id).map_or(false, |c| c.is_observer) {
+            return self.handle_observer_message(msg, client_id);
+        }
+        if !self.check_permission(&msg, client_id)? {
+            return Ok(());
+        }
+        self.dispatch_message(msg, client_id)
+    }
+
+    /// Passes the message to the handler for it's type.
+    fn dispatch_message(&mut self, msg: Message, client_id: &ClientId) -> Result<()> {
         match msg.type_ {
             // MessageKind::Heartbeat => (),
             MessageType::PingRequest => self.handle_ping_request(msg, client_id)?,
@@ -772,9 +1479,59 @@ impl Server {
             MessageType::SpawnEntitiesRequest => {
                 self.handle_spawn_entities_request(msg, client_id)?
             }
+            MessageType::DespawnEntitiesRequest => {
+                self.handle_despawn_entities_request(msg, client_id)?
+            }
+            MessageType::ModifyEntityRequest => {
+                self.handle_modify_entity_request(msg, client_id)?
+            }
             MessageType::ExportSnapshotRequest => {
                 self.handle_export_snapshot_request(msg, client_id)?
             }
+            MessageType::GetRuntimeErrorsRequest => {
+                self.handle_get_runtime_errors_request(msg, client_id)?
+            }
+            MessageType::InvokeEventsRequest => {
+                self.handle_invoke_events_request(msg, client_id)?
+            }
+            MessageType::ScheduleEventRequest => {
+                self.handle_schedule_event_request(msg, client_id)?
+            }
+            MessageType::SetStepTriggerRequest => {
+                self.handle_set_step_trigger_request(msg, client_id)?
+            }
+            MessageType::SubscribeRequest => self.handle_subscribe_request(msg, client_id)?,
+            MessageType::UnsubscribeRequest => self.handle_unsubscribe_request(msg, client_id)?,
+            MessageType::CreateSelectionRequest => {
+                self.handle_create_selection_request(msg, client_id)?
+            }
+            MessageType::RefreshSelectionRequest => {
+                self.handle_refresh_selection_request(msg, client_id)?
+            }
+            MessageType::SelectionOperationRequest => {
+                self.handle_selection_operation_request(msg, client_id)?
+            }
+            MessageType::GridRegionRequest => self.handle_grid_region_request(msg, client_id)?,
+            MessageType::FindPathRequest => self.handle_find_path_request(msg, client_id)?,
+            MessageType::AttributionReportRequest => {
+                self.handle_attribution_report_request(msg, client_id)?
+            }
+            MessageType::RegisterComponentRequest => {
+                self.handle_register_component_request(msg, client_id)?
+            }
+            MessageType::UpdateComponentLogicRequest => {
+                self.handle_update_component_logic_request(msg, client_id)?
+            }
+            MessageType::AddPrefabRequest => self.handle_add_prefab_request(msg, client_id)?,
+            MessageType::SetPrefabDefaultsRequest => {
+                self.handle_set_prefab_defaults_request(msg, client_id)?
+            }
+            MessageType::ModelUpdateRequest => self.handle_model_update_request(msg, client_id)?,
+            MessageType::LoadSnapshotRequest => {
+                self.handle_load_snapshot_request(msg, client_id)?
+            }
+            MessageType::StartSimRequest => self.handle_start_sim_request(msg, client_id)?,
+            MessageType::RecorderRequest => self.handle_recorder_request(msg, client_id)?,
             _ => println!("unknown message type: {:?}", msg.type_),
         }
         Ok(())
@@ -790,33 +1547,80 @@ impl Server {
             .get(client_id)
             .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
         let req: ExportSnapshotRequest = msg.unpack_payload(client.connection.encoding())?;
-        let snap = match &mut self.sim {
+        if req.save_to_disk {
+            let result = match client.permission < Permission::Admin {
+                true => Err(ResponseError::new(
+                    ErrorCode::Unauthorized,
+                    "saving a snapshot to disk requires admin scope",
+                )),
+                false => restore::validate_snapshot_name(&req.name).map_err(ResponseError::from),
+            };
+            if let Err(e) = result {
+                let (error, code) = e.into_fields();
+                return client.connection.send_payload(
+                    ExportSnapshotResponse {
+                        error,
+                        code,
+                        total_len: 0,
+                        compressed: false,
+                    },
+                    None,
+                );
+            }
+        }
+        let bytes = match &mut self.sim {
             SimConnection::Local(sim) => {
+                // defer serialization to idle time if possible
+                if req.save_to_disk && self.config.maintenance_slice.is_some() {
+                    self.maintenance
+                        .push_back(MaintenanceTask::SerializeSnapshot {
+                            client_id: *client_id,
+                            req,
+                        });
+                    return Ok(());
+                }
                 if req.save_to_disk {
                     sim.save_snapshot(&req.name, false)?;
                 }
-                if req.send_back {
-                    let resp = ExportSnapshotResponse {
-                        error: "".to_string(),
-                        snapshot: vec![],
-                    };
-                    client.connection.send_payload(resp, None);
+                if !req.send_back {
+                    return Ok(());
                 }
-                return Ok(());
+                sim.to_snapshot()?
             }
             SimConnection::UnionOrganizer(organizer) => {
                 let task_id = organizer.download_snapshots()?;
                 // TODO perhaps request separate id for organizer and server levels
                 self.tasks.insert(
                     task_id,
-                    ServerTask::WaitForOrganizerSnapshotResponses(task_id, req),
+                    ServerTask::WaitForOrganizerSnapshotResponses(
+                        *client_id,
+                        req,
+                        trace::current(),
+                    ),
                 );
                 return Err(Error::WouldBlock);
             }
-            _ => unimplemented!(),
+            SimConnection::UnionWorker(worker) => {
+                let node = worker
+                    .sim_node
+                    .as_ref()
+                    .ok_or(Error::Other("node not initialized".to_string()))?;
+                let bytes = node.to_snapshot()?;
+                if req.save_to_disk {
+                    export::save_snapshot_bytes(&node.model, &req.name, &bytes)?;
+                }
+                if !req.send_back {
+                    return Ok(());
+                }
+                bytes
+            }
         };
 
-        // client.connection.send_payload(resp, None)
+        let chunk_size = self.config.snapshot_chunk_size;
+        self.clients
+            .get_mut(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?
+            .start_snapshot_download(bytes, &req, chunk_size)
     }
 
     pub fn handle_spawn_entities_request(
@@ -826,8 +1630,26 @@ impl Server {
     ) -> Result<()> {
         let client = self.clients.get(client_id).unwrap();
         let mut out_names = Vec::new();
-        let mut error = String::new();
+        let mut error = ResponseError::default();
         let req: SpawnEntitiesRequest = msg.unpack_payload(client.connection.encoding())?;
+        if self.replay_idempotent_response(&req.idempotency_key, msg.task_id, client_id)? {
+            return Ok(());
+        }
+        if self.refuses_spawns() {
+            let (error, code) =
+                ResponseError::from(Error::MemoryPressure(self.memory.resident)).into_fields();
+            let resp = SpawnEntitiesResponse {
+                entity_names: out_names,
+                error,
+                code,
+            };
+            return self.send_idempotent_response(
+                resp,
+                req.idempotency_key,
+                msg.task_id,
+                client_id,
+            );
+        }
 
         for (i, prefab) in req.entity_prefabs.iter().enumerate() {
             trace!("handling prefab: {}", prefab);
@@ -837,26 +1659,393 @@ impl Server {
             };
             match &mut self.sim {
                 SimConnection::Local(sim) => {
-                    match sim
-                        .spawn_entity(Some(&outcome::string::new_truncate(&prefab)), entity_name)
-                    {
-                        Ok(entity_id) => out_names.push(entity_id.to_string()),
-                        Err(e) => error = e.to_string(),
+                    let prefab_name = outcome::string::new_truncate(&prefab);
+                    match sim.spawn_entity(Some(&prefab_name), entity_name.clone()) {
+                        Ok(entity_id) => {
+                            replay::log_mutation(
+                                sim,
+                                Mutation::Spawn {
+                                    prefab: Some(prefab_name),
+                                    name: entity_name,
+                                    id: entity_id,
+                                },
+                            );
+                            if let Some(audit) = &mut self.audit {
+                                audit.record(
+                                    client_id,
+                                    sim.get_clock(),
+                                    AuditAction::Spawn {
+                                        prefab,
+                                        entity: entity_id.to_string(),
+                                    },
+                                );
+                            }
+                            out_names.push(entity_id.to_string())
+                        }
+                        Err(e) => error = e.into(),
                     }
                 }
-                SimConnection::UnionOrganizer(organizer) => organizer.central.spawn_entity(
-                    Some(prefab.clone()),
-                    entity_name,
-                    outcome::distr::DistributionPolicy::Random,
-                )?,
-                _ => unimplemented!(),
+                SimConnection::UnionOrganizer(organizer) => {
+                    let entity_id = organizer.central.spawn_entity(
+                        Some(prefab.clone()),
+                        entity_name,
+                        outcome::distr::DistributionPolicy::Random,
+                    )?;
+                    out_names.push(entity_id.to_string());
+                }
+                _ => {
+                    error = ResponseError::unsupported("spawning not available on worker");
+                    break;
+                }
             }
         }
+        let (error, code) = error.into_fields();
         let resp = SpawnEntitiesResponse {
             entity_names: out_names,
             error,
+            code,
+        };
+
+        self.send_idempotent_response(resp, req.idempotency_key, msg.task_id, client_id)
+    }
+
+    pub fn handle_despawn_entities_request(
+        &mut self,
+        msg: Message,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        let client = self.clients.get(client_id).unwrap();
+        let req: DespawnEntitiesRequest = msg.unpack_payload(client.connection.encoding())?;
+        if self.replay_idempotent_response(&req.idempotency_key, msg.task_id, client_id)? {
+            return Ok(());
+        }
+        let mut resp = DespawnEntitiesResponse {
+            entity_ids: Vec::new(),
+            error: String::new(),
+            code: None,
         };
+        let sim = match &mut self.sim {
+            SimConnection::Local(sim) => sim,
+            _ => {
+                resp.set_error(ResponseError::unsupported(
+                    "despawning only available with local backend",
+                ));
+                return self.send_idempotent_response(
+                    resp,
+                    req.idempotency_key,
+                    msg.task_id,
+                    client_id,
+                );
+            }
+        };
+
+        for entity in &req.entities {
+            let id = match entity.parse::<outcome::EntityId>() {
+                Ok(id) => Ok(id),
+                Err(_) => sim
+                    .entity_id_by_name(&string::new_truncate(entity))
+                    .ok_or_else(|| {
+                        outcome::error::Error::FailedGettingEntityByName(entity.clone())
+                    }),
+            };
+            let name = id.as_ref().ok().and_then(|id| {
+                sim.entity_index()
+                    .iter()
+                    .find(|(_, _id)| *_id == id)
+                    .map(|(name, _)| name.clone())
+            });
+            match id.and_then(|id| sim.despawn_entity(&id).map(|_| id)) {
+                Ok(id) => {
+                    // ids are recycled, make sure nothing stored for the
+                    // clients ends up pointing at a newly spawned entity
+                    for client in self.clients.values_mut() {
+                        client.forget_entity(id, name.as_ref());
+                    }
+                    replay::log_mutation(sim, Mutation::Despawn(id));
+                    if let Some(audit) = &mut self.audit {
+                        audit.record(
+                            client_id,
+                            sim.get_clock(),
+                            AuditAction::Despawn {
+                                entity: id.to_string(),
+                            },
+                        );
+                    }
+                    resp.entity_ids.push(id);
+                }
+                Err(e) => resp.set_first_error(e),
+            }
+        }
+
+        self.send_idempotent_response(resp, req.idempotency_key, msg.task_id, client_id)
+    }
+
+    pub fn handle_modify_entity_request(
+        &mut self,
+        msg: Message,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        let client = self.clients.get(client_id).unwrap();
+        let req: ModifyEntityRequest = msg.unpack_payload(client.connection.encoding())?;
+        if self.replay_idempotent_response(&req.idempotency_key, msg.task_id, client_id)? {
+            return Ok(());
+        }
+        let mut resp = ModifyEntityResponse {
+            attached: Vec::new(),
+            detached: Vec::new(),
+            error: String::new(),
+            code: None,
+        };
+        let sim = match &mut self.sim {
+            SimConnection::Local(sim) => sim,
+            // entities of a distributed sim are spread across workers, none
+            // of which can attach or detach components on its own yet
+            SimConnection::UnionOrganizer(_) | SimConnection::UnionWorker(_) => {
+                resp.set_error(ResponseError::unsupported(
+                    "modifying entities only available with local backend",
+                ));
+                return self.send_idempotent_response(
+                    resp,
+                    req.idempotency_key,
+                    msg.task_id,
+                    client_id,
+                );
+            }
+            SimConnection::Idle => {
+                resp.set_error(Error::SimNotStarted);
+                return self.send_idempotent_response(
+                    resp,
+                    req.idempotency_key,
+                    msg.task_id,
+                    client_id,
+                );
+            }
+        };
+
+        let id = match req.entity.parse::<outcome::EntityId>() {
+            Ok(id) => id,
+            Err(_) => match sim.entity_id_by_name(&string::new_truncate(&req.entity)) {
+                Some(id) => id,
+                None => {
+                    resp.set_error(outcome::error::Error::FailedGettingEntityByName(req.entity));
+                    return self.send_idempotent_response(
+                        resp,
+                        req.idempotency_key,
+                        msg.task_id,
+                        client_id,
+                    );
+                }
+            },
+        };
+        for component in &req.detach {
+            let comp_name = string::new_truncate(component);
+            match sim.detach_component(&id, &comp_name) {
+                Ok(()) => {
+                    replay::log_mutation(sim, Mutation::DetachComponent(id, comp_name));
+                    if let Some(audit) = &mut self.audit {
+                        audit.record(
+                            client_id,
+                            sim.get_clock(),
+                            AuditAction::Detach {
+                                component,
+                                entity: id.to_string(),
+                            },
+                        );
+                    }
+                    resp.detached.push(component.clone());
+                }
+                Err(e) => resp.set_first_error(e),
+            }
+        }
+        for component in &req.attach {
+            let comp_name = string::new_truncate(component);
+            match sim.attach_component(&id, &comp_name) {
+                Ok(()) => {
+                    replay::log_mutation(sim, Mutation::AttachComponent(id, comp_name));
+                    if let Some(audit) = &mut self.audit {
+                        audit.record(
+                            client_id,
+                            sim.get_clock(),
+                            AuditAction::Attach {
+                                component,
+                                entity: id.to_string(),
+                            },
+                        );
+                    }
+                    resp.attached.push(component.clone());
+                }
+                Err(e) => resp.set_first_error(e),
+            }
+        }
+
+        self.send_idempotent_response(resp, req.idempotency_key, msg.task_id, client_id)
+    }
+
+    pub fn handle_get_runtime_errors_request(
+        &mut self,
+        msg: Message,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: GetRuntimeErrorsRequest = msg.unpack_payload(client.connection.encoding())?;
+        let resp = match &self.sim {
+            #[cfg(feature = "machine")]
+            SimConnection::Local(sim) => GetRuntimeErrorsResponse {
+                clock: sim.get_clock(),
+                errors: self
+                    .runtime_errors
+                    .since(req.since_clock.unwrap_or(sim.get_clock().saturating_sub(1))),
+                error: String::new(),
+                code: None,
+            },
+            // workers report their errors to the organizer at the end
+            // of each step
+            #[cfg(feature = "machine")]
+            SimConnection::UnionOrganizer(coord) => GetRuntimeErrorsResponse {
+                clock: coord.central.clock,
+                errors: self.runtime_errors.since(
+                    req.since_clock
+                        .unwrap_or(coord.central.clock.saturating_sub(1)),
+                ),
+                error: String::new(),
+                code: None,
+            },
+            // workers don't retain errors, they're only available on the
+            // organizer
+            _ => {
+                let (error, code) =
+                    ResponseError::unsupported("runtime errors not available").into_fields();
+                GetRuntimeErrorsResponse {
+                    clock: 0,
+                    errors: Vec::new(),
+                    error,
+                    code,
+                }
+            }
+        };
+        client.connection.send_payload(resp, None)
+    }
+
+    pub fn handle_attribution_report_request(
+        &mut self,
+        msg: Message,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: AttributionReportRequest = msg.unpack_payload(client.connection.encoding())?;
+        let resp = match &self.sim {
+            SimConnection::Local(sim) => AttributionReportResponse {
+                report: sim.attribution_report(),
+                error: String::new(),
+                code: None,
+            },
+            // workers don't report execution times to central, a report
+            // covering only the storage part would be misleading
+            _ => {
+                let (error, code) = ResponseError::unsupported(
+                    "attribution report only available with local backend",
+                )
+                .into_fields();
+                AttributionReportResponse {
+                    report: Default::default(),
+                    error,
+                    code,
+                }
+            }
+        };
+        client.connection.send_payload(resp, None)
+    }
+
+    pub fn handle_grid_region_request(&mut self, msg: Message, client_id: &ClientId) -> Result<()> {
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: GridRegionRequest = msg.unpack_payload(client.connection.encoding())?;
+        let mut resp = GridRegionResponse {
+            grid_size: (0, 0),
+            region: Vec::new(),
+            error: String::new(),
+            code: None,
+        };
+        match &self.sim {
+            SimConnection::Local(sim) => {
+                let result = Address::from_str(&req.address)
+                    .and_then(|addr| sim.get_var(&addr))
+                    .and_then(|var| {
+                        let (width, height) = var.grid_size()?;
+                        let region = var.grid_region(
+                            req.x as usize,
+                            req.y as usize,
+                            req.width as usize,
+                            req.height as usize,
+                        )?;
+                        Ok(((width as u32, height as u32), region))
+                    });
+                match result {
+                    Ok((grid_size, region)) => {
+                        resp.grid_size = grid_size;
+                        resp.region = region;
+                    }
+                    Err(e) => resp.set_error(e),
+                }
+            }
+            _ => resp.set_error(ResponseError::unsupported(
+                "grid regions only available with local backend",
+            )),
+        }
+        client.connection.send_payload(resp, None)
+    }
 
+    pub fn handle_find_path_request(&mut self, msg: Message, client_id: &ClientId) -> Result<()> {
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: FindPathRequest = msg.unpack_payload(client.connection.encoding())?;
+        let mut resp = FindPathResponse {
+            path: Var::List(Vec::new()),
+            cost: -1.,
+            error: String::new(),
+            code: None,
+        };
+        #[cfg(feature = "pathfinding")]
+        match &self.sim {
+            SimConnection::Local(sim) => {
+                let algorithm = match req.algorithm.as_str() {
+                    "" => Ok(outcome::path::Algorithm::default()),
+                    algo => algo.parse(),
+                };
+                let result = algorithm.and_then(|algorithm| {
+                    let addr = Address::from_str(&req.address)?;
+                    outcome::path::find_path(sim.get_var(&addr)?, &req.from, &req.to, algorithm)
+                });
+                match result {
+                    Ok(Some((path, cost))) => {
+                        resp.path = path;
+                        resp.cost = cost as f64;
+                    }
+                    Ok(None) => (),
+                    Err(e) => resp.set_error(e),
+                }
+            }
+            _ => resp.set_error(ResponseError::unsupported(
+                "pathfinding only available with local backend",
+            )),
+        }
+        #[cfg(not(feature = "pathfinding"))]
+        {
+            let _ = req;
+            resp.set_error(ResponseError::unsupported(
+                "server built without pathfinding support",
+            ));
+        }
         client.connection.send_payload(resp, None)
     }
 
@@ -869,6 +2058,29 @@ impl Server {
 
     pub fn handle_status_request(&mut self, msg: Message, client_id: &ClientId) -> Result<()> {
         let connected_clients = self.clients.iter().map(|(id, c)| c.name.clone()).collect();
+        let dropped_pushes = self
+            .clients
+            .values()
+            .map(|c| (c.name.clone(), c.pushes_dropped))
+            .collect();
+        let mut traffic = self
+            .clients
+            .values_mut()
+            .map(|c| (c.name.clone(), c.connection.traffic()))
+            .collect::<Vec<_>>();
+        match &mut self.sim {
+            SimConnection::UnionOrganizer(coord) => {
+                for (worker_id, worker) in coord.net.workers.iter_mut() {
+                    traffic.push((format!("worker {}", worker_id), worker.connection.traffic()));
+                }
+            }
+            SimConnection::UnionWorker(worker) => {
+                if let Some(organizer) = worker.network.organizer.as_mut() {
+                    traffic.push(("organizer".to_string(), organizer.traffic()));
+                }
+            }
+            SimConnection::Local(_) | SimConnection::Idle => (),
+        }
         let mut client = self.clients.get_mut(client_id).unwrap();
         let req: StatusRequest = msg.unpack_payload(client.connection.encoding())?;
         let model_scenario = match &self.sim {
@@ -881,18 +2093,22 @@ impl Server {
                     unimplemented!()
                 }
             }
+            SimConnection::Idle => Default::default(),
         };
         let resp = StatusResponse {
             name: self.config.name.clone(),
             description: self.config.description.clone(),
             // address: self.greeters.first().unwrap().local_addr()?.to_string(),
             connected_clients,
+            dropped_pushes,
+            traffic,
             engine_version: outcome_core::VERSION.to_owned(),
             uptime: self.uptime.as_millis() as usize,
             current_tick: match &self.sim {
                 SimConnection::Local(sim) => sim.get_clock(),
                 SimConnection::UnionOrganizer(coord) => coord.central.get_clock(),
                 SimConnection::UnionWorker(worker) => worker.sim_node.as_ref().unwrap().clock,
+                SimConnection::Idle => 0,
             },
             scenario_name: model_scenario.manifest.name.clone(),
             scenario_title: model_scenario
@@ -949,17 +2165,33 @@ impl Server {
         msg: Message,
         client_id: &ClientId,
     ) -> Result<()> {
-        let mut client = self.clients.get_mut(client_id).unwrap();
+        let client = self.clients.get(client_id).unwrap();
         let dtr: DataTransferRequest = match msg.unpack_payload(client.connection.encoding()) {
             Ok(r) => r,
             Err(e) => {
                 panic!("failed unpacking payload: {}", e);
             }
         };
-        let mut data_pack = TypedSimDataPack::empty();
+        let mut response = self.data_transfer_response(&dtr, client_id)?;
+        let client = self.clients.get(client_id).unwrap();
+        response.data = response.data.with_precision(client.precision_for(&dtr));
+        response.interpolation = self.interpolation.metadata();
+        client
+            .connection
+            .send_payload_with_task(response, msg.task_id, None)?;
+        Ok(())
+    }
+
+    /// Gathers the data requested by the client, at native precision.
+    pub(crate) fn data_transfer_response(
+        &mut self,
+        dtr: &DataTransferRequest,
+        client_id: &ClientId,
+    ) -> Result<DataTransferResponse> {
+        let mut client = self.clients.get_mut(client_id).unwrap();
         match &mut self.sim {
             SimConnection::Local(sim_instance) => {
-                handle_data_transfer_request_local(&dtr, sim_instance, client)?
+                handle_data_transfer_request_local(dtr, sim_instance, client)
             }
             SimConnection::UnionOrganizer(coord) => {
                 let mut vars = FnvHashMap::default();
@@ -979,10 +2211,10 @@ impl Server {
                             }
                         }
 
-                        let response = DataTransferResponse {
+                        Ok(DataTransferResponse {
                             data: TransferResponseData::Var(VarSimDataPack { vars }),
-                        };
-                        client.connection.send_payload(response, None)?;
+                            interpolation: None,
+                        })
                     }
                     _ => unimplemented!(),
                 }
@@ -1022,15 +2254,19 @@ impl Server {
                         }
                     }
 
-                    let response = DataTransferResponse {
+                    Ok(DataTransferResponse {
                         data: TransferResponseData::Var(data_pack),
-                    };
-                    client.connection.send_payload(response, None)?;
+                        interpolation: None,
+                    })
+                } else {
+                    Err(Error::Other(format!(
+                        "unexpected response to data request: {:?}",
+                        resp
+                    )))
                 }
             }
-        };
-
-        Ok(())
+            SimConnection::Idle => Err(Error::SimNotStarted),
+        }
     }
 
     pub fn handle_typed_data_transfer_request(
@@ -1053,8 +2289,9 @@ impl Server {
                     "Full" => {
                         // let mut data_pack = outcome::query::AddressedTypedMap::default();
                         let mut data_pack = TypedSimDataPack::empty();
-                        for (entity_uid, entity) in &sim_instance.entities {
-                            for ((comp_name, var_id), v) in entity.storage.map.iter() {
+                        for entity in sim_instance.iter_entities() {
+                            let entity_uid = entity.id();
+                            for ((comp_name, var_id), v) in entity.entity().storage.map.iter() {
                                 if v.is_float() {
                                     data_pack.floats.insert(
                                         // format!(
@@ -1072,14 +2309,11 @@ impl Server {
                                         // ),
                                         Address {
                                             // get entity string id if available
-                                            entity: sim_instance
-                                                .entity_idx
-                                                .iter()
-                                                .find(|(e_id, e_idx)| e_idx == &entity_uid)
-                                                .map(|(e_id, _)| e_id.clone())
-                                                .unwrap_or(outcome::string::new_truncate(
+                                            entity: entity.name().cloned().unwrap_or(
+                                                outcome::string::new_truncate(
                                                     &entity_uid.to_string(),
-                                                )),
+                                                ),
+                                            ),
                                             // entity: entity_uid.parse().unwrap(),
                                             component: comp_name.clone(),
                                             var_type: VarType::Float,
@@ -1096,6 +2330,7 @@ impl Server {
                         let response = TypedDataTransferResponse {
                             data: data_pack,
                             error: String::new(),
+                            code: None,
                         };
                         client.connection.send_payload(response, None);
                     }
@@ -1128,6 +2363,9 @@ impl Server {
             let dtr = DataTransferRequest {
                 transfer_type: sdtr.transfer_type.clone(),
                 selection: sdtr.selection.clone(),
+                precision: sdtr.precision,
+                since_step: None,
+                order_id: None,
             };
             client
                 .scheduled_transfers
@@ -1154,6 +2392,7 @@ impl Server {
         let resp = ListLocalScenariosResponse {
             scenarios: Vec::new(),
             error: String::new(),
+            code: None,
         };
         client.connection.send_payload(resp, None)
     }
@@ -1169,6 +2408,7 @@ impl Server {
 
         let resp = LoadLocalScenarioResponse {
             error: String::new(),
+            code: None,
         };
         client.connection.send_payload(resp, None)
     }
@@ -1184,37 +2424,57 @@ impl Server {
 
         let resp = LoadRemoteScenarioResponse {
             error: String::new(),
+            code: None,
         };
         client.connection.send_payload(resp, None)
     }
 }
 
-fn handle_data_transfer_request_local(
+/// Parses the selected addresses, expanding any wildcards. Invalid
+/// addresses are skipped.
+fn expand_selection(selection: &[String], sim: &Sim) -> Vec<outcome::Address> {
+    selection
+        .iter()
+        .filter_map(|address| outcome::Address::from_str(address).ok())
+        .flat_map(|address| address.expand(sim))
+        .collect()
+}
+
+/// Returns the name each named entity is addressed by.
+fn entity_names(sim: &Sim) -> FnvHashMap<outcome::EntityId, &outcome::EntityName> {
+    sim.entity_index()
+        .iter()
+        .map(|(name, id)| (*id, name))
+        .collect()
+}
+
+/// Returns the name the entity is addressed by in var data packs, which is
+/// the id for unnamed entities.
+fn pack_entity_name(
+    names: &FnvHashMap<outcome::EntityId, &outcome::EntityName>,
+    id: &outcome::EntityId,
+) -> outcome::EntityName {
+    match names.get(id) {
+        Some(name) => (*name).clone(),
+        None => outcome::EntityName::from(id.to_string()),
+    }
+}
+
+pub(crate) fn handle_data_transfer_request_local(
     request: &DataTransferRequest,
     sim: &Sim,
     client: &mut Client,
-) -> Result<()> {
+) -> Result<DataTransferResponse> {
     let model = &sim.model;
     match request.transfer_type.as_str() {
         "Full" => {
+            let names = entity_names(sim);
             let mut data_pack = VarSimDataPack::default();
-            for (entity_id, entity) in &sim.entities {
-                for ((comp_name, var_id), v) in entity.storage.map.iter() {
-                    let mut ent_name = outcome::EntityName::from(entity_id.to_string());
-                    if let Some((_ent_name, _)) =
-                        sim.entity_idx.iter().find(|(_, id)| id == &entity_id)
-                    {
-                        ent_name = _ent_name.clone();
-                    }
+            for entity in sim.iter_entities() {
+                let ent_name = pack_entity_name(&names, &entity.id());
+                for ((comp_name, var_id), v) in entity.entity().storage.map.iter() {
                     data_pack.vars.insert(
-                        // format!(
-                        //     "{}:{}:{}:{}",
-                        //     entity_uid,
-                        //     comp_name,
-                        //     v.get_type().to_str(),
-                        //     var_id
-                        // ),
-                        (ent_name, comp_name.clone(), var_id.clone()),
+                        (ent_name.clone(), comp_name.clone(), var_id.clone()),
                         v.clone(),
                     );
                 }
@@ -1222,30 +2482,62 @@ fn handle_data_transfer_request_local(
 
             let response = DataTransferResponse {
                 data: TransferResponseData::Var(data_pack),
+                interpolation: None,
             };
-            client.connection.send_payload(response, None)
+            Ok(response)
+        }
+        "Diff" => {
+            let since = request
+                .since_step
+                .map(|step| step as usize)
+                .or(client.last_diff_clock)
+                .unwrap_or(0);
+            let names = entity_names(sim);
+            let mut data_pack = VarSimDataPack::default();
+            let mut removed = VarRemovals::default();
+            match sim.collect_removed_since(since) {
+                Some(removals) => {
+                    for removal in removals {
+                        let ent_name = match &removal.name {
+                            Some(name) => name.clone(),
+                            None => outcome::EntityName::from(removal.entity.to_string()),
+                        };
+                        match &removal.var {
+                            Some((comp, var)) => {
+                                removed.vars.push((ent_name, comp.clone(), var.clone()))
+                            }
+                            None => removed.entities.push(ent_name),
+                        }
+                    }
+                    for (entity_id, idx, var) in sim.collect_changed_since(since) {
+                        let ent_name = pack_entity_name(&names, &entity_id);
+                        data_pack.vars.insert((ent_name, idx.0, idx.1), var);
+                    }
+                }
+                // removals are no longer known, send everything
+                None => {
+                    removed.full = true;
+                    for entity in sim.iter_entities() {
+                        let ent_name = pack_entity_name(&names, &entity.id());
+                        for ((comp, var), v) in &entity.entity().storage.map {
+                            data_pack
+                                .vars
+                                .insert((ent_name.clone(), comp.clone(), var.clone()), v.clone());
+                        }
+                    }
+                }
+            }
+            client.last_diff_clock = Some(sim.get_clock());
+
+            let response = DataTransferResponse {
+                data: TransferResponseData::VarDiff(data_pack, removed),
+                interpolation: None,
+            };
+            Ok(response)
         }
         "Select" => {
             let mut data_pack = TypedSimDataPack::empty();
-            let mut selected = Vec::new();
-            selected.extend_from_slice(&request.selection);
-
-            // todo handle asterrisk addresses
-            // for address in &dtr.selection {
-            //     if address.contains("*") {
-            //         let addr = Address::from_str(address).unwrap();
-            //         selected.extend(
-            //             addr.expand(sim_instance)
-            //                 .iter()
-            //                 .map(|addr| addr.to_string()),
-            //         );
-            //     }
-            // }
-            for address in &selected {
-                let address = match outcome::Address::from_str(&address) {
-                    Ok(a) => a,
-                    Err(_) => continue,
-                };
+            for address in expand_selection(&request.selection, sim) {
                 if let Ok(var) = sim.get_var(&address) {
                     if var.is_float() {
                         data_pack
@@ -1257,64 +2549,112 @@ fn handle_data_transfer_request_local(
 
             let response = DataTransferResponse {
                 data: TransferResponseData::Typed(data_pack),
+                interpolation: None,
             };
-            client.connection.send_payload(response, None)
+            Ok(response)
+        }
+        "SelectVar" => {
+            let mut data = FnvHashMap::default();
+            for address in expand_selection(&request.selection, sim) {
+                if let Ok(var) = sim.get_var(&address) {
+                    data.insert(address, var.clone());
+                }
+            }
+            Ok(DataTransferResponse {
+                data: TransferResponseData::AddressedVar(data),
+                interpolation: None,
+            })
         }
         // select using addresses but return data as ordered set without
         // address keys, order is stored on server under it's own unique id
-        "SelectVarOrdered" => {
+        "SelectVarOrdered" | "SelectVarOrderedDelta" => {
             let mut data = VarSimDataPackOrdered::default();
             let selection = &request.selection;
 
             // empty selection means reuse last ordering
             if selection.is_empty() {
-                let order_id = 1;
-                let order = client.order_store.get(&order_id).unwrap();
+                let order_id = match request.order_id {
+                    Some(order_id) => order_id,
+                    None => client
+                        .last_order
+                        .ok_or_else(|| Error::Other("no var order to reuse".to_string()))?,
+                };
+                let order = client
+                    .order_store
+                    .get(&order_id)
+                    .ok_or_else(|| Error::Other(format!("unknown var order: {}", order_id)))?;
                 for addr in order {
                     if let Ok(var) = sim.get_var(&addr) {
                         data.vars.push(var.clone());
                     }
                 }
+                // send only the changes if delta encoding was requested when
+                // creating the order, falling back to the whole set if the
+                // number of vars changed, plain `SelectVarOrdered` always
+                // sends the whole set, resyncing the client
+                if let Some(previous) = client.order_deltas.get_mut(&order_id) {
+                    let delta = match request.transfer_type.as_str() {
+                        "SelectVarOrderedDelta" => data.delta_from(previous),
+                        _ => None,
+                    };
+                    *previous = data.clone();
+                    if let Some(delta) = delta {
+                        return Ok(DataTransferResponse {
+                            data: TransferResponseData::VarOrderedDelta(order_id, delta),
+                            interpolation: None,
+                        });
+                    }
+                }
                 let response = DataTransferResponse {
                     data: TransferResponseData::VarOrdered(order_id, data),
+                    interpolation: None,
                 };
-                client.connection.send_payload(response, None)
+                Ok(response)
             } else {
                 let mut order = Vec::new();
 
                 for query in selection {
-                    if query.contains("*") {
-                        for (id, entity) in &sim.entities {
-                            if id == &0 || id == &1 {
-                                continue;
-                            }
-                            let _query = query.replace("*", &id.to_string());
-                            let addr = outcome::Address::from_str(&_query)?;
-                            order.push(addr.clone());
-                            if let Ok(var) = sim.get_var(&addr) {
-                                data.vars.push(var.clone());
-                            }
-                        }
-                    } else {
-                        // TODO save the ordered list of addresses on the server for handling response
-                        let addr = outcome::Address::from_str(query)?;
-                        order.push(addr.clone());
+                    let addr = outcome::Address::from_str(query)?;
+                    for addr in addr.expand(sim) {
                         if let Ok(var) = sim.get_var(&addr) {
                             data.vars.push(var.clone());
                         }
+                        order.push(addr);
                     }
                 }
 
-                let order_id = client
-                    .order_id_pool
-                    .request_id()
-                    .ok_or(Error::Other("failed getting new order id".to_string()))?;
+                // selection given along with an existing order id updates
+                // that order instead of creating a new one
+                let order_id = match request.order_id {
+                    Some(order_id) if client.order_store.contains_key(&order_id) => order_id,
+                    _ => client
+                        .order_id_pool
+                        .request_id()
+                        .ok_or(Error::Other("failed getting new order id".to_string()))?,
+                };
                 client.order_store.insert(order_id, order);
+                client.last_order = Some(order_id);
+                if request.transfer_type == "SelectVarOrderedDelta" {
+                    let delta = client
+                        .order_deltas
+                        .get(&order_id)
+                        .and_then(|previous| data.delta_from(previous));
+                    client.order_deltas.insert(order_id, data.clone());
+                    if let Some(delta) = delta {
+                        return Ok(DataTransferResponse {
+                            data: TransferResponseData::VarOrderedDelta(order_id, delta),
+                            interpolation: None,
+                        });
+                    }
+                } else {
+                    client.order_deltas.remove(&order_id);
+                }
 
                 let response = DataTransferResponse {
                     data: TransferResponseData::VarOrdered(order_id, data),
+                    interpolation: None,
                 };
-                client.connection.send_payload(response, None)
+                Ok(response)
             }
         }
         _ => Err(Error::Unknown),
@@ -1325,11 +2665,20 @@ impl Server {
     // assumes the same task id for task on coord and server level
     fn handle_coord_tasks(
         tasks: &mut HashMap<TaskId, ServerTask>,
-        clients: &HashMap<ClientId, Client>,
+        clients: &mut HashMap<ClientId, Client>,
+        observer_cache: &mut ObserverCache,
+        snapshot_chunk_size: usize,
         organ: &mut Organizer,
     ) -> Result<()> {
         let mut finished_tasks = Vec::new();
         for (task_id, organ_task) in &mut organ.tasks {
+            // migration and checkpoint results are collected by whoever
+            // started them
+            if let OrganizerTask::MigrateEntity { .. } | OrganizerTask::Checkpoint { .. } =
+                organ_task
+            {
+                continue;
+            }
             if organ_task.is_finished() {
                 finished_tasks.push(*task_id);
             }
@@ -1339,13 +2688,24 @@ impl Server {
                 if organ_task.is_finished() {
                     println!("task {} is finished", task_id);
                     if let Some(server_task) = tasks.get(&task_id) {
+                        // responses carry the trace id of the original request
+                        let _trace = trace::enter(server_task.trace_id());
                         match server_task {
-                            ServerTask::WaitForCoordQueryResponse(client_id) => {
+                            ServerTask::WaitForCoordQueryResponse(client_id, _) => {
                                 if let Some(client) = clients.get(client_id) {
                                     match organ_task {
                                         OrganizerTask::WaitForQueryResponses {
-                                            products, ..
+                                            products,
+                                            partial,
+                                            ..
                                         } => {
+                                            // partial data is still sent,
+                                            // flagged with an error
+                                            let (error, code) = match partial {
+                                                true => ResponseError::from(Error::IncompleteQuery)
+                                                    .into_fields(),
+                                                false => (String::new(), None),
+                                            };
                                             let qp =
                                                 outcome::query::QueryProduct::combine(products);
                                             // if let outcome::query::QueryProduct::AddressedTyped(
@@ -1355,7 +2715,8 @@ impl Server {
                                             client.connection.send_payload(
                                                 TypedDataTransferResponse {
                                                     data: TypedSimDataPack::from_query_product(qp),
-                                                    error: "".to_string(),
+                                                    error,
+                                                    code,
                                                 },
                                                 // NativeQueryResponse {
                                                 //     query_product: qp,
@@ -1368,15 +2729,42 @@ impl Server {
                                     }
                                 }
                             }
-                            ServerTask::WaitForOrganizerSnapshotResponses(client_id, req) => {
+                            ServerTask::WaitForObserverTransfer(
+                                client_id,
+                                dtr,
+                                request_task_id,
+                                _,
+                            ) => {
+                                if let OrganizerTask::WaitForQueryResponses { products, .. } =
+                                    organ_task
+                                {
+                                    let data = observer::transfer_data_from_products(products);
+                                    observer_cache.insert(
+                                        organ.central.get_clock(),
+                                        dtr,
+                                        data.clone(),
+                                    );
+                                    if let Some(client) = clients.get(client_id) {
+                                        client.connection.send_payload_with_task(
+                                            DataTransferResponse {
+                                                data: data
+                                                    .with_precision(client.precision_for(dtr)),
+                                                interpolation: None,
+                                            },
+                                            *request_task_id,
+                                            None,
+                                        )?;
+                                    }
+                                }
+                            }
+                            ServerTask::WaitForOrganizerSnapshotResponses(client_id, req, _) => {
                                 let client = clients
-                                    .get(client_id)
+                                    .get_mut(client_id)
                                     .ok_or(Error::FailedGettingClientById(*client_id))?;
                                 if let OrganizerTask::WaitForSnapshotResponses {
                                     snapshots, ..
                                 } = organ_task
                                 {
-                                    let mut bytes = Vec::new();
                                     // consolidate the snapshot
                                     // TODO implement Snap on Organizer
                                     let header = outcome::snapshot::SnapshotHeader {
@@ -1389,30 +2777,33 @@ impl Server {
                                         model: organ.central.model.clone(),
                                         entities_idx: organ.central.entities_idx.clone(),
                                         event_queue: organ.central.event_queue.clone(),
+                                        scheduler: organ.central.scheduler.clone(),
                                         entity_pool: organ.central.entity_idpool.clone(),
                                     };
-                                    bytes.extend(bincode::serialize(&header)?);
-                                    for part in &snapshots {
-                                        bytes.extend(bincode::serialize(part)?);
+                                    // entities from all the workers end up in
+                                    // a single part, same as with local sims
+                                    let mut part = outcome::snapshot::SnapshotPart {
+                                        entities: FnvHashMap::default(),
+                                    };
+                                    for snapshot in snapshots {
+                                        part.entities.extend(snapshot.entities);
                                     }
+                                    let mut bytes = bincode::serialize(&header)?;
+                                    bytes.extend(bincode::serialize(&part)?);
 
                                     if req.save_to_disk {
-                                        let project_path = outcome::util::find_project_root(
-                                            organ.central.model.scenario.path.clone(),
-                                            3,
+                                        export::save_snapshot_bytes(
+                                            &organ.central.model,
+                                            &req.name,
+                                            &bytes,
                                         )?;
-                                        let snapshot_path = project_path
-                                            .join(outcome::SNAPSHOTS_DIR_NAME)
-                                            .join(req.name.clone());
-                                        let mut file = File::create(snapshot_path)?;
-                                        file.write_all(&bytes);
                                     }
                                     if req.send_back {
-                                        let payload = ExportSnapshotResponse {
-                                            error: "".to_string(),
-                                            snapshot: bytes,
-                                        };
-                                        client.connection.send_payload(payload, None);
+                                        client.start_snapshot_download(
+                                            bytes,
+                                            req,
+                                            snapshot_chunk_size,
+                                        )?;
                                     }
                                 }
                             }
@@ -1425,3 +2816,127 @@ impl Server {
         Ok(())
     }
 }
+
+/// Creates a read-write client with an unconnected socket, for testing
+/// handling of client state.
+#[cfg(test)]
+pub(crate) fn test_client(id: ClientId) -> Client {
+    Client {
+        id,
+        addr: String::new(),
+        connection: Socket::new(None, Transport::Tcp).unwrap(),
+        is_blocking: false,
+        is_observer: false,
+        msg_window: (Instant::now(), 0),
+        furthest_step: 0,
+        keepalive: None,
+        last_event: Instant::now(),
+        last_busy_heartbeat: Instant::now(),
+        auth_pair: None,
+        permission: Permission::ReadWrite,
+        name: String::new(),
+        session_token: session::new_token(),
+        scheduled_transfers: Default::default(),
+        scheduled_queries: Default::default(),
+        scheduled_advance_response: None,
+        scheduled_events: Vec::new(),
+        order_store: Default::default(),
+        order_id_pool: IdPool::new(),
+        last_order: None,
+        order_deltas: Default::default(),
+        last_diff_clock: None,
+        pushes: VecDeque::new(),
+        pushes_sent: 0,
+        pushes_dropped: 0,
+        selections: HashMap::new(),
+        subscriptions: Default::default(),
+        subscription_id_pool: IdPool::new(),
+        float_precision: FloatPrecision::Native,
+        snapshot_upload: Vec::new(),
+        snapshot_download: None,
+    }
+}
+
+#[test]
+fn despawned_entities_are_removed_from_id_filters() {
+    let mut query = outcome::Query {
+        trigger: outcome::query::Trigger::Immediate,
+        description: outcome::query::Description::Addressed,
+        layout: outcome::query::Layout::Var,
+        filters: vec![outcome::query::Filter::Id(vec![1, 2])],
+        mappings: vec![outcome::query::Map::All],
+    };
+    forget_entity_in_query(&mut query, 1);
+    assert_eq!(query.filters, vec![outcome::query::Filter::Id(vec![2])]);
+}
+
+#[test]
+fn in_flight_operations_count_queued_messages_and_tasks() {
+    let sim = outcome::SimModelBuilder::new().build_sim().unwrap();
+    let mut server = Server::new("tcp://127.0.0.1:0", SimConnection::Local(sim)).unwrap();
+    for client_id in 1..=3 {
+        server.clients.insert(client_id, test_client(client_id));
+    }
+    let msg = |type_| Message {
+        task_id: 0,
+        trace_id: Default::default(),
+        type_,
+        payload: vec![],
+    };
+    server.lanes.push(1, msg(MessageType::DataTransferRequest));
+    server.lanes.push(1, msg(MessageType::TurnAdvanceRequest));
+    server
+        .tasks
+        .insert(7, ServerTask::WaitForCoordQueryResponse(2, 0));
+
+    let in_flight = server.in_flight_operations();
+    assert_eq!(in_flight.get(&1), Some(&2));
+    assert_eq!(in_flight.get(&2), Some(&1));
+    assert_eq!(in_flight.get(&3), None);
+}
+
+#[test]
+fn modify_entity_request_attaches_and_detaches_components() {
+    use crate::harness::{TestCluster, TestServer};
+
+    let model = outcome::SimModelBuilder::new()
+        .component("pos", |c| c.var("float:x", None))
+        .component("tag", |c| c.var("int:n", outcome::Var::Int(3)))
+        .prefab("dot", &["pos"])
+        .spawn("dot", Some("a"))
+        .build()
+        .unwrap();
+
+    let server = TestServer::start(model.clone()).unwrap();
+    let mut client = server.client().unwrap();
+    let resp = client
+        .modify_entity("a".to_string(), vec!["tag".to_string()], vec![])
+        .unwrap();
+    assert_eq!(resp.code, None);
+    assert_eq!(resp.attached, vec!["tag".to_string()]);
+    assert_eq!(client.get_var("a:tag:int:n").unwrap(), outcome::Var::Int(3));
+    // detaching a component that's not attached fails, the rest of the
+    // request is still processed
+    let resp = client
+        .modify_entity(
+            "a".to_string(),
+            vec![],
+            vec!["missing".to_string(), "pos".to_string()],
+        )
+        .unwrap();
+    assert!(resp.code.is_some());
+    assert_eq!(resp.detached, vec!["pos".to_string()]);
+    assert!(client.get_var("a:pos:float:x").is_err());
+    client.disconnect().unwrap();
+    server.shutdown().unwrap();
+
+    let cluster = TestCluster::start(model, 1).unwrap();
+    let mut client = cluster.client().unwrap();
+    let resp = client
+        .modify_entity("a".to_string(), vec!["tag".to_string()], vec![])
+        .unwrap();
+    assert_eq!(resp.code, Some(ErrorCode::Unsupported));
+    assert!(resp.attached.is_empty());
+    client.disconnect().unwrap();
+    cluster.shutdown().unwrap();
+}
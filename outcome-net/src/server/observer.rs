@@ -0,0 +1,384 @@
+//! Handling of observer clients.
+//!
+//! Observers are clients with read-only access, meant for attaching to
+//! running simulations without affecting them. They are never blocking and
+//! their messages are always queued in the bulk lane. Messages that would
+//! modify the simulation are rejected, the client is sent back an
+//! `ErrorResponse` instead.
+//!
+//! Data transfers requested by observers are served from a cache that is
+//! valid for a single simulation step, so that any number of observers
+//! polling for the same data results in at most one gather per step.
+//! With an organizer the gather is not awaited on the poll thread, the
+//! response is sent once all the workers have responded.
+//!
+//! Ordered and diff transfers depend on the order and the last diff stored
+//! for the client and as such are not cached. Servers can be configured to always use delta encoding
+//! for them.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use outcome::distr::{CentralCommunication, Signal};
+use outcome::query::{Description, Layout, Map, QueryProduct, Trigger};
+
+use crate::msg::{
+    DataTransferRequest, DataTransferResponse, ErrorCode, ErrorResponse, Message, MessageType,
+    ResponseError, TransferResponseData, VarSimDataPack,
+};
+use crate::organizer::OrganizerTask;
+use crate::server::{ClientId, Server, ServerTask, SimConnection};
+use crate::{trace, Error, Result, TaskId};
+
+/// Transfer type and selection identifying a cached transfer.
+type TransferKey = (String, Vec<String>);
+
+/// Responses to data transfers, retained for the duration of a single step.
+#[derive(Default)]
+pub(crate) struct ObserverCache {
+    /// Clock at which the responses were gathered
+    clock: usize,
+    /// Data at native precision, keyed by transfer type and selection
+    transfers: HashMap<TransferKey, TransferResponseData>,
+    /// Transfers requested at the current clock, along with one of the
+    /// requesting clients
+    requested: HashMap<TransferKey, ClientId>,
+    /// Transfers requested at the previous clock, used for warming up the
+    /// cache before observers ask for them again
+    previous: HashMap<TransferKey, ClientId>,
+}
+
+impl ObserverCache {
+    fn get(&self, clock: usize, dtr: &DataTransferRequest) -> Option<&TransferResponseData> {
+        if self.clock != clock {
+            return None;
+        }
+        self.transfers
+            .get(&(dtr.transfer_type.clone(), dtr.selection.clone()))
+    }
+
+    /// Stores the data gathered for the transfer at the given clock,
+    /// discarding data gathered at any other clock.
+    pub(crate) fn insert(
+        &mut self,
+        clock: usize,
+        dtr: &DataTransferRequest,
+        data: TransferResponseData,
+    ) {
+        self.advance(clock);
+        self.transfers
+            .insert((dtr.transfer_type.clone(), dtr.selection.clone()), data);
+    }
+
+    /// Notes that the client requested the transfer at the given clock.
+    fn record(&mut self, clock: usize, dtr: &DataTransferRequest, client_id: ClientId) {
+        self.advance(clock);
+        self.requested
+            .entry((dtr.transfer_type.clone(), dtr.selection.clone()))
+            .or_insert(client_id);
+    }
+
+    /// Returns the transfers requested at the previous clock that are not
+    /// yet cached at the given clock.
+    pub(crate) fn to_warm(&mut self, clock: usize) -> Vec<(ClientId, DataTransferRequest)> {
+        self.advance(clock);
+        self.previous
+            .iter()
+            .filter(|(key, _)| !self.transfers.contains_key(*key))
+            .map(|((transfer_type, selection), client_id)| {
+                let dtr = DataTransferRequest {
+                    transfer_type: transfer_type.clone(),
+                    selection: selection.clone(),
+                    precision: None,
+                    since_step: None,
+                    order_id: None,
+                };
+                (*client_id, dtr)
+            })
+            .collect()
+    }
+
+    /// Moves the cache to the given clock, discarding data gathered at any
+    /// other clock.
+    fn advance(&mut self, clock: usize) {
+        if self.clock != clock {
+            self.clock = clock;
+            self.transfers.clear();
+            self.previous = std::mem::take(&mut self.requested);
+        }
+    }
+}
+
+/// Collects the data gathered from the workers into a transfer response.
+pub(crate) fn transfer_data_from_products(products: Vec<QueryProduct>) -> TransferResponseData {
+    let mut vars = VarSimDataPack::default();
+    for product in products {
+        if let QueryProduct::NativeAddressedVar(map) = product {
+            for ((entity_id, comp_name, var_name), var) in map {
+                vars.vars.insert(
+                    (
+                        outcome::string::new_truncate(&entity_id.to_string()),
+                        comp_name,
+                        var_name,
+                    ),
+                    var,
+                );
+            }
+        }
+    }
+    TransferResponseData::Var(vars)
+}
+
+impl Server {
+    pub(crate) fn handle_observer_message(
+        &mut self,
+        msg: Message,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        // stepping the simulation or writing snapshots is never available to
+        // observers, even with no blocking clients around
+        let affects_sim = match msg.type_ {
+            MessageType::TurnAdvanceRequest | MessageType::ExportSnapshotRequest => true,
+            type_ => !type_.is_read_only(),
+        };
+        if affects_sim {
+            warn!(
+                "rejecting {:?} from observer client {}, observers have read-only access",
+                msg.type_, client_id
+            );
+            let client = self
+                .clients
+                .get(client_id)
+                .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+            let (error, code) = ResponseError::new(
+                ErrorCode::Unauthorized,
+                format!("{:?} is not available to observers", msg.type_),
+            )
+            .into_fields();
+            return client.connection.send_payload_with_task(
+                ErrorResponse {
+                    request: msg.type_,
+                    error,
+                    code,
+                },
+                msg.task_id,
+                None,
+            );
+        }
+        match msg.type_ {
+            MessageType::DataTransferRequest => {
+                self.handle_observer_data_transfer_request(msg, client_id)
+            }
+            _ => self.dispatch_message(msg, client_id),
+        }
+    }
+
+    fn handle_observer_data_transfer_request(
+        &mut self,
+        msg: Message,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let mut dtr: DataTransferRequest = msg.unpack_payload(client.connection.encoding())?;
+
+        let data = match dtr.transfer_type.as_str() {
+            "SelectVarOrdered" | "SelectVarOrderedDelta" => {
+                if self.config.observer_delta_transfers {
+                    dtr.transfer_type = "SelectVarOrderedDelta".to_string();
+                }
+                self.data_transfer_response(&dtr, client_id)?.data
+            }
+            "Diff" => self.data_transfer_response(&dtr, client_id)?.data,
+            _ => {
+                let clock = self.current_clock();
+                self.observer_cache.record(clock, &dtr, *client_id);
+                match self.observer_cache.get(clock, &dtr) {
+                    Some(data) => data.clone(),
+                    None => {
+                        if let SimConnection::UnionOrganizer(_) = &self.sim {
+                            if dtr.transfer_type == "Full" {
+                                return self.gather_observer_transfer(dtr, msg.task_id, client_id);
+                            }
+                        }
+                        let data = self.data_transfer_response(&dtr, client_id)?.data;
+                        self.observer_cache.insert(clock, &dtr, data.clone());
+                        data
+                    }
+                }
+            }
+        };
+
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        client.connection.send_payload_with_task(
+            DataTransferResponse {
+                data: data.with_precision(client.precision_for(&dtr)),
+                interpolation: self.interpolation.metadata(),
+            },
+            msg.task_id,
+            None,
+        )
+    }
+
+    /// Requests the data from all the workers without waiting for the
+    /// responses. The response is sent to the client, and the data is
+    /// cached, once all the workers have responded.
+    fn gather_observer_transfer(
+        &mut self,
+        dtr: DataTransferRequest,
+        request_task_id: TaskId,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        let coord = match &mut self.sim {
+            SimConnection::UnionOrganizer(coord) => coord,
+            _ => return Err(Error::Other("expected organizer".to_string())),
+        };
+        let query = outcome::Query {
+            trigger: Trigger::Immediate,
+            description: Description::NativeDescribed,
+            layout: Layout::Var,
+            filters: vec![],
+            mappings: vec![Map::All],
+        };
+        let task_id = coord.register_task(OrganizerTask::WaitForQueryResponses {
+            remaining: coord.net.workers.len() as u32,
+            products: vec![],
+            responded: vec![],
+            partial: false,
+        })?;
+        self.tasks.insert(
+            task_id,
+            ServerTask::WaitForObserverTransfer(*client_id, dtr, request_task_id, trace::current()),
+        );
+        coord
+            .net
+            .broadcast_sig(task_id, Signal::QueryRequest(query))?;
+        Ok(())
+    }
+
+    /// Checks whether the client is an observer that reached the configured
+    /// rate limit, starting a new rate limiting window if the current one
+    /// has passed.
+    pub(crate) fn observer_throttled(&mut self, client_id: &ClientId) -> bool {
+        let limit = match self.config.observer_rate_limit {
+            Some(l) => l,
+            None => return false,
+        };
+        let client = match self.clients.get_mut(client_id) {
+            Some(c) if c.is_observer => c,
+            _ => return false,
+        };
+        if client.msg_window.0.elapsed() >= Duration::from_secs(1) {
+            client.msg_window = (Instant::now(), 0);
+        }
+        client.msg_window.1 >= limit
+    }
+
+    fn current_clock(&self) -> usize {
+        match &self.sim {
+            SimConnection::Local(sim) => sim.get_clock(),
+            SimConnection::UnionOrganizer(organizer) => organizer.central.get_clock(),
+            SimConnection::UnionWorker(worker) => {
+                worker.sim_node.as_ref().map_or(0, |node| node.clock)
+            }
+            SimConnection::Idle => 0,
+        }
+    }
+}
+
+#[test]
+fn gathered_data_is_cached_for_a_single_step() {
+    use outcome::string::new_truncate;
+
+    let mut map = fnv::FnvHashMap::default();
+    map.insert(
+        (3, new_truncate("pos"), new_truncate("x")),
+        outcome::Var::Float(1.),
+    );
+    let data = transfer_data_from_products(vec![QueryProduct::NativeAddressedVar(map)]);
+    match &data {
+        TransferResponseData::Var(pack) => assert_eq!(
+            pack.vars
+                .get(&(new_truncate("3"), new_truncate("pos"), new_truncate("x"))),
+            Some(&outcome::Var::Float(1.))
+        ),
+        _ => panic!("expected var data"),
+    }
+
+    let dtr = DataTransferRequest {
+        transfer_type: "Full".to_string(),
+        selection: vec![],
+        precision: None,
+        since_step: None,
+        order_id: None,
+    };
+    let mut cache = ObserverCache::default();
+    cache.record(1, &dtr, 7);
+    cache.insert(1, &dtr, data);
+    assert!(cache.get(1, &dtr).is_some());
+    assert!(cache.get(2, &dtr).is_none());
+
+    // transfers requested during the previous step get warmed up once
+    let to_warm = cache.to_warm(2);
+    assert_eq!(to_warm.len(), 1);
+    assert_eq!(to_warm[0].0, 7);
+    assert_eq!(to_warm[0].1.transfer_type, "Full");
+    cache.insert(
+        2,
+        &dtr,
+        TransferResponseData::Var(VarSimDataPack::default()),
+    );
+    assert!(cache.to_warm(2).is_empty());
+}
+
+#[test]
+fn observer_turn_advance_does_not_move_clock() {
+    use crate::harness::TestServer;
+    use crate::msg::TurnAdvanceRequest;
+    use crate::server::ServerConfig;
+    use crate::ClientConfig;
+
+    let sim = outcome::SimModelBuilder::new()
+        .component("counter", |c| c.var("int:count", outcome::Var::Int(0)))
+        .prefab("thing", &["counter"])
+        .spawn("thing", Some("first"))
+        .build_sim()
+        .unwrap();
+    let server = TestServer::start_with_config(sim, ServerConfig::default()).unwrap();
+    let mut observer = server
+        .client_with_config(ClientConfig {
+            name: "observer".to_string(),
+            is_observer: true,
+            ..ClientConfig::default()
+        })
+        .unwrap();
+
+    // go around the client side check, the server has to enforce it too
+    observer
+        .connection
+        .send_payload_with_task(
+            TurnAdvanceRequest {
+                step_count: 3,
+                wait: false,
+            },
+            1,
+            None,
+        )
+        .unwrap();
+    let (_, msg) = observer.recv_msg().unwrap();
+    assert_eq!(msg.type_, MessageType::ErrorResponse);
+    let resp: ErrorResponse = msg
+        .unpack_payload(observer.connection.encoding())
+        .unwrap();
+    assert_eq!(resp.request, MessageType::TurnAdvanceRequest);
+    assert_eq!(resp.code, Some(ErrorCode::Unauthorized));
+
+    assert_eq!(observer.server_status().unwrap().current_tick, 0);
+    observer.disconnect().unwrap();
+    server.shutdown().unwrap();
+}
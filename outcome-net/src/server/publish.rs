@@ -0,0 +1,120 @@
+//! Publishing broadcast data to many subscribers at once.
+//!
+//! Server can be configured with a publishing socket, separate from the
+//! client connections. Each time a step is completed the server publishes
+//! a notice on it, followed by the current values of any var streams
+//! defined in the config. Payloads are encoded once and fanned out to all
+//! subscribers, instead of being sent separately to each client.
+//!
+//! Subscribers connect to the publishing socket using the address returned
+//! at client registration, see [`Client::subscribe`].
+//!
+//! [`Client::subscribe`]: crate::Client::subscribe
+
+use std::collections::HashMap;
+
+use fnv::FnvHashMap;
+use outcome::{Address, Sim};
+use serde::Serialize;
+
+use crate::msg::{msg_bytes_from_payload, Payload, StepCompletedNotice, VarStreamNotice};
+use crate::socket::{
+    CompositeSocketAddress, Encoding, Socket, SocketConfig, SocketType, Transport,
+};
+use crate::Result;
+
+/// Socket used for publishing broadcast data.
+pub(crate) struct Publisher {
+    socket: Socket,
+}
+
+impl Publisher {
+    /// Binds a new publishing socket. Transport and encoding default to
+    /// plain tcp with bincode if not specified in the address.
+    pub fn bind(address: &str) -> Result<Self> {
+        let composite: CompositeSocketAddress = address.parse()?;
+        let config = SocketConfig {
+            type_: SocketType::Pub,
+            encoding: composite.encoding.unwrap_or(Encoding::Bincode),
+            ..Default::default()
+        };
+        let socket = Socket::new_with_config(
+            Some(composite.address),
+            composite.transport.unwrap_or(Transport::Tcp),
+            config,
+        )?;
+        info!(
+            "starting publisher on: {}",
+            socket.listener_addr_composite()?
+        );
+        Ok(Self { socket })
+    }
+
+    /// Composite address subscribers can connect to.
+    pub fn address(&self) -> Result<String> {
+        Ok(self.socket.listener_addr_composite()?.to_string())
+    }
+
+    /// Handles incoming events, keeping track of connecting and
+    /// disconnecting subscribers. Anything sent by subscribers is ignored.
+    pub fn poll(&mut self) {
+        while let Ok(_) = self.socket.try_recv() {}
+    }
+
+    pub fn publish<P: Payload + Serialize>(&self, payload: P) -> Result<()> {
+        let bytes = msg_bytes_from_payload(payload, 0, self.socket.encoding())?;
+        self.socket.publish_bytes(bytes)
+    }
+
+    /// Publishes the step completed notice, along with the var streams if
+    /// the simulation is available locally.
+    pub fn publish_step(
+        &self,
+        clock: usize,
+        streams: &HashMap<String, Vec<Address>>,
+        sim: Option<&Sim>,
+    ) -> Result<()> {
+        self.publish(StepCompletedNotice { clock })?;
+        let sim = match sim {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        for (stream, addresses) in streams {
+            let mut vars = FnvHashMap::default();
+            for address in addresses {
+                if let Ok(var) = sim.get_var(address) {
+                    vars.insert(address.clone(), var.clone());
+                }
+            }
+            self.publish(VarStreamNotice {
+                stream: stream.clone(),
+                clock,
+                vars,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn publishing_continues_past_dropped_subscribers() {
+    use crate::subscriber::{Published, Subscriber};
+
+    let mut publisher = Publisher::bind("127.0.0.1:0").unwrap();
+    let address = publisher.address().unwrap();
+    let dropped = Subscriber::connect(&address).unwrap();
+    let mut subscriber = Subscriber::connect(&address).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    publisher.poll();
+    drop(dropped);
+
+    for clock in 0..3 {
+        publisher.publish(StepCompletedNotice { clock }).unwrap();
+    }
+    for clock in 0..3 {
+        match subscriber.recv().unwrap() {
+            Published::StepCompleted(notice) => assert_eq!(notice.clock, clock),
+            published => panic!("unexpected published data: {:?}", published),
+        }
+    }
+}
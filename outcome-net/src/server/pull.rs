@@ -1,16 +1,18 @@
 use fnv::FnvHashMap;
 
 use crate::msg::{
-    DataPullRequest, DataPullResponse, JsonPullRequest, Message, PullRequestData,
-    TypedDataPullRequest,
+    DataPullRequest, DataPullResponse, ErrorCode, JsonPullRequest, Message, PullItemError,
+    PullRequestData, ResponseError, TypedDataPullRequest,
 };
-use crate::server::ClientId;
+use crate::server::audit::{AuditAction, AuditLog};
+use crate::server::{replay, ClientId};
 use crate::socket::{pack, unpack};
 use crate::{Error, Result};
 use crate::{Server, SimConnection};
 
 use outcome::distr::{CentralCommunication, Signal};
-use outcome::Address;
+use outcome::sim::replay::Mutation;
+use outcome::{Address, Sim, SimInterface, Var};
 use std::str::FromStr;
 
 impl Server {
@@ -23,10 +25,21 @@ impl Server {
         println!("json pull: {:?}", req);
 
         if let SimConnection::Local(sim) = &mut self.sim {
+            let (mut pulled, mut rejected) = (0, Vec::new());
             for (address, var) in req.data {
-                if let Ok(v) = sim.get_var_mut(&address) {
-                    *v = var.into();
-                }
+                pull_var_local(
+                    sim,
+                    &address,
+                    var.into(),
+                    None,
+                    &mut pulled,
+                    &mut rejected,
+                    client_id,
+                    self.audit.as_mut(),
+                );
+            }
+            for item in rejected {
+                warn!("json pull: rejected {}: {}", item.address, item.error);
             }
         }
 
@@ -34,6 +47,18 @@ impl Server {
     }
 
     pub fn handle_data_pull_request(&mut self, msg: Message, client_id: &ClientId) -> Result<()> {
+        let encoding = self
+            .clients
+            .get(client_id)
+            .unwrap()
+            .connection
+            .encoding()
+            .clone();
+        let dpr: DataPullRequest = msg.unpack_payload(&encoding)?;
+        if self.replay_idempotent_response(&dpr.idempotency_key, msg.task_id, client_id)? {
+            return Ok(());
+        }
+
         let mut client = self.clients.get_mut(client_id).unwrap();
 
         let mut map = FnvHashMap::default();
@@ -52,18 +77,21 @@ impl Server {
 
         let mock = DataPullRequest {
             data: PullRequestData::AddressedVars(map),
+            idempotency_key: None,
+            units: Default::default(),
         };
         let mock_msg = pack(mock, client.connection.encoding())?;
         // println!("mock: {:?}", mock_msg);
 
+        let mut pulled = 0;
+        let mut rejected = Vec::new();
+        let mut error = ResponseError::default();
         {
             let use_compression = self.config.use_compression.clone();
             // let sim_model = server.sim_model.clone();
+            let units = dpr.units;
             match &mut self.sim {
                 SimConnection::Local(sim) => {
-                    //TODO
-                    let dpr: DataPullRequest = msg.unpack_payload(client.connection.encoding())?;
-                    // println!("dpr: {:?}", dpr);
                     match dpr.data {
                         PullRequestData::Typed(data) => {
                             // //TODO handle errors
@@ -95,78 +123,172 @@ impl Server {
                         }
                         PullRequestData::NativeAddressedVars(data) => {
                             for ((ent, comp, var_name), v) in data.vars {
-                                // let addr = Address::from_str(&k)?;
-                                let ent_id = ent.parse::<outcome::EntityId>()?;
-                                if let Some(entity) = sim.entities.get_mut(&ent_id) {
-                                    if let Some(var) = entity.storage.map.get_mut(&(comp, var_name))
-                                    {
-                                        *var = v;
-                                    }
-                                }
-                                // sim_instance * sim_instance.get_var_mut(&addr)? = v;
+                                let addr = Address {
+                                    entity: ent,
+                                    component: comp,
+                                    var_type: v.get_type(),
+                                    var_name,
+                                };
+                                pull_var_local(
+                                    sim,
+                                    &addr,
+                                    v,
+                                    units.get(&addr),
+                                    &mut pulled,
+                                    &mut rejected,
+                                    client_id,
+                                    self.audit.as_mut(),
+                                );
                             }
                         }
                         PullRequestData::VarOrdered(order_idx, data) => {
-                            if let Some(order) = client.order_store.get(&order_idx) {
-                                if data.vars.len() != order.len() {
-                                    warn!("PullRequestData::VarOrdered: var list length doesn't match ({} vs {})", data.vars.len(), order.len());
-                                    panic!();
+                            match client.order_store.get(&order_idx) {
+                                Some(order) if data.vars.len() != order.len() => {
+                                    error = ResponseError::new(
+                                        ErrorCode::InvalidRequest,
+                                        format!(
+                                            "var list length doesn't match ({} vs {})",
+                                            data.vars.len(),
+                                            order.len()
+                                        ),
+                                    );
+                                }
+                                Some(order) => {
+                                    for (n, addr) in order.iter().enumerate() {
+                                        pull_var_local(
+                                            sim,
+                                            addr,
+                                            data.vars[n].clone(),
+                                            units.get(addr),
+                                            &mut pulled,
+                                            &mut rejected,
+                                            client_id,
+                                            self.audit.as_mut(),
+                                        );
+                                    }
                                 }
-                                for (n, addr) in order.iter().enumerate() {
-                                    *sim.get_var_mut(addr)? = data.vars[n].clone();
+                                None => {
+                                    error = ResponseError::new(
+                                        ErrorCode::NotFound,
+                                        format!("unknown var order: {}", order_idx),
+                                    )
                                 }
                             }
                         }
                         PullRequestData::NativeAddressedVar((ent_id, comp_name, var_name), var) => {
-                            if let Some(entity) = sim.entities.get_mut(&ent_id) {
-                                if let Ok(v) = entity.storage.get_var_mut(&(comp_name, var_name)) {
-                                    *v = var;
-                                    // println!("pulled in {:?}", v);
-                                }
-                            }
+                            let addr = Address {
+                                entity: outcome::string::new_truncate(&ent_id.to_string()),
+                                component: comp_name,
+                                var_type: var.get_type(),
+                                var_name,
+                            };
+                            pull_var_local(
+                                sim,
+                                &addr,
+                                var,
+                                units.get(&addr),
+                                &mut pulled,
+                                &mut rejected,
+                                client_id,
+                                self.audit.as_mut(),
+                            );
                         }
                         PullRequestData::AddressedVars(data) => {
                             for (address, var) in data {
-                                if let Ok(v) = sim.get_var_mut(&address) {
-                                    *v = var;
-                                }
+                                pull_var_local(
+                                    sim,
+                                    &address,
+                                    var,
+                                    units.get(&address),
+                                    &mut pulled,
+                                    &mut rejected,
+                                    client_id,
+                                    self.audit.as_mut(),
+                                );
                             }
                         }
                     }
                 }
                 SimConnection::UnionOrganizer(coord) => {
-                    // TODO
+                    let data: Vec<(Address, Var)> = match dpr.data {
+                        PullRequestData::AddressedVars(data) => data.into_iter().collect(),
+                        PullRequestData::NativeAddressedVars(data) => data
+                            .vars
+                            .into_iter()
+                            .map(|((entity, component, var_name), var)| {
+                                let addr = Address {
+                                    entity,
+                                    component,
+                                    var_type: var.get_type(),
+                                    var_name,
+                                };
+                                (addr, var)
+                            })
+                            .collect(),
+                        _ => {
+                            error = ResponseError::unsupported(
+                                "only addressed vars can be pulled on an organizer",
+                            );
+                            Vec::new()
+                        }
+                    };
+                    // wildcards are expanded by each of the workers against
+                    // the entities they store, only the number of forwarded
+                    // vars is known here
+                    if !data.is_empty() {
+                        pulled = data.len() as u32;
+                        coord.net.broadcast_sig(0, Signal::DataPullRequest(data))?;
+                    }
                 }
-                SimConnection::UnionWorker(worker) => {
-                    //TODO
-                    let dpr: DataPullRequest = msg.unpack_payload(client.connection.encoding())?;
-                    match dpr.data {
-                        PullRequestData::NativeAddressedVars(data) => {
-                            for ((ent, comp, var), v) in data.vars {
-                                // let addr = Address::from_str(&k)?;
-                                if let Some(sim_node) = worker.sim_node.as_mut() {
-                                    if let Some(ent) =
-                                        sim_node.entities.get_mut(&ent.parse().unwrap())
-                                    {
-                                        if let Some(var) = ent.storage.map.get_mut(&(comp, var)) {
-                                            *var = v;
-                                        }
+                SimConnection::UnionWorker(worker) => match dpr.data {
+                    PullRequestData::NativeAddressedVars(data) => {
+                        for ((ent, comp, var_name), v) in data.vars {
+                            let addr = Address {
+                                entity: ent,
+                                component: comp,
+                                var_type: v.get_type(),
+                                var_name,
+                            };
+                            let expanded = match &worker.sim_node {
+                                Some(node) => addr.expand_node(node),
+                                None => vec![addr.clone()],
+                            };
+                            if expanded.is_empty() {
+                                rejected.push(PullItemError::new(
+                                    addr.to_string(),
+                                    ResponseError::new(
+                                        ErrorCode::NotFound,
+                                        "no vars matching the address",
+                                    ),
+                                ));
+                            }
+                            for addr in expanded {
+                                match worker.set_var(&addr, v.clone()) {
+                                    Ok(()) => pulled += 1,
+                                    Err(e) => {
+                                        rejected.push(PullItemError::new(addr.to_string(), e))
                                     }
                                 }
-
-                                // sim_instance * sim_instance.get_var_mut(&addr)? = v;
                             }
                         }
-                        _ => unimplemented!(),
                     }
-                }
+                    _ => {
+                        error = ResponseError::unsupported(
+                            "only native addressed vars can be pulled on a worker",
+                        )
+                    }
+                },
+                SimConnection::Idle => return Err(Error::SimNotStarted),
             };
         }
+        let (error, code) = error.into_fields();
         let resp = DataPullResponse {
-            error: String::new(),
+            error,
+            code,
+            pulled,
+            rejected,
         };
-        // send_message(message_from_payload(resp, false), stream, None);
-        client.connection.send_payload(resp, None)
+        self.send_idempotent_response(resp, dpr.idempotency_key, msg.task_id, client_id)
     }
 
     pub fn handle_typed_data_pull_request(
@@ -213,9 +335,14 @@ impl Server {
 
                 let resp = DataPullResponse {
                     error: String::new(),
+                    code: None,
+                    pulled: 0,
+                    rejected: Vec::new(),
                 };
                 // send_message(message_from_payload(resp, false), stream, None);
-                client.connection.send_payload(resp, None)?;
+                client
+                    .connection
+                    .send_payload_with_task(resp, msg.task_id, None)?;
             }
             SimConnection::UnionOrganizer(coord) => {
                 let mut data_vec = Vec::new();
@@ -227,8 +354,141 @@ impl Server {
                     .broadcast_sig(22, Signal::DataPullRequest(data_vec))?;
             }
             SimConnection::UnionWorker(worker) => unimplemented!(),
+            SimConnection::Idle => return Err(Error::SimNotStarted),
         };
 
         Ok(())
     }
 }
+
+/// Validates a single var against the model and writes it into the sim.
+/// Vars failing validation are added to the rejected list. Applied writes
+/// are recorded in the audit log, if one is provided.
+///
+/// If the unit of the provided value is known, the value is first
+/// converted into the unit declared for the var.
+///
+/// Wildcard addresses are expanded, with the value written to each of the
+/// matching vars, see [`Address::expand`].
+fn pull_var_local(
+    sim: &mut Sim,
+    addr: &Address,
+    var: Var,
+    unit: Option<&String>,
+    pulled: &mut u32,
+    rejected: &mut Vec<PullItemError>,
+    client_id: &ClientId,
+    mut audit: Option<&mut AuditLog>,
+) {
+    if !addr.is_wildcard() {
+        return set_var_local(sim, addr, var, unit, pulled, rejected, client_id, audit);
+    }
+    let expanded = addr.expand(sim);
+    if expanded.is_empty() {
+        rejected.push(PullItemError::new(
+            addr.to_string(),
+            ResponseError::new(ErrorCode::NotFound, "no vars matching the address"),
+        ));
+    }
+    for addr in expanded {
+        set_var_local(
+            sim,
+            &addr,
+            var.clone(),
+            unit,
+            pulled,
+            rejected,
+            client_id,
+            audit.as_mut().map(|a| &mut **a),
+        );
+    }
+}
+
+/// Overwrites a single var, expanded addresses never contain wildcards.
+fn set_var_local(
+    sim: &mut Sim,
+    addr: &Address,
+    var: Var,
+    unit: Option<&String>,
+    pulled: &mut u32,
+    rejected: &mut Vec<PullItemError>,
+    client_id: &ClientId,
+    audit: Option<&mut AuditLog>,
+) {
+    let var = match unit.map(|u| sim.convert_unit(addr, var.clone(), u)) {
+        Some(Ok(converted)) => converted,
+        Some(Err(e)) => {
+            rejected.push(PullItemError::new(addr.to_string(), e));
+            return;
+        }
+        None => var,
+    };
+    if let Err(e) = sim.validate_var(addr, &var) {
+        rejected.push(PullItemError::new(addr.to_string(), e));
+        return;
+    }
+    let clock = sim.get_clock();
+    let logged = sim.replay_log.as_ref().map(|_| var.clone());
+    if let Ok(v) = sim.get_var_mut(addr) {
+        let before = std::mem::replace(v, var);
+        *pulled += 1;
+        if let Some(audit) = audit {
+            audit.record(
+                client_id,
+                clock,
+                AuditAction::Pull {
+                    address: addr,
+                    before: &before,
+                    after: v,
+                },
+            );
+        }
+        if let Some(var) = logged {
+            replay::log_mutation(sim, Mutation::SetVar(addr.clone(), var));
+        }
+    }
+}
+
+#[test]
+fn wildcard_pulls_report_unmatched_addresses() {
+    let mut sim = outcome::SimModelBuilder::new()
+        .component("pos", |c| c.var("float:x", None))
+        .prefab("dot", &["pos"])
+        .build_sim()
+        .unwrap();
+    sim.spawn_entity(
+        Some(&outcome::string::new_truncate("dot")),
+        Some(outcome::string::new_truncate("*")),
+    )
+    .unwrap();
+    let (mut pulled, mut rejected) = (0, Vec::new());
+
+    let addr = Address::from_str("*:pos:float:x").unwrap();
+    pull_var_local(
+        &mut sim,
+        &addr,
+        Var::Float(1.),
+        None,
+        &mut pulled,
+        &mut rejected,
+        &0,
+        None,
+    );
+    assert_eq!(pulled, 1);
+    assert!(rejected.is_empty());
+
+    let addr = Address::from_str("*:pos:float:y").unwrap();
+    pull_var_local(
+        &mut sim,
+        &addr,
+        Var::Float(1.),
+        None,
+        &mut pulled,
+        &mut rejected,
+        &0,
+        None,
+    );
+    assert_eq!(pulled, 1);
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].code, Some(ErrorCode::NotFound));
+}
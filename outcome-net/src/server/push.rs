@@ -0,0 +1,145 @@
+//! Buffered fan-out of pushed data.
+//!
+//! Data pushed to clients without an explicit request, such as scheduled
+//! transfers and queries, is not sent right away. Instead it's put on the
+//! client's own outbound buffer, which is then drained at a limited rate on
+//! each poll. This way a single slow client can't stall pushing to others.
+//!
+//! Pushes are considered non-critical. When the buffer is full the oldest
+//! frame is dropped to make room for the new one, as it's expected to be
+//! superseded by the more recent data anyway. Dropped frames are counted
+//! for each client. Subscriptions that lose an update are resynced, see the
+//! [`subscription`] module. Likewise ordered transfers with delta encoding
+//! that lose a frame send the whole set with the next transfer.
+//!
+//! [`subscription`]: crate::server::subscription
+
+use serde::Serialize;
+
+use crate::msg::{
+    msg_bytes_from_payload, CompactData, DataTransferResponse, Message, MessageType, Payload,
+    SubscriptionUpdate, TransferResponseData,
+};
+use crate::server::{Client, Server};
+use crate::{Result, TaskId};
+
+impl Client {
+    /// Puts the payload on the client's outbound push buffer, dropping the
+    /// oldest frame if the buffer is full.
+    pub fn queue_push<P: Payload + Serialize>(
+        &mut self,
+        payload: P,
+        task_id: TaskId,
+        buffer_size: usize,
+    ) -> Result<()> {
+        let bytes = msg_bytes_from_payload(payload, task_id, self.connection.encoding())?;
+        while self.pushes.len() >= buffer_size.max(1) {
+            let dropped = match self.pushes.pop_front() {
+                Some(d) => d,
+                None => break,
+            };
+            self.pushes_dropped += 1;
+            let encoding = self.connection.encoding().clone();
+            if let Ok(msg) = Message::from_bytes(dropped, &encoding) {
+                if msg.type_ == MessageType::SubscriptionUpdate {
+                    if let Ok(update) = msg.unpack_payload::<SubscriptionUpdate>(&encoding) {
+                        self.subscription_update_dropped(update.subscription_id);
+                    }
+                } else if msg.type_ == MessageType::DataTransferResponse {
+                    if let Ok(response) = msg.unpack_payload::<DataTransferResponse>(&encoding) {
+                        self.ordered_transfer_dropped(response.data);
+                    }
+                }
+            }
+            debug!(
+                "[client: {}] push buffer full, dropped oldest frame (total dropped: {})",
+                self.id, self.pushes_dropped
+            );
+        }
+        self.pushes.push_back(bytes);
+        Ok(())
+    }
+
+    /// Makes the next transfer of the order a dropped frame belonged to
+    /// send the whole set, as the client is missing the base for any
+    /// further deltas.
+    fn ordered_transfer_dropped(&mut self, data: TransferResponseData) {
+        let order_id = match data {
+            TransferResponseData::VarOrdered(order_id, _)
+            | TransferResponseData::VarOrderedDelta(order_id, _)
+            | TransferResponseData::Compact(_, CompactData::VarOrdered(order_id, _)) => order_id,
+            _ => return,
+        };
+        if let Some(previous) = self.order_deltas.get_mut(&order_id) {
+            debug!(
+                "[client: {}] ordered transfer {} dropped, resyncing",
+                self.id, order_id
+            );
+            previous.vars.clear();
+        }
+    }
+
+    /// Sends up to `limit` buffered frames to the client. Returns the number
+    /// of frames sent.
+    pub fn flush_pushes(&mut self, limit: usize) -> Result<usize> {
+        let mut sent = 0;
+        while sent < limit {
+            let bytes = match self.pushes.pop_front() {
+                Some(b) => b,
+                None => break,
+            };
+            self.connection.send_bytes(bytes, None)?;
+            self.pushes_sent += 1;
+            sent += 1;
+        }
+        Ok(sent)
+    }
+}
+
+impl Server {
+    /// Drains the push buffers of all clients, sending at most the
+    /// configured number of frames to each client.
+    pub(crate) fn flush_pushes(&mut self) {
+        let limit = self.config.pushes_per_poll;
+        for (client_id, client) in &mut self.clients {
+            if client.pushes.is_empty() {
+                continue;
+            }
+            if let Err(e) = client.flush_pushes(limit) {
+                warn!("[client: {}] failed sending pushed data: {}", client_id, e);
+            }
+        }
+    }
+}
+
+#[test]
+fn full_push_buffer_drops_oldest_frames() {
+    use crate::msg::{VarOrderedDelta, VarSimDataPackOrdered};
+
+    let mut client = crate::server::test_client(0);
+    client.order_deltas.insert(
+        1,
+        VarSimDataPackOrdered {
+            vars: vec![outcome::Var::Int(1)],
+        },
+    );
+    let delta = DataTransferResponse {
+        data: TransferResponseData::VarOrderedDelta(
+            1,
+            VarOrderedDelta {
+                len: 1,
+                changed: vec![],
+            },
+        ),
+        interpolation: None,
+    };
+    client.queue_push(delta.clone(), 0, 2).unwrap();
+    client.queue_push(delta.clone(), 0, 2).unwrap();
+    assert_eq!(client.pushes_dropped, 0);
+
+    client.queue_push(delta, 0, 2).unwrap();
+    assert_eq!(client.pushes.len(), 2);
+    assert_eq!(client.pushes_dropped, 1);
+    // the next transfer of the order sends the whole set
+    assert!(client.order_deltas[&1].vars.is_empty());
+}
@@ -8,7 +8,7 @@ use crate::msg::{
 };
 use crate::organizer::OrganizerTask;
 use crate::server::{ClientId, ServerTask};
-use crate::{Error, Result};
+use crate::{trace, Error, Result};
 use crate::{Server, SimConnection};
 
 impl Server {
@@ -26,7 +26,7 @@ impl Server {
                     unimplemented!()
                 } else {
                     // let insta = std::time::Instant::now();
-                    let product = query.process(&sim.entities, &sim.entity_idx)?;
+                    let product = sim.query(&query)?;
                     // println!(
                     //     "processing query took: {} ms",
                     //     Instant::now().duration_since(insta).as_millis()
@@ -36,7 +36,9 @@ impl Server {
                     if let outcome::query::QueryProduct::AddressedVar(map) = product {
                         client.connection.send_payload_with_task(
                             DataTransferResponse {
-                                data: TransferResponseData::AddressedVar(map),
+                                data: TransferResponseData::AddressedVar(map)
+                                    .with_precision(client.float_precision),
+                                interpolation: None,
                             },
                             msg.task_id,
                             None,
@@ -60,9 +62,13 @@ impl Server {
                 let task_id = coord.register_task(OrganizerTask::WaitForQueryResponses {
                     remaining: coord.net.workers.len() as u32,
                     products: vec![],
+                    responded: vec![],
+                    partial: false,
                 })?;
-                self.tasks
-                    .insert(task_id, ServerTask::WaitForCoordQueryResponse(*client_id));
+                self.tasks.insert(
+                    task_id,
+                    ServerTask::WaitForCoordQueryResponse(*client_id, trace::current()),
+                );
                 coord
                     .net
                     .broadcast_sig(task_id, Signal::QueryRequest(query))?;
@@ -72,6 +78,7 @@ impl Server {
                 // // check if query wants local entities only
                 // if query.filters.contains(&outcome::query::Filter::Node(0)) {}
             }
+            SimConnection::Idle => return Err(Error::SimNotStarted),
         }
 
         Ok(())
@@ -87,12 +94,14 @@ impl Server {
 
         match &mut self.sim {
             SimConnection::Local(sim) => {
-                let product = qr.query.process(&sim.entities, &sim.entity_idx)?;
-                client.connection.send_payload(
+                let product = sim.query(&qr.query)?;
+                client.connection.send_payload_with_task(
                     NativeQueryResponse {
                         query_product: product,
                         error: None,
+                        code: None,
                     },
+                    msg.task_id,
                     None,
                 )?;
             }
@@ -103,16 +112,23 @@ impl Server {
             }
             SimConnection::UnionWorker(worker) => {
                 if let Some(node) = &worker.sim_node {
-                    let product = qr.query.process(&node.entities, &node.entities_idx)?;
-                    client.connection.send_payload(
+                    let product = qr.query.process_with(
+                        &node.entities,
+                        &node.entities_idx,
+                        &node.query_plugins,
+                    )?;
+                    client.connection.send_payload_with_task(
                         NativeQueryResponse {
                             query_product: product,
                             error: None,
+                            code: None,
                         },
+                        msg.task_id,
                         None,
                     )?;
                 }
             }
+            SimConnection::Idle => return Err(Error::SimNotStarted),
         }
         Ok(())
     }
@@ -0,0 +1,121 @@
+//! Controlling the simulation recorder.
+//!
+//! Clients can have the server record selected vars to disk over time, see
+//! [`outcome::sim::recorder`]. Recordings are written to the recordings
+//! directory of the project the simulation was loaded from.
+//!
+//! Controlling the recorder requires the admin scope and is only supported
+//! with the local backend.
+
+use crate::msg::{
+    ErrorCode, ErrorFields, Message, RecorderAction, RecorderRequest, RecorderResponse,
+    ResponseError,
+};
+use crate::server::{ClientId, Permission};
+use crate::{Error, Result};
+use crate::{Server, SimConnection};
+
+impl Server {
+    pub fn handle_recorder_request(&mut self, msg: Message, client_id: &ClientId) -> Result<()> {
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: RecorderRequest = msg.unpack_payload(client.connection.encoding())?;
+
+        let mut resp = RecorderResponse {
+            error: String::new(),
+            code: None,
+            recording: false,
+        };
+        if client.permission < Permission::Admin {
+            resp.set_error(ResponseError::new(
+                ErrorCode::Unauthorized,
+                "controlling the recorder requires admin scope",
+            ));
+            return client.connection.send_payload(resp, None);
+        }
+
+        #[cfg(feature = "recorder")]
+        match &mut self.sim {
+            SimConnection::Local(sim) => {
+                let result = match req.action {
+                    RecorderAction::Start => {
+                        config_from_request(sim, &req).and_then(|c| sim.start_recording(c))
+                    }
+                    RecorderAction::Stop => sim.stop_recording(),
+                    RecorderAction::Flush => sim.flush_recording(),
+                };
+                if let Err(e) = result {
+                    resp.set_error(e);
+                }
+                resp.recording = sim.recorder.as_ref().map(|r| r.recording).unwrap_or(false);
+            }
+            _ => resp.set_error(ResponseError::unsupported(
+                "recorder only available with local backend",
+            )),
+        }
+        #[cfg(not(feature = "recorder"))]
+        {
+            let _ = (req, &self.sim);
+            resp.set_error(ResponseError::unsupported(
+                "server built without recorder support",
+            ));
+        }
+
+        client.connection.send_payload(resp, None)
+    }
+}
+
+/// Creates the recorder configuration, resolving the output path within
+/// the project's recordings directory.
+#[cfg(feature = "recorder")]
+fn config_from_request(
+    sim: &outcome::Sim,
+    req: &RecorderRequest,
+) -> outcome::Result<outcome::sim::recorder::RecorderConfig> {
+    use std::path::{Component, Path};
+    use std::str::FromStr;
+
+    use outcome::error::Error as CoreError;
+    use outcome::sim::recorder::{RecordFormat, RecorderConfig};
+    use outcome::Address;
+
+    let format = match req.format.as_str() {
+        "" => RecordFormat::default(),
+        f => RecordFormat::from_str(f)?,
+    };
+    let addrs = req
+        .addrs
+        .iter()
+        .map(|a| Address::from_str(a))
+        .collect::<outcome::Result<Vec<_>>>()?;
+    if addrs.is_empty() {
+        return Err(CoreError::Other("no addresses to record".to_string()));
+    }
+
+    let file_name = Path::new(&req.path);
+    let is_relative = file_name
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)));
+    if req.path.is_empty() || !is_relative {
+        return Err(CoreError::Other(format!(
+            "invalid recording path: \"{}\", expected path relative to the recordings directory",
+            req.path
+        )));
+    }
+    let path = outcome::util::find_project_root(sim.model.scenario.path.clone(), 3)?
+        .join(outcome::RECORDINGS_DIR_NAME)
+        .join(file_name);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    Ok(RecorderConfig {
+        addrs,
+        interval: (req.interval as usize).max(1),
+        format,
+        path,
+        ..RecorderConfig::default()
+    })
+}
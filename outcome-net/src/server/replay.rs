@@ -0,0 +1,24 @@
+//! Logging client-originated mutations for replay.
+//!
+//! With a replay log configured, the server logs every mutation applied to
+//! the local simulation on behalf of clients, along with all the processed
+//! steps, so that the run can later be reproduced exactly using
+//! [`outcome::Sim::replay_from`]. Logging starts whenever a new local
+//! simulation is started. Loading a snapshot uploaded by a client ends it.
+//!
+//! Only the local simulation keeps a replay log. Requests that apply to
+//! the organizer alone, such as changing the step trigger, are not logged.
+
+use outcome::sim::replay::Mutation;
+use outcome::Sim;
+
+/// Records the mutation in the sim's replay log, if logging is enabled.
+/// Failing to write the log doesn't fail the request.
+pub(crate) fn log_mutation(sim: &mut Sim, mutation: Mutation) {
+    if sim.replay_log.is_none() {
+        return;
+    }
+    if let Err(e) = sim.log_mutation(mutation) {
+        warn!("failed writing replay log: {}", e);
+    }
+}
@@ -0,0 +1,193 @@
+//! Restoring the simulation from snapshots uploaded by clients.
+//!
+//! Snapshots are uploaded in chunks, each acknowledged with a response
+//! carrying the number of bytes received so far. Chunks are buffered per
+//! client until the whole snapshot is received, at which point the current
+//! simulation is either replaced or forked, with the previous state saved
+//! to disk on the server.
+//!
+//! Loading uploaded snapshots requires the admin scope and is only
+//! supported with the local backend. Compressed snapshots are only
+//! decompressed if their declared size is within the upload size limit.
+
+use std::path::{Component, Path};
+
+use outcome::snapshot::Snap;
+use outcome::Sim;
+
+use crate::msg::{
+    ErrorCode, LoadSnapshotRequest, LoadSnapshotResponse, Message, ResponseError, SnapshotLoadMode,
+};
+use crate::server::{Client, ClientId, Permission};
+use crate::{Error, Result};
+use crate::{Server, SimConnection};
+
+impl Server {
+    pub fn handle_load_snapshot_request(
+        &mut self,
+        msg: Message,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        let max_len = self.config.max_snapshot_upload;
+        let client = self
+            .clients
+            .get_mut(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: LoadSnapshotRequest = msg.unpack_payload(client.connection.encoding())?;
+
+        let mut result = accept_chunk(client, &req, max_len);
+        let received = client.snapshot_upload.len() as u64;
+        let mut loaded = false;
+        if let Ok(true) = result {
+            let bytes = std::mem::take(&mut client.snapshot_upload);
+            result = self
+                .restore_snapshot(bytes, &req.mode, max_len)
+                .map(|_| true)
+                .map_err(ResponseError::from);
+            loaded = result.is_ok();
+        }
+
+        let client = self
+            .clients
+            .get_mut(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let (error, code) = match result {
+            Ok(_) => (String::new(), None),
+            Err(e) => {
+                warn!("client {} failed loading snapshot: {}", client_id, e);
+                client.snapshot_upload.clear();
+                e.into_fields()
+            }
+        };
+        client.connection.send_payload(
+            LoadSnapshotResponse {
+                received,
+                loaded,
+                error,
+                code,
+            },
+            None,
+        )
+    }
+
+    /// Replaces the current simulation with one restored from the given
+    /// snapshot bytes, which may or may not be compressed.
+    fn restore_snapshot(
+        &mut self,
+        mut bytes: Vec<u8>,
+        mode: &SnapshotLoadMode,
+        max_len: usize,
+    ) -> Result<()> {
+        let sim = match &mut self.sim {
+            SimConnection::Local(sim) => sim,
+            _ => {
+                return Err(Error::Other(
+                    "loading uploaded snapshots is only supported with the local backend"
+                        .to_string(),
+                ))
+            }
+        };
+
+        if let SnapshotLoadMode::Fork(name) = mode {
+            validate_snapshot_name(name)?;
+        }
+
+        #[cfg(feature = "lz4")]
+        {
+            // compressed blocks are prefixed with the uncompressed size
+            let declared_len = match bytes.get(..4) {
+                Some(prefix) => u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]),
+                None => 0,
+            };
+            if declared_len as usize <= max_len {
+                if let Ok(decompressed) = lz4::block::decompress(&bytes, None) {
+                    bytes = decompressed;
+                }
+            }
+        }
+        let new_sim = Sim::from_snapshot(&mut bytes)?;
+
+        if let SnapshotLoadMode::Fork(name) = mode {
+            sim.save_snapshot(name, false)?;
+        }
+        info!(
+            "restoring simulation from uploaded snapshot at clock {}",
+            new_sim.get_clock()
+        );
+        *sim = new_sim;
+
+        // work queued up for the previous state no longer applies
+        self.maintenance.retain(|task| !task.is_cancellable());
+        let clock = sim.get_clock();
+        for client in self.clients.values_mut() {
+            client.furthest_step = clock;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that the snapshot name is a plain file name, so that the
+/// snapshot can't be written outside the snapshots directory.
+pub(crate) fn validate_snapshot_name(name: &str) -> Result<()> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(()),
+        _ => Err(Error::Other(format!("invalid snapshot name: \"{}\"", name))),
+    }
+}
+
+/// Appends the uploaded chunk to the client's buffer, returning whether the
+/// snapshot is now complete.
+fn accept_chunk(
+    client: &mut Client,
+    req: &LoadSnapshotRequest,
+    max_len: usize,
+) -> std::result::Result<bool, ResponseError> {
+    if client.permission < Permission::Admin {
+        return Err(ResponseError::new(
+            ErrorCode::Unauthorized,
+            "loading a snapshot requires admin scope",
+        ));
+    }
+    if req.total_len > max_len as u64 {
+        return Err(ResponseError::new(
+            ErrorCode::ResourceExhausted,
+            format!(
+                "snapshot size of {} bytes exceeds the limit of {} bytes",
+                req.total_len, max_len
+            ),
+        ));
+    }
+    if req.offset == 0 {
+        client.snapshot_upload.clear();
+    }
+    if req.offset != client.snapshot_upload.len() as u64 {
+        return Err(ResponseError::new(
+            ErrorCode::InvalidRequest,
+            format!(
+                "expected chunk at offset {}, got {}",
+                client.snapshot_upload.len(),
+                req.offset
+            ),
+        ));
+    }
+    if req.offset + req.chunk.len() as u64 > req.total_len {
+        return Err(ResponseError::new(
+            ErrorCode::InvalidRequest,
+            "chunk exceeds the declared snapshot size",
+        ));
+    }
+    client.snapshot_upload.extend_from_slice(&req.chunk);
+    Ok(client.snapshot_upload.len() as u64 == req.total_len)
+}
+
+#[test]
+fn snapshot_names_stay_in_snapshots_dir() {
+    assert!(validate_snapshot_name("fork_1").is_ok());
+    assert!(validate_snapshot_name("").is_err());
+    assert!(validate_snapshot_name("..").is_err());
+    assert!(validate_snapshot_name("../escape").is_err());
+    assert!(validate_snapshot_name("nested/name").is_err());
+    assert!(validate_snapshot_name("/etc/passwd").is_err());
+}
@@ -0,0 +1,125 @@
+//! Retention of logic errors across steps.
+//!
+//! The simulation only keeps the errors recorded while processing the
+//! last step. The server collects them after each processed step, tagged
+//! with the clock at which they occurred, so that clients stepping the
+//! simulation multiple times in a row, or polling errors only once in a
+//! while, don't miss any.
+//!
+//! With a distributed backend the workers report their errors to the
+//! organizer at the end of each step, and the organizer's server collects
+//! them the same way. Workers themselves don't retain errors, requests
+//! made to them are answered with an `Unsupported` error code instead of
+//! an empty list.
+
+use std::collections::VecDeque;
+
+#[cfg(feature = "machine")]
+use outcome::machine::{Error as MachineError, ExecutionContext};
+use outcome::Sim;
+
+use crate::msg::RuntimeError;
+
+/// Maximum number of errors retained, oldest errors are dropped first.
+const RETAINED_ERRORS: usize = 1000;
+
+/// Logic errors collected from processed steps.
+#[derive(Default)]
+pub(crate) struct RuntimeErrorLog {
+    errors: VecDeque<RuntimeError>,
+}
+
+impl RuntimeErrorLog {
+    /// Collects the errors recorded by the simulation during the step
+    /// that began at the given clock, to be called right after the
+    /// simulation is stepped.
+    #[cfg(feature = "machine")]
+    pub fn record(&mut self, clock: usize, sim: &Sim) {
+        self.record_journal(clock, &sim.error_journal)
+    }
+
+    #[cfg(not(feature = "machine"))]
+    pub fn record(&mut self, _clock: usize, _sim: &Sim) {}
+
+    /// Collects errors from the given journal, e.g. the one gathered from
+    /// the workers by the organizer.
+    #[cfg(feature = "machine")]
+    pub fn record_journal(&mut self, clock: usize, journal: &[(ExecutionContext, MachineError)]) {
+        for (ctx, e) in journal {
+            if self.errors.len() >= RETAINED_ERRORS {
+                self.errors.pop_front();
+            }
+            self.errors.push_back(RuntimeError {
+                clock,
+                entity: ctx.ent,
+                component: ctx.comp.to_string(),
+                source: ctx
+                    .location
+                    .source
+                    .as_ref()
+                    .map(|s| s.to_string())
+                    .unwrap_or_default(),
+                line: ctx.location.source_line.unwrap_or(0),
+                message: e.to_string(),
+            });
+        }
+    }
+
+    /// Returns the retained errors that occurred at or after the given
+    /// clock.
+    pub fn since(&self, clock: usize) -> Vec<RuntimeError> {
+        self.errors
+            .iter()
+            .filter(|e| e.clock >= clock)
+            .cloned()
+            .collect()
+    }
+}
+
+#[test]
+fn retains_errors_from_multiple_steps() {
+    let mut log = RuntimeErrorLog::default();
+    for clock in 0..3 {
+        log.errors.push_back(RuntimeError {
+            clock,
+            entity: 0,
+            component: "comp".to_string(),
+            source: String::new(),
+            line: 0,
+            message: format!("error at {}", clock),
+        });
+    }
+    assert_eq!(log.since(0).len(), 3);
+    assert_eq!(log.since(2).len(), 1);
+    assert_eq!(log.since(3).len(), 0);
+}
+
+#[cfg(feature = "machine")]
+#[test]
+fn records_errors_from_the_sim_journal() {
+    use outcome::machine::{Error, ErrorKind, ExecutionContext, LocationInfo};
+
+    let mut sim = outcome::SimModelBuilder::new().build_sim().unwrap();
+    let location = LocationInfo {
+        source_line: Some(3),
+        ..Default::default()
+    };
+    sim.error_journal.push((
+        ExecutionContext {
+            ent: 1,
+            comp: outcome::string::new_truncate("comp"),
+            location: location.clone(),
+        },
+        Error::new(location, ErrorKind::CoreError("failed".to_string())),
+    ));
+
+    let mut log = RuntimeErrorLog::default();
+    log.record(4, &sim);
+    let errors = log.since(4);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].clock, 4);
+    assert_eq!(errors[0].entity, 1);
+    assert_eq!(errors[0].component, "comp");
+    assert_eq!(errors[0].line, 3);
+    assert!(log.since(5).is_empty());
+}
@@ -0,0 +1,293 @@
+//! Named entity selections and group operations.
+//!
+//! Clients can create named selections based on a query, and then issue
+//! operations against all of the selected entities using only the
+//! selection's name. Selections are stored per client.
+
+use std::convert::TryInto;
+
+use outcome::{EntityId, Sim};
+
+use crate::msg::{
+    CreateSelectionRequest, CreateSelectionResponse, Message, RefreshSelectionRequest,
+    RefreshSelectionResponse, SelectionOperation, SelectionOperationRequest,
+    SelectionOperationResponse,
+};
+use crate::msg::{ErrorCode, ErrorFields, ResponseError};
+use crate::server::audit::AuditAction;
+use crate::server::{ClientId, Server, SimConnection};
+use crate::{Error, Result};
+
+/// Named selection of entities as stored on the server.
+pub struct Selection {
+    /// Query used for selecting entities
+    pub query: outcome::Query,
+    /// Whether the selection is re-evaluated after each step
+    pub dynamic: bool,
+    /// Currently selected entities
+    pub entities: Vec<EntityId>,
+}
+
+impl Selection {
+    /// Re-evaluates the query against the current simulation state.
+    pub fn refresh(&mut self, sim: &Sim) {
+        self.entities = sim.select_entities(&self.query);
+    }
+}
+
+impl Server {
+    pub fn handle_create_selection_request(
+        &mut self,
+        msg: Message,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        let client = self
+            .clients
+            .get_mut(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: CreateSelectionRequest = msg.unpack_payload(client.connection.encoding())?;
+        let resp = match &self.sim {
+            SimConnection::Local(sim) => {
+                let mut selection = Selection {
+                    query: req.query.try_into()?,
+                    dynamic: req.dynamic,
+                    entities: Vec::new(),
+                };
+                selection.refresh(sim);
+                let count = selection.entities.len() as u32;
+                client.selections.insert(req.name, selection);
+                CreateSelectionResponse {
+                    count,
+                    error: String::new(),
+                    code: None,
+                }
+            }
+            _ => {
+                let (error, code) =
+                    ResponseError::unsupported("selections only available with local backend")
+                        .into_fields();
+                CreateSelectionResponse {
+                    count: 0,
+                    error,
+                    code,
+                }
+            }
+        };
+        client.connection.send_payload(resp, None)
+    }
+
+    pub fn handle_refresh_selection_request(
+        &mut self,
+        msg: Message,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        let client = self
+            .clients
+            .get_mut(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: RefreshSelectionRequest = msg.unpack_payload(client.connection.encoding())?;
+        let mut resp = RefreshSelectionResponse {
+            count: 0,
+            error: String::new(),
+            code: None,
+        };
+        match (&self.sim, client.selections.get_mut(&req.name)) {
+            (SimConnection::Local(sim), Some(selection)) => {
+                selection.refresh(sim);
+                resp.count = selection.entities.len() as u32;
+            }
+            (SimConnection::Local(_), None) => resp.set_error(ResponseError::new(
+                ErrorCode::NotFound,
+                format!("selection not found: {}", req.name),
+            )),
+            _ => resp.set_error(ResponseError::unsupported(
+                "selections only available with local backend",
+            )),
+        }
+        client.connection.send_payload(resp, None)
+    }
+
+    pub fn handle_selection_operation_request(
+        &mut self,
+        msg: Message,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        let client = self
+            .clients
+            .get_mut(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: SelectionOperationRequest = msg.unpack_payload(client.connection.encoding())?;
+        let mut resp = SelectionOperationResponse {
+            affected: 0,
+            error: String::new(),
+            code: None,
+        };
+        let sim = match &mut self.sim {
+            SimConnection::Local(sim) => sim,
+            _ => {
+                resp.set_error(ResponseError::unsupported(
+                    "selections only available with local backend",
+                ));
+                return client.connection.send_payload(resp, None);
+            }
+        };
+        let selection = match client.selections.get_mut(&req.name) {
+            Some(s) => s,
+            None => {
+                resp.set_error(ResponseError::new(
+                    ErrorCode::NotFound,
+                    format!("selection not found: {}", req.name),
+                ));
+                return client.connection.send_payload(resp, None);
+            }
+        };
+
+        let clock = sim.get_clock();
+        let audit_log = &mut self.audit;
+        for entity_id in &selection.entities {
+            let result = match &req.operation {
+                SelectionOperation::SetVar {
+                    component,
+                    var,
+                    value,
+                } => {
+                    let address = outcome::Address {
+                        entity: outcome::string::new_truncate(&entity_id.to_string()),
+                        component: outcome::string::new_truncate(component),
+                        var_type: value.get_type(),
+                        var_name: outcome::string::new_truncate(var),
+                    };
+                    match sim.validate_var(&address, value) {
+                        Ok(()) => sim.get_var_mut(&address).map(|v| {
+                            let before = std::mem::replace(v, value.clone());
+                            if let Some(audit) = audit_log.as_mut() {
+                                audit.record(
+                                    client_id,
+                                    clock,
+                                    AuditAction::Pull {
+                                        address: &address,
+                                        before: &before,
+                                        after: v,
+                                    },
+                                );
+                            }
+                        }),
+                        Err(e) => Err(e),
+                    }
+                }
+                #[cfg(feature = "machine")]
+                SelectionOperation::InvokeEvent(event) => sim
+                    .invoke_entity_event(entity_id, outcome::string::new_truncate(event))
+                    .map(|_| {
+                        if let Some(audit) = audit_log.as_mut() {
+                            audit.record(
+                                client_id,
+                                clock,
+                                AuditAction::Invoke {
+                                    event,
+                                    entity: Some(entity_id.to_string()),
+                                },
+                            );
+                        }
+                    }),
+                #[cfg(not(feature = "machine"))]
+                SelectionOperation::InvokeEvent(_) => Err(outcome::error::Error::Other(
+                    "invoking events requires the machine feature".to_string(),
+                )),
+                SelectionOperation::Despawn => sim.despawn_entity(entity_id).map(|_| {
+                    if let Some(audit) = audit_log.as_mut() {
+                        audit.record(
+                            client_id,
+                            clock,
+                            AuditAction::Despawn {
+                                entity: entity_id.to_string(),
+                            },
+                        );
+                    }
+                }),
+            };
+            match result {
+                Ok(()) => resp.affected += 1,
+                Err(e) => resp.set_first_error(e),
+            }
+        }
+        if let SelectionOperation::Despawn = req.operation {
+            selection.entities.clear();
+        }
+
+        client.connection.send_payload(resp, None)
+    }
+
+    /// Re-evaluates all dynamic selections. Called after each processed
+    /// step.
+    pub(crate) fn refresh_dynamic_selections(&mut self) {
+        if let SimConnection::Local(sim) = &self.sim {
+            for (_, client) in &mut self.clients {
+                for (_, selection) in &mut client.selections {
+                    if selection.dynamic {
+                        selection.refresh(sim);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn operations_apply_to_selected_entities_only() {
+    use crate::harness::TestServer;
+    use crate::msg::query::*;
+    use outcome::Var;
+
+    let model = outcome::SimModelBuilder::new()
+        .component("pos", |c| c.var("float:x", None))
+        .component("tag", |c| c)
+        .prefab("dot", &["pos"])
+        .prefab("tagged", &["pos", "tag"])
+        .spawn("dot", Some("first"))
+        .spawn("tagged", Some("second"))
+        .spawn("tagged", Some("third"))
+        .build()
+        .unwrap();
+    let server = TestServer::start(model).unwrap();
+    let mut client = server.client().unwrap();
+    let query = Query {
+        trigger: Trigger {
+            type_: TriggerType::Immediate,
+            args: vec![],
+        },
+        description: Description::None,
+        layout: Layout::Var,
+        filters: vec![Filter {
+            type_: FilterType::AllComponents,
+            args: vec!["tag".to_string()],
+        }],
+        mappings: vec![],
+    };
+    assert_eq!(
+        client
+            .create_selection("tagged", query, false)
+            .unwrap()
+            .count,
+        2
+    );
+
+    let set = SelectionOperation::SetVar {
+        component: "pos".to_string(),
+        var: "x".to_string(),
+        value: Var::Float(2.),
+    };
+    let resp = client.apply_to_selection("tagged", set.clone()).unwrap();
+    assert_eq!(resp.affected, 2);
+    assert_eq!(
+        client.get_var("second:pos:float:x").unwrap(),
+        Var::Float(2.)
+    );
+    assert_eq!(client.get_var("first:pos:float:x").unwrap(), Var::Float(0.));
+
+    let resp = client.apply_to_selection("missing", set).unwrap();
+    assert_eq!(resp.code, Some(ErrorCode::NotFound));
+
+    client.disconnect().unwrap();
+    server.shutdown().unwrap();
+}
@@ -0,0 +1,254 @@
+//! Resuming client sessions after reconnection.
+//!
+//! Each registered client is issued a session token. When a client is
+//! removed, for example after it's connection dropped and it stopped
+//! responding within the keepalive window, the parts of it's state that are
+//! costly or impossible to recreate on the client side are retained for
+//! the duration of the retention window.
+//!
+//! Client reconnecting with a retained token gets it's state back, including
+//! scheduled transfers and queries, blocking status, stored orders,
+//! selections, subscriptions and the events it scheduled. Subscriptions
+//! carry all the selected vars with the first update after resuming, as
+//! updates pushed before the connection dropped could have been lost. Token
+//! stays the same for the duration of the session.
+//!
+//! Pushes still buffered and snapshot transfers in progress are not
+//! retained.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+use id_pool::IdPool;
+use outcome::sim::compact::IdRemap;
+use outcome::{Address, EntityId, EntityName, EventName};
+
+use crate::msg::{DataTransferRequest, VarSimDataPackOrdered};
+use crate::server::maintenance::{
+    remap_orders, remap_queries, remap_selections, remap_subscriptions, remap_transfers,
+};
+use crate::server::{Client, Permission, Selection, Subscription};
+use crate::trace;
+use crate::TaskId;
+
+/// Client state retained after the client was removed.
+struct RetainedSession {
+    is_blocking: bool,
+    permission: Permission,
+    scheduled_transfers: FnvHashMap<EventName, Vec<DataTransferRequest>>,
+    scheduled_queries: FnvHashMap<EventName, Vec<(TaskId, outcome::Query)>>,
+    order_store: FnvHashMap<u32, Vec<Address>>,
+    order_id_pool: IdPool,
+    last_order: Option<u32>,
+    order_deltas: FnvHashMap<u32, VarSimDataPackOrdered>,
+    selections: HashMap<String, Selection>,
+    subscriptions: FnvHashMap<u32, Subscription>,
+    subscription_id_pool: IdPool,
+    scheduled_events: Vec<outcome::scheduler::ScheduledEvent>,
+}
+
+/// Generates a new session token.
+///
+/// Hasher keys are randomized for each `RandomState`, which makes tokens
+/// hard to guess without pulling in an additional dependency.
+pub(crate) fn new_token() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(trace::new_id());
+    let first = hasher.finish();
+    hasher.write_u64(first);
+    format!("{:016x}{:016x}", first, hasher.finish())
+}
+
+/// Sessions retained after their clients were removed, by session token.
+#[derive(Default)]
+pub(crate) struct Sessions {
+    retained: HashMap<String, (Instant, RetainedSession)>,
+}
+
+impl Sessions {
+    /// Retains the state of the removed client, if session retention is
+    /// enabled.
+    pub fn retain(&mut self, client: Client, window: Option<Duration>) {
+        let window = match window {
+            Some(w) => w,
+            None => return,
+        };
+        self.retained.retain(|_, (time, _)| time.elapsed() < window);
+        if client.session_token.is_empty() {
+            return;
+        }
+        debug!("retaining session of client {}", client.id);
+        self.retained.insert(
+            client.session_token,
+            (
+                Instant::now(),
+                RetainedSession {
+                    is_blocking: client.is_blocking,
+                    permission: client.permission,
+                    scheduled_transfers: client.scheduled_transfers,
+                    scheduled_queries: client.scheduled_queries,
+                    order_store: client.order_store,
+                    order_id_pool: client.order_id_pool,
+                    last_order: client.last_order,
+                    order_deltas: client.order_deltas,
+                    selections: client.selections,
+                    subscriptions: client.subscriptions,
+                    subscription_id_pool: client.subscription_id_pool,
+                    scheduled_events: client.scheduled_events,
+                },
+            ),
+        );
+    }
+
+    /// Restores retained state onto the newly registered client. Returns
+    /// whether a session was resumed.
+    ///
+    /// Permission level of the resumed session is never raised above the
+    /// one the client was just granted.
+    pub fn resume(&mut self, token: &str, client: &mut Client, window: Option<Duration>) -> bool {
+        let window = match window {
+            Some(w) => w,
+            None => return false,
+        };
+        let session = match self.retained.remove(token) {
+            Some((time, session)) if time.elapsed() < window => session,
+            _ => return false,
+        };
+        info!("client {} resumed it's previous session", client.id);
+        client.session_token = token.to_string();
+        client.is_blocking = session.is_blocking && !client.is_observer;
+        client.permission = client.permission.min(session.permission);
+        client.scheduled_transfers = session.scheduled_transfers;
+        client.scheduled_queries = session.scheduled_queries;
+        client.order_store = session.order_store;
+        client.order_id_pool = session.order_id_pool;
+        client.last_order = session.last_order;
+        client.order_deltas = session.order_deltas;
+        client.selections = session.selections;
+        client.subscriptions = session.subscriptions;
+        for subscription in client.subscriptions.values_mut() {
+            subscription.last.clear();
+            subscription.resync = true;
+        }
+        client.subscription_id_pool = session.subscription_id_pool;
+        client.scheduled_events = session.scheduled_events;
+        true
+    }
+
+    /// Updates entity ids held by retained sessions after entity
+    /// compaction.
+    pub fn remap_entities(
+        &mut self,
+        remap: &IdRemap,
+        entity_idx: &FnvHashMap<EntityName, EntityId>,
+    ) {
+        for (_, (_, session)) in &mut self.retained {
+            remap_orders(&mut session.order_store, remap, entity_idx);
+            remap_transfers(&mut session.scheduled_transfers, remap, entity_idx);
+            remap_queries(&mut session.scheduled_queries, remap, entity_idx);
+            remap_selections(&mut session.selections, remap);
+            remap_subscriptions(&mut session.subscriptions, remap, entity_idx);
+        }
+    }
+}
+
+/// Client with a selection, a subscription that already sent an update
+/// and a scheduled event.
+#[cfg(test)]
+fn client_with_state(id: crate::server::ClientId) -> Client {
+    let query = outcome::Query {
+        trigger: outcome::query::Trigger::Immediate,
+        description: outcome::query::Description::Addressed,
+        layout: outcome::query::Layout::Var,
+        filters: vec![outcome::query::Filter::Id(vec![1])],
+        mappings: vec![outcome::query::Map::All],
+    };
+    let mut client = crate::server::test_client(id);
+    client.is_blocking = true;
+    client.selections.insert(
+        "picked".to_string(),
+        Selection {
+            query: query.clone(),
+            dynamic: false,
+            entities: vec![1],
+        },
+    );
+    let mut subscription = Subscription::new(query);
+    subscription.resync = false;
+    subscription
+        .last
+        .insert("1:pos:float:x".parse().unwrap(), outcome::Var::Float(1.));
+    let sub_id = client.subscription_id_pool.request_id().unwrap();
+    client.subscriptions.insert(sub_id, subscription);
+    client
+        .scheduled_events
+        .push(outcome::scheduler::ScheduledEvent {
+            event: outcome::string::new_truncate("tick"),
+            at_step: 10,
+            every_n_steps: Some(5),
+        });
+    client
+}
+
+#[test]
+fn session_is_resumed_within_window() {
+    let window = Some(Duration::from_secs(60));
+    let mut sessions = Sessions::default();
+    let client = client_with_state(1);
+    let token = client.session_token.clone();
+    sessions.retain(client, window);
+
+    let mut resumed = crate::server::test_client(2);
+    assert!(sessions.resume(&token, &mut resumed, window));
+    assert_eq!(resumed.session_token, token);
+    assert!(resumed.is_blocking);
+    assert_eq!(resumed.selections["picked"].entities, vec![1]);
+    assert_eq!(resumed.subscriptions.len(), 1);
+    // updates pushed before the connection dropped could have been lost
+    let subscription = resumed.subscriptions.values().next().unwrap();
+    assert!(subscription.resync);
+    assert!(subscription.last.is_empty());
+    // new subscriptions don't clash with the retained ones
+    let next_id = resumed.subscription_id_pool.request_id().unwrap();
+    assert!(!resumed.subscriptions.contains_key(&next_id));
+    assert_eq!(resumed.scheduled_events.len(), 1);
+
+    // session can only be resumed once
+    let mut other = crate::server::test_client(3);
+    assert!(!sessions.resume(&token, &mut other, window));
+}
+
+#[test]
+fn expired_session_is_not_resumed() {
+    let window = Some(Duration::from_millis(1));
+    let mut sessions = Sessions::default();
+    let client = client_with_state(1);
+    let token = client.session_token.clone();
+    sessions.retain(client, window);
+    std::thread::sleep(Duration::from_millis(10));
+
+    let mut client = crate::server::test_client(2);
+    let new_token = client.session_token.clone();
+    assert!(!sessions.resume(&token, &mut client, window));
+    assert_eq!(client.session_token, new_token);
+    assert!(client.selections.is_empty());
+    assert!(client.subscriptions.is_empty());
+    assert!(client.scheduled_events.is_empty());
+}
+
+#[test]
+fn unknown_token_is_not_resumed() {
+    let window = Some(Duration::from_secs(60));
+    let mut sessions = Sessions::default();
+    sessions.retain(client_with_state(1), window);
+
+    let mut client = crate::server::test_client(2);
+    let new_token = client.session_token.clone();
+    assert!(!sessions.resume("unknown", &mut client, window));
+    assert_eq!(client.session_token, new_token);
+    assert!(!client.is_blocking);
+    assert!(client.selections.is_empty());
+}
@@ -0,0 +1,266 @@
+//! Streaming simulation output to external data pipelines.
+//!
+//! Server can be configured with a sink publishing the journal of events
+//! processed at each step, along with changes to selected vars, to Kafka
+//! or NATS. Records are encoded as json and sent in batches, once either
+//! the batch size or the batch interval is reached.
+//!
+//! Event records are sent to the events topic:
+//!
+//! ```json
+//! {"kind":"event","clock":12,"event":"step"}
+//! ```
+//!
+//! Var change records are sent to the vars topic, only when the value
+//! differs from the one sent previously:
+//!
+//! ```json
+//! {"kind":"var","clock":12,"address":"1:position:float:x","value":"0.5"}
+//! ```
+//!
+//! Only supported with the local backend. Requires either the `kafka_sink`
+//! or the `nats_sink` feature.
+
+use std::time::{Duration, Instant};
+
+use fnv::FnvHashMap;
+use outcome::{Address, EventName, Sim, Var};
+
+use crate::{Error, Result};
+
+/// Configuration of the event sink.
+#[derive(Debug, Clone)]
+pub struct SinkConfig {
+    pub backend: SinkBackend,
+    /// Topic, or subject with NATS, for the event records
+    pub events_topic: String,
+    /// Topic, or subject with NATS, for the var change records
+    pub vars_topic: String,
+    /// Vars tracked for changes
+    pub vars: Vec<Address>,
+    /// Max number of records held before sending
+    pub batch_size: usize,
+    /// Max time records are held before sending
+    pub batch_interval: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub enum SinkBackend {
+    /// Kafka cluster reachable at any of the listed `host:port` brokers
+    Kafka(Vec<String>),
+    /// NATS server at the given url
+    Nats(String),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Record {
+    Event {
+        clock: usize,
+        event: String,
+    },
+    Var {
+        clock: usize,
+        address: String,
+        value: String,
+    },
+}
+
+/// Connection to the pipeline's message broker.
+trait Producer {
+    /// Sends the encoded records to the topic, returning once they were
+    /// accepted by the broker.
+    fn send(&mut self, topic: &str, records: &[Vec<u8>]) -> Result<()>;
+}
+
+#[cfg(feature = "kafka_sink")]
+impl Producer for kafka::producer::Producer {
+    fn send(&mut self, topic: &str, records: &[Vec<u8>]) -> Result<()> {
+        let records = records
+            .iter()
+            .map(|r| kafka::producer::Record::from_value(topic, r.as_slice()))
+            .collect::<Vec<_>>();
+        self.send_all(&records)
+            .map_err(|e| Error::Other(format!("kafka error: {}", e)))?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "nats_sink")]
+impl Producer for nats::Connection {
+    fn send(&mut self, topic: &str, records: &[Vec<u8>]) -> Result<()> {
+        for record in records {
+            self.publish(topic, record)?;
+        }
+        self.flush()?;
+        Ok(())
+    }
+}
+
+/// Batches journal records and sends them to the configured backend.
+pub(crate) struct Sink {
+    config: SinkConfig,
+    producer: Box<dyn Producer>,
+    events: Vec<Vec<u8>>,
+    vars: Vec<Vec<u8>>,
+    /// Values of the tracked vars as last sent
+    last_values: FnvHashMap<Address, Var>,
+    last_flush: Instant,
+}
+
+impl Sink {
+    /// Connects to the configured backend.
+    pub fn connect(config: SinkConfig) -> Result<Self> {
+        let producer: Box<dyn Producer> = match &config.backend {
+            #[cfg(feature = "kafka_sink")]
+            SinkBackend::Kafka(brokers) => Box::new(
+                kafka::producer::Producer::from_hosts(brokers.clone())
+                    .with_required_acks(kafka::producer::RequiredAcks::One)
+                    .create()
+                    .map_err(|e| Error::Other(format!("kafka error: {}", e)))?,
+            ),
+            #[cfg(not(feature = "kafka_sink"))]
+            SinkBackend::Kafka(_) => {
+                return Err(Error::Other(
+                    "kafka sink requires the `kafka_sink` feature".to_string(),
+                ))
+            }
+            #[cfg(feature = "nats_sink")]
+            SinkBackend::Nats(url) => Box::new(nats::connect(url)?),
+            #[cfg(not(feature = "nats_sink"))]
+            SinkBackend::Nats(_) => {
+                return Err(Error::Other(
+                    "nats sink requires the `nats_sink` feature".to_string(),
+                ))
+            }
+        };
+        info!("connected event sink: {:?}", config.backend);
+        Ok(Self {
+            config,
+            producer,
+            events: Vec::new(),
+            vars: Vec::new(),
+            last_values: FnvHashMap::default(),
+            last_flush: Instant::now(),
+        })
+    }
+
+    /// Records the events about to be processed at the given clock.
+    pub fn record_events(&mut self, clock: usize, events: &[EventName]) -> Result<()> {
+        for event in events {
+            self.events.push(serde_json::to_vec(&Record::Event {
+                clock,
+                event: event.to_string(),
+            })?);
+        }
+        Ok(())
+    }
+
+    /// Records changes to the tracked vars, to be called after a step was
+    /// processed.
+    pub fn record_vars(&mut self, clock: usize, sim: &Sim) -> Result<()> {
+        for address in &self.config.vars {
+            let var = match sim.get_var(address) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            if self.last_values.get(address) == Some(var) {
+                continue;
+            }
+            self.last_values.insert(address.clone(), var.clone());
+            self.vars.push(serde_json::to_vec(&Record::Var {
+                clock,
+                address: address.to_string(),
+                value: var.to_string(),
+            })?);
+        }
+        Ok(())
+    }
+
+    /// Sends the batched records if the batch is full or the batch
+    /// interval has passed.
+    pub fn flush_due(&mut self) -> Result<()> {
+        let len = self.events.len() + self.vars.len();
+        if len == 0 {
+            self.last_flush = Instant::now();
+            return Ok(());
+        }
+        if len >= self.config.batch_size || self.last_flush.elapsed() >= self.config.batch_interval
+        {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Sends all the batched records. Records that failed to send are
+    /// kept, to be sent again with the next flush.
+    pub fn flush(&mut self) -> Result<()> {
+        self.last_flush = Instant::now();
+        if !self.events.is_empty() {
+            self.producer
+                .send(&self.config.events_topic, &self.events)?;
+            self.events.clear();
+        }
+        if !self.vars.is_empty() {
+            self.producer.send(&self.config.vars_topic, &self.vars)?;
+            self.vars.clear();
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn failed_flush_keeps_batch() {
+    use std::sync::{Arc, Mutex};
+
+    /// Producer failing until told otherwise, collecting sent records.
+    struct FlakyProducer {
+        failing: Arc<Mutex<bool>>,
+        sent: Arc<Mutex<Vec<(String, usize)>>>,
+    }
+
+    impl Producer for FlakyProducer {
+        fn send(&mut self, topic: &str, records: &[Vec<u8>]) -> Result<()> {
+            if *self.failing.lock().unwrap() {
+                return Err(Error::Other("broker unavailable".to_string()));
+            }
+            self.sent
+                .lock()
+                .unwrap()
+                .push((topic.to_string(), records.len()));
+            Ok(())
+        }
+    }
+
+    let failing = Arc::new(Mutex::new(true));
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let mut sink = Sink {
+        config: SinkConfig {
+            backend: SinkBackend::Nats("nats://127.0.0.1:4222".to_string()),
+            events_topic: "events".to_string(),
+            vars_topic: "vars".to_string(),
+            vars: Vec::new(),
+            batch_size: 10,
+            batch_interval: Duration::from_secs(1),
+        },
+        producer: Box::new(FlakyProducer {
+            failing: failing.clone(),
+            sent: sent.clone(),
+        }),
+        events: Vec::new(),
+        vars: Vec::new(),
+        last_values: FnvHashMap::default(),
+        last_flush: Instant::now(),
+    };
+
+    let events = vec![outcome::string::new_truncate("step")];
+    sink.record_events(0, &events).unwrap();
+    sink.record_events(1, &events).unwrap();
+    assert!(sink.flush().is_err());
+    assert_eq!(sink.events.len(), 2);
+
+    *failing.lock().unwrap() = false;
+    sink.flush().unwrap();
+    assert!(sink.events.is_empty());
+    assert_eq!(*sent.lock().unwrap(), vec![("events".to_string(), 2)]);
+}
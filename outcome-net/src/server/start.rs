@@ -0,0 +1,120 @@
+//! Starting simulations on idle servers.
+//!
+//! Server started without a simulation waits in an idle state, answering
+//! only pings and status requests, until a client with the admin scope
+//! starts a simulation from one of the scenarios available to the server.
+//! Seed and settings found in the scenario manifest can be overridden,
+//! allowing for parameterized runs without modifying files on disk.
+//!
+//! Scenarios can only be started from within the configured scenarios
+//! directory, see [`ServerConfig::scenarios_dir`].
+//!
+//! [`ServerConfig::scenarios_dir`]: crate::server::ServerConfig::scenarios_dir
+
+use outcome::model::Scenario;
+use outcome::Sim;
+
+use crate::msg::{
+    ErrorCode, Message, MessageType, ResponseError, StartSimRequest, StartSimResponse,
+};
+use crate::server::{ClientId, Permission};
+use crate::{Error, Result};
+use crate::{Server, SimConnection};
+
+impl Server {
+    pub fn handle_start_sim_request(&mut self, msg: Message, client_id: &ClientId) -> Result<()> {
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: StartSimRequest = msg.unpack_payload(client.connection.encoding())?;
+
+        let result = if client.permission < Permission::Admin {
+            Err(ResponseError::new(
+                ErrorCode::Unauthorized,
+                "starting a simulation requires admin scope",
+            ))
+        } else if let SimConnection::Idle = self.sim {
+            self.start_sim(req).map_err(ResponseError::from)
+        } else {
+            Err(ResponseError::new(
+                ErrorCode::AlreadyExists,
+                "simulation already running",
+            ))
+        };
+        if let Err(e) = &result {
+            warn!("client {} failed starting simulation: {}", client_id, e);
+        }
+
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let (error, code) = result.err().unwrap_or_default().into_fields();
+        client
+            .connection
+            .send_payload(StartSimResponse { error, code }, None)
+    }
+
+    /// Creates a local simulation from the requested scenario, applying
+    /// the overrides, and starts any services it defines.
+    fn start_sim(&mut self, req: StartSimRequest) -> Result<()> {
+        let root = self.config.scenarios_dir.canonicalize()?;
+        let path = root.join(&req.scenario).canonicalize()?;
+        if !path.starts_with(&root) {
+            return Err(Error::RequestRejected(
+                MessageType::StartSimRequest,
+                ResponseError::new(
+                    ErrorCode::Unauthorized,
+                    format!("scenario outside of scenarios directory: {}", req.scenario),
+                ),
+            ));
+        }
+        let mut scenario = Scenario::from_path(path)?;
+        if let Some(seed) = req.seed {
+            scenario.manifest.seed = seed;
+        }
+        scenario.manifest.settings.extend(req.settings);
+        let mut sim = Sim::from_scenario(scenario)?;
+        if let Some(path) = &self.config.replay_log {
+            sim.start_replay_log(path)?;
+        }
+
+        info!(
+            "starting simulation from scenario: {}",
+            sim.model.scenario.manifest.name
+        );
+        let clock = sim.get_clock();
+        self.sim = SimConnection::Local(sim);
+        for client in self.clients.values_mut() {
+            client.furthest_step = clock;
+        }
+        self.initialize_services()
+    }
+}
+
+#[test]
+fn idle_server_rejects_requests_and_foreign_scenarios() {
+    use crate::harness::TestServer;
+    use crate::server::ServerConfig;
+
+    let root = std::env::temp_dir().join("outcome_idle_scenarios");
+    std::fs::create_dir_all(&root).unwrap();
+    let config = ServerConfig {
+        anonymous_admin: true,
+        scenarios_dir: root,
+        ..ServerConfig::default()
+    };
+    let server = TestServer::start_idle(config).unwrap();
+    let mut client = server.client().unwrap();
+    match client.get_var("thing:counter:int:count") {
+        Err(Error::RequestRejected(_, e)) => assert_eq!(e.code, ErrorCode::SimNotStarted),
+        other => panic!("expected sim not started, got: {:?}", other),
+    }
+    let error = client
+        .start_sim("..", None, Default::default())
+        .unwrap_err();
+    assert!(error.to_string().contains("outside of scenarios directory"));
+    client.disconnect().unwrap();
+    server.shutdown().unwrap();
+}
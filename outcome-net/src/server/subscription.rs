@@ -0,0 +1,239 @@
+//! Query subscriptions with pushed updates.
+//!
+//! Instead of polling for data, clients can subscribe with a query. The
+//! query is re-evaluated after each processed step, and the client is
+//! pushed only the vars whose values changed since the last update, along
+//! with addresses of vars that are no longer selected. The first update,
+//! sent right after subscribing, contains all the selected vars.
+//!
+//! Updates go through the client's push buffer, see the [`push`] module.
+//! Since changes are computed against the previous update, an update
+//! dropped from a full buffer would leave the client with stale values.
+//! In that case the next update is a full one again.
+//!
+//! Subscriptions are stored per client and are only available with the
+//! local backend.
+//!
+//! [`push`]: crate::server::push
+
+use std::convert::TryInto;
+
+use fnv::FnvHashMap;
+use outcome::query::{Description, Layout, QueryProduct};
+use outcome::{Address, Sim, Var};
+
+use std::collections::HashMap;
+
+use crate::msg::{ErrorCode, ResponseError};
+use crate::msg::{
+    Message, SubscribeRequest, SubscribeResponse, SubscriptionUpdate, UnsubscribeRequest,
+    UnsubscribeResponse,
+};
+use crate::server::{Client, ClientId, Server, SimConnection};
+use crate::{Error, Result};
+
+/// Query subscription as stored on the server.
+pub struct Subscription {
+    /// Query used for selecting vars, trigger is ignored
+    pub query: outcome::Query,
+    /// Values sent with the previous updates
+    pub last: FnvHashMap<Address, Var>,
+    /// Whether the next update has to carry all the selected vars
+    pub resync: bool,
+}
+
+impl Subscription {
+    pub fn new(mut query: outcome::Query) -> Self {
+        // changes are tracked per address
+        query.description = Description::Addressed;
+        query.layout = Layout::Var;
+        Self {
+            query,
+            last: FnvHashMap::default(),
+            resync: true,
+        }
+    }
+
+    /// Evaluates the query and creates an update containing changes since
+    /// the last one, or all the selected vars if resync was requested.
+    /// Returns `None` if nothing changed.
+    pub fn update(&mut self, id: u32, sim: &Sim) -> Result<Option<SubscriptionUpdate>> {
+        let product = sim.query(&self.query)?;
+        let current = match product {
+            QueryProduct::AddressedVar(map) => map,
+            _ => FnvHashMap::default(),
+        };
+
+        let mut update = SubscriptionUpdate {
+            subscription_id: id,
+            clock: sim.get_clock(),
+            vars: FnvHashMap::default(),
+            removed: Vec::new(),
+            full: self.resync,
+        };
+        for (address, var) in &current {
+            if update.full || self.last.get(address) != Some(var) {
+                update.vars.insert(address.clone(), var.clone());
+            }
+        }
+        for address in self.last.keys() {
+            if !current.contains_key(address) {
+                update.removed.push(address.clone());
+            }
+        }
+        self.last = current;
+        self.resync = false;
+
+        if !update.full && update.vars.is_empty() && update.removed.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(update))
+        }
+    }
+}
+
+impl Client {
+    /// Marks the subscription for resync after one of it's updates was
+    /// dropped from the push buffer.
+    pub(crate) fn subscription_update_dropped(&mut self, subscription_id: u32) {
+        if let Some(subscription) = self.subscriptions.get_mut(&subscription_id) {
+            debug!(
+                "[client: {}] subscription {} update dropped, resyncing",
+                self.id, subscription_id
+            );
+            subscription.resync = true;
+        }
+    }
+
+    /// Evaluates all the client's subscriptions, putting updates on the
+    /// push buffer.
+    fn push_subscription_updates(&mut self, sim: &Sim, buffer_size: usize) -> Result<()> {
+        let mut updates = Vec::new();
+        for (id, subscription) in &mut self.subscriptions {
+            if let Some(update) = subscription.update(*id, sim)? {
+                updates.push(update);
+            }
+        }
+        for update in updates {
+            self.queue_push(update, 0, buffer_size)?;
+        }
+        Ok(())
+    }
+}
+
+impl Server {
+    pub fn handle_subscribe_request(&mut self, msg: Message, client_id: &ClientId) -> Result<()> {
+        let client = self
+            .clients
+            .get_mut(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: SubscribeRequest = msg.unpack_payload(client.connection.encoding())?;
+        let sim = match &self.sim {
+            SimConnection::Local(sim) => sim,
+            _ => {
+                let (error, code) =
+                    ResponseError::unsupported("subscriptions only available with local backend")
+                        .into_fields();
+                let resp = SubscribeResponse {
+                    subscription_id: 0,
+                    error,
+                    code,
+                };
+                return client.connection.send_payload(resp, None);
+            }
+        };
+        let id = client
+            .subscription_id_pool
+            .request_id()
+            .ok_or_else(|| Error::Other("no subscription ids left".to_string()))?;
+        let mut subscription = Subscription::new(req.query.try_into()?);
+        // initial update carries all the selected vars
+        let update = subscription.update(id, sim)?;
+        client.subscriptions.insert(id, subscription);
+        client.connection.send_payload(
+            SubscribeResponse {
+                subscription_id: id,
+                error: String::new(),
+                code: None,
+            },
+            None,
+        )?;
+        if let Some(update) = update {
+            client.queue_push(update, 0, self.config.push_buffer_size)?;
+        }
+        Ok(())
+    }
+
+    pub fn handle_unsubscribe_request(&mut self, msg: Message, client_id: &ClientId) -> Result<()> {
+        let client = self
+            .clients
+            .get_mut(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: UnsubscribeRequest = msg.unpack_payload(client.connection.encoding())?;
+        let (error, code) = match client.subscriptions.remove(&req.subscription_id) {
+            Some(_) => {
+                let _ = client.subscription_id_pool.return_id(req.subscription_id);
+                (String::new(), None)
+            }
+            None => ResponseError::new(
+                ErrorCode::NotFound,
+                format!("subscription not found: {}", req.subscription_id),
+            )
+            .into_fields(),
+        };
+        client
+            .connection
+            .send_payload(UnsubscribeResponse { error, code }, None)
+    }
+}
+
+/// Pushes updates for subscriptions of all the clients. Called after each
+/// processed step.
+pub(crate) fn push_subscription_updates(
+    clients: &mut HashMap<ClientId, Client>,
+    sim: &Sim,
+    buffer_size: usize,
+) {
+    for (client_id, client) in clients {
+        if client.subscriptions.is_empty() {
+            continue;
+        }
+        if let Err(e) = client.push_subscription_updates(sim, buffer_size) {
+            warn!(
+                "[client: {}] failed pushing subscription updates: {}",
+                client_id, e
+            );
+        }
+    }
+}
+
+#[test]
+fn subscription_resyncs_after_drop() {
+    use outcome::query::{Filter, Map, Trigger};
+
+    let sim = outcome::SimModelBuilder::new()
+        .component("counter", |c| c.var("int:count", Var::Int(0)))
+        .prefab("thing", &["counter"])
+        .spawn("thing", Some("first"))
+        .build_sim()
+        .unwrap();
+    let mut subscription = Subscription::new(outcome::Query {
+        trigger: Trigger::Immediate,
+        description: Description::Addressed,
+        layout: Layout::Var,
+        filters: vec![Filter::AllComponents(vec![outcome::string::new_truncate(
+            "counter",
+        )])],
+        mappings: vec![Map::All],
+    });
+
+    let first = subscription.update(0, &sim).unwrap().unwrap();
+    assert!(first.full);
+    assert_eq!(first.vars.len(), 1);
+    assert!(subscription.update(0, &sim).unwrap().is_none());
+
+    subscription.resync = true;
+    let resynced = subscription.update(0, &sim).unwrap().unwrap();
+    assert!(resynced.full);
+    assert_eq!(resynced.vars, first.vars);
+}
@@ -1,12 +1,21 @@
+use std::str::FromStr;
+
 use crate::msg::{
-    DataTransferResponse, Message, TurnAdvanceRequest, TurnAdvanceResponse, TypedSimDataPack,
+    DataTransferResponse, InvokeEventsRequest, InvokeEventsResponse, Message, ScheduleEventRequest,
+    ScheduleEventResponse, SetStepTriggerRequest, SetStepTriggerResponse, TransferResponseData,
+    TurnAdvanceRequest, TurnAdvanceResponse, TypedSimDataPack,
 };
+use crate::msg::{ErrorCode, ResponseError};
+use crate::organizer::StepTrigger;
+use crate::server::audit::AuditAction;
+use crate::server::{crashdump, replay, subscription};
 use crate::server::{handle_data_transfer_request_local, ClientId};
 use crate::{Server, SimConnection};
 
 use crate::msg::TransferResponseData::AddressedVar;
 use crate::{Error, Result};
 use outcome::distr::NodeCommunication;
+use outcome::sim::replay::Mutation;
 
 impl Server {
     // fn advance_turn(&mut self, tick_num: u32) -> Result<()> {}
@@ -26,6 +35,7 @@ impl Server {
             SimConnection::Local(s) => s.get_clock(),
             SimConnection::UnionOrganizer(c) => c.central.clock,
             SimConnection::UnionWorker(w) => w.sim_node.as_ref().unwrap().clock,
+            SimConnection::Idle => return Err(Error::SimNotStarted),
         };
 
         trace!("step count before advance attempt: {}", step_before_advance);
@@ -82,19 +92,67 @@ impl Server {
         }
 
         let mut clock_after_advance = step_before_advance;
+        let push_buffer_size = self.config.push_buffer_size;
         trace!(
             "common_furthest_step: {}, step_before_advance: {}",
             common_furthest_step,
             step_before_advance
         );
         if common_furthest_step > step_before_advance {
+            // step begins, stop any maintenance work in progress
+            self.interrupt_maintenance()?;
             match &mut self.sim {
                 SimConnection::Local(sim_instance) => {
                     // for local sim instance simply step until common
                     // furthest step is achieved
                     for _ in 0..common_furthest_step - step_before_advance {
-                        sim_instance.step();
+                        #[cfg(any(feature = "kafka_sink", feature = "nats_sink"))]
+                        if let Some(sink) = &mut self.sink {
+                            if let Err(e) = sink
+                                .record_events(clock_after_advance, sim_instance.queued_events())
+                            {
+                                warn!("failed recording events: {}", e);
+                            }
+                        }
+                        let result = sim_instance.step();
+                        self.runtime_errors
+                            .record(clock_after_advance, sim_instance);
+                        if let Err(e) = result {
+                            if let Some(dir) = &self.config.crashdump_dir {
+                                crashdump::write_local(dir, sim_instance, &e, &self.message_log);
+                            }
+                            // surface the error to the requesting client,
+                            // e.g. when the sim is running in strict mode
+                            let client = self.clients.get_mut(client_id).unwrap();
+                            client.furthest_step = clock_after_advance;
+                            let (error, code) = ResponseError::from(e).into_fields();
+                            let resp = TurnAdvanceResponse { error, code };
+                            client
+                                .connection
+                                .send_payload_with_task(resp, msg.task_id, None)?;
+                            return Ok(());
+                        }
                         clock_after_advance += 1;
+                        self.interpolation
+                            .capture(&self.config.interpolated_vars, sim_instance);
+                        #[cfg(any(feature = "kafka_sink", feature = "nats_sink"))]
+                        if let Some(sink) = &mut self.sink {
+                            if let Err(e) = sink
+                                .record_vars(clock_after_advance, sim_instance)
+                                .and_then(|_| sink.flush_due())
+                            {
+                                warn!("failed streaming to event sink: {}", e);
+                            }
+                        }
+                        if let Some(publisher) = &self.publisher {
+                            if let Err(e) = publisher.publish_step(
+                                clock_after_advance,
+                                &self.config.var_streams,
+                                Some(sim_instance),
+                            ) {
+                                warn!("failed publishing step: {}", e);
+                            }
+                        }
                         // let events = sim_instance.event_queue.clone();
                         trace!("processed single tick");
                         trace!(
@@ -103,43 +161,63 @@ impl Server {
                             step_before_advance
                         );
 
+                        subscription::push_subscription_updates(
+                            &mut self.clients,
+                            sim_instance,
+                            push_buffer_size,
+                        );
+
                         // advanced turn, check if any scheduled transfers/queries need sending
                         for (_, client) in &mut self.clients {
                             for (event, dts_list) in &client.scheduled_transfers.clone() {
                                 trace!("handling scheduled data transfer: event: {}", event);
-                                if sim_instance.event_queue.contains(&event) {
-                                    for dtr in dts_list {
+                                if sim_instance.queued_events().contains(&event) {
+                                    for (idx, dtr) in dts_list.iter().enumerate() {
                                         info!("handling scheduled data transfer: dtr: {:?}", dtr);
-                                        handle_data_transfer_request_local(
+                                        let mut response = handle_data_transfer_request_local(
                                             dtr,
                                             sim_instance,
                                             client,
-                                        )?
+                                        )?;
+                                        // keep ordered transfers on the order
+                                        // created with the first push
+                                        if let TransferResponseData::VarOrdered(order_id, _) =
+                                            &response.data
+                                        {
+                                            if let Some(stored) = client
+                                                .scheduled_transfers
+                                                .get_mut(event)
+                                                .and_then(|list| list.get_mut(idx))
+                                            {
+                                                stored.order_id = Some(*order_id);
+                                            }
+                                        }
+                                        response.data =
+                                            response.data.with_precision(client.precision_for(dtr));
+                                        response.interpolation = self.interpolation.metadata();
+                                        client.queue_push(response, 0, push_buffer_size)?;
                                     }
                                 }
                             }
-                            for (event, queries) in &client.scheduled_queries {
-                                if sim_instance.event_queue.contains(event) {
+                            for (event, queries) in &client.scheduled_queries.clone() {
+                                if sim_instance.queued_events().contains(event) {
                                     for (task_id, query) in queries {
                                         trace!("handling scheduled query: {:?}", query);
-                                        let product = query.process(
-                                            &sim_instance.entities,
-                                            &sim_instance.entity_idx,
-                                        )?;
+                                        let product = sim_instance.query(query)?;
 
                                         let mut data_pack = TypedSimDataPack::empty();
                                         if let outcome::query::QueryProduct::AddressedVar(map) =
                                             product
                                         {
-                                            if let Err(e) =
-                                                client.connection.send_payload_with_task(
-                                                    DataTransferResponse {
-                                                        data: AddressedVar(map),
-                                                    },
-                                                    *task_id,
-                                                    None,
-                                                )
-                                            {
+                                            if let Err(e) = client.queue_push(
+                                                DataTransferResponse {
+                                                    data: AddressedVar(map)
+                                                        .with_precision(client.float_precision),
+                                                    interpolation: None,
+                                                },
+                                                *task_id,
+                                                push_buffer_size,
+                                            ) {
                                                 error!("{}", e);
                                             }
                                         }
@@ -150,7 +228,9 @@ impl Server {
                             if &client.id == client_id {
                                 continue;
                             }
-                            if let Some(scheduled_step) = client.scheduled_advance_response {
+                            if let Some((scheduled_step, task_id)) =
+                                client.scheduled_advance_response
+                            {
                                 trace!(
                                     "[client: {}] scheduled_step: {}, current_step: {}",
                                     client.id,
@@ -160,8 +240,11 @@ impl Server {
                                 if scheduled_step == clock_after_advance {
                                     let resp = TurnAdvanceResponse {
                                         error: String::new(),
+                                        code: None,
                                     };
-                                    client.connection.send_payload(resp, None)?;
+                                    client
+                                        .connection
+                                        .send_payload_with_task(resp, task_id, None)?;
                                     client.scheduled_advance_response = None;
                                 }
                             }
@@ -170,32 +253,42 @@ impl Server {
                     trace!("clock step after advance: {}", clock_after_advance);
                 }
                 SimConnection::UnionOrganizer(coord) => {
-                    let mut event_queue = coord.central.event_queue.clone();
-
-                    let step_event_name = outcome::string::new_truncate("step");
-                    if !event_queue.contains(&step_event_name) {
-                        event_queue.push(step_event_name);
+                    // aborted step keeps the events queued up on the central
+                    if let Err(e) = coord.step() {
+                        error!("failed processing step: {}", e);
+                        if let Some(dir) = &self.config.crashdump_dir {
+                            crashdump::write_organizer(
+                                dir,
+                                coord,
+                                &e.to_string(),
+                                &self.message_log,
+                            );
+                        }
+                        let client = self.clients.get_mut(client_id).unwrap();
+                        client.furthest_step = step_before_advance;
+                        let (error, code) = ResponseError::from(e).into_fields();
+                        let resp = TurnAdvanceResponse { error, code };
+                        client
+                            .connection
+                            .send_payload_with_task(resp, msg.task_id, None)?;
+                        return Ok(());
+                    }
+                    // errors were reported by the workers during the step
+                    // that began before the clock was moved
+                    #[cfg(feature = "machine")]
+                    self.runtime_errors.record_journal(
+                        coord.central.clock.saturating_sub(1),
+                        &coord.central.error_journal,
+                    );
+                    if let Some(publisher) = &self.publisher {
+                        if let Err(e) = publisher.publish_step(
+                            coord.central.clock,
+                            &self.config.var_streams,
+                            None,
+                        ) {
+                            warn!("failed publishing step: {}", e);
+                        }
                     }
-                    coord.central.event_queue.clear();
-
-                    // let network = &coord_lock.network;
-                    // let central = &mut coord_lock.central;
-                    // for (worker_id, worker) in &coord.net.workers {
-                    //     if worker.is_blocking_step {
-                    //         let resp = TurnAdvanceResponse {
-                    //             error: "BlockedFully".to_string(),
-                    //         };
-                    //         trace!("BlockedFully");
-                    //         let client = self.clients.get_mut(client_id).unwrap();
-                    //         client.connection.pack_send_msg_payload(resp, None)?;
-                    //         return Ok(());
-                    //     }
-                    // }
-                    coord.central.step_network(&mut coord.net, event_queue);
-                    // coord_lock
-                    //     .central
-                    //     .step_network(&mut coord_lock.network, event_queue)?;
-                    coord.central.clock += 1;
 
                     // let mut addr_book = HashMap::new();
                     // for node in &coord.nodes {
@@ -232,7 +325,11 @@ impl Server {
                     // if other workers are not ready, coordinator response to this
                     // message will be delayed
                 }
+                SimConnection::Idle => return Err(Error::SimNotStarted),
             };
+            self.refresh_dynamic_selections();
+            // queue up maintenance work for the time until the next step
+            self.schedule_maintenance();
         } else {
             match &mut self.sim {
                 SimConnection::UnionOrganizer(coord) => {
@@ -251,30 +348,37 @@ impl Server {
             // client.scheduled_advance_response = Some(client.)
             // immediate response requested
             if !req.wait {
-                let resp = TurnAdvanceResponse {
-                    error: "BlockedFully".to_string(),
-                };
-                client.connection.send_payload(resp, None)?;
+                let (error, code) =
+                    ResponseError::new(ErrorCode::WouldBlock, "BlockedFully").into_fields();
+                let resp = TurnAdvanceResponse { error, code };
+                client
+                    .connection
+                    .send_payload_with_task(resp, msg.task_id, None)?;
             } else {
-                client.scheduled_advance_response = Some(client.furthest_step);
+                client.scheduled_advance_response = Some((client.furthest_step, msg.task_id));
             }
         } else if common_furthest_step < client_furthest_step {
             trace!("BlockedPartially");
             if !req.wait {
-                let resp = TurnAdvanceResponse {
-                    error: "BlockedPartially".to_string(),
-                };
-                client.connection.send_payload(resp, None)?;
+                let (error, code) =
+                    ResponseError::new(ErrorCode::WouldBlock, "BlockedPartially").into_fields();
+                let resp = TurnAdvanceResponse { error, code };
+                client
+                    .connection
+                    .send_payload_with_task(resp, msg.task_id, None)?;
             } else {
-                client.scheduled_advance_response = Some(client.furthest_step);
+                client.scheduled_advance_response = Some((client.furthest_step, msg.task_id));
             }
             //        } else if common_furthest_tick == client_furthest_tick {
         } else {
             trace!("Didn't block");
             let resp = TurnAdvanceResponse {
                 error: String::new(),
+                code: None,
             };
-            client.connection.send_payload(resp, None)?;
+            client
+                .connection
+                .send_payload_with_task(resp, msg.task_id, None)?;
         }
 
         // // check the clients for scheduled step advance responses
@@ -299,4 +403,203 @@ impl Server {
 
         Ok(())
     }
+
+    /// Handles events invoked by clients. Events are added to the event
+    /// queue and processed on the next step. With the organizer backend,
+    /// such external event can also trigger the step itself, see
+    /// [`StepTrigger::Event`].
+    pub fn handle_invoke_events_request(
+        &mut self,
+        msg: Message,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: InvokeEventsRequest = msg.unpack_payload(client.connection.encoding())?;
+        let clock = match &self.sim {
+            SimConnection::Local(sim) => Some(sim.get_clock()),
+            SimConnection::UnionOrganizer(coord) => Some(coord.central.clock),
+            SimConnection::UnionWorker(_) | SimConnection::Idle => None,
+        };
+        let mut error = ResponseError::default();
+        match clock {
+            Some(clock) => {
+                for event in &req.events {
+                    if let Some(audit) = &mut self.audit {
+                        audit.record(
+                            client_id,
+                            clock,
+                            AuditAction::Invoke {
+                                event,
+                                entity: None,
+                            },
+                        );
+                    }
+                    let event = outcome::string::new_truncate(event);
+                    match &mut self.sim {
+                        SimConnection::Local(sim) => sim.invoke_event(event),
+                        SimConnection::UnionOrganizer(coord) => {
+                            if !coord.central.event_queue.contains(&event) {
+                                coord.central.event_queue.push(event);
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            None => error = ResponseError::unsupported("invoking events not available on worker"),
+        }
+        if let SimConnection::Local(sim) = &mut self.sim {
+            for event in &req.events {
+                let event = outcome::string::new_truncate(event);
+                replay::log_mutation(sim, Mutation::Invoke(event));
+            }
+        }
+        let (error, code) = error.into_fields();
+        client
+            .connection
+            .send_payload(InvokeEventsResponse { error, code }, None)
+    }
+
+    pub fn handle_schedule_event_request(
+        &mut self,
+        msg: Message,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        let client = self
+            .clients
+            .get_mut(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: ScheduleEventRequest = msg.unpack_payload(client.connection.encoding())?;
+        let at_step = req.at_step.map(|s| s as usize);
+        let every_n_steps = req.every_n_steps.map(|n| n as usize);
+        let clock = match &self.sim {
+            SimConnection::Local(sim) => sim.get_clock(),
+            SimConnection::UnionOrganizer(coord) => coord.central.clock,
+            SimConnection::UnionWorker(_) | SimConnection::Idle => 0,
+        };
+        // one-off events scheduled for passed steps have already fired
+        client
+            .scheduled_events
+            .retain(|e| e.every_n_steps.is_some() || e.at_step >= clock);
+
+        let result = match (at_step, every_n_steps, self.config.max_scheduled_events) {
+            (None, None, _) => Err(ResponseError::new(
+                ErrorCode::InvalidRequest,
+                "either the step or the interval is required",
+            )),
+            (_, _, Some(max)) if client.scheduled_events.len() >= max => Err(ResponseError::new(
+                ErrorCode::ResourceExhausted,
+                format!("can't have more than {} events scheduled", max),
+            )),
+            (at_step, every_n_steps, _) => {
+                let invalid = |e: outcome::error::Error| {
+                    ResponseError::new(ErrorCode::InvalidRequest, e.to_string())
+                };
+                let scheduled = match &mut self.sim {
+                    SimConnection::Local(sim) => match (at_step, every_n_steps) {
+                        (Some(at_step), None) => sim.schedule_event(&req.event, at_step),
+                        (Some(at_step), Some(n)) => {
+                            sim.schedule_recurring_event_at(&req.event, at_step, n)
+                        }
+                        (None, n) => sim.schedule_recurring_event(&req.event, n.unwrap_or(0)),
+                    }
+                    .map_err(invalid),
+                    SimConnection::UnionOrganizer(coord) => match (at_step, every_n_steps) {
+                        (Some(at_step), None) => coord.central.schedule_event(&req.event, at_step),
+                        (Some(at_step), Some(n)) => coord
+                            .central
+                            .schedule_recurring_event_at(&req.event, at_step, n),
+                        (None, n) => coord
+                            .central
+                            .schedule_recurring_event(&req.event, n.unwrap_or(0)),
+                    }
+                    .map_err(invalid),
+                    SimConnection::UnionWorker(_) | SimConnection::Idle => Err(
+                        ResponseError::unsupported("scheduling events not available on worker"),
+                    ),
+                };
+                scheduled.map(|_| outcome::scheduler::ScheduledEvent {
+                    event: outcome::string::new_truncate(&req.event),
+                    at_step: at_step.unwrap_or(clock + every_n_steps.unwrap_or(0)),
+                    every_n_steps,
+                })
+            }
+        };
+        if let Ok(scheduled) = &result {
+            if let SimConnection::Local(sim) = &mut self.sim {
+                replay::log_mutation(
+                    sim,
+                    Mutation::ScheduleEvent {
+                        event: scheduled.event.clone(),
+                        at_step: scheduled.at_step,
+                        every_n_steps: scheduled.every_n_steps,
+                    },
+                );
+            }
+            client.scheduled_events.push(scheduled.clone());
+        }
+        let (error, code) = result.err().unwrap_or_default().into_fields();
+        client
+            .connection
+            .send_payload(ScheduleEventResponse { error, code }, None)
+    }
+
+    /// Handles the administrative request for changing the step trigger
+    /// policy. Only applicable with the organizer backend.
+    pub fn handle_set_step_trigger_request(
+        &mut self,
+        msg: Message,
+        client_id: &ClientId,
+    ) -> Result<()> {
+        let client = self
+            .clients
+            .get(client_id)
+            .ok_or(Error::FailedGettingClientById(client_id.clone()))?;
+        let req: SetStepTriggerRequest = msg.unpack_payload(client.connection.encoding())?;
+        let error = match &mut self.sim {
+            SimConnection::UnionOrganizer(coord) => match StepTrigger::from_str(&req.trigger) {
+                Ok(trigger) => {
+                    info!(
+                        "[client: {}] setting step trigger: {:?}",
+                        client_id, trigger
+                    );
+                    coord.trigger = trigger;
+                    ResponseError::default()
+                }
+                Err(e) => ResponseError::new(ErrorCode::InvalidRequest, e.to_string()),
+            },
+            _ => ResponseError::unsupported("step triggers only available with organizer backend"),
+        };
+        let (error, code) = error.into_fields();
+        client
+            .connection
+            .send_payload(SetStepTriggerResponse { error, code }, None)
+    }
+}
+
+#[test]
+fn scheduled_events_are_limited_per_client() {
+    use crate::harness::TestServer;
+    use crate::ServerConfig;
+
+    let sim = outcome::SimModelBuilder::new()
+        .component("pos", |c| c.var("float:x", None))
+        .prefab("dot", &["pos"])
+        .build_sim()
+        .unwrap();
+    let config = ServerConfig {
+        max_scheduled_events: Some(1),
+        ..ServerConfig::default()
+    };
+    let server = TestServer::start_with_config(sim, config).unwrap();
+    let mut client = server.client().unwrap();
+    let resp = client.schedule_event("ping", Some(10), None).unwrap();
+    assert_eq!(resp.code, None);
+    let resp = client.schedule_event("pong", None, Some(5)).unwrap();
+    assert_eq!(resp.code, Some(ErrorCode::ResourceExhausted));
+    client.disconnect().unwrap();
+    server.shutdown().unwrap();
 }
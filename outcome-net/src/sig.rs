@@ -1,13 +1,179 @@
-use crate::socket::Encoding;
-use crate::{Result, TaskId};
+//! Signals exchanged between the organizer and workers.
+//!
+//! # Delivery guarantees
+//!
+//! Over transports that don't guarantee delivery (see
+//! [`Transport::is_reliable`]) signals go through a [`SignalChannel`].
+//! Each outgoing signal is assigned a sequence number and kept around until
+//! the receiving side acknowledges it. Signals that aren't acknowledged
+//! within [`RetryConfig::ack_timeout`] are resent, up to
+//! [`RetryConfig::max_retries`] times, after which the signal is dropped,
+//! the delivery is considered failed and [`Error::SignalDeliveryFailed`]
+//! is returned.
+//!
+//! Receiving side delivers signals in sequence order. Acknowledgements are
+//! cumulative. A gap in the sequence is reported back with a negative
+//! acknowledgement, prompting the sender to resend the missing signal
+//! without waiting for the timeout.
+//!
+//! Sequence numbers are tracked per connection. When a peer connects or
+//! disconnects the streams exchanged with it are reset, so that both sides
+//! start counting from the beginning again.
+//!
+//! # Priority and deadlines
+//!
+//! Signals carry a [`Priority`], derived from the kind of the signal unless
+//! set explicitly, and an optional deadline, expressed as the last clock
+//! value at which the signal is still relevant. Workers handle signals
+//! with higher priority first, and reject signals which deadline has
+//! already passed. Critical signals are never reordered, signals received
+//! before a critical signal are handled before it, so that e.g. data
+//! requests are answered with the state as of their arrival.
+//!
+//! [`Transport::is_reliable`]: crate::socket::Transport::is_reliable
+//! [`Error::SignalDeliveryFailed`]: crate::Error::SignalDeliveryFailed
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::socket::{Encoding, SocketAddress};
+use crate::trace::{self, TraceId};
+use crate::{Error, Result, TaskId};
 use serde::{Deserialize, Serialize};
 
+/// Sequence number of a signal within a single connection.
+pub type SeqNum = u32;
+
+/// Sequence number of signals sent outside of a [`SignalChannel`], as well
+/// as of the channel's own acknowledgements.
+pub const UNSEQUENCED: SeqNum = 0;
+
+/// Order in which signals are handled by the receiving side, from lowest
+/// to highest.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Priority {
+    /// Bulk data transfers, e.g. data requests and responses or queries
+    Bulk,
+    Normal,
+    /// Signals driving the step or changing the simulation state, which
+    /// relative order is always preserved
+    Critical,
+}
+
+impl Priority {
+    /// Returns the default priority for the given signal.
+    pub fn of(sig: &outcome::distr::Signal) -> Self {
+        use outcome::distr::Signal::*;
+        match sig {
+            InitializeNode(_)
+            | SpawnEntities(_)
+            | StartProcessStep(_)
+            | UpdateModel(_)
+            | DataPullRequest(_)
+            | EndOfMessages
+            | ProcessStepFinished
+            | StepAborted
+            | ShuttingDown
+            | Ack(_)
+            | Nack(_)
+            | MigrateEntity(..)
+            | MigratingEntity(..)
+            | IngestEntity(..)
+            | EntityIngested(_)
+            | MigrationFailed(..)
+            | Checkpoint(..)
+            | CheckpointSaved(_)
+            | RestoreCheckpoint(..)
+            | CheckpointRestored(_)
+            | CheckpointFailed(_) => Priority::Critical,
+            SnapshotRequest | SnapshotResponse(_) | DataRequestAll | DataRequestSelect(_)
+            | DataResponse(_) | QueryRequest(_) | QueryResponse(_) => Priority::Bulk,
+            _ => Priority::Normal,
+        }
+    }
+}
+
+/// Sorts a batch of received signals into the order of handling. Signals
+/// give way to signals of higher priority received after them, except
+/// that no signal is moved across a critical one. Order of signals with
+/// the same priority is kept.
+pub(crate) fn sort_by_priority(signals: &mut [Signal]) {
+    let mut start = 0;
+    while start < signals.len() {
+        let end = signals[start..]
+            .iter()
+            .position(|sig| sig.priority() == Priority::Critical)
+            .map_or(signals.len(), |n| start + n);
+        signals[start..end].sort_by_key(|sig| std::cmp::Reverse(sig.priority()));
+        start = end + 1;
+    }
+}
+
+/// Scheduling information attached to a signal.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct SignalMeta {
+    pub priority: Priority,
+    /// Last clock value at which the signal should still be handled
+    pub deadline: Option<usize>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Signal(u32, outcome::distr::Signal);
+pub struct Signal(u32, outcome::distr::Signal, TraceId, SeqNum, SignalMeta);
 
 impl Signal {
+    /// Creates a new signal, carrying the trace id current for the thread.
     pub fn from(id: TaskId, sig: outcome::distr::Signal) -> Self {
-        Self(id, sig)
+        let meta = SignalMeta {
+            priority: Priority::of(&sig),
+            deadline: None,
+        };
+        Self(id, sig, trace::current(), UNSEQUENCED, meta)
+    }
+
+    /// Overrides the default priority of the signal.
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.4.priority = priority;
+        self
+    }
+
+    /// Sets the last clock value at which the signal should be handled.
+    pub fn with_deadline(mut self, clock: usize) -> Self {
+        self.4.deadline = Some(clock);
+        self
+    }
+
+    pub fn task_id(&self) -> TaskId {
+        self.0
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.4.priority
+    }
+
+    pub fn deadline(&self) -> Option<usize> {
+        self.4.deadline
+    }
+
+    /// Checks whether the deadline has passed at the given clock.
+    pub fn is_expired(&self, clock: usize) -> bool {
+        self.4.deadline.map_or(false, |d| clock > d)
+    }
+
+    pub fn trace_id(&self) -> TraceId {
+        self.2
+    }
+
+    pub fn seq(&self) -> SeqNum {
+        self.3
+    }
+
+    /// Checks whether the signal is part of the delivery protocol itself
+    /// rather than carrying data.
+    pub fn is_control(&self) -> bool {
+        match self.1 {
+            outcome::distr::Signal::Ack(_) | outcome::distr::Signal::Nack(_) => true,
+            _ => false,
+        }
     }
 
     pub fn from_bytes(bytes: &[u8], encoding: &Encoding) -> Result<Self> {
@@ -41,3 +207,325 @@ impl Signal {
         (self.0, self.1)
     }
 }
+
+/// Settings for acknowledged signal delivery.
+#[derive(Debug, Copy, Clone)]
+pub struct RetryConfig {
+    /// Time to wait for an acknowledgement before resending a signal
+    pub ack_timeout: Duration,
+    /// Number of times a signal is resent before delivery is considered
+    /// failed
+    pub max_retries: u32,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            ack_timeout: Duration::from_millis(500),
+            max_retries: 5,
+        }
+    }
+}
+
+struct Pending {
+    sig: Signal,
+    addr: Option<SocketAddress>,
+    sent: Instant,
+    attempts: u32,
+}
+
+#[derive(Default)]
+struct Outgoing {
+    last_seq: SeqNum,
+    unacked: BTreeMap<SeqNum, Pending>,
+}
+
+struct Incoming {
+    expected: SeqNum,
+    out_of_order: BTreeMap<SeqNum, Signal>,
+}
+
+impl Default for Incoming {
+    fn default() -> Self {
+        Self {
+            expected: UNSEQUENCED + 1,
+            out_of_order: BTreeMap::new(),
+        }
+    }
+}
+
+/// Sequencing, acknowledgement and retry bookkeeping for signals sent over
+/// a single socket.
+///
+/// The channel doesn't do any io on it's own, instead it returns the signals
+/// that need to be sent out.
+pub struct SignalChannel {
+    config: RetryConfig,
+    /// Outgoing streams, keyed by the target address as passed when sending
+    outgoing: HashMap<Option<SocketAddress>, Outgoing>,
+    incoming: HashMap<SocketAddress, Incoming>,
+    /// Signals received in order, ready to be read
+    ready: VecDeque<(SocketAddress, Signal)>,
+}
+
+impl SignalChannel {
+    pub fn new(config: RetryConfig) -> Self {
+        Self {
+            config,
+            outgoing: HashMap::new(),
+            incoming: HashMap::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Assigns the next sequence number to an outgoing signal, keeping a copy
+    /// of it until it's acknowledged.
+    pub fn sequence(&mut self, mut sig: Signal, addr: Option<SocketAddress>) -> Signal {
+        let out = self.outgoing.entry(addr.clone()).or_default();
+        out.last_seq += 1;
+        sig.3 = out.last_seq;
+        out.unacked.insert(
+            sig.3,
+            Pending {
+                sig: sig.clone(),
+                addr,
+                sent: Instant::now(),
+                attempts: 0,
+            },
+        );
+        sig
+    }
+
+    /// Processes a signal received from the given address, returning
+    /// signals that need to be sent out in response.
+    ///
+    /// Data signals become available through [`SignalChannel::pop_ready`]
+    /// once all the signals preceding them were received.
+    pub fn receive(
+        &mut self,
+        addr: SocketAddress,
+        sig: Signal,
+    ) -> Vec<(Option<SocketAddress>, Signal)> {
+        let mut out = Vec::new();
+        match sig.1 {
+            outcome::distr::Signal::Ack(seq) => {
+                if let Some(stream) = self.outgoing_for(&addr) {
+                    let acked = stream
+                        .unacked
+                        .range(..=seq)
+                        .map(|(s, _)| *s)
+                        .collect::<Vec<_>>();
+                    for s in acked {
+                        stream.unacked.remove(&s);
+                    }
+                }
+            }
+            outcome::distr::Signal::Nack(seq) => {
+                if let Some(stream) = self.outgoing_for(&addr) {
+                    if let Some(pending) = stream.unacked.get_mut(&seq) {
+                        pending.sent = Instant::now();
+                        out.push((pending.addr.clone(), pending.sig.clone()));
+                    }
+                }
+            }
+            _ if sig.3 == UNSEQUENCED => self.ready.push_back((addr, sig)),
+            _ => {
+                let stream = self.incoming.entry(addr.clone()).or_default();
+                if sig.3 < stream.expected {
+                    // duplicate, the previous ack was likely lost
+                    trace!("received duplicate signal #{} from {}", sig.3, addr);
+                } else if sig.3 > stream.expected {
+                    trace!(
+                        "received signal #{} from {}, expected #{}",
+                        sig.3,
+                        addr,
+                        stream.expected
+                    );
+                    let nack = control(outcome::distr::Signal::Nack(stream.expected));
+                    stream.out_of_order.insert(sig.3, sig);
+                    out.push((Some(addr.clone()), nack));
+                } else {
+                    stream.expected += 1;
+                    self.ready.push_back((addr.clone(), sig));
+                    while let Some(next) = stream.out_of_order.remove(&stream.expected) {
+                        stream.expected += 1;
+                        self.ready.push_back((addr.clone(), next));
+                    }
+                }
+                let ack = control(outcome::distr::Signal::Ack(stream.expected - 1));
+                out.push((Some(addr), ack));
+            }
+        }
+        out
+    }
+
+    /// Takes the next signal that's ready to be read.
+    pub fn pop_ready(&mut self) -> Option<(SocketAddress, Signal)> {
+        self.ready.pop_front()
+    }
+
+    /// Collects signals that weren't acknowledged in time and need to be
+    /// resent.
+    ///
+    /// Signals that were already resent the maximum number of times are
+    /// dropped and an error is returned. Other due signals are left as they
+    /// are, to be collected on the next call.
+    pub fn due(&mut self) -> Result<Vec<(Option<SocketAddress>, Signal)>> {
+        let (timeout, max_retries) = (self.config.ack_timeout, self.config.max_retries);
+        let mut failed = None;
+        for stream in self.outgoing.values_mut() {
+            let exhausted = stream
+                .unacked
+                .iter()
+                .filter(|(_, p)| p.sent.elapsed() >= timeout && p.attempts >= max_retries)
+                .map(|(seq, _)| *seq)
+                .collect::<Vec<_>>();
+            for seq in exhausted {
+                stream.unacked.remove(&seq);
+                failed.get_or_insert(seq);
+            }
+        }
+        if let Some(seq) = failed {
+            return Err(Error::SignalDeliveryFailed(seq));
+        }
+
+        let mut out = Vec::new();
+        for stream in self.outgoing.values_mut() {
+            for (seq, pending) in stream.unacked.iter_mut() {
+                if pending.sent.elapsed() < timeout {
+                    continue;
+                }
+                pending.attempts += 1;
+                pending.sent = Instant::now();
+                out.push((pending.addr.clone(), pending.sig.clone()));
+            }
+        }
+        Ok(out)
+    }
+
+    /// Forgets the sequence state of streams exchanged with the given
+    /// address, along with signals still waiting for acknowledgement.
+    /// Called when the peer connects or disconnects.
+    pub fn reset(&mut self, addr: &SocketAddress) {
+        self.incoming.remove(addr);
+        // streams without an explicit target go to the only connected peer
+        for key in vec![Some(addr.clone()), None] {
+            if let Some(out) = self.outgoing.remove(&key) {
+                if !out.unacked.is_empty() {
+                    warn!(
+                        "dropping {} unacknowledged signals after connection change: {}",
+                        out.unacked.len(),
+                        addr
+                    );
+                }
+            }
+        }
+        self.ready.retain(|(a, _)| a != addr);
+    }
+
+    /// Number of sent signals still waiting for acknowledgement.
+    pub fn unacked_count(&self) -> usize {
+        self.outgoing.values().map(|o| o.unacked.len()).sum()
+    }
+
+    fn outgoing_for(&mut self, addr: &SocketAddress) -> Option<&mut Outgoing> {
+        let key = if self.outgoing.contains_key(&Some(addr.clone())) {
+            Some(addr.clone())
+        } else {
+            None
+        };
+        self.outgoing.get_mut(&key)
+    }
+}
+
+fn control(sig: outcome::distr::Signal) -> Signal {
+    Signal::from(0, sig)
+}
+
+#[test]
+fn exhausted_signals_are_dropped() {
+    let mut channel = SignalChannel::new(RetryConfig {
+        ack_timeout: Duration::from_millis(0),
+        max_retries: 1,
+    });
+    channel.sequence(Signal::from(1, outcome::distr::Signal::Heartbeat), None);
+    assert_eq!(channel.due().unwrap().len(), 1);
+    assert!(channel.due().is_err());
+    assert_eq!(channel.unacked_count(), 0);
+    assert!(channel.due().unwrap().is_empty());
+}
+
+#[test]
+fn sequence_restarts_after_reset() {
+    let addr = SocketAddress::Net("127.0.0.1:9000".parse().unwrap());
+    let mut receiver = SignalChannel::new(RetryConfig::default());
+    for _ in 0..2 {
+        // reconnected peer starts counting from the beginning
+        let mut sender = SignalChannel::new(RetryConfig::default());
+        let sig = sender.sequence(Signal::from(1, outcome::distr::Signal::Heartbeat), None);
+        receiver.receive(addr.clone(), sig);
+        assert!(receiver.pop_ready().is_some());
+        receiver.reset(&addr);
+    }
+}
+
+#[test]
+fn priority_and_deadline_travel_with_signal() {
+    use outcome::distr::Signal::*;
+
+    let step = Signal::from(1, StartProcessStep(Vec::new())).with_deadline(5);
+    assert_eq!(step.priority(), Priority::Critical);
+    assert!(!step.is_expired(5));
+    assert!(step.is_expired(6));
+
+    let data = Signal::from(2, DataRequestAll);
+    assert_eq!(data.priority(), Priority::Bulk);
+    assert_eq!(data.deadline(), None);
+    assert!(!data.is_expired(usize::MAX));
+
+    let beat = Signal::from(3, Heartbeat).with_priority(Priority::Critical);
+    let bytes = beat.to_bytes(&Encoding::Bincode).unwrap();
+    let beat = Signal::from_bytes(&bytes, &Encoding::Bincode).unwrap();
+    assert_eq!(beat.priority(), Priority::Critical);
+
+    // critical signals keep their place
+    let mut incoming = vec![data, step, beat];
+    sort_by_priority(&mut incoming);
+    assert_eq!(
+        incoming.iter().map(|s| s.task_id()).collect::<Vec<_>>(),
+        vec![2, 1, 3]
+    );
+}
+
+#[test]
+fn reads_are_not_reordered_across_state_changes() {
+    use outcome::distr::Signal::*;
+
+    let ids = |signals: &[Signal]| signals.iter().map(|s| s.task_id()).collect::<Vec<_>>();
+
+    // read received before the step is handled before it, the one received
+    // after it is handled after it
+    let mut incoming = vec![
+        Signal::from(1, DataRequestAll),
+        Signal::from(2, StartProcessStep(Vec::new())),
+        Signal::from(3, DataRequestSelect(Vec::new())),
+        Signal::from(4, UpdateModel(Default::default())),
+        Signal::from(5, SnapshotRequest),
+    ];
+    sort_by_priority(&mut incoming);
+    assert_eq!(ids(&incoming), vec![1, 2, 3, 4, 5]);
+
+    // between critical signals higher priority goes first, stable within
+    // a priority
+    let mut incoming = vec![
+        Signal::from(1, DataRequestAll),
+        Signal::from(2, Heartbeat),
+        Signal::from(3, SnapshotRequest),
+        Signal::from(4, Heartbeat),
+        Signal::from(5, SpawnEntities(Vec::new())),
+        Signal::from(6, DataRequestAll),
+        Signal::from(7, Heartbeat),
+    ];
+    sort_by_priority(&mut incoming);
+    assert_eq!(ids(&incoming), vec![2, 4, 1, 3, 5, 7, 6]);
+}
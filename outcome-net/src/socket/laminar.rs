@@ -100,6 +100,11 @@ impl LaminarSocket {
         Ok(())
     }
 
+    /// Returns addresses of all the connected sockets.
+    pub fn connections(&self) -> &[SocketAddress] {
+        &self.connections
+    }
+
     pub fn connect(&mut self, addr: SocketAddress) -> Result<()> {
         //self.endpoint_addr = Some(addr.parse().unwrap());
         self.connections.push(addr);
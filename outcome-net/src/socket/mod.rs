@@ -1,5 +1,5 @@
 use crate::msg::{msg_bytes_from_payload, Message, Payload};
-use crate::sig::Signal;
+use crate::sig::{RetryConfig, Signal, SignalChannel};
 use crate::{sig, Error, Result, TaskId};
 use serde::{Deserialize, Serialize};
 use serde_repr::*;
@@ -8,14 +8,20 @@ use std::fmt::{Display, Formatter};
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 #[cfg(feature = "laminar_transport")]
 pub mod laminar;
+#[cfg(feature = "websocket_transport")]
+pub mod websocket;
 #[cfg(feature = "zmq_transport")]
 pub mod zmq;
 
 mod tcp;
+pub mod traffic;
+
+use traffic::{Traffic, TrafficStats};
 
 #[derive(Copy, Clone)]
 pub struct SocketConfig {
@@ -26,6 +32,9 @@ pub struct SocketConfig {
     pub try_timeout: Option<Duration>,
     pub idle_timeout: Option<Duration>,
     pub heartbeat_interval: Option<Duration>,
+    /// Acknowledgement and retry settings for signals sent over transports
+    /// that don't guarantee delivery, `None` disables acknowledgements
+    pub sig_retry: Option<RetryConfig>,
 }
 
 impl Default for SocketConfig {
@@ -36,14 +45,22 @@ impl Default for SocketConfig {
             try_timeout: None,
             idle_timeout: Some(Duration::from_secs(3)),
             heartbeat_interval: Some(Duration::from_secs(1)),
+            sig_retry: Some(RetryConfig::default()),
         }
     }
 }
 
+/// Callback invoked from a socket's background thread whenever new events
+/// become available for receiving.
+pub type Waker = Arc<dyn Fn() + Send + Sync>;
+
 /// Main socket abstraction.
 pub struct Socket {
     inner: InnerSocket,
     last_heartbeat: Instant,
+    /// Acknowledged signal delivery, only used with unreliable transports
+    sig_channel: Option<SignalChannel>,
+    traffic: Traffic,
 }
 
 /// Wrapper over different socket types by transport.
@@ -53,6 +70,8 @@ pub enum InnerSocket {
     Laminar(laminar::LaminarSocket),
     #[cfg(feature = "zmq_transport")]
     Zmq(zmq::ZmqSocket),
+    #[cfg(feature = "websocket_transport")]
+    WebSocket(websocket::WsSocket),
 }
 
 impl Socket {
@@ -66,6 +85,8 @@ impl Socket {
                 zmq::ZmqTransport::Tcp => Transport::ZmqTcp,
                 zmq::ZmqTransport::Ipc => Transport::ZmqIpc,
             },
+            #[cfg(feature = "websocket_transport")]
+            InnerSocket::WebSocket(socket) => Transport::WebSocket,
             _ => unimplemented!(),
         }
     }
@@ -77,6 +98,8 @@ impl Socket {
             InnerSocket::Laminar(socket) => socket.config,
             #[cfg(feature = "zmq_transport")]
             InnerSocket::Zmq(socket) => socket.config,
+            #[cfg(feature = "websocket_transport")]
+            InnerSocket::WebSocket(socket) => socket.config,
             _ => unimplemented!(),
         }
     }
@@ -128,11 +151,23 @@ impl Socket {
                     )?)
                 }
             }
+            Transport::WebSocket => {
+                #[cfg(not(feature = "websocket_transport"))]
+                return Err(Error::TransportUnavailable(transport));
+                #[cfg(feature = "websocket_transport")]
+                InnerSocket::WebSocket(websocket::WsSocket::new_with_config(addr, config)?)
+            }
             _ => unimplemented!(),
         };
+        let sig_channel = match config.sig_retry {
+            Some(retry) if !transport.is_reliable() => Some(SignalChannel::new(retry)),
+            _ => None,
+        };
         Ok(Self {
             inner,
             last_heartbeat: Instant::now(),
+            sig_channel,
+            traffic: Traffic::default(),
         })
     }
 
@@ -143,6 +178,8 @@ impl Socket {
             InnerSocket::Laminar(socket) => socket.encoding(),
             #[cfg(feature = "zmq_transport")]
             InnerSocket::Zmq(socket) => socket.encoding(),
+            #[cfg(feature = "websocket_transport")]
+            InnerSocket::WebSocket(socket) => socket.encoding(),
             _ => unimplemented!(),
         }
     }
@@ -154,6 +191,8 @@ impl Socket {
             InnerSocket::Laminar(socket) => socket.listener_addr(),
             #[cfg(feature = "zmq_transport")]
             InnerSocket::Zmq(socket) => socket.listener_addr(),
+            #[cfg(feature = "websocket_transport")]
+            InnerSocket::WebSocket(socket) => socket.listener_addr(),
             _ => unimplemented!(),
         }
     }
@@ -166,6 +205,31 @@ impl Socket {
         })
     }
 
+    /// Sets the callback invoked whenever new events arrive at the socket,
+    /// allowing the owner to wait for events instead of polling.
+    ///
+    /// Returns false if the underlying transport doesn't support wakers,
+    /// in which case the socket still has to be polled.
+    pub fn set_waker(&mut self, waker: Waker) -> bool {
+        match &mut self.inner {
+            InnerSocket::SimpleTcp(socket) => {
+                socket.set_waker(waker);
+                true
+            }
+            #[cfg(feature = "websocket_transport")]
+            InnerSocket::WebSocket(socket) => {
+                socket.set_waker(waker);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the amount of data sent and received through the socket.
+    pub fn traffic(&mut self) -> TrafficStats {
+        self.traffic.stats()
+    }
+
     pub fn manual_poll(&mut self) -> Result<()> {
         // send heartbeats
         if let Some(heartbeat) = self.config().heartbeat_interval {
@@ -177,6 +241,7 @@ impl Socket {
                 self.send_event(heartbeat, None)?;
             }
         }
+        self.resend_due_sigs()?;
         Ok(())
     }
 
@@ -193,6 +258,8 @@ impl Socket {
             InnerSocket::Laminar(socket) => socket.connect(addr)?,
             #[cfg(feature = "zmq_transport")]
             InnerSocket::Zmq(socket) => socket.connect(addr)?,
+            #[cfg(feature = "websocket_transport")]
+            InnerSocket::WebSocket(socket) => socket.connect(addr)?,
             _ => unimplemented!(),
         }
         Ok(())
@@ -224,6 +291,8 @@ impl Socket {
             InnerSocket::Laminar(socket) => socket.disconnect(addr)?,
             #[cfg(feature = "zmq_transport")]
             InnerSocket::Zmq(socket) => socket.disconnect(addr)?,
+            #[cfg(feature = "websocket_transport")]
+            InnerSocket::WebSocket(socket) => socket.disconnect(addr)?,
             _ => unimplemented!(),
         }
         Ok(())
@@ -237,14 +306,18 @@ impl Socket {
     /// Return type is a tuple that includes the address of the socket where
     /// the received event came from.
     pub fn recv(&mut self) -> Result<(SocketAddress, SocketEvent)> {
-        match &mut self.inner {
+        let (addr, event) = match &mut self.inner {
             InnerSocket::SimpleTcp(socket) => socket.recv(),
             #[cfg(feature = "laminar_transport")]
             InnerSocket::Laminar(socket) => socket.recv(),
             #[cfg(feature = "zmq_transport")]
             InnerSocket::Zmq(socket) => socket.recv(),
+            #[cfg(feature = "websocket_transport")]
+            InnerSocket::WebSocket(socket) => socket.recv(),
             _ => unimplemented!(),
-        }
+        }?;
+        self.traffic.record_in(event.bytes.len());
+        Ok((addr, event))
     }
 
     /// Receives the newest message from the socket, blocking until a message
@@ -257,14 +330,18 @@ impl Socket {
     /// to the backlog can still be read using the regular socket event
     /// receiving functions.
     pub fn recv_msg(&mut self) -> Result<(SocketAddress, Message)> {
-        match &mut self.inner {
+        let (addr, msg) = match &mut self.inner {
             InnerSocket::SimpleTcp(ref mut socket) => socket.recv_msg(),
             #[cfg(feature = "laminar_transport")]
             InnerSocket::Laminar(socket) => socket.recv_msg(),
             #[cfg(feature = "zmq_transport")]
             InnerSocket::Zmq(socket) => socket.recv_msg(),
+            #[cfg(feature = "websocket_transport")]
+            InnerSocket::WebSocket(socket) => socket.recv_msg(),
             _ => unimplemented!(),
-        }
+        }?;
+        self.traffic.record_in(msg_size(&msg));
+        Ok((addr, msg))
     }
 
     /// Receives the newest signal from the socket, blocking until a signal
@@ -276,48 +353,157 @@ impl Socket {
     /// function will be placed in an internal event backlog. Events pushed
     /// to the backlog can still be read using the regular socket event
     /// receiving functions.
+    ///
+    /// # Acknowledgements
+    ///
+    /// With transports requiring signal acknowledgements, signals are
+    /// returned in the order they were sent, and unacknowledged signals sent
+    /// from this socket are resent while waiting.
     pub fn recv_sig(&mut self) -> Result<(SocketAddress, Signal)> {
-        match &mut self.inner {
+        if self.sig_channel.is_some() {
+            loop {
+                match self.try_recv_sig() {
+                    Err(Error::WouldBlock) => std::thread::sleep(Duration::from_millis(1)),
+                    result => return result,
+                }
+            }
+        }
+        let (addr, sig) = match &mut self.inner {
             #[cfg(feature = "laminar_transport")]
             InnerSocket::Laminar(socket) => socket.recv_sig(),
             #[cfg(feature = "zmq_transport")]
             InnerSocket::Zmq(socket) => socket.recv_sig(),
+            #[cfg(feature = "websocket_transport")]
+            InnerSocket::WebSocket(socket) => socket.recv_sig(),
             InnerSocket::SimpleTcp(sock) => sock.recv_sig(),
             _ => unimplemented!(),
-        }
+        }?;
+        self.traffic.record_in(sig_size(&sig));
+        Ok((addr, sig))
     }
 
     /// Tries to receive the newest event from the socket without blocking.
     /// If no event is currently available returns an error.
     pub fn try_recv(&mut self) -> Result<(SocketAddress, SocketEvent)> {
-        match &mut self.inner {
+        let (addr, event) = match &mut self.inner {
             InnerSocket::SimpleTcp(ref mut socket) => socket.try_recv(),
             #[cfg(feature = "laminar_transport")]
             InnerSocket::Laminar(socket) => socket.try_recv(),
             #[cfg(feature = "zmq_transport")]
             InnerSocket::Zmq(socket) => socket.try_recv(),
+            #[cfg(feature = "websocket_transport")]
+            InnerSocket::WebSocket(socket) => socket.try_recv(),
+        }?;
+        self.traffic.record_in(event.bytes.len());
+        // new connection starts it's signal sequence from the beginning
+        match event.type_ {
+            SocketEventType::Connect | SocketEventType::Disconnect | SocketEventType::Timeout => {
+                if let Some(channel) = &mut self.sig_channel {
+                    channel.reset(&addr);
+                }
+            }
+            _ => (),
         }
+        Ok((addr, event))
     }
 
     /// Tries to receive the newest message from the socket without blocking.
     /// If no message is currently available returns an error.
     pub fn try_recv_msg(&mut self) -> Result<(SocketAddress, Message)> {
-        match &mut self.inner {
+        let (addr, msg) = match &mut self.inner {
             InnerSocket::SimpleTcp(ref mut socket) => socket.try_recv_msg(),
             #[cfg(feature = "laminar_transport")]
             InnerSocket::Laminar(socket) => socket.try_recv_msg(),
             #[cfg(feature = "zmq_transport")]
             InnerSocket::Zmq(socket) => socket.try_recv_msg(),
-        }
+            #[cfg(feature = "websocket_transport")]
+            InnerSocket::WebSocket(socket) => socket.try_recv_msg(),
+        }?;
+        self.traffic.record_in(msg_size(&msg));
+        Ok((addr, msg))
     }
 
     pub fn try_recv_sig(&mut self) -> Result<(SocketAddress, Signal)> {
-        match &mut self.inner {
+        if self.sig_channel.is_none() {
+            return self.try_recv_sig_inner();
+        }
+        self.resend_due_sigs()?;
+        loop {
+            if let Some(ready) = self.sig_channel.as_mut().and_then(|c| c.pop_ready()) {
+                return Ok(ready);
+            }
+            let (addr, sig) = self.try_recv_sig_inner()?;
+            self.receive_sequenced(addr, sig)?;
+        }
+    }
+
+    fn try_recv_sig_inner(&mut self) -> Result<(SocketAddress, Signal)> {
+        let (addr, sig) = match &mut self.inner {
             InnerSocket::SimpleTcp(socket) => socket.try_recv_sig(),
             #[cfg(feature = "laminar_transport")]
             InnerSocket::Laminar(socket) => socket.try_recv_sig(),
+            #[cfg(feature = "websocket_transport")]
+            InnerSocket::WebSocket(socket) => socket.try_recv_sig(),
             _ => unimplemented!(),
+        }?;
+        self.traffic.record_in(sig_size(&sig));
+        Ok((addr, sig))
+    }
+
+    /// Passes a signal received outside of the regular signal receiving
+    /// functions, e.g. decoded from a raw socket event, through the
+    /// acknowledgement layer, returning all the signals that are now ready
+    /// to be handled, in order.
+    ///
+    /// If the socket doesn't use acknowledgements the signal is returned
+    /// back as is.
+    pub fn accept_sig(
+        &mut self,
+        addr: SocketAddress,
+        sig: Signal,
+    ) -> Result<Vec<(SocketAddress, Signal)>> {
+        if self.sig_channel.is_none() {
+            return Ok(vec![(addr, sig)]);
+        }
+        self.receive_sequenced(addr, sig)?;
+        let mut ready = Vec::new();
+        while let Some(next) = self.sig_channel.as_mut().and_then(|c| c.pop_ready()) {
+            ready.push(next);
+        }
+        Ok(ready)
+    }
+
+    fn receive_sequenced(&mut self, addr: SocketAddress, sig: Signal) -> Result<()> {
+        let responses = match &mut self.sig_channel {
+            Some(channel) => channel.receive(addr, sig),
+            None => return Ok(()),
+        };
+        for (target, sig) in responses {
+            let bytes = sig.to_bytes(self.encoding())?;
+            self.send_bytes(bytes, target)?;
+        }
+        Ok(())
+    }
+
+    /// Resends signals that weren't acknowledged in time.
+    ///
+    /// Called as part of [`Socket::manual_poll`] and when receiving
+    /// signals, otherwise needs to be called periodically.
+    pub fn resend_due_sigs(&mut self) -> Result<()> {
+        let due = match &mut self.sig_channel {
+            Some(channel) => channel.due()?,
+            None => return Ok(()),
+        };
+        for (addr, sig) in due {
+            debug!(
+                "{} resending unacknowledged signal #{}",
+                crate::trace::Display(sig.trace_id()),
+                sig.seq()
+            );
+            let bytes = sig.to_bytes(self.encoding())?;
+            self.send_bytes(bytes, addr)?;
         }
+        Ok(())
     }
 
     /// Sends data over to a connected socket.
@@ -327,26 +513,60 @@ impl Socket {
     /// For socket types supporting multiple connections, the address of the
     /// target socket must be specified.
     pub fn send_bytes(&self, bytes: Vec<u8>, addr: Option<SocketAddress>) -> Result<()> {
+        self.traffic.record_out(bytes.len());
         match &self.inner {
             InnerSocket::SimpleTcp(socket) => socket.send_bytes(bytes, addr),
             #[cfg(feature = "laminar_transport")]
             InnerSocket::Laminar(socket) => socket.send_bytes(bytes, addr),
             #[cfg(feature = "zmq_transport")]
             InnerSocket::Zmq(socket) => socket.send_bytes(bytes, addr),
+            #[cfg(feature = "websocket_transport")]
+            InnerSocket::WebSocket(socket) => socket.send_bytes(bytes, addr),
         }
     }
 
+    /// Sends the same data to all the connected sockets.
+    ///
+    /// With zmq transports the fan-out is handled by the underlying `PUB`
+    /// socket. Other transports send the already encoded bytes to each of
+    /// the connections in turn. Failing to send to any single connection
+    /// is logged and doesn't prevent sending to the remaining ones.
+    pub fn publish_bytes(&self, bytes: Vec<u8>) -> Result<()> {
+        let connections = match &self.inner {
+            InnerSocket::SimpleTcp(socket) => socket.connections().to_vec(),
+            #[cfg(feature = "laminar_transport")]
+            InnerSocket::Laminar(socket) => socket.connections().to_vec(),
+            #[cfg(feature = "zmq_transport")]
+            InnerSocket::Zmq(_) => return self.send_bytes(bytes, None),
+            #[cfg(feature = "websocket_transport")]
+            InnerSocket::WebSocket(socket) => socket.connections().to_vec(),
+        };
+        for addr in connections {
+            if let Err(e) = self.send_bytes(bytes.clone(), Some(addr.clone())) {
+                warn!("failed publishing to {}: {}", addr, e);
+            }
+        }
+        Ok(())
+    }
+
     pub fn send_event(&self, event: SocketEvent, addr: Option<SocketAddress>) -> Result<()> {
+        self.traffic.record_out(event.bytes.len());
         match &self.inner {
             InnerSocket::SimpleTcp(socket) => socket.send_event(event, addr),
             #[cfg(feature = "laminar_transport")]
             InnerSocket::Laminar(socket) => socket.send_event(event, addr),
             #[cfg(feature = "zmq_transport")]
             InnerSocket::Zmq(socket) => socket.send_event(event, addr),
+            #[cfg(feature = "websocket_transport")]
+            InnerSocket::WebSocket(socket) => socket.send_event(event, addr),
         }
     }
 
     pub fn send_sig(&mut self, sig: sig::Signal, addr: Option<SocketAddress>) -> Result<()> {
+        let sig = match &mut self.sig_channel {
+            Some(channel) => channel.sequence(sig, addr.clone()),
+            None => sig,
+        };
         let bytes = sig.to_bytes(self.encoding())?;
         trace!("sending {} byte signal", bytes.len());
         self.send_bytes(bytes, addr)
@@ -424,6 +644,11 @@ pub enum SocketType {
     Stream,
     Router,
     Dealer,
+    /// Sends the same data to all connected subscribers, see
+    /// [`Socket::publish_bytes`]
+    Pub,
+    /// Receives data from a publishing socket, can't send
+    Sub,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -524,7 +749,6 @@ impl Display for SocketAddress {
 }
 
 /// List of possible network transports.
-// TODO websockets
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize_repr, Serialize_repr)]
 #[repr(u8)]
 pub enum Transport {
@@ -541,6 +765,8 @@ pub enum Transport {
     NngIpc,
     /// NNG based WebSocket transport
     NngWs,
+    /// WebSocket transport usable by browser-based clients
+    WebSocket,
 }
 
 impl Display for Transport {
@@ -552,6 +778,7 @@ impl Display for Transport {
             Self::ZmqIpc => write!(f, "zmq_ipc"),
             Self::NngIpc => write!(f, "nng_ipc"),
             Self::NngWs => write!(f, "nng_ws"),
+            Self::WebSocket => write!(f, "ws"),
         }
     }
 }
@@ -579,6 +806,15 @@ impl FromStr for Transport {
                     s
                 )));
             }
+            "ws" | "websocket" => {
+                #[cfg(feature = "websocket_transport")]
+                return Ok(Transport::WebSocket);
+                #[cfg(not(feature = "websocket_transport"))]
+                return Err(Error::Other(format!(
+                    "trying to use transport: {}, but crate feature websocket_transport is not enabled",
+                    s
+                )));
+            }
             "laminar" | "udp" => {
                 #[cfg(feature = "laminar_transport")]
                 return Ok(Transport::LaminarUdp);
@@ -599,6 +835,16 @@ impl FromStr for Transport {
 }
 
 impl Transport {
+    /// Checks whether the transport guarantees delivery of the data sent
+    /// over it. Signals sent over unreliable transports are acknowledged
+    /// and retried, see [`SignalChannel`].
+    pub fn is_reliable(&self) -> bool {
+        match self {
+            Transport::LaminarUdp => false,
+            _ => true,
+        }
+    }
+
     /// Checks if laminar transport is available, otherwise falls back on tcp.
     pub fn prefer_laminar() -> Self {
         #[cfg(feature = "laminar_transport")]
@@ -651,6 +897,20 @@ impl Display for Encoding {
 }
 
 /// Packs serializable object to bytes based on selected encoding.
+/// Approximate encoded size of a message, used for traffic accounting.
+fn msg_size(msg: &Message) -> usize {
+    // task id, trace id and message type
+    std::mem::size_of::<TaskId>()
+        + std::mem::size_of::<crate::trace::TraceId>()
+        + 1
+        + msg.payload.len()
+}
+
+/// Encoded size of a signal, used for traffic accounting.
+fn sig_size(sig: &Signal) -> usize {
+    bincode::serialized_size(sig).unwrap_or(0) as usize
+}
+
 pub(crate) fn pack<S: Serialize>(obj: S, encoding: &Encoding) -> Result<Vec<u8>> {
     let packed: Vec<u8> = match encoding {
         Encoding::Bincode => bincode::serialize(&obj)?,
@@ -3,6 +3,7 @@ use std::convert::{TryFrom, TryInto};
 use std::io::{Read, Write};
 use std::net::{SocketAddr, TcpListener, TcpStream};
 use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread::{sleep, yield_now, JoinHandle};
 use std::time::Duration;
 
@@ -11,6 +12,7 @@ use byteorder::{ByteOrder, LittleEndian};
 use crate::msg::{Message, MessageType};
 use crate::socket::{
     CompositeSocketAddress, Encoding, SocketAddress, SocketConfig, SocketEvent, SocketEventType,
+    Waker,
 };
 use crate::{
     error::{Error, Result},
@@ -27,6 +29,7 @@ pub struct TcpSocket {
     in_receiver: Receiver<(SocketAddress, SocketEvent)>,
     out_sender: Sender<(SocketAddress, SocketEvent)>,
     event_backlog: VecDeque<(SocketAddress, SocketEvent)>,
+    waker: Arc<Mutex<Option<Waker>>>,
 }
 
 impl TcpSocket {
@@ -61,6 +64,8 @@ impl TcpSocket {
 
         // let addr = listener.local_addr().unwrap();
 
+        let waker = Arc::new(Mutex::new(None));
+
         let mut handler = ConnectionHandler {
             listener,
             connections: Default::default(),
@@ -69,6 +74,7 @@ impl TcpSocket {
             out_sender: out_sender.clone(),
             heartbeat_interval: config.heartbeat_interval,
             time_since_heartbeat: Default::default(),
+            waker: waker.clone(),
         };
 
         // Starts the poll mechanism to receive and send messages
@@ -82,13 +88,24 @@ impl TcpSocket {
             in_receiver,
             out_sender,
             event_backlog: VecDeque::new(),
+            waker,
         })
     }
 
+    /// Sets the callback invoked whenever new events arrive.
+    pub fn set_waker(&mut self, waker: Waker) {
+        *self.waker.lock().unwrap() = Some(waker);
+    }
+
     // pub fn bind(&mut self, addr: SocketAddress) -> Result<()> {
     //     self.
     // }
 
+    /// Returns addresses of all the connected sockets.
+    pub fn connections(&self) -> &[SocketAddress] {
+        &self.connections
+    }
+
     pub fn connect(&mut self, addr: SocketAddress) -> Result<()> {
         self.connections.push(addr.clone());
         self.out_sender
@@ -314,6 +331,7 @@ struct ConnectionHandler {
     out_sender: Sender<(SocketAddress, SocketEvent)>,
     heartbeat_interval: Option<Duration>,
     time_since_heartbeat: Duration,
+    waker: Arc<Mutex<Option<Waker>>>,
 }
 
 impl ConnectionHandler {
@@ -356,6 +374,7 @@ impl ConnectionHandler {
         // }
 
         // read incoming events
+        let mut received = false;
         for (addr, (stream, buffer)) in &mut self.connections {
             // read from stream into the connection buffer
             // TODO perhaps don't read more if the buffer is really backed up
@@ -383,6 +402,7 @@ impl ConnectionHandler {
                             .send((addr.clone(), event))
                             .map_err(|e| Error::Other(e.to_string()))?;
                         buffer.drain(..len as usize + 4);
+                        received = true;
                     } else {
                         continue;
                     }
@@ -390,6 +410,12 @@ impl ConnectionHandler {
             }
         }
 
+        if received {
+            if let Some(wake) = self.waker.lock().unwrap().as_ref() {
+                wake();
+            }
+        }
+
         // grab all the waiting events and send them over
         while let Ok((address, event)) = self.out_receiver.try_recv() {
             match &event.type_ {
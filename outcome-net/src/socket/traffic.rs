@@ -0,0 +1,134 @@
+//! Accounting of data sent and received over a socket.
+//!
+//! Byte counts cover the encoded events, messages and signals as passed
+//! between the socket and the transport, without any framing added by the
+//! transport itself.
+
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Minimum time between rate recalculations.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Counters of data going through a single socket.
+pub struct Traffic {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    /// Time and totals at the start of the current rate window
+    window_start: (Instant, u64, u64),
+    /// Rates calculated over the last complete window, in bytes per second
+    rates: (f64, f64),
+}
+
+impl Default for Traffic {
+    fn default() -> Self {
+        Self {
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            window_start: (Instant::now(), 0, 0),
+            rates: (0., 0.),
+        }
+    }
+}
+
+impl Traffic {
+    pub fn record_in(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_out(&self, bytes: usize) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Returns current totals and rates, recalculating the rates if enough
+    /// time has passed since the last calculation.
+    pub fn stats(&mut self) -> TrafficStats {
+        let bytes_in = self.bytes_in.load(Ordering::Relaxed);
+        let bytes_out = self.bytes_out.load(Ordering::Relaxed);
+        let (start, start_in, start_out) = self.window_start;
+        let elapsed = start.elapsed();
+        if elapsed >= RATE_WINDOW {
+            let secs = elapsed.as_secs_f64();
+            self.rates = (
+                (bytes_in - start_in) as f64 / secs,
+                (bytes_out - start_out) as f64 / secs,
+            );
+            self.window_start = (Instant::now(), bytes_in, bytes_out);
+        }
+        TrafficStats {
+            bytes_in,
+            bytes_out,
+            rate_in: self.rates.0,
+            rate_out: self.rates.1,
+        }
+    }
+}
+
+/// Snapshot of socket traffic counters.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrafficStats {
+    /// Total bytes received
+    pub bytes_in: u64,
+    /// Total bytes sent
+    pub bytes_out: u64,
+    /// Receive rate in bytes per second
+    pub rate_in: f64,
+    /// Send rate in bytes per second
+    pub rate_out: f64,
+}
+
+impl Display for TrafficStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "in: {} ({}/s), out: {} ({}/s)",
+            human_bytes(self.bytes_in as f64),
+            human_bytes(self.rate_in),
+            human_bytes(self.bytes_out as f64),
+            human_bytes(self.rate_out)
+        )
+    }
+}
+
+fn human_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024. && unit < UNITS.len() - 1 {
+        value /= 1024.;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{:.0} {}", value, UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[test]
+fn totals_and_rates_are_tracked() {
+    let mut traffic = Traffic::default();
+    traffic.record_in(1000);
+    traffic.record_in(24);
+    traffic.record_out(3 * 1024 * 1024);
+
+    // rates are only calculated once the window is complete
+    let stats = traffic.stats();
+    assert_eq!(stats.bytes_in, 1024);
+    assert_eq!(stats.bytes_out, 3 * 1024 * 1024);
+    assert_eq!(stats.rate_in, 0.);
+
+    traffic.window_start.0 = Instant::now() - 2 * RATE_WINDOW;
+    let stats = traffic.stats();
+    assert!(stats.rate_in > 400. && stats.rate_in <= 512.);
+    assert_eq!(
+        TrafficStats {
+            rate_in: 512.,
+            rate_out: 0.,
+            ..stats
+        }
+        .to_string(),
+        "in: 1.0 KiB (512 B/s), out: 3.0 MiB (0 B/s)"
+    );
+}
@@ -0,0 +1,557 @@
+//! WebSocket transport.
+//!
+//! Allows browser-based clients, such as dashboards, to connect to a server
+//! directly, without any proxies in between.
+//!
+//! Unlike with the basic tcp transport, socket events are not sent over the
+//! wire as they are. Byte events are sent as binary frames containing just
+//! the encoded message or signal, so that browser clients only need to know
+//! about the message encoding. Text frames received from the other side are
+//! treated the same as binary ones. Heartbeats are sent as pings and
+//! disconnects as close frames.
+//!
+//! Each connection is served by a thread of its own, which blocks reading
+//! from the connection for up to [`READ_TIMEOUT`] before sending out the
+//! events queued up for it in the meantime. Handshakes are done on that
+//! same thread, so a slow peer never holds up other connections. Events
+//! sent to an outgoing connection before it's established are queued up,
+//! and a failed connection attempt is reported as a disconnect event.
+
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use fnv::FnvHashMap;
+use tungstenite::{Message as WsMessage, WebSocket};
+
+use crate::msg::Message;
+use crate::sig::Signal;
+use crate::socket::{Encoding, SocketAddress, SocketConfig, SocketEvent, SocketEventType, Waker};
+use crate::{Error, Result};
+
+/// Max time the initial handshake on a new connection can take.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Max time a connection's thread blocks on reading before sending out
+/// queued up events. Also the interval at which new incoming connections
+/// are accepted.
+const READ_TIMEOUT: Duration = Duration::from_millis(5);
+
+/// WebSocket based socket.
+pub struct WsSocket {
+    pub config: SocketConfig,
+    listener_addr: Option<SocketAddress>,
+    connections: Vec<SocketAddress>,
+    poll_handle: Option<JoinHandle<()>>,
+    in_receiver: Receiver<(SocketAddress, SocketEvent)>,
+    out_sender: Sender<(SocketAddress, SocketEvent)>,
+    event_backlog: VecDeque<(SocketAddress, SocketEvent)>,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl WsSocket {
+    pub fn new_with_config(addr: Option<SocketAddress>, config: SocketConfig) -> Result<Self> {
+        let listener = match addr {
+            Some(_addr) => {
+                let socket_addr: SocketAddr = _addr.try_into()?;
+                let listener = TcpListener::bind(socket_addr)?;
+                listener.set_nonblocking(true)?;
+                Some(listener)
+            }
+            None => None,
+        };
+        let listener_addr = match &listener {
+            Some(l) => Some(SocketAddress::Net(l.local_addr()?)),
+            None => None,
+        };
+
+        let (out_sender, out_receiver) = channel();
+        let (in_sender, in_receiver) = channel();
+        let waker = Arc::new(Mutex::new(None));
+
+        let mut handler = ConnectionHandler {
+            listener,
+            connections: Default::default(),
+            in_sender,
+            out_receiver,
+            waker: waker.clone(),
+        };
+        let poll_handle = std::thread::spawn(move || handler.start_polling());
+
+        Ok(Self {
+            config,
+            listener_addr,
+            connections: Vec::new(),
+            poll_handle: Some(poll_handle),
+            in_receiver,
+            out_sender,
+            event_backlog: VecDeque::new(),
+            waker,
+        })
+    }
+
+    /// Sets the callback invoked whenever new events arrive.
+    pub fn set_waker(&mut self, waker: Waker) {
+        *self.waker.lock().unwrap() = Some(waker);
+    }
+
+    /// Returns addresses of all the connected sockets.
+    pub fn connections(&self) -> &[SocketAddress] {
+        &self.connections
+    }
+
+    pub fn encoding(&self) -> &Encoding {
+        &self.config.encoding
+    }
+
+    pub fn listener_addr(&self) -> Result<SocketAddress> {
+        self.listener_addr
+            .clone()
+            .ok_or(Error::SocketNotBoundToAddress)
+    }
+
+    pub fn connect(&mut self, addr: SocketAddress) -> Result<()> {
+        self.connections.push(addr.clone());
+        self.out_sender
+            .send((addr, SocketEvent::new(SocketEventType::Connect)))
+            .map_err(|e| Error::Other(e.to_string()))
+    }
+
+    pub fn disconnect(&mut self, addr: Option<SocketAddress>) -> Result<()> {
+        let addr = match addr {
+            Some(a) => a,
+            None => self
+                .connections
+                .first()
+                .cloned()
+                .ok_or(Error::SocketNotConnected)?,
+        };
+        if let Some(idx) = self.connections.iter().position(|a| a == &addr) {
+            self.connections.remove(idx);
+            self.out_sender
+                .send((addr, SocketEvent::new(SocketEventType::Disconnect)))
+                .map_err(|e| Error::Other(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the next socket event, blocking until one is available.
+    ///
+    /// Events skipped over while receiving messages or signals are returned
+    /// first.
+    pub fn recv(&mut self) -> Result<(SocketAddress, SocketEvent)> {
+        match self.event_backlog.pop_front() {
+            Some(backlogged) => Ok(backlogged),
+            None => self.recv_event(),
+        }
+    }
+
+    pub fn recv_msg(&mut self) -> Result<(SocketAddress, Message)> {
+        loop {
+            let (addr, event) = self.recv_event()?;
+            match event.type_ {
+                SocketEventType::Bytes => {
+                    return Ok((addr, Message::from_bytes(event.bytes, self.encoding())?))
+                }
+                _ => self.backlog(addr, event),
+            }
+        }
+    }
+
+    pub fn recv_sig(&mut self) -> Result<(SocketAddress, Signal)> {
+        loop {
+            let (addr, event) = self.recv_event()?;
+            match event.type_ {
+                SocketEventType::Bytes => {
+                    return Ok((addr, Signal::from_bytes(&event.bytes, self.encoding())?))
+                }
+                _ => self.backlog(addr, event),
+            }
+        }
+    }
+
+    /// Tries receiving next socket event, returning immediately if there are
+    /// none available.
+    ///
+    /// Events skipped over while receiving messages or signals are returned
+    /// first.
+    pub fn try_recv(&mut self) -> Result<(SocketAddress, SocketEvent)> {
+        match self.event_backlog.pop_front() {
+            Some(backlogged) => Ok(backlogged),
+            None => self.try_recv_event(),
+        }
+    }
+
+    pub fn try_recv_msg(&mut self) -> Result<(SocketAddress, Message)> {
+        loop {
+            let (addr, event) = self.try_recv_event()?;
+            match event.type_ {
+                SocketEventType::Bytes => {
+                    return Ok((addr, Message::from_bytes(event.bytes, self.encoding())?))
+                }
+                _ => self.backlog(addr, event),
+            }
+        }
+    }
+
+    pub fn try_recv_sig(&mut self) -> Result<(SocketAddress, Signal)> {
+        loop {
+            let (addr, event) = self.try_recv_event()?;
+            match event.type_ {
+                SocketEventType::Bytes => {
+                    return Ok((addr, Signal::from_bytes(&event.bytes, self.encoding())?))
+                }
+                _ => self.backlog(addr, event),
+            }
+        }
+    }
+
+    /// Waits for the next event coming from the polling thread.
+    fn recv_event(&mut self) -> Result<(SocketAddress, SocketEvent)> {
+        let (addr, event) = self
+            .in_receiver
+            .recv()
+            .map_err(|e| Error::Other(e.to_string()))?;
+        self.handle_internally(&addr, &event);
+        if let SocketEventType::Disconnect = event.type_ {
+            return Err(Error::HostUnreachable);
+        }
+        Ok((addr, event))
+    }
+
+    /// Tries receiving the next event coming from the polling thread.
+    fn try_recv_event(&mut self) -> Result<(SocketAddress, SocketEvent)> {
+        let (addr, event) = self.in_receiver.try_recv().map_err(|_| Error::WouldBlock)?;
+        self.handle_internally(&addr, &event);
+        Ok((addr, event))
+    }
+
+    /// Keeps an event skipped over while receiving messages or signals, so
+    /// that it's returned by the next call to `recv` or `try_recv`.
+    ///
+    /// Heartbeats are not kept, they carry nothing beyond the fact that the
+    /// connection is alive, which the skipped over message already proves.
+    fn backlog(&mut self, addr: SocketAddress, event: SocketEvent) {
+        if let SocketEventType::Heartbeat = event.type_ {
+            return;
+        }
+        self.event_backlog.push_back((addr, event));
+    }
+
+    pub fn send_bytes(&self, bytes: Vec<u8>, addr: Option<SocketAddress>) -> Result<()> {
+        self.send_event(SocketEvent::new_bytes(bytes), addr)
+    }
+
+    pub fn send_event(&self, event: SocketEvent, addr: Option<SocketAddress>) -> Result<()> {
+        let addr = match addr {
+            Some(a) => a,
+            None => self
+                .connections
+                .first()
+                .cloned()
+                .ok_or(Error::SocketNotConnected)?,
+        };
+        self.out_sender
+            .send((addr, event))
+            .map_err(|e| Error::Other(e.to_string()))
+    }
+
+    /// Some events necessitate changes to socket's state.
+    fn handle_internally(&mut self, addr: &SocketAddress, event: &SocketEvent) {
+        match &event.type_ {
+            SocketEventType::Connect => self.connections.push(addr.clone()),
+            SocketEventType::Disconnect => {
+                if let Some(idx) = self.connections.iter().position(|a| a == addr) {
+                    self.connections.remove(idx);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Accepts incoming connections and hands out events queued up by the
+/// owning socket to the threads serving the connections.
+struct ConnectionHandler {
+    listener: Option<TcpListener>,
+    /// Senders for passing events to the threads serving connections
+    connections: FnvHashMap<SocketAddress, Sender<SocketEvent>>,
+    in_sender: Sender<(SocketAddress, SocketEvent)>,
+    out_receiver: Receiver<(SocketAddress, SocketEvent)>,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl ConnectionHandler {
+    fn start_polling(&mut self) {
+        loop {
+            if let Err(e) = self.accept_incoming() {
+                error!("websocket accept error: {:?}", e);
+            }
+            match self.out_receiver.recv_timeout(READ_TIMEOUT) {
+                Ok((addr, event)) => self.dispatch(addr, event),
+                Err(RecvTimeoutError::Timeout) => (),
+                // owning socket was dropped, dropping the senders lets the
+                // connection threads know as well
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+
+    /// Passes an event to the thread serving the connection with the given
+    /// address, starting a new connection if requested.
+    fn dispatch(&mut self, addr: SocketAddress, event: SocketEvent) {
+        let sender = match (self.connections.get(&addr), &event.type_) {
+            (Some(sender), _) => sender,
+            (None, SocketEventType::Connect) => {
+                self.spawn_connection(addr, Peer::Outgoing);
+                return;
+            }
+            (None, _) => {
+                debug!("websocket: dropping event for unknown connection {}", addr);
+                return;
+            }
+        };
+        let disconnecting = matches!(event.type_, SocketEventType::Disconnect);
+        if sender.send(event).is_err() || disconnecting {
+            // connection thread is done
+            self.connections.remove(&addr);
+        }
+    }
+
+    /// Accepts new connections, if there are any.
+    fn accept_incoming(&mut self) -> Result<()> {
+        loop {
+            let (stream, peer_addr) = match &self.listener {
+                Some(listener) => match listener.accept() {
+                    Ok(s) => s,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                    Err(e) => return Err(e.into()),
+                },
+                None => return Ok(()),
+            };
+            self.spawn_connection(SocketAddress::Net(peer_addr), Peer::Incoming(stream));
+        }
+    }
+
+    fn spawn_connection(&mut self, addr: SocketAddress, peer: Peer) {
+        let (sender, receiver) = channel();
+        self.connections.insert(addr.clone(), sender);
+        let mut connection = Connection {
+            addr,
+            events: receiver,
+            in_sender: self.in_sender.clone(),
+            waker: self.waker.clone(),
+        };
+        std::thread::spawn(move || connection.serve(peer));
+    }
+}
+
+/// Side that initiated the connection.
+enum Peer {
+    /// Accepted connection, handshake is yet to be done
+    Incoming(TcpStream),
+    /// Connection to be established with the connection's address
+    Outgoing,
+}
+
+/// Single websocket connection, served on a thread of its own.
+struct Connection {
+    addr: SocketAddress,
+    /// Events queued up by the owning socket
+    events: Receiver<SocketEvent>,
+    in_sender: Sender<(SocketAddress, SocketEvent)>,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl Connection {
+    fn serve(&mut self, peer: Peer) {
+        let mut ws = match peer {
+            Peer::Incoming(stream) => match accept(stream) {
+                Ok(ws) => {
+                    debug!("accepting new websocket connection: {}", self.addr);
+                    self.report(SocketEvent::new(SocketEventType::Connect));
+                    ws
+                }
+                Err(e) => {
+                    warn!("websocket handshake with {} failed: {}", self.addr, e);
+                    return;
+                }
+            },
+            Peer::Outgoing => match connect(&self.addr) {
+                Ok(ws) => {
+                    debug!("established websocket connection: {}", self.addr);
+                    ws
+                }
+                Err(e) => {
+                    warn!("websocket: failed connecting to {}: {}", self.addr, e);
+                    self.report(SocketEvent::new(SocketEventType::Disconnect));
+                    return;
+                }
+            },
+        };
+
+        loop {
+            if !self.write_queued(&mut ws) {
+                return;
+            }
+            match ws.read_message() {
+                Ok(WsMessage::Binary(bytes)) => self.report(SocketEvent::new_bytes(bytes)),
+                Ok(WsMessage::Text(text)) => self.report(SocketEvent::new_bytes(text.into_bytes())),
+                Ok(WsMessage::Ping(_)) | Ok(WsMessage::Pong(_)) => {
+                    self.report(SocketEvent::new(SocketEventType::Heartbeat))
+                }
+                Ok(WsMessage::Close(_)) => {
+                    // reply to the close frame is queued up by the read
+                    let _ = ws.write_pending();
+                    self.report(SocketEvent::new(SocketEventType::Disconnect));
+                    return;
+                }
+                Err(tungstenite::Error::Io(e))
+                    if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+                Err(e) => {
+                    debug!("websocket connection {} lost: {}", self.addr, e);
+                    self.report(SocketEvent::new(SocketEventType::Disconnect));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Writes out all the events queued up by the owning socket, returning
+    /// whether the connection is to be kept open.
+    fn write_queued(&mut self, ws: &mut WebSocket<TcpStream>) -> bool {
+        loop {
+            let msg = match self.events.try_recv() {
+                Ok(event) => match event.type_ {
+                    SocketEventType::Bytes => WsMessage::Binary(event.bytes),
+                    SocketEventType::Heartbeat => WsMessage::Ping(Vec::new()),
+                    SocketEventType::Disconnect => WsMessage::Close(None),
+                    SocketEventType::Connect | SocketEventType::Timeout => continue,
+                },
+                Err(TryRecvError::Empty) => break,
+                // owning socket was dropped
+                Err(TryRecvError::Disconnected) => WsMessage::Close(None),
+            };
+            let closing = matches!(msg, WsMessage::Close(_));
+            if let Err(e) = ws.write_message(msg) {
+                warn!("websocket: failed sending to {}: {}", self.addr, e);
+            }
+            if closing {
+                let _ = ws.write_pending();
+                return false;
+            }
+        }
+        if let Err(e) = ws.write_pending() {
+            debug!("websocket: failed flushing to {}: {}", self.addr, e);
+        }
+        true
+    }
+
+    /// Passes an event to the owning socket.
+    fn report(&self, event: SocketEvent) {
+        // owning socket being dropped is noticed when writing
+        if self.in_sender.send((self.addr.clone(), event)).is_ok() {
+            if let Some(wake) = self.waker.lock().unwrap().as_ref() {
+                wake();
+            }
+        }
+    }
+}
+
+/// Performs the server side of the handshake on an accepted connection,
+/// blocking until it's done or times out.
+fn accept(stream: TcpStream) -> Result<WebSocket<TcpStream>> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let ws = tungstenite::accept(stream)
+        .map_err(|e| Error::Other(format!("websocket handshake failed: {}", e)))?;
+    ws.get_ref().set_read_timeout(Some(READ_TIMEOUT))?;
+    Ok(ws)
+}
+
+/// Establishes a new websocket connection with the given address, blocking
+/// until the handshake is done or times out.
+fn connect(addr: &SocketAddress) -> Result<WebSocket<TcpStream>> {
+    let socket_addr: SocketAddr = addr.clone().try_into()?;
+    let stream = TcpStream::connect_timeout(&socket_addr, HANDSHAKE_TIMEOUT)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let url = format!("ws://{}/", socket_addr);
+    let (ws, _) = tungstenite::client(url.as_str(), stream)
+        .map_err(|e| Error::Other(format!("websocket handshake failed: {}", e)))?;
+    ws.get_ref().set_read_timeout(Some(READ_TIMEOUT))?;
+    Ok(ws)
+}
+
+/// Waits for the next event on the socket, failing if none arrives in time.
+#[cfg(test)]
+fn recv_within(socket: &mut WsSocket, timeout: Duration) -> (SocketAddress, SocketEvent) {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        match socket.try_recv() {
+            Ok(received) => return received,
+            Err(Error::WouldBlock) if std::time::Instant::now() < deadline => {
+                std::thread::sleep(Duration::from_millis(1))
+            }
+            Err(e) => panic!("no event received: {:?}", e),
+        }
+    }
+}
+
+#[test]
+fn loopback_connection_exchanges_bytes_both_ways() {
+    let timeout = Duration::from_secs(5);
+    let mut server = WsSocket::new_with_config(
+        Some(SocketAddress::Net("127.0.0.1:0".parse().unwrap())),
+        SocketConfig::default(),
+    )
+    .unwrap();
+    let mut client = WsSocket::new_with_config(None, SocketConfig::default()).unwrap();
+
+    // events sent before the handshake is done are queued up
+    client.connect(server.listener_addr().unwrap()).unwrap();
+    client.send_bytes(vec![1, 2, 3], None).unwrap();
+
+    let (peer, event) = recv_within(&mut server, timeout);
+    assert!(matches!(event.type_, SocketEventType::Connect));
+    assert_eq!(server.connections(), &[peer.clone()]);
+    let (addr, event) = recv_within(&mut server, timeout);
+    assert_eq!(addr, peer);
+    assert!(matches!(event.type_, SocketEventType::Bytes));
+    assert_eq!(event.bytes, vec![1, 2, 3]);
+
+    server.send_bytes(vec![4, 5], Some(peer.clone())).unwrap();
+    let (_, event) = recv_within(&mut client, timeout);
+    assert!(matches!(event.type_, SocketEventType::Bytes));
+    assert_eq!(event.bytes, vec![4, 5]);
+
+    // disconnect is sent as a close frame
+    client.disconnect(None).unwrap();
+    assert!(client.connections().is_empty());
+    let (addr, event) = recv_within(&mut server, timeout);
+    assert_eq!(addr, peer);
+    assert!(matches!(event.type_, SocketEventType::Disconnect));
+    assert!(server.connections().is_empty());
+}
+
+#[test]
+fn failed_connection_attempt_is_reported_as_disconnect() {
+    // grab a free port, nothing is listening once the listener is dropped
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let mut client = WsSocket::new_with_config(None, SocketConfig::default()).unwrap();
+    client.connect(SocketAddress::Net(addr)).unwrap();
+    let (_, event) = recv_within(&mut client, Duration::from_secs(5));
+    assert!(matches!(event.type_, SocketEventType::Disconnect));
+    assert!(client.connections().is_empty());
+}
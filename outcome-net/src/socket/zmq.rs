@@ -53,6 +53,8 @@ impl ZmqSocket {
             SocketType::Pair => zmq::SocketType::PAIR,
             SocketType::Router => zmq::SocketType::ROUTER,
             SocketType::Dealer => zmq::SocketType::DEALER,
+            SocketType::Pub => zmq::SocketType::PUB,
+            SocketType::Sub => zmq::SocketType::SUB,
             _ => unimplemented!(),
         };
         println!("socket_type: {:?}", socket_type);
@@ -96,6 +98,11 @@ impl ZmqSocket {
         let conn_addr = prepend_transport(&addr.to_string(), &self.transport);
         println!("conn_addr: {}", conn_addr);
         self.inner.connect(&conn_addr)?;
+        // subscribers receive everything published and can't send
+        if self.config.type_ == SocketType::Sub {
+            self.inner.set_subscribe(b"")?;
+            return Ok(());
+        }
         // self.inner.set_rcvtimeo(10)?;
         // self.inner.set_sndtimeo(10)?;
         self.send_event(SocketEvent::new(SocketEventType::Connect), None)?;
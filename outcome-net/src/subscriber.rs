@@ -0,0 +1,72 @@
+//! Subscribing to data published by the server.
+
+use crate::msg::{
+    MemoryPressureNotice, Message, MessageType, StepCompletedNotice, VarStreamNotice,
+};
+use crate::socket::{
+    CompositeSocketAddress, Encoding, Socket, SocketConfig, SocketType, Transport,
+};
+use crate::{Error, Result};
+
+/// Data received from the server's publishing socket.
+#[derive(Clone, Debug)]
+pub enum Published {
+    StepCompleted(StepCompletedNotice),
+    VarStream(VarStreamNotice),
+    MemoryPressure(MemoryPressureNotice),
+}
+
+/// Receiving end of the server's publishing socket.
+///
+/// Subscribers only receive data, they can't send requests to the server.
+/// Any number of subscribers can be connected to a single server without
+/// the server having to send data to each of them separately.
+pub struct Subscriber {
+    connection: Socket,
+}
+
+impl Subscriber {
+    /// Connects to the publishing socket at the given composite address.
+    pub fn connect(address: &str) -> Result<Self> {
+        let composite: CompositeSocketAddress = address.parse()?;
+        let config = SocketConfig {
+            type_: SocketType::Sub,
+            encoding: composite.encoding.unwrap_or(Encoding::Bincode),
+            ..Default::default()
+        };
+        let mut connection =
+            Socket::new_with_config(None, composite.transport.unwrap_or(Transport::Tcp), config)?;
+        connection.connect(composite.address)?;
+        Ok(Self { connection })
+    }
+
+    /// Waits for the next published data, blocking until it's available.
+    pub fn recv(&mut self) -> Result<Published> {
+        let (_, msg) = self.connection.recv_msg()?;
+        self.unpack(msg)
+    }
+
+    /// Tries receiving the next published data, returning immediately if
+    /// there's none available.
+    pub fn try_recv(&mut self) -> Result<Published> {
+        let (_, msg) = self.connection.try_recv_msg()?;
+        self.unpack(msg)
+    }
+
+    fn unpack(&self, msg: Message) -> Result<Published> {
+        let encoding = self.connection.encoding();
+        match msg.type_ {
+            MessageType::StepCompletedNotice => {
+                Ok(Published::StepCompleted(msg.unpack_payload(encoding)?))
+            }
+            MessageType::VarStreamNotice => Ok(Published::VarStream(msg.unpack_payload(encoding)?)),
+            MessageType::MemoryPressureNotice => {
+                Ok(Published::MemoryPressure(msg.unpack_payload(encoding)?))
+            }
+            _ => Err(Error::Other(format!(
+                "unexpected published message: {:?}",
+                msg.type_
+            ))),
+        }
+    }
+}
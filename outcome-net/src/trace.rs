@@ -0,0 +1,115 @@
+//! Correlation ids for tracing requests across the cluster.
+//!
+//! Each request sent by a [`Client`] is assigned a new trace id, carried
+//! within the [`Message`]. The server handles the message within the scope
+//! of that trace id, and any messages or signals created while handling it
+//! carry the same id, be it the response sent back to the client or
+//! signals sent out to workers, which in turn pass it on to their own
+//! responses. Log lines and recorded errors include the id, which allows
+//! following a single request end-to-end through a distributed deployment.
+//!
+//! Current trace id is kept per thread. Zero means no trace.
+//!
+//! [`Client`]: crate::Client
+//! [`Message`]: crate::msg::Message
+
+use std::cell::Cell;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Correlation id carried by messages and signals.
+pub type TraceId = u64;
+
+/// Value signifying lack of trace.
+pub const NO_TRACE: TraceId = 0;
+
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static CURRENT: Cell<TraceId> = Cell::new(NO_TRACE);
+}
+
+/// Generates a new, likely unique trace id.
+pub fn new_id() -> TraceId {
+    let seq = NEXT_SEQ.fetch_add(1, Ordering::Relaxed);
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    let id = ((std::process::id() as u64) << 48 ^ time).wrapping_add(seq);
+    if id == NO_TRACE {
+        1
+    } else {
+        id
+    }
+}
+
+/// Returns the trace id for the current thread.
+pub fn current() -> TraceId {
+    CURRENT.with(|c| c.get())
+}
+
+/// Sets the trace id for the current thread until the returned guard is
+/// dropped, at which point the previous id is restored.
+pub fn enter(id: TraceId) -> TraceGuard {
+    let previous = CURRENT.with(|c| c.replace(id));
+    TraceGuard { previous }
+}
+
+/// Restores the previous trace id when dropped.
+pub struct TraceGuard {
+    previous: TraceId,
+}
+
+impl Drop for TraceGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|c| c.set(self.previous));
+    }
+}
+
+/// Formats a trace id for log output, e.g. `[trace 0a1b2c3d4e5f6071]`.
+pub struct Display(pub TraceId);
+
+impl fmt::Display for Display {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            NO_TRACE => write!(f, "[untraced]"),
+            id => write!(f, "[trace {:016x}]", id),
+        }
+    }
+}
+
+#[test]
+fn trace_id_is_scoped_and_carried_by_outgoing_messages() {
+    use crate::msg::{BusyHeartbeat, Message};
+    use crate::sig::Signal;
+    use crate::socket::Encoding;
+
+    assert_eq!(current(), NO_TRACE);
+    let (outer, inner) = (new_id(), new_id());
+    assert_ne!(outer, inner);
+    assert_ne!(outer, NO_TRACE);
+    {
+        let _outer = enter(outer);
+        {
+            let _inner = enter(inner);
+            assert_eq!(current(), inner);
+        }
+        assert_eq!(current(), outer);
+
+        let sig = Signal::from(1, outcome::distr::Signal::EndOfMessages);
+        assert_eq!(sig.trace_id(), outer);
+        let bytes = sig.to_bytes(&Encoding::Bincode).unwrap();
+        let sig = Signal::from_bytes(&bytes, &Encoding::Bincode).unwrap();
+        assert_eq!(sig.trace_id(), outer);
+
+        let msg =
+            Message::from_payload(BusyHeartbeat { in_flight: 1 }, &Encoding::Bincode).unwrap();
+        assert_eq!(msg.trace_id, outer);
+    }
+    assert_eq!(current(), NO_TRACE);
+
+    assert_eq!(Display(NO_TRACE).to_string(), "[untraced]");
+    assert_eq!(Display(0xab).to_string(), "[trace 00000000000000ab]");
+}
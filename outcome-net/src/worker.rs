@@ -1,29 +1,33 @@
+use std::collections::VecDeque;
 use std::io::{ErrorKind, Write};
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
 
 use crate::msg::coord_worker::{
-    IntroduceCoordRequest, IntroduceCoordResponse, IntroduceWorkerToCoordResponse,
-    IntroduceWorkerToOrganizerRequest,
+    DiagnosticsRequest, DiagnosticsResponse, EntitySummary, InspectReport, InspectRequest,
+    InspectResponse, InspectTarget, IntroduceCoordRequest, IntroduceCoordResponse,
+    IntroduceWorkerToCoordResponse, IntroduceWorkerToOrganizerRequest,
 };
 use crate::msg::*;
 use crate::socket::{
     Encoding, Socket, SocketAddress, SocketConfig, SocketEvent, SocketEventType, Transport,
 };
-use crate::{error::Error, sig, Result, TaskId};
+use crate::{error::Error, sig, trace, Result, TaskId};
 
 use fnv::FnvHashMap;
 use id_pool::IdPool;
 use outcome::Sim;
 use outcome_core::distr::{NodeCommunication, Signal, SimNode};
+use outcome_core::entity::Entity;
 use outcome_core::query::{Query, QueryProduct};
 use outcome_core::{
-    string, Address, CompName, EntityId, EntityName, SimModel, StringId, Var, VarType,
+    string, Address, CompName, EntityId, EntityName, EventName, PrefabName, SimInterface, SimModel,
+    StringId, Var, VarType,
 };
 use std::str::FromStr;
 
@@ -34,6 +38,9 @@ pub enum WorkerTask {
 /// Network-unique identifier for a single worker
 pub type WorkerId = u32;
 
+/// Number of most recent errors kept for diagnostics.
+const LAST_ERRORS_LIMIT: usize = 32;
+
 /// Represents a single union node.
 ///
 /// `Worker`s are connected to, and orchestrated by, a union organizer.
@@ -83,6 +90,23 @@ pub struct Worker {
     /// Simulation node running on this worker
     pub sim_node: Option<outcome::distr::SimNode>,
 
+    /// Most recent errors that occurred while handling signals, oldest first
+    pub last_errors: VecDeque<String>,
+
+    /// Interval at which heartbeats are sent to the organizer, none
+    /// disables heartbeats
+    pub heartbeat_interval: Option<Duration>,
+    /// Time the last heartbeat was sent
+    last_heartbeat: Instant,
+
+    /// Plugins loaded by this worker
+    #[cfg(feature = "worker_plugins")]
+    pub plugins: crate::plugin::WorkerPlugins,
+
+    /// Metrics exposed for scraping, see [`Worker::enable_metrics`]
+    #[cfg(feature = "metrics")]
+    pub metrics: Option<crate::metrics::Metrics>,
+
     tasks: Vec<(u32, WorkerTask)>,
 }
 
@@ -116,10 +140,30 @@ impl Worker {
             use_auth: false,
             passwd_list: vec![],
             sim_node: None,
+            last_errors: VecDeque::new(),
+            heartbeat_interval: Some(Duration::from_secs(1)),
+            last_heartbeat: Instant::now(),
+            #[cfg(feature = "worker_plugins")]
+            plugins: crate::plugin::WorkerPlugins::default(),
+            #[cfg(feature = "metrics")]
+            metrics: None,
             tasks: vec![],
         })
     }
 
+    /// Loads plugins declared in the config.
+    ///
+    /// Plugins should be loaded before the worker joins a union, so that
+    /// everything they register is available to the node once it's
+    /// initialized.
+    #[cfg(feature = "worker_plugins")]
+    pub fn load_plugins(&mut self, config: &crate::plugin::WorkerConfig) -> Result<()> {
+        for plugin in &config.plugins {
+            self.plugins.load_plugin(&plugin.path)?;
+        }
+        Ok(())
+    }
+
     /// Registers a fellow worker.
     pub fn register_comrade(&mut self, comrade: Comrade) -> Result<()> {
         // if self.use_auth {
@@ -178,9 +222,49 @@ impl Worker {
     pub fn handle_coordinator(&mut self) -> Result<()> {
         print!("Waiting for message from coordinator... ");
         std::io::stdout().flush()?;
-        let (peer_addr, msg) = self.greeter.recv_msg()?;
+        let (peer_addr, msg) = loop {
+            let (peer_addr, msg) = self.greeter.recv_msg()?;
+            if let Some(msg) = self.handle_pre_coordinator_msg(peer_addr.clone(), msg)? {
+                break (peer_addr, msg);
+            }
+        };
         println!("success");
+        self.accept_coordinator(peer_addr, msg)
+    }
+
+    /// Handles initial connection from the cluster coordinator if it
+    /// already reached out, without blocking. Returns whether the
+    /// coordinator was accepted.
+    pub fn try_handle_coordinator(&mut self) -> Result<bool> {
+        loop {
+            let (peer_addr, msg) = match self.greeter.try_recv_msg() {
+                Ok(received) => received,
+                Err(Error::WouldBlock) => return Ok(false),
+                Err(e) => return Err(e),
+            };
+            if let Some(msg) = self.handle_pre_coordinator_msg(peer_addr.clone(), msg)? {
+                self.accept_coordinator(peer_addr, msg)?;
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Handles requests that can arrive before the coordinator does, such
+    /// as inspection requests. Any other message is handed back.
+    fn handle_pre_coordinator_msg(
+        &mut self,
+        peer_addr: SocketAddress,
+        msg: Message,
+    ) -> Result<Option<Message>> {
+        match msg.type_ {
+            MessageType::InspectRequest => self.handle_inspect_request(peer_addr, msg)?,
+            MessageType::DiagnosticsRequest => self.handle_diagnostics_request(peer_addr, msg)?,
+            _ => return Ok(Some(msg)),
+        }
+        Ok(None)
+    }
 
+    fn accept_coordinator(&mut self, peer_addr: SocketAddress, msg: Message) -> Result<()> {
         debug!("message from coordinator: {:?}", msg);
 
         let req: IntroduceCoordRequest = msg.unpack_payload(self.greeter.encoding())?;
@@ -244,38 +328,87 @@ impl Worker {
 
 impl Worker {
     pub fn manual_poll(&mut self) -> Result<()> {
-        loop {
-            if let Some(organ_connection) = self.network.organizer.as_mut() {
-                // if let Some(heartbeat_interval) = organ_connection.config().heartbeat_interval {
-                //     organ_connection.send_event(SocketEvent::new(SocketEventType::Heartbeat), None);
-                // }
-                match organ_connection.try_recv() {
-                    Ok((addr, event)) => match event.type_ {
-                        SocketEventType::Bytes => {
-                            let sig =
-                                crate::sig::Signal::from_bytes(&event.bytes, &Encoding::Bincode)?;
-                            let (task_id, sig) = sig.into_inner();
-                            self.handle_coord_signal(task_id, sig)
-                                .unwrap_or_else(|e| error!("{:?}", e));
-                        }
-                        SocketEventType::Heartbeat => (),
-                        SocketEventType::Connect => {
-                            info!("coordinator connected");
-                        }
-                        SocketEventType::Timeout => {
-                            info!("connection timed out");
-                            break;
-                        }
-                        SocketEventType::Disconnect => {
-                            info!("coordinator ended the connection");
-                            break;
-                            // return Err(Error::SocketNotConnected);
+        if let Ok((addr, msg)) = self.greeter.try_recv_msg() {
+            #[cfg(feature = "metrics")]
+            if let Some(metrics) = &mut self.metrics {
+                metrics.count_received(&format!("{:?}", msg.type_));
+            }
+            let result = match msg.type_ {
+                MessageType::InspectRequest => self.handle_inspect_request(addr, msg),
+                MessageType::DiagnosticsRequest => self.handle_diagnostics_request(addr, msg),
+                _ => {
+                    trace!("unhandled greeter msg type: {:?}", msg.type_);
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                warn!("failed handling greeter message: {}", e);
+            }
+        }
+        // let the organizer know the worker is still alive
+        if let Some(interval) = self.heartbeat_interval {
+            if self.network.organizer.is_some() && self.last_heartbeat.elapsed() >= interval {
+                self.last_heartbeat = Instant::now();
+                if let Err(e) = self.network.sig_send_central(0, Signal::Heartbeat) {
+                    warn!("failed sending heartbeat: {}", e);
+                }
+            }
+        }
+        // signals are collected first so that they can be handled in order
+        // of priority, a bad signal doesn't discard the ones already
+        // accepted
+        let mut incoming = Vec::new();
+        let mut errors = Vec::new();
+        while let Some(organ_connection) = self.network.organizer.as_mut() {
+            // if let Some(heartbeat_interval) = organ_connection.config().heartbeat_interval {
+            //     organ_connection.send_event(SocketEvent::new(SocketEventType::Heartbeat), None);
+            // }
+            if let Err(e) = organ_connection.resend_due_sigs() {
+                error!("{}", e);
+                errors.push(e.to_string());
+                break;
+            }
+            match organ_connection.try_recv() {
+                Ok((addr, event)) => match event.type_ {
+                    SocketEventType::Bytes => {
+                        let sig = match crate::sig::Signal::from_bytes(
+                            &event.bytes,
+                            &Encoding::Bincode,
+                        ) {
+                            Ok(sig) => sig,
+                            Err(e) => {
+                                warn!("failed decoding signal: {}", e);
+                                errors.push(format!("failed decoding signal: {}", e));
+                                continue;
+                            }
+                        };
+                        // with unreliable transports the signal may need
+                        // to wait for the ones preceding it
+                        match organ_connection.accept_sig(addr, sig) {
+                            Ok(ready) => incoming.extend(ready.into_iter().map(|(_, sig)| sig)),
+                            Err(e) => {
+                                error!("failed accepting signal: {}", e);
+                                errors.push(format!("failed accepting signal: {}", e));
+                            }
                         }
-                    },
-                    Err(e) => {
-                        // error!("{}", e);
+                    }
+                    SocketEventType::Heartbeat => (),
+                    SocketEventType::Connect => {
+                        info!("coordinator connected");
+                    }
+                    SocketEventType::Timeout => {
+                        info!("connection timed out");
                         break;
                     }
+                    SocketEventType::Disconnect => {
+                        info!("coordinator ended the connection");
+                        break;
+                        // return Err(Error::SocketNotConnected);
+                    }
+                },
+                Err(e) => {
+                    // error!("{}", e);
+                    break;
                 }
             }
             // if let Ok((addr, sig)) = self.network.coord.as_mut().unwrap().try_recv_sig() {
@@ -285,9 +418,293 @@ impl Worker {
             //     break;
             // }
         }
+        for error in errors {
+            self.record_error(error);
+        }
+        // state changing signals keep their place relative to the rest
+        sig::sort_by_priority(&mut incoming);
+        for sig in incoming {
+            self.handle_coord_envelope(sig);
+        }
+
+        #[cfg(feature = "metrics")]
+        self.serve_metrics();
+
         Ok(())
     }
 
+    /// Handles a signal received from the organizer, unless it's deadline
+    /// has already passed, in which case it's rejected.
+    fn handle_coord_envelope(&mut self, sig: sig::Signal) {
+        // responses to the signal carry the same trace id
+        let trace_id = sig.trace_id();
+        let _trace = trace::enter(trace_id);
+        let clock = self.sim_node.as_ref().map(|node| node.clock);
+        if let Some(clock) = clock.filter(|clock| sig.is_expired(*clock)) {
+            warn!(
+                "{} rejecting signal with deadline {:?} at clock {}",
+                trace::Display(trace_id),
+                sig.deadline(),
+                clock
+            );
+            // let the organizer know not to wait for the response
+            if sig.task_id() != 0 {
+                if let Err(e) = self
+                    .network
+                    .sig_send_central(sig.task_id(), Signal::DeadlineExceeded(clock))
+                {
+                    error!("{} {}", trace::Display(trace_id), e);
+                }
+            }
+            return;
+        }
+        let (task_id, sig) = sig.into_inner();
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &mut self.metrics {
+            metrics.count_received(sig.name());
+        }
+        if let Err(e) = self.handle_coord_signal(task_id, sig) {
+            error!("{} {:?}", trace::Display(trace_id), e);
+            self.record_error(format!("{} {}", trace::Display(trace_id), e));
+        }
+    }
+
+    fn handle_inspect_request(&mut self, addr: SocketAddress, msg: Message) -> Result<()> {
+        let req: InspectRequest = msg.unpack_payload(self.greeter.encoding())?;
+        let resp = match self.inspect(req.target) {
+            Ok(report) => InspectResponse {
+                report,
+                error: String::new(),
+            },
+            Err(e) => InspectResponse {
+                report: InspectReport::Empty,
+                error: e.to_string(),
+            },
+        };
+        self.greeter.send_payload(resp, Some(addr))
+    }
+
+    fn record_error(&mut self, error: String) {
+        if self.last_errors.len() >= LAST_ERRORS_LIMIT {
+            self.last_errors.pop_front();
+        }
+        self.last_errors.push_back(error);
+    }
+
+    fn handle_diagnostics_request(&mut self, addr: SocketAddress, msg: Message) -> Result<()> {
+        let req: DiagnosticsRequest = msg.unpack_payload(self.greeter.encoding())?;
+        let resp = match self.diagnose(&req.command) {
+            Ok(output) => DiagnosticsResponse {
+                output,
+                error: String::new(),
+            },
+            Err(e) => DiagnosticsResponse {
+                output: String::new(),
+                error: e.to_string(),
+            },
+        };
+        self.greeter.send_payload(resp, Some(addr))
+    }
+
+    /// Runs a diagnostics command, returning readable output.
+    ///
+    /// Only a restricted, read-only set of commands is available:
+    /// - `entities [filter]` lists stored entities, optionally only the
+    /// ones with the filter string in their id, name or components
+    /// - `var <address>` prints a single var
+    /// - `queues` prints the depths of event and task queues
+    /// - `traffic` prints data sent and received over each connection
+    /// - `errors` prints the most recent errors, including logic errors
+    /// from the last step
+    /// - `help` lists the commands
+    pub fn diagnose(&mut self, command: &str) -> Result<String> {
+        let mut split = command.trim().splitn(2, char::is_whitespace);
+        let cmd = split.next().unwrap_or("");
+        let args = split.next().unwrap_or("").trim();
+        let mut out = String::new();
+        match cmd {
+            "entities" => {
+                let node = self
+                    .sim_node
+                    .as_ref()
+                    .ok_or(Error::Other("node not initialized".to_string()))?;
+                let mut ids = node.entities.keys().copied().collect::<Vec<_>>();
+                ids.sort_unstable();
+                for id in ids {
+                    let entity = &node.entities[&id];
+                    let name = node
+                        .entities_idx
+                        .iter()
+                        .find(|(_, _id)| **_id == id)
+                        .map(|(name, _)| format!(" ({})", name))
+                        .unwrap_or_default();
+                    let comps = entity
+                        .components
+                        .iter()
+                        .map(|c| c.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    let line = format!("{}{}: {}", id, name, comps);
+                    if args.is_empty() || line.contains(args) {
+                        out.push_str(&line);
+                        out.push('\n');
+                    }
+                }
+            }
+            "var" => {
+                let addr = Address::from_str(args)?;
+                let node = self
+                    .sim_node
+                    .as_ref()
+                    .ok_or(Error::Other("node not initialized".to_string()))?;
+                out = format!("{}: {}\n", addr, node.get_var(&addr)?.to_string());
+            }
+            "queues" => {
+                if let Some(node) = &self.sim_node {
+                    out.push_str(&format!(
+                        "event queue: {} {:?}\n",
+                        node.event_queue.len(),
+                        node.event_queue
+                    ));
+                    let entity_events: usize =
+                        node.entities.values().map(|e| e.event_queue.len()).sum();
+                    out.push_str(&format!("entity event queues: {}\n", entity_events));
+                }
+                out.push_str(&format!("pending tasks: {}\n", self.tasks.len()));
+                out.push_str(&format!("comrades: {}\n", self.network.comrades.len()));
+            }
+            "traffic" => {
+                if let Some(organizer) = self.network.organizer.as_mut() {
+                    out.push_str(&format!("organizer: {}\n", organizer.traffic()));
+                }
+                for (comrade_id, comrade) in self.network.comrades.iter_mut() {
+                    out.push_str(&format!(
+                        "comrade {}: {}\n",
+                        comrade_id,
+                        comrade.connection.traffic()
+                    ));
+                }
+                out.push_str(&format!("greeter: {}\n", self.greeter.traffic()));
+            }
+            "errors" => {
+                for error in &self.last_errors {
+                    out.push_str(error);
+                    out.push('\n');
+                }
+                #[cfg(feature = "machine")]
+                if let Some(node) = &self.sim_node {
+                    for (context, error) in &node.error_journal {
+                        out.push_str(&format!("step {}: {:?}: {}\n", node.clock, context, error));
+                    }
+                }
+                if out.is_empty() {
+                    out.push_str("no errors\n");
+                }
+            }
+            "help" | "" => {
+                out.push_str("entities [filter], var <address>, queues, traffic, errors, help\n");
+            }
+            _ => {
+                return Err(Error::Other(format!(
+                    "unknown diagnostics command: {}",
+                    cmd
+                )))
+            }
+        }
+        Ok(out)
+    }
+
+    /// Creates a report on the selected part of the worker state.
+    pub fn inspect(&mut self, target: InspectTarget) -> Result<InspectReport> {
+        let report = match target {
+            InspectTarget::Status => {
+                let mut status = vec![
+                    ("role".to_string(), "worker".to_string()),
+                    ("address".to_string(), self.addr.clone()),
+                    (
+                        "organizer".to_string(),
+                        self.network.organizer.is_some().to_string(),
+                    ),
+                    (
+                        "comrades".to_string(),
+                        self.network.comrades.len().to_string(),
+                    ),
+                ];
+                if let Some(node) = &self.sim_node {
+                    status.push(("clock".to_string(), node.clock.to_string()));
+                    status.push(("entities".to_string(), node.entities.len().to_string()));
+                } else {
+                    status.push(("node".to_string(), "not initialized".to_string()));
+                }
+                if let Some(organizer) = self.network.organizer.as_mut() {
+                    status.push((
+                        "organizer traffic".to_string(),
+                        organizer.traffic().to_string(),
+                    ));
+                }
+                InspectReport::Status(status)
+            }
+            InspectTarget::Entities => {
+                let node = self
+                    .sim_node
+                    .as_ref()
+                    .ok_or(Error::Other("node not initialized".to_string()))?;
+                let mut entities = node
+                    .entities
+                    .iter()
+                    .map(|(id, entity)| EntitySummary {
+                        id: *id,
+                        name: node
+                            .entities_idx
+                            .iter()
+                            .find(|(_, _id)| *_id == id)
+                            .map(|(name, _)| name.to_string()),
+                        worker: None,
+                        components: entity.components.iter().map(|c| c.to_string()).collect(),
+                    })
+                    .collect::<Vec<_>>();
+                entities.sort_by_key(|e| e.id);
+                InspectReport::Entities(entities)
+            }
+            InspectTarget::Entity(id) => {
+                let entity = self
+                    .sim_node
+                    .as_ref()
+                    .and_then(|node| node.entities.get(&id))
+                    .ok_or(Error::Other(format!("entity not stored on worker: {}", id)))?;
+                let mut vars = entity
+                    .storage
+                    .map
+                    .iter()
+                    .map(|((comp, var_name), var)| {
+                        (
+                            format!("{}:{}:{}", comp, var.get_type().to_str(), var_name),
+                            var.to_string(),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                vars.sort();
+                InspectReport::Entity(vars)
+            }
+            InspectTarget::Routing => {
+                return Err(Error::Other(
+                    "workers don't keep a routing table, attach to the organizer".to_string(),
+                ))
+            }
+            InspectTarget::Pending => InspectReport::Pending(
+                self.tasks
+                    .iter()
+                    .map(|(task_id, task)| match task {
+                        WorkerTask::RequestedCoordToProcessStep => {
+                            format!("task {}: requested step from organizer", task_id)
+                        }
+                    })
+                    .collect(),
+            ),
+        };
+        Ok(report)
+    }
+
     fn handle_coord_signal(&mut self, task_id: u32, sig: Signal) -> Result<()> {
         debug!("handling signal: {:?}", sig);
 
@@ -295,14 +712,36 @@ impl Worker {
             Signal::InitializeNode(model) => self.handle_sig_initialize_node(model)?,
             Signal::StartProcessStep(event_queue) => {
                 let sim_node = self.sim_node.as_mut().unwrap();
+                #[cfg(feature = "worker_plugins")]
+                self.plugins.pre_step(sim_node);
+                let step_start = Instant::now();
                 sim_node.step(&mut self.network, &event_queue)?;
+                #[cfg(feature = "metrics")]
+                if let Some(metrics) = &mut self.metrics {
+                    metrics.record_step(step_start.elapsed());
+                }
+                #[cfg(feature = "worker_plugins")]
+                self.plugins.post_step(sim_node);
             }
             Signal::DataRequestAll => self.handle_sig_data_request_all()?,
             Signal::SpawnEntities(entities) => self.handle_sig_spawn_entities(entities)?,
             Signal::QueryRequest(query) => self.handle_sig_query_request(task_id, query)?,
+            Signal::SnapshotRequest => self.handle_sig_snapshot_request(task_id)?,
             Signal::DataPullRequest(pull_data) => {
                 self.handle_sig_pull_data_request(task_id, pull_data)?
             }
+            Signal::MigrateEntity(entity_id, _) => {
+                self.handle_sig_migrate_entity(task_id, entity_id)?
+            }
+            Signal::IngestEntity(entity_id, name, entity) => {
+                self.handle_sig_ingest_entity(task_id, entity_id, name, entity)?
+            }
+            Signal::Checkpoint(location, key, clock) => {
+                self.handle_sig_checkpoint(task_id, location, key, clock)?
+            }
+            Signal::RestoreCheckpoint(location, key) => {
+                self.handle_sig_restore_checkpoint(task_id, location, key)?
+            }
             _ => warn!("unhandled signal: {:?}", sig),
         }
 
@@ -312,6 +751,10 @@ impl Worker {
     //TODO include event_queue in the initialization process?
     fn handle_sig_initialize_node(&mut self, model: SimModel) -> Result<()> {
         let mut node = SimNode::from_model(&model)?;
+        #[cfg(feature = "worker_plugins")]
+        {
+            node.query_plugins = self.plugins.registrar.query_plugins.clone();
+        }
         self.sim_node = Some(node);
         Ok(())
     }
@@ -330,10 +773,78 @@ impl Worker {
         Ok(())
     }
 
+    /// Hands the entity over to the organizer, to be moved to another
+    /// worker.
+    fn handle_sig_migrate_entity(&mut self, task_id: TaskId, entity_id: EntityId) -> Result<()> {
+        let sig = match self.node_mut()?.take_entity(&entity_id) {
+            Ok((name, entity)) => Signal::MigratingEntity(entity_id, name, entity),
+            Err(e) => Signal::MigrationFailed(entity_id, e.to_string()),
+        };
+        self.network.sig_send_central(task_id, sig)?;
+        Ok(())
+    }
+
+    /// Takes in an entity moved from another worker.
+    fn handle_sig_ingest_entity(
+        &mut self,
+        task_id: TaskId,
+        entity_id: EntityId,
+        name: Option<EntityName>,
+        entity: Entity,
+    ) -> Result<()> {
+        let sig = match self.node_mut()?.insert_entity(entity_id, name, entity) {
+            Ok(()) => Signal::EntityIngested(entity_id),
+            Err(e) => Signal::MigrationFailed(entity_id, e.to_string()),
+        };
+        self.network.sig_send_central(task_id, sig)?;
+        Ok(())
+    }
+
+    /// Writes the state of the node to the checkpoint store, see
+    /// [`Organizer::checkpoint`](crate::Organizer::checkpoint).
+    fn handle_sig_checkpoint(
+        &mut self,
+        task_id: TaskId,
+        location: String,
+        key: String,
+        clock: usize,
+    ) -> Result<()> {
+        let result = self
+            .node_mut()
+            .map_err(Error::from)
+            .and_then(|node| crate::checkpoint::save_node(node, &location, &key, clock));
+        let sig = match result {
+            Ok(()) => Signal::CheckpointSaved(key),
+            Err(e) => Signal::CheckpointFailed(e.to_string()),
+        };
+        self.network.sig_send_central(task_id, sig)?;
+        Ok(())
+    }
+
+    /// Merges the node state stored in the checkpoint store into the node.
+    fn handle_sig_restore_checkpoint(
+        &mut self,
+        task_id: TaskId,
+        location: String,
+        key: String,
+    ) -> Result<()> {
+        let result = self
+            .node_mut()
+            .map_err(Error::from)
+            .and_then(|node| crate::checkpoint::restore_node(node, &location, &key));
+        let sig = match result {
+            Ok(()) => Signal::CheckpointRestored(key),
+            Err(e) => Signal::CheckpointFailed(e.to_string()),
+        };
+        self.network.sig_send_central(task_id, sig)?;
+        Ok(())
+    }
+
     fn handle_sig_query_request(&mut self, task_id: TaskId, query: Query) -> Result<()> {
         info!("handling query request: {:?}", query);
         if let Some(node) = &self.sim_node {
-            let product = query.process(&node.entities, &node.entities_idx)?;
+            let product =
+                query.process_with(&node.entities, &node.entities_idx, &node.query_plugins)?;
             info!("  product: {:?}", product);
             self.network
                 .sig_send_central(task_id, Signal::QueryResponse(product))?;
@@ -341,6 +852,17 @@ impl Worker {
         Ok(())
     }
 
+    /// Sends the entities stored on the node back to the organizer, to be
+    /// consolidated into a single snapshot.
+    fn handle_sig_snapshot_request(&mut self, task_id: TaskId) -> Result<()> {
+        let part = outcome::snapshot::SnapshotPart {
+            entities: self.node_mut()?.entities.clone(),
+        };
+        self.network
+            .sig_send_central(task_id, Signal::SnapshotResponse(part))?;
+        Ok(())
+    }
+
     fn handle_sig_pull_data_request(
         &mut self,
         task_id: TaskId,
@@ -349,7 +871,14 @@ impl Worker {
         info!("handling pull data request: {:?}", pull_data);
         if let Some(node) = &mut self.sim_node {
             for (addr, var) in pull_data {
-                *node.get_var_mut(&addr)? = var;
+                // pull requests can be broadcast to all the workers, only
+                // the worker storing the entity applies the change,
+                // wildcards are matched against the locally stored entities
+                for addr in addr.expand_node(node) {
+                    if let Ok(v) = node.get_var_mut(&addr) {
+                        *v = var.clone();
+                    }
+                }
             }
         }
         Ok(())
@@ -470,12 +999,14 @@ pub fn handle_data_transfer_request(msg: Message, server_arc: Arc<Mutex<Worker>>
             }
         }
         "SelectedAddresses" => {
-            for address in &dtr.selection {
-                //                println!("{}", address.clone());
-                let address = match outcome::Address::from_str(&address) {
-                    Ok(a) => a,
-                    Err(_) => continue,
-                };
+            let node = server.sim_node.as_ref().unwrap();
+            let selected = dtr
+                .selection
+                .iter()
+                .filter_map(|address| outcome::Address::from_str(address).ok())
+                .flat_map(|address| address.expand_node(node))
+                .collect::<Vec<_>>();
+            for address in selected {
                 match address.var_type {
                     //                    VarType::Str => match server.sim_node.as_ref().unwrap().get_str(&address) {
                     //                        Some(s) => data_pack.strings.insert(
@@ -491,6 +1022,7 @@ pub fn handle_data_transfer_request(msg: Message, server_arc: Arc<Mutex<Worker>>
     }
     let response = DataTransferResponse {
         data: TransferResponseData::Var(data_pack),
+        interpolation: None,
     };
     Ok(())
     // TODO
@@ -533,6 +1065,9 @@ pub fn handle_data_pull_request(msg: Message, server_arc: Arc<Mutex<Worker>>) ->
 
     let resp = DataPullResponse {
         error: String::new(),
+        code: None,
+        pulled: 0,
+        rejected: Vec::new(),
     };
 
     Ok(())
@@ -557,10 +1092,7 @@ impl outcome::distr::NodeCommunication for WorkerNetwork {
 
     fn sig_read_central(&mut self) -> outcome::Result<(u32, Signal)> {
         if let Some(coord) = &mut self.organizer {
-            let sig = match coord.recv_sig() {
-                Ok((addr, sig)) => sig,
-                Err(e) => return Err(outcome::error::Error::Other(e.to_string())),
-            };
+            let (_, sig) = coord.recv_sig()?;
             Ok(sig.into_inner())
         } else {
             Err(outcome::error::Error::Other("no coord".to_string()))
@@ -570,9 +1102,8 @@ impl outcome::distr::NodeCommunication for WorkerNetwork {
     fn sig_send_central(&mut self, task_id: u32, signal: Signal) -> outcome::Result<()> {
         self.organizer
             .as_mut()
-            .unwrap()
-            .send_sig(sig::Signal::from(task_id, signal), None)
-            .unwrap();
+            .ok_or(outcome::error::Error::Other("no coord".to_string()))?
+            .send_sig(sig::Signal::from(task_id, signal), None)?;
         Ok(())
     }
 
@@ -618,3 +1149,172 @@ impl outcome::distr::NodeCommunication for WorkerNetwork {
         unimplemented!()
     }
 }
+
+impl Worker {
+    fn node(&self) -> outcome::Result<&SimNode> {
+        self.sim_node.as_ref().ok_or(outcome::error::Error::Other(
+            "node not initialized".to_string(),
+        ))
+    }
+
+    fn node_mut(&mut self) -> outcome::Result<&mut SimNode> {
+        self.sim_node.as_mut().ok_or(outcome::error::Error::Other(
+            "node not initialized".to_string(),
+        ))
+    }
+
+    pub(crate) fn send_central(&mut self, signal: Signal) -> outcome::Result<()> {
+        if self.network.organizer.is_none() {
+            return Err(outcome::error::Error::Other(
+                "not connected to organizer".to_string(),
+            ));
+        }
+        self.network.sig_send_central(0, signal)
+    }
+}
+
+/// Data access is limited to entities stored on this worker's node.
+/// Operations requiring central authority are forwarded to the organizer.
+impl SimInterface for Worker {
+    fn get_clock(&mut self) -> outcome::Result<usize> {
+        Ok(self.node()?.clock)
+    }
+
+    fn get_var(&mut self, addr: &Address) -> outcome::Result<Var> {
+        self.node()?.get_var(addr).map(|var| var.clone())
+    }
+
+    fn set_var(&mut self, addr: &Address, var: Var) -> outcome::Result<()> {
+        let node = self.node_mut()?;
+        node.validate_var(addr, &var)?;
+        *node.get_var_mut(addr)? = var;
+        Ok(())
+    }
+
+    fn get_entities_of_type(&mut self, type_: &[CompName]) -> outcome::Result<Vec<EntityId>> {
+        let mut ids = self
+            .node()?
+            .entities
+            .iter()
+            .filter(|(_, e)| type_.iter().all(|c| e.components.contains(c)))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    /// Entity ids are assigned by the organizer, which doesn't report them
+    /// back to the requesting worker, so spawning is not supported.
+    fn spawn_entity(
+        &mut self,
+        _prefab: Option<PrefabName>,
+        _name: Option<EntityName>,
+    ) -> outcome::Result<EntityId> {
+        Err(outcome::error::Error::Other(
+            "spawning entities from a worker is not supported, use the organizer".to_string(),
+        ))
+    }
+
+    fn add_event(&mut self, name: EventName) -> outcome::Result<()> {
+        self.send_central(Signal::AddEvent(name))
+    }
+
+    /// Requests the organizer to process a step. Doesn't wait for the step
+    /// to be processed.
+    fn step(&mut self) -> outcome::Result<()> {
+        self.send_central(Signal::WorkerStepAdvanceRequest(1))
+    }
+}
+
+#[test]
+fn inspect_reports_node_state() {
+    use outcome::entity::Entity;
+
+    let mut worker = Worker::new(Some("127.0.0.1:0")).unwrap();
+    match worker.inspect(InspectTarget::Status).unwrap() {
+        InspectReport::Status(status) => {
+            assert!(status.contains(&("node".to_string(), "not initialized".to_string())))
+        }
+        report => panic!("unexpected report: {:?}", report),
+    }
+    assert!(worker.inspect(InspectTarget::Entities).is_err());
+    assert!(worker.inspect(InspectTarget::Routing).is_err());
+
+    let model = outcome::SimModelBuilder::new().build().unwrap();
+    let mut node = SimNode::from_model(&model).unwrap();
+    let mut entity = Entity::empty();
+    entity.storage.insert(
+        (
+            outcome::string::new_truncate("pos"),
+            outcome::string::new_truncate("x"),
+        ),
+        outcome::Var::Float(1.),
+    );
+    node.insert_entity(2, Some(outcome::string::new_truncate("second")), entity)
+        .unwrap();
+    node.insert_entity(1, None, Entity::empty()).unwrap();
+    worker.sim_node = Some(node);
+
+    match worker.inspect(InspectTarget::Entities).unwrap() {
+        InspectReport::Entities(entities) => {
+            assert_eq!(
+                entities.iter().map(|e| e.id).collect::<Vec<_>>(),
+                vec![1, 2]
+            );
+            assert_eq!(entities[1].name.as_deref(), Some("second"));
+        }
+        report => panic!("unexpected report: {:?}", report),
+    }
+    assert_eq!(
+        worker.inspect(InspectTarget::Entity(2)).unwrap(),
+        InspectReport::Entity(vec![("pos:float:x".to_string(), "1".to_string())])
+    );
+    assert!(worker.inspect(InspectTarget::Entity(3)).is_err());
+    assert_eq!(
+        worker.inspect(InspectTarget::Pending).unwrap(),
+        InspectReport::Pending(Vec::new())
+    );
+}
+
+#[test]
+fn diagnostics_commands_are_answered() {
+    use outcome::entity::Entity;
+
+    let mut worker = Worker::new(Some("127.0.0.1:0")).unwrap();
+    assert!(worker.diagnose("entities").is_err());
+    assert_eq!(worker.diagnose("errors").unwrap(), "no errors\n");
+    assert!(worker.diagnose("shutdown").is_err());
+
+    let model = outcome::SimModelBuilder::new().build().unwrap();
+    let mut node = SimNode::from_model(&model).unwrap();
+    let mut entity = Entity::empty();
+    entity.components.push(outcome::string::new_truncate("pos"));
+    entity.storage.insert(
+        (
+            outcome::string::new_truncate("pos"),
+            outcome::string::new_truncate("x"),
+        ),
+        outcome::Var::Float(1.),
+    );
+    node.insert_entity(2, Some(outcome::string::new_truncate("second")), entity)
+        .unwrap();
+    node.insert_entity(1, None, Entity::empty()).unwrap();
+    worker.sim_node = Some(node);
+
+    assert_eq!(
+        worker.diagnose("entities").unwrap(),
+        "1: \n2 (second): pos\n"
+    );
+    assert_eq!(
+        worker.diagnose("entities second").unwrap(),
+        "2 (second): pos\n"
+    );
+    assert_eq!(
+        worker.diagnose("var second:pos:float:x").unwrap(),
+        "second:pos:float:x: 1\n"
+    );
+    assert!(worker
+        .diagnose("queues")
+        .unwrap()
+        .contains("pending tasks: 0"));
+}